@@ -0,0 +1,10 @@
+// Generates the Rust types of proto/butterfly.proto with the pure-Rust
+// protobuf parser, so no protoc install is needed
+fn main() {
+    protobuf_codegen::Codegen::new()
+        .pure()
+        .include("proto")
+        .input("proto/butterfly.proto")
+        .cargo_out_dir("protos")
+        .run_from_script();
+}
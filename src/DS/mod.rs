@@ -1 +1,2 @@
 pub mod B_tree;
+pub mod skip_list;
@@ -0,0 +1,165 @@
+use std::cmp::Ordering;
+
+/// Highest level a node can reach; plenty for millions of entries at p = 1/4
+const MAX_LEVEL: usize = 16;
+
+/// Ordered map kept as a skip list. Nodes live in one vector and link to each
+/// other by position, so the list needs no unsafe code; entries are never
+/// removed (an LSM memtable records deletes as tombstone values).
+pub struct SkipList<K: Ord, V> {
+    /// `nodes[0]` is the head, which holds no entry
+    nodes: Vec<SkipNode<K, V>>,
+    level: usize,
+}
+
+struct SkipNode<K, V> {
+    entry: Option<(K, V)>,
+    next: Vec<Option<usize>>,
+}
+
+impl<K: Ord, V> Default for SkipList<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Ord, V> SkipList<K, V> {
+    pub fn new() -> Self {
+        Self {
+            nodes: vec![SkipNode {
+                entry: None,
+                next: vec![None; MAX_LEVEL],
+            }],
+            level: 1,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len() - 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn key(&self, node: usize) -> &K {
+        &self.nodes[node].entry.as_ref().expect("skip list head has no key").0
+    }
+
+    /// For every level, the last node whose key is below `key`
+    fn predecessors(&self, key: &K) -> [usize; MAX_LEVEL] {
+        let mut update = [0; MAX_LEVEL];
+        let mut node = 0;
+        for level in (0..self.level).rev() {
+            while let Some(next) = self.nodes[node].next[level] {
+                if self.key(next).cmp(key) == Ordering::Less {
+                    node = next;
+                } else {
+                    break;
+                }
+            }
+            update[level] = node;
+        }
+        update
+    }
+
+    /// Insert `value` under `key`, returning the value it replaces
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let mut update = self.predecessors(&key);
+        if let Some(next) = self.nodes[update[0]].next[0] {
+            if self.key(next) == &key {
+                let entry = self.nodes[next].entry.as_mut().expect("skip list node has an entry");
+                return Some(std::mem::replace(&mut entry.1, value));
+            }
+        }
+
+        let height = random_level();
+        if height > self.level {
+            for slot in update.iter_mut().take(height).skip(self.level) {
+                *slot = 0;
+            }
+            self.level = height;
+        }
+        let id = self.nodes.len();
+        let next = (0..height).map(|level| self.nodes[update[level]].next[level]).collect();
+        self.nodes.push(SkipNode {
+            entry: Some((key, value)),
+            next,
+        });
+        for (level, &previous) in update.iter().enumerate().take(height) {
+            self.nodes[previous].next[level] = Some(id);
+        }
+        None
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        let node = self.nodes[self.predecessors(key)[0]].next[0]?;
+        match &self.nodes[node].entry {
+            Some((k, v)) if k == key => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Entries in key order
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            list: self,
+            node: self.nodes[0].next[0],
+        }
+    }
+
+    /// Entries in key order, starting at the first key not below `key`
+    pub fn range_from(&self, key: &K) -> Iter<'_, K, V> {
+        Iter {
+            list: self,
+            node: self.nodes[self.predecessors(key)[0]].next[0],
+        }
+    }
+}
+
+/// Levels for a new node: each further level with probability 1/4
+fn random_level() -> usize {
+    let mut level = 1;
+    while level < MAX_LEVEL && rand::random::<u32>().is_multiple_of(4) {
+        level += 1;
+    }
+    level
+}
+
+pub struct Iter<'a, K: Ord, V> {
+    list: &'a SkipList<K, V>,
+    node: Option<usize>,
+}
+
+impl<'a, K: Ord, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = &self.list.nodes[self.node?];
+        self.node = node.next[0];
+        node.entry.as_ref().map(|(k, v)| (k, v))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_skip_list_orders_and_replaces() {
+        let mut list = SkipList::new();
+        for i in (0..500).rev() {
+            assert_eq!(list.insert(i * 2, i), None);
+        }
+        assert_eq!(list.insert(10, 99), Some(5));
+        assert_eq!(list.len(), 500);
+        assert_eq!(list.get(&10), Some(&99));
+        assert_eq!(list.get(&11), None);
+
+        let keys: Vec<i32> = list.iter().map(|(k, _)| *k).collect();
+        assert_eq!(keys, (0..500).map(|i| i * 2).collect::<Vec<_>>());
+        let from: Vec<i32> = list.range_from(&995).map(|(k, _)| *k).collect();
+        assert_eq!(from, vec![996, 998]);
+        assert_eq!(list.range_from(&2000).next(), None);
+    }
+}
@@ -15,6 +15,14 @@ pub struct NetworkConfig {
 
     #[serde(default = "default_timeout_ms")]
     pub connection_timeout_ms: u32,
+
+    /// Port the PostgreSQL wire protocol listens on
+    #[serde(default = "default_pg_port")]
+    pub pg_port: u16,
+
+    /// Port the gRPC API listens on
+    #[serde(default = "default_grpc_port")]
+    pub grpc_port: u16,
 }
 
 #[derive(Debug, Deserialize, Serialize, Default, Clone)]
@@ -36,6 +44,23 @@ pub struct ResourceConfig {
 
     #[serde(default = "default_resource_path")]
     pub default_path: String,
+
+    /// Worker threads one query may use (0 = one per core)
+    #[serde(default)]
+    pub query_workers: u32,
+}
+
+impl ResourceConfig {
+    /// Workers a single query may use: `query_workers`, capped at the share
+    /// of `cores` that `max_cpu_percent` allows, and at least one
+    pub fn query_worker_limit(&self, cores: usize) -> usize {
+        let allowed = (cores as f32 * self.max_cpu_percent / 100.0).floor() as usize;
+        let requested = match self.query_workers {
+            0 => cores,
+            n => n as usize,
+        };
+        requested.min(allowed).max(1)
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Default, Clone)]
@@ -75,6 +100,19 @@ impl Default for PoolConfig {
     }
 }
 
+/// A change data capture consumer whose changes are copied to a local file
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct FileSinkConfig {
+    pub consumer: String,
+    pub path: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
+pub struct CdcConfig {
+    #[serde(default)]
+    pub file_sinks: Vec<FileSinkConfig>,
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Config {
     #[serde(default = "default_name")]
@@ -94,6 +132,10 @@ pub struct Config {
 
     #[serde(default = "default_pool")]
     pub pool: PoolConfig,
+
+    /// Change data capture
+    #[serde(default)]
+    pub cdc: CdcConfig,
 }
 
 pub fn get_config() -> Result<Config, Box<dyn std::error::Error>> {
@@ -140,12 +182,20 @@ fn default_port() -> u16 {
 fn default_timeout_ms() -> u32 {
     5000
 } // 5 seconds
+fn default_pg_port() -> u16 {
+    5432
+}
+fn default_grpc_port() -> u16 {
+    50051
+}
 
 fn default_network() -> NetworkConfig {
     NetworkConfig {
         bind_address: default_bind_address(),
         port: default_port(),
         connection_timeout_ms: default_timeout_ms(),
+        pg_port: default_pg_port(),
+        grpc_port: default_grpc_port(),
     }
 }
 // Replication Defaults
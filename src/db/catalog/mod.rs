@@ -1,11 +1,16 @@
 // Database Catalog - Stores table schemas and metadata
+pub mod statistics;
+
 use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, RwLock};
 
+use crate::db::database::default_dir;
 use crate::db::sql::parser::DataType;
+pub use statistics::{ColumnStatistics, TableStatistics};
 
 /// Column definition stored in the catalog
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -22,6 +27,84 @@ pub struct TableSchema {
     pub name: String,
     pub columns: Vec<ColumnSchema>,
     pub created_at: String,
+    /// Column sets declared UNIQUE (column or table constraint)
+    #[serde(default)]
+    pub unique_keys: Vec<Vec<String>>,
+    /// Secondary indexes created with CREATE INDEX
+    #[serde(default)]
+    pub indexes: Vec<IndexSchema>,
+    /// Storage layout, set with `CREATE TABLE ... WITH (format = '...')`
+    #[serde(default)]
+    pub format: TableFormat,
+    /// Storage engine, set with `CREATE TABLE ... WITH (engine = '...')`
+    #[serde(default)]
+    pub engine: TableEngine,
+}
+
+/// How a table's rows are laid out in storage
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TableFormat {
+    /// One map per row; cheap point writes
+    #[default]
+    Row,
+    /// Encoded column chunks with zone maps; for tables written once and
+    /// scanned by a few columns
+    Columnar,
+}
+
+/// Which storage engine holds a table's rows
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TableEngine {
+    /// The whole table in memory, persisted with every write
+    #[default]
+    Memory,
+    /// Log-structured merge tree on disk, keyed by the primary key; for
+    /// write-heavy key-value tables
+    Lsm,
+}
+
+impl TableEngine {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name.to_lowercase().as_str() {
+            "memory" => Ok(TableEngine::Memory),
+            "lsm" => Ok(TableEngine::Lsm),
+            _ => Err(format!("Unknown storage engine '{}' (expected 'memory' or 'lsm')", name)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TableEngine::Memory => "memory",
+            TableEngine::Lsm => "lsm",
+        }
+    }
+}
+
+impl TableFormat {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name.to_lowercase().as_str() {
+            "row" => Ok(TableFormat::Row),
+            "columnar" => Ok(TableFormat::Columnar),
+            _ => Err(format!("Unknown table format '{}' (expected 'row' or 'columnar')", name)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TableFormat::Row => "row",
+            TableFormat::Columnar => "columnar",
+        }
+    }
+}
+
+/// Secondary index definition. Lookups use the leading column.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IndexSchema {
+    pub name: String,
+    pub columns: Vec<String>,
+    pub unique: bool,
 }
 
 impl TableSchema {
@@ -30,6 +113,10 @@ impl TableSchema {
             name,
             columns,
             created_at: chrono::Local::now().to_rfc3339(),
+            unique_keys: Vec::new(),
+            indexes: Vec::new(),
+            format: TableFormat::Row,
+            engine: TableEngine::Memory,
         }
     }
 
@@ -40,6 +127,35 @@ impl TableSchema {
     pub fn column_names(&self) -> Vec<&str> {
         self.columns.iter().map(|c| c.name.as_str()).collect()
     }
+
+    /// Primary key columns in declaration order (empty if the table has none)
+    pub fn primary_key(&self) -> Vec<String> {
+        self.columns
+            .iter()
+            .filter(|c| c.is_primary_key)
+            .map(|c| c.name.clone())
+            .collect()
+    }
+
+    /// Every column set that uniquely identifies a row: the primary key
+    /// followed by the UNIQUE constraints
+    pub fn candidate_keys(&self) -> Vec<Vec<String>> {
+        let primary_key = self.primary_key();
+        let mut keys = Vec::new();
+        if !primary_key.is_empty() {
+            keys.push(primary_key);
+        }
+        keys.extend(self.unique_keys.iter().cloned());
+        keys
+    }
+
+    /// Indexes whose leading column is `column`
+    pub fn indexes_on(&self, column: &str) -> impl Iterator<Item = &IndexSchema> {
+        let column = column.to_string();
+        self.indexes
+            .iter()
+            .filter(move |index| index.columns.first() == Some(&column))
+    }
 }
 
 /// Database metadata
@@ -47,6 +163,9 @@ impl TableSchema {
 pub struct DatabaseSchema {
     pub name: String,
     pub tables: HashMap<String, TableSchema>,
+    /// Statistics gathered by ANALYZE, keyed by table name
+    #[serde(default)]
+    pub statistics: HashMap<String, TableStatistics>,
 }
 
 /// Catalog stores all database and table metadata
@@ -59,29 +178,37 @@ pub struct CatalogData {
 /// Thread-safe catalog wrapper
 pub struct Catalog {
     data: Arc<RwLock<CatalogData>>,
-    storage_path: PathBuf,
+    /// Where the catalog is saved; None keeps it in memory only
+    storage_path: Option<PathBuf>,
 }
 
 impl Catalog {
     /// Create a new catalog, loading from disk if available
     pub fn new() -> Self {
-        let storage_path = Self::get_default_path();
+        Self::open(&default_dir())
+    }
+
+    /// The catalog kept in `dir`, loading it if it exists
+    pub fn open(dir: &Path) -> Self {
+        fs::create_dir_all(dir).ok();
+        let storage_path = dir.join("catalog.json");
         let data = Self::load_or_create(&storage_path);
-        
+
         Self {
             data: Arc::new(RwLock::new(data)),
-            storage_path,
+            storage_path: Some(storage_path),
         }
     }
 
-    fn get_default_path() -> PathBuf {
-        let home = std::env::var("HOME").unwrap_or_else(|_| ".".to_string());
-        let path = PathBuf::from(home).join(".butterfly_db");
-        fs::create_dir_all(&path).ok();
-        path.join("catalog.json")
+    /// An empty catalog that is never written to disk
+    pub fn in_memory() -> Self {
+        Self {
+            data: Arc::new(RwLock::new(Self::default_data())),
+            storage_path: None,
+        }
     }
 
-    fn load_or_create(path: &PathBuf) -> CatalogData {
+    fn load_or_create(path: &Path) -> CatalogData {
         if path.exists() {
             match fs::read_to_string(path) {
                 Ok(content) => {
@@ -90,26 +217,34 @@ impl Catalog {
                 Err(_) => CatalogData::default(),
             }
         } else {
-            // Create default database
-            let mut data = CatalogData::default();
-            data.databases.insert(
-                "default".to_string(),
-                DatabaseSchema {
-                    name: "default".to_string(),
-                    tables: HashMap::new(),
-                },
-            );
-            data.current_database = Some("default".to_string());
-            data
+            Self::default_data()
         }
     }
 
+    /// A catalog holding just the empty "default" database
+    fn default_data() -> CatalogData {
+        let mut data = CatalogData::default();
+        data.databases.insert(
+            "default".to_string(),
+            DatabaseSchema {
+                name: "default".to_string(),
+                tables: HashMap::new(),
+                statistics: HashMap::new(),
+            },
+        );
+        data.current_database = Some("default".to_string());
+        data
+    }
+
     /// Persist catalog to disk
     pub fn save(&self) -> Result<(), String> {
+        let Some(storage_path) = &self.storage_path else {
+            return Ok(());
+        };
         let data = self.data.read().map_err(|e| e.to_string())?;
         let json = serde_json::to_string_pretty(&*data)
             .map_err(|e| e.to_string())?;
-        fs::write(&self.storage_path, json)
+        fs::write(storage_path, json)
             .map_err(|e| e.to_string())?;
         Ok(())
     }
@@ -130,6 +265,7 @@ impl Catalog {
             DatabaseSchema {
                 name: name.to_string(),
                 tables: HashMap::new(),
+                statistics: HashMap::new(),
             },
         );
         
@@ -170,6 +306,16 @@ impl Catalog {
         columns: Vec<ColumnSchema>,
         if_not_exists: bool,
     ) -> Result<(), String> {
+        self.create_table_from_schema(TableSchema::new(name.to_string(), columns), if_not_exists)
+    }
+
+    /// Create a table in the current database from a fully built schema
+    pub fn create_table_from_schema(
+        &self,
+        schema: TableSchema,
+        if_not_exists: bool,
+    ) -> Result<(), String> {
+        let name = schema.name.clone();
         let mut data = self.data.write().map_err(|e| e.to_string())?;
         
         let db_name = data.current_database.clone()
@@ -178,17 +324,14 @@ impl Catalog {
         let db = data.databases.get_mut(&db_name)
             .ok_or(format!("Database '{}' not found", db_name))?;
 
-        if db.tables.contains_key(name) {
+        if db.tables.contains_key(&name) {
             if if_not_exists {
                 return Ok(());
             }
             return Err(format!("Table '{}' already exists", name));
         }
 
-        db.tables.insert(
-            name.to_string(),
-            TableSchema::new(name.to_string(), columns),
-        );
+        db.tables.insert(name, schema);
 
         drop(data);
         self.save()
@@ -212,11 +355,70 @@ impl Catalog {
         }
 
         db.tables.remove(name);
+        db.statistics.remove(name);
+
+        drop(data);
+        self.save()
+    }
+
+    /// Add an index to a table in the current database. Index names are
+    /// unique across the database; a UNIQUE index also becomes a candidate key.
+    pub fn create_index(&self, table: &str, index: IndexSchema, if_not_exists: bool) -> Result<(), String> {
+        let mut data = self.data.write().map_err(|e| e.to_string())?;
+
+        let db_name = data.current_database.clone()
+            .ok_or("No database selected")?;
+
+        let db = data.databases.get_mut(&db_name)
+            .ok_or(format!("Database '{}' not found", db_name))?;
+
+        if db.tables.values().flat_map(|t| &t.indexes).any(|i| i.name == index.name) {
+            if if_not_exists {
+                return Ok(());
+            }
+            return Err(format!("Index '{}' already exists", index.name));
+        }
+
+        let schema = db.tables.get_mut(table)
+            .ok_or(format!("Table '{}' does not exist", table))?;
+        if let Some(missing) = index.columns.iter().find(|c| schema.get_column(c).is_none()) {
+            return Err(format!("Column '{}' does not exist in table '{}'", missing, table));
+        }
+        if index.unique && !schema.candidate_keys().contains(&index.columns) {
+            schema.unique_keys.push(index.columns.clone());
+        }
+        schema.indexes.push(index);
+
+        drop(data);
+        self.save()
+    }
+
+    /// Replace the statistics stored for a table
+    pub fn set_statistics(&self, table: &str, statistics: TableStatistics) -> Result<(), String> {
+        let mut data = self.data.write().map_err(|e| e.to_string())?;
+
+        let db_name = data.current_database.clone()
+            .ok_or("No database selected")?;
+
+        let db = data.databases.get_mut(&db_name)
+            .ok_or(format!("Database '{}' not found", db_name))?;
+
+        if !db.tables.contains_key(table) {
+            return Err(format!("Table '{}' does not exist", table));
+        }
+        db.statistics.insert(table.to_string(), statistics);
 
         drop(data);
         self.save()
     }
 
+    /// Statistics from the last ANALYZE of a table, if any
+    pub fn get_statistics(&self, table: &str) -> Option<TableStatistics> {
+        let data = self.data.read().ok()?;
+        let db_name = data.current_database.as_ref()?;
+        data.databases.get(db_name)?.statistics.get(table).cloned()
+    }
+
     /// Get a table schema
     pub fn get_table(&self, name: &str) -> Result<TableSchema, String> {
         let data = self.data.read().map_err(|e| e.to_string())?;
@@ -269,7 +471,33 @@ pub fn data_type_to_string(dt: &DataType) -> String {
 
 // Global catalog instance
 lazy_static::lazy_static! {
-    pub static ref CATALOG: Catalog = Catalog::new();
+    pub static ref CATALOG: Arc<Catalog> = Arc::new(Catalog::new());
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<Arc<Catalog>>> = const { RefCell::new(None) };
+}
+
+/// The catalog statements on this thread resolve names against: the one
+/// installed by `with_catalog`, or else the global `CATALOG`
+pub fn current() -> Arc<Catalog> {
+    CURRENT
+        .with(|current| current.borrow().clone())
+        .unwrap_or_else(|| CATALOG.clone())
+}
+
+/// Run `f` with `catalog` as this thread's catalog (see `engine::with_engine`)
+pub fn with_catalog<T>(catalog: Arc<Catalog>, f: impl FnOnce() -> T) -> T {
+    struct Restore(Option<Arc<Catalog>>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let previous = self.0.take();
+            CURRENT.with(|current| *current.borrow_mut() = previous);
+        }
+    }
+
+    let _restore = Restore(CURRENT.with(|current| current.borrow_mut().replace(catalog)));
+    f()
 }
 
 #[cfg(test)]
@@ -294,13 +522,14 @@ mod tests {
             DatabaseSchema {
                 name: "test_db".to_string(),
                 tables: HashMap::new(),
+                statistics: HashMap::new(),
             },
         );
         data.current_database = Some("test_db".to_string());
         
         Catalog {
             data: Arc::new(RwLock::new(data)),
-            storage_path,
+            storage_path: Some(storage_path),
         }
     }
 
@@ -385,6 +614,31 @@ mod tests {
         assert!(names.contains(&"email"));
     }
 
+    #[test]
+    fn test_table_schema_candidate_keys() {
+        let columns = vec![
+            ColumnSchema {
+                name: "id".to_string(),
+                data_type: "INTEGER".to_string(),
+                nullable: false,
+                is_primary_key: true,
+            },
+            ColumnSchema {
+                name: "email".to_string(),
+                data_type: "VARCHAR(255)".to_string(),
+                nullable: true,
+                is_primary_key: false,
+            },
+        ];
+
+        let mut table = TableSchema::new("users".to_string(), columns);
+        assert_eq!(table.candidate_keys(), vec![vec!["id".to_string()]]);
+
+        table.unique_keys.push(vec!["email".to_string()]);
+        assert_eq!(table.primary_key(), vec!["id".to_string()]);
+        assert_eq!(table.candidate_keys().len(), 2);
+    }
+
     #[test]
     fn test_catalog_create_table() {
         let catalog = create_test_catalog();
@@ -510,6 +764,38 @@ mod tests {
         assert!(tables.contains(&"table2".to_string()));
     }
 
+    #[test]
+    fn test_catalog_indexes_and_statistics() {
+        let catalog = create_test_catalog();
+        let columns = vec![ColumnSchema {
+            name: "email".to_string(),
+            data_type: "TEXT".to_string(),
+            nullable: true,
+            is_primary_key: false,
+        }];
+        catalog.create_table("people", columns, false).unwrap();
+
+        let index = |name: &str, column: &str| IndexSchema {
+            name: name.to_string(),
+            columns: vec![column.to_string()],
+            unique: true,
+        };
+        catalog.create_index("people", index("people_email", "email"), false).unwrap();
+        assert!(catalog.create_index("people", index("people_email", "email"), false).is_err());
+        assert!(catalog.create_index("people", index("people_email", "email"), true).is_ok());
+        assert!(catalog.create_index("people", index("people_age", "age"), false).is_err());
+
+        let table = catalog.get_table("people").unwrap();
+        assert_eq!(table.indexes_on("email").count(), 1);
+        assert_eq!(table.candidate_keys(), vec![vec!["email".to_string()]]);
+
+        let stats = TableStatistics::collect(&[], &["email".to_string()]);
+        catalog.set_statistics("people", stats.clone()).unwrap();
+        assert_eq!(catalog.get_statistics("people"), Some(stats));
+        catalog.drop_table("people", false).unwrap();
+        assert_eq!(catalog.get_statistics("people"), None);
+    }
+
     #[test]
     fn test_catalog_create_database() {
         let catalog = create_test_catalog();
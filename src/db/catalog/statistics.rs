@@ -0,0 +1,197 @@
+// Planner statistics collected by ANALYZE
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::ops::Bound;
+
+use crate::db::storage::{KeyRange, Row, Value};
+
+/// Number of equal-height buckets in a column histogram
+pub const HISTOGRAM_BUCKETS: usize = 10;
+
+/// Per-column statistics
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ColumnStatistics {
+    /// Number of distinct non-NULL values
+    pub distinct_count: usize,
+    /// Fraction of rows where the column is NULL
+    pub null_fraction: f64,
+    /// Equi-depth histogram over the non-NULL values: `HISTOGRAM_BUCKETS + 1`
+    /// ascending bounds (fewer for small tables), so each pair of neighbouring
+    /// bounds encloses roughly the same number of rows
+    pub histogram: Vec<Value>,
+}
+
+/// Statistics for one table, stored in the catalog next to its `TableSchema`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TableStatistics {
+    pub row_count: usize,
+    pub analyzed_at: String,
+    pub columns: HashMap<String, ColumnStatistics>,
+}
+
+impl TableStatistics {
+    /// Compute statistics for `columns` over every row of a table
+    pub fn collect(rows: &[Row], columns: &[String]) -> Self {
+        let columns = columns
+            .iter()
+            .map(|column| {
+                let values = rows.iter().map(|row| row.get(column).unwrap_or(&Value::Null));
+                (column.clone(), ColumnStatistics::from_values(values, rows.len()))
+            })
+            .collect();
+
+        Self {
+            row_count: rows.len(),
+            analyzed_at: chrono::Local::now().to_rfc3339(),
+            columns,
+        }
+    }
+}
+
+impl ColumnStatistics {
+    fn from_values<'a>(values: impl Iterator<Item = &'a Value>, row_count: usize) -> Self {
+        let mut present: Vec<&Value> = values.filter(|v| !matches!(v, Value::Null)).collect();
+        present.sort_by(|a, b| a.total_cmp(b));
+
+        let distinct_count = match present.first() {
+            Some(_) => 1 + present.windows(2).filter(|w| w[0].total_cmp(w[1]) != Ordering::Equal).count(),
+            None => 0,
+        };
+
+        let histogram = if present.is_empty() {
+            Vec::new()
+        } else {
+            let buckets = HISTOGRAM_BUCKETS.min(present.len() - 1).max(1);
+            (0..=buckets)
+                .map(|i| present[i * (present.len() - 1) / buckets].clone())
+                .collect()
+        };
+
+        let null_fraction = if row_count == 0 {
+            0.0
+        } else {
+            (row_count - present.len()) as f64 / row_count as f64
+        };
+
+        Self {
+            distinct_count,
+            null_fraction,
+            histogram,
+        }
+    }
+
+    /// Estimated fraction of rows whose value equals `value`
+    pub fn equality_selectivity(&self, value: &Value) -> f64 {
+        let (Some(min), Some(max)) = (self.histogram.first(), self.histogram.last()) else {
+            return 0.0;
+        };
+        if matches!(value, Value::Null)
+            || value.total_cmp(min) == Ordering::Less
+            || value.total_cmp(max) == Ordering::Greater
+        {
+            return 0.0;
+        }
+        (1.0 - self.null_fraction) / self.distinct_count.max(1) as f64
+    }
+
+    /// Estimated fraction of rows whose value falls inside `range`
+    pub fn range_selectivity(&self, range: &KeyRange) -> f64 {
+        if let (Bound::Included(low), Bound::Included(high)) = (&range.lower, &range.upper) {
+            if low.total_cmp(high) == Ordering::Equal {
+                return self.equality_selectivity(low);
+            }
+        }
+        if range.is_empty() || self.histogram.is_empty() {
+            return 0.0;
+        }
+
+        let upper = match &range.upper {
+            Bound::Included(v) => self.fraction_below(v, true),
+            Bound::Excluded(v) => self.fraction_below(v, false),
+            Bound::Unbounded => 1.0,
+        };
+        let lower = match &range.lower {
+            Bound::Included(v) => self.fraction_below(v, false),
+            Bound::Excluded(v) => self.fraction_below(v, true),
+            Bound::Unbounded => 0.0,
+        };
+        (upper - lower).clamp(0.0, 1.0) * (1.0 - self.null_fraction)
+    }
+
+    /// Fraction of non-NULL values below `value` (or at most `value` when
+    /// `inclusive`), interpolating linearly inside numeric buckets
+    fn fraction_below(&self, value: &Value, inclusive: bool) -> f64 {
+        let bounds = &self.histogram;
+        let buckets = (bounds.len() - 1).max(1) as f64;
+        let position = bounds.partition_point(|b| match b.total_cmp(value) {
+            Ordering::Less => true,
+            Ordering::Equal => inclusive,
+            Ordering::Greater => false,
+        });
+        if position == 0 {
+            return 0.0;
+        }
+        if position == bounds.len() {
+            return 1.0;
+        }
+
+        let (low, high) = (&bounds[position - 1], &bounds[position]);
+        let within = match (as_f64(low), as_f64(high), as_f64(value)) {
+            (Some(l), Some(h), Some(v)) if h > l => ((v - l) / (h - l)).clamp(0.0, 1.0),
+            _ => 0.5,
+        };
+        (position - 1) as f64 / buckets + within / buckets
+    }
+}
+
+fn as_f64(value: &Value) -> Option<f64> {
+    match value {
+        Value::Integer(i) => Some(*i as f64),
+        Value::Float(f) => Some(*f),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(values: impl IntoIterator<Item = Value>) -> Vec<Row> {
+        values
+            .into_iter()
+            .map(|v| Row::from([("x".to_string(), v)]))
+            .collect()
+    }
+
+    #[test]
+    fn test_collect_counts_distinct_and_nulls() {
+        let stats = TableStatistics::collect(
+            &rows([Value::Integer(1), Value::Integer(1), Value::Null, Value::Integer(3)]),
+            &["x".to_string(), "missing".to_string()],
+        );
+        assert_eq!(stats.row_count, 4);
+        let x = &stats.columns["x"];
+        assert_eq!(x.distinct_count, 2);
+        assert_eq!(x.null_fraction, 0.25);
+        assert_eq!(x.histogram.first(), Some(&Value::Integer(1)));
+        assert_eq!(x.histogram.last(), Some(&Value::Integer(3)));
+        assert_eq!(stats.columns["missing"].null_fraction, 1.0);
+    }
+
+    #[test]
+    fn test_equi_depth_histogram_selectivity() {
+        let stats = TableStatistics::collect(&rows((0..1000).map(Value::Integer)), &["x".to_string()]);
+        let x = &stats.columns["x"];
+        assert_eq!(x.histogram.len(), HISTOGRAM_BUCKETS + 1);
+
+        let below_100 = KeyRange {
+            lower: Bound::Unbounded,
+            upper: Bound::Excluded(Value::Integer(100)),
+        };
+        assert!((x.range_selectivity(&below_100) - 0.1).abs() < 0.01);
+        assert!((x.equality_selectivity(&Value::Integer(5)) - 0.001).abs() < 1e-9);
+        assert_eq!(x.equality_selectivity(&Value::Integer(5000)), 0.0);
+        assert_eq!(x.range_selectivity(&KeyRange::full()), 1.0);
+    }
+}
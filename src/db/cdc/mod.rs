@@ -0,0 +1,585 @@
+// Change data capture - a durable log of committed row changes for other
+// systems to read at their own pace
+//
+// Every commit to a table of the process-wide database gets the next commit
+// sequence number and is appended to the log as one entry. Consumers are
+// registered by name; a consumer's offset is the last sequence number it
+// acknowledged, and an entry is kept until every consumer has acknowledged
+// it. Reads start after the offset, so anything not acknowledged is read
+// again (delivery is at least once).
+//
+// Files, under `default_dir()/cdc`:
+//   changes.log   one JSON entry per line, written before the commit it
+//                 records reaches storage and synced before the writer is
+//                 told the commit succeeded
+//   offsets.json  the consumers' offsets and the last sequence number used
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+
+use crate::config::CdcConfig;
+use crate::db::database::default_dir;
+use crate::db::storage::changes::{self, Change, ChangeListener, Commit};
+use crate::db::storage::log_file;
+use crate::db::storage::{Row, Value};
+use crate::{error, warn};
+
+/// Entries the log file holds before a rewrite is considered
+const COMPACT_MIN_ENTRIES: usize = 1024;
+/// Entries one read returns when no limit is given
+pub const DEFAULT_READ_LIMIT: usize = 1000;
+
+type JsonRow = HashMap<String, serde_json::Value>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    Insert,
+    Update,
+    Delete,
+}
+
+/// One row changed by a commit. Inserts have no before image and deletes
+/// no after image.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RowChange {
+    pub op: Operation,
+    pub before: Option<JsonRow>,
+    pub after: Option<JsonRow>,
+}
+
+/// The changes of one commit
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub seq: u64,
+    pub table: String,
+    pub changes: Vec<RowChange>,
+}
+
+fn json_value(value: &Value) -> serde_json::Value {
+    match value {
+        Value::Null => serde_json::Value::Null,
+        Value::Integer(i) => serde_json::Value::from(*i),
+        Value::Float(f) => serde_json::Value::from(*f),
+        Value::Text(s) => serde_json::Value::from(s.as_str()),
+        Value::Boolean(b) => serde_json::Value::from(*b),
+    }
+}
+
+fn image(row: &Row) -> Option<JsonRow> {
+    Some(row.iter().map(|(column, value)| (column.clone(), json_value(value))).collect())
+}
+
+impl RowChange {
+    fn from_change(change: &Change) -> Self {
+        match change {
+            Change::Insert { row } => RowChange {
+                op: Operation::Insert,
+                before: None,
+                after: image(row),
+            },
+            Change::Update { old, new } => RowChange {
+                op: Operation::Update,
+                before: image(old),
+                after: image(new),
+            },
+            Change::Delete { row } => RowChange {
+                op: Operation::Delete,
+                before: image(row),
+                after: None,
+            },
+        }
+    }
+}
+
+/// Where consumers are, as kept in offsets.json
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Offsets {
+    /// Last commit sequence number handed out
+    pub last_seq: u64,
+    /// Last sequence number each consumer acknowledged
+    pub consumers: BTreeMap<String, u64>,
+}
+
+struct State {
+    dir: PathBuf,
+    file: File,
+    /// Entries in the file, retained or not
+    logged: usize,
+    /// Entries some consumer has yet to acknowledge, oldest first
+    entries: VecDeque<Entry>,
+    offsets: Offsets,
+    /// Entry written for a commit not yet made, with the file's length
+    /// before it
+    pending: Option<(u64, Entry)>,
+}
+
+impl State {
+    fn offset(&self, consumer: &str) -> Result<u64, String> {
+        self.offsets
+            .consumers
+            .get(consumer)
+            .copied()
+            .ok_or_else(|| format!("Unknown change log consumer '{}'", consumer))
+    }
+
+    fn save_offsets(&self) -> Result<(), String> {
+        let path = self.dir.join("offsets.json");
+        let temp = self.dir.join("offsets.json.tmp");
+        let json = serde_json::to_string_pretty(&self.offsets).map_err(|e| e.to_string())?;
+        let mut file = File::create(&temp).map_err(|e| e.to_string())?;
+        file.write_all(json.as_bytes()).map_err(|e| e.to_string())?;
+        file.sync_all().map_err(|e| e.to_string())?;
+        fs::rename(&temp, &path).map_err(|e| e.to_string())
+    }
+
+    /// Drop the entries every consumer has acknowledged (all of them when
+    /// nobody is registered), then save the offsets and, once the file is
+    /// mostly dropped entries, rewrite it
+    fn trim(&mut self) -> Result<(), String> {
+        let acknowledged = self.offsets.consumers.values().min().copied().unwrap_or(u64::MAX);
+        while self.entries.front().is_some_and(|entry| entry.seq <= acknowledged) {
+            self.entries.pop_front();
+        }
+        self.save_offsets()?;
+        // A rewrite would lose the pending entry's line
+        if self.pending.is_some() || self.logged < COMPACT_MIN_ENTRIES || self.logged < self.entries.len() * 2 {
+            return Ok(());
+        }
+
+        let path = self.dir.join("changes.log");
+        let temp = self.dir.join("changes.log.tmp");
+        let mut file = File::create(&temp).map_err(|e| e.to_string())?;
+        for entry in &self.entries {
+            let line = serde_json::to_string(entry).map_err(|e| e.to_string())?;
+            writeln!(file, "{}", line).map_err(|e| e.to_string())?;
+        }
+        file.sync_all().map_err(|e| e.to_string())?;
+        fs::rename(&temp, &path).map_err(|e| e.to_string())?;
+        self.file = OpenOptions::new().append(true).open(&path).map_err(|e| e.to_string())?;
+        self.logged = self.entries.len();
+        Ok(())
+    }
+
+    /// Write the entry for `commit` to the file, leaving it pending. A
+    /// failed write is cut off again so no torn line is left behind.
+    fn write(&mut self, commit: &Commit) -> Result<(), String> {
+        let entry = Entry {
+            seq: self.offsets.last_seq + 1,
+            table: commit.table.clone(),
+            changes: commit.changes.iter().map(RowChange::from_change).collect(),
+        };
+        let mut line = serde_json::to_string(&entry).map_err(|e| e.to_string())?;
+        line.push('\n');
+        let len = self.file.metadata().map_err(|e| e.to_string())?.len();
+        log_file::append(&mut self.file, line.as_bytes())?;
+        self.pending = Some((len, entry));
+        Ok(())
+    }
+
+    /// Make the pending entry (or, if none was written, a new one for
+    /// `commit`) part of the log
+    fn append(&mut self, commit: &Commit) -> Result<(), String> {
+        if self.pending.is_none() {
+            self.write(commit)?;
+        }
+        if let Some((_, entry)) = self.pending.take() {
+            self.offsets.last_seq = entry.seq;
+            self.logged += 1;
+            self.entries.push_back(entry);
+        }
+        Ok(())
+    }
+
+    /// Drop the pending entry, whose commit never happened
+    fn discard(&mut self) -> Result<(), String> {
+        match self.pending.take() {
+            Some((len, _)) => self.file.set_len(len).map_err(|e| e.to_string()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The change log and its consumers
+pub struct ChangeLog {
+    state: Mutex<State>,
+    /// Last sequence number known to be synced to disk; held while syncing
+    synced: Mutex<u64>,
+    /// Woken whenever an entry is appended
+    appended: Notify,
+}
+
+impl ChangeLog {
+    /// Open (or create) the log kept in directory `dir`
+    pub fn open(dir: &Path) -> Result<ChangeLog, String> {
+        fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        let mut offsets: Offsets = match fs::read_to_string(dir.join("offsets.json")) {
+            Ok(json) => serde_json::from_str(&json).map_err(|e| format!("Invalid CDC offsets: {}", e))?,
+            Err(_) => Offsets::default(),
+        };
+
+        let (file, logged_entries) = log_file::open::<Entry>(&dir.join("changes.log"))?;
+        let logged = logged_entries.len();
+        for entry in &logged_entries {
+            offsets.last_seq = offsets.last_seq.max(entry.seq);
+        }
+        let entries = VecDeque::from(logged_entries);
+
+        let mut state = State {
+            dir: dir.to_path_buf(),
+            file,
+            logged,
+            entries,
+            offsets,
+            pending: None,
+        };
+        state.trim()?;
+        Ok(ChangeLog {
+            synced: Mutex::new(state.offsets.last_seq),
+            state: Mutex::new(state),
+            appended: Notify::new(),
+        })
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Register `consumer`, starting from the next commit, and return its
+    /// offset. A consumer already registered keeps its offset.
+    pub fn register(&self, consumer: &str) -> Result<u64, String> {
+        if consumer.is_empty() {
+            return Err("A change log consumer needs a name".to_string());
+        }
+        let mut state = self.state();
+        if let Ok(offset) = state.offset(consumer) {
+            return Ok(offset);
+        }
+        let last_seq = state.offsets.last_seq;
+        state.offsets.consumers.insert(consumer.to_string(), last_seq);
+        state.save_offsets()?;
+        Ok(last_seq)
+    }
+
+    /// Forget `consumer`, releasing the entries only it was holding.
+    /// Returns whether it was registered.
+    pub fn unregister(&self, consumer: &str) -> Result<bool, String> {
+        let mut state = self.state();
+        if state.offsets.consumers.remove(consumer).is_none() {
+            return Ok(false);
+        }
+        state.trim()?;
+        Ok(true)
+    }
+
+    /// Up to `limit` entries after `after` (by default, after the consumer's
+    /// offset). Reading does not move the offset.
+    pub fn read(&self, consumer: &str, after: Option<u64>, limit: usize) -> Result<Vec<Entry>, String> {
+        let state = self.state();
+        let from = after.unwrap_or(0).max(state.offset(consumer)?);
+        Ok(state
+            .entries
+            .iter()
+            .skip_while(|entry| entry.seq <= from)
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    /// Move `consumer`'s offset up to `seq`, having processed every entry up
+    /// to it. Offsets never move back.
+    pub fn acknowledge(&self, consumer: &str, seq: u64) -> Result<u64, String> {
+        let mut state = self.state();
+        let offset = state.offset(consumer)?;
+        if seq > state.offsets.last_seq {
+            return Err(format!("Cannot acknowledge {}: the last commit is {}", seq, state.offsets.last_seq));
+        }
+        if seq <= offset {
+            return Ok(offset);
+        }
+        state.offsets.consumers.insert(consumer.to_string(), seq);
+        state.trim()?;
+        Ok(seq)
+    }
+
+    /// Offsets of every consumer and the last sequence number
+    pub fn offsets(&self) -> Offsets {
+        self.state().offsets.clone()
+    }
+
+    /// Entries still held for some consumer
+    pub fn retained(&self) -> usize {
+        self.state().entries.len()
+    }
+}
+
+impl ChangeListener for ChangeLog {
+    /// Everything is captured while anyone is registered, nothing otherwise
+    fn watches(&self, _table: &str) -> bool {
+        !self.state().offsets.consumers.is_empty()
+    }
+
+    /// Writes the commit's entry to the file, so a commit that reaches
+    /// storage is never missing from the log
+    fn prepare(&self, commit: &Commit) -> Result<(), String> {
+        let mut state = self.state();
+        if state.offsets.consumers.is_empty() {
+            return Ok(());
+        }
+        state
+            .write(commit)
+            .map_err(|e| format!("Failed to append commit on {} to the change log: {}", commit.table, e))
+    }
+
+    fn aborted(&self, commit: &Commit) {
+        if let Err(e) = self.state().discard() {
+            error!(format!("Failed to drop aborted commit on {} from the change log: {}", commit.table, e));
+        }
+    }
+
+    fn committed(&self, commit: &Commit) {
+        let mut state = self.state();
+        if state.pending.is_none() && state.offsets.consumers.is_empty() {
+            return;
+        }
+        if let Err(e) = state.append(commit) {
+            error!(format!("Failed to append commit on {} to the change log: {}", commit.table, e));
+            return;
+        }
+        drop(state);
+        self.appended.notify_waiters();
+    }
+
+    /// Syncs the file up to the last entry. Writers that commit while one
+    /// syncs wait for it and then find their entries synced already.
+    fn settle(&self) -> Result<(), String> {
+        let (file, last_seq) = {
+            let state = self.state();
+            (state.file.try_clone().map_err(|e| e.to_string())?, state.offsets.last_seq)
+        };
+        let mut synced = self.synced.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if *synced >= last_seq {
+            return Ok(());
+        }
+        file.sync_data().map_err(|e| format!("Failed to sync the change log: {}", e))?;
+        *synced = last_seq;
+        Ok(())
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref CHANGE_LOG: Result<Arc<ChangeLog>, String> = ChangeLog::open(&default_dir().join("cdc")).map(|log| {
+        let log = Arc::new(log);
+        changes::listen(log.clone());
+        log
+    });
+}
+
+/// The process-wide change log, capturing commits from the first call on
+pub fn log() -> Result<Arc<ChangeLog>, String> {
+    CHANGE_LOG.clone()
+}
+
+/// Copy the entries `consumer` has not acknowledged to the file at `path`,
+/// one JSON entry per line, acknowledging each batch once it is synced.
+/// Entries written just before a crash may be written again on restart.
+pub async fn run_file_sink(log: Arc<ChangeLog>, consumer: String, path: PathBuf) -> Result<(), String> {
+    let mut file = {
+        let (log, consumer) = (Arc::clone(&log), consumer.clone());
+        blocking(move || {
+            log.register(&consumer)?;
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+            }
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .map_err(|e| format!("{}: {}", path.display(), e))
+        })
+        .await?
+    };
+    loop {
+        // Wait on the notification before reading, so an append between the
+        // read and the wait is not missed
+        let appended = log.appended.notified();
+        let entries = log.read(&consumer, None, DEFAULT_READ_LIMIT)?;
+        let Some(last) = entries.last().map(|entry| entry.seq) else {
+            appended.await;
+            continue;
+        };
+        let mut lines = String::new();
+        for entry in &entries {
+            lines.push_str(&serde_json::to_string(entry).map_err(|e| e.to_string())?);
+            lines.push('\n');
+        }
+        let (log, consumer) = (Arc::clone(&log), consumer.clone());
+        file = blocking(move || {
+            file.write_all(lines.as_bytes()).map_err(|e| e.to_string())?;
+            file.sync_data().map_err(|e| e.to_string())?;
+            log.acknowledge(&consumer, last)?;
+            Ok(file)
+        })
+        .await?;
+    }
+}
+
+/// Run file work on the blocking pool rather than an async worker
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> Result<T, String> + Send + 'static) -> Result<T, String> {
+    tokio::task::spawn_blocking(f).await.map_err(|e| e.to_string())?
+}
+
+/// Open the change log and start the configured file sinks. Consumers that
+/// registered in an earlier run start being fed again from here.
+pub fn start(config: &CdcConfig) -> Vec<tokio::task::JoinHandle<()>> {
+    let log = match log() {
+        Ok(log) => log,
+        Err(e) => {
+            warn!(format!("Change log not available: {}", e));
+            return Vec::new();
+        }
+    };
+    config
+        .file_sinks
+        .iter()
+        .map(|sink| {
+            let (log, consumer, path) = (Arc::clone(&log), sink.consumer.clone(), PathBuf::from(&sink.path));
+            tokio::spawn(async move {
+                if let Err(e) = run_file_sink(log, consumer.clone(), path).await {
+                    warn!(format!("Change log file sink for {} stopped: {}", consumer, e));
+                }
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("butterfly_cdc_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn commit(table: &str, id: i64) -> Commit {
+        Commit {
+            table: table.to_string(),
+            changes: vec![Change::Insert {
+                row: HashMap::from([("id".to_string(), Value::Integer(id))]),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_entries_are_retained_until_every_consumer_acknowledges() {
+        let dir = temp_dir("retention");
+        let log = ChangeLog::open(&dir).unwrap();
+        log.committed(&commit("t", 0));
+        assert_eq!(log.retained(), 0);
+
+        assert_eq!(log.register("search").unwrap(), 0);
+        assert_eq!(log.register("warehouse").unwrap(), 0);
+        for id in 1..=3 {
+            log.committed(&commit("t", id));
+        }
+        let entries = log.read("search", None, 2).unwrap();
+        assert_eq!(entries.iter().map(|entry| entry.seq).collect::<Vec<_>>(), [1, 2]);
+        assert_eq!(entries[0].changes[0].op, Operation::Insert);
+        assert_eq!(entries[0].changes[0].after.as_ref().unwrap()["id"], serde_json::json!(1));
+
+        assert_eq!(log.acknowledge("search", 2).unwrap(), 2);
+        assert_eq!(log.read("search", None, 10).unwrap().len(), 1);
+        assert_eq!(log.retained(), 3);
+        assert!(log.acknowledge("search", 4).is_err());
+        assert!(log.read("nobody", None, 10).is_err());
+
+        // Offsets and unacknowledged entries survive a restart
+        drop(log);
+        let log = ChangeLog::open(&dir).unwrap();
+        assert_eq!(log.offsets().consumers, BTreeMap::from([("search".to_string(), 2), ("warehouse".to_string(), 0)]));
+        assert_eq!(log.read("warehouse", None, 10).unwrap().len(), 3);
+
+        log.acknowledge("warehouse", 3).unwrap();
+        assert_eq!(log.retained(), 1);
+        assert!(log.unregister("search").unwrap());
+        assert_eq!(log.retained(), 0);
+
+        log.committed(&commit("t", 4));
+        let entries = log.read("warehouse", None, 10).unwrap();
+        assert_eq!(entries.iter().map(|entry| entry.seq).collect::<Vec<_>>(), [4]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_prepared_entries_are_kept_only_if_committed() {
+        let dir = temp_dir("prepared");
+        let log = ChangeLog::open(&dir).unwrap();
+        log.register("search").unwrap();
+
+        log.prepare(&commit("t", 1)).unwrap();
+        log.aborted(&commit("t", 1));
+        log.prepare(&commit("t", 2)).unwrap();
+        log.committed(&commit("t", 2));
+        log.settle().unwrap();
+        let entries = log.read("search", None, 10).unwrap();
+        assert_eq!(entries.iter().map(|entry| entry.seq).collect::<Vec<_>>(), [1]);
+        assert_eq!(entries[0].changes[0].after.as_ref().unwrap()["id"], serde_json::json!(2));
+
+        drop(log);
+        let log = ChangeLog::open(&dir).unwrap();
+        assert_eq!(log.read("search", None, 10).unwrap(), entries);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_entries_after_a_torn_tail_survive_reopen() {
+        let dir = temp_dir("torn");
+        let log = ChangeLog::open(&dir).unwrap();
+        log.register("search").unwrap();
+        log.committed(&commit("t", 1));
+        drop(log);
+        // A crash in the middle of appending the next entry
+        let mut file = OpenOptions::new().append(true).open(dir.join("changes.log")).unwrap();
+        file.write_all(b"{\"seq\":2,\"ta").unwrap();
+        drop(file);
+
+        ChangeLog::open(&dir).unwrap().committed(&commit("t", 2));
+        let log = ChangeLog::open(&dir).unwrap();
+        let entries = log.read("search", None, 10).unwrap();
+        assert_eq!(entries.iter().map(|entry| entry.seq).collect::<Vec<_>>(), [1, 2]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_file_sink_copies_and_acknowledges() {
+        let dir = temp_dir("sink");
+        let log = Arc::new(ChangeLog::open(&dir).unwrap());
+        let path = dir.join("out").join("sink.jsonl");
+        let sink = tokio::spawn(run_file_sink(Arc::clone(&log), "files".to_string(), path.clone()));
+        while log.offsets().consumers.is_empty() {
+            tokio::task::yield_now().await;
+        }
+        log.committed(&commit("t", 1));
+        log.committed(&commit("t", 2));
+        while log.offsets().consumers["files"] < 2 {
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        }
+        sink.abort();
+
+        let lines: Vec<Entry> = fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.iter().map(|entry| entry.seq).collect::<Vec<_>>(), [1, 2]);
+        assert_eq!(log.retained(), 0);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,329 @@
+// Embedded databases - a catalog, its storage and a session bundled behind
+// one handle, so several independent databases can live in one process
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::db::catalog::{self, Catalog};
+use crate::db::executor::ExecutionResult;
+use crate::db::session::Session;
+use crate::db::storage::lsm::LsmEngine;
+use crate::db::storage::memory::MemoryEngine;
+use crate::db::storage::{engine, Storage, Value};
+
+/// Directory the process-wide database (`CATALOG` and `STORAGE`) lives in:
+/// `$HOME/.butterfly_db`, or under unit tests a directory of the test
+/// process's own
+pub fn default_dir() -> PathBuf {
+    if cfg!(test) {
+        return std::env::temp_dir().join(format!("butterfly_db_test_{}", std::process::id()));
+    }
+    let home = std::env::var("HOME").unwrap_or_else(|_| ".".to_string());
+    PathBuf::from(home).join(".butterfly_db")
+}
+
+/// Counter for the scratch directories of in-memory databases
+static SCRATCH_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// A database owned by the embedding program. Statements run through a
+/// handle see only its tables; handles share nothing with each other or with
+/// the process-wide database the server uses.
+pub struct Database {
+    catalog: Arc<Catalog>,
+    storage: Arc<Storage>,
+    session: Mutex<Session>,
+    /// Directory LSM tables of an in-memory database are kept in, removed
+    /// when the handle is dropped
+    scratch: Option<PathBuf>,
+}
+
+impl Database {
+    /// Open (or create) the database kept in directory `path`
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, String> {
+        let dir = path.as_ref();
+        std::fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        let catalog = Arc::new(Catalog::open(dir));
+        Ok(Self {
+            storage: Arc::new(Storage::open(dir, catalog.clone())),
+            catalog,
+            session: Mutex::new(Session::new()),
+            scratch: None,
+        })
+    }
+
+    /// A database that is gone once the handle is dropped. Row and columnar
+    /// tables never touch the disk; LSM tables use a temporary directory.
+    pub fn in_memory() -> Self {
+        let scratch = std::env::temp_dir().join(format!(
+            "butterfly_db_mem_{}_{}",
+            std::process::id(),
+            SCRATCH_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let catalog = Arc::new(Catalog::in_memory());
+        Self {
+            storage: Arc::new(Storage::with_engines(
+                MemoryEngine::in_memory(),
+                LsmEngine::new(scratch.clone()),
+                catalog.clone(),
+            )),
+            catalog,
+            session: Mutex::new(Session::new()),
+            scratch: Some(scratch),
+        }
+    }
+
+    /// Run `f` on this database's session with its catalog and storage
+    /// installed for the calling thread
+    fn run(&self, f: impl FnOnce(&mut Session) -> ExecutionResult) -> Result<ExecutionResult, String> {
+        let mut session = self.session.lock().map_err(|e| e.to_string())?;
+        let result = catalog::with_catalog(self.catalog.clone(), || {
+            engine::with_engine(self.storage.clone(), || f(&mut session))
+        });
+        match result {
+            ExecutionResult::Error { message } => Err(message),
+            result => Ok(result),
+        }
+    }
+
+    /// Execute one SQL statement
+    pub fn execute(&self, sql: &str) -> Result<ExecutionResult, String> {
+        self.run(|session| session.execute_sql(sql))
+    }
+
+    /// Execute one SQL statement with `$n` / `?` placeholders bound to `params`
+    pub fn execute_with_params(&self, sql: &str, params: &[Value]) -> Result<ExecutionResult, String> {
+        self.run(|session| session.execute_with_params(sql, params))
+    }
+
+    /// Run a statement that returns rows (a query, or a write with RETURNING)
+    pub fn query(&self, sql: &str) -> Result<Rows, String> {
+        Rows::from_result(self.execute(sql)?)
+    }
+
+    /// `query` with `$n` / `?` placeholders bound to `params`
+    pub fn query_with_params(&self, sql: &str, params: &[Value]) -> Result<Rows, String> {
+        Rows::from_result(self.execute_with_params(sql, params)?)
+    }
+
+    /// Names of the tables in the current database
+    pub fn tables(&self) -> Result<Vec<String>, String> {
+        self.catalog.list_tables()
+    }
+}
+
+impl Drop for Database {
+    fn drop(&mut self) {
+        if let Some(scratch) = &self.scratch {
+            let _ = std::fs::remove_dir_all(scratch);
+        }
+    }
+}
+
+/// Rows returned by `Database::query`, in output order
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Rows {
+    columns: Arc<[String]>,
+    rows: Vec<ResultRow>,
+}
+
+impl Rows {
+    fn from_result(result: ExecutionResult) -> Result<Self, String> {
+        let ExecutionResult::Rows { columns, rows } = result else {
+            return Err("Statement did not return rows".to_string());
+        };
+        let columns: Arc<[String]> = columns.into();
+        let rows = rows
+            .iter()
+            .map(|row| {
+                let values = columns
+                    .iter()
+                    .map(|c| row.get(c).map_or(Ok(Value::Null), Value::from_json))
+                    .collect::<Result<Vec<_>, String>>()?;
+                Ok(ResultRow {
+                    columns: columns.clone(),
+                    values,
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(Self { columns, rows })
+    }
+
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&ResultRow> {
+        self.rows.get(index)
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, ResultRow> {
+        self.rows.iter()
+    }
+}
+
+impl IntoIterator for Rows {
+    type Item = ResultRow;
+    type IntoIter = std::vec::IntoIter<ResultRow>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.rows.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Rows {
+    type Item = &'a ResultRow;
+    type IntoIter = std::slice::Iter<'a, ResultRow>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.rows.iter()
+    }
+}
+
+/// One row of a query result
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResultRow {
+    columns: Arc<[String]>,
+    values: Vec<Value>,
+}
+
+impl ResultRow {
+    /// Raw value of `column`
+    pub fn value(&self, column: &str) -> Option<&Value> {
+        let index = self.columns.iter().position(|c| c == column)?;
+        self.values.get(index)
+    }
+
+    /// Value of `column` converted to `T`, e.g. `row.get::<i64>("id")`
+    pub fn get<T: FromValue>(&self, column: &str) -> Result<T, String> {
+        let value = self
+            .value(column)
+            .ok_or_else(|| format!("Column '{}' is not in the result", column))?;
+        T::from_value(value).map_err(|e| format!("Column '{}': {}", column, e))
+    }
+
+    /// Values in column order
+    pub fn values(&self) -> &[Value] {
+        &self.values
+    }
+}
+
+/// Types a result value can be read as
+pub trait FromValue: Sized {
+    fn from_value(value: &Value) -> Result<Self, String>;
+}
+
+impl FromValue for Value {
+    fn from_value(value: &Value) -> Result<Self, String> {
+        Ok(value.clone())
+    }
+}
+
+impl FromValue for i64 {
+    fn from_value(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::Integer(i) => Ok(*i),
+            other => Err(format!("expected an integer, got {:?}", other)),
+        }
+    }
+}
+
+impl FromValue for f64 {
+    fn from_value(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::Float(f) => Ok(*f),
+            Value::Integer(i) => Ok(*i as f64),
+            other => Err(format!("expected a number, got {:?}", other)),
+        }
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::Boolean(b) => Ok(*b),
+            other => Err(format!("expected a boolean, got {:?}", other)),
+        }
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::Text(s) => Ok(s.clone()),
+            other => Err(format!("expected text, got {:?}", other)),
+        }
+    }
+}
+
+/// NULL reads as None
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::Null => Ok(None),
+            value => T::from_value(value).map(Some),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_in_memory_databases_are_independent() {
+        let first = Database::in_memory();
+        let second = Database::in_memory();
+        first.execute("CREATE TABLE embedded_items (id INT PRIMARY KEY, name TEXT)").unwrap();
+        first.execute("INSERT INTO embedded_items VALUES (1, 'first')").unwrap();
+        second.execute("CREATE TABLE embedded_items (id INT PRIMARY KEY, name TEXT, price FLOAT)").unwrap();
+        second.execute("INSERT INTO embedded_items VALUES (1, 'second', 2.5), (2, 'other', NULL)").unwrap();
+
+        let rows = first.query("SELECT id, name FROM embedded_items").unwrap();
+        assert_eq!(rows.columns(), ["id", "name"]);
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows.get(0).unwrap().get::<String>("name").unwrap(), "first");
+
+        let rows = second.query("SELECT * FROM embedded_items ORDER BY id").unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows.get(0).unwrap().get::<f64>("price").unwrap(), 2.5);
+        assert_eq!(rows.get(1).unwrap().get::<Option<f64>>("price").unwrap(), None);
+        assert!(rows.get(0).unwrap().get::<i64>("name").is_err());
+        assert!(rows.get(0).unwrap().get::<i64>("missing").is_err());
+
+        // Neither touched the process-wide catalog
+        assert!(catalog::CATALOG.get_table("embedded_items").is_err());
+        assert_eq!(first.tables().unwrap(), ["embedded_items"]);
+    }
+
+    #[test]
+    fn test_opened_database_persists_across_handles() {
+        let dir = std::env::temp_dir().join(format!("butterfly_db_open_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        {
+            let db = Database::open(&dir).unwrap();
+            db.execute("CREATE TABLE notes (id INT PRIMARY KEY, body TEXT)").unwrap();
+            db.execute("CREATE TABLE events (id INT PRIMARY KEY, kind TEXT) WITH (engine = 'lsm')").unwrap();
+            db.execute_with_params("INSERT INTO notes VALUES ($1, $2)", &[Value::Integer(7), Value::Text("kept".to_string())])
+                .unwrap();
+            db.execute("INSERT INTO events VALUES (1, 'start')").unwrap();
+        }
+
+        let db = Database::open(&dir).unwrap();
+        let rows = db.query_with_params("SELECT body FROM notes WHERE id = $1", &[Value::Integer(7)]).unwrap();
+        assert_eq!(rows.get(0).unwrap().get::<String>("body").unwrap(), "kept");
+        let rows = db.query("SELECT kind FROM events").unwrap();
+        assert_eq!(rows.iter().map(|row| row.get::<String>("kind").unwrap()).collect::<Vec<_>>(), ["start"]);
+        assert!(db.query("INSERT INTO notes VALUES (8, 'x')").is_err());
+        assert!(db.execute("SELECT * FROM missing").is_err());
+        drop(db);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
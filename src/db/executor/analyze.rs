@@ -0,0 +1,71 @@
+// ANALYZE - collect planner statistics into the catalog
+use super::{ExecutionResult, Executor};
+use crate::db::catalog::{self, TableStatistics};
+use crate::db::storage::engine;
+
+impl Executor {
+    /// ANALYZE [table]: recompute statistics for one table, or for every
+    /// table of the current database
+    pub(crate) fn execute_analyze(table: Option<&str>) -> ExecutionResult {
+        let tables = match table {
+            Some(table) => vec![table.to_string()],
+            None => match catalog::current().list_tables() {
+                Ok(mut tables) => {
+                    tables.sort();
+                    tables
+                }
+                Err(e) => return ExecutionResult::Error { message: e },
+            },
+        };
+
+        for name in &tables {
+            match Self::analyze_table(name) {
+                Ok(()) => {}
+                // A table dropped while analyzing the whole database is skipped
+                Err(_) if table.is_none() && catalog::current().get_table(name).is_err() => {}
+                Err(e) => return ExecutionResult::Error { message: e },
+            }
+        }
+
+        ExecutionResult::Success {
+            message: match table {
+                Some(table) => format!("Table '{}' analyzed", table),
+                None => format!("{} table(s) analyzed", tables.len()),
+            },
+        }
+    }
+
+    fn analyze_table(table: &str) -> Result<(), String> {
+        let schema = catalog::current().get_table(table)?;
+        let rows = engine::current().select(table, &[], &|_| true).unwrap_or_default();
+        let columns: Vec<String> = schema.columns.iter().map(|c| c.name.clone()).collect();
+        catalog::current().set_statistics(table, TableStatistics::collect(&rows, &columns))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::sql::execute_sql;
+    use crate::db::storage::Value;
+
+    #[test]
+    fn test_analyze_stores_statistics() {
+        execute_sql("DROP TABLE IF EXISTS analyze_people");
+        execute_sql("CREATE TABLE analyze_people (id INT, city TEXT)");
+        execute_sql(
+            "INSERT INTO analyze_people VALUES (1, 'Oslo'), (2, 'Oslo'), (3, NULL), (4, 'Rome')",
+        );
+
+        assert!(matches!(execute_sql("ANALYZE analyze_people"), ExecutionResult::Success { .. }));
+        let stats = catalog::current().get_statistics("analyze_people").unwrap();
+        assert_eq!(stats.row_count, 4);
+        assert_eq!(stats.columns["id"].distinct_count, 4);
+        assert_eq!(stats.columns["city"].distinct_count, 2);
+        assert_eq!(stats.columns["city"].null_fraction, 0.25);
+        assert_eq!(stats.columns["city"].histogram.last(), Some(&Value::Text("Rome".to_string())));
+
+        assert!(matches!(execute_sql("ANALYZE analyze_missing"), ExecutionResult::Error { .. }));
+        execute_sql("DROP TABLE analyze_people");
+    }
+}
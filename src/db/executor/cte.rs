@@ -0,0 +1,303 @@
+// Common table expressions (WITH / WITH RECURSIVE)
+use std::collections::HashSet;
+
+use super::{Executor, QueryScope, ResultSet};
+use crate::db::sql::constants::{CommonTableExpression, Statement, TableReference};
+use crate::db::sql::parser::Expression;
+
+/// Maximum number of iterations a recursive CTE may run before it is aborted
+pub const MAX_RECURSION_DEPTH: usize = 1000;
+
+impl Executor {
+    /// Evaluate a common table expression once so every reference to it reads the same rows
+    pub(crate) fn materialize_cte(
+        cte: &CommonTableExpression,
+        recursive: bool,
+        scope: &QueryScope,
+    ) -> Result<ResultSet, String> {
+        if recursive && references_relation(&cte.query, &cte.name) {
+            Self::run_recursive_cte(cte, scope)
+        } else {
+            let result_set = Self::run_query(&cte.query, scope)?;
+            rename_columns(result_set, cte)
+        }
+    }
+
+    /// Fixpoint iteration: run the anchor once, then feed each round's new rows
+    /// back into the recursive term until it produces nothing new
+    fn run_recursive_cte(
+        cte: &CommonTableExpression,
+        scope: &QueryScope,
+    ) -> Result<ResultSet, String> {
+        let (anchor, step, all) = match cte.query.as_ref() {
+            Statement::Union { left, right, all } => (left, right, *all),
+            _ => {
+                return Err(format!(
+                    "Recursive query '{}' must have the form <anchor> UNION [ALL] <recursive term>",
+                    cte.name
+                ));
+            }
+        };
+
+        if references_relation(anchor, &cte.name) {
+            return Err(format!(
+                "Recursive reference to '{}' must not appear in its non-recursive term",
+                cte.name
+            ));
+        }
+
+        let anchor_set = rename_columns(Self::run_query(anchor, scope)?, cte)?;
+        let columns = anchor_set.columns;
+
+        let mut seen = HashSet::new();
+        let mut working = if all {
+            anchor_set.rows
+        } else {
+            anchor_set
+                .rows
+                .into_iter()
+                .filter(|row| seen.insert(Self::row_key(row, &columns)))
+                .collect()
+        };
+        let mut rows = working.clone();
+
+        let mut depth = 0;
+        while !working.is_empty() {
+            depth += 1;
+            if depth > MAX_RECURSION_DEPTH {
+                return Err(format!(
+                    "Recursive query '{}' exceeded the maximum recursion depth of {}",
+                    cte.name, MAX_RECURSION_DEPTH
+                ));
+            }
+
+            let mut iteration_scope = scope.clone();
+            iteration_scope.bind(
+                &cte.name,
+                ResultSet {
+                    columns: columns.clone(),
+                    rows: working,
+                },
+            );
+
+            let produced = Self::realign_rows(Self::run_query(step, &iteration_scope)?, &columns)?;
+            working = if all {
+                produced
+            } else {
+                produced
+                    .into_iter()
+                    .filter(|row| seen.insert(Self::row_key(row, &columns)))
+                    .collect()
+            };
+            rows.extend(working.iter().cloned());
+        }
+
+        Ok(ResultSet { columns, rows })
+    }
+}
+
+/// Apply the optional `name(col, ...)` column list of a CTE
+fn rename_columns(
+    result_set: ResultSet,
+    cte: &CommonTableExpression,
+) -> Result<ResultSet, String> {
+    match &cte.columns {
+        None => Ok(result_set),
+        Some(columns) => {
+            if columns.len() != result_set.columns.len() {
+                return Err(format!(
+                    "WITH query '{}' has {} columns available but {} columns specified",
+                    cte.name,
+                    result_set.columns.len(),
+                    columns.len()
+                ));
+            }
+            let rows = Executor::realign_rows(result_set, columns)?;
+            Ok(ResultSet {
+                columns: columns.clone(),
+                rows,
+            })
+        }
+    }
+}
+
+/// Whether `stmt` reads from a relation called `name` anywhere in its tree
+pub fn references_relation(stmt: &Statement, name: &str) -> bool {
+    match stmt {
+        Statement::Select {
+            projection,
+            from,
+            joins,
+            where_clause,
+            having,
+            ..
+        } => {
+            from.iter().any(|t| table_references(t, name))
+                || joins.iter().any(|j| {
+                    table_references(&j.table, name)
+                        || j.condition.as_ref().is_some_and(|c| expression_references(c, name))
+                })
+                || projection.iter().any(|e| expression_references(e, name))
+                || where_clause.as_ref().is_some_and(|e| expression_references(e, name))
+                || having.as_ref().is_some_and(|e| expression_references(e, name))
+        }
+        Statement::Union { left, right, .. } => {
+            references_relation(left, name) || references_relation(right, name)
+        }
+        Statement::With { ctes, body, .. } => {
+            // An inner CTE with the same name shadows the outer one
+            if ctes.iter().any(|c| c.name == name) {
+                return false;
+            }
+            ctes.iter().any(|c| references_relation(&c.query, name))
+                || references_relation(body, name)
+        }
+        _ => false,
+    }
+}
+
+fn table_references(table_ref: &TableReference, name: &str) -> bool {
+    match table_ref {
+        TableReference::Table { name: table, .. } => table == name,
+        TableReference::Subquery { query, .. } => references_relation(query, name),
+    }
+}
+
+fn expression_references(expr: &Expression, name: &str) -> bool {
+    match expr {
+        Expression::Subquery(query) => references_relation(query, name),
+        Expression::BinaryOp { left, right, .. } => {
+            expression_references(left, name) || expression_references(right, name)
+        }
+        Expression::UnaryOp { operand, .. } => expression_references(operand, name),
+        Expression::Function { args, .. } => args.iter().any(|a| expression_references(a, name)),
+        Expression::Case {
+            when_clauses,
+            else_clause,
+        } => {
+            when_clauses
+                .iter()
+                .any(|(w, t)| expression_references(w, name) || expression_references(t, name))
+                || else_clause.as_ref().is_some_and(|e| expression_references(e, name))
+        }
+        Expression::Alias { expr, .. } => expression_references(expr, name),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::executor::ExecutionResult;
+    use crate::db::sql::{execute_sql, parse_sql};
+
+    fn query_rows(sql: &str) -> (Vec<String>, Vec<std::collections::HashMap<String, serde_json::Value>>) {
+        match execute_sql(sql) {
+            ExecutionResult::Rows { columns, rows } => (columns, rows),
+            other => panic!("Expected rows for {}, got {:?}", sql, other),
+        }
+    }
+
+    #[test]
+    fn test_references_relation_detects_recursive_term() {
+        let stmts = parse_sql("SELECT n + 1 FROM t WHERE n < 5").unwrap();
+        assert!(references_relation(&stmts[0], "t"));
+        assert!(!references_relation(&stmts[0], "other"));
+    }
+
+    #[test]
+    fn test_cte_simple() {
+        let (columns, rows) = query_rows("WITH t AS (SELECT 1 AS a, 'x' AS b) SELECT a, b FROM t");
+        assert_eq!(columns, vec!["a", "b"]);
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0]["a"], serde_json::json!(1));
+        assert_eq!(rows[0]["b"], serde_json::json!("x"));
+    }
+
+    #[test]
+    fn test_cte_referenced_twice() {
+        let (_, rows) = query_rows(
+            "WITH t AS (SELECT 1 AS a UNION ALL SELECT 2) \
+             SELECT x.a, y.a AS b FROM t x JOIN t y ON x.a = y.a",
+        );
+        assert_eq!(rows.len(), 2);
+        for row in rows {
+            assert_eq!(row["a"], row["b"]);
+        }
+    }
+
+    #[test]
+    fn test_cte_column_list() {
+        let (columns, rows) = query_rows("WITH t(x, y) AS (SELECT 1, 2) SELECT * FROM t");
+        assert_eq!(columns, vec!["x", "y"]);
+        assert_eq!(rows[0]["y"], serde_json::json!(2));
+    }
+
+    #[test]
+    fn test_cte_column_list_mismatch() {
+        match execute_sql("WITH t(x) AS (SELECT 1, 2) SELECT * FROM t") {
+            ExecutionResult::Error { message } => assert!(message.contains("columns specified")),
+            other => panic!("Expected error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_recursive_cte_counts() {
+        let (_, rows) = query_rows(
+            "WITH RECURSIVE t(n) AS (SELECT 1 UNION ALL SELECT n + 1 FROM t WHERE n < 5) \
+             SELECT n FROM t",
+        );
+        let values: Vec<i64> = rows.iter().map(|r| r["n"].as_i64().unwrap()).collect();
+        assert_eq!(values, vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_recursive_cte_org_chart() {
+        let (_, rows) = query_rows(
+            "WITH RECURSIVE \
+               employees(id, manager_id, name) AS ( \
+                 SELECT 1, NULL, 'ceo' \
+                 UNION ALL SELECT 2, 1, 'cto' \
+                 UNION ALL SELECT 3, 2, 'engineer' \
+                 UNION ALL SELECT 4, 1, 'cfo' \
+                 UNION ALL SELECT 5, 99, 'contractor'), \
+               chart(id, name, depth) AS ( \
+                 SELECT id, name, 0 FROM employees WHERE id = 1 \
+                 UNION ALL \
+                 SELECT e.id, e.name, c.depth + 1 FROM employees e JOIN chart c ON e.manager_id = c.id) \
+             SELECT name, depth FROM chart",
+        );
+        assert_eq!(rows.len(), 4);
+        let engineer = rows.iter().find(|r| r["name"] == "engineer").unwrap();
+        assert_eq!(engineer["depth"], serde_json::json!(2));
+        assert!(!rows.iter().any(|r| r["name"] == "contractor"));
+    }
+
+    #[test]
+    fn test_recursive_cte_union_stops_on_cycle() {
+        let (_, rows) = query_rows(
+            "WITH RECURSIVE t(n) AS (SELECT 1 UNION SELECT n FROM t) SELECT n FROM t",
+        );
+        assert_eq!(rows.len(), 1);
+    }
+
+    #[test]
+    fn test_recursive_cte_depth_guard() {
+        match execute_sql(
+            "WITH RECURSIVE t(n) AS (SELECT 1 UNION ALL SELECT n + 1 FROM t) SELECT n FROM t",
+        ) {
+            ExecutionResult::Error { message } => {
+                assert!(message.contains("maximum recursion depth"));
+            }
+            other => panic!("Expected recursion depth error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_recursive_cte_requires_union() {
+        match execute_sql("WITH RECURSIVE t(n) AS (SELECT n FROM t) SELECT n FROM t") {
+            ExecutionResult::Error { message } => assert!(message.contains("UNION")),
+            other => panic!("Expected error, got {:?}", other),
+        }
+    }
+}
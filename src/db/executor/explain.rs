@@ -0,0 +1,346 @@
+// EXPLAIN and EXPLAIN ANALYZE
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use super::operators::{OperatorStats, PhysicalOperator};
+use super::{ExecutionResult, Executor, QueryScope};
+use crate::db::planner::LogicalPlan;
+use crate::db::sql::constants::{ExplainFormat, Statement};
+use crate::db::sql::parser::{Expression, SqlPrettyPrinter};
+
+/// Name of the single column EXPLAIN returns
+pub const QUERY_PLAN_COLUMN: &str = "QUERY PLAN";
+
+/// One node of an explained plan
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlanNode {
+    pub name: String,
+    pub detail: String,
+    pub stats: Option<OperatorStats>,
+    pub children: Vec<PlanNode>,
+}
+
+impl PlanNode {
+    fn new(name: &str, detail: String) -> Self {
+        Self {
+            name: name.to_string(),
+            detail,
+            stats: None,
+            children: Vec::new(),
+        }
+    }
+
+    /// Snapshot a physical operator tree
+    pub fn from_operator(op: &dyn PhysicalOperator) -> Self {
+        Self {
+            name: op.name().to_string(),
+            detail: op.detail(),
+            stats: op.stats().cloned(),
+            children: op.children().into_iter().map(Self::from_operator).collect(),
+        }
+    }
+
+    fn label(&self) -> String {
+        let mut label = if self.detail.is_empty() {
+            self.name.clone()
+        } else {
+            format!("{} {}", self.name, self.detail)
+        };
+        if let Some(stats) = &self.stats {
+            label.push_str(&format!(
+                " (actual rows={} loops={} time={:.3} ms)",
+                stats.rows,
+                stats.loops,
+                millis(stats.elapsed)
+            ));
+        }
+        label
+    }
+
+    fn write_lines(&self, depth: usize, lines: &mut Vec<String>) {
+        if depth == 0 {
+            lines.push(self.label());
+        } else {
+            lines.push(format!("{}->  {}", "      ".repeat(depth - 1), self.label()));
+        }
+        for child in &self.children {
+            child.write_lines(depth + 1, lines);
+        }
+    }
+
+    /// Indented text rendering, one line per node
+    pub fn to_lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        self.write_lines(0, &mut lines);
+        lines
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut node = serde_json::Map::new();
+        node.insert("Node Type".to_string(), serde_json::json!(self.name));
+        if !self.detail.is_empty() {
+            node.insert("Detail".to_string(), serde_json::json!(self.detail));
+        }
+        if let Some(stats) = &self.stats {
+            node.insert("Actual Rows".to_string(), serde_json::json!(stats.rows));
+            node.insert("Actual Loops".to_string(), serde_json::json!(stats.loops));
+            node.insert("Actual Total Time".to_string(), serde_json::json!(millis(stats.elapsed)));
+        }
+        if !self.children.is_empty() {
+            let plans: Vec<serde_json::Value> = self.children.iter().map(|c| c.to_json()).collect();
+            node.insert("Plans".to_string(), serde_json::Value::Array(plans));
+        }
+        serde_json::Value::Object(node)
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+impl Executor {
+    /// Describe how `statement` would run. With `analyze` the statement is
+    /// executed (including any writes) and every node reports what it did.
+    pub(crate) fn execute_explain(
+        statement: &Statement,
+        analyze: bool,
+        format: ExplainFormat,
+    ) -> ExecutionResult {
+        let started = Instant::now();
+        let plan = match statement {
+            Statement::Select { .. } | Statement::Union { .. } | Statement::With { .. } => {
+                Self::explain_query(statement, analyze)
+            }
+            Statement::Insert { .. } | Statement::Update { .. } | Statement::Delete { .. } => {
+                Self::explain_write(statement, analyze)
+            }
+            _ => Err("EXPLAIN is only supported for SELECT, INSERT, UPDATE and DELETE".to_string()),
+        };
+        let plan = match plan {
+            Ok(plan) => plan,
+            Err(e) => return ExecutionResult::Error { message: e },
+        };
+        let execution_time = analyze.then(|| started.elapsed());
+
+        let rows: Vec<HashMap<String, serde_json::Value>> = match format {
+            ExplainFormat::Text => {
+                let mut lines = plan.to_lines();
+                if let Some(elapsed) = execution_time {
+                    lines.push(format!("Execution Time: {:.3} ms", millis(elapsed)));
+                }
+                lines
+                    .into_iter()
+                    .map(|line| HashMap::from([(QUERY_PLAN_COLUMN.to_string(), serde_json::json!(line))]))
+                    .collect()
+            }
+            ExplainFormat::Json => {
+                let mut document = serde_json::Map::new();
+                document.insert("Plan".to_string(), plan.to_json());
+                if let Some(elapsed) = execution_time {
+                    document.insert("Execution Time".to_string(), serde_json::json!(millis(elapsed)));
+                }
+                vec![HashMap::from([(
+                    QUERY_PLAN_COLUMN.to_string(),
+                    serde_json::Value::Object(document),
+                )])]
+            }
+        };
+
+        ExecutionResult::Rows {
+            columns: vec![QUERY_PLAN_COLUMN.to_string()],
+            rows,
+        }
+    }
+
+    fn explain_query(statement: &Statement, analyze: bool) -> Result<PlanNode, String> {
+        let plan = LogicalPlan::from_statement(statement)?;
+        let scope = QueryScope::default();
+        if !analyze {
+            return Ok(PlanNode::from_operator(Self::lower_plan(&plan, &scope)?.as_ref()));
+        }
+
+        let mut op = Self::lower_plan_instrumented(&plan, &scope)?;
+        while op.next()?.is_some() {}
+        Ok(PlanNode::from_operator(op.as_ref()))
+    }
+
+    /// INSERT, UPDATE and DELETE are not built from operators; describe the
+    /// steps they take and, under ANALYZE, run them and report the rows written
+    fn explain_write(statement: &Statement, analyze: bool) -> Result<PlanNode, String> {
+        let mut node = match statement {
+            Statement::Insert { table, values, query, .. } => {
+                let mut node = PlanNode::new("Insert", format!("on {}", table));
+                node.children.push(match query {
+                    Some(query) => Self::explain_query(query, false)?,
+                    None => PlanNode::new("Values", format!("{} row(s)", values.len())),
+                });
+                node
+            }
+            Statement::Update { table, where_clause, .. } => {
+                let mut node = PlanNode::new("Update", format!("on {}", table));
+                node.children.push(scan_node(table, where_clause.as_ref()));
+                node
+            }
+            Statement::Delete { table, where_clause, .. } => {
+                let mut node = PlanNode::new("Delete", format!("on {}", table));
+                node.children.push(scan_node(table, where_clause.as_ref()));
+                node
+            }
+            _ => unreachable!("explain_write called with a query statement"),
+        };
+
+        if analyze {
+            let started = Instant::now();
+            let rows = match Self::execute(statement) {
+                ExecutionResult::RowsAffected { count } => count,
+                ExecutionResult::Upserted { inserted, updated } => inserted + updated,
+                ExecutionResult::Rows { rows, .. } => rows.len(),
+                ExecutionResult::Error { message } => return Err(message),
+                ExecutionResult::Success { .. } => 0,
+            };
+            node.stats = Some(OperatorStats {
+                rows: rows as u64,
+                loops: 1,
+                elapsed: started.elapsed(),
+            });
+        }
+        Ok(node)
+    }
+}
+
+/// The full scan (and optional predicate) UPDATE and DELETE use to find rows
+fn scan_node(table: &str, where_clause: Option<&Expression>) -> PlanNode {
+    let scan = PlanNode::new("Seq Scan", format!("on {}", table));
+    match where_clause {
+        Some(predicate) => {
+            let mut filter = PlanNode::new("Filter", SqlPrettyPrinter::new().print_expression(predicate));
+            filter.children.push(scan);
+            filter
+        }
+        None => scan,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::sql::execute_sql;
+
+    fn plan_lines(sql: &str) -> Vec<String> {
+        match execute_sql(sql) {
+            ExecutionResult::Rows { columns, rows } => {
+                assert_eq!(columns, vec![QUERY_PLAN_COLUMN]);
+                rows.iter()
+                    .map(|r| r[QUERY_PLAN_COLUMN].as_str().unwrap().to_string())
+                    .collect()
+            }
+            other => panic!("Expected rows, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_explain_shows_operator_tree() {
+        execute_sql("DROP TABLE IF EXISTS explain_tree");
+        execute_sql("CREATE TABLE explain_tree (id INT, n INT)");
+        execute_sql("INSERT INTO explain_tree VALUES (1, 10), (2, 20), (3, 30)");
+
+        let lines = plan_lines("EXPLAIN SELECT id FROM explain_tree WHERE n > 10 ORDER BY id LIMIT 1");
+        assert_eq!(lines[0], "Limit 1 offset 0");
+        assert!(lines[1].starts_with("->  Project"));
+        assert!(lines.last().unwrap().ends_with("Seq Scan on explain_tree"));
+        assert!(lines.iter().any(|l| l.contains("Filter (n > 10)")));
+        assert!(lines.iter().all(|l| !l.contains("actual")));
+
+        execute_sql("DROP TABLE explain_tree");
+    }
+
+    #[test]
+    fn test_explain_analyze_reports_rows_and_loops() {
+        execute_sql("DROP TABLE IF EXISTS explain_analyze");
+        execute_sql("CREATE TABLE explain_analyze (id INT, n INT)");
+        execute_sql("INSERT INTO explain_analyze VALUES (1, 10), (2, 20), (3, 30)");
+
+        let lines = plan_lines("EXPLAIN ANALYZE SELECT id FROM explain_analyze WHERE n > 10");
+        let filter = lines.iter().find(|l| l.contains("Filter")).unwrap();
+        assert!(filter.contains("actual rows=2 loops=1"));
+        let scan = lines.iter().find(|l| l.contains("Seq Scan")).unwrap();
+        assert!(scan.contains("actual rows=3 loops=1"));
+        assert!(lines.last().unwrap().starts_with("Execution Time:"));
+
+        execute_sql("DROP TABLE explain_analyze");
+    }
+
+    #[test]
+    fn test_explain_json_format() {
+        execute_sql("DROP TABLE IF EXISTS explain_json");
+        execute_sql("CREATE TABLE explain_json (id INT)");
+
+        let rows = match execute_sql("EXPLAIN (ANALYZE, FORMAT JSON) SELECT COUNT(*) FROM explain_json") {
+            ExecutionResult::Rows { rows, .. } => rows,
+            other => panic!("Expected rows, got {:?}", other),
+        };
+        assert_eq!(rows.len(), 1);
+        let plan = &rows[0][QUERY_PLAN_COLUMN]["Plan"];
+        assert_eq!(plan["Node Type"], "Project");
+        assert_eq!(plan["Actual Rows"], 1);
+        assert_eq!(plan["Plans"][0]["Node Type"], "Hash Aggregate");
+        assert!(rows[0][QUERY_PLAN_COLUMN]["Execution Time"].is_number());
+
+        execute_sql("DROP TABLE explain_json");
+    }
+
+    #[test]
+    fn test_explain_shows_index_scan_when_cheaper() {
+        execute_sql("DROP TABLE IF EXISTS explain_index");
+        execute_sql("CREATE TABLE explain_index (id INT, n INT)");
+        let values: Vec<String> = (0..100).map(|i| format!("({}, {})", i, i * 10)).collect();
+        execute_sql(&format!("INSERT INTO explain_index VALUES {}", values.join(", ")));
+        execute_sql("CREATE INDEX explain_index_id ON explain_index (id)");
+        execute_sql("ANALYZE explain_index");
+
+        let sql = "SELECT n FROM explain_index WHERE id = 42";
+        let lines = plan_lines(&format!("EXPLAIN {}", sql));
+        assert!(lines
+            .last()
+            .unwrap()
+            .ends_with("Index Scan using explain_index_id on explain_index (id = 42)"));
+
+        // The index follows later writes
+        execute_sql("UPDATE explain_index SET n = 0 WHERE id = 42");
+        execute_sql("INSERT INTO explain_index VALUES (42, 1)");
+        match execute_sql(sql) {
+            ExecutionResult::Rows { rows, .. } => {
+                let n: Vec<&serde_json::Value> = rows.iter().map(|r| &r["n"]).collect();
+                assert_eq!(n, vec![0, 1]);
+            }
+            other => panic!("Expected rows, got {:?}", other),
+        }
+
+        // Half the table is cheaper to read sequentially
+        let lines = plan_lines("EXPLAIN SELECT n FROM explain_index WHERE id >= 50");
+        assert!(lines.last().unwrap().ends_with("Seq Scan on explain_index"));
+
+        execute_sql("DROP TABLE explain_index");
+    }
+
+    #[test]
+    fn test_explain_analyze_runs_writes() {
+        execute_sql("DROP TABLE IF EXISTS explain_write");
+        execute_sql("CREATE TABLE explain_write (id INT)");
+        execute_sql("INSERT INTO explain_write VALUES (1), (2), (3)");
+
+        let lines = plan_lines("EXPLAIN DELETE FROM explain_write WHERE id < 3");
+        assert_eq!(lines[0], "Delete on explain_write");
+        assert_eq!(lines.len(), 3);
+
+        let lines = plan_lines("EXPLAIN ANALYZE DELETE FROM explain_write WHERE id < 3");
+        assert!(lines[0].starts_with("Delete on explain_write (actual rows=2"));
+        let remaining = match execute_sql("SELECT * FROM explain_write") {
+            ExecutionResult::Rows { rows, .. } => rows.len(),
+            other => panic!("Expected rows, got {:?}", other),
+        };
+        assert_eq!(remaining, 1);
+
+        execute_sql("DROP TABLE explain_write");
+    }
+}
@@ -1,14 +1,27 @@
 // SQL Query Executor - Executes parsed SQL statements
+pub mod analyze;
+pub mod cte;
+pub mod explain;
+pub mod operators;
+pub mod parallel;
+pub mod upsert;
+pub mod vectorized;
+
 use std::collections::HashMap;
+use std::sync::Arc;
 use serde::{Deserialize, Serialize};
 
-use crate::db::catalog::{ColumnSchema, CATALOG, data_type_to_string};
-use crate::db::storage::{Row, Value, STORAGE};
+use crate::db::catalog::{self, ColumnSchema, IndexSchema, TableEngine, TableFormat, TableSchema, data_type_to_string};
+use crate::db::optimizer::Rewriter;
+use crate::db::planner::{aggregate_key, is_aggregate_function, LogicalPlan};
+use crate::db::planner::cost;
+use crate::db::storage::{engine, Row, Selection, Value};
 use crate::db::sql::constants::{
     Statement, Assignment, ColumnDef, ColumnConstraint, 
-    TableReference, Literal, BinaryOperator,
+    Literal, BinaryOperator, OnConflict, TableConstraint,
 };
 use crate::db::sql::parser::Expression;
+use operators::PhysicalOperator;
 
 /// Result of executing a SQL statement
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -17,6 +30,8 @@ pub enum ExecutionResult {
     Success { message: String },
     /// For INSERT, UPDATE, DELETE
     RowsAffected { count: usize },
+    /// For INSERT ... ON CONFLICT
+    Upserted { inserted: usize, updated: usize },
     /// For SELECT
     Rows { columns: Vec<String>, rows: Vec<HashMap<String, serde_json::Value>> },
     /// For errors
@@ -29,18 +44,100 @@ impl ExecutionResult {
     }
 }
 
+/// Rows produced by a query, with the output column order preserved
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ResultSet {
+    pub columns: Vec<String>,
+    pub rows: Vec<Row>,
+}
+
+impl ResultSet {
+    /// Convert to the JSON-friendly result returned to clients
+    pub fn into_execution_result(self) -> ExecutionResult {
+        let rows = self
+            .rows
+            .iter()
+            .map(|row| {
+                self.columns
+                    .iter()
+                    .map(|c| {
+                        let value = row.get(c).unwrap_or(&Value::Null);
+                        (c.clone(), Executor::value_to_json(value))
+                    })
+                    .collect()
+            })
+            .collect();
+
+        ExecutionResult::Rows {
+            columns: self.columns,
+            rows,
+        }
+    }
+}
+
+/// The rows of a query, pulled from its plan one at a time as they are
+/// read, so a caller can send them on without holding the whole result.
+/// Each row holds the values of `columns`, in order.
+pub struct RowStream {
+    columns: Vec<String>,
+    op: Box<dyn PhysicalOperator>,
+}
+
+impl RowStream {
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+}
+
+impl Iterator for RowStream {
+    type Item = Result<Vec<Value>, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.op.next() {
+            Ok(Some(mut row)) => Some(Ok(self
+                .columns
+                .iter()
+                .map(|c| row.remove(c).unwrap_or(Value::Null))
+                .collect())),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// Named relations (common table expressions) visible while evaluating a query
+#[derive(Debug, Clone, Default)]
+pub struct QueryScope {
+    relations: HashMap<String, Arc<ResultSet>>,
+}
+
+impl QueryScope {
+    /// Make `result_set` visible under `name`, shadowing any outer binding
+    pub fn bind(&mut self, name: &str, result_set: ResultSet) {
+        self.relations.insert(name.to_string(), Arc::new(result_set));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Arc<ResultSet>> {
+        self.relations.get(name)
+    }
+}
+
 /// The SQL Executor
 pub struct Executor;
 
 impl Executor {
     /// Execute a single SQL statement
     pub fn execute(stmt: &Statement) -> ExecutionResult {
+        let stmt = &Rewriter::default().rewrite(stmt);
         match stmt {
             Statement::CreateDatabase { name, if_not_exists } => {
                 Self::execute_create_database(name, *if_not_exists)
             }
-            Statement::CreateTable { name, columns, if_not_exists, .. } => {
-                Self::execute_create_table(name, columns, *if_not_exists)
+            Statement::CreateTable { name, columns, constraints, if_not_exists, options } => {
+                Self::execute_create_table(name, columns, constraints, *if_not_exists, options)
+            }
+            Statement::CreateIndex { name, table, columns, unique, if_not_exists } => {
+                Self::execute_create_index(name, table, columns, *unique, *if_not_exists)
             }
             Statement::DropDatabase { name, if_exists } => {
                 Self::execute_drop_database(name, *if_exists)
@@ -48,17 +145,43 @@ impl Executor {
             Statement::DropTable { name, if_exists } => {
                 Self::execute_drop_table(name, *if_exists)
             }
-            Statement::Insert { table, columns, values } => {
-                Self::execute_insert(table, columns.as_ref(), values)
+            Statement::Insert { table, columns, values, query, on_conflict, returning } => {
+                Self::execute_insert(
+                    table,
+                    columns.as_ref(),
+                    values,
+                    query.as_deref(),
+                    on_conflict.as_ref(),
+                    returning.as_deref(),
+                )
+            }
+            Statement::Select { .. } | Statement::Union { .. } | Statement::With { .. } => {
+                Self::execute_query(stmt)
             }
-            Statement::Select { projection, from, where_clause, limit, .. } => {
-                Self::execute_select(projection, from.as_ref(), where_clause.as_ref(), *limit)
+            Statement::Update { table, assignments, where_clause, returning } => {
+                Self::execute_update(table, assignments, where_clause.as_ref(), returning.as_deref())
             }
-            Statement::Update { table, assignments, where_clause } => {
-                Self::execute_update(table, assignments, where_clause.as_ref())
+            Statement::Delete { table, where_clause, returning } => {
+                Self::execute_delete(table, where_clause.as_ref(), returning.as_deref())
             }
-            Statement::Delete { table, where_clause } => {
-                Self::execute_delete(table, where_clause.as_ref())
+            Statement::Explain { analyze, format, statement } => {
+                Self::execute_explain(statement, *analyze, *format)
+            }
+            Statement::Analyze { table } => Self::execute_analyze(table.as_deref()),
+            Statement::Prepare { .. } | Statement::Execute { .. } | Statement::Deallocate { .. } => {
+                ExecutionResult::Error {
+                    message: "PREPARE, EXECUTE and DEALLOCATE must be run through a session".to_string(),
+                }
+            }
+            Statement::DeclareCursor { .. } | Statement::Fetch { .. } | Statement::CloseCursor { .. } => {
+                ExecutionResult::Error {
+                    message: "DECLARE, FETCH and CLOSE must be run through a session".to_string(),
+                }
+            }
+            Statement::Listen { .. } | Statement::Unlisten { .. } | Statement::Notify { .. } => {
+                ExecutionResult::Error {
+                    message: "LISTEN, UNLISTEN and NOTIFY must be run through a session".to_string(),
+                }
             }
             _ => ExecutionResult::Error {
                 message: format!("Statement type not yet supported: {:?}", std::mem::discriminant(stmt)),
@@ -67,7 +190,7 @@ impl Executor {
     }
 
     fn execute_create_database(name: &str, if_not_exists: bool) -> ExecutionResult {
-        match CATALOG.create_database(name, if_not_exists) {
+        match catalog::current().create_database(name, if_not_exists) {
             Ok(()) => ExecutionResult::Success {
                 message: format!("Database '{}' created", name),
             },
@@ -76,7 +199,7 @@ impl Executor {
     }
 
     fn execute_drop_database(name: &str, if_exists: bool) -> ExecutionResult {
-        match CATALOG.drop_database(name, if_exists) {
+        match catalog::current().drop_database(name, if_exists) {
             Ok(()) => ExecutionResult::Success {
                 message: format!("Database '{}' dropped", name),
             },
@@ -84,9 +207,25 @@ impl Executor {
         }
     }
 
-    fn execute_create_table(name: &str, columns: &[ColumnDef], if_not_exists: bool) -> ExecutionResult {
+    fn execute_create_table(
+        name: &str,
+        columns: &[ColumnDef],
+        constraints: &[TableConstraint],
+        if_not_exists: bool,
+        options: &[(String, String)],
+    ) -> ExecutionResult {
+        let table_primary_key: Vec<&String> = constraints
+            .iter()
+            .filter_map(|c| match c {
+                TableConstraint::PrimaryKey(cols) => Some(cols),
+                _ => None,
+            })
+            .flatten()
+            .collect();
+
         let column_schemas: Vec<ColumnSchema> = columns.iter().map(|col| {
-            let is_primary = col.constraints.iter().any(|c| matches!(c, ColumnConstraint::PrimaryKey));
+            let is_primary = col.constraints.iter().any(|c| matches!(c, ColumnConstraint::PrimaryKey))
+                || table_primary_key.contains(&&col.name);
             let is_nullable = !col.constraints.iter().any(|c| matches!(c, ColumnConstraint::NotNull));
             
             ColumnSchema {
@@ -97,22 +236,97 @@ impl Executor {
             }
         }).collect();
 
-        match CATALOG.create_table(name, column_schemas, if_not_exists) {
+        let mut schema = TableSchema::new(name.to_string(), column_schemas);
+        for col in columns {
+            if col.constraints.iter().any(|c| matches!(c, ColumnConstraint::Unique)) {
+                schema.unique_keys.push(vec![col.name.clone()]);
+            }
+        }
+        for constraint in constraints {
+            if let TableConstraint::Unique(cols) = constraint {
+                schema.unique_keys.push(cols.clone());
+            }
+        }
+        for (key, value) in options {
+            let applied = match key.as_str() {
+                "format" => TableFormat::parse(value).map(|format| schema.format = format),
+                "engine" => TableEngine::parse(value).map(|engine| schema.engine = engine),
+                _ => Err(format!("Unknown table option '{}'", key)),
+            };
+            if let Err(message) = applied {
+                return ExecutionResult::Error { message };
+            }
+        }
+        if schema.engine != TableEngine::Memory && schema.format != TableFormat::Row {
+            return ExecutionResult::Error {
+                message: format!("The {} engine only stores tables in row format", schema.engine.name()),
+            };
+        }
+
+        match catalog::current().create_table_from_schema(schema, if_not_exists) {
             Ok(()) => {
-                // Also initialize storage for the table
-                let _ = STORAGE.get_or_create_table(name);
-                ExecutionResult::Success {
-                    message: format!("Table '{}' created", name),
+                // Also initialize storage for the table, in the engine the catalog names
+                let created = catalog::current()
+                    .get_table(name)
+                    .and_then(|schema| engine::current().create_table(&schema));
+                match created {
+                    Ok(()) => ExecutionResult::Success {
+                        message: format!("Table '{}' created", name),
+                    },
+                    Err(e) => ExecutionResult::Error { message: e },
                 }
             }
             Err(e) => ExecutionResult::Error { message: e },
         }
     }
 
+    fn execute_create_index(
+        name: &str,
+        table: &str,
+        columns: &[String],
+        unique: bool,
+        if_not_exists: bool,
+    ) -> ExecutionResult {
+        let index = IndexSchema {
+            name: name.to_string(),
+            columns: columns.to_vec(),
+            unique,
+        };
+        let created = match unique {
+            true => Self::create_unique_index(table, index, if_not_exists),
+            false => catalog::current().create_index(table, index, if_not_exists),
+        };
+        match created {
+            Ok(()) => ExecutionResult::Success {
+                message: format!("Index '{}' created", name),
+            },
+            Err(e) => ExecutionResult::Error { message: e },
+        }
+    }
+
+    /// Register a unique index once the stored rows are checked against it
+    /// (NULLs never collide). Both happen inside one write to the table, so
+    /// no other write can store a duplicate in between.
+    fn create_unique_index(table: &str, index: IndexSchema, if_not_exists: bool) -> Result<(), String> {
+        let schema = catalog::current().get_table(table)?;
+        let engine = engine::current();
+        if !engine.has_table(table) {
+            engine.create_table(&schema)?;
+        }
+        let mut write = engine.begin(table, &schema.candidate_keys())?;
+        let result = write
+            .add_key(&index.columns)
+            .map_err(|e| format!("Could not create unique index '{}': {}", index.name, e))
+            .and_then(|()| catalog::current().create_index(table, index, if_not_exists));
+        // The write only held other writers off; it changed nothing
+        write.rollback();
+        result
+    }
+
     fn execute_drop_table(name: &str, if_exists: bool) -> ExecutionResult {
-        match CATALOG.drop_table(name, if_exists) {
+        match catalog::current().drop_table(name, if_exists) {
             Ok(()) => {
-                let _ = STORAGE.drop_table(name);
+                let _ = engine::current().drop_table(name);
                 ExecutionResult::Success {
                     message: format!("Table '{}' dropped", name),
                 }
@@ -125,9 +339,12 @@ impl Executor {
         table: &str,
         columns: Option<&Vec<String>>,
         values: &[Vec<Expression>],
+        query: Option<&Statement>,
+        on_conflict: Option<&OnConflict>,
+        returning: Option<&[Expression]>,
     ) -> ExecutionResult {
         // Verify table exists
-        let schema = match CATALOG.get_table(table) {
+        let schema = match catalog::current().get_table(table) {
             Ok(s) => s,
             Err(e) => return ExecutionResult::Error { message: e },
         };
@@ -138,166 +355,334 @@ impl Executor {
             schema.columns.iter().map(|c| c.name.clone()).collect()
         };
 
-        let mut total_inserted = 0;
-        for row_values in values {
-            if row_values.len() != col_names.len() {
-                return ExecutionResult::Error {
-                    message: format!(
-                        "Column count mismatch: expected {}, got {}",
-                        col_names.len(),
-                        row_values.len()
-                    ),
-                };
-            }
+        // Build every row up front so a bad row aborts the whole statement
+        // before anything reaches storage
+        let rows = match query {
+            Some(query) => Self::rows_from_query(query, &col_names),
+            None => Self::rows_from_values(values, &col_names),
+        };
+        let rows = match rows {
+            Ok(rows) => rows,
+            Err(e) => return ExecutionResult::Error { message: e },
+        };
 
-            let mut row: Row = HashMap::new();
-            for (i, expr) in row_values.iter().enumerate() {
-                let value = Self::eval_expression(expr, &HashMap::new());
-                row.insert(col_names[i].clone(), value);
-            }
+        if let Some(on_conflict) = on_conflict {
+            return match Self::apply_upsert(&schema, rows, on_conflict) {
+                Ok(outcome) => match returning {
+                    Some(exprs) => Self::returning_result(table, exprs, outcome.rows),
+                    None => ExecutionResult::Upserted {
+                        inserted: outcome.inserted,
+                        updated: outcome.updated,
+                    },
+                },
+                Err(e) => ExecutionResult::Error { message: e },
+            };
+        }
 
-            match STORAGE.insert(table, row) {
-                Ok(n) => total_inserted += n,
-                Err(e) => return ExecutionResult::Error { message: e },
+        // A table in the catalog may not have stored anything yet
+        let engine = engine::current();
+        if !engine.has_table(table) {
+            if let Err(e) = engine.create_table(&schema) {
+                return ExecutionResult::Error { message: e };
             }
         }
+        let written = returning.map(|_| rows.clone());
+        match engine.insert_recorded(table, &schema.candidate_keys(), rows) {
+            Ok(count) => match (returning, written) {
+                (Some(exprs), Some(written)) => Self::returning_result(table, exprs, written),
+                _ => ExecutionResult::RowsAffected { count },
+            },
+            Err(e) => ExecutionResult::Error { message: e },
+        }
+    }
 
-        ExecutionResult::RowsAffected { count: total_inserted }
+    /// Evaluate a RETURNING list (or a live query's select list) against
+    /// rows of `table`
+    pub(crate) fn returning_result(table: &str, returning: &[Expression], rows: Vec<Row>) -> ExecutionResult {
+        let columns = match catalog::current().get_table(table) {
+            Ok(schema) => schema.columns.iter().map(|c| c.name.clone()).collect(),
+            Err(e) => return ExecutionResult::Error { message: e },
+        };
+        let source = Arc::new(ResultSet { columns, rows });
+        let op = operators::Project::new(Box::new(operators::RelationScan::new(source, table)), returning);
+        match Self::collect_rows(Box::new(op)) {
+            Ok(result_set) => result_set.into_execution_result(),
+            Err(e) => ExecutionResult::Error { message: e },
+        }
     }
 
-    fn execute_select(
-        projection: &[Expression],
-        from: Option<&TableReference>,
-        where_clause: Option<&Expression>,
-        limit: Option<u64>,
-    ) -> ExecutionResult {
-        let table_name = match from {
-            Some(TableReference::Table { name, .. }) => name.as_str(),
-            Some(TableReference::Subquery { .. }) => {
-                return ExecutionResult::Error {
-                    message: "Subqueries not yet supported".to_string(),
-                };
-            }
-            None => {
-                // SELECT without FROM (e.g., SELECT 1+1)
-                let values: Vec<serde_json::Value> = projection
+    /// Evaluate the tuples of a VALUES list into rows keyed by target column
+    fn rows_from_values(values: &[Vec<Expression>], col_names: &[String]) -> Result<Vec<Row>, String> {
+        let empty = HashMap::new();
+        values
+            .iter()
+            .map(|row_values| {
+                if row_values.len() != col_names.len() {
+                    return Err(format!(
+                        "Column count mismatch: expected {}, got {}",
+                        col_names.len(),
+                        row_values.len()
+                    ));
+                }
+                Ok(col_names
                     .iter()
-                    .map(|expr| {
-                        let v = Self::eval_expression(expr, &HashMap::new());
-                        Self::value_to_json(&v)
-                    })
-                    .collect();
-                
-                return ExecutionResult::Rows {
-                    columns: (0..projection.len()).map(|i| format!("column{}", i)).collect(),
-                    rows: vec![values.into_iter().enumerate().map(|(i, v)| (format!("column{}", i), v)).collect()],
-                };
-            }
-        };
+                    .cloned()
+                    .zip(row_values.iter().map(|expr| Self::eval_expression(expr, &empty)))
+                    .collect())
+            })
+            .collect()
+    }
 
-        // Get columns to select
-        let col_names: Vec<String> = projection
+    /// Run the source query of INSERT ... SELECT and map its columns onto the
+    /// target columns by position
+    fn rows_from_query(query: &Statement, col_names: &[String]) -> Result<Vec<Row>, String> {
+        let result_set = Self::run_query(query, &QueryScope::default())?;
+        if result_set.columns.len() != col_names.len() {
+            return Err(format!(
+                "Column count mismatch: expected {}, got {}",
+                col_names.len(),
+                result_set.columns.len()
+            ));
+        }
+        Ok(result_set
+            .rows
             .iter()
-            .filter_map(|expr| match expr {
-                Expression::Identifier(name) => Some(name.clone()),
-                Expression::QualifiedColumn { column, .. } => Some(column.clone()),
-                _ => None,
+            .map(|row| {
+                col_names
+                    .iter()
+                    .zip(&result_set.columns)
+                    .map(|(target, source)| {
+                        (target.clone(), row.get(source).cloned().unwrap_or(Value::Null))
+                    })
+                    .collect()
             })
-            .collect();
+            .collect())
+    }
 
-        // Create predicate from WHERE clause
-        let predicate = |row: &Row| -> bool {
-            match where_clause {
-                Some(expr) => Self::eval_condition(expr, row),
-                None => true,
-            }
+    /// Run a query statement (SELECT, UNION, WITH) and package its rows. Rows
+    /// are converted as they are pulled, so the internal rows are never all
+    /// held at once.
+    fn execute_query(stmt: &Statement) -> ExecutionResult {
+        let stream = match Self::plan_stream(stmt) {
+            Ok(stream) => stream,
+            Err(e) => return ExecutionResult::Error { message: e },
         };
+        let columns = stream.columns.clone();
+        let mut rows = Vec::new();
+        for row in stream {
+            match row {
+                Ok(values) => rows.push(
+                    columns
+                        .iter()
+                        .zip(&values)
+                        .map(|(c, value)| (c.clone(), Self::value_to_json(value)))
+                        .collect(),
+                ),
+                Err(e) => return ExecutionResult::Error { message: e },
+            }
+        }
+        ExecutionResult::Rows { columns, rows }
+    }
 
-        match STORAGE.select(table_name, &col_names, predicate) {
-            Ok(mut rows) => {
-                // Apply limit
-                if let Some(lim) = limit {
-                    rows.truncate(lim as usize);
-                }
-
-                // Convert to JSON-friendly format
-                let json_rows: Vec<HashMap<String, serde_json::Value>> = rows
-                    .into_iter()
-                    .map(|row| {
-                        row.into_iter()
-                            .map(|(k, v)| (k, Self::value_to_json(&v)))
-                            .collect()
-                    })
-                    .collect();
+    /// Plan a query statement (SELECT, UNION, WITH) without running it; the
+    /// rows are produced as the stream is read. Fails for other statements.
+    pub fn stream(stmt: &Statement) -> Result<RowStream, String> {
+        let stmt = &Rewriter::default().rewrite(stmt);
+        match stmt {
+            Statement::Select { .. } | Statement::Union { .. } | Statement::With { .. } => Self::plan_stream(stmt),
+            _ => Err("Only SELECT, UNION and WITH queries can be streamed".to_string()),
+        }
+    }
 
-                let columns = if col_names.is_empty() || col_names.contains(&"*".to_string()) {
-                    json_rows.first().map(|r| r.keys().cloned().collect()).unwrap_or_default()
-                } else {
-                    col_names
-                };
+    fn plan_stream(stmt: &Statement) -> Result<RowStream, String> {
+        let plan = LogicalPlan::from_statement(stmt)?;
+        let op = Self::lower_plan(&plan, &QueryScope::default())?;
+        Ok(RowStream {
+            columns: operators::column_names(op.schema()),
+            op,
+        })
+    }
 
-                ExecutionResult::Rows { columns, rows: json_rows }
+    /// Output columns of a statement, worked out by planning it without
+    /// running it; None for statements that return no rows
+    pub fn describe(stmt: &Statement) -> Result<Option<Vec<String>>, String> {
+        match stmt {
+            Statement::Select { .. } | Statement::Union { .. } | Statement::With { .. } => {
+                let plan = LogicalPlan::from_statement(stmt)?;
+                let op = Self::lower_plan(&plan, &QueryScope::default())?;
+                Ok(Some(operators::column_names(op.schema())))
             }
-            Err(e) => ExecutionResult::Error { message: e },
+            Statement::Insert { table, returning: Some(returning), .. }
+            | Statement::Update { table, returning: Some(returning), .. }
+            | Statement::Delete { table, returning: Some(returning), .. } => {
+                let columns = catalog::current().get_table(table)?.columns.into_iter().map(|c| c.name).collect();
+                let source = Arc::new(ResultSet { columns, rows: Vec::new() });
+                let op = operators::Project::new(Box::new(operators::RelationScan::new(source, table)), returning);
+                Ok(Some(operators::column_names(op.schema())))
+            }
+            Statement::Explain { .. } => Ok(Some(vec![explain::QUERY_PLAN_COLUMN.to_string()])),
+            _ => Ok(None),
+        }
+    }
+
+    /// Evaluate a query statement to a materialized result set
+    pub(crate) fn run_query(stmt: &Statement, scope: &QueryScope) -> Result<ResultSet, String> {
+        let plan = LogicalPlan::from_statement(stmt)?;
+        Self::collect_rows(Self::lower_plan(&plan, scope)?)
+    }
+
+    /// Name of the output column produced by a projection expression
+    pub(crate) fn output_column_name(expr: &Expression, index: usize) -> String {
+        match expr {
+            Expression::Identifier(name) => name.clone(),
+            Expression::QualifiedColumn { column, .. } => column.clone(),
+            Expression::Alias { alias, .. } => alias.clone(),
+            Expression::Function { name, .. } => name.to_lowercase(),
+            _ => format!("column{}", index),
+        }
+    }
+
+    /// Rename the columns of `result_set` positionally to `columns`
+    fn realign_rows(result_set: ResultSet, columns: &[String]) -> Result<Vec<Row>, String> {
+        if result_set.columns.len() != columns.len() {
+            return Err(format!(
+                "Each UNION query must have the same number of columns: expected {}, got {}",
+                columns.len(),
+                result_set.columns.len()
+            ));
         }
+        if result_set.columns == columns {
+            return Ok(result_set.rows);
+        }
+
+        Ok(result_set
+            .rows
+            .into_iter()
+            .map(|row| {
+                result_set
+                    .columns
+                    .iter()
+                    .zip(columns)
+                    .map(|(from, to)| (to.clone(), row.get(from).cloned().unwrap_or(Value::Null)))
+                    .collect()
+            })
+            .collect())
+    }
+
+    /// Hashable identity of a row restricted to `columns`
+    fn row_key(row: &Row, columns: &[String]) -> String {
+        let values: Vec<&Value> = columns
+            .iter()
+            .map(|c| row.get(c).unwrap_or(&Value::Null))
+            .collect();
+        format!("{:?}", values)
+    }
+
+    /// The rows of `table` a WHERE clause picks, with the range it confines
+    /// a column to (the primary key's if it has one), so storage can find
+    /// them without visiting every row
+    fn write_selection<'a>(
+        schema: Option<&TableSchema>,
+        where_clause: Option<&Expression>,
+        predicate: &'a dyn Fn(&Row) -> bool,
+    ) -> Selection<'a> {
+        let (Some(condition), Some(schema)) = (where_clause, schema) else {
+            return Selection::matching(predicate);
+        };
+        let table = schema.name.as_str();
+        let ranges = cost::column_ranges(condition, table, |column, range| cost::indexable(schema, column, range));
+        let primary = schema.primary_key();
+        let key = ranges
+            .iter()
+            .find(|(column, _)| primary == [column.as_str()])
+            .or(ranges.first())
+            .cloned();
+        Selection { predicate, key }
     }
 
     fn execute_update(
         table: &str,
         assignments: &[Assignment],
         where_clause: Option<&Expression>,
+        returning: Option<&[Expression]>,
     ) -> ExecutionResult {
-        let mut updates: HashMap<String, Value> = HashMap::new();
-        for assignment in assignments {
-            let value = Self::eval_expression(&assignment.value, &HashMap::new());
-            updates.insert(assignment.column.clone(), value);
-        }
-
-        let predicate = |row: &Row| -> bool {
-            match where_clause {
-                Some(expr) => Self::eval_condition(expr, row),
-                None => true,
-            }
-        };
-
-        match STORAGE.update(table, &updates, predicate) {
-            Ok(count) => ExecutionResult::RowsAffected { count },
-            Err(e) => ExecutionResult::Error { message: e },
+        let schema = catalog::current().get_table(table).ok();
+        let keys = schema.as_ref().map(TableSchema::candidate_keys).unwrap_or_default();
+        let predicate = |row: &Row| where_clause.is_none_or(|expr| Self::eval_condition(expr, row));
+        let selection = Self::write_selection(schema.as_ref(), where_clause, &predicate);
+
+        // Collect the new row images in the same step that writes them, so
+        // RETURNING never observes another writer's changes
+        let updated = engine::current().write_recorded(table, &keys, |write, recorder| {
+            let updated = write.update(&selection, &mut |row| {
+                // Every assignment sees the row as it was before the update
+                let mut new = row.clone();
+                for assignment in assignments {
+                    new.insert(assignment.column.clone(), Self::eval_expression(&assignment.value, row));
+                }
+                Ok(new)
+            })?;
+            Ok(updated
+                .into_iter()
+                .map(|(old, new)| {
+                    recorder.update(old, &new);
+                    new
+                })
+                .collect::<Vec<Row>>())
+        });
+
+        match (updated, returning) {
+            (Ok(rows), Some(exprs)) => Self::returning_result(table, exprs, rows),
+            (Ok(rows), None) => ExecutionResult::RowsAffected { count: rows.len() },
+            (Err(e), _) => ExecutionResult::Error { message: e },
         }
     }
 
     fn execute_delete(
         table: &str,
         where_clause: Option<&Expression>,
+        returning: Option<&[Expression]>,
     ) -> ExecutionResult {
-        let predicate = |row: &Row| -> bool {
-            match where_clause {
-                Some(expr) => Self::eval_condition(expr, row),
-                None => true,
-            }
-        };
-
-        match STORAGE.delete(table, predicate) {
-            Ok(count) => ExecutionResult::RowsAffected { count },
-            Err(e) => ExecutionResult::Error { message: e },
+        let schema = catalog::current().get_table(table).ok();
+        let keys = schema.as_ref().map(TableSchema::candidate_keys).unwrap_or_default();
+        let predicate = |row: &Row| where_clause.is_none_or(|expr| Self::eval_condition(expr, row));
+        let selection = Self::write_selection(schema.as_ref(), where_clause, &predicate);
+        let deleted = engine::current().write_recorded(table, &keys, |write, recorder| {
+            let deleted = write.delete(&selection)?;
+            deleted.iter().for_each(|row| recorder.delete(row));
+            Ok(deleted)
+        });
+
+        match (deleted, returning) {
+            (Ok(rows), Some(exprs)) => Self::returning_result(table, exprs, rows),
+            (Ok(rows), None) => ExecutionResult::RowsAffected { count: rows.len() },
+            (Err(e), _) => ExecutionResult::Error { message: e },
         }
     }
 
     /// Evaluate an expression to a Value
-    fn eval_expression(expr: &Expression, row: &Row) -> Value {
+    pub(crate) fn eval_expression(expr: &Expression, row: &Row) -> Value {
         match expr {
             Expression::Literal(lit) => Value::from_literal(lit),
             Expression::Identifier(name) => {
                 row.get(name).cloned().unwrap_or(Value::Null)
             }
-            Expression::QualifiedColumn { column, .. } => {
-                row.get(column).cloned().unwrap_or(Value::Null)
+            Expression::QualifiedColumn { table, column } => {
+                row.get(&format!("{}.{}", table, column))
+                    .or_else(|| row.get(column))
+                    .cloned()
+                    .unwrap_or(Value::Null)
             }
             Expression::BinaryOp { left, operator, right } => {
                 let l = Self::eval_expression(left, row);
                 let r = Self::eval_expression(right, row);
                 Self::eval_binary_op(&l, operator, &r)
             }
+            Expression::Alias { expr, .. } => Self::eval_expression(expr, row),
+            // Aggregates are computed by the Aggregate operator below the projection
+            Expression::Function { name, .. } if is_aggregate_function(name) => {
+                row.get(&aggregate_key(expr)).cloned().unwrap_or(Value::Null)
+            }
             _ => Value::Null,
         }
     }
@@ -340,40 +725,57 @@ impl Executor {
     }
 
     /// Evaluate a condition expression to a boolean
-    fn eval_condition(expr: &Expression, row: &Row) -> bool {
+    pub(crate) fn eval_condition(expr: &Expression, row: &Row) -> bool {
         match expr {
             Expression::Literal(Literal::Boolean(b)) => *b,
+            Expression::BinaryOp {
+                left,
+                operator: BinaryOperator::And,
+                right,
+            } => Self::eval_condition(left, row) && Self::eval_condition(right, row),
+            Expression::BinaryOp {
+                left,
+                operator: BinaryOperator::Or,
+                right,
+            } => Self::eval_condition(left, row) || Self::eval_condition(right, row),
             Expression::BinaryOp { left, operator, right } => {
                 let l = Self::eval_expression(left, row);
                 let r = Self::eval_expression(right, row);
-                
-                match operator {
-                    BinaryOperator::Equals => l == r,
-                    BinaryOperator::NotEquals => l != r,
-                    BinaryOperator::LessThan => Self::compare(&l, &r) < 0,
-                    BinaryOperator::LessThanOrEqual => Self::compare(&l, &r) <= 0,
-                    BinaryOperator::GreaterThan => Self::compare(&l, &r) > 0,
-                    BinaryOperator::GreaterThanOrEqual => Self::compare(&l, &r) >= 0,
-                    BinaryOperator::And => {
-                        Self::eval_condition(left, row) && Self::eval_condition(right, row)
-                    }
-                    BinaryOperator::Or => {
-                        Self::eval_condition(left, row) || Self::eval_condition(right, row)
-                    }
-                    BinaryOperator::Like => {
-                        if let (Value::Text(text), Value::Text(pattern)) = (&l, &r) {
-                            Self::match_like(text, pattern)
-                        } else {
-                            false
-                        }
-                    }
-                    _ => false,
-                }
+                Self::eval_comparison(&l, operator, &r)
             }
             _ => true,
         }
     }
 
+    /// Apply a comparison or LIKE to two values. Comparisons against NULL
+    /// are never true; other operators are false.
+    pub(crate) fn eval_comparison(l: &Value, operator: &BinaryOperator, r: &Value) -> bool {
+        let has_null = matches!(l, Value::Null) || matches!(r, Value::Null);
+        match operator {
+            BinaryOperator::Equals
+            | BinaryOperator::NotEquals
+            | BinaryOperator::LessThan
+            | BinaryOperator::LessThanOrEqual
+            | BinaryOperator::GreaterThan
+            | BinaryOperator::GreaterThanOrEqual
+                if has_null => false,
+            BinaryOperator::Equals => l == r,
+            BinaryOperator::NotEquals => l != r,
+            BinaryOperator::LessThan => Self::compare(l, r) < 0,
+            BinaryOperator::LessThanOrEqual => Self::compare(l, r) <= 0,
+            BinaryOperator::GreaterThan => Self::compare(l, r) > 0,
+            BinaryOperator::GreaterThanOrEqual => Self::compare(l, r) >= 0,
+            BinaryOperator::Like => {
+                if let (Value::Text(text), Value::Text(pattern)) = (l, r) {
+                    Self::match_like(text, pattern)
+                } else {
+                    false
+                }
+            }
+            _ => false,
+        }
+    }
+
     /// Compare two values (-1, 0, 1)
     fn compare(left: &Value, right: &Value) -> i8 {
         match (left, right) {
@@ -386,18 +788,23 @@ impl Executor {
             (Value::Text(a), Value::Text(b)) => {
                 if a < b { -1 } else if a > b { 1 } else { 0 }
             }
+            (Value::Integer(a), Value::Float(_)) => Self::compare(&Value::Float(*a as f64), right),
+            (Value::Float(_), Value::Integer(b)) => Self::compare(left, &Value::Float(*b as f64)),
             _ => 0,
         }
     }
 
     /// Simple LIKE pattern matching (% and _ wildcards)
     fn match_like(text: &str, pattern: &str) -> bool {
+        Self::like_regex(pattern).is_some_and(|re| re.is_match(text))
+    }
+
+    /// The regex a LIKE pattern stands for (None if it does not compile)
+    fn like_regex(pattern: &str) -> Option<regex::Regex> {
         let regex_pattern = pattern
             .replace('%', ".*")
             .replace('_', ".");
-        regex::Regex::new(&format!("^{}$", regex_pattern))
-            .map(|re| re.is_match(text))
-            .unwrap_or(false)
+        regex::Regex::new(&format!("^{}$", regex_pattern)).ok()
     }
 
     /// Convert Value to JSON
@@ -780,4 +1187,304 @@ mod tests {
         let result = Executor::value_to_json(&Value::Boolean(true));
         assert_eq!(result, serde_json::json!(true));
     }
+
+    // ==========================================
+    // INSERT Tests
+    // ==========================================
+
+    fn run(sql: &str) -> ExecutionResult {
+        crate::db::sql::execute_sql(sql)
+    }
+
+    fn count_rows(table: &str) -> usize {
+        match run(&format!("SELECT * FROM {}", table)) {
+            ExecutionResult::Rows { rows, .. } => rows.len(),
+            other => panic!("Expected rows, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_stream_pulls_rows_in_column_order() {
+        run("DROP TABLE IF EXISTS exec_stream");
+        run("CREATE TABLE exec_stream (id INT, name TEXT)");
+        run("INSERT INTO exec_stream VALUES (1, 'a'), (2, NULL), (3, 'c')");
+
+        let parse = |sql: &str| crate::db::sql::SqlParser::parse_statement(sql).unwrap();
+        let mut stream = Executor::stream(&parse("SELECT name, id FROM exec_stream ORDER BY id")).unwrap();
+        assert_eq!(stream.columns(), ["name", "id"]);
+        assert_eq!(stream.next().unwrap().unwrap(), [Value::Text("a".to_string()), Value::Integer(1)]);
+        let rest: Vec<Vec<Value>> = stream.map(Result::unwrap).collect();
+        assert_eq!(rest, [vec![Value::Null, Value::Integer(2)], vec![Value::Text("c".to_string()), Value::Integer(3)]]);
+
+        assert!(Executor::stream(&parse("DELETE FROM exec_stream")).is_err());
+        assert!(Executor::stream(&parse("SELECT * FROM exec_stream_missing")).is_err());
+        assert_eq!(count_rows("exec_stream"), 3);
+        run("DROP TABLE exec_stream");
+    }
+
+    #[test]
+    fn test_insert_multiple_rows_single_statement() {
+        run("DROP TABLE IF EXISTS exec_insert_multi");
+        run("CREATE TABLE exec_insert_multi (id INT, name TEXT)");
+
+        match run("INSERT INTO exec_insert_multi VALUES (1, 'a'), (2, 'b'), (3, 'c')") {
+            ExecutionResult::RowsAffected { count } => assert_eq!(count, 3),
+            other => panic!("Expected RowsAffected, got {:?}", other),
+        }
+        assert_eq!(count_rows("exec_insert_multi"), 3);
+
+        // A malformed tuple rejects the whole statement
+        match run("INSERT INTO exec_insert_multi VALUES (4, 'd'), (5)") {
+            ExecutionResult::Error { message } => assert!(message.contains("Column count mismatch")),
+            other => panic!("Expected error, got {:?}", other),
+        }
+        assert_eq!(count_rows("exec_insert_multi"), 3);
+
+        run("DROP TABLE exec_insert_multi");
+    }
+
+    #[test]
+    fn test_insert_select() {
+        run("DROP TABLE IF EXISTS exec_insert_src");
+        run("DROP TABLE IF EXISTS exec_insert_dst");
+        run("CREATE TABLE exec_insert_src (id INT, name TEXT)");
+        run("CREATE TABLE exec_insert_dst (ref_id INT, label TEXT)");
+        run("INSERT INTO exec_insert_src VALUES (1, 'a'), (2, 'b'), (3, 'c')");
+
+        match run("INSERT INTO exec_insert_dst (ref_id, label) SELECT id, name FROM exec_insert_src WHERE id > 1") {
+            ExecutionResult::RowsAffected { count } => assert_eq!(count, 2),
+            other => panic!("Expected RowsAffected, got {:?}", other),
+        }
+
+        match run("SELECT label FROM exec_insert_dst WHERE ref_id = 3") {
+            ExecutionResult::Rows { rows, .. } => {
+                assert_eq!(rows.len(), 1);
+                assert_eq!(rows[0]["label"], serde_json::json!("c"));
+            }
+            other => panic!("Expected rows, got {:?}", other),
+        }
+
+        match run("INSERT INTO exec_insert_dst SELECT id FROM exec_insert_src") {
+            ExecutionResult::Error { message } => assert!(message.contains("Column count mismatch")),
+            other => panic!("Expected error, got {:?}", other),
+        }
+
+        run("DROP TABLE exec_insert_src");
+        run("DROP TABLE exec_insert_dst");
+    }
+
+    fn assert_duplicate(sql: &str) {
+        match run(sql) {
+            ExecutionResult::Error { message } => assert!(message.starts_with("Duplicate key value"), "{}", message),
+            other => panic!("Expected a duplicate key error for {}, got {:?}", sql, other),
+        }
+    }
+
+    #[test]
+    fn test_unique_keys_reject_duplicates() {
+        let tables = [
+            ("exec_unique_row", ""),
+            ("exec_unique_lsm", " WITH (engine = 'lsm')"),
+            ("exec_unique_col", " WITH (format = 'columnar')"),
+        ];
+        for (table, with) in tables {
+            run(&format!("DROP TABLE IF EXISTS {}", table));
+            run(&format!("CREATE TABLE {} (id INT PRIMARY KEY, email TEXT, n INT){}", table, with));
+            if let ExecutionResult::Error { message } = run(&format!("CREATE UNIQUE INDEX {}_email ON {} (email)", table, table)) {
+                panic!("CREATE UNIQUE INDEX failed: {}", message);
+            }
+            run(&format!("INSERT INTO {} VALUES (1, 'a', 1), (2, 'b', 2), (3, NULL, 3), (4, NULL, 4)", table));
+
+            // Duplicates against stored rows and within one statement
+            assert_duplicate(&format!("INSERT INTO {} VALUES (1, 'z', 0)", table));
+            assert_duplicate(&format!("INSERT INTO {} VALUES (5, 'a', 0)", table));
+            assert_duplicate(&format!("INSERT INTO {} VALUES (5, 'c', 0), (6, 'c', 0)", table));
+            assert_duplicate(&format!("UPDATE {} SET email = 'a' WHERE id = 2", table));
+            assert_duplicate(&format!("UPDATE {} SET id = 1 WHERE id = 2", table));
+            // An upsert on the primary key still may not take another row's email
+            assert_duplicate(&format!(
+                "INSERT INTO {} VALUES (2, 'x', 0), (7, 'a', 0) ON CONFLICT (id) DO UPDATE SET n = EXCLUDED.n",
+                table
+            ));
+            assert_eq!(count_rows(table), 4, "{}", table);
+            let (_, rows) = returned_rows(&format!("SELECT n FROM {} WHERE id = 2", table));
+            assert_eq!(rows[0]["n"], serde_json::json!(2), "{}", table);
+
+            // Values freed by the same statement can be taken again
+            match run(&format!("UPDATE {} SET email = 'c' WHERE id = 1", table)) {
+                ExecutionResult::RowsAffected { count } => assert_eq!(count, 1),
+                other => panic!("Expected RowsAffected, got {:?}", other),
+            }
+            match run(&format!("INSERT INTO {} VALUES (5, 'a', 5)", table)) {
+                ExecutionResult::RowsAffected { count } => assert_eq!(count, 1),
+                other => panic!("Expected RowsAffected, got {:?}", other),
+            }
+
+            // A unique index is refused over stored duplicates, and not registered
+            run(&format!("UPDATE {} SET n = 1 WHERE id = 2", table));
+            match run(&format!("CREATE UNIQUE INDEX {}_n ON {} (n)", table, table)) {
+                ExecutionResult::Error { message } => {
+                    assert!(message.starts_with("Could not create unique index"), "{}", message)
+                }
+                other => panic!("Expected an error, got {:?}", other),
+            }
+            match run(&format!("INSERT INTO {} VALUES (6, 'd', 1)", table)) {
+                ExecutionResult::RowsAffected { count } => assert_eq!(count, 1),
+                other => panic!("Expected RowsAffected, got {:?}", other),
+            }
+            run(&format!("DROP TABLE {}", table));
+        }
+    }
+
+    // ==========================================
+    // RETURNING Tests
+    // ==========================================
+
+    fn returned_rows(sql: &str) -> (Vec<String>, Vec<HashMap<String, serde_json::Value>>) {
+        match run(sql) {
+            ExecutionResult::Rows { columns, rows } => (columns, rows),
+            other => panic!("Expected rows for {}, got {:?}", sql, other),
+        }
+    }
+
+    #[test]
+    fn test_insert_returning() {
+        run("DROP TABLE IF EXISTS exec_ret_insert");
+        run("CREATE TABLE exec_ret_insert (id INT, name TEXT)");
+
+        let (columns, rows) =
+            returned_rows("INSERT INTO exec_ret_insert VALUES (1, 'a'), (2, 'b') RETURNING *");
+        assert_eq!(columns, vec!["id", "name"]);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1]["name"], serde_json::json!("b"));
+
+        let (columns, rows) =
+            returned_rows("INSERT INTO exec_ret_insert (id) VALUES (3) RETURNING id + 10 AS shifted, name");
+        assert_eq!(columns, vec!["shifted", "name"]);
+        assert_eq!(rows[0]["shifted"], serde_json::json!(13));
+        assert!(rows[0]["name"].is_null());
+
+        run("DROP TABLE exec_ret_insert");
+    }
+
+    #[test]
+    fn test_update_returning_new_values() {
+        run("DROP TABLE IF EXISTS exec_ret_update");
+        run("CREATE TABLE exec_ret_update (id INT, n INT)");
+        run("INSERT INTO exec_ret_update VALUES (1, 10), (2, 20), (3, 30)");
+
+        let (_, rows) =
+            returned_rows("UPDATE exec_ret_update SET n = n + 1 WHERE id >= 2 RETURNING id, n");
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["n"], serde_json::json!(21));
+        assert_eq!(rows[1]["n"], serde_json::json!(31));
+
+        match run("UPDATE exec_ret_update SET n = 0 WHERE id = 1") {
+            ExecutionResult::RowsAffected { count } => assert_eq!(count, 1),
+            other => panic!("Expected RowsAffected, got {:?}", other),
+        }
+
+        run("DROP TABLE exec_ret_update");
+    }
+
+    #[test]
+    fn test_delete_returning_deleted_rows() {
+        run("DROP TABLE IF EXISTS exec_ret_delete");
+        run("CREATE TABLE exec_ret_delete (id INT, name TEXT)");
+        run("INSERT INTO exec_ret_delete VALUES (1, 'a'), (2, 'b'), (3, 'c')");
+
+        let (_, rows) = returned_rows("DELETE FROM exec_ret_delete WHERE id < 3 RETURNING name");
+        let names: Vec<&serde_json::Value> = rows.iter().map(|r| &r["name"]).collect();
+        assert_eq!(names, vec!["a", "b"]);
+        assert_eq!(count_rows("exec_ret_delete"), 1);
+
+        run("DROP TABLE exec_ret_delete");
+    }
+
+    #[test]
+    fn test_upsert_returning() {
+        run("DROP TABLE IF EXISTS exec_ret_upsert");
+        run("CREATE TABLE exec_ret_upsert (id INT PRIMARY KEY, n INT)");
+        run("INSERT INTO exec_ret_upsert VALUES (1, 1)");
+
+        let (_, rows) = returned_rows(
+            "INSERT INTO exec_ret_upsert VALUES (1, 5), (2, 2) \
+             ON CONFLICT (id) DO UPDATE SET n = exec_ret_upsert.n + EXCLUDED.n RETURNING id, n",
+        );
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["n"], serde_json::json!(6));
+        assert_eq!(rows[1]["n"], serde_json::json!(2));
+
+        run("DROP TABLE exec_ret_upsert");
+    }
+
+    #[test]
+    fn test_group_by_with_aggregates_and_order_by() {
+        run("DROP TABLE IF EXISTS exec_group_by");
+        run("CREATE TABLE exec_group_by (dept TEXT, salary INT)");
+        run("INSERT INTO exec_group_by VALUES ('eng', 100), ('ops', 50), ('eng', 120), ('ops', 70), ('hr', 60)");
+
+        let (columns, rows) = returned_rows(
+            "SELECT dept, COUNT(*) AS n, SUM(salary) AS total, MAX(salary) FROM exec_group_by \
+             GROUP BY dept HAVING COUNT(*) > 1 ORDER BY total DESC",
+        );
+        assert_eq!(columns, vec!["dept", "n", "total", "max"]);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["dept"], serde_json::json!("eng"));
+        assert_eq!(rows[0]["n"], serde_json::json!(2));
+        assert_eq!(rows[0]["total"], serde_json::json!(220));
+        assert_eq!(rows[0]["max"], serde_json::json!(120));
+        assert_eq!(rows[1]["dept"], serde_json::json!("ops"));
+
+        let (_, rows) = returned_rows("SELECT COUNT(*), AVG(salary) FROM exec_group_by WHERE salary > 1000");
+        assert_eq!(rows[0]["count"], serde_json::json!(0));
+        assert_eq!(rows[0]["avg"], serde_json::Value::Null);
+
+        run("DROP TABLE exec_group_by");
+    }
+
+    #[test]
+    fn test_order_by_limit_offset_over_join() {
+        run("DROP TABLE IF EXISTS exec_join_a");
+        run("DROP TABLE IF EXISTS exec_join_b");
+        run("CREATE TABLE exec_join_a (id INT, name TEXT)");
+        run("CREATE TABLE exec_join_b (a_id INT, score INT)");
+        run("INSERT INTO exec_join_a VALUES (1, 'x'), (2, 'y'), (3, 'z')");
+        run("INSERT INTO exec_join_b VALUES (1, 5), (2, 9), (1, 7)");
+
+        let (_, rows) = returned_rows(
+            "SELECT a.name, b.score FROM exec_join_a a JOIN exec_join_b b ON a.id = b.a_id \
+             ORDER BY b.score DESC LIMIT 2 OFFSET 1",
+        );
+        let scores: Vec<&serde_json::Value> = rows.iter().map(|r| &r["score"]).collect();
+        assert_eq!(scores, vec![7, 5]);
+
+        let (_, rows) = returned_rows(
+            "SELECT DISTINCT a.name FROM exec_join_a a LEFT JOIN exec_join_b b ON a.id = b.a_id ORDER BY 1",
+        );
+        let names: Vec<&serde_json::Value> = rows.iter().map(|r| &r["name"]).collect();
+        assert_eq!(names, vec!["x", "y", "z"]);
+
+        run("DROP TABLE exec_join_a");
+        run("DROP TABLE exec_join_b");
+    }
+
+    #[test]
+    fn test_self_join_keeps_both_columns() {
+        run("DROP TABLE IF EXISTS exec_self_join");
+        run("CREATE TABLE exec_self_join (id INT, name TEXT)");
+        run("INSERT INTO exec_self_join VALUES (1, 'x'), (2, 'y')");
+
+        let (columns, rows) = returned_rows(
+            "SELECT a.id, b.id, b.name FROM exec_self_join a JOIN exec_self_join b ON a.id = b.id ORDER BY 1",
+        );
+        assert_eq!(columns, vec!["id", "id_1", "name"]);
+        assert_eq!(rows[1]["id"], serde_json::json!(2));
+        assert_eq!(rows[1]["id_1"], serde_json::json!(2));
+
+        let (columns, _) = returned_rows("SELECT * FROM exec_self_join a JOIN exec_self_join b ON a.id = b.id");
+        assert_eq!(columns, vec!["id", "name", "id_1", "name_1"]);
+
+        run("DROP TABLE exec_self_join");
+    }
 }
@@ -0,0 +1,1986 @@
+// Physical operators - pull-based (Volcano) execution of logical plans
+use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::ops::Bound;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::parallel::{self, ParallelSeqScan};
+use super::vectorized::{BatchFilter, VectorizedAggregate, VectorizedScan, VECTORIZE_MIN_ROWS};
+use super::{Executor, QueryScope, ResultSet};
+use crate::db::catalog::{self, TableFormat};
+use crate::db::optimizer::conjuncts;
+use crate::db::planner::cost::{self, AccessPath, CostModel};
+use crate::db::planner::{aggregate_key, LogicalPlan};
+use crate::db::sql::constants::{BinaryOperator, JoinType, OrderBy, OrderDirection};
+use crate::db::sql::parser::{Expression, SqlPrettyPrinter};
+use crate::db::storage::{engine, KeyRange, Row, ScanFilter, StorageEngine, TableSnapshot, Value};
+
+/// Rows a sequential scan copies out of storage per fetch
+pub const SCAN_BATCH_SIZE: usize = 1024;
+
+/// (qualifier, column) pairs describing the rows an operator produces.
+/// Projected columns have an empty qualifier.
+pub type Schema = Vec<(String, String)>;
+
+/// A node of a physical plan. Rows are pulled one at a time from the root, so
+/// each operator only does as much work as its consumer asks for.
+pub trait PhysicalOperator: Send {
+    fn schema(&self) -> &Schema;
+
+    /// Produce the next row, or None once the operator is exhausted
+    fn next(&mut self) -> Result<Option<Row>, String>;
+
+    /// Node name shown by EXPLAIN, e.g. "Seq Scan"
+    fn name(&self) -> &'static str;
+
+    /// What this node works on (table, predicate, keys, ...), shown by EXPLAIN
+    fn detail(&self) -> String {
+        String::new()
+    }
+
+    /// Inputs of this node
+    fn children(&self) -> Vec<&dyn PhysicalOperator> {
+        Vec::new()
+    }
+
+    /// Runtime statistics, present when the plan was lowered for EXPLAIN ANALYZE
+    fn stats(&self) -> Option<&OperatorStats> {
+        None
+    }
+}
+
+pub type BoxedOperator = Box<dyn PhysicalOperator>;
+
+/// What EXPLAIN ANALYZE reports for one plan node
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OperatorStats {
+    /// Rows the node produced
+    pub rows: u64,
+    /// Times the node was started (0 if its consumer never pulled from it)
+    pub loops: u64,
+    /// Time spent producing rows, including time spent in its inputs
+    pub elapsed: Duration,
+}
+
+/// Wraps an operator and records `OperatorStats` for it
+pub struct Instrumented {
+    inner: BoxedOperator,
+    stats: OperatorStats,
+}
+
+impl Instrumented {
+    pub fn new(inner: BoxedOperator) -> Self {
+        Self {
+            inner,
+            stats: OperatorStats::default(),
+        }
+    }
+}
+
+impl PhysicalOperator for Instrumented {
+    fn schema(&self) -> &Schema {
+        self.inner.schema()
+    }
+
+    fn next(&mut self) -> Result<Option<Row>, String> {
+        // None of the operators rescan their inputs, so a node runs at most once
+        self.stats.loops = 1;
+        let started = Instant::now();
+        let row = self.inner.next();
+        self.stats.elapsed += started.elapsed();
+        if let Ok(Some(_)) = row {
+            self.stats.rows += 1;
+        }
+        row
+    }
+
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn detail(&self) -> String {
+        self.inner.detail()
+    }
+
+    fn children(&self) -> Vec<&dyn PhysicalOperator> {
+        self.inner.children()
+    }
+
+    fn stats(&self) -> Option<&OperatorStats> {
+        Some(&self.stats)
+    }
+}
+
+/// Output column names of an operator, in order
+pub fn column_names(schema: &Schema) -> Vec<String> {
+    schema.iter().map(|(_, name)| name.clone()).collect()
+}
+
+/// SQL text of an expression, for EXPLAIN output
+fn print_expression(expr: &Expression) -> String {
+    SqlPrettyPrinter::new().print_expression(expr)
+}
+
+/// Expose every listed column of `row` both bare and as `qualifier.column`
+pub(crate) fn qualify_row(qualifier: &str, row: Row) -> Row {
+    let mut qualified = Row::with_capacity(row.len() * 2);
+    for (name, value) in row {
+        qualified.insert(format!("{}.{}", qualifier, name), value.clone());
+        qualified.insert(name, value);
+    }
+    qualified
+}
+
+/// How a plan is lowered
+#[derive(Debug, Clone, Copy)]
+struct LowerOptions {
+    /// Wrap every operator in `Instrumented` (EXPLAIN ANALYZE)
+    instrument: bool,
+    /// Workers parallel operators may use
+    workers: usize,
+    /// Run scan-heavy subtrees over column batches when they qualify
+    vectorize: bool,
+}
+
+impl Executor {
+    /// Turn a logical plan into a tree of physical operators
+    pub(crate) fn lower_plan(plan: &LogicalPlan, scope: &QueryScope) -> Result<BoxedOperator, String> {
+        Self::lower_plan_with(plan, scope, parallel::worker_limit(), true)
+    }
+
+    /// Like `lower_plan`, but every operator records `OperatorStats` (EXPLAIN ANALYZE)
+    pub(crate) fn lower_plan_instrumented(
+        plan: &LogicalPlan,
+        scope: &QueryScope,
+    ) -> Result<BoxedOperator, String> {
+        let options = LowerOptions {
+            instrument: true,
+            workers: parallel::worker_limit(),
+            vectorize: true,
+        };
+        Self::lower(plan, scope, options)
+    }
+
+    /// Like `lower_plan`, with an explicit worker count instead of the
+    /// configured one, and vectorized operators only if `vectorize` is set
+    pub(crate) fn lower_plan_with(
+        plan: &LogicalPlan,
+        scope: &QueryScope,
+        workers: usize,
+        vectorize: bool,
+    ) -> Result<BoxedOperator, String> {
+        let options = LowerOptions {
+            instrument: false,
+            workers: workers.max(1),
+            vectorize,
+        };
+        Self::lower(plan, scope, options)
+    }
+
+    fn lower(plan: &LogicalPlan, scope: &QueryScope, options: LowerOptions) -> Result<BoxedOperator, String> {
+        let lower = |input: &LogicalPlan| Self::lower(input, scope, options);
+        let wrap = |op: BoxedOperator| -> BoxedOperator {
+            if options.instrument {
+                Box::new(Instrumented::new(op))
+            } else {
+                op
+            }
+        };
+        // Small inputs are not worth handing to other threads
+        let workers_for = |input: &LogicalPlan| -> usize {
+            if options.workers > 1 && CostModel::new(scope).estimate_rows(input) >= parallel::PARALLEL_MIN_ROWS {
+                options.workers
+            } else {
+                1
+            }
+        };
+        let op: BoxedOperator = match plan {
+            LogicalPlan::Scan { table, qualifier } => match scope.get(table) {
+                // Common table expressions shadow stored tables
+                Some(relation) => Box::new(RelationScan::new(Arc::clone(relation), qualifier)),
+                None => match workers_for(plan) {
+                    1 => Box::new(SeqScan::new(table, qualifier)?),
+                    workers => Box::new(ParallelSeqScan::new(table, qualifier, None, workers, options.vectorize)?),
+                },
+            },
+            LogicalPlan::SingleRow => Box::new(SingleRow::default()),
+            LogicalPlan::Subquery { input, alias } => Box::new(SubqueryScan::new(lower(input)?, alias)),
+            LogicalPlan::Filter { input, predicate } => {
+                let input = match input.as_ref() {
+                    LogicalPlan::Scan { table, qualifier } if scope.get(table).is_none() => {
+                        match CostModel::new(scope).access_path(table, qualifier, predicate) {
+                            // The filter stays on top: the index only narrows the candidates
+                            AccessPath::Index { index, column, range } => {
+                                wrap(Box::new(IndexScan::new(table, qualifier, &index.name, &column, range)?))
+                            }
+                            // Workers scan and filter in one pass
+                            AccessPath::Sequential => match workers_for(input) {
+                                1 => match Self::batch_filter(input, predicate, scope, options) {
+                                    Some(filter) => {
+                                        return Ok(wrap(Box::new(VectorizedScan::new(table, qualifier, filter)?)));
+                                    }
+                                    None => wrap(Box::new(SeqScan::pruned_by(table, qualifier, predicate)?)),
+                                },
+                                workers => {
+                                    let predicate = Some(predicate.clone());
+                                    return Ok(wrap(Box::new(ParallelSeqScan::new(
+                                        table,
+                                        qualifier,
+                                        predicate,
+                                        workers,
+                                        options.vectorize,
+                                    )?)));
+                                }
+                            },
+                        }
+                    }
+                    _ => lower(input)?,
+                };
+                Box::new(Filter {
+                    input,
+                    predicate: predicate.clone(),
+                })
+            }
+            LogicalPlan::Join { .. } => match CostModel::new(scope).order_joins(plan) {
+                Some(order) => Self::lower_join_tree(&order.plan, scope, options, Some(&order.original_order))?,
+                None => Self::lower_join_tree(plan, scope, options, None)?,
+            },
+            LogicalPlan::Aggregate {
+                input,
+                group_by,
+                aggregates,
+            } => match Self::vectorized_aggregate(input, group_by, aggregates, scope, options)? {
+                Some(aggregate) => Box::new(aggregate),
+                None => Box::new(
+                    HashAggregate::new(lower(input)?, group_by.clone(), aggregates.clone())
+                        .with_workers(workers_for(input)),
+                ),
+            },
+            LogicalPlan::Project { input, exprs } => Box::new(Project::new(lower(input)?, exprs)),
+            LogicalPlan::Distinct { input } => Box::new(Distinct::new(lower(input)?)),
+            LogicalPlan::Sort { input, order_by } => Box::new(Sort::new(lower(input)?, order_by.clone())),
+            LogicalPlan::Limit {
+                input,
+                limit,
+                offset,
+            } => Box::new(Limit::new(lower(input)?, *limit, *offset)),
+            LogicalPlan::Union { left, right, all } => {
+                Box::new(Union::new(lower(left)?, lower(right)?, *all)?)
+            }
+            LogicalPlan::With {
+                recursive,
+                ctes,
+                input,
+            } => {
+                // CTEs are materialized up front; the body then scans them like tables
+                let mut scope = scope.clone();
+                for cte in ctes {
+                    let result_set = Self::materialize_cte(cte, *recursive, &scope)?;
+                    scope.bind(&cte.name, result_set);
+                }
+                return Self::lower(input, &scope, options);
+            }
+        };
+
+        Ok(wrap(op))
+    }
+
+    /// The vectorized form of a filter over a stored table, if the table is
+    /// big enough to be worth it and the predicate compiles
+    fn batch_filter(
+        scan: &LogicalPlan,
+        predicate: &Expression,
+        scope: &QueryScope,
+        options: LowerOptions,
+    ) -> Option<BatchFilter> {
+        let LogicalPlan::Scan { qualifier, .. } = scan else {
+            return None;
+        };
+        if !options.vectorize || CostModel::new(scope).estimate_rows(scan) < VECTORIZE_MIN_ROWS {
+            return None;
+        }
+        BatchFilter::compile(predicate, qualifier)
+    }
+
+    /// Aggregation straight over a sequentially scanned (and optionally
+    /// filtered) stored table runs over column batches when everything compiles
+    fn vectorized_aggregate(
+        input: &LogicalPlan,
+        group_by: &[Expression],
+        aggregates: &[Expression],
+        scope: &QueryScope,
+        options: LowerOptions,
+    ) -> Result<Option<VectorizedAggregate>, String> {
+        let (scan, predicate) = match input {
+            LogicalPlan::Filter { input, predicate } => (input.as_ref(), Some(predicate)),
+            scan => (scan, None),
+        };
+        let LogicalPlan::Scan { table, qualifier } = scan else {
+            return Ok(None);
+        };
+        let cost = CostModel::new(scope);
+        if !options.vectorize || scope.get(table).is_some() || cost.estimate_rows(scan) < VECTORIZE_MIN_ROWS {
+            return Ok(None);
+        }
+        if let Some(predicate) = predicate {
+            if !matches!(cost.access_path(table, qualifier, predicate), AccessPath::Sequential) {
+                return Ok(None);
+            }
+        }
+
+        let workers = if options.workers > 1 && cost.estimate_rows(input) >= parallel::PARALLEL_MIN_ROWS {
+            options.workers
+        } else {
+            1
+        };
+        VectorizedAggregate::try_new(table, qualifier, predicate, group_by, aggregates, workers)
+    }
+
+    /// Lower a join without reconsidering its order (the inputs of a reordered
+    /// chain are joins too). `original_order` is set on the top join of a
+    /// reordered chain: the relations in the order the query wrote them.
+    /// Equi-joins become hash joins; anything else a nested loop.
+    fn lower_join_tree(
+        plan: &LogicalPlan,
+        scope: &QueryScope,
+        options: LowerOptions,
+        original_order: Option<&[String]>,
+    ) -> Result<BoxedOperator, String> {
+        let LogicalPlan::Join {
+            left: left_plan,
+            right,
+            join_type,
+            condition,
+        } = plan
+        else {
+            return Err("Expected a join".to_string());
+        };
+        let left = match left_plan.as_ref() {
+            LogicalPlan::Join { .. } => {
+                let join = Self::lower_join_tree(left_plan, scope, options, None)?;
+                if options.instrument {
+                    Box::new(Instrumented::new(join))
+                } else {
+                    join
+                }
+            }
+            other => Self::lower(other, scope, options)?,
+        };
+        let right = Self::lower(right, scope, options)?;
+
+        // Output columns in written order; unqualified names are re-resolved
+        // against it so they keep naming the leftmost written relation
+        let output_schema = original_order.map(|order| {
+            let mut schema = left.schema().clone();
+            schema.extend(right.schema().iter().cloned());
+            schema.sort_by_key(|(qualifier, _)| order.iter().position(|q| q == qualifier));
+            schema
+        });
+
+        let hash_keys = match join_type {
+            JoinType::Inner | JoinType::Left => condition
+                .as_ref()
+                .and_then(|c| HashJoin::equi_keys(c, left.schema(), right.schema())),
+            _ => None,
+        };
+        Ok(match hash_keys {
+            Some(keys) => {
+                let workers = if options.workers > 1
+                    && CostModel::new(scope).estimate_rows(left_plan) >= parallel::PARALLEL_MIN_ROWS
+                {
+                    options.workers
+                } else {
+                    1
+                };
+                let mut join = HashJoin::new(left, right, join_type.clone(), condition.clone(), keys, workers);
+                join.output_schema = output_schema;
+                if let Some(schema) = &join.output_schema {
+                    join.schema = schema.clone();
+                }
+                Box::new(join)
+            }
+            None => {
+                let mut join = NestedLoopJoin::new(left, right, join_type.clone(), condition.clone());
+                join.output_schema = output_schema;
+                if let Some(schema) = &join.output_schema {
+                    join.schema = schema.clone();
+                }
+                Box::new(join)
+            }
+        })
+    }
+
+    /// Pull every row out of an operator
+    pub(crate) fn collect_rows(mut op: BoxedOperator) -> Result<ResultSet, String> {
+        let columns = column_names(op.schema());
+        let mut rows = Vec::new();
+        while let Some(row) = op.next()? {
+            rows.push(row);
+        }
+        Ok(ResultSet { columns, rows })
+    }
+}
+
+/// Reads a stored table in batches of `SCAN_BATCH_SIZE` rows
+pub struct SeqScan {
+    /// Every batch comes from this, so concurrent writes don't shift rows
+    /// between batches
+    snapshot: Arc<dyn TableSnapshot>,
+    table: String,
+    qualifier: String,
+    schema: Schema,
+    filter: ScanFilter,
+    buffer: VecDeque<Row>,
+    next_offset: usize,
+    exhausted: bool,
+    skipped_chunks: usize,
+}
+
+impl SeqScan {
+    pub fn new(table: &str, qualifier: &str) -> Result<Self, String> {
+        let snapshot = engine::current().snapshot(table)?;
+        let first = snapshot.scan(0, SCAN_BATCH_SIZE, &ScanFilter::default())?.rows;
+
+        Ok(Self {
+            snapshot,
+            table: table.to_string(),
+            qualifier: qualifier.to_string(),
+            schema: table_schema(table, qualifier, first.first()),
+            filter: ScanFilter::default(),
+            exhausted: first.len() < SCAN_BATCH_SIZE,
+            next_offset: first.len(),
+            buffer: first.into(),
+            skipped_chunks: 0,
+        })
+    }
+
+    /// Let a columnar table skip the chunks `predicate` rules out. The
+    /// predicate itself is still applied above the scan.
+    pub fn pruned_by(table: &str, qualifier: &str, predicate: &Expression) -> Result<Self, String> {
+        let filter = scan_filter(table, qualifier, Some(predicate));
+        if filter.ranges.is_empty() {
+            return Self::new(table, qualifier);
+        }
+        let snapshot = engine::current().snapshot(table)?;
+        let sample = snapshot.scan(0, 1, &ScanFilter::default())?.rows;
+        Ok(Self {
+            snapshot,
+            table: table.to_string(),
+            qualifier: qualifier.to_string(),
+            schema: table_schema(table, qualifier, sample.first()),
+            filter,
+            buffer: VecDeque::new(),
+            next_offset: 0,
+            exhausted: false,
+            skipped_chunks: 0,
+        })
+    }
+}
+
+impl PhysicalOperator for SeqScan {
+    fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    fn next(&mut self) -> Result<Option<Row>, String> {
+        while self.buffer.is_empty() && !self.exhausted {
+            let batch = self.snapshot.scan(self.next_offset, SCAN_BATCH_SIZE, &self.filter)?;
+            self.exhausted = batch.scanned < SCAN_BATCH_SIZE;
+            self.next_offset += batch.scanned;
+            self.skipped_chunks += batch.skipped_chunks;
+            self.buffer = batch.rows.into();
+        }
+        Ok(self.buffer.pop_front().map(|row| qualify_row(&self.qualifier, row)))
+    }
+
+    fn name(&self) -> &'static str {
+        "Seq Scan"
+    }
+
+    fn detail(&self) -> String {
+        let mut detail = if self.qualifier == self.table {
+            format!("on {}", self.table)
+        } else {
+            format!("on {} {}", self.table, self.qualifier)
+        };
+        detail.push_str(&skipped_chunks_note(self.skipped_chunks));
+        detail
+    }
+}
+
+/// What a scan of `table` filtered by `predicate` has to read. Columnar
+/// tables skip chunks whose zone maps rule out a comparison on one of the
+/// table's own columns; row tables read everything either way.
+pub(crate) fn scan_filter(table: &str, qualifier: &str, predicate: Option<&Expression>) -> ScanFilter {
+    let Some(predicate) = predicate else {
+        return ScanFilter::default();
+    };
+    if engine::current().table_format(table) != Ok(TableFormat::Columnar) {
+        return ScanFilter::default();
+    }
+    let Ok(schema) = catalog::current().get_table(table) else {
+        return ScanFilter::default();
+    };
+    ScanFilter {
+        columns: None,
+        ranges: cost::column_ranges(predicate, qualifier, |column, _| schema.get_column(column).is_some()),
+    }
+}
+
+/// EXPLAIN ANALYZE note on the chunks a columnar scan skipped
+pub(crate) fn skipped_chunks_note(skipped: usize) -> String {
+    if skipped == 0 {
+        String::new()
+    } else {
+        format!(" (chunks skipped={})", skipped)
+    }
+}
+
+/// Columns of a stored table, ordered as declared in the catalog (or by name
+/// from a sample row when the table is not in the catalog)
+pub(crate) fn table_schema(table: &str, qualifier: &str, sample: Option<&Row>) -> Schema {
+    let columns: Vec<String> = match catalog::current().get_table(table) {
+        Ok(schema) => schema.columns.iter().map(|c| c.name.clone()).collect(),
+        Err(_) => {
+            let mut keys: Vec<String> = sample.map(|r| r.keys().cloned().collect()).unwrap_or_default();
+            keys.sort();
+            keys
+        }
+    };
+    columns.into_iter().map(|c| (qualifier.to_string(), c)).collect()
+}
+
+/// Reads the rows of a stored table whose indexed column lies in a range,
+/// fetched through the storage index on the first pull
+pub struct IndexScan {
+    storage: Arc<dyn StorageEngine>,
+    table: String,
+    qualifier: String,
+    index: String,
+    column: String,
+    range: KeyRange,
+    schema: Schema,
+    rows: Option<VecDeque<Row>>,
+}
+
+impl IndexScan {
+    pub fn new(table: &str, qualifier: &str, index: &str, column: &str, range: KeyRange) -> Result<Self, String> {
+        catalog::current().get_table(table)?;
+        Ok(Self {
+            storage: engine::current(),
+            table: table.to_string(),
+            qualifier: qualifier.to_string(),
+            index: index.to_string(),
+            column: column.to_string(),
+            range,
+            schema: table_schema(table, qualifier, None),
+            rows: None,
+        })
+    }
+
+    fn describe_range(&self) -> String {
+        let bound = |bound: &Bound<Value>, inclusive: &str, exclusive: &str| match bound {
+            Bound::Included(v) => Some(format!("{} {} {}", self.column, inclusive, literal(v))),
+            Bound::Excluded(v) => Some(format!("{} {} {}", self.column, exclusive, literal(v))),
+            Bound::Unbounded => None,
+        };
+        match (&self.range.lower, &self.range.upper) {
+            (Bound::Included(low), Bound::Included(high)) if low == high => {
+                format!("{} = {}", self.column, literal(low))
+            }
+            (lower, upper) => [bound(lower, ">=", ">"), bound(upper, "<=", "<")]
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>()
+                .join(" AND "),
+        }
+    }
+}
+
+fn literal(value: &Value) -> String {
+    print_expression(&Expression::Literal(value.to_literal()))
+}
+
+impl PhysicalOperator for IndexScan {
+    fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    fn next(&mut self) -> Result<Option<Row>, String> {
+        if self.rows.is_none() {
+            self.rows = Some(self.storage.index_scan(&self.table, &self.column, &self.range)?.into());
+        }
+        let row = self.rows.as_mut().and_then(VecDeque::pop_front);
+        Ok(row.map(|row| qualify_row(&self.qualifier, row)))
+    }
+
+    fn name(&self) -> &'static str {
+        "Index Scan"
+    }
+
+    fn detail(&self) -> String {
+        let target = if self.qualifier == self.table {
+            self.table.clone()
+        } else {
+            format!("{} {}", self.table, self.qualifier)
+        };
+        format!("using {} on {} ({})", self.index, target, self.describe_range())
+    }
+}
+
+/// Reads a materialized relation, e.g. a common table expression
+pub struct RelationScan {
+    relation: Arc<ResultSet>,
+    qualifier: String,
+    schema: Schema,
+    position: usize,
+}
+
+impl RelationScan {
+    pub fn new(relation: Arc<ResultSet>, qualifier: &str) -> Self {
+        let schema = relation
+            .columns
+            .iter()
+            .map(|c| (qualifier.to_string(), c.clone()))
+            .collect();
+        Self {
+            relation,
+            qualifier: qualifier.to_string(),
+            schema,
+            position: 0,
+        }
+    }
+}
+
+impl PhysicalOperator for RelationScan {
+    fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    fn next(&mut self) -> Result<Option<Row>, String> {
+        let row = self.relation.rows.get(self.position).cloned();
+        self.position += 1;
+        Ok(row.map(|row| qualify_row(&self.qualifier, row)))
+    }
+
+    fn name(&self) -> &'static str {
+        "CTE Scan"
+    }
+
+    fn detail(&self) -> String {
+        format!("on {}", self.qualifier)
+    }
+}
+
+/// Produces one empty row (the input of a SELECT without FROM)
+#[derive(Default)]
+pub struct SingleRow {
+    schema: Schema,
+    done: bool,
+}
+
+impl PhysicalOperator for SingleRow {
+    fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    fn next(&mut self) -> Result<Option<Row>, String> {
+        if self.done {
+            return Ok(None);
+        }
+        self.done = true;
+        Ok(Some(Row::new()))
+    }
+
+    fn name(&self) -> &'static str {
+        "Result"
+    }
+}
+
+/// Re-qualifies a subquery's output columns with the derived table's alias
+pub struct SubqueryScan {
+    input: BoxedOperator,
+    alias: String,
+    columns: Vec<String>,
+    schema: Schema,
+}
+
+impl SubqueryScan {
+    pub fn new(input: BoxedOperator, alias: &str) -> Self {
+        let columns = column_names(input.schema());
+        let schema = columns.iter().map(|c| (alias.to_string(), c.clone())).collect();
+        Self {
+            input,
+            alias: alias.to_string(),
+            columns,
+            schema,
+        }
+    }
+}
+
+impl PhysicalOperator for SubqueryScan {
+    fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    fn next(&mut self) -> Result<Option<Row>, String> {
+        Ok(self.input.next()?.map(|mut row| {
+            let listed: Row = self
+                .columns
+                .iter()
+                .map(|c| (c.clone(), row.remove(c).unwrap_or(Value::Null)))
+                .collect();
+            qualify_row(&self.alias, listed)
+        }))
+    }
+
+    fn name(&self) -> &'static str {
+        "Subquery Scan"
+    }
+
+    fn detail(&self) -> String {
+        format!("on {}", self.alias)
+    }
+
+    fn children(&self) -> Vec<&dyn PhysicalOperator> {
+        vec![self.input.as_ref()]
+    }
+}
+
+/// Passes through rows for which the predicate holds
+pub struct Filter {
+    input: BoxedOperator,
+    predicate: Expression,
+}
+
+impl PhysicalOperator for Filter {
+    fn schema(&self) -> &Schema {
+        self.input.schema()
+    }
+
+    fn next(&mut self) -> Result<Option<Row>, String> {
+        while let Some(row) = self.input.next()? {
+            if Executor::eval_condition(&self.predicate, &row) {
+                return Ok(Some(row));
+            }
+        }
+        Ok(None)
+    }
+
+    fn name(&self) -> &'static str {
+        "Filter"
+    }
+
+    fn detail(&self) -> String {
+        print_expression(&self.predicate)
+    }
+
+    fn children(&self) -> Vec<&dyn PhysicalOperator> {
+        vec![self.input.as_ref()]
+    }
+}
+
+/// One output column of a projection
+enum ProjectItem {
+    /// Column copied from the input (from `*` expansion), output as `name`
+    Column { qualifier: String, column: String, name: String },
+    Expr { name: String, expr: Expression },
+}
+
+/// Evaluates the SELECT list, expanding `*` to the input's columns
+pub struct Project {
+    input: BoxedOperator,
+    items: Vec<ProjectItem>,
+    schema: Schema,
+}
+
+impl Project {
+    pub fn new(input: BoxedOperator, exprs: &[Expression]) -> Self {
+        let mut items = Vec::new();
+        for (i, expr) in exprs.iter().enumerate() {
+            match expr {
+                Expression::Identifier(name) if name == "*" => {
+                    for (qualifier, name) in input.schema() {
+                        items.push(ProjectItem::Column {
+                            qualifier: qualifier.clone(),
+                            column: name.clone(),
+                            name: name.clone(),
+                        });
+                    }
+                }
+                _ => items.push(ProjectItem::Expr {
+                    name: Executor::output_column_name(expr, i),
+                    expr: expr.clone(),
+                }),
+            }
+        }
+
+        // Rows are keyed by column name, so a name seen before (`a.id, b.id`,
+        // or `*` over a join) gets the first free `_1`, `_2`, ... suffix
+        let mut schema: Schema = Vec::new();
+        for item in &mut items {
+            let name = match item {
+                ProjectItem::Column { name, .. } | ProjectItem::Expr { name, .. } => name,
+            };
+            let taken = |candidate: &str| schema.iter().any(|(_, n)| n == candidate);
+            if taken(name) {
+                let suffix = (1..).find(|i| !taken(&format!("{}_{}", name, i))).unwrap_or_default();
+                *name = format!("{}_{}", name, suffix);
+            }
+            schema.push((String::new(), name.clone()));
+        }
+
+        Self { input, items, schema }
+    }
+}
+
+impl PhysicalOperator for Project {
+    fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    fn next(&mut self) -> Result<Option<Row>, String> {
+        let row = match self.input.next()? {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+
+        let mut out = Row::with_capacity(self.schema.len());
+        for item in &self.items {
+            match item {
+                ProjectItem::Column { qualifier, column, name } => {
+                    let value = row
+                        .get(&format!("{}.{}", qualifier, column))
+                        .or_else(|| row.get(column))
+                        .cloned()
+                        .unwrap_or(Value::Null);
+                    out.insert(name.clone(), value);
+                }
+                ProjectItem::Expr { name, expr } => {
+                    out.insert(name.clone(), Executor::eval_expression(expr, &row));
+                }
+            }
+        }
+        Ok(Some(out))
+    }
+
+    fn name(&self) -> &'static str {
+        "Project"
+    }
+
+    fn detail(&self) -> String {
+        column_names(&self.schema).join(", ")
+    }
+
+    fn children(&self) -> Vec<&dyn PhysicalOperator> {
+        vec![self.input.as_ref()]
+    }
+}
+
+/// Nested-loop join. The inner side is buffered on the first pull; the outer
+/// side streams. RIGHT joins stream the right input so its unmatched rows can
+/// be emitted as they are seen.
+pub struct NestedLoopJoin {
+    outer: BoxedOperator,
+    inner: BoxedOperator,
+    /// Buffered inner rows, loaded on the first pull
+    inner_rows: Option<Vec<Row>>,
+    inner_matched: Vec<bool>,
+    join_type: JoinType,
+    condition: Option<Expression>,
+    pending: VecDeque<Row>,
+    outer_done: bool,
+    schema: Schema,
+    /// Set on the top join of a reordered chain: the columns in the order the
+    /// query wrote its relations. Unqualified names are re-resolved against it
+    /// so they keep naming the leftmost written relation.
+    output_schema: Option<Schema>,
+}
+
+fn join_type_name(join_type: &JoinType) -> &'static str {
+    match join_type {
+        JoinType::Inner => "Inner",
+        JoinType::Left => "Left",
+        JoinType::Right => "Right",
+        JoinType::Full => "Full",
+        JoinType::Cross => "Cross",
+    }
+}
+
+impl NestedLoopJoin {
+    pub fn new(
+        left: BoxedOperator,
+        right: BoxedOperator,
+        join_type: JoinType,
+        condition: Option<Expression>,
+    ) -> Self {
+        let mut schema = left.schema().clone();
+        schema.extend(right.schema().iter().cloned());
+
+        let (outer, inner) = match join_type {
+            JoinType::Right => (right, left),
+            _ => (left, right),
+        };
+
+        Self {
+            outer,
+            inner,
+            inner_rows: None,
+            inner_matched: Vec::new(),
+            join_type,
+            condition,
+            pending: VecDeque::new(),
+            outer_done: false,
+            schema,
+            output_schema: None,
+        }
+    }
+
+    fn load_inner(&mut self) -> Result<Vec<Row>, String> {
+        let mut rows = Vec::new();
+        while let Some(row) = self.inner.next()? {
+            rows.push(row);
+        }
+        self.inner_matched = vec![false; rows.len()];
+        Ok(rows)
+    }
+
+    fn combine(&self, left: &Row, right: &Row) -> Option<Row> {
+        combine_rows(left, right, self.condition.as_ref())
+    }
+}
+
+/// Merge a left and right row and keep it if the join condition holds;
+/// unqualified names resolve to the leftmost relation that has them
+fn combine_rows(left: &Row, right: &Row, condition: Option<&Expression>) -> Option<Row> {
+    let mut merged = left.clone();
+    for (k, v) in right {
+        merged.entry(k.clone()).or_insert_with(|| v.clone());
+    }
+    match condition {
+        Some(expr) if !Executor::eval_condition(expr, &merged) => None,
+        _ => Some(merged),
+    }
+}
+
+/// A row of one join input, extended with NULLs for the other input's columns
+fn pad_row(row: &Row, missing: &Schema) -> Row {
+    let mut padded = row.clone();
+    for (qualifier, name) in missing {
+        padded.insert(format!("{}.{}", qualifier, name), Value::Null);
+        padded.entry(name.clone()).or_insert(Value::Null);
+    }
+    padded
+}
+
+/// Point unqualified names of a reordered join's row back at the leftmost
+/// written relation that has them
+fn restore_written_order(row: &mut Row, output_schema: Option<&Schema>) {
+    if let Some(schema) = output_schema {
+        for (qualifier, name) in schema.iter().rev() {
+            if let Some(value) = row.get(&format!("{}.{}", qualifier, name)) {
+                row.insert(name.clone(), value.clone());
+            }
+        }
+    }
+}
+
+impl PhysicalOperator for NestedLoopJoin {
+    fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    fn next(&mut self) -> Result<Option<Row>, String> {
+        if self.inner_rows.is_none() {
+            self.inner_rows = Some(self.load_inner()?);
+        }
+        let inner_rows = self.inner_rows.as_deref().unwrap_or_default();
+
+        loop {
+            if let Some(mut row) = self.pending.pop_front() {
+                restore_written_order(&mut row, self.output_schema.as_ref());
+                return Ok(Some(row));
+            }
+            if self.outer_done {
+                return Ok(None);
+            }
+
+            let outer_row = match self.outer.next()? {
+                Some(row) => row,
+                None => {
+                    self.outer_done = true;
+                    if matches!(self.join_type, JoinType::Full) {
+                        for (i, inner_row) in inner_rows.iter().enumerate() {
+                            if !self.inner_matched[i] {
+                                self.pending.push_back(pad_row(inner_row, self.outer.schema()));
+                            }
+                        }
+                    }
+                    continue;
+                }
+            };
+
+            let right_is_outer = matches!(self.join_type, JoinType::Right);
+            let mut matched = false;
+            for (i, inner_row) in inner_rows.iter().enumerate() {
+                let combined = if right_is_outer {
+                    self.combine(inner_row, &outer_row)
+                } else {
+                    self.combine(&outer_row, inner_row)
+                };
+                if let Some(row) = combined {
+                    self.pending.push_back(row);
+                    self.inner_matched[i] = true;
+                    matched = true;
+                }
+            }
+
+            if !matched
+                && matches!(self.join_type, JoinType::Left | JoinType::Right | JoinType::Full)
+            {
+                self.pending.push_back(pad_row(&outer_row, self.inner.schema()));
+            }
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "Nested Loop"
+    }
+
+    fn detail(&self) -> String {
+        let join = join_type_name(&self.join_type);
+        match &self.condition {
+            Some(condition) => format!("{} join on {}", join, print_expression(condition)),
+            None => format!("{} join", join),
+        }
+    }
+
+    fn children(&self) -> Vec<&dyn PhysicalOperator> {
+        // Report the inputs in query order (left, right)
+        match self.join_type {
+            JoinType::Right => vec![self.inner.as_ref(), self.outer.as_ref()],
+            _ => vec![self.outer.as_ref(), self.inner.as_ref()],
+        }
+    }
+}
+
+/// Rows of a hash join's build side, bucketed by join key
+struct HashTable {
+    rows: Vec<Row>,
+    /// Key -> positions in `rows`, ascending
+    buckets: HashMap<String, Vec<usize>>,
+}
+
+/// Hash join for INNER and LEFT equi-joins. The right input is hashed on the
+/// first pull; the left input streams in batches whose probes are split
+/// across workers. Matches come out in the same order a nested loop would
+/// produce them, and the full condition is still checked on every match.
+pub struct HashJoin {
+    left: BoxedOperator,
+    right: BoxedOperator,
+    join_type: JoinType,
+    condition: Option<Expression>,
+    /// (left key, right key) pairs taken from the condition's equalities
+    keys: Vec<(Expression, Expression)>,
+    workers: usize,
+    table: Option<HashTable>,
+    pending: VecDeque<Row>,
+    left_done: bool,
+    schema: Schema,
+    /// See `NestedLoopJoin::output_schema`
+    output_schema: Option<Schema>,
+}
+
+impl HashJoin {
+    pub fn new(
+        left: BoxedOperator,
+        right: BoxedOperator,
+        join_type: JoinType,
+        condition: Option<Expression>,
+        keys: Vec<(Expression, Expression)>,
+        workers: usize,
+    ) -> Self {
+        let mut schema = left.schema().clone();
+        schema.extend(right.schema().iter().cloned());
+
+        Self {
+            left,
+            right,
+            join_type,
+            condition,
+            keys,
+            workers: workers.max(1),
+            table: None,
+            pending: VecDeque::new(),
+            left_done: false,
+            schema,
+            output_schema: None,
+        }
+    }
+
+    /// The `column = column` conjuncts of `condition` that compare one input
+    /// with the other, as (left, right) pairs; None if there are none
+    pub fn equi_keys(
+        condition: &Expression,
+        left: &Schema,
+        right: &Schema,
+    ) -> Option<Vec<(Expression, Expression)>> {
+        let keys: Vec<(Expression, Expression)> = conjuncts(condition)
+            .into_iter()
+            .filter_map(|conjunct| match conjunct {
+                Expression::BinaryOp {
+                    left: a,
+                    operator: BinaryOperator::Equals,
+                    right: b,
+                } => match (join_side(&a, left, right)?, join_side(&b, left, right)?) {
+                    (JoinSide::Left, JoinSide::Right) => Some((*a, *b)),
+                    (JoinSide::Right, JoinSide::Left) => Some((*b, *a)),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect();
+        (!keys.is_empty()).then_some(keys)
+    }
+
+    fn build(&mut self) -> Result<HashTable, String> {
+        let mut rows = Vec::new();
+        let mut buckets: HashMap<String, Vec<usize>> = HashMap::new();
+        while let Some(row) = self.right.next()? {
+            // NULL keys never satisfy the equality, so they are not hashed
+            if let Some(key) = join_key(self.keys.iter().map(|(_, right)| right), &row) {
+                buckets.entry(key).or_default().push(rows.len());
+            }
+            rows.push(row);
+        }
+        Ok(HashTable { rows, buckets })
+    }
+
+    /// Join a batch of left rows against the hash table
+    fn probe(
+        batch: &[Row],
+        table: &HashTable,
+        keys: &[(Expression, Expression)],
+        condition: Option<&Expression>,
+        pad_unmatched: Option<&Schema>,
+    ) -> Vec<Row> {
+        let mut out = Vec::new();
+        for left in batch {
+            let before = out.len();
+            let candidates = join_key(keys.iter().map(|(left, _)| left), left)
+                .and_then(|key| table.buckets.get(&key));
+            for &i in candidates.into_iter().flatten() {
+                out.extend(combine_rows(left, &table.rows[i], condition));
+            }
+            if out.len() == before {
+                if let Some(missing) = pad_unmatched {
+                    out.push(pad_row(left, missing));
+                }
+            }
+        }
+        out
+    }
+}
+
+#[derive(PartialEq)]
+enum JoinSide {
+    Left,
+    Right,
+}
+
+/// Which join input a column reference belongs to (None if it is not a plain
+/// column, or if both inputs could supply it)
+fn join_side(expr: &Expression, left: &Schema, right: &Schema) -> Option<JoinSide> {
+    let (qualifier, column) = match expr {
+        Expression::QualifiedColumn { table, column } => (Some(table.as_str()), column.as_str()),
+        Expression::Identifier(name) => match name.split_once('.') {
+            Some((qualifier, column)) => (Some(qualifier), column),
+            None => (None, name.as_str()),
+        },
+        _ => return None,
+    };
+    let provides = |schema: &Schema| {
+        schema
+            .iter()
+            .any(|(q, c)| c == column && qualifier.is_none_or(|qualifier| q == qualifier))
+    };
+    match (provides(left), provides(right)) {
+        (true, false) => Some(JoinSide::Left),
+        (false, true) => Some(JoinSide::Right),
+        _ => None,
+    }
+}
+
+/// Hash key of a row's join columns. Values that compare equal get the same
+/// key; None when a value can never compare equal (NULL, NaN).
+fn join_key<'e>(exprs: impl Iterator<Item = &'e Expression>, row: &Row) -> Option<String> {
+    let mut key = String::new();
+    for expr in exprs {
+        let value = match Executor::eval_expression(expr, row) {
+            Value::Null => return None,
+            Value::Float(f) if f.is_nan() => return None,
+            // Also matches -0.0, which equals 0.0
+            Value::Float(0.0) => Value::Float(0.0),
+            value => value,
+        };
+        key.push_str(&format!("{:?}\u{1f}", value));
+    }
+    Some(key)
+}
+
+impl PhysicalOperator for HashJoin {
+    fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    fn next(&mut self) -> Result<Option<Row>, String> {
+        if self.table.is_none() {
+            self.table = Some(self.build()?);
+        }
+
+        loop {
+            if let Some(mut row) = self.pending.pop_front() {
+                restore_written_order(&mut row, self.output_schema.as_ref());
+                return Ok(Some(row));
+            }
+            if self.left_done {
+                return Ok(None);
+            }
+
+            let mut batch = Vec::new();
+            while batch.len() < parallel::MORSEL_SIZE * self.workers {
+                match self.left.next()? {
+                    Some(row) => batch.push(row),
+                    None => {
+                        self.left_done = true;
+                        break;
+                    }
+                }
+            }
+
+            let table = self.table.as_ref().ok_or("Hash table not built")?;
+            let (keys, condition) = (&self.keys, self.condition.as_ref());
+            let pad_unmatched = matches!(self.join_type, JoinType::Left).then(|| self.right.schema());
+            for rows in parallel::map_morsels(&batch, self.workers, |morsel| {
+                Self::probe(morsel, table, keys, condition, pad_unmatched)
+            }) {
+                self.pending.extend(rows);
+            }
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "Hash Join"
+    }
+
+    fn detail(&self) -> String {
+        let join = join_type_name(&self.join_type);
+        let mut detail = match &self.condition {
+            Some(condition) => format!("{} join on {}", join, print_expression(condition)),
+            None => format!("{} join", join),
+        };
+        if self.workers > 1 {
+            detail.push_str(&format!(" (workers={})", self.workers));
+        }
+        detail
+    }
+
+    fn children(&self) -> Vec<&dyn PhysicalOperator> {
+        vec![self.left.as_ref(), self.right.as_ref()]
+    }
+}
+
+/// Running state of one aggregate call within a group
+pub(crate) enum Accumulator {
+    Count(i64),
+    Sum(Option<Value>),
+    Avg { sum: f64, count: i64 },
+    Min(Option<Value>),
+    Max(Option<Value>),
+}
+
+impl Accumulator {
+    pub(crate) fn new(name: &str) -> Self {
+        match name.to_uppercase().as_str() {
+            "COUNT" => Accumulator::Count(0),
+            "SUM" => Accumulator::Sum(None),
+            "AVG" => Accumulator::Avg { sum: 0.0, count: 0 },
+            "MIN" => Accumulator::Min(None),
+            _ => Accumulator::Max(None),
+        }
+    }
+
+    /// Fold in one input value. `None` means COUNT(*), which counts every row.
+    pub(crate) fn update(&mut self, value: Option<Value>) {
+        let value = match value {
+            None => {
+                if let Accumulator::Count(n) = self {
+                    *n += 1;
+                }
+                return;
+            }
+            // Aggregates skip NULL inputs
+            Some(Value::Null) => return,
+            Some(value) => value,
+        };
+
+        match self {
+            Accumulator::Count(n) => *n += 1,
+            Accumulator::Sum(total) => {
+                *total = Some(match total.take() {
+                    Some(t) => Executor::eval_binary_op(&t, &BinaryOperator::Plus, &value),
+                    None => value,
+                });
+            }
+            Accumulator::Avg { sum, count } => {
+                if let Some(v) = value_as_f64(&value) {
+                    *sum += v;
+                    *count += 1;
+                }
+            }
+            Accumulator::Min(current) => {
+                if current.as_ref().is_none_or(|c| sort_order(&value, c) == Ordering::Less) {
+                    *current = Some(value);
+                }
+            }
+            Accumulator::Max(current) => {
+                if current.as_ref().is_none_or(|c| sort_order(&value, c) == Ordering::Greater) {
+                    *current = Some(value);
+                }
+            }
+        }
+    }
+
+    pub(crate) fn finish(&self) -> Value {
+        match self {
+            Accumulator::Count(n) => Value::Integer(*n),
+            Accumulator::Sum(total) => total.clone().unwrap_or(Value::Null),
+            Accumulator::Avg { count: 0, .. } => Value::Null,
+            Accumulator::Avg { sum, count } => Value::Float(sum / *count as f64),
+            Accumulator::Min(v) | Accumulator::Max(v) => v.clone().unwrap_or(Value::Null),
+        }
+    }
+}
+
+fn value_as_f64(value: &Value) -> Option<f64> {
+    match value {
+        Value::Integer(i) => Some(*i as f64),
+        Value::Float(f) => Some(*f),
+        _ => None,
+    }
+}
+
+/// Hash aggregation. Consumes its whole input on the first pull, then emits
+/// one row per group in first-seen order.
+///
+/// With several workers the input is collected first; workers then evaluate
+/// keys and aggregate inputs morsel by morsel, and accumulate disjoint
+/// partitions of the groups. Each group still folds its rows in input order,
+/// so results (floating-point sums included) match a single worker exactly.
+pub struct HashAggregate {
+    input: BoxedOperator,
+    group_by: Vec<Expression>,
+    aggregates: Vec<Expression>,
+    workers: usize,
+    output: Option<VecDeque<Row>>,
+}
+
+impl HashAggregate {
+    pub fn new(input: BoxedOperator, group_by: Vec<Expression>, aggregates: Vec<Expression>) -> Self {
+        Self {
+            input,
+            group_by,
+            aggregates,
+            workers: 1,
+            output: None,
+        }
+    }
+
+    pub fn with_workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self
+    }
+
+    fn aggregate_input(expr: &Expression, row: &Row) -> Option<Value> {
+        match expr {
+            Expression::Function { args, .. } => match args.first() {
+                None => None,
+                Some(Expression::Identifier(star)) if star == "*" => None,
+                Some(arg) => Some(Executor::eval_expression(arg, row)),
+            },
+            _ => None,
+        }
+    }
+
+    pub(crate) fn new_accumulators(aggregates: &[Expression]) -> Vec<Accumulator> {
+        aggregates
+            .iter()
+            .map(|expr| match expr {
+                Expression::Function { name, .. } => Accumulator::new(name),
+                _ => Accumulator::Count(0),
+            })
+            .collect()
+    }
+
+    fn group_key(group_by: &[Expression], row: &Row) -> String {
+        let key_values: Vec<Value> = group_by.iter().map(|e| Executor::eval_expression(e, row)).collect();
+        format!("{:?}", key_values)
+    }
+
+    fn build(&mut self) -> Result<VecDeque<Row>, String> {
+        let mut groups = if self.workers > 1 {
+            self.build_groups_parallel()?
+        } else {
+            self.build_groups()?
+        };
+
+        // Without GROUP BY, aggregates over no rows still yield one row
+        if groups.is_empty() && self.group_by.is_empty() {
+            groups.push((Row::new(), Self::new_accumulators(&self.aggregates)));
+        }
+
+        Ok(groups
+            .into_iter()
+            .map(|(mut row, accumulators)| {
+                for (expr, acc) in self.aggregates.iter().zip(accumulators) {
+                    row.insert(aggregate_key(expr), acc.finish());
+                }
+                row
+            })
+            .collect())
+    }
+
+    /// Groups in first-seen order, each with its first row
+    fn build_groups(&mut self) -> Result<Vec<(Row, Vec<Accumulator>)>, String> {
+        let mut groups: Vec<(Row, Vec<Accumulator>)> = Vec::new();
+        let mut index: HashMap<String, usize> = HashMap::new();
+
+        while let Some(row) = self.input.next()? {
+            let key = Self::group_key(&self.group_by, &row);
+            let group = match index.get(&key) {
+                Some(&g) => g,
+                None => {
+                    groups.push((row.clone(), Self::new_accumulators(&self.aggregates)));
+                    index.insert(key, groups.len() - 1);
+                    groups.len() - 1
+                }
+            };
+
+            for (i, expr) in self.aggregates.iter().enumerate() {
+                groups[group].1[i].update(Self::aggregate_input(expr, &row));
+            }
+        }
+        Ok(groups)
+    }
+
+    /// Same result as `build_groups`, computed by `workers` threads
+    fn build_groups_parallel(&mut self) -> Result<Vec<(Row, Vec<Accumulator>)>, String> {
+        let mut rows = Vec::new();
+        while let Some(row) = self.input.next()? {
+            rows.push(row);
+        }
+        let (group_by, aggregates, partitions) = (&self.group_by, &self.aggregates, self.workers);
+
+        // Phase 1: per row, its group key, the partition owning that group and
+        // its aggregate inputs
+        let evaluated: Vec<(usize, String, Vec<Option<Value>>)> =
+            parallel::map_morsels(&rows, self.workers, |morsel| {
+                morsel
+                    .iter()
+                    .map(|row| {
+                        let key = Self::group_key(group_by, row);
+                        let mut hasher = DefaultHasher::new();
+                        key.hash(&mut hasher);
+                        let partition = (hasher.finish() % partitions as u64) as usize;
+                        let inputs = aggregates.iter().map(|e| Self::aggregate_input(e, row)).collect();
+                        (partition, key, inputs)
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .into_iter()
+            .flatten()
+            .collect();
+
+        // Phase 2: each worker accumulates its own groups, visiting rows in order
+        let mut groups: Vec<(usize, Vec<Accumulator>)> = parallel::map_partitions(partitions, |p| {
+            let mut groups: Vec<(usize, Vec<Accumulator>)> = Vec::new();
+            let mut index: HashMap<&str, usize> = HashMap::new();
+            for (position, (partition, key, inputs)) in evaluated.iter().enumerate() {
+                if *partition != p {
+                    continue;
+                }
+                let group = *index.entry(key).or_insert_with(|| {
+                    groups.push((position, Self::new_accumulators(aggregates)));
+                    groups.len() - 1
+                });
+                for (acc, input) in groups[group].1.iter_mut().zip(inputs) {
+                    acc.update(input.clone());
+                }
+            }
+            groups
+        })
+        .into_iter()
+        .flatten()
+        .collect();
+
+        // First-seen order, as with one worker
+        groups.sort_by_key(|(first, _)| *first);
+        Ok(groups
+            .into_iter()
+            .map(|(first, accumulators)| (rows[first].clone(), accumulators))
+            .collect())
+    }
+}
+
+impl PhysicalOperator for HashAggregate {
+    fn schema(&self) -> &Schema {
+        self.input.schema()
+    }
+
+    fn next(&mut self) -> Result<Option<Row>, String> {
+        if self.output.is_none() {
+            self.output = Some(self.build()?);
+        }
+        Ok(self.output.as_mut().and_then(|rows| rows.pop_front()))
+    }
+
+    fn name(&self) -> &'static str {
+        "Hash Aggregate"
+    }
+
+    fn detail(&self) -> String {
+        let aggregates: Vec<String> = self.aggregates.iter().map(print_expression).collect();
+        let mut detail = if self.group_by.is_empty() {
+            aggregates.join(", ")
+        } else {
+            let keys: Vec<String> = self.group_by.iter().map(print_expression).collect();
+            format!("{} by {}", aggregates.join(", "), keys.join(", "))
+        };
+        if self.workers > 1 {
+            detail.push_str(&format!(" (workers={})", self.workers));
+        }
+        detail
+    }
+
+    fn children(&self) -> Vec<&dyn PhysicalOperator> {
+        vec![self.input.as_ref()]
+    }
+}
+
+/// Ordering used by ORDER BY: NULLs sort after every other value
+fn sort_order(left: &Value, right: &Value) -> Ordering {
+    match (left, right) {
+        (Value::Null, Value::Null) => Ordering::Equal,
+        (Value::Null, _) => Ordering::Greater,
+        (_, Value::Null) => Ordering::Less,
+        _ => Executor::compare(left, right).cmp(&0),
+    }
+}
+
+/// Blocking sort: buffers its input on the first pull
+pub struct Sort {
+    input: BoxedOperator,
+    order_by: Vec<OrderBy>,
+    sorted: Option<std::vec::IntoIter<Row>>,
+}
+
+impl Sort {
+    pub fn new(input: BoxedOperator, order_by: Vec<OrderBy>) -> Self {
+        Self {
+            input,
+            order_by,
+            sorted: None,
+        }
+    }
+
+    fn build(&mut self) -> Result<Vec<Row>, String> {
+        let mut keyed: Vec<(Vec<Value>, Row)> = Vec::new();
+        while let Some(row) = self.input.next()? {
+            let keys = self
+                .order_by
+                .iter()
+                .map(|o| Executor::eval_expression(&o.expression, &row))
+                .collect();
+            keyed.push((keys, row));
+        }
+
+        keyed.sort_by(|(a, _), (b, _)| {
+            for (i, o) in self.order_by.iter().enumerate() {
+                let ordering = match o.direction {
+                    OrderDirection::Asc => sort_order(&a[i], &b[i]),
+                    OrderDirection::Desc => sort_order(&b[i], &a[i]),
+                };
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            Ordering::Equal
+        });
+
+        Ok(keyed.into_iter().map(|(_, row)| row).collect())
+    }
+}
+
+impl PhysicalOperator for Sort {
+    fn schema(&self) -> &Schema {
+        self.input.schema()
+    }
+
+    fn next(&mut self) -> Result<Option<Row>, String> {
+        if self.sorted.is_none() {
+            self.sorted = Some(self.build()?.into_iter());
+        }
+        Ok(self.sorted.as_mut().and_then(|rows| rows.next()))
+    }
+
+    fn name(&self) -> &'static str {
+        "Sort"
+    }
+
+    fn detail(&self) -> String {
+        let keys: Vec<String> = self
+            .order_by
+            .iter()
+            .map(|o| match o.direction {
+                OrderDirection::Asc => print_expression(&o.expression),
+                OrderDirection::Desc => format!("{} DESC", print_expression(&o.expression)),
+            })
+            .collect();
+        format!("by {}", keys.join(", "))
+    }
+
+    fn children(&self) -> Vec<&dyn PhysicalOperator> {
+        vec![self.input.as_ref()]
+    }
+}
+
+/// Skips `offset` rows, then stops pulling from its input after `limit` rows
+pub struct Limit {
+    input: BoxedOperator,
+    limit: Option<u64>,
+    offset: u64,
+    skipped: u64,
+    emitted: u64,
+}
+
+impl Limit {
+    pub fn new(input: BoxedOperator, limit: Option<u64>, offset: u64) -> Self {
+        Self {
+            input,
+            limit,
+            offset,
+            skipped: 0,
+            emitted: 0,
+        }
+    }
+}
+
+impl PhysicalOperator for Limit {
+    fn schema(&self) -> &Schema {
+        self.input.schema()
+    }
+
+    fn next(&mut self) -> Result<Option<Row>, String> {
+        if self.limit.is_some_and(|limit| self.emitted >= limit) {
+            return Ok(None);
+        }
+        while self.skipped < self.offset {
+            if self.input.next()?.is_none() {
+                return Ok(None);
+            }
+            self.skipped += 1;
+        }
+        let row = self.input.next()?;
+        if row.is_some() {
+            self.emitted += 1;
+        }
+        Ok(row)
+    }
+
+    fn name(&self) -> &'static str {
+        "Limit"
+    }
+
+    fn detail(&self) -> String {
+        match self.limit {
+            Some(limit) => format!("{} offset {}", limit, self.offset),
+            None => format!("offset {}", self.offset),
+        }
+    }
+
+    fn children(&self) -> Vec<&dyn PhysicalOperator> {
+        vec![self.input.as_ref()]
+    }
+}
+
+/// Drops rows whose output columns repeat an earlier row
+pub struct Distinct {
+    input: BoxedOperator,
+    columns: Vec<String>,
+    seen: HashSet<String>,
+}
+
+impl Distinct {
+    pub fn new(input: BoxedOperator) -> Self {
+        Self {
+            columns: column_names(input.schema()),
+            input,
+            seen: HashSet::new(),
+        }
+    }
+}
+
+impl PhysicalOperator for Distinct {
+    fn schema(&self) -> &Schema {
+        self.input.schema()
+    }
+
+    fn next(&mut self) -> Result<Option<Row>, String> {
+        while let Some(row) = self.input.next()? {
+            if self.seen.insert(Executor::row_key(&row, &self.columns)) {
+                return Ok(Some(row));
+            }
+        }
+        Ok(None)
+    }
+
+    fn name(&self) -> &'static str {
+        "Distinct"
+    }
+
+    fn children(&self) -> Vec<&dyn PhysicalOperator> {
+        vec![self.input.as_ref()]
+    }
+}
+
+/// UNION [ALL]: left rows, then right rows renamed positionally to the left's
+/// column names; UNION drops duplicates
+pub struct Union {
+    left: BoxedOperator,
+    right: BoxedOperator,
+    left_done: bool,
+    columns: Vec<String>,
+    right_columns: Vec<String>,
+    seen: Option<HashSet<String>>,
+}
+
+impl Union {
+    pub fn new(left: BoxedOperator, right: BoxedOperator, all: bool) -> Result<Self, String> {
+        let columns = column_names(left.schema());
+        let right_columns = column_names(right.schema());
+        if columns.len() != right_columns.len() {
+            return Err(format!(
+                "Each UNION query must have the same number of columns: expected {}, got {}",
+                columns.len(),
+                right_columns.len()
+            ));
+        }
+
+        Ok(Self {
+            left,
+            right,
+            left_done: false,
+            columns,
+            right_columns,
+            seen: if all { None } else { Some(HashSet::new()) },
+        })
+    }
+
+    fn pull(&mut self) -> Result<Option<Row>, String> {
+        if !self.left_done {
+            match self.left.next()? {
+                Some(row) => return Ok(Some(row)),
+                None => self.left_done = true,
+            }
+        }
+        Ok(self.right.next()?.map(|mut row| {
+            self.columns
+                .iter()
+                .zip(&self.right_columns)
+                .map(|(target, source)| (target.clone(), row.remove(source).unwrap_or(Value::Null)))
+                .collect()
+        }))
+    }
+}
+
+impl PhysicalOperator for Union {
+    fn schema(&self) -> &Schema {
+        self.left.schema()
+    }
+
+    fn next(&mut self) -> Result<Option<Row>, String> {
+        while let Some(row) = self.pull()? {
+            let is_new = match &mut self.seen {
+                Some(seen) => seen.insert(Executor::row_key(&row, &self.columns)),
+                None => true,
+            };
+            if is_new {
+                return Ok(Some(row));
+            }
+        }
+        Ok(None)
+    }
+
+    fn name(&self) -> &'static str {
+        if self.seen.is_some() {
+            "Union"
+        } else {
+            "Union All"
+        }
+    }
+
+    fn children(&self) -> Vec<&dyn PhysicalOperator> {
+        vec![self.left.as_ref(), self.right.as_ref()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicI64, Ordering as AtomicOrdering};
+
+    /// Emits `total` rows and counts how many were pulled
+    struct CountingSource {
+        schema: Schema,
+        total: i64,
+        pulled: Arc<AtomicI64>,
+    }
+
+    impl PhysicalOperator for CountingSource {
+        fn schema(&self) -> &Schema {
+            &self.schema
+        }
+
+        fn next(&mut self) -> Result<Option<Row>, String> {
+            let n = self.pulled.load(AtomicOrdering::Relaxed);
+            if n >= self.total {
+                return Ok(None);
+            }
+            self.pulled.store(n + 1, AtomicOrdering::Relaxed);
+            Ok(Some(Row::from([("n".to_string(), Value::Integer(n))])))
+        }
+
+        fn name(&self) -> &'static str {
+            "Counting Source"
+        }
+    }
+
+    fn counting_source(total: i64) -> (BoxedOperator, Arc<AtomicI64>) {
+        let pulled = Arc::new(AtomicI64::new(0));
+        let source = CountingSource {
+            schema: vec![("t".to_string(), "n".to_string())],
+            total,
+            pulled: Arc::clone(&pulled),
+        };
+        (Box::new(source), pulled)
+    }
+
+    fn relation(qualifier: &str, columns: [&str; 2], rows: Vec<[Value; 2]>) -> BoxedOperator {
+        let rows = rows
+            .into_iter()
+            .map(|[a, b]| Row::from([(columns[0].to_string(), a), (columns[1].to_string(), b)]))
+            .collect();
+        let result_set = ResultSet {
+            columns: columns.iter().map(|c| c.to_string()).collect(),
+            rows,
+        };
+        Box::new(RelationScan::new(Arc::new(result_set), qualifier))
+    }
+
+    #[test]
+    fn test_hash_join_matches_nested_loop() {
+        let key = |i: i64| match i % 6 {
+            0 => Value::Null,
+            1 => Value::Float(if i % 4 == 1 { -0.0 } else { 0.0 }),
+            2 => Value::Boolean(i % 4 == 2),
+            3 => Value::Integer(i % 5),
+            4 => Value::Float((i % 5) as f64),
+            _ => Value::Text(format!("k{}", i % 7)),
+        };
+        let left = || relation("l", ["k", "a"], (0..3000).map(|i| [key(i), Value::Integer(i)]).collect());
+        let right = || relation("r", ["k", "b"], (0..120).map(|i| [key(i * 7), Value::Integer(i * 25)]).collect());
+        let column = |name: &str| Box::new(Expression::Identifier(name.to_string()));
+        let condition = Expression::BinaryOp {
+            left: Box::new(Expression::BinaryOp {
+                left: column("l.k"),
+                operator: BinaryOperator::Equals,
+                right: column("r.k"),
+            }),
+            operator: BinaryOperator::And,
+            right: Box::new(Expression::BinaryOp {
+                left: column("a"),
+                operator: BinaryOperator::LessThan,
+                right: column("b"),
+            }),
+        };
+
+        for join_type in [JoinType::Inner, JoinType::Left] {
+            let nested = NestedLoopJoin::new(left(), right(), join_type.clone(), Some(condition.clone()));
+            let expected = Executor::collect_rows(Box::new(nested)).unwrap();
+            assert!(!expected.rows.is_empty());
+
+            for workers in [1, 3] {
+                let (l, r) = (left(), right());
+                let keys = HashJoin::equi_keys(&condition, l.schema(), r.schema()).unwrap();
+                assert_eq!(keys.len(), 1);
+                let hash = HashJoin::new(l, r, join_type.clone(), Some(condition.clone()), keys, workers);
+                assert_eq!(Executor::collect_rows(Box::new(hash)).unwrap(), expected);
+            }
+        }
+
+        // NaN never equals anything, so it is not hashed
+        let nan = Row::from([("k".to_string(), Value::Float(f64::NAN))]);
+        assert_eq!(join_key([*column("k")].iter(), &nan), None);
+    }
+
+    #[test]
+    fn test_limit_stops_pulling_early() {
+        let (source, pulled) = counting_source(1_000_000);
+        let filter = Box::new(Filter {
+            input: source,
+            predicate: Expression::BinaryOp {
+                left: Box::new(Expression::Identifier("n".to_string())),
+                operator: BinaryOperator::GreaterThan,
+                right: Box::new(Expression::Literal(crate::db::sql::constants::Literal::Number("10".to_string()))),
+            },
+        });
+        let mut limit = Limit::new(filter, Some(3), 2);
+
+        let mut rows = Vec::new();
+        while let Some(row) = limit.next().unwrap() {
+            rows.push(row["n"].clone());
+        }
+
+        assert_eq!(rows, vec![Value::Integer(13), Value::Integer(14), Value::Integer(15)]);
+        assert_eq!(pulled.load(AtomicOrdering::Relaxed), 16);
+    }
+
+    #[test]
+    fn test_hash_aggregate_empty_input_without_group_by() {
+        let (source, _) = counting_source(0);
+        let count = Expression::Function {
+            name: "COUNT".to_string(),
+            args: vec![Expression::Identifier("*".to_string())],
+        };
+        let sum = Expression::Function {
+            name: "SUM".to_string(),
+            args: vec![Expression::Identifier("n".to_string())],
+        };
+        let mut agg = HashAggregate::new(source, Vec::new(), vec![count.clone(), sum.clone()]);
+
+        let row = agg.next().unwrap().unwrap();
+        assert_eq!(row[&aggregate_key(&count)], Value::Integer(0));
+        assert_eq!(row[&aggregate_key(&sum)], Value::Null);
+        assert!(agg.next().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_sort_nulls_last() {
+        let mut values = vec![Value::Integer(2), Value::Null, Value::Integer(1)];
+        values.sort_by(sort_order);
+        assert_eq!(values, vec![Value::Integer(1), Value::Integer(2), Value::Null]);
+    }
+}
@@ -0,0 +1,390 @@
+// Morsel-driven parallelism - split scans, filters, joins and aggregation across workers
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::sync::Arc;
+
+use super::operators::{qualify_row, scan_filter, skipped_chunks_note, table_schema, PhysicalOperator, Schema};
+use super::vectorized::BatchFilter;
+use super::Executor;
+use crate::config::ResourceConfig;
+use crate::db::sql::parser::{Expression, SqlPrettyPrinter};
+use crate::db::storage::{engine, Row, ScanFilter, TableSnapshot};
+
+/// Rows in one unit of parallel work
+pub const MORSEL_SIZE: usize = 1024;
+
+/// Inputs smaller than this stay on the calling thread: spawning workers
+/// costs more than it saves
+pub const PARALLEL_MIN_ROWS: f64 = (2 * MORSEL_SIZE) as f64;
+
+/// Workers one query may use; one until `set_worker_limit` is called
+static QUERY_WORKERS: AtomicUsize = AtomicUsize::new(1);
+
+/// Take the per-query worker limit from the `resource` section of the
+/// loaded config, applied to the cores of this machine
+pub fn set_worker_limit(resource: &ResourceConfig) {
+    let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+    QUERY_WORKERS.store(resource.query_worker_limit(cores), Ordering::Relaxed);
+}
+
+/// Workers available to one query
+pub fn worker_limit() -> usize {
+    QUERY_WORKERS.load(Ordering::Relaxed)
+}
+
+/// Apply `f` to consecutive morsels of `items` on up to `workers` threads and
+/// return the results in morsel order. Small inputs run on the calling thread.
+pub(crate) fn map_morsels<T, R, F>(items: &[T], workers: usize, f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&[T]) -> R + Sync,
+{
+    let morsels: Vec<&[T]> = items.chunks(MORSEL_SIZE).collect();
+    let workers = workers.min(morsels.len());
+    if workers <= 1 {
+        return morsels.into_iter().map(f).collect();
+    }
+
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<(usize, R)> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        match morsels.get(i) {
+                            Some(morsel) => done.push((i, f(morsel))),
+                            None => return done,
+                        }
+                    }
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().expect("parallel query worker panicked"))
+            .collect()
+    });
+    results.sort_by_key(|(i, _)| *i);
+    results.into_iter().map(|(_, r)| r).collect()
+}
+
+/// Run `f(p)` for every partition `p` in `0..partitions`, one thread each
+pub(crate) fn map_partitions<R, F>(partitions: usize, f: F) -> Vec<R>
+where
+    R: Send,
+    F: Fn(usize) -> R + Sync,
+{
+    if partitions <= 1 {
+        return (0..partitions).map(f).collect();
+    }
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..partitions).map(|p| scope.spawn({
+            let f = &f;
+            move || f(p)
+        })).collect();
+        handles
+            .into_iter()
+            .map(|h| h.join().expect("parallel query worker panicked"))
+            .collect()
+    })
+}
+
+enum MorselMessage {
+    Rows(usize, Vec<Row>),
+    Failed(String),
+    Done,
+}
+
+/// State shared by the workers of one parallel scan
+struct ScanShared {
+    /// Morsels are numbered by position in this, so concurrent writes
+    /// don't shift rows between them
+    snapshot: Arc<dyn TableSnapshot>,
+    table: String,
+    qualifier: String,
+    predicate: Option<Expression>,
+    /// The predicate compiled to vectorized kernels, when it can be
+    batch_filter: Option<BatchFilter>,
+    /// Chunks of a columnar table the predicate rules out
+    scan_filter: ScanFilter,
+    next_morsel: AtomicUsize,
+    cancelled: AtomicBool,
+    skipped_chunks: AtomicUsize,
+}
+
+/// Workers claim morsels of a stored table, qualify and filter their rows,
+/// and hand them back; rows come out in table order, as from `SeqScan` + `Filter`
+pub struct ParallelSeqScan {
+    shared: Arc<ScanShared>,
+    workers: usize,
+    schema: Schema,
+    receiver: Option<Receiver<MorselMessage>>,
+    /// Morsels that arrived ahead of their turn
+    arrived: BTreeMap<usize, Vec<Row>>,
+    next_morsel: usize,
+    workers_done: usize,
+    current: VecDeque<Row>,
+}
+
+impl ParallelSeqScan {
+    pub fn new(
+        table: &str,
+        qualifier: &str,
+        predicate: Option<Expression>,
+        workers: usize,
+        vectorize: bool,
+    ) -> Result<Self, String> {
+        let snapshot = engine::current().snapshot(table)?;
+        let sample = snapshot.scan(0, 1, &ScanFilter::default())?.rows;
+        let batch_filter = match &predicate {
+            Some(predicate) if vectorize => BatchFilter::compile(predicate, qualifier),
+            _ => None,
+        };
+        Ok(Self {
+            shared: Arc::new(ScanShared {
+                snapshot,
+                table: table.to_string(),
+                qualifier: qualifier.to_string(),
+                scan_filter: scan_filter(table, qualifier, predicate.as_ref()),
+                predicate,
+                batch_filter,
+                next_morsel: AtomicUsize::new(0),
+                cancelled: AtomicBool::new(false),
+                skipped_chunks: AtomicUsize::new(0),
+            }),
+            workers: workers.max(1),
+            schema: table_schema(table, qualifier, sample.first()),
+            receiver: None,
+            arrived: BTreeMap::new(),
+            next_morsel: 0,
+            workers_done: 0,
+            current: VecDeque::new(),
+        })
+    }
+
+    fn start(&mut self) -> Receiver<MorselMessage> {
+        let (sender, receiver) = sync_channel(self.workers * 2);
+        for _ in 0..self.workers {
+            let shared = Arc::clone(&self.shared);
+            let sender = sender.clone();
+            std::thread::spawn(move || {
+                while !shared.cancelled.load(Ordering::Relaxed) {
+                    let morsel = shared.next_morsel.fetch_add(1, Ordering::Relaxed);
+                    let batch = shared.snapshot.scan(morsel * MORSEL_SIZE, MORSEL_SIZE, &shared.scan_filter);
+                    let batch = match batch {
+                        Ok(batch) => batch,
+                        Err(e) => {
+                            let _ = sender.send(MorselMessage::Failed(e));
+                            return;
+                        }
+                    };
+                    if batch.scanned == 0 {
+                        break;
+                    }
+                    shared.skipped_chunks.fetch_add(batch.skipped_chunks, Ordering::Relaxed);
+                    let last = batch.scanned < MORSEL_SIZE;
+                    let rows = batch.rows;
+                    let rows = match &shared.batch_filter {
+                        Some(filter) => filter
+                            .apply(rows)
+                            .into_iter()
+                            .map(|row| qualify_row(&shared.qualifier, row))
+                            .collect(),
+                        None => rows
+                            .into_iter()
+                            .map(|row| qualify_row(&shared.qualifier, row))
+                            .filter(|row| {
+                                shared
+                                    .predicate
+                                    .as_ref()
+                                .is_none_or(|p| Executor::eval_condition(p, row))
+                            })
+                            .collect(),
+                    };
+                    if sender.send(MorselMessage::Rows(morsel, rows)).is_err() || last {
+                        break;
+                    }
+                }
+                let _ = sender.send(MorselMessage::Done);
+            });
+        }
+        receiver
+    }
+}
+
+impl PhysicalOperator for ParallelSeqScan {
+    fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    fn next(&mut self) -> Result<Option<Row>, String> {
+        if self.receiver.is_none() {
+            self.receiver = Some(self.start());
+        }
+        loop {
+            if let Some(row) = self.current.pop_front() {
+                return Ok(Some(row));
+            }
+            if let Some(rows) = self.arrived.remove(&self.next_morsel) {
+                self.next_morsel += 1;
+                self.current = rows.into();
+                continue;
+            }
+            if self.workers_done == self.workers {
+                // Every worker stopped: what is left follows a morsel the
+                // table shrank out from under, so emit it in order
+                match self.arrived.pop_first() {
+                    Some((morsel, rows)) => {
+                        self.next_morsel = morsel + 1;
+                        self.current = rows.into();
+                        continue;
+                    }
+                    None => return Ok(None),
+                }
+            }
+
+            let receiver = self.receiver.as_ref().ok_or("Parallel scan not started")?;
+            match receiver.recv() {
+                Ok(MorselMessage::Rows(morsel, rows)) => {
+                    self.arrived.insert(morsel, rows);
+                }
+                Ok(MorselMessage::Done) => self.workers_done += 1,
+                Ok(MorselMessage::Failed(e)) => return Err(e),
+                Err(_) => return Err("Parallel scan worker stopped unexpectedly".to_string()),
+            }
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "Parallel Seq Scan"
+    }
+
+    fn detail(&self) -> String {
+        let shared = &self.shared;
+        let mut detail = if shared.qualifier == shared.table {
+            format!("on {}", shared.table)
+        } else {
+            format!("on {} {}", shared.table, shared.qualifier)
+        };
+        if let Some(predicate) = &shared.predicate {
+            detail.push_str(&format!(
+                " filter {}",
+                SqlPrettyPrinter::new().print_expression(predicate)
+            ));
+        }
+        if shared.batch_filter.is_some() {
+            detail.push_str(&format!(" (workers={}, vectorized)", self.workers));
+        } else {
+            detail.push_str(&format!(" (workers={})", self.workers));
+        }
+        detail.push_str(&skipped_chunks_note(shared.skipped_chunks.load(Ordering::Relaxed)));
+        detail
+    }
+}
+
+impl Drop for ParallelSeqScan {
+    fn drop(&mut self) {
+        // Workers notice on their next morsel, or when their send fails
+        self.shared.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ResourceConfig;
+    use crate::db::executor::explain::PlanNode;
+    use crate::db::executor::{QueryScope, ResultSet};
+    use crate::db::planner::LogicalPlan;
+    use crate::db::sql::execute_sql;
+    use crate::db::sql::parser::SqlParser;
+
+    fn run(sql: &str, workers: usize) -> ResultSet {
+        let stmt = SqlParser::parse_statement(sql).unwrap();
+        let plan = LogicalPlan::from_statement(&stmt).unwrap();
+        let op = Executor::lower_plan_with(&plan, &QueryScope::default(), workers, true).unwrap();
+        Executor::collect_rows(op).unwrap()
+    }
+
+    fn plan_lines(sql: &str, workers: usize) -> Vec<String> {
+        let stmt = SqlParser::parse_statement(sql).unwrap();
+        let plan = LogicalPlan::from_statement(&stmt).unwrap();
+        let op = Executor::lower_plan_with(&plan, &QueryScope::default(), workers, true).unwrap();
+        PlanNode::from_operator(op.as_ref()).to_lines()
+    }
+
+    #[test]
+    fn test_worker_limit_respects_cpu_share() {
+        let mut config = ResourceConfig {
+            max_cpu_percent: 50.0,
+            ..ResourceConfig::default()
+        };
+        assert_eq!(config.query_worker_limit(8), 4);
+        config.query_workers = 2;
+        assert_eq!(config.query_worker_limit(8), 2);
+        config.query_workers = 16;
+        assert_eq!(config.query_worker_limit(8), 4);
+        config.max_cpu_percent = 5.0;
+        assert_eq!(config.query_worker_limit(8), 1);
+    }
+
+    #[test]
+    fn test_map_morsels_keeps_order() {
+        let items: Vec<usize> = (0..10 * MORSEL_SIZE + 7).collect();
+        let sums = map_morsels(&items, 4, |morsel| morsel.iter().sum::<usize>());
+        let expected: Vec<usize> = items.chunks(MORSEL_SIZE).map(|m| m.iter().sum()).collect();
+        assert_eq!(sums, expected);
+    }
+
+    #[test]
+    fn test_parallel_plans_match_single_threaded() {
+        execute_sql("DROP TABLE IF EXISTS parallel_orders");
+        execute_sql("DROP TABLE IF EXISTS parallel_customers");
+        execute_sql("CREATE TABLE parallel_customers (id INT, region TEXT)");
+        execute_sql("CREATE TABLE parallel_orders (id INT, customer_id INT, amount FLOAT, note TEXT)");
+
+        let customers: Vec<String> = (0..3000)
+            .map(|i| format!("({}, '{}')", i, ["north", "south", "east", "west"][i % 4]))
+            .collect();
+        execute_sql(&format!("INSERT INTO parallel_customers VALUES {}", customers.join(", ")));
+        let orders: Vec<String> = (0..6000)
+            .map(|i| {
+                let note = if i % 7 == 0 { "NULL".to_string() } else { format!("'n{}'", i % 13) };
+                format!("({}, {}, {}.{}, {})", i, (i * 7) % 3500, i % 97, i % 10, note)
+            })
+            .collect();
+        execute_sql(&format!("INSERT INTO parallel_orders VALUES {}", orders.join(", ")));
+
+        for sql in [
+            "SELECT * FROM parallel_orders WHERE amount > 50.5 AND note LIKE 'n1%'",
+            "SELECT note, COUNT(*), SUM(amount), AVG(amount), MIN(id), MAX(amount) \
+             FROM parallel_orders GROUP BY note",
+            "SELECT COUNT(note), SUM(amount) FROM parallel_orders WHERE id > 100",
+            "SELECT o.id, c.region FROM parallel_orders o JOIN parallel_customers c ON o.customer_id = c.id \
+             WHERE o.amount < 20",
+            "SELECT c.region, COUNT(*), SUM(o.amount) FROM parallel_customers c \
+             LEFT JOIN parallel_orders o ON c.id = o.customer_id GROUP BY c.region",
+        ] {
+            let serial = run(sql, 1);
+            let parallel = run(sql, 4);
+            assert!(!serial.rows.is_empty(), "no rows for {}", sql);
+            assert_eq!(serial, parallel, "results differ for {}", sql);
+        }
+
+        // The parallel operators were actually chosen
+        let lines = plan_lines("SELECT * FROM parallel_orders WHERE id > 10", 4);
+        assert!(lines.last().unwrap().contains("Parallel Seq Scan on parallel_orders filter (id > 10) (workers=4, vectorized)"));
+        let lines = plan_lines(
+            "SELECT o.id FROM parallel_orders o LEFT JOIN parallel_customers c ON o.customer_id = c.id",
+            4,
+        );
+        assert!(lines[1].contains("Hash Join Left join on (o.customer_id = c.id) (workers=4)"));
+
+        execute_sql("DROP TABLE parallel_orders");
+        execute_sql("DROP TABLE parallel_customers");
+    }
+}
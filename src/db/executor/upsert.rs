@@ -0,0 +1,223 @@
+// INSERT ... ON CONFLICT (upsert)
+use std::collections::HashSet;
+
+use super::Executor;
+use crate::db::catalog::TableSchema;
+use crate::db::sql::constants::{ConflictAction, OnConflict};
+use crate::db::storage::changes::Change;
+use crate::db::storage::{engine, Row};
+
+/// What an upsert did: counts plus the final image of every written row
+pub struct UpsertOutcome {
+    pub inserted: usize,
+    pub updated: usize,
+    pub rows: Vec<Row>,
+}
+
+impl Executor {
+    /// Insert `rows`, resolving collisions on the conflict target as directed by
+    /// the ON CONFLICT action. The whole statement is applied atomically.
+    pub(crate) fn apply_upsert(
+        schema: &TableSchema,
+        rows: Vec<Row>,
+        on_conflict: &OnConflict,
+    ) -> Result<UpsertOutcome, String> {
+        let arbiters = conflict_arbiters(schema, &on_conflict.target)?;
+
+        engine::current().write_recorded(&schema.name, &schema.candidate_keys(), |write, recorder| {
+            let changes = write.upsert(&arbiters, rows, &mut |existing, proposed| {
+                let (assignments, where_clause) = match &on_conflict.action {
+                    ConflictAction::DoNothing => return Ok(None),
+                    ConflictAction::DoUpdate {
+                        assignments,
+                        where_clause,
+                    } => (assignments, where_clause),
+                };
+
+                let context = conflict_context(&schema.name, existing, proposed);
+                if let Some(condition) = where_clause {
+                    if !Self::eval_condition(condition, &context) {
+                        return Ok(None);
+                    }
+                }
+                let mut row = existing.clone();
+                for assignment in assignments {
+                    row.insert(assignment.column.clone(), Self::eval_expression(&assignment.value, &context));
+                }
+                Ok(Some(row))
+            })?;
+
+            let mut outcome = UpsertOutcome {
+                inserted: 0,
+                updated: 0,
+                rows: Vec::new(),
+            };
+            for change in changes {
+                match change {
+                    Change::Insert { row } => {
+                        recorder.insert(&row);
+                        outcome.rows.push(row);
+                        outcome.inserted += 1;
+                    }
+                    Change::Update { old, new } => {
+                        recorder.update(old, &new);
+                        outcome.rows.push(new);
+                        outcome.updated += 1;
+                    }
+                    Change::Delete { .. } => {}
+                }
+            }
+            Ok(outcome)
+        })
+    }
+}
+
+/// Unique column sets used to detect conflicts. An explicit target must match
+/// the table's primary key or one of its UNIQUE constraints; no target means
+/// any of them.
+fn conflict_arbiters(schema: &TableSchema, target: &[String]) -> Result<Vec<Vec<String>>, String> {
+    let candidates = schema.candidate_keys();
+    if candidates.is_empty() {
+        return Err(format!(
+            "Table '{}' has no primary key or unique constraint for ON CONFLICT",
+            schema.name
+        ));
+    }
+    if target.is_empty() {
+        return Ok(candidates);
+    }
+
+    let wanted: HashSet<&String> = target.iter().collect();
+    candidates
+        .into_iter()
+        .find(|key| key.len() == wanted.len() && key.iter().all(|c| wanted.contains(c)))
+        .map(|key| vec![key])
+        .ok_or_else(|| {
+            format!(
+                "There is no primary key or unique constraint on '{}' matching the ON CONFLICT columns ({})",
+                schema.name,
+                target.join(", ")
+            )
+        })
+}
+
+/// Row visible to DO UPDATE expressions: the existing row (bare and qualified
+/// by table name) plus the proposed row under `EXCLUDED`
+fn conflict_context(table: &str, existing: &Row, proposed: &Row) -> Row {
+    let mut context = existing.clone();
+    for (column, value) in existing {
+        context.insert(format!("{}.{}", table, column), value.clone());
+    }
+    for (column, value) in proposed {
+        // The tokenizer keeps identifier case, so accept both spellings
+        context.insert(format!("excluded.{}", column), value.clone());
+        context.insert(format!("EXCLUDED.{}", column), value.clone());
+    }
+    context
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::db::executor::ExecutionResult;
+    use crate::db::sql::execute_sql;
+
+    fn select_all(sql: &str) -> Vec<HashMap<String, serde_json::Value>> {
+        match execute_sql(sql) {
+            ExecutionResult::Rows { rows, .. } => rows,
+            other => panic!("Expected rows, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_upsert_do_nothing() {
+        execute_sql("DROP TABLE IF EXISTS upsert_nothing");
+        execute_sql("CREATE TABLE upsert_nothing (id INT PRIMARY KEY, name TEXT)");
+        execute_sql("INSERT INTO upsert_nothing VALUES (1, 'a')");
+
+        match execute_sql(
+            "INSERT INTO upsert_nothing VALUES (1, 'x'), (2, 'b'), (2, 'y') ON CONFLICT (id) DO NOTHING",
+        ) {
+            ExecutionResult::Upserted { inserted, updated } => {
+                assert_eq!(inserted, 1);
+                assert_eq!(updated, 0);
+            }
+            other => panic!("Expected Upserted, got {:?}", other),
+        }
+
+        let rows = select_all("SELECT name FROM upsert_nothing WHERE id = 1");
+        assert_eq!(rows[0]["name"], serde_json::json!("a"));
+        assert_eq!(select_all("SELECT * FROM upsert_nothing").len(), 2);
+
+        execute_sql("DROP TABLE upsert_nothing");
+    }
+
+    #[test]
+    fn test_upsert_do_update_with_excluded() {
+        execute_sql("DROP TABLE IF EXISTS upsert_update");
+        execute_sql("CREATE TABLE upsert_update (id INT PRIMARY KEY, hits INT)");
+        execute_sql("INSERT INTO upsert_update VALUES (1, 10), (2, 20)");
+
+        match execute_sql(
+            "INSERT INTO upsert_update VALUES (1, 15), (2, 5), (3, 1) \
+             ON CONFLICT (id) DO UPDATE SET hits = EXCLUDED.hits WHERE upsert_update.hits < excluded.hits",
+        ) {
+            ExecutionResult::Upserted { inserted, updated } => {
+                assert_eq!(inserted, 1);
+                assert_eq!(updated, 1);
+            }
+            other => panic!("Expected Upserted, got {:?}", other),
+        }
+
+        let hits = |id: i64| {
+            select_all(&format!("SELECT hits FROM upsert_update WHERE id = {}", id))[0]["hits"].clone()
+        };
+        assert_eq!(hits(1), serde_json::json!(15));
+        assert_eq!(hits(2), serde_json::json!(20));
+        assert_eq!(hits(3), serde_json::json!(1));
+
+        execute_sql("DROP TABLE upsert_update");
+    }
+
+    #[test]
+    fn test_upsert_unique_column_target() {
+        execute_sql("DROP TABLE IF EXISTS upsert_unique");
+        execute_sql("CREATE TABLE upsert_unique (id INT PRIMARY KEY, email TEXT UNIQUE, name TEXT)");
+        execute_sql("INSERT INTO upsert_unique VALUES (1, 'a@x', 'a')");
+
+        match execute_sql(
+            "INSERT INTO upsert_unique VALUES (2, 'a@x', 'b') ON CONFLICT (email) DO UPDATE SET name = EXCLUDED.name",
+        ) {
+            ExecutionResult::Upserted { inserted, updated } => assert_eq!((inserted, updated), (0, 1)),
+            other => panic!("Expected Upserted, got {:?}", other),
+        }
+
+        match execute_sql("INSERT INTO upsert_unique VALUES (3, 'c@x', 'c') ON CONFLICT (name) DO NOTHING") {
+            ExecutionResult::Error { message } => assert!(message.contains("no primary key or unique constraint")),
+            other => panic!("Expected error, got {:?}", other),
+        }
+
+        execute_sql("DROP TABLE upsert_unique");
+    }
+
+    #[test]
+    fn test_upsert_cannot_update_row_twice() {
+        execute_sql("DROP TABLE IF EXISTS upsert_twice");
+        execute_sql("CREATE TABLE upsert_twice (id INT PRIMARY KEY, n INT)");
+        execute_sql("INSERT INTO upsert_twice VALUES (1, 0)");
+
+        match execute_sql(
+            "INSERT INTO upsert_twice VALUES (1, 1), (1, 2) ON CONFLICT (id) DO UPDATE SET n = EXCLUDED.n",
+        ) {
+            ExecutionResult::Error { message } => assert!(message.contains("second time")),
+            other => panic!("Expected error, got {:?}", other),
+        }
+        // The failed statement left the table untouched
+        let rows = select_all("SELECT n FROM upsert_twice");
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0]["n"], serde_json::json!(0));
+
+        execute_sql("DROP TABLE upsert_twice");
+    }
+}
@@ -0,0 +1,242 @@
+// Column batches - typed vectors with validity bitmaps
+use std::collections::HashMap;
+
+use crate::db::storage::{Row, Value};
+
+/// One bit per row
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bitmap {
+    words: Vec<u64>,
+    len: usize,
+}
+
+impl Bitmap {
+    /// `len` bits, all set to `value`
+    pub fn new(len: usize, value: bool) -> Self {
+        let fill = if value { u64::MAX } else { 0 };
+        let mut bitmap = Self {
+            words: vec![fill; len.div_ceil(64)],
+            len,
+        };
+        bitmap.clear_tail();
+        bitmap
+    }
+
+    pub fn from_fn(len: usize, mut f: impl FnMut(usize) -> bool) -> Self {
+        let mut words = vec![0u64; len.div_ceil(64)];
+        for i in 0..len {
+            if f(i) {
+                words[i / 64] |= 1 << (i % 64);
+            }
+        }
+        Self { words, len }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, i: usize) -> bool {
+        self.words[i / 64] & (1 << (i % 64)) != 0
+    }
+
+    pub fn set(&mut self, i: usize, value: bool) {
+        if value {
+            self.words[i / 64] |= 1 << (i % 64);
+        } else {
+            self.words[i / 64] &= !(1 << (i % 64));
+        }
+    }
+
+    pub fn and(&self, other: &Bitmap) -> Bitmap {
+        self.zip(other, |a, b| a & b)
+    }
+
+    pub fn or(&self, other: &Bitmap) -> Bitmap {
+        self.zip(other, |a, b| a | b)
+    }
+
+    pub fn count_ones(&self) -> usize {
+        self.words.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    /// Positions of the set bits, ascending (a selection vector)
+    pub fn ones(&self) -> Vec<usize> {
+        let mut positions = Vec::with_capacity(self.count_ones());
+        for (w, &word) in self.words.iter().enumerate() {
+            let mut bits = word;
+            while bits != 0 {
+                positions.push(w * 64 + bits.trailing_zeros() as usize);
+                bits &= bits - 1;
+            }
+        }
+        positions
+    }
+
+    fn zip(&self, other: &Bitmap, f: impl Fn(u64, u64) -> u64) -> Bitmap {
+        Bitmap {
+            words: self.words.iter().zip(&other.words).map(|(&a, &b)| f(a, b)).collect(),
+            len: self.len,
+        }
+    }
+
+    /// Keep the unused bits of the last word clear so word-wise counts stay exact
+    fn clear_tail(&mut self) {
+        if !self.len.is_multiple_of(64) {
+            if let Some(last) = self.words.last_mut() {
+                *last &= (1u64 << (self.len % 64)) - 1;
+            }
+        }
+    }
+}
+
+/// Values of one column. A column whose non-NULL values do not all share one
+/// type is kept as `Mixed` and handled value by value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColumnData {
+    Integer(Vec<i64>),
+    Float(Vec<f64>),
+    Text(Vec<String>),
+    Boolean(Vec<bool>),
+    Mixed(Vec<Value>),
+}
+
+/// A typed column; `validity` is clear where the value is NULL (the slot in
+/// `data` then holds a placeholder)
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnVector {
+    pub data: ColumnData,
+    pub validity: Bitmap,
+}
+
+impl ColumnVector {
+    pub fn from_values(values: Vec<Value>) -> Self {
+        let validity = Bitmap::from_fn(values.len(), |i| !matches!(values[i], Value::Null));
+        let first = values.iter().find(|v| !matches!(v, Value::Null));
+        let uniform = first.is_none_or(|first| {
+            values.iter().all(|v| {
+                matches!(v, Value::Null) || std::mem::discriminant(v) == std::mem::discriminant(first)
+            })
+        });
+        if !uniform {
+            return Self {
+                data: ColumnData::Mixed(values),
+                validity,
+            };
+        }
+
+        let data = match first {
+            None | Some(Value::Integer(_)) => ColumnData::Integer(
+                values.iter().map(|v| if let Value::Integer(i) = v { *i } else { 0 }).collect(),
+            ),
+            Some(Value::Float(_)) => ColumnData::Float(
+                values.iter().map(|v| if let Value::Float(f) = v { *f } else { 0.0 }).collect(),
+            ),
+            Some(Value::Boolean(_)) => ColumnData::Boolean(
+                values.iter().map(|v| matches!(v, Value::Boolean(true))).collect(),
+            ),
+            Some(_) => ColumnData::Text(
+                values
+                    .into_iter()
+                    .map(|v| if let Value::Text(s) = v { s } else { String::new() })
+                    .collect(),
+            ),
+        };
+        Self { data, validity }
+    }
+
+    pub fn len(&self) -> usize {
+        self.validity.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.validity.is_empty()
+    }
+
+    /// The value at `i` (NULL where invalid)
+    pub fn value(&self, i: usize) -> Value {
+        if !self.validity.get(i) {
+            return Value::Null;
+        }
+        match &self.data {
+            ColumnData::Integer(v) => Value::Integer(v[i]),
+            ColumnData::Float(v) => Value::Float(v[i]),
+            ColumnData::Text(v) => Value::Text(v[i].clone()),
+            ColumnData::Boolean(v) => Value::Boolean(v[i]),
+            ColumnData::Mixed(v) => v[i].clone(),
+        }
+    }
+}
+
+/// Columns of a run of rows, gathered from row storage
+#[derive(Debug, Clone, Default)]
+pub struct ColumnBatch {
+    columns: HashMap<String, ColumnVector>,
+    len: usize,
+}
+
+impl ColumnBatch {
+    /// Gather `columns` out of `rows`; a row without a column holds NULL
+    pub fn from_rows(rows: &[Row], columns: &[String]) -> Self {
+        let columns = columns
+            .iter()
+            .map(|name| {
+                let values = rows.iter().map(|row| row.get(name).cloned().unwrap_or(Value::Null)).collect();
+                (name.clone(), ColumnVector::from_values(values))
+            })
+            .collect();
+        Self {
+            columns,
+            len: rows.len(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn column(&self, name: &str) -> Option<&ColumnVector> {
+        self.columns.get(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bitmap_ones_and_counts() {
+        let bitmap = Bitmap::from_fn(130, |i| i % 3 == 0);
+        assert_eq!(bitmap.count_ones(), 44);
+        assert_eq!(bitmap.ones()[..4], [0, 3, 6, 9]);
+        assert_eq!(*bitmap.ones().last().unwrap(), 129);
+
+        let all = Bitmap::new(70, true);
+        assert_eq!(all.count_ones(), 70);
+        assert_eq!(all.and(&Bitmap::new(70, false)).count_ones(), 0);
+        assert_eq!(Bitmap::new(70, false).or(&all).count_ones(), 70);
+    }
+
+    #[test]
+    fn test_column_vector_types() {
+        let ints = ColumnVector::from_values(vec![Value::Integer(1), Value::Null, Value::Integer(3)]);
+        assert_eq!(ints.data, ColumnData::Integer(vec![1, 0, 3]));
+        assert_eq!(ints.value(1), Value::Null);
+        assert_eq!(ints.validity.count_ones(), 2);
+
+        let mixed = ColumnVector::from_values(vec![Value::Integer(1), Value::Float(2.5)]);
+        assert!(matches!(mixed.data, ColumnData::Mixed(_)));
+        assert_eq!(mixed.value(1), Value::Float(2.5));
+
+        let text = ColumnVector::from_values(vec![Value::Null, Value::Text("a".to_string())]);
+        assert_eq!(text.data, ColumnData::Text(vec![String::new(), "a".to_string()]));
+    }
+}
@@ -0,0 +1,466 @@
+// Vectorized kernels - comparison, arithmetic and aggregation over whole columns
+//
+// Every kernel gives exactly the result the row-at-a-time evaluator would
+// give for each row: typed loops cover the common cases and anything else
+// falls back to the scalar rules value by value.
+use std::borrow::Cow;
+use std::cmp::Ordering;
+
+use super::batch::{Bitmap, ColumnData, ColumnVector};
+use crate::db::executor::operators::Accumulator;
+use crate::db::executor::Executor;
+use crate::db::sql::constants::BinaryOperator;
+use crate::db::storage::Value;
+
+/// An operand or result: a whole column, or one value standing for every row
+#[derive(Debug, Clone)]
+pub enum Datum<'a> {
+    Vector(Cow<'a, ColumnVector>),
+    Scalar(Value),
+}
+
+impl Datum<'_> {
+    /// The value for row `i`
+    pub fn value(&self, i: usize) -> Value {
+        match self {
+            Datum::Vector(v) => v.value(i),
+            Datum::Scalar(v) => v.clone(),
+        }
+    }
+
+    pub fn into_owned(self) -> Datum<'static> {
+        match self {
+            Datum::Vector(v) => Datum::Vector(Cow::Owned(v.into_owned())),
+            Datum::Scalar(v) => Datum::Scalar(v),
+        }
+    }
+}
+
+/// A typed view of an operand
+enum Side<'a, T> {
+    Each(&'a [T], &'a Bitmap),
+    All(&'a T),
+}
+
+impl<T> Side<'_, T> {
+    /// The value for row `i` (None where NULL)
+    fn get(&self, i: usize) -> Option<&T> {
+        match self {
+            Side::Each(values, validity) => validity.get(i).then(|| &values[i]),
+            Side::All(value) => Some(value),
+        }
+    }
+}
+
+macro_rules! typed_side {
+    ($name:ident, $column:ident, $value:ident, $t:ty) => {
+        fn $name<'a>(datum: &'a Datum) -> Option<Side<'a, $t>> {
+            match datum {
+                Datum::Vector(v) => match &v.data {
+                    ColumnData::$column(values) => Some(Side::Each(values, &v.validity)),
+                    _ => None,
+                },
+                Datum::Scalar(Value::$value(value)) => Some(Side::All(value)),
+                Datum::Scalar(_) => None,
+            }
+        }
+    };
+}
+
+typed_side!(ints, Integer, Integer, i64);
+typed_side!(floats, Float, Float, f64);
+typed_side!(texts, Text, Text, String);
+typed_side!(bools, Boolean, Boolean, bool);
+
+/// Rows where `f` holds for two non-NULL operands
+fn mask<T, U>(left: Side<T>, right: Side<U>, len: usize, f: impl Fn(&T, &U) -> bool) -> Bitmap {
+    Bitmap::from_fn(len, |i| match (left.get(i), right.get(i)) {
+        (Some(a), Some(b)) => f(a, b),
+        _ => false,
+    })
+}
+
+/// `Executor::compare` for floats: unordered pairs (NaN) compare equal
+fn float_order(a: f64, b: f64) -> Ordering {
+    a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+}
+
+/// Whether an ordering satisfies a comparison operator
+fn holds(ordering: Ordering, operator: &BinaryOperator) -> bool {
+    match operator {
+        BinaryOperator::LessThan => ordering == Ordering::Less,
+        BinaryOperator::LessThanOrEqual => ordering != Ordering::Greater,
+        BinaryOperator::GreaterThan => ordering == Ordering::Greater,
+        BinaryOperator::GreaterThanOrEqual => ordering != Ordering::Less,
+        _ => false,
+    }
+}
+
+/// Rows where `left <operator> right` is true
+pub fn compare(left: &Datum, operator: &BinaryOperator, right: &Datum, len: usize) -> Bitmap {
+    use BinaryOperator::*;
+    let ordered = matches!(operator, LessThan | LessThanOrEqual | GreaterThan | GreaterThanOrEqual);
+
+    if let (Some(l), Some(r)) = (ints(left), ints(right)) {
+        return match operator {
+            Equals => mask(l, r, len, |a, b| a == b),
+            NotEquals => mask(l, r, len, |a, b| a != b),
+            op if ordered => mask(l, r, len, |a, b| holds(a.cmp(b), op)),
+            _ => Bitmap::new(len, false),
+        };
+    }
+    if let (Some(l), Some(r)) = (floats(left), floats(right)) {
+        return match operator {
+            Equals => mask(l, r, len, |a, b| a == b),
+            NotEquals => mask(l, r, len, |a, b| a != b),
+            op if ordered => mask(l, r, len, |a, b| holds(float_order(*a, *b), op)),
+            _ => Bitmap::new(len, false),
+        };
+    }
+    // Integers and floats compare numerically but are never equal
+    if let (Some(l), Some(r)) = (ints(left), floats(right)) {
+        return match operator {
+            Equals => Bitmap::new(len, false),
+            NotEquals => mask(l, r, len, |_, _| true),
+            op if ordered => mask(l, r, len, |a, b| holds(float_order(*a as f64, *b), op)),
+            _ => Bitmap::new(len, false),
+        };
+    }
+    if let (Some(l), Some(r)) = (floats(left), ints(right)) {
+        return match operator {
+            Equals => Bitmap::new(len, false),
+            NotEquals => mask(l, r, len, |_, _| true),
+            op if ordered => mask(l, r, len, |a, b| holds(float_order(*a, *b as f64), op)),
+            _ => Bitmap::new(len, false),
+        };
+    }
+    if let (Some(l), Some(r)) = (texts(left), texts(right)) {
+        return match operator {
+            Equals => mask(l, r, len, |a, b| a == b),
+            NotEquals => mask(l, r, len, |a, b| a != b),
+            op if ordered => mask(l, r, len, |a, b| holds(a.cmp(b), op)),
+            // Compile a constant pattern once for the whole column
+            Like => match r {
+                Side::All(pattern) => match Executor::like_regex(pattern) {
+                    Some(re) => mask(l, Side::All(&()), len, |text, _| re.is_match(text)),
+                    None => Bitmap::new(len, false),
+                },
+                r => mask(l, r, len, |text, pattern| Executor::match_like(text, pattern)),
+            },
+            _ => Bitmap::new(len, false),
+        };
+    }
+    if let (Some(l), Some(r)) = (bools(left), bools(right)) {
+        return match operator {
+            Equals => mask(l, r, len, |a, b| a == b),
+            NotEquals => mask(l, r, len, |a, b| a != b),
+            // Booleans have no order: every pair compares equal
+            op if ordered => mask(l, r, len, |_, _| holds(Ordering::Equal, op)),
+            _ => Bitmap::new(len, false),
+        };
+    }
+
+    Bitmap::from_fn(len, |i| Executor::eval_comparison(&left.value(i), operator, &right.value(i)))
+}
+
+/// `left <operator> right` for + - * /; NULL where an operand is NULL or the
+/// operation is undefined (division by zero, mismatched types)
+pub fn arithmetic<'a>(left: &Datum, operator: &BinaryOperator, right: &Datum, len: usize) -> Datum<'a> {
+    use BinaryOperator::*;
+    if let (Datum::Scalar(l), Datum::Scalar(r)) = (left, right) {
+        return Datum::Scalar(Executor::eval_binary_op(l, operator, r));
+    }
+
+    if let (Some(l), Some(r)) = (ints(left), ints(right)) {
+        let op: fn(i64, i64) -> Option<i64> = match operator {
+            Plus => |a, b| Some(a + b),
+            Minus => |a, b| Some(a - b),
+            Multiply => |a, b| Some(a * b),
+            Divide => |a, b| (b != 0).then(|| a / b),
+            _ => return Datum::Scalar(Value::Null),
+        };
+        return typed_result(len, |i| op(*l.get(i)?, *r.get(i)?), ColumnData::Integer);
+    }
+    let float_op: Option<fn(f64, f64) -> Option<f64>> = match operator {
+        Plus => Some(|a, b| Some(a + b)),
+        Minus => Some(|a, b| Some(a - b)),
+        Multiply => Some(|a, b| Some(a * b)),
+        Divide => Some(|a, b| (b != 0.0).then(|| a / b)),
+        _ => None,
+    };
+    if let (Some(op), Some(l), Some(r)) = (float_op, floats(left), floats(right)) {
+        return typed_result(len, |i| op(*l.get(i)?, *r.get(i)?), ColumnData::Float);
+    }
+    // Only addition mixes integers and floats
+    if matches!(operator, Plus) {
+        if let (Some(l), Some(r)) = (ints(left), floats(right)) {
+            return typed_result(len, |i| Some(*l.get(i)? as f64 + r.get(i)?), ColumnData::Float);
+        }
+        if let (Some(l), Some(r)) = (floats(left), ints(right)) {
+            return typed_result(len, |i| Some(l.get(i)? + *r.get(i)? as f64), ColumnData::Float);
+        }
+    }
+
+    let values = (0..len)
+        .map(|i| Executor::eval_binary_op(&left.value(i), operator, &right.value(i)))
+        .collect();
+    Datum::Vector(Cow::Owned(ColumnVector::from_values(values)))
+}
+
+fn typed_result<'a, T: Default>(
+    len: usize,
+    f: impl Fn(usize) -> Option<T>,
+    wrap: fn(Vec<T>) -> ColumnData,
+) -> Datum<'a> {
+    let mut validity = Bitmap::new(len, true);
+    let values = (0..len)
+        .map(|i| match f(i) {
+            Some(v) => v,
+            None => {
+                validity.set(i, false);
+                T::default()
+            }
+        })
+        .collect();
+    Datum::Vector(Cow::Owned(ColumnVector {
+        data: wrap(values),
+        validity,
+    }))
+}
+
+/// Fold the `selection` rows of `input` into an accumulator, in order.
+/// `None` is COUNT(*): every selected row counts.
+pub(crate) fn accumulate(accumulator: &mut Accumulator, input: Option<&Datum>, selection: &[usize]) {
+    let Some(input) = input else {
+        if let Accumulator::Count(n) = accumulator {
+            *n += selection.len() as i64;
+        }
+        return;
+    };
+    let valid = || selection.iter().copied().filter(move |&i| match input {
+        Datum::Vector(v) => v.validity.get(i),
+        Datum::Scalar(v) => !matches!(v, Value::Null),
+    });
+
+    match accumulator {
+        Accumulator::Count(n) => {
+            *n += valid().count() as i64;
+            return;
+        }
+        Accumulator::Avg { sum, count } => {
+            if let Some(values) = ints(input) {
+                for i in valid() {
+                    *sum += *values.get(i).unwrap_or(&0) as f64;
+                    *count += 1;
+                }
+                return;
+            }
+            if let Some(values) = floats(input) {
+                for i in valid() {
+                    *sum += values.get(i).unwrap_or(&0.0);
+                    *count += 1;
+                }
+                return;
+            }
+        }
+        Accumulator::Sum(total @ (None | Some(Value::Integer(_)))) if ints(input).is_some() => {
+            let values = ints(input).unwrap();
+            let mut running = match total {
+                Some(Value::Integer(t)) => Some(*t),
+                _ => None,
+            };
+            for i in valid() {
+                let v = *values.get(i).unwrap_or(&0);
+                running = Some(running.map_or(v, |t| t + v));
+            }
+            *total = running.map(Value::Integer);
+            return;
+        }
+        Accumulator::Sum(total @ (None | Some(Value::Float(_)))) if floats(input).is_some() => {
+            let values = floats(input).unwrap();
+            let mut running = match total {
+                Some(Value::Float(t)) => Some(*t),
+                _ => None,
+            };
+            for i in valid() {
+                let v = *values.get(i).unwrap_or(&0.0);
+                running = Some(running.map_or(v, |t| t + v));
+            }
+            *total = running.map(Value::Float);
+            return;
+        }
+        Accumulator::Min(current) => {
+            *current = extreme(current.as_ref(), input, valid(), Ordering::Less);
+            return;
+        }
+        Accumulator::Max(current) => {
+            *current = extreme(current.as_ref(), input, valid(), Ordering::Greater);
+            return;
+        }
+        _ => {}
+    }
+
+    for i in valid() {
+        accumulator.update(Some(input.value(i)));
+    }
+}
+
+fn accumulator_value(accumulator: &Accumulator) -> Option<&Value> {
+    match accumulator {
+        Accumulator::Min(current) | Accumulator::Max(current) => current.as_ref(),
+        _ => None,
+    }
+}
+
+/// The value MIN (`want` = Less) or MAX (`want` = Greater) holds after seeing
+/// `current` and then the `rows` of `input`; None if it is still unset.
+/// Like `Accumulator::update`, a value only replaces the current one when it
+/// is strictly smaller (larger).
+fn extreme(
+    current: Option<&Value>,
+    input: &Datum,
+    rows: impl Iterator<Item = usize>,
+    want: Ordering,
+) -> Option<Value> {
+    macro_rules! typed {
+        ($values:expr, $variant:ident, $order:expr) => {{
+            let values = $values;
+            let mut best = match current {
+                Some(Value::$variant(v)) => Some(v.clone()),
+                _ => None,
+            };
+            for i in rows {
+                let Some(v) = values.get(i) else { continue };
+                if best.as_ref().is_none_or(|b| $order(v, b) == want) {
+                    best = Some(v.clone());
+                }
+            }
+            return best.map(Value::$variant);
+        }};
+    }
+
+    match current {
+        None | Some(Value::Integer(_)) if ints(input).is_some() => {
+            typed!(ints(input).unwrap(), Integer, |a: &i64, b: &i64| a.cmp(b))
+        }
+        None | Some(Value::Float(_)) if floats(input).is_some() => {
+            typed!(floats(input).unwrap(), Float, |a: &f64, b: &f64| float_order(*a, *b))
+        }
+        None | Some(Value::Text(_)) if texts(input).is_some() => {
+            typed!(texts(input).unwrap(), Text, |a: &String, b: &String| a.cmp(b))
+        }
+        _ => {}
+    }
+
+    let mut accumulator = match want {
+        Ordering::Less => Accumulator::Min(current.cloned()),
+        _ => Accumulator::Max(current.cloned()),
+    };
+    for i in rows {
+        accumulator.update(Some(input.value(i)));
+    }
+    accumulator_value(&accumulator).cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vector(values: Vec<Value>) -> Datum<'static> {
+        Datum::Vector(Cow::Owned(ColumnVector::from_values(values)))
+    }
+
+    fn sample() -> Vec<Value> {
+        vec![
+            Value::Integer(3),
+            Value::Null,
+            Value::Float(2.5),
+            Value::Float(f64::NAN),
+            Value::Integer(-1),
+            Value::Text("b".to_string()),
+            Value::Boolean(true),
+            Value::Float(-0.0),
+            Value::Integer(3),
+        ]
+    }
+
+    /// Every kernel agrees with the scalar evaluator, typed or not
+    #[test]
+    fn test_kernels_match_scalar_rules() {
+        let values = sample();
+        let columns = [
+            values.clone(),
+            values.iter().map(|v| if let Value::Float(_) = v { v.clone() } else { Value::Null }).collect(),
+            values.iter().map(|v| if let Value::Integer(_) = v { v.clone() } else { Value::Null }).collect(),
+        ];
+        let scalars = [Value::Integer(3), Value::Float(2.5), Value::Float(0.0), Value::Null, Value::Text("b".to_string())];
+
+        for column in &columns {
+            let len = column.len();
+            let left = vector(column.clone());
+            let mut rights: Vec<Datum> = scalars.iter().cloned().map(Datum::Scalar).collect();
+            rights.extend(columns.iter().map(|c| vector(c.clone())));
+
+            for right in &rights {
+                for op in [
+                    BinaryOperator::Equals,
+                    BinaryOperator::NotEquals,
+                    BinaryOperator::LessThan,
+                    BinaryOperator::LessThanOrEqual,
+                    BinaryOperator::GreaterThan,
+                    BinaryOperator::GreaterThanOrEqual,
+                ] {
+                    let mask = compare(&left, &op, right, len);
+                    for i in 0..len {
+                        let expected = Executor::eval_comparison(&left.value(i), &op, &right.value(i));
+                        assert_eq!(mask.get(i), expected, "{:?} {:?} {:?}", left.value(i), op, right.value(i));
+                    }
+                }
+                for op in [BinaryOperator::Plus, BinaryOperator::Minus, BinaryOperator::Divide] {
+                    let result = arithmetic(&left, &op, right, len);
+                    for i in 0..len {
+                        let expected = Executor::eval_binary_op(&left.value(i), &op, &right.value(i));
+                        let got = result.value(i);
+                        let same = got == expected
+                            || matches!((&got, &expected), (Value::Float(a), Value::Float(b)) if a.is_nan() && b.is_nan());
+                        assert!(same, "{:?} {:?} {:?}: {:?} vs {:?}", left.value(i), op, right.value(i), got, expected);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_accumulate_matches_row_updates() {
+        let inputs = [
+            (0..50).map(|i| if i % 7 == 0 { Value::Null } else { Value::Float(i as f64 * 0.1) }).collect::<Vec<_>>(),
+            (0..50).map(|i| if i % 5 == 0 { Value::Null } else { Value::Integer(40 - i) }).collect(),
+            (0..50).map(|i| Value::Text(format!("t{}", i % 9))).collect(),
+            sample(),
+        ];
+        for values in inputs {
+            let input = vector(values.clone());
+            let selection: Vec<usize> = (0..values.len()).filter(|i| i % 3 != 1).collect();
+            for name in ["COUNT", "SUM", "AVG", "MIN", "MAX"] {
+                let mut vectorized = Accumulator::new(name);
+                // Two calls, as across two batches
+                let (first, second) = selection.split_at(selection.len() / 2);
+                accumulate(&mut vectorized, Some(&input), first);
+                accumulate(&mut vectorized, Some(&input), second);
+
+                let mut scalar = Accumulator::new(name);
+                for &i in &selection {
+                    scalar.update(Some(values[i].clone()));
+                }
+                let (got, expected) = (vectorized.finish(), scalar.finish());
+                let same = got == expected
+                    || matches!((&got, &expected), (Value::Float(a), Value::Float(b)) if a.is_nan() && b.is_nan());
+                assert!(same, "{} over {:?}: {:?} vs {:?}", name, values, got, expected);
+            }
+        }
+
+        let mut count_star = Accumulator::new("COUNT");
+        accumulate(&mut count_star, None, &[0, 4, 9]);
+        assert_eq!(count_star.finish(), Value::Integer(3));
+    }
+}
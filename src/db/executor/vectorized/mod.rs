@@ -0,0 +1,596 @@
+// Vectorized execution - scans, filters and aggregates over column batches
+//
+// Rows are read from storage a batch at a time and the columns a query needs
+// are gathered into typed vectors. Predicates, arithmetic and aggregates then
+// run as kernels over whole columns instead of once per row per expression.
+pub mod batch;
+pub mod kernels;
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+
+use batch::{Bitmap, ColumnBatch};
+use kernels::Datum;
+
+use super::operators::{
+    qualify_row, scan_filter, skipped_chunks_note, table_schema, Accumulator, HashAggregate, PhysicalOperator, Schema,
+    SCAN_BATCH_SIZE,
+};
+use super::parallel::{self, MORSEL_SIZE};
+use crate::db::planner::aggregate_key;
+use crate::db::sql::constants::BinaryOperator;
+use crate::db::sql::parser::{Expression, SqlPrettyPrinter};
+use crate::db::catalog::TableFormat;
+use crate::db::storage::{engine, Row, ScanFilter, TableSnapshot, Value};
+
+/// Tables smaller than this are evaluated row at a time: gathering columns
+/// would cost more than it saves
+pub const VECTORIZE_MIN_ROWS: f64 = SCAN_BATCH_SIZE as f64;
+
+/// A value expression compiled against one scanned table
+#[derive(Debug, Clone)]
+pub enum VectorExpr {
+    /// Column of the stored rows (NULL where a row lacks it)
+    Column(String),
+    Literal(Value),
+    Arithmetic {
+        left: Box<VectorExpr>,
+        operator: BinaryOperator,
+        right: Box<VectorExpr>,
+    },
+}
+
+impl VectorExpr {
+    /// Compile an expression over the rows of a table scanned as `qualifier`;
+    /// None if it uses anything the kernels do not cover
+    pub fn compile(expr: &Expression, qualifier: &str) -> Option<Self> {
+        match expr {
+            Expression::Literal(literal) => Some(VectorExpr::Literal(Value::from_literal(literal))),
+            Expression::Identifier(name) => match name.split_once('.') {
+                Some((q, column)) if q == qualifier => Some(VectorExpr::Column(column.to_string())),
+                // A qualifier of some other relation never matches a row of this table
+                Some(_) => Some(VectorExpr::Literal(Value::Null)),
+                None => Some(VectorExpr::Column(name.clone())),
+            },
+            // Resolves the bare column when the qualifier does not match
+            Expression::QualifiedColumn { column, .. } => Some(VectorExpr::Column(column.clone())),
+            Expression::BinaryOp { left, operator, right } => match operator {
+                BinaryOperator::Plus
+                | BinaryOperator::Minus
+                | BinaryOperator::Multiply
+                | BinaryOperator::Divide => Some(VectorExpr::Arithmetic {
+                    left: Box::new(Self::compile(left, qualifier)?),
+                    operator: operator.clone(),
+                    right: Box::new(Self::compile(right, qualifier)?),
+                }),
+                _ => None,
+            },
+            Expression::Alias { expr, .. } => Self::compile(expr, qualifier),
+            _ => None,
+        }
+    }
+
+    pub fn evaluate<'b>(&self, batch: &'b ColumnBatch) -> Datum<'b> {
+        match self {
+            VectorExpr::Column(name) => match batch.column(name) {
+                Some(column) => Datum::Vector(std::borrow::Cow::Borrowed(column)),
+                None => Datum::Scalar(Value::Null),
+            },
+            VectorExpr::Literal(value) => Datum::Scalar(value.clone()),
+            VectorExpr::Arithmetic { left, operator, right } => {
+                kernels::arithmetic(&left.evaluate(batch), operator, &right.evaluate(batch), batch.len())
+            }
+        }
+    }
+
+    fn columns(&self, out: &mut Vec<String>) {
+        match self {
+            VectorExpr::Column(name) if !out.contains(name) => out.push(name.clone()),
+            VectorExpr::Arithmetic { left, right, .. } => {
+                left.columns(out);
+                right.columns(out);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// A WHERE condition compiled against one scanned table
+#[derive(Debug, Clone)]
+pub enum VectorPredicate {
+    Constant(bool),
+    Compare {
+        left: VectorExpr,
+        operator: BinaryOperator,
+        right: VectorExpr,
+    },
+    And(Box<VectorPredicate>, Box<VectorPredicate>),
+    Or(Box<VectorPredicate>, Box<VectorPredicate>),
+}
+
+impl VectorPredicate {
+    pub fn compile(expr: &Expression, qualifier: &str) -> Option<Self> {
+        use crate::db::sql::constants::Literal;
+        match expr {
+            Expression::Literal(Literal::Boolean(b)) => Some(VectorPredicate::Constant(*b)),
+            Expression::BinaryOp { left, operator, right } => match operator {
+                BinaryOperator::And => Some(VectorPredicate::And(
+                    Box::new(Self::compile(left, qualifier)?),
+                    Box::new(Self::compile(right, qualifier)?),
+                )),
+                BinaryOperator::Or => Some(VectorPredicate::Or(
+                    Box::new(Self::compile(left, qualifier)?),
+                    Box::new(Self::compile(right, qualifier)?),
+                )),
+                BinaryOperator::Equals
+                | BinaryOperator::NotEquals
+                | BinaryOperator::LessThan
+                | BinaryOperator::LessThanOrEqual
+                | BinaryOperator::GreaterThan
+                | BinaryOperator::GreaterThanOrEqual
+                | BinaryOperator::Like => Some(VectorPredicate::Compare {
+                    left: VectorExpr::compile(left, qualifier)?,
+                    operator: operator.clone(),
+                    right: VectorExpr::compile(right, qualifier)?,
+                }),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Rows of the batch the condition holds for
+    pub fn evaluate(&self, batch: &ColumnBatch) -> Bitmap {
+        match self {
+            VectorPredicate::Constant(b) => Bitmap::new(batch.len(), *b),
+            VectorPredicate::Compare { left, operator, right } => {
+                kernels::compare(&left.evaluate(batch), operator, &right.evaluate(batch), batch.len())
+            }
+            VectorPredicate::And(left, right) => left.evaluate(batch).and(&right.evaluate(batch)),
+            VectorPredicate::Or(left, right) => left.evaluate(batch).or(&right.evaluate(batch)),
+        }
+    }
+
+    fn columns(&self, out: &mut Vec<String>) {
+        match self {
+            VectorPredicate::Constant(_) => {}
+            VectorPredicate::Compare { left, right, .. } => {
+                left.columns(out);
+                right.columns(out);
+            }
+            VectorPredicate::And(left, right) | VectorPredicate::Or(left, right) => {
+                left.columns(out);
+                right.columns(out);
+            }
+        }
+    }
+}
+
+/// A compiled WHERE condition and the columns it reads
+#[derive(Debug, Clone)]
+pub struct BatchFilter {
+    predicate: VectorPredicate,
+    expr: Expression,
+    columns: Vec<String>,
+}
+
+impl BatchFilter {
+    pub fn compile(expr: &Expression, qualifier: &str) -> Option<Self> {
+        let predicate = VectorPredicate::compile(expr, qualifier)?;
+        let mut columns = Vec::new();
+        predicate.columns(&mut columns);
+        Some(Self {
+            predicate,
+            expr: expr.clone(),
+            columns,
+        })
+    }
+
+    /// Positions of the rows the condition holds for
+    pub fn select(&self, rows: &[Row]) -> Vec<usize> {
+        let batch = ColumnBatch::from_rows(rows, &self.columns);
+        self.predicate.evaluate(&batch).ones()
+    }
+
+    /// Keep the stored rows the condition holds for, in order
+    pub fn apply(&self, rows: Vec<Row>) -> Vec<Row> {
+        let selected = self.select(&rows);
+        let mut keep = selected.into_iter().peekable();
+        rows.into_iter()
+            .enumerate()
+            .filter_map(|(i, row)| (keep.next_if_eq(&i).is_some()).then_some(row))
+            .collect()
+    }
+
+    pub fn expression(&self) -> &Expression {
+        &self.expr
+    }
+
+    /// Columns the condition reads
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+}
+
+fn describe_scan(table: &str, qualifier: &str, filter: Option<&BatchFilter>) -> String {
+    let mut detail = if qualifier == table {
+        format!("on {}", table)
+    } else {
+        format!("on {} {}", table, qualifier)
+    };
+    if let Some(filter) = filter {
+        detail.push_str(&format!(
+            " filter {}",
+            SqlPrettyPrinter::new().print_expression(filter.expression())
+        ));
+    }
+    detail
+}
+
+/// Sequential scan that filters each batch with vectorized kernels before
+/// turning the surviving rows into qualified rows
+pub struct VectorizedScan {
+    snapshot: Arc<dyn TableSnapshot>,
+    table: String,
+    qualifier: String,
+    filter: BatchFilter,
+    scan_filter: ScanFilter,
+    schema: Schema,
+    buffer: VecDeque<Row>,
+    next_offset: usize,
+    exhausted: bool,
+    skipped_chunks: usize,
+}
+
+impl VectorizedScan {
+    pub fn new(table: &str, qualifier: &str, filter: BatchFilter) -> Result<Self, String> {
+        let snapshot = engine::current().snapshot(table)?;
+        let sample = snapshot.scan(0, 1, &ScanFilter::default())?.rows;
+        Ok(Self {
+            snapshot,
+            table: table.to_string(),
+            qualifier: qualifier.to_string(),
+            scan_filter: scan_filter(table, qualifier, Some(filter.expression())),
+            filter,
+            schema: table_schema(table, qualifier, sample.first()),
+            buffer: VecDeque::new(),
+            next_offset: 0,
+            exhausted: false,
+            skipped_chunks: 0,
+        })
+    }
+}
+
+impl PhysicalOperator for VectorizedScan {
+    fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    fn next(&mut self) -> Result<Option<Row>, String> {
+        loop {
+            if let Some(row) = self.buffer.pop_front() {
+                return Ok(Some(qualify_row(&self.qualifier, row)));
+            }
+            if self.exhausted {
+                return Ok(None);
+            }
+            let batch = self.snapshot.scan(self.next_offset, SCAN_BATCH_SIZE, &self.scan_filter)?;
+            self.exhausted = batch.scanned < SCAN_BATCH_SIZE;
+            self.next_offset += batch.scanned;
+            self.skipped_chunks += batch.skipped_chunks;
+            self.buffer = self.filter.apply(batch.rows).into();
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "Vectorized Seq Scan"
+    }
+
+    fn detail(&self) -> String {
+        let mut detail = describe_scan(&self.table, &self.qualifier, Some(&self.filter));
+        detail.push_str(&skipped_chunks_note(self.skipped_chunks));
+        detail
+    }
+}
+
+/// One morsel after the parallel phase of a vectorized aggregate
+struct EvaluatedMorsel {
+    /// Positions (within the morsel) of the rows that passed the filter
+    selection: Vec<usize>,
+    /// Group key of each selected row (None without GROUP BY)
+    keys: Option<Vec<String>>,
+    /// Aggregate inputs over the whole morsel (None for COUNT(*))
+    inputs: Vec<Option<Datum<'static>>>,
+}
+
+/// Scan, filter and aggregate of a single table, fused and evaluated over
+/// column batches. Workers evaluate morsels; each group then folds its rows in
+/// table order, so results match `HashAggregate` over a scan exactly.
+pub struct VectorizedAggregate {
+    snapshot: Arc<dyn TableSnapshot>,
+    table: String,
+    qualifier: String,
+    filter: Option<BatchFilter>,
+    group_by: Vec<Expression>,
+    group_exprs: Vec<VectorExpr>,
+    aggregates: Vec<Expression>,
+    inputs: Vec<Option<VectorExpr>>,
+    /// Columns the keys and inputs read
+    columns: Vec<String>,
+    /// What to read from storage: a columnar table decodes only the columns
+    /// above and the filter's, in the chunks the filter does not rule out
+    scan_filter: ScanFilter,
+    workers: usize,
+    schema: Schema,
+    output: Option<VecDeque<Row>>,
+    skipped_chunks: usize,
+}
+
+impl VectorizedAggregate {
+    /// None unless the filter, keys and aggregate arguments all compile
+    pub fn try_new(
+        table: &str,
+        qualifier: &str,
+        predicate: Option<&Expression>,
+        group_by: &[Expression],
+        aggregates: &[Expression],
+        workers: usize,
+    ) -> Result<Option<Self>, String> {
+        let filter = match predicate {
+            Some(predicate) => match BatchFilter::compile(predicate, qualifier) {
+                Some(filter) => Some(filter),
+                None => return Ok(None),
+            },
+            None => None,
+        };
+        let Some(group_exprs) = group_by
+            .iter()
+            .map(|e| VectorExpr::compile(e, qualifier))
+            .collect::<Option<Vec<_>>>()
+        else {
+            return Ok(None);
+        };
+        let Some(inputs) = aggregates
+            .iter()
+            .map(|expr| match expr {
+                Expression::Function { args, .. } => match args.first() {
+                    None => Some(None),
+                    Some(Expression::Identifier(star)) if star == "*" => Some(None),
+                    Some(arg) => VectorExpr::compile(arg, qualifier).map(Some),
+                },
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()
+        else {
+            return Ok(None);
+        };
+
+        let mut columns = Vec::new();
+        for expr in group_exprs.iter().chain(inputs.iter().flatten()) {
+            expr.columns(&mut columns);
+        }
+        let storage = engine::current();
+        let snapshot = storage.snapshot(table)?;
+        let sample = snapshot.scan(0, 1, &ScanFilter::default())?.rows;
+        let mut scan_filter = scan_filter(table, qualifier, predicate);
+        if storage.table_format(table)? == TableFormat::Columnar {
+            let mut read = columns.clone();
+            for column in filter.iter().flat_map(|f| f.columns()) {
+                if !read.contains(column) {
+                    read.push(column.clone());
+                }
+            }
+            scan_filter.columns = Some(read);
+        }
+
+        Ok(Some(Self {
+            snapshot,
+            table: table.to_string(),
+            qualifier: qualifier.to_string(),
+            filter,
+            group_by: group_by.to_vec(),
+            group_exprs,
+            aggregates: aggregates.to_vec(),
+            inputs,
+            columns,
+            scan_filter,
+            workers: workers.max(1),
+            schema: table_schema(table, qualifier, sample.first()),
+            output: None,
+            skipped_chunks: 0,
+        }))
+    }
+
+    /// Filter a morsel and evaluate its group keys and aggregate inputs
+    fn evaluate(&self, rows: &[Row]) -> EvaluatedMorsel {
+        let selection = match &self.filter {
+            Some(filter) => filter.select(rows),
+            None => (0..rows.len()).collect(),
+        };
+        let batch = ColumnBatch::from_rows(rows, &self.columns);
+
+        let keys = (!self.group_exprs.is_empty()).then(|| {
+            let keys: Vec<Datum> = self.group_exprs.iter().map(|e| e.evaluate(&batch)).collect();
+            selection
+                .iter()
+                .map(|&i| {
+                    let key_values: Vec<Value> = keys.iter().map(|k| k.value(i)).collect();
+                    format!("{:?}", key_values)
+                })
+                .collect()
+        });
+        let inputs = self
+            .inputs
+            .iter()
+            .map(|input| input.as_ref().map(|e| e.evaluate(&batch).into_owned()))
+            .collect();
+
+        EvaluatedMorsel {
+            selection,
+            keys,
+            inputs,
+        }
+    }
+
+    fn build(&mut self) -> Result<VecDeque<Row>, String> {
+        let mut groups: Vec<(Row, Vec<Accumulator>)> = Vec::new();
+        let mut index: HashMap<String, usize> = HashMap::new();
+
+        let chunk_size = MORSEL_SIZE * self.workers;
+        let mut offset = 0;
+        loop {
+            let batch = self.snapshot.scan(offset, chunk_size, &self.scan_filter)?;
+            self.skipped_chunks += batch.skipped_chunks;
+            let chunk = batch.rows;
+            let morsels = parallel::map_morsels(&chunk, self.workers, |rows| self.evaluate(rows));
+
+            for (m, morsel) in morsels.iter().enumerate() {
+                // Split the morsel's rows by group, keeping table order within each
+                let mut local: Vec<(usize, Vec<usize>)> = Vec::new();
+                for (k, &i) in morsel.selection.iter().enumerate() {
+                    let key = morsel.keys.as_ref().map_or("[]", |keys| keys[k].as_str());
+                    let group = match index.get(key) {
+                        Some(&g) => g,
+                        None => {
+                            let position = m * MORSEL_SIZE + i;
+                            // A group row carries every column, also the ones not decoded
+                            let first = match self.scan_filter.columns {
+                                Some(_) => self
+                                    .snapshot
+                                    .scan(batch.positions[position], 1, &ScanFilter::default())?
+                                    .rows
+                                    .pop()
+                                    .unwrap_or_else(|| chunk[position].clone()),
+                                None => chunk[position].clone(),
+                            };
+                            let first = qualify_row(&self.qualifier, first);
+                            groups.push((first, HashAggregate::new_accumulators(&self.aggregates)));
+                            index.insert(key.to_string(), groups.len() - 1);
+                            groups.len() - 1
+                        }
+                    };
+                    match local.iter_mut().find(|(g, _)| *g == group) {
+                        Some((_, rows)) => rows.push(i),
+                        None => local.push((group, vec![i])),
+                    }
+                }
+
+                for (group, selection) in local {
+                    for (accumulator, input) in groups[group].1.iter_mut().zip(&morsel.inputs) {
+                        kernels::accumulate(accumulator, input.as_ref(), &selection);
+                    }
+                }
+            }
+
+            offset += batch.scanned;
+            if batch.scanned < chunk_size {
+                break;
+            }
+        }
+
+        // Without GROUP BY, aggregates over no rows still yield one row
+        if groups.is_empty() && self.group_by.is_empty() {
+            groups.push((Row::new(), HashAggregate::new_accumulators(&self.aggregates)));
+        }
+
+        Ok(groups
+            .into_iter()
+            .map(|(mut row, accumulators)| {
+                for (expr, acc) in self.aggregates.iter().zip(accumulators) {
+                    row.insert(aggregate_key(expr), acc.finish());
+                }
+                row
+            })
+            .collect())
+    }
+}
+
+impl PhysicalOperator for VectorizedAggregate {
+    fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    fn next(&mut self) -> Result<Option<Row>, String> {
+        if self.output.is_none() {
+            self.output = Some(self.build()?);
+        }
+        Ok(self.output.as_mut().and_then(|rows| rows.pop_front()))
+    }
+
+    fn name(&self) -> &'static str {
+        "Vectorized Aggregate"
+    }
+
+    fn detail(&self) -> String {
+        let printer = SqlPrettyPrinter::new();
+        let aggregates: Vec<String> = self.aggregates.iter().map(|e| printer.print_expression(e)).collect();
+        let mut detail = aggregates.join(", ");
+        if !self.group_by.is_empty() {
+            let keys: Vec<String> = self.group_by.iter().map(|e| printer.print_expression(e)).collect();
+            detail.push_str(&format!(" by {}", keys.join(", ")));
+        }
+        detail.push(' ');
+        detail.push_str(&describe_scan(&self.table, &self.qualifier, self.filter.as_ref()));
+        if self.workers > 1 {
+            detail.push_str(&format!(" (workers={})", self.workers));
+        }
+        detail.push_str(&skipped_chunks_note(self.skipped_chunks));
+        detail
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::executor::explain::PlanNode;
+    use crate::db::executor::{Executor, QueryScope, ResultSet};
+    use crate::db::planner::LogicalPlan;
+    use crate::db::sql::execute_sql;
+    use crate::db::sql::parser::SqlParser;
+
+    fn run(sql: &str, workers: usize, vectorize: bool) -> (ResultSet, Vec<String>) {
+        let stmt = SqlParser::parse_statement(sql).unwrap();
+        let plan = LogicalPlan::from_statement(&stmt).unwrap();
+        let op = Executor::lower_plan_with(&plan, &QueryScope::default(), workers, vectorize).unwrap();
+        let lines = PlanNode::from_operator(op.as_ref()).to_lines();
+        (Executor::collect_rows(op).unwrap(), lines)
+    }
+
+    #[test]
+    fn test_vectorized_plans_match_row_at_a_time() {
+        execute_sql("DROP TABLE IF EXISTS vector_events");
+        execute_sql("CREATE TABLE vector_events (id INT, kind TEXT, score FLOAT, weight INT)");
+        let rows: Vec<String> = (0..3000)
+            .map(|i| {
+                let kind = if i % 11 == 0 { "NULL".to_string() } else { format!("'k{}'", i % 5) };
+                let weight = if i % 13 == 0 { "NULL".to_string() } else { (i % 17 - 8).to_string() };
+                format!("({}, {}, {}.{}, {})", i, kind, i % 41, i % 7, weight)
+            })
+            .collect();
+        execute_sql(&format!("INSERT INTO vector_events VALUES {}", rows.join(", ")));
+
+        for sql in [
+            "SELECT * FROM vector_events WHERE score > 20.5 AND kind LIKE 'k%' OR weight = 3",
+            "SELECT id FROM vector_events WHERE weight * 2 + id / 10 >= 50 AND kind <> 'k2'",
+            "SELECT kind, COUNT(*), COUNT(weight), SUM(score), AVG(weight), MIN(kind), MAX(score * weight) \
+             FROM vector_events WHERE id > 10 GROUP BY kind",
+            "SELECT weight, SUM(id) FROM vector_events GROUP BY weight",
+            "SELECT COUNT(*), SUM(score + 1.5), AVG(score) FROM vector_events WHERE score < 3.5",
+            "SELECT COUNT(*), SUM(weight) FROM vector_events WHERE id < 0",
+        ] {
+            let (expected, row_plan) = run(sql, 1, false);
+            assert!(!row_plan.iter().any(|l| l.contains("Vectorized")));
+            for workers in [1, 3] {
+                let (got, plan) = run(sql, workers, true);
+                let vectorized = plan.iter().any(|l| l.to_lowercase().contains("vectorized"));
+                assert!(vectorized, "not vectorized: {}", sql);
+                assert_eq!(got, expected, "results differ for {} with {} worker(s)", sql, workers);
+            }
+        }
+
+        // Conditions outside the kernels keep the row-at-a-time plan
+        let (_, plan) = run("SELECT id FROM vector_events WHERE UPPER(kind) = 'K1'", 1, true);
+        assert!(!plan.iter().any(|l| l.contains("Vectorized")));
+
+        execute_sql("DROP TABLE vector_events");
+    }
+}
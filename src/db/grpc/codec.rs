@@ -0,0 +1,63 @@
+// gRPC codec for rust-protobuf messages
+use std::marker::PhantomData;
+
+use tonic::codec::{Codec, DecodeBuf, Decoder, EncodeBuf, Encoder};
+use tonic::codegen::Bytes;
+use tonic::Status;
+
+/// Encodes `E` and decodes `D`, both generated by rust-protobuf
+pub struct ProtobufCodec<E, D>(PhantomData<fn(E) -> D>);
+
+impl<E, D> Default for ProtobufCodec<E, D> {
+    fn default() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<E, D> Codec for ProtobufCodec<E, D>
+where
+    E: protobuf::Message + Send + 'static,
+    D: protobuf::Message + Send + 'static,
+{
+    type Encode = E;
+    type Decode = D;
+    type Encoder = ProtobufEncoder<E>;
+    type Decoder = ProtobufDecoder<D>;
+
+    fn encoder(&mut self) -> Self::Encoder {
+        ProtobufEncoder(PhantomData)
+    }
+
+    fn decoder(&mut self) -> Self::Decoder {
+        ProtobufDecoder(PhantomData)
+    }
+}
+
+pub struct ProtobufEncoder<E>(PhantomData<fn(E)>);
+
+impl<E: protobuf::Message> Encoder for ProtobufEncoder<E> {
+    type Item = E;
+    type Error = Status;
+
+    fn encode(&mut self, item: E, dst: &mut EncodeBuf<'_>) -> Result<(), Status> {
+        let bytes = item
+            .write_to_bytes()
+            .map_err(|e| Status::internal(format!("Failed to encode {}: {}", E::NAME, e)))?;
+        bytes::BufMut::put_slice(dst, &bytes);
+        Ok(())
+    }
+}
+
+pub struct ProtobufDecoder<D>(PhantomData<fn() -> D>);
+
+impl<D: protobuf::Message> Decoder for ProtobufDecoder<D> {
+    type Item = D;
+    type Error = Status;
+
+    fn decode(&mut self, src: &mut DecodeBuf<'_>) -> Result<Option<D>, Status> {
+        let bytes: Bytes = bytes::Buf::copy_to_bytes(src, bytes::Buf::remaining(src));
+        D::parse_from_bytes(&bytes)
+            .map(Some)
+            .map_err(|e| Status::invalid_argument(format!("Invalid {}: {}", D::NAME, e)))
+    }
+}
@@ -0,0 +1,820 @@
+// gRPC front end - the typed query API of proto/butterfly.proto, for
+// services that want schema'd binary results instead of JSON
+//
+// Every RPC fails with a `Status`, which is large; boxing it would only
+// move the allocation
+#![allow(clippy::result_large_err)]
+pub mod codec;
+
+pub mod proto {
+    include!(concat!(env!("OUT_DIR"), "/protos/mod.rs"));
+}
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+use tokio::sync::mpsc;
+use tonic::body::BoxBody;
+use tonic::codegen::{http, tokio_stream, BoxFuture, BoxStream, Service};
+use tonic::metadata::MetadataValue;
+use tonic::server::{Grpc, NamedService, ServerStreamingService, UnaryService};
+use tonic::transport::Server;
+use tonic::{Code, Request, Response, Status};
+
+use crate::db::executor::{ExecutionResult, Executor, RowStream};
+use crate::db::notify::Listener;
+use crate::db::pgwire::{self, types};
+use crate::db::pool::POOL;
+use crate::db::session::{PreparedStatement, Session};
+use crate::db::sql::{SqlParser, Statement, TransactionStatement};
+use crate::db::storage::Value;
+use crate::info;
+use codec::ProtobufCodec;
+use proto::butterfly::{
+    execute_request, execute_response, value, BeginTransactionRequest, BeginTransactionResponse, Column, ColumnType,
+    CommitRequest, CommitResponse, DeallocateRequest, DeallocateResponse, ExecuteRequest, ExecuteResponse,
+    ListenRequest, Notification, PrepareRequest, PrepareResponse, QueryResponse, RollbackRequest, RollbackResponse,
+    Row, RowSet, Upserted,
+};
+
+/// Rows per QueryResponse message
+pub const QUERY_BATCH_ROWS: usize = 256;
+
+/// Serve the Butterfly gRPC service on `addr`
+pub async fn serve(addr: SocketAddr) -> Result<(), tonic::transport::Error> {
+    info!(format!("gRPC listening on {}", addr));
+    Server::builder().add_service(ButterflyService::default()).serve(addr).await
+}
+
+/// Most statements Prepare keeps at once
+pub const MAX_PREPARED_STATEMENTS: usize = 10_000;
+
+/// How long a prepared statement is kept after its last use
+pub const PREPARED_STATEMENT_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Batches a Query call produces ahead of what the client has read
+const QUERY_BUFFERED_BATCHES: usize = 2;
+
+/// Send a query's rows in QueryResponse batches, the first one (sent even
+/// for no rows) with the columns, typed from its rows where the catalog
+/// cannot. Stops at an error, which goes out last, or once the client has
+/// gone.
+fn send_batches(stmt: &Statement, mut rows: RowStream, sender: &mpsc::Sender<Result<QueryResponse, Status>>) {
+    let names = rows.columns().to_vec();
+    let mut first = true;
+    loop {
+        let mut batch = Vec::with_capacity(QUERY_BATCH_ROWS);
+        for row in rows.by_ref().take(QUERY_BATCH_ROWS) {
+            match row {
+                Ok(row) => batch.push(row),
+                Err(message) => {
+                    let _ = sender.blocking_send(Err(status(message)));
+                    return;
+                }
+            }
+        }
+        if batch.is_empty() && !first {
+            return;
+        }
+        let mut message = QueryResponse::new();
+        if std::mem::take(&mut first) {
+            message.columns = columns(stmt, &names, &batch);
+        }
+        let last = batch.len() < QUERY_BATCH_ROWS;
+        message.rows = batch.iter().map(|row| row_message(row)).collect();
+        if sender.blocking_send(Ok(message)).is_err() || last {
+            return;
+        }
+    }
+}
+
+/// State shared by every RPC
+#[derive(Default)]
+struct ServiceState {
+    /// Statements prepared by Prepare, by id, with when they were last used
+    statements: Mutex<HashMap<String, (Arc<PreparedStatement>, Instant)>>,
+    /// Sessions of the transactions BeginTransaction opened, by id
+    transactions: Mutex<HashMap<String, Transaction>>,
+}
+
+/// An open transaction. It is rolled back once it sits unused for longer
+/// than the pool's idle timeout, the way an idle PostgreSQL connection is
+/// reaped.
+struct Transaction {
+    session: Arc<Mutex<Session>>,
+    last_used: Instant,
+}
+
+/// The `butterfly.v1.Butterfly` service
+#[derive(Clone, Default)]
+pub struct ButterflyService {
+    state: Arc<ServiceState>,
+}
+
+impl NamedService for ButterflyService {
+    const NAME: &'static str = "butterfly.v1.Butterfly";
+}
+
+fn new_id() -> String {
+    format!("{:016x}", rand::rng().random::<u64>())
+}
+
+/// Status for an error message from the parser or executor; the SQLSTATE
+/// it maps to goes along in the `sqlstate` metadata entry
+fn status(message: String) -> Status {
+    let sqlstate = types::sqlstate(&message);
+    let code = match sqlstate {
+        "42601" | "22P02" | "08P01" => Code::InvalidArgument,
+        "42P01" | "42703" | "3D000" | "26000" => Code::NotFound,
+        "42P07" | "42P04" | "42P05" => Code::AlreadyExists,
+        "0A000" => Code::Unimplemented,
+        _ => Code::Internal,
+    };
+    let mut status = Status::new(code, message);
+    status.metadata_mut().insert("sqlstate", MetadataValue::from_static(sqlstate));
+    status
+}
+
+fn transaction_not_found(id: &str) -> Status {
+    Status::not_found(format!("Transaction '{}' does not exist or has timed out", id))
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> Result<std::sync::MutexGuard<'_, T>, Status> {
+    mutex.lock().map_err(|e| Status::internal(e.to_string()))
+}
+
+pub fn to_proto(value: &Value) -> proto::butterfly::Value {
+    let mut message = proto::butterfly::Value::new();
+    message.kind = match value {
+        Value::Null => None,
+        Value::Integer(i) => Some(value::Kind::Integer(*i)),
+        Value::Float(f) => Some(value::Kind::Float(*f)),
+        Value::Text(s) => Some(value::Kind::Text(s.clone())),
+        Value::Boolean(b) => Some(value::Kind::Boolean(*b)),
+    };
+    message
+}
+
+pub fn from_proto(message: &proto::butterfly::Value) -> Value {
+    match &message.kind {
+        None => Value::Null,
+        Some(value::Kind::Integer(i)) => Value::Integer(*i),
+        Some(value::Kind::Float(f)) => Value::Float(*f),
+        Some(value::Kind::Text(s)) => Value::Text(s.clone()),
+        Some(value::Kind::Boolean(b)) => Value::Boolean(*b),
+    }
+}
+
+fn column_type(oid: i32) -> ColumnType {
+    match oid {
+        types::INT8 | types::INT4 | types::INT2 => ColumnType::COLUMN_TYPE_INTEGER,
+        types::FLOAT8 | types::FLOAT4 => ColumnType::COLUMN_TYPE_FLOAT,
+        types::BOOL => ColumnType::COLUMN_TYPE_BOOLEAN,
+        types::DATE => ColumnType::COLUMN_TYPE_DATE,
+        types::TIMESTAMP => ColumnType::COLUMN_TYPE_TIMESTAMP,
+        _ => ColumnType::COLUMN_TYPE_TEXT,
+    }
+}
+
+/// Column messages for `columns`, typed from the catalog where the
+/// statement's tables describe them and from the values otherwise
+fn columns(stmt: &Statement, columns: &[String], rows: &[Vec<Value>]) -> Vec<Column> {
+    columns
+        .iter()
+        .zip(pgwire::column_types(stmt, columns, rows))
+        .map(|(name, oid)| {
+            let mut column = Column::new();
+            column.name = name.clone();
+            column.type_ = column_type(oid).into();
+            column
+        })
+        .collect()
+}
+
+/// The rows of a result in column order
+fn result_rows(columns: &[String], rows: &[HashMap<String, serde_json::Value>]) -> Vec<Vec<Value>> {
+    rows.iter()
+        .map(|row| {
+            columns
+                .iter()
+                .map(|c| row.get(c).and_then(|v| Value::from_json(v).ok()).unwrap_or(Value::Null))
+                .collect()
+        })
+        .collect()
+}
+
+fn row_message(values: &[Value]) -> Row {
+    let mut row = Row::new();
+    row.values = values.iter().map(to_proto).collect();
+    row
+}
+
+/// One statement parsed from `sql`
+fn parse_one(sql: &str) -> Result<Statement, Status> {
+    let mut statements = SqlParser::parse(sql).map_err(|e| status(format!("SQL parse error: {}", e)))?;
+    match statements.len() {
+        1 => Ok(statements.remove(0)),
+        0 => Err(Status::invalid_argument("No statement given")),
+        _ => Err(Status::invalid_argument("Only one statement may be sent per request")),
+    }
+}
+
+impl ServiceState {
+    /// The statement a request runs, with its parameters bound
+    fn statement(&self, request: &ExecuteRequest) -> Result<Statement, Status> {
+        let params: Vec<Value> = request.params.iter().map(from_proto).collect();
+        match &request.statement {
+            Some(execute_request::Statement::Sql(sql)) => {
+                let stmt = parse_one(sql)?;
+                if params.is_empty() {
+                    return Ok(stmt);
+                }
+                PreparedStatement::new(stmt, Vec::new())
+                    .and_then(|prepared| prepared.bind(&params))
+                    .map_err(status)
+            }
+            Some(execute_request::Statement::StatementId(id)) => {
+                let prepared = match lock(&self.statements)?.get_mut(id) {
+                    Some((prepared, last_used)) => {
+                        *last_used = Instant::now();
+                        Arc::clone(prepared)
+                    }
+                    None => return Err(status(format!("Prepared statement '{}' does not exist", id))),
+                };
+                prepared.bind(&params).map_err(status)
+            }
+            None => Err(Status::invalid_argument("Request has neither sql nor statement_id")),
+        }
+    }
+
+    /// Keep a prepared statement under a new id, first dropping the ones
+    /// unused for `PREPARED_STATEMENT_TTL`
+    fn keep_statement(&self, prepared: Arc<PreparedStatement>) -> Result<String, Status> {
+        let mut statements = lock(&self.statements)?;
+        statements.retain(|_, (_, last_used)| last_used.elapsed() < PREPARED_STATEMENT_TTL);
+        if statements.len() >= MAX_PREPARED_STATEMENTS {
+            return Err(Status::resource_exhausted(format!(
+                "Too many prepared statements ({}); deallocate some first",
+                MAX_PREPARED_STATEMENTS
+            )));
+        }
+        let id = new_id();
+        statements.insert(id.clone(), (prepared, Instant::now()));
+        Ok(id)
+    }
+
+    /// Lock the transactions, rolling back the ones left idle too long.
+    /// A transaction with a statement running is never idle.
+    fn transactions(&self) -> Result<std::sync::MutexGuard<'_, HashMap<String, Transaction>>, Status> {
+        let timeout = Duration::from_millis(POOL.config().idle_timeout_ms);
+        let mut transactions = lock(&self.transactions)?;
+        transactions.retain(|_, t| Arc::strong_count(&t.session) > 1 || t.last_used.elapsed() < timeout);
+        Ok(transactions)
+    }
+
+    /// The session of an open transaction, marked as used
+    fn transaction(&self, id: &str) -> Result<Arc<Mutex<Session>>, Status> {
+        let mut transactions = self.transactions()?;
+        let transaction = transactions.get_mut(id).ok_or_else(|| transaction_not_found(id))?;
+        transaction.last_used = Instant::now();
+        Ok(Arc::clone(&transaction.session))
+    }
+
+    /// The session a request runs in: its transaction's, or None for a
+    /// session of its own
+    fn session(&self, request: &ExecuteRequest) -> Result<Option<Arc<Mutex<Session>>>, Status> {
+        match request.transaction_id.as_str() {
+            "" => Ok(None),
+            id => self.transaction(id).map(Some),
+        }
+    }
+
+    /// Mark a request's transaction as used, or forget it if a COMMIT or
+    /// ROLLBACK sent as SQL ended it
+    fn ran(&self, request: &ExecuteRequest, open: bool) -> Result<(), Status> {
+        if !request.transaction_id.is_empty() {
+            let mut transactions = lock(&self.transactions)?;
+            match open {
+                true => transactions.get_mut(&request.transaction_id).map(|t| t.last_used = Instant::now()),
+                false => transactions.remove(&request.transaction_id).map(|_| ()),
+            };
+        }
+        Ok(())
+    }
+
+    /// Run a request's statement off the async runtime, in its transaction's
+    /// session or in a session of its own
+    async fn run(&self, request: &ExecuteRequest) -> Result<(Statement, ExecutionResult), Status> {
+        let _guard = POOL.acquire().await.map_err(Status::resource_exhausted)?;
+        let stmt = self.statement(request)?;
+        let session = self.session(request)?;
+        let (stmt, result, open) = tokio::task::spawn_blocking(move || -> Result<_, Status> {
+            let Some(session) = session else {
+                let result = Session::stateless().execute(&stmt);
+                return Ok((stmt, result, false));
+            };
+            let mut session = lock(&session)?;
+            let result = session.execute(&stmt);
+            Ok((stmt, result, session.in_transaction()))
+        })
+        .await
+        .map_err(|_| Status::internal("Statement execution panicked"))??;
+
+        self.ran(request, open)?;
+        match result {
+            ExecutionResult::Error { message } => Err(status(message)),
+            result => Ok((stmt, result)),
+        }
+    }
+
+    /// Commit or roll back the transaction `id`, which ends with it either way
+    fn finish(&self, id: &str, end: TransactionStatement) -> Result<(), Status> {
+        let transaction = lock(&self.transactions)?.remove(id).ok_or_else(|| transaction_not_found(id))?;
+        let mut session = lock(&transaction.session)?;
+        match session.transaction(&end) {
+            ExecutionResult::Error { message } => Err(status(message)),
+            _ => Ok(()),
+        }
+    }
+
+    async fn execute(self: Arc<Self>, request: ExecuteRequest) -> Result<ExecuteResponse, Status> {
+        let (stmt, result) = self.run(&request).await?;
+        let mut response = ExecuteResponse::new();
+        response.result = Some(match result {
+            ExecutionResult::Rows { columns: names, rows } => {
+                let rows = result_rows(&names, &rows);
+                let mut set = RowSet::new();
+                set.columns = columns(&stmt, &names, &rows);
+                set.rows = rows.iter().map(|row| row_message(row)).collect();
+                execute_response::Result::Rows(set)
+            }
+            ExecutionResult::RowsAffected { count } => execute_response::Result::RowsAffected(count as u64),
+            ExecutionResult::Upserted { inserted, updated } => {
+                let mut upserted = Upserted::new();
+                upserted.inserted = inserted as u64;
+                upserted.updated = updated as u64;
+                execute_response::Result::Upserted(upserted)
+            }
+            ExecutionResult::Success { message } => execute_response::Result::Message(message),
+            ExecutionResult::Error { message } => return Err(status(message)),
+        });
+        Ok(response)
+    }
+
+    /// A query's rows go out as its plan produces them, a batch at a time;
+    /// other statements that return rows are run first
+    async fn query(self: Arc<Self>, request: ExecuteRequest) -> Result<BoxStream<QueryResponse>, Status> {
+        let stmt = self.statement(&request)?;
+        if !matches!(stmt, Statement::Select { .. } | Statement::Union { .. } | Statement::With { .. }) {
+            return self.query_result(&request).await;
+        }
+
+        let guard = POOL.acquire().await.map_err(Status::resource_exhausted)?;
+        let session = self.session(&request)?;
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(QUERY_BUFFERED_BATCHES);
+        let producer = tokio::task::spawn_blocking(move || {
+            let _guard = guard;
+            let stream = |session: &mut Session| match session.stream(&stmt) {
+                Some(Ok(rows)) => send_batches(&stmt, rows, &sender),
+                Some(Err(message)) => {
+                    let _ = sender.blocking_send(Err(status(message)));
+                }
+                None => {}
+            };
+            match session {
+                Some(session) => match session.lock() {
+                    Ok(mut session) => stream(&mut session),
+                    Err(e) => {
+                        let _ = sender.blocking_send(Err(Status::internal(e.to_string())));
+                    }
+                },
+                None => stream(&mut Session::stateless()),
+            }
+        });
+
+        // The call fails outright if the statement does before any row
+        let first = match receiver.recv().await {
+            Some(first) => first?,
+            None => {
+                let _ = producer.await;
+                return Err(Status::internal("Statement execution panicked"));
+            }
+        };
+        self.ran(&request, true)?;
+        let rest = tokio_stream::wrappers::ReceiverStream::new(receiver);
+        Ok(Box::pin(tokio_stream::StreamExt::chain(tokio_stream::once(Ok(first)), rest)))
+    }
+
+    /// `query` for a statement that runs before its rows are sent
+    async fn query_result(&self, request: &ExecuteRequest) -> Result<BoxStream<QueryResponse>, Status> {
+        let (stmt, result) = self.run(request).await?;
+        let ExecutionResult::Rows { columns: names, rows } = result else {
+            return Err(Status::invalid_argument("Statement did not return rows"));
+        };
+        let rows = result_rows(&names, &rows);
+        let mut header = QueryResponse::new();
+        header.columns = columns(&stmt, &names, &rows);
+
+        // The columns go out even for an empty result
+        let mut messages = vec![header];
+        for (i, batch) in rows.chunks(QUERY_BATCH_ROWS).enumerate() {
+            if i > 0 {
+                messages.push(QueryResponse::new());
+            }
+            if let Some(message) = messages.last_mut() {
+                message.rows = batch.iter().map(|row| row_message(row)).collect();
+            }
+        }
+        Ok(Box::pin(tokio_stream::iter(messages.into_iter().map(Ok))))
+    }
+
+    async fn prepare(self: Arc<Self>, request: PrepareRequest) -> Result<PrepareResponse, Status> {
+        let stmt = parse_one(&request.sql)?;
+        let prepared = Arc::new(PreparedStatement::new(stmt, Vec::new()).map_err(status)?);
+
+        // Output columns come from the plan, with every parameter NULL
+        let described = Arc::clone(&prepared);
+        let columns = tokio::task::spawn_blocking(move || {
+            let stmt = described.bind(&vec![Value::Null; described.param_count])?;
+            Ok::<_, String>(Executor::describe(&stmt)?.map(|names| columns(&stmt, &names, &[])))
+        })
+        .await
+        .map_err(|_| Status::internal("Describing the statement panicked"))?
+        .map_err(status)?;
+
+        let mut response = PrepareResponse::new();
+        response.param_count = prepared.param_count as u32;
+        response.columns = columns.unwrap_or_default();
+        response.statement_id = self.keep_statement(prepared)?;
+        Ok(response)
+    }
+
+    async fn deallocate(self: Arc<Self>, request: DeallocateRequest) -> Result<DeallocateResponse, Status> {
+        match lock(&self.statements)?.remove(&request.statement_id) {
+            Some(_) => Ok(DeallocateResponse::new()),
+            None => Err(status(format!("Prepared statement '{}' does not exist", request.statement_id))),
+        }
+    }
+
+    /// Open a transaction in a session of its own. Open transactions are
+    /// capped at the pool's connection limit, as PostgreSQL connections are.
+    async fn begin(self: Arc<Self>, _request: BeginTransactionRequest) -> Result<BeginTransactionResponse, Status> {
+        let mut session = Session::new();
+        session.transaction(&TransactionStatement::Begin);
+        let mut transactions = self.transactions()?;
+        if transactions.len() >= POOL.max_connections() as usize {
+            return Err(Status::resource_exhausted(format!(
+                "Too many open transactions ({}); commit or roll back some first",
+                transactions.len()
+            )));
+        }
+        let mut response = BeginTransactionResponse::new();
+        response.transaction_id = new_id();
+        transactions.insert(
+            response.transaction_id.clone(),
+            Transaction {
+                session: Arc::new(Mutex::new(session)),
+                last_used: Instant::now(),
+            },
+        );
+        Ok(response)
+    }
+
+    async fn commit(self: Arc<Self>, request: CommitRequest) -> Result<CommitResponse, Status> {
+        self.finish(&request.transaction_id, TransactionStatement::Commit)?;
+        Ok(CommitResponse::new())
+    }
+
+    async fn rollback(self: Arc<Self>, request: RollbackRequest) -> Result<RollbackResponse, Status> {
+        self.finish(&request.transaction_id, TransactionStatement::Rollback)?;
+        Ok(RollbackResponse::new())
+    }
+
+    async fn listen(self: Arc<Self>, request: ListenRequest) -> Result<BoxStream<Notification>, Status> {
+        if request.channels.iter().all(String::is_empty) {
+            return Err(Status::invalid_argument("No channel given"));
+        }
+        let listener = Listener::new();
+        request.channels.iter().filter(|channel| !channel.is_empty()).for_each(|channel| listener.listen(channel));
+        Ok(Box::pin(Notifications(listener)))
+    }
+}
+
+/// A Listen call's stream. It owns the listener, so the subscription ends
+/// with the call.
+struct Notifications(Listener);
+
+impl tokio_stream::Stream for Notifications {
+    type Item = Result<Notification, Status>;
+
+    fn poll_next(mut self: std::pin::Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.0.poll_recv(cx).map(|notification| {
+            notification.map(|notification| {
+                let mut message = Notification::new();
+                message.channel = notification.channel;
+                message.payload = notification.payload;
+                message.process_id = notification.process_id;
+                Ok(message)
+            })
+        })
+    }
+}
+
+/// A unary RPC handled by `F`
+struct Unary<F, Res>(F, PhantomData<fn() -> Res>);
+
+impl<Req, Res, F, Fut> UnaryService<Req> for Unary<F, Res>
+where
+    F: FnMut(Req) -> Fut,
+    Fut: Future<Output = Result<Res, Status>> + Send + 'static,
+{
+    type Response = Res;
+    type Future = BoxFuture<Response<Res>, Status>;
+
+    fn call(&mut self, request: Request<Req>) -> Self::Future {
+        let response = (self.0)(request.into_inner());
+        Box::pin(async move { response.await.map(Response::new) })
+    }
+}
+
+/// A server-streaming RPC handled by `F`
+struct Streaming<F, Res>(F, PhantomData<fn() -> Res>);
+
+impl<Req, Res, F, Fut> ServerStreamingService<Req> for Streaming<F, Res>
+where
+    F: FnMut(Req) -> Fut,
+    Fut: Future<Output = Result<BoxStream<Res>, Status>> + Send + 'static,
+{
+    type Response = Res;
+    type ResponseStream = BoxStream<Res>;
+    type Future = BoxFuture<Response<BoxStream<Res>>, Status>;
+
+    fn call(&mut self, request: Request<Req>) -> Self::Future {
+        let response = (self.0)(request.into_inner());
+        Box::pin(async move { response.await.map(Response::new) })
+    }
+}
+
+async fn unary<Req, Res, F, Fut>(request: http::Request<BoxBody>, handler: F) -> http::Response<BoxBody>
+where
+    Req: protobuf::Message + Send + 'static,
+    Res: protobuf::Message + Send + 'static,
+    F: FnMut(Req) -> Fut,
+    Fut: Future<Output = Result<Res, Status>> + Send + 'static,
+{
+    let mut grpc = Grpc::new(ProtobufCodec::<Res, Req>::default());
+    grpc.unary(Unary(handler, PhantomData), request).await
+}
+
+async fn server_streaming<Req, Res, F, Fut>(request: http::Request<BoxBody>, handler: F) -> http::Response<BoxBody>
+where
+    Req: protobuf::Message + Send + 'static,
+    Res: protobuf::Message + Send + 'static,
+    F: FnMut(Req) -> Fut,
+    Fut: Future<Output = Result<BoxStream<Res>, Status>> + Send + 'static,
+{
+    let mut grpc = Grpc::new(ProtobufCodec::<Res, Req>::default());
+    grpc.server_streaming(Streaming(handler, PhantomData), request).await
+}
+
+impl Service<http::Request<BoxBody>> for ButterflyService {
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+        let state = Arc::clone(&self.state);
+        Box::pin(async move {
+            let path = request.uri().path().to_string();
+            let method = path.strip_prefix("/butterfly.v1.Butterfly/").unwrap_or("");
+            Ok(match method {
+                "Execute" => unary(request, |r| Arc::clone(&state).execute(r)).await,
+                "Query" => server_streaming(request, |r| Arc::clone(&state).query(r)).await,
+                "Prepare" => unary(request, |r| Arc::clone(&state).prepare(r)).await,
+                "Deallocate" => unary(request, |r| Arc::clone(&state).deallocate(r)).await,
+                "BeginTransaction" => unary(request, |r| Arc::clone(&state).begin(r)).await,
+                "Commit" => unary(request, |r| Arc::clone(&state).commit(r)).await,
+                "Rollback" => unary(request, |r| Arc::clone(&state).rollback(r)).await,
+                "Listen" => server_streaming(request, |r| Arc::clone(&state).listen(r)).await,
+                _ => Status::unimplemented(format!("Unknown method {}", path)).into_http(),
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_stream::StreamExt;
+    use tonic::client::Grpc as Client;
+    use tonic::codegen::http::uri::PathAndQuery;
+    use tonic::transport::Channel;
+
+    async fn connect() -> Client<Channel> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            Server::builder()
+                .add_service(ButterflyService::default())
+                .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener)),
+        );
+        let channel = Channel::from_shared(format!("http://{}", addr)).unwrap().connect().await.unwrap();
+        Client::new(channel)
+    }
+
+    async fn call<Req, Res>(client: &mut Client<Channel>, method: &str, request: Req) -> Result<Res, Status>
+    where
+        Req: protobuf::Message + Send + 'static,
+        Res: protobuf::Message + Send + 'static,
+    {
+        client.ready().await.unwrap();
+        let path = PathAndQuery::try_from(format!("/butterfly.v1.Butterfly/{}", method)).unwrap();
+        let codec = ProtobufCodec::<Req, Res>::default();
+        client.unary(Request::new(request), path, codec).await.map(Response::into_inner)
+    }
+
+    fn sql(text: &str, params: &[Value]) -> ExecuteRequest {
+        let mut request = ExecuteRequest::new();
+        request.set_sql(text.to_string());
+        request.params = params.iter().map(to_proto).collect();
+        request
+    }
+
+    #[tokio::test]
+    async fn test_typed_execute_and_prepare() {
+        let mut client = connect().await;
+        let _: Result<ExecuteResponse, _> = call(&mut client, "Execute", sql("DROP TABLE IF EXISTS grpc_items", &[])).await;
+        let created: ExecuteResponse =
+            call(&mut client, "Execute", sql("CREATE TABLE grpc_items (id INTEGER PRIMARY KEY, name TEXT, price FLOAT)", &[]))
+                .await
+                .unwrap();
+        assert!(created.has_message());
+
+        let params = [Value::Integer(1), Value::Text("pen".to_string()), Value::Float(1.5)];
+        let inserted: ExecuteResponse =
+            call(&mut client, "Execute", sql("INSERT INTO grpc_items VALUES ($1, $2, $3), (2, NULL, 3)", &params))
+                .await
+                .unwrap();
+        assert_eq!(inserted.rows_affected(), 2);
+
+        let selected: ExecuteResponse =
+            call(&mut client, "Execute", sql("SELECT id, name, price FROM grpc_items ORDER BY id", &[])).await.unwrap();
+        let rows = selected.rows();
+        let types: Vec<ColumnType> = rows.columns.iter().map(|c| c.type_.enum_value_or_default()).collect();
+        assert_eq!(
+            types,
+            [ColumnType::COLUMN_TYPE_INTEGER, ColumnType::COLUMN_TYPE_TEXT, ColumnType::COLUMN_TYPE_FLOAT]
+        );
+        let values: Vec<Vec<Value>> = rows.rows.iter().map(|r| r.values.iter().map(from_proto).collect()).collect();
+        assert_eq!(values[0], params);
+        assert_eq!(values[1][1], Value::Null);
+
+        let mut prepare = PrepareRequest::new();
+        prepare.sql = "SELECT name FROM grpc_items WHERE id = $1".to_string();
+        let prepared: PrepareResponse = call(&mut client, "Prepare", prepare).await.unwrap();
+        assert_eq!(prepared.param_count, 1);
+        assert_eq!(prepared.columns[0].name, "name");
+        let mut run = ExecuteRequest::new();
+        run.set_statement_id(prepared.statement_id.clone());
+        run.params = vec![to_proto(&Value::Integer(1))];
+        let found: ExecuteResponse = call(&mut client, "Execute", run).await.unwrap();
+        assert_eq!(from_proto(&found.rows().rows[0].values[0]), Value::Text("pen".to_string()));
+
+        let missing: Status = call::<_, ExecuteResponse>(&mut client, "Execute", sql("SELECT * FROM grpc_missing", &[]))
+            .await
+            .unwrap_err();
+        assert_eq!(missing.code(), Code::NotFound);
+        assert_eq!(missing.metadata().get("sqlstate").unwrap(), "42P01");
+        let syntax = call::<_, ExecuteResponse>(&mut client, "Execute", sql("SELEC 1", &[])).await.unwrap_err();
+        assert_eq!(syntax.code(), Code::InvalidArgument);
+        let _: ExecuteResponse = call(&mut client, "Execute", sql("DROP TABLE grpc_items", &[])).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_query_streams_batches() {
+        let mut client = connect().await;
+        let _: Result<ExecuteResponse, _> = call(&mut client, "Execute", sql("DROP TABLE IF EXISTS grpc_events", &[])).await;
+        let _: ExecuteResponse =
+            call(&mut client, "Execute", sql("CREATE TABLE grpc_events (id INTEGER PRIMARY KEY)", &[])).await.unwrap();
+        let values: Vec<String> = (0..QUERY_BATCH_ROWS + 10).map(|i| format!("({})", i)).collect();
+        let insert = sql(&format!("INSERT INTO grpc_events VALUES {}", values.join(", ")), &[]);
+        let _: ExecuteResponse = call(&mut client, "Execute", insert).await.unwrap();
+
+        client.ready().await.unwrap();
+        let path = PathAndQuery::from_static("/butterfly.v1.Butterfly/Query");
+        let codec = ProtobufCodec::<ExecuteRequest, QueryResponse>::default();
+        let mut stream = client
+            .server_streaming(Request::new(sql("SELECT id FROM grpc_events ORDER BY id", &[])), path, codec)
+            .await
+            .unwrap()
+            .into_inner();
+        let mut messages = Vec::new();
+        while let Some(message) = stream.next().await {
+            messages.push(message.unwrap());
+        }
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].columns[0].name, "id");
+        assert!(messages[1].columns.is_empty());
+        assert_eq!(messages[0].rows.len() + messages[1].rows.len(), QUERY_BATCH_ROWS + 10);
+        assert_eq!(from_proto(&messages[1].rows[9].values[0]), Value::Integer(QUERY_BATCH_ROWS as i64 + 9));
+
+        // A query that cannot run fails the call before any message
+        client.ready().await.unwrap();
+        let path = PathAndQuery::from_static("/butterfly.v1.Butterfly/Query");
+        let codec = ProtobufCodec::<ExecuteRequest, QueryResponse>::default();
+        let missing = client
+            .server_streaming(Request::new(sql("SELECT * FROM grpc_events_missing", &[])), path, codec)
+            .await
+            .unwrap_err();
+        assert_eq!(missing.code(), Code::NotFound);
+
+        let _: ExecuteResponse = call(&mut client, "Execute", sql("DROP TABLE grpc_events", &[])).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_transactions_run_in_a_session_per_id() {
+        let mut client = connect().await;
+        client.ready().await.unwrap();
+        let mut listen = ListenRequest::new();
+        listen.channels = vec!["grpc_tx".to_string()];
+        let path = PathAndQuery::from_static("/butterfly.v1.Butterfly/Listen");
+        let codec = ProtobufCodec::<ListenRequest, Notification>::default();
+        let mut stream = client.server_streaming(Request::new(listen), path, codec).await.unwrap().into_inner();
+
+        // A rolled back transaction's NOTIFY is dropped, a committed one's is sent
+        for (payload, end) in [("dropped", "Rollback"), ("sent", "Commit")] {
+            let begun: BeginTransactionResponse =
+                call(&mut client, "BeginTransaction", BeginTransactionRequest::new()).await.unwrap();
+            let id = begun.transaction_id;
+            let mut notify = sql(&format!("NOTIFY grpc_tx, '{}'", payload), &[]);
+            notify.transaction_id = id.clone();
+            let _: ExecuteResponse = call(&mut client, "Execute", notify).await.unwrap();
+            match end {
+                "Rollback" => {
+                    let mut rollback = RollbackRequest::new();
+                    rollback.transaction_id = id.clone();
+                    let _: RollbackResponse = call(&mut client, "Rollback", rollback).await.unwrap();
+                }
+                _ => {
+                    let mut commit = CommitRequest::new();
+                    commit.transaction_id = id.clone();
+                    let _: CommitResponse = call(&mut client, "Commit", commit).await.unwrap();
+                }
+            }
+            let mut ended = CommitRequest::new();
+            ended.transaction_id = id;
+            let missing = call::<_, CommitResponse>(&mut client, "Commit", ended).await.unwrap_err();
+            assert_eq!(missing.code(), Code::NotFound);
+        }
+        assert_eq!(stream.next().await.unwrap().unwrap().payload, "sent");
+
+        // SQL COMMIT ends the transaction as well
+        let begun: BeginTransactionResponse =
+            call(&mut client, "BeginTransaction", BeginTransactionRequest::new()).await.unwrap();
+        let mut commit = sql("COMMIT", &[]);
+        commit.transaction_id = begun.transaction_id.clone();
+        let _: ExecuteResponse = call(&mut client, "Execute", commit.clone()).await.unwrap();
+        let gone = call::<_, ExecuteResponse>(&mut client, "Execute", commit).await.unwrap_err();
+        assert_eq!(gone.code(), Code::NotFound);
+
+        let mut unknown = sql("SELECT 1", &[]);
+        unknown.transaction_id = "0".to_string();
+        let executed = call::<_, ExecuteResponse>(&mut client, "Execute", unknown).await.unwrap_err();
+        assert_eq!(executed.code(), Code::NotFound);
+        let began = call::<_, ExecuteResponse>(&mut client, "Execute", sql("BEGIN", &[])).await.unwrap_err();
+        assert!(began.message().contains("needs a session"), "{}", began.message());
+    }
+
+    #[tokio::test]
+    async fn test_listen_streams_notifications() {
+        let mut client = connect().await;
+        client.ready().await.unwrap();
+        let mut listen = ListenRequest::new();
+        listen.channels = vec!["grpc_cache".to_string()];
+        let path = PathAndQuery::from_static("/butterfly.v1.Butterfly/Listen");
+        let codec = ProtobufCodec::<ListenRequest, Notification>::default();
+        let mut stream = client.server_streaming(Request::new(listen), path, codec).await.unwrap().into_inner();
+
+        let _: ExecuteResponse = call(&mut client, "Execute", sql("NOTIFY grpc_cache, 'first'", &[])).await.unwrap();
+        let _: ExecuteResponse = call(&mut client, "Execute", sql("NOTIFY grpc_cache, 'second'", &[])).await.unwrap();
+
+        let mut payloads = Vec::new();
+        for _ in 0..2 {
+            let notification = stream.next().await.unwrap().unwrap();
+            assert_eq!(notification.channel, "grpc_cache");
+            payloads.push(notification.payload);
+        }
+        assert_eq!(payloads, ["first", "second"]);
+    }
+}
@@ -0,0 +1,555 @@
+// Result formats for /sql: picked per request from `?format=` or the Accept
+// header, and encoded a batch of rows at a time so large results stream
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use arrow_array::{ArrayRef, BooleanArray, Float64Array, Int64Array, RecordBatch, StringArray};
+use arrow_ipc::writer::StreamWriter;
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+
+use super::HttpRequest;
+use crate::db::executor::RowStream;
+use crate::db::pgwire::{self, types};
+use crate::db::sql::{SqlParser, Statement};
+use crate::db::storage::Value;
+
+/// Rows encoded into each piece of a streamed body
+pub const BATCH_ROWS: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResultFormat {
+    /// The execution result as pretty-printed JSON objects (the default)
+    Json,
+    /// Column names and types once, then each row as a positional array
+    CompactJson,
+    /// One JSON object per row, newline-delimited
+    Ndjson,
+    /// RFC 4180 CSV with a header row
+    Csv,
+    /// Arrow IPC streaming format
+    Arrow,
+}
+
+impl ResultFormat {
+    const ALL: [ResultFormat; 5] = [
+        ResultFormat::Json,
+        ResultFormat::CompactJson,
+        ResultFormat::Ndjson,
+        ResultFormat::Csv,
+        ResultFormat::Arrow,
+    ];
+
+    /// Name used in `?format=`
+    pub fn name(self) -> &'static str {
+        match self {
+            ResultFormat::Json => "json",
+            ResultFormat::CompactJson => "compact",
+            ResultFormat::Ndjson => "ndjson",
+            ResultFormat::Csv => "csv",
+            ResultFormat::Arrow => "arrow",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ResultFormat::Json => "application/json",
+            ResultFormat::CompactJson => "application/vnd.butterfly.compact+json",
+            ResultFormat::Ndjson => "application/x-ndjson",
+            ResultFormat::Csv => "text/csv; charset=utf-8; header=present",
+            ResultFormat::Arrow => "application/vnd.apache.arrow.stream",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|format| format.name().eq_ignore_ascii_case(name))
+    }
+
+    /// Format served for one media range of an Accept header
+    fn from_media_type(media_type: &str) -> Option<Self> {
+        match media_type.to_ascii_lowercase().as_str() {
+            "application/json" | "application/*" | "*/*" => Some(ResultFormat::Json),
+            "application/vnd.butterfly.compact+json" => Some(ResultFormat::CompactJson),
+            "application/x-ndjson" | "application/ndjson" => Some(ResultFormat::Ndjson),
+            "text/csv" => Some(ResultFormat::Csv),
+            "application/vnd.apache.arrow.stream" => Some(ResultFormat::Arrow),
+            _ => None,
+        }
+    }
+}
+
+/// The format a request asks for. `?format=` wins over the Accept header,
+/// whose media ranges are tried in order of preference; no header at all
+/// means the default JSON. Err carries the status and message to answer with.
+pub fn negotiate(request: &HttpRequest) -> Result<ResultFormat, (usize, String)> {
+    if let Some(name) = request.query("format") {
+        return ResultFormat::from_name(&name).ok_or_else(|| (400, format!("Unknown result format '{}'; expected one of {}", name, names())));
+    }
+
+    let accept = match request.header("Accept") {
+        Some(accept) if !accept.trim().is_empty() => accept,
+        _ => return Ok(ResultFormat::Json),
+    };
+    let mut ranges: Vec<(f32, &str)> = accept
+        .split(',')
+        .map(|range| {
+            let mut parts = range.split(';');
+            let media_type = parts.next().unwrap_or("").trim();
+            let quality = parts
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .map_or(1.0, |q| q.trim().parse().unwrap_or(0.0));
+            (quality, media_type)
+        })
+        .filter(|(quality, _)| *quality > 0.0)
+        .collect();
+    // Stable, so equally preferred ranges keep the client's order
+    ranges.sort_by(|a, b| b.0.total_cmp(&a.0));
+    ranges
+        .iter()
+        .find_map(|(_, media_type)| ResultFormat::from_media_type(media_type))
+        .ok_or_else(|| {
+            let available: Vec<&str> = ResultFormat::ALL.iter().map(|format| format.content_type()).collect();
+            (406, format!("None of the accepted media types can be produced; available: {}", available.join(", ")))
+        })
+}
+
+fn names() -> String {
+    ResultFormat::ALL.map(ResultFormat::name).join(", ")
+}
+
+/// Type of a result column as reported by the typed formats
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnType {
+    Integer,
+    Float,
+    Text,
+    Boolean,
+    Date,
+    Timestamp,
+}
+
+impl ColumnType {
+    pub fn name(self) -> &'static str {
+        match self {
+            ColumnType::Integer => "integer",
+            ColumnType::Float => "float",
+            ColumnType::Text => "text",
+            ColumnType::Boolean => "boolean",
+            ColumnType::Date => "date",
+            ColumnType::Timestamp => "timestamp",
+        }
+    }
+
+    fn from_oid(oid: i32) -> Self {
+        match oid {
+            types::INT8 | types::INT4 | types::INT2 => ColumnType::Integer,
+            types::FLOAT8 | types::FLOAT4 => ColumnType::Float,
+            types::BOOL => ColumnType::Boolean,
+            types::DATE => ColumnType::Date,
+            types::TIMESTAMP => ColumnType::Timestamp,
+            _ => ColumnType::Text,
+        }
+    }
+
+    /// Whether `value` can be written as this type without loss
+    fn holds(self, value: &Value) -> bool {
+        matches!(
+            (self, value),
+            (_, Value::Null)
+                | (ColumnType::Integer, Value::Integer(_))
+                | (ColumnType::Float, Value::Float(_) | Value::Integer(_))
+                | (ColumnType::Boolean, Value::Boolean(_))
+                | (ColumnType::Text | ColumnType::Date | ColumnType::Timestamp, Value::Text(_))
+        )
+    }
+
+    fn arrow_type(self) -> DataType {
+        match self {
+            ColumnType::Integer => DataType::Int64,
+            ColumnType::Float => DataType::Float64,
+            ColumnType::Boolean => DataType::Boolean,
+            // Dates and timestamps are stored as text and go out as such
+            ColumnType::Text | ColumnType::Date | ColumnType::Timestamp => DataType::Utf8,
+        }
+    }
+}
+
+/// A row result in column order, with a type for each column
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypedRows {
+    pub columns: Vec<String>,
+    pub types: Vec<ColumnType>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+impl TypedRows {
+    /// Type the rows `sql` returned: from the catalog where the statement's
+    /// tables describe a column, else from its values. A column holding a
+    /// value its type cannot represent is reported as text.
+    pub fn new(sql: &str, columns: Vec<String>, rows: &[HashMap<String, serde_json::Value>]) -> Self {
+        let rows: Vec<Vec<Value>> = rows
+            .iter()
+            .map(|row| {
+                columns
+                    .iter()
+                    .map(|c| row.get(c).and_then(|v| Value::from_json(v).ok()).unwrap_or(Value::Null))
+                    .collect()
+            })
+            .collect();
+        let stmt = SqlParser::parse(sql).ok().and_then(|statements| statements.into_iter().next());
+        Self::typed(stmt.as_ref(), columns, rows)
+    }
+
+    fn typed(stmt: Option<&Statement>, columns: Vec<String>, rows: Vec<Vec<Value>>) -> Self {
+        let oids = match stmt {
+            Some(stmt) => pgwire::column_types(stmt, &columns, &rows),
+            None => (0..columns.len())
+                .map(|i| rows.iter().find_map(|row| types::oid_for_value(&row[i])).unwrap_or(types::TEXT))
+                .collect(),
+        };
+        let types = oids
+            .into_iter()
+            .enumerate()
+            .map(|(i, oid)| match ColumnType::from_oid(oid) {
+                column_type if rows.iter().all(|row| column_type.holds(&row[i])) => column_type,
+                _ => ColumnType::Text,
+            })
+            .collect();
+        TypedRows { columns, types, rows }
+    }
+
+    /// Encode in `format` (any but the default JSON), yielding the body a
+    /// batch of rows at a time
+    pub fn encode(self, format: ResultFormat) -> Encoder {
+        let rows = self.rows.into_iter().map(Ok);
+        Self::encoder(self.columns, self.types, Box::new(rows), format)
+    }
+
+    /// Encode the rows of `stmt` in `format` as they are pulled from
+    /// `stream`, so the result is never held whole. The columns are typed
+    /// from the first batch, which is read here: a query that fails before
+    /// producing it fails here, before anything is sent.
+    pub fn stream(stmt: &Statement, mut stream: RowStream, format: ResultFormat) -> Result<Encoder, String> {
+        let first = stream.by_ref().take(BATCH_ROWS).collect::<Result<Vec<_>, _>>()?;
+        let TypedRows { columns, types, rows } = Self::typed(Some(stmt), stream.columns().to_vec(), first);
+        let rows = rows.into_iter().map(Ok).chain(stream);
+        Ok(Self::encoder(columns, types, Box::new(rows), format))
+    }
+
+    fn encoder(columns: Vec<String>, types: Vec<ColumnType>, rows: Rows, format: ResultFormat) -> Encoder {
+        let schema = Arc::new(Schema::new(
+            columns
+                .iter()
+                .zip(&types)
+                .map(|(name, column_type)| Field::new(name, column_type.arrow_type(), true))
+                .collect::<Vec<_>>(),
+        ));
+        Encoder {
+            format,
+            columns,
+            types,
+            rows,
+            schema,
+            arrow: None,
+            written: 0,
+            started: false,
+            finished: false,
+        }
+    }
+}
+
+/// Rows in column order, pulled as they are encoded
+type Rows = Box<dyn Iterator<Item = Result<Vec<Value>, String>> + Send>;
+
+/// Iterator over the pieces of an encoded body: the first carries any
+/// header, the last any trailer
+pub struct Encoder {
+    format: ResultFormat,
+    columns: Vec<String>,
+    types: Vec<ColumnType>,
+    rows: Rows,
+    schema: SchemaRef,
+    arrow: Option<StreamWriter<Vec<u8>>>,
+    /// Rows encoded so far
+    written: usize,
+    started: bool,
+    finished: bool,
+}
+
+impl Iterator for Encoder {
+    type Item = Result<Vec<u8>, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        let mut out = Vec::new();
+        let result = (|| {
+            if !self.started {
+                self.started = true;
+                self.head(&mut out)?;
+            }
+            let batch = self.rows.by_ref().take(BATCH_ROWS).collect::<Result<Vec<_>, _>>()?;
+            if !batch.is_empty() {
+                self.batch(&batch, &mut out)?;
+            }
+            // A short batch is the last, so the trailer rides along with it
+            if batch.len() < BATCH_ROWS {
+                self.finished = true;
+                self.tail(&mut out)?;
+            }
+            Ok(())
+        })();
+        match result {
+            Ok(()) => Some(Ok(out)),
+            Err(e) => {
+                self.finished = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+impl Encoder {
+    fn head(&mut self, out: &mut Vec<u8>) -> Result<(), String> {
+        match self.format {
+            ResultFormat::CompactJson => {
+                let columns: Vec<serde_json::Value> = self
+                    .columns
+                    .iter()
+                    .zip(&self.types)
+                    .map(|(name, column_type)| serde_json::json!({ "name": name, "type": column_type.name() }))
+                    .collect();
+                out.extend(format!(r#"{{"columns":{},"rows":["#, serde_json::Value::Array(columns)).into_bytes());
+            }
+            ResultFormat::Csv => {
+                let header: Vec<String> = self.columns.iter().map(|name| csv_field(name)).collect();
+                out.extend(header.join(",").into_bytes());
+                out.extend(b"\r\n");
+            }
+            ResultFormat::Arrow => {
+                let mut writer = StreamWriter::try_new(Vec::new(), &self.schema).map_err(|e| format!("Arrow encoding failed: {}", e))?;
+                out.append(writer.get_mut());
+                self.arrow = Some(writer);
+            }
+            ResultFormat::Json | ResultFormat::Ndjson => {}
+        }
+        Ok(())
+    }
+
+    fn batch(&mut self, rows: &[Vec<Value>], out: &mut Vec<u8>) -> Result<(), String> {
+        match self.format {
+            ResultFormat::CompactJson => {
+                for row in rows {
+                    if self.written > 0 {
+                        out.push(b',');
+                    }
+                    let values: Vec<serde_json::Value> = row.iter().map(json_value).collect();
+                    out.extend(serde_json::Value::Array(values).to_string().into_bytes());
+                    self.written += 1;
+                }
+            }
+            ResultFormat::Ndjson | ResultFormat::Json => {
+                // Built by hand so keys keep column order
+                for row in rows {
+                    out.push(b'{');
+                    for (i, (name, value)) in self.columns.iter().zip(row).enumerate() {
+                        if i > 0 {
+                            out.push(b',');
+                        }
+                        out.extend(format!("{}:{}", serde_json::Value::from(name.as_str()), json_value(value)).into_bytes());
+                    }
+                    out.extend(b"}\n");
+                    self.written += 1;
+                }
+            }
+            ResultFormat::Csv => {
+                for row in rows {
+                    let fields: Vec<String> = row.iter().map(csv_value).collect();
+                    out.extend(fields.join(",").into_bytes());
+                    out.extend(b"\r\n");
+                    self.written += 1;
+                }
+            }
+            ResultFormat::Arrow => {
+                let batch = record_batch(&self.schema, &self.types, rows)?;
+                let writer = self.arrow.as_mut().ok_or("Arrow stream was not started")?;
+                writer.write(&batch).map_err(|e| format!("Arrow encoding failed: {}", e))?;
+                out.append(writer.get_mut());
+                self.written += rows.len();
+            }
+        }
+        Ok(())
+    }
+
+    fn tail(&mut self, out: &mut Vec<u8>) -> Result<(), String> {
+        match self.format {
+            ResultFormat::CompactJson => out.extend(b"]}"),
+            ResultFormat::Arrow => {
+                let mut writer = self.arrow.take().ok_or("Arrow stream was not started")?;
+                writer.finish().map_err(|e| format!("Arrow encoding failed: {}", e))?;
+                out.append(writer.get_mut());
+            }
+            ResultFormat::Json | ResultFormat::Ndjson | ResultFormat::Csv => {}
+        }
+        Ok(())
+    }
+}
+
+fn json_value(value: &Value) -> serde_json::Value {
+    match value {
+        Value::Null => serde_json::Value::Null,
+        Value::Integer(i) => serde_json::Value::from(*i),
+        // Non-finite floats have no JSON form and come out as null
+        Value::Float(f) => serde_json::Value::from(*f),
+        Value::Text(s) => serde_json::Value::from(s.as_str()),
+        Value::Boolean(b) => serde_json::Value::from(*b),
+    }
+}
+
+/// A field quoted if it holds a separator, quote or line break
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+/// NULL is an empty field; an empty string is quoted to tell the two apart
+fn csv_value(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::Text(s) if s.is_empty() => "\"\"".to_string(),
+        Value::Text(s) => csv_field(s),
+        other => other.to_string(),
+    }
+}
+
+fn record_batch(schema: &SchemaRef, types: &[ColumnType], rows: &[Vec<Value>]) -> Result<RecordBatch, String> {
+    let arrays: Vec<ArrayRef> = types
+        .iter()
+        .enumerate()
+        .map(|(i, column_type)| -> ArrayRef {
+            let values = rows.iter().map(|row| &row[i]);
+            match column_type {
+                ColumnType::Integer => Arc::new(Int64Array::from_iter(values.map(|v| match v {
+                    Value::Integer(i) => Some(*i),
+                    _ => None,
+                }))),
+                ColumnType::Float => Arc::new(Float64Array::from_iter(values.map(|v| match v {
+                    Value::Float(f) => Some(*f),
+                    Value::Integer(i) => Some(*i as f64),
+                    _ => None,
+                }))),
+                ColumnType::Boolean => Arc::new(BooleanArray::from_iter(values.map(|v| match v {
+                    Value::Boolean(b) => Some(*b),
+                    _ => None,
+                }))),
+                ColumnType::Text | ColumnType::Date | ColumnType::Timestamp => Arc::new(StringArray::from_iter(values.map(|v| match v {
+                    Value::Null => None,
+                    other => Some(other.to_string()),
+                }))),
+            }
+        })
+        .collect();
+    RecordBatch::try_new(schema.clone(), arrays).map_err(|e| format!("Arrow encoding failed: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::Array;
+    use arrow_ipc::reader::StreamReader;
+
+    fn request(path: &str, accept: Option<&str>) -> HttpRequest {
+        HttpRequest {
+            method: "POST".to_string(),
+            path: path.to_string(),
+            version: "HTTP/1.1".to_string(),
+            headers: accept.map(|a| ("Accept".to_string(), a.to_string())).into_iter().collect(),
+            body: Vec::new(),
+        }
+    }
+
+    fn rows() -> TypedRows {
+        TypedRows {
+            columns: vec!["id".to_string(), "name".to_string(), "score".to_string()],
+            types: vec![ColumnType::Integer, ColumnType::Text, ColumnType::Float],
+            rows: vec![
+                vec![Value::Integer(1), Value::Text("a,\"b\"".to_string()), Value::Float(1.5)],
+                vec![Value::Integer(2), Value::Text(String::new()), Value::Null],
+            ],
+        }
+    }
+
+    fn encode(rows: TypedRows, format: ResultFormat) -> Vec<u8> {
+        rows.encode(format).collect::<Result<Vec<_>, _>>().unwrap().concat()
+    }
+
+    #[test]
+    fn test_negotiation_prefers_query_then_accept() {
+        assert_eq!(negotiate(&request("/sql", None)), Ok(ResultFormat::Json));
+        assert_eq!(negotiate(&request("/sql?x=1&format=csv", Some("application/json"))), Ok(ResultFormat::Csv));
+        assert_eq!(negotiate(&request("/sql?format=yaml", None)).unwrap_err().0, 400);
+        assert_eq!(negotiate(&request("/sql", Some("application/x-ndjson"))), Ok(ResultFormat::Ndjson));
+        assert_eq!(
+            negotiate(&request("/sql", Some("text/csv;q=0.5, application/vnd.apache.arrow.stream"))),
+            Ok(ResultFormat::Arrow)
+        );
+        assert_eq!(negotiate(&request("/sql", Some("text/html, */*;q=0.1"))), Ok(ResultFormat::Json));
+        assert_eq!(negotiate(&request("/sql", Some("text/html, text/csv;q=0"))).unwrap_err().0, 406);
+    }
+
+    #[test]
+    fn test_text_formats_keep_column_order_and_quote() {
+        let compact: serde_json::Value = serde_json::from_slice(&encode(rows(), ResultFormat::CompactJson)).unwrap();
+        assert_eq!(compact["columns"][2], serde_json::json!({ "name": "score", "type": "float" }));
+        assert_eq!(compact["rows"], serde_json::json!([[1, "a,\"b\"", 1.5], [2, "", null]]));
+
+        let ndjson = String::from_utf8(encode(rows(), ResultFormat::Ndjson)).unwrap();
+        assert_eq!(ndjson, "{\"id\":1,\"name\":\"a,\\\"b\\\"\",\"score\":1.5}\n{\"id\":2,\"name\":\"\",\"score\":null}\n");
+
+        let csv = String::from_utf8(encode(rows(), ResultFormat::Csv)).unwrap();
+        assert_eq!(csv, "id,name,score\r\n1,\"a,\"\"b\"\"\",1.5\r\n2,\"\",\r\n");
+    }
+
+    #[test]
+    fn test_arrow_stream_reads_back() {
+        let bytes = encode(rows(), ResultFormat::Arrow);
+        let reader = StreamReader::try_new(&bytes[..], None).unwrap();
+        let batches: Vec<RecordBatch> = reader.map(Result::unwrap).collect();
+        assert_eq!(batches.len(), 1);
+        let batch = &batches[0];
+        assert_eq!(batch.schema().field(1).data_type(), &DataType::Utf8);
+        let ids = batch.column(0).as_any().downcast_ref::<Int64Array>().unwrap();
+        assert_eq!(ids.values(), &[1, 2]);
+        let scores = batch.column(2).as_any().downcast_ref::<Float64Array>().unwrap();
+        assert!(scores.is_null(1));
+    }
+
+    #[test]
+    fn test_large_results_encode_in_batches() {
+        let mut large = rows();
+        large.rows = (0..BATCH_ROWS as i64 * 2 + 1)
+            .map(|i| vec![Value::Integer(i), Value::Text(i.to_string()), Value::Null])
+            .collect();
+        let chunks: Vec<Vec<u8>> = large.clone().encode(ResultFormat::CompactJson).map(Result::unwrap).collect();
+        assert_eq!(chunks.len(), 3);
+        let compact: serde_json::Value = serde_json::from_slice(&chunks.concat()).unwrap();
+        assert_eq!(compact["rows"].as_array().unwrap().len(), BATCH_ROWS * 2 + 1);
+
+        let bytes = encode(large, ResultFormat::Arrow);
+        let reader = StreamReader::try_new(&bytes[..], None).unwrap();
+        assert_eq!(reader.map(|batch| batch.unwrap().num_rows()).sum::<usize>(), BATCH_ROWS * 2 + 1);
+    }
+
+    #[test]
+    fn test_mixed_columns_are_reported_as_text() {
+        let rows = vec![
+            HashMap::from([("v".to_string(), serde_json::json!(1))]),
+            HashMap::from([("v".to_string(), serde_json::json!("x"))]),
+        ];
+        let typed = TypedRows::new("SELECT 1 AS v", vec!["v".to_string()], &rows);
+        assert_eq!(typed.types, [ColumnType::Text]);
+    }
+}
@@ -1,128 +1,906 @@
-use std::{
-    fmt::Display,
-    io::{Error, Read, Write},
-    net::TcpStream,
-};
+// HTTP/1.1 front end - request framing, keep-alive and the SQL endpoints
+pub mod format;
+pub mod pagination;
 
-use crate::db::sql::execute_sql;
+use std::{fmt::Display, io::Error, time::Duration};
+
+use serde::Deserialize;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::mpsc;
+
+use crate::db::cdc;
 use crate::db::executor::ExecutionResult;
+use crate::db::http::format::{ResultFormat, TypedRows};
+use crate::db::http::pagination::Page;
+use crate::db::live;
+use crate::db::notify::Listener;
+use crate::db::pool::{ConnectionGuard, POOL};
+use crate::db::session::cursor::Snapshot;
+use crate::db::session::{PreparedStatement, Session};
+use crate::db::sql::SqlParser;
+use crate::db::storage::Value;
+use crate::{error, info};
+
+/// Size and time limits applied to every connection
+#[derive(Debug, Clone)]
+pub struct Limits {
+    /// Request line plus headers
+    pub max_header_bytes: usize,
+    pub max_body_bytes: usize,
+    /// How long a request may take to arrive once it has started; idle
+    /// time between requests is governed by the connection pool
+    pub request_timeout: Duration,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_header_bytes: 16 * 1024,
+            max_body_bytes: 16 * 1024 * 1024,
+            request_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpRequest {
+    pub method: String,
+    pub path: String,
+    pub version: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpRequest {
+    /// First value of header `name` (case-insensitive)
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Whether the client wants the connection kept open after the response:
+    /// the default for HTTP/1.1, opt-in for HTTP/1.0
+    pub fn keep_alive(&self) -> bool {
+        let connection = self.header("Connection").map(str::to_ascii_lowercase);
+        let has = |token: &str| connection.as_deref().is_some_and(|c| c.split(',').any(|t| t.trim() == token));
+        match self.version.as_str() {
+            "HTTP/1.1" => !has("close"),
+            _ => has("keep-alive"),
+        }
+    }
+
+    /// Path without the query string
+    pub fn route(&self) -> &str {
+        self.path.split('?').next().unwrap_or("")
+    }
+
+    /// First value of query parameter `name`, percent-decoded
+    pub fn query(&self, name: &str) -> Option<String> {
+        self.query_all(name).into_iter().next()
+    }
+
+    /// Every value of query parameter `name`, percent-decoded
+    pub fn query_all(&self, name: &str) -> Vec<String> {
+        let query = self.path.split_once('?').map_or("", |(_, query)| query);
+        query
+            .split('&')
+            .map(|pair| pair.split_once('=').unwrap_or((pair, "")))
+            .filter(|(key, _)| percent_decode(key) == name)
+            .map(|(_, value)| percent_decode(value))
+            .collect()
+    }
+}
+
+/// Decode a query string component: `+` is a space and `%XX` a byte.
+/// Malformed escapes are kept as they are.
+fn percent_decode(component: &str) -> String {
+    let bytes = component.as_bytes();
+    let hex = |at: usize| {
+        bytes
+            .get(at..at + 2)
+            .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok())
+    };
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match (bytes[i], hex(i + 1)) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 2;
+            }
+            (b'+', _) => decoded.push(b' '),
+            (byte, _) => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Why a request could not be read
+#[derive(Debug)]
+pub enum RequestError {
+    /// The connection failed or closed in the middle of a request
+    Io(Error),
+    /// The request is malformed or over a limit; it is answered with
+    /// `status` and the connection closed
+    Rejected { status: usize, message: String },
+}
+
+impl From<Error> for RequestError {
+    fn from(e: Error) -> Self {
+        RequestError::Io(e)
+    }
+}
+
+fn rejected(status: usize, message: impl Into<String>) -> RequestError {
+    RequestError::Rejected {
+        status,
+        message: message.into(),
+    }
+}
+
+/// A body produced piece by piece as it is written out
+pub enum BodyStream {
+    /// A body of known extent; an Err ends the response early
+    Chunks(Box<dyn Iterator<Item = Result<Vec<u8>, String>> + Send>),
+    /// Server-sent events, until the source ends or the client goes away
+    Events(EventSource),
+}
+
+impl std::fmt::Debug for BodyStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BodyStream::Chunks(_) => f.write_str("BodyStream::Chunks"),
+            BodyStream::Events(_) => f.write_str("BodyStream::Events"),
+        }
+    }
+}
+
+/// Where the events of a `text/event-stream` response come from
+pub enum EventSource {
+    /// A live query's snapshot and deltas
+    Live(mpsc::Receiver<live::Event>),
+    /// Notifications on the channels the listener is on
+    Notifications(Listener),
+}
+
+impl EventSource {
+    /// The next event, framed; None once the source has ended
+    async fn next(&mut self) -> Option<String> {
+        let (name, data) = match self {
+            EventSource::Live(events) => {
+                let event = events.recv().await?;
+                (event.name(), event.data())
+            }
+            EventSource::Notifications(listener) => {
+                let notification = listener.recv().await?;
+                ("notification", serde_json::json!(notification))
+            }
+        };
+        Some(format!("event: {}\ndata: {}\n\n", name, data))
+    }
+}
 
 #[derive(Debug)]
 pub struct HttpResponse {
     pub status_code: usize,
     pub protocol: String,
-    pub headers: String,
+    pub headers: Vec<(String, String)>,
     pub body: String,
+    /// Streamed body, sent instead of `body` and framed when written
+    pub stream: Option<BodyStream>,
 }
 
 impl Display for HttpResponse {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{} {}\r\n{}\r\n\r\n{}",
-            self.protocol, self.status_code, self.headers, self.body
-        )
+        write!(f, "{} {} {}\r\n", self.protocol, self.status_code, reason_phrase(self.status_code))?;
+        for (name, value) in &self.headers {
+            write!(f, "{}: {}\r\n", name, value)?;
+        }
+        write!(f, "\r\n{}", self.body)
     }
 }
 
 impl HttpResponse {
-    fn json(status_code: usize, body: String) -> Self {
+    fn new(status_code: usize, content_type: &str, body: String) -> Self {
         HttpResponse {
             status_code,
             protocol: "HTTP/1.1".to_string(),
-            headers: format!(
-                "Content-Type: application/json\r\nContent-Length: {}",
-                body.len()
-            ),
+            headers: vec![
+                ("Content-Type".to_string(), content_type.to_string()),
+                ("Content-Length".to_string(), body.len().to_string()),
+            ],
             body,
+            stream: None,
         }
     }
 
-    fn text(status_code: usize, body: String) -> Self {
+    /// A response whose body is streamed from `chunks`
+    fn streamed(status_code: usize, content_type: &str, chunks: impl Iterator<Item = Result<Vec<u8>, String>> + Send + 'static) -> Self {
         HttpResponse {
             status_code,
             protocol: "HTTP/1.1".to_string(),
-            headers: format!(
-                "Content-Type: text/plain\r\nContent-Length: {}",
-                body.len()
-            ),
-            body,
+            headers: vec![("Content-Type".to_string(), content_type.to_string())],
+            body: String::new(),
+            stream: Some(BodyStream::Chunks(Box::new(chunks))),
+        }
+    }
+
+    /// A `text/event-stream` response carrying the events of `source`
+    fn events(source: EventSource) -> Self {
+        HttpResponse {
+            status_code: 200,
+            protocol: "HTTP/1.1".to_string(),
+            headers: vec![
+                ("Content-Type".to_string(), "text/event-stream".to_string()),
+                ("Cache-Control".to_string(), "no-cache".to_string()),
+            ],
+            body: String::new(),
+            stream: Some(BodyStream::Events(source)),
+        }
+    }
+
+    fn json(status_code: usize, body: String) -> Self {
+        Self::new(status_code, "application/json", body)
+    }
+
+    fn text(status_code: usize, body: String) -> Self {
+        Self::new(status_code, "text/plain", body)
+    }
+
+    fn error(status_code: usize, message: &str) -> Self {
+        Self::json(status_code, serde_json::json!({ "error": message }).to_string())
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+/// Standard reason phrase of a status code
+pub fn reason_phrase(status_code: usize) -> &'static str {
+    match status_code {
+        100 => "Continue",
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        406 => "Not Acceptable",
+        408 => "Request Timeout",
+        411 => "Length Required",
+        413 => "Content Too Large",
+        414 => "URI Too Long",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        503 => "Service Unavailable",
+        505 => "HTTP Version Not Supported",
+        _ => "Unknown",
+    }
+}
+
+/// Read one line ending in LF, counting it against `budget`. The line is
+/// returned without its line ending; None means the stream ended first.
+async fn read_line<R: AsyncBufRead + Unpin>(reader: &mut R, budget: &mut usize, too_long: usize) -> Result<Option<String>, RequestError> {
+    let mut line = Vec::new();
+    let read = (&mut *reader).take(*budget as u64 + 1).read_until(b'\n', &mut line).await?;
+    if read == 0 {
+        return Ok(None);
+    }
+    if !line.ends_with(b"\n") {
+        if read > *budget {
+            return Err(rejected(too_long, "Request header is too large"));
+        }
+        return Err(RequestError::Io(Error::new(std::io::ErrorKind::UnexpectedEof, "connection closed mid-line")));
+    }
+    *budget -= read;
+    line.pop();
+    if line.ends_with(b"\r") {
+        line.pop();
+    }
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|_| rejected(400, "Request header is not valid UTF-8"))
+}
+
+/// Read the next request off a connection. Returns None if the client closed
+/// the connection between requests. `on_continue` is awaited before the body
+/// is read when the client sent `Expect: 100-continue`.
+pub async fn read_request<R, F, Fut>(reader: &mut R, limits: &Limits, on_continue: F) -> Result<Option<HttpRequest>, RequestError>
+where
+    R: AsyncBufRead + Unpin,
+    F: FnOnce() -> Fut,
+    Fut: std::future::Future<Output = Result<(), Error>>,
+{
+    let mut budget = limits.max_header_bytes;
+
+    // Tolerate blank lines before the request line (RFC 9112 section 2.2)
+    let request_line = loop {
+        match read_line(reader, &mut budget, 414).await? {
+            None => return Ok(None),
+            Some(line) if line.is_empty() => continue,
+            Some(line) => break line,
+        }
+    };
+    let (method, path, version) = match request_line.split(' ').collect::<Vec<_>>()[..] {
+        [method, path, version] if !method.is_empty() && !path.is_empty() => (method, path, version),
+        _ => return Err(rejected(400, "Malformed request line")),
+    };
+    if !version.starts_with("HTTP/") {
+        return Err(rejected(400, "Malformed request line"));
+    }
+    if version != "HTTP/1.1" && version != "HTTP/1.0" {
+        return Err(rejected(505, format!("Unsupported protocol version {}", version)));
+    }
+
+    let mut headers = Vec::new();
+    loop {
+        let line = read_line(reader, &mut budget, 431)
+            .await?
+            .ok_or_else(|| RequestError::Io(Error::new(std::io::ErrorKind::UnexpectedEof, "connection closed in headers")))?;
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with([' ', '\t']) {
+            return Err(rejected(400, "Folded header lines are not supported"));
+        }
+        let (name, value) = line.split_once(':').ok_or_else(|| rejected(400, "Malformed header line"))?;
+        if name.is_empty() || name.ends_with([' ', '\t']) {
+            return Err(rejected(400, "Malformed header line"));
+        }
+        headers.push((name.to_string(), value.trim().to_string()));
+    }
+
+    let mut request = HttpRequest {
+        method: method.to_string(),
+        path: path.to_string(),
+        version: version.to_string(),
+        headers,
+        body: Vec::new(),
+    };
+
+    let lengths: Vec<&str> = request
+        .headers
+        .iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("Content-Length"))
+        .map(|(_, value)| value.as_str())
+        .collect();
+    let chunked = match request.header("Transfer-Encoding") {
+        None => false,
+        Some(encoding) if encoding.eq_ignore_ascii_case("chunked") => true,
+        Some(encoding) => return Err(rejected(501, format!("Unsupported transfer encoding '{}'", encoding))),
+    };
+    // Both framings at once is a classic request smuggling vector
+    if chunked && !lengths.is_empty() {
+        return Err(rejected(400, "Both Content-Length and Transfer-Encoding given"));
+    }
+    let length = match lengths.first() {
+        None => None,
+        Some(first) => {
+            if lengths.iter().any(|length| length != first) {
+                return Err(rejected(400, "Conflicting Content-Length headers"));
+            }
+            if !first.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(rejected(400, "Invalid Content-Length"));
+            }
+            Some(first.parse::<usize>().map_err(|_| rejected(413, "Request body is too large"))?)
+        }
+    };
+    if length.is_some_and(|length| length > limits.max_body_bytes) {
+        return Err(rejected(413, "Request body is too large"));
+    }
+
+    let has_body = chunked || length.is_some_and(|length| length > 0);
+    if has_body && request.header("Expect").is_some_and(|e| e.eq_ignore_ascii_case("100-continue")) {
+        on_continue().await?;
+    }
+
+    if chunked {
+        request.body = read_chunked_body(reader, limits).await?;
+    } else if let Some(length) = length {
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).await?;
+        request.body = body;
+    }
+    Ok(Some(request))
+}
+
+/// Decode a chunked body, discarding chunk extensions and trailers
+async fn read_chunked_body<R: AsyncBufRead + Unpin>(reader: &mut R, limits: &Limits) -> Result<Vec<u8>, RequestError> {
+    let closed = || RequestError::Io(Error::new(std::io::ErrorKind::UnexpectedEof, "connection closed in chunked body"));
+    let mut body = Vec::new();
+    loop {
+        let mut budget = limits.max_header_bytes;
+        let line = read_line(reader, &mut budget, 400).await?.ok_or_else(closed)?;
+        let size = line.split(';').next().unwrap_or("").trim();
+        let size = usize::from_str_radix(size, 16).map_err(|_| rejected(400, "Invalid chunk size"))?;
+        if size == 0 {
+            break;
+        }
+        if body.len() + size > limits.max_body_bytes {
+            return Err(rejected(413, "Request body is too large"));
+        }
+        let start = body.len();
+        body.resize(start + size, 0);
+        reader.read_exact(&mut body[start..]).await?;
+        match read_line(reader, &mut budget, 400).await?.ok_or_else(closed)? {
+            line if line.is_empty() => {}
+            _ => return Err(rejected(400, "Chunk is longer than its size")),
         }
     }
+
+    let mut budget = limits.max_header_bytes;
+    while !read_line(reader, &mut budget, 431).await?.ok_or_else(closed)?.is_empty() {}
+    Ok(body)
+}
+
+/// JSON body accepted by /sql: `{"sql": "... WHERE id = $1", "params": [42]}`.
+/// With `page_size`, rows come back that many at a time, each page with a
+/// token that `{"continuation": "..."}` exchanges for the page after it.
+#[derive(Debug, Default, Deserialize)]
+struct SqlRequest {
+    #[serde(default)]
+    sql: String,
+    #[serde(default)]
+    params: Vec<serde_json::Value>,
+    page_size: Option<usize>,
+    continuation: Option<String>,
+}
+
+/// Parse a /sql request body. Plain text is the SQL itself; a JSON object
+/// carries the SQL plus parameter values and paging options.
+fn sql_request(body: &str) -> Result<SqlRequest, String> {
+    if !body.starts_with('{') {
+        return Ok(SqlRequest {
+            sql: body.to_string(),
+            ..SqlRequest::default()
+        });
+    }
+    serde_json::from_str(body).map_err(|e| format!("Invalid JSON request body: {}", e))
 }
 
-/// Extract HTTP request body from raw request
-fn extract_body(request: &str) -> String {
-    // Find the blank line that separates headers from body
-    if let Some(pos) = request.find("\r\n\r\n") {
-        request[pos + 4..].trim_matches('\0').trim().to_string()
-    } else if let Some(pos) = request.find("\n\n") {
-        request[pos + 2..].trim_matches('\0').trim().to_string()
+/// Run a stateless request's SQL in a session of its own
+fn execute_sql(sql: &str, params: &[Value]) -> ExecutionResult {
+    let mut session = Session::stateless();
+    if params.is_empty() {
+        session.execute_sql(sql)
     } else {
-        String::new()
+        session.execute_with_params(sql, params)
     }
 }
 
-pub async fn handle_client(mut stream: TcpStream) -> Result<(), Error> {
-    let mut buffer = [0; 8192]; // Larger buffer for SQL queries
-    let bytes_read = stream.read(&mut buffer)?;
-    
-    let request_as_str = String::from_utf8_lossy(&buffer[..bytes_read]);
-    let first_request_line = request_as_str.lines().next().unwrap_or("");
+/// Run a /sql request, or read the page of an earlier result it continues
+fn run_sql_request(request: SqlRequest) -> Page {
+    let failed = |message: String| Page {
+        sql: request.sql.clone(),
+        result: ExecutionResult::Error { message },
+        continuation: None,
+    };
+    if request.page_size == Some(0) {
+        return failed("page_size must be at least 1".to_string());
+    }
+    if let Some(continuation) = &request.continuation {
+        return pagination::next_page(continuation, request.page_size).unwrap_or_else(failed);
+    }
+    if request.sql.trim().is_empty() {
+        return failed("No SQL query provided".to_string());
+    }
+    let params = match request.params.iter().map(Value::from_json).collect::<Result<Vec<_>, _>>() {
+        Ok(params) => params,
+        Err(message) => return failed(message),
+    };
 
-    let response = match parse_request_line(first_request_line) {
-        Some((method, path)) => {
-            match (method, path) {
-                (_, "/heart-beat") => {
-                    HttpResponse::text(200, "OK\n".to_string())
-                }
-                (_, "/ping") => {
-                    HttpResponse::text(200, "pong\n".to_string())
-                }
-                ("POST", "/sql") | ("GET", "/sql") => {
-                    // Extract SQL from request body
-                    let sql = extract_body(&request_as_str);
-                    
-                    if sql.is_empty() {
-                        HttpResponse::json(400, r#"{"error":"No SQL query provided. Send SQL in request body."}"#.to_string())
-                    } else {
-                        // Execute the SQL query
-                        let result = execute_sql(&sql);
-                        let status = match &result {
-                            ExecutionResult::Error { .. } => 400,
-                            _ => 200,
-                        };
-                        HttpResponse::json(status, result.to_json())
-                    }
-                }
-                (_, "/tables") => {
-                    // List all tables
-                    match crate::db::catalog::CATALOG.list_tables() {
-                        Ok(tables) => {
-                            let json = serde_json::json!({
-                                "tables": tables
-                            });
-                            HttpResponse::json(200, json.to_string())
-                        }
-                        Err(e) => {
-                            HttpResponse::json(400, format!(r#"{{"error":"{}"}}"#, e))
-                        }
-                    }
-                }
-                _ => {
-                    HttpResponse::json(404, r#"{"error":"Not Found"}"#.to_string())
+    match (request.page_size, execute_sql(&request.sql, &params)) {
+        (Some(page_size), ExecutionResult::Rows { columns, rows }) => {
+            pagination::first_page(&request.sql, Snapshot::new(columns, rows), page_size)
+        }
+        (_, result) => Page {
+            sql: request.sql,
+            result,
+            continuation: None,
+        },
+    }
+}
+
+/// Answer a query whose rows go out in a typed format, encoded as its plan
+/// produces them so the result is never held whole. None for requests that
+/// need the whole result first (the default format and paging) and for SQL
+/// that is not a single query; those run through `run_sql_request`, which
+/// also reports any error in them.
+fn streamed_sql_response(format: ResultFormat, request: &SqlRequest) -> Option<HttpResponse> {
+    if format == ResultFormat::Json || request.page_size.is_some() || request.continuation.is_some() {
+        return None;
+    }
+    let params = request.params.iter().map(Value::from_json).collect::<Result<Vec<_>, _>>().ok()?;
+    let stmt = SqlParser::parse_statement(&request.sql).ok()?;
+    let stmt = match params.is_empty() {
+        true => stmt,
+        false => PreparedStatement::new(stmt, Vec::new()).and_then(|prepared| prepared.bind(&params)).ok()?,
+    };
+    let response = match Session::stateless().stream(&stmt)?.and_then(|rows| TypedRows::stream(&stmt, rows, format)) {
+        Ok(encoder) => HttpResponse::streamed(200, format.content_type(), encoder),
+        Err(message) => HttpResponse::error(400, &message),
+    };
+    Some(response.with_header("Vary", "Accept"))
+}
+
+/// Answer a /sql request with a page of results in `format`. Rows stream in
+/// the negotiated format; other results and errors go out as compact JSON,
+/// except in the default format, which keeps the result's pretty form. The
+/// next page's token is sent in a header, and in the default format also in
+/// the result itself.
+fn sql_response(format: ResultFormat, page: Page) -> HttpResponse {
+    let Page { sql, result, continuation } = page;
+    let response = match (format, result) {
+        (ResultFormat::Json, result) => {
+            let status = match &result {
+                ExecutionResult::Error { .. } => 400,
+                _ => 200,
+            };
+            let body = match (&continuation, serde_json::to_value(&result)) {
+                (Some(token), Ok(mut json)) => {
+                    json["Rows"]["continuation"] = serde_json::Value::from(token.as_str());
+                    serde_json::to_string_pretty(&json).unwrap_or_else(|_| result.to_json())
                 }
+                _ => result.to_json(),
+            };
+            HttpResponse::json(status, body)
+        }
+        (_, ExecutionResult::Error { message }) => HttpResponse::error(400, &message),
+        (_, ExecutionResult::Rows { columns, rows }) => {
+            let rows = TypedRows::new(&sql, columns, &rows);
+            HttpResponse::streamed(200, format.content_type(), rows.encode(format))
+        }
+        (_, result) => match serde_json::to_string(&result) {
+            Ok(json) => HttpResponse::json(200, json),
+            Err(e) => HttpResponse::error(500, &e.to_string()),
+        },
+    };
+    let response = response.with_header("Vary", "Accept");
+    match continuation {
+        Some(token) => response.with_header("X-Continuation-Token", &token),
+        None => response,
+    }
+}
+
+/// Answer a change log request:
+/// `GET /cdc` (offsets), `POST /cdc/<consumer>` (register),
+/// `GET /cdc/<consumer>?after=&limit=` (read), `POST /cdc/<consumer>/ack?seq=`
+/// (acknowledge) and `DELETE /cdc/<consumer>` (unregister)
+fn cdc_response(request: &HttpRequest) -> HttpResponse {
+    let log = match cdc::log() {
+        Ok(log) => log,
+        Err(e) => return HttpResponse::error(503, &format!("Change log not available: {}", e)),
+    };
+    let number = |name: &str| -> Result<Option<u64>, String> {
+        match request.query(name) {
+            Some(text) => text.parse().map(Some).map_err(|_| format!("Invalid {} '{}'", name, text)),
+            None => Ok(None),
+        }
+    };
+    let path: Vec<&str> = request.route().trim_end_matches('/').split('/').skip(2).collect();
+    let result = match (request.method.as_str(), path.as_slice()) {
+        ("GET", []) => Ok(serde_json::json!({ "offsets": log.offsets(), "retained": log.retained() })),
+        ("POST", [consumer]) => log
+            .register(consumer)
+            .map(|offset| serde_json::json!({ "consumer": consumer, "offset": offset })),
+        ("DELETE", [consumer]) => match log.unregister(consumer) {
+            Ok(true) => Ok(serde_json::json!({ "consumer": consumer })),
+            Ok(false) => return HttpResponse::error(404, &format!("Unknown change log consumer '{}'", consumer)),
+            Err(e) => Err(e),
+        },
+        ("GET", [consumer]) => number("after").and_then(|after| {
+            let limit = number("limit")?.map_or(cdc::DEFAULT_READ_LIMIT, |limit| limit as usize);
+            let entries = log.read(consumer, after, limit)?;
+            Ok(serde_json::json!({ "consumer": consumer, "entries": entries }))
+        }),
+        ("POST", [consumer, "ack"]) => {
+            // The sequence number may also come as the body
+            let seq = request.query("seq").unwrap_or_else(|| String::from_utf8_lossy(&request.body).trim().to_string());
+            match seq.parse() {
+                Ok(seq) => log
+                    .acknowledge(consumer, seq)
+                    .map(|offset| serde_json::json!({ "consumer": consumer, "offset": offset })),
+                Err(_) => Err(format!("Invalid sequence number '{}'", seq)),
+            }
+        }
+        _ => return HttpResponse::error(404, "Not Found"),
+    };
+    match result {
+        Ok(json) => HttpResponse::json(200, json.to_string()),
+        Err(e) => HttpResponse::error(400, &e),
+    }
+}
+
+/// Answer one request. Statements run here, so callers on the async
+/// runtime hand this to a blocking thread.
+pub fn respond(request: &HttpRequest) -> HttpResponse {
+    match (request.method.as_str(), request.route()) {
+        (_, "/heart-beat") => HttpResponse::text(200, "OK\n".to_string()),
+        (_, "/ping") => HttpResponse::text(200, "pong\n".to_string()),
+        ("POST", "/sql") | ("GET", "/sql") => {
+            let Ok(body) = std::str::from_utf8(&request.body) else {
+                return HttpResponse::error(400, "Request body is not valid UTF-8");
+            };
+            let sql = body.trim();
+            if sql.is_empty() {
+                return HttpResponse::error(400, "No SQL query provided. Send SQL in request body.");
+            }
+            let format = match format::negotiate(request) {
+                Ok(format) => format,
+                Err((status, message)) => return HttpResponse::error(status, &message).with_header("Vary", "Accept"),
+            };
+            let page = match sql_request(sql) {
+                Ok(request) => match streamed_sql_response(format, &request) {
+                    Some(response) => return response,
+                    None => run_sql_request(request),
+                },
+                Err(message) => Page {
+                    sql: sql.to_string(),
+                    result: ExecutionResult::Error { message },
+                    continuation: None,
+                },
+            };
+            sql_response(format, page)
+        }
+        ("POST", "/subscribe") | ("GET", "/subscribe") => {
+            let sql = match request.query("sql") {
+                Some(sql) => sql,
+                None => match std::str::from_utf8(&request.body) {
+                    Ok(body) => body.to_string(),
+                    Err(_) => return HttpResponse::error(400, "Request body is not valid UTF-8"),
+                },
+            };
+            if sql.trim().is_empty() {
+                return HttpResponse::error(400, "No SQL query provided. Send SQL in the sql parameter or request body.");
+            }
+            match live::subscribe(sql.trim()) {
+                Ok(events) => HttpResponse::events(EventSource::Live(events)),
+                Err(message) => HttpResponse::error(400, &message),
+            }
+        }
+        ("GET", "/listen") => {
+            let channels = request.query_all("channel");
+            if channels.iter().all(String::is_empty) {
+                return HttpResponse::error(400, "No channel given. Name channels with ?channel=...");
             }
+            let listener = Listener::new();
+            channels.iter().filter(|channel| !channel.is_empty()).for_each(|channel| listener.listen(channel));
+            HttpResponse::events(EventSource::Notifications(listener))
+        }
+        (_, route) if route == "/cdc" || route.starts_with("/cdc/") => cdc_response(request),
+        ("GET", "/admin/pool") => match serde_json::to_string(&POOL.stats()) {
+            Ok(json) => HttpResponse::json(200, json),
+            Err(e) => HttpResponse::error(500, &e.to_string()),
+        },
+        (_, "/tables") => match crate::db::catalog::current().list_tables() {
+            Ok(tables) => {
+                let json = serde_json::json!({
+                    "tables": tables
+                });
+                HttpResponse::json(200, json.to_string())
+            }
+            Err(e) => HttpResponse::error(400, &e),
+        },
+        _ => HttpResponse::error(404, "Not Found"),
+    }
+}
+
+/// Wait for the first byte of the next request, marking the connection idle
+/// meanwhile. Returns false once the client has closed the connection or
+/// the pool reaps it for idling past its idle timeout.
+async fn await_request<R: AsyncBufRead + Unpin>(reader: &mut R, guard: &mut ConnectionGuard) -> Result<bool, Error> {
+    guard.set_idle(true);
+    let ready = loop {
+        match tokio::time::timeout(guard.idle_timeout(), reader.fill_buf()).await {
+            Ok(buffered) => break !buffered?.is_empty(),
+            Err(_) if guard.try_reap() => break false,
+            // Kept open to hold the pool's minimum
+            Err(_) => continue,
         }
+    };
+    guard.set_idle(false);
+    Ok(ready)
+}
+
+/// Interval between comments sent on an idle event stream, so proxies keep
+/// it open and a client that has gone away is noticed
+const EVENT_KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// Add the headers for a body of unknown length: chunked for HTTP/1.1,
+/// delimited by closing the connection for HTTP/1.0
+fn unbounded(response: HttpResponse, chunked: bool) -> HttpResponse {
+    match chunked {
+        true => response.with_header("Transfer-Encoding", "chunked"),
+        false if response.headers.iter().any(|(name, _)| name.eq_ignore_ascii_case("Connection")) => response,
+        false => response.with_header("Connection", "close"),
+    }
+}
+
+async fn write_chunk<W: AsyncWrite + Unpin>(writer: &mut W, chunk: &[u8], chunked: bool) -> Result<(), Error> {
+    if chunked {
+        writer.write_all(format!("{:x}\r\n", chunk.len()).as_bytes()).await?;
+        writer.write_all(chunk).await?;
+        writer.write_all(b"\r\n").await
+    } else {
+        writer.write_all(chunk).await
+    }
+}
+
+/// Write a response. A streamed body is sent with a Content-Length if it
+/// comes in one piece; otherwise HTTP/1.1 clients get it chunked and HTTP/1.0
+/// clients get it delimited by closing the connection. Returns false if the
+/// connection cannot be reused afterwards.
+async fn write_response<W: AsyncWrite + Unpin>(writer: &mut W, mut response: HttpResponse, version: &str) -> Result<bool, Error> {
+    let body = match response.stream.take() {
         None => {
-            HttpResponse::json(400, r#"{"error":"Bad Request"}"#.to_string())
+            writer.write_all(response.to_string().as_bytes()).await?;
+            return Ok(true);
+        }
+        Some(BodyStream::Events(source)) => return write_events(writer, response, version, source).await,
+        Some(BodyStream::Chunks(body)) => body,
+    };
+    // Producing a piece may run the query behind it, so the pieces are
+    // produced on a blocking thread, a couple ahead of the writer. An empty
+    // chunk would read as the end of a chunked body.
+    let (sender, mut chunks) = mpsc::channel(2);
+    tokio::task::spawn_blocking(move || {
+        for chunk in body.filter(|chunk| !matches!(chunk, Ok(bytes) if bytes.is_empty())) {
+            if sender.blocking_send(chunk).is_err() {
+                break;
+            }
+        }
+    });
+
+    // Look one piece ahead to choose the framing; nothing has been sent yet,
+    // so a failure here can still be answered with an error status
+    let first = chunks.recv().await.transpose();
+    let second = match first {
+        Ok(_) => chunks.recv().await.transpose(),
+        Err(_) => Ok(None),
+    };
+    let (first, second) = match (first, second) {
+        (Ok(first), Ok(second)) => (first.unwrap_or_default(), second),
+        (Err(message), _) | (_, Err(message)) => {
+            let response = HttpResponse::error(500, &message);
+            writer.write_all(response.to_string().as_bytes()).await?;
+            return Ok(true);
         }
     };
+    let Some(second) = second else {
+        let response = response.with_header("Content-Length", &first.len().to_string());
+        writer.write_all(response.to_string().as_bytes()).await?;
+        writer.write_all(&first).await?;
+        return Ok(true);
+    };
+
+    let chunked = version == "HTTP/1.1";
+    writer.write_all(unbounded(response, chunked).to_string().as_bytes()).await?;
+    write_chunk(writer, &first, chunked).await?;
+    write_chunk(writer, &second, chunked).await?;
+    while let Some(chunk) = chunks.recv().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(message) => {
+                // Headers are out, so the only signal left is ending the body
+                // without its terminating chunk
+                error!(format!("Response to a streamed request failed: {}", message));
+                return Ok(false);
+            }
+        };
+        write_chunk(writer, &chunk, chunked).await?;
+    }
+    if chunked {
+        writer.write_all(b"0\r\n\r\n").await?;
+    }
+    Ok(chunked)
+}
+
+/// Write server-sent events, each flushed as it comes, until the source
+/// ends. Fails once the client has gone away.
+async fn write_events<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    response: HttpResponse,
+    version: &str,
+    mut source: EventSource,
+) -> Result<bool, Error> {
+    let chunked = version == "HTTP/1.1";
+    writer.write_all(unbounded(response, chunked).to_string().as_bytes()).await?;
+    writer.flush().await?;
+    loop {
+        let frame = match tokio::time::timeout(EVENT_KEEP_ALIVE, source.next()).await {
+            Ok(Some(frame)) => frame,
+            Ok(None) => break,
+            Err(_) => ": keep-alive\n\n".to_string(),
+        };
+        write_chunk(writer, frame.as_bytes(), chunked).await?;
+        writer.flush().await?;
+    }
+    if chunked {
+        writer.write_all(b"0\r\n\r\n").await?;
+    }
+    Ok(chunked)
+}
+
+/// Serve requests on one connection until the client closes it, asks to,
+/// idles until reaped, or sends a request that cannot be read. `guard` is the
+/// connection's slot in the pool.
+pub async fn handle_client<S>(stream: S, limits: &Limits, guard: &mut ConnectionGuard) -> Result<(), Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut reader = BufReader::new(reader);
+
+    loop {
+        if !await_request(&mut reader, guard).await? {
+            return Ok(());
+        }
+        let read = read_request(&mut reader, limits, || async {
+            let mut interim = HttpResponse::text(100, String::new());
+            interim.headers.clear();
+            writer.write_all(interim.to_string().as_bytes()).await?;
+            writer.flush().await
+        });
+        let request = match tokio::time::timeout(limits.request_timeout, read).await {
+            Err(_) => {
+                let response = HttpResponse::error(408, "Request took too long to arrive").with_header("Connection", "close");
+                writer.write_all(response.to_string().as_bytes()).await?;
+                writer.flush().await?;
+                return Ok(());
+            }
+            Ok(Ok(None)) => return Ok(()),
+            Ok(Ok(Some(request))) => request,
+            Ok(Err(RequestError::Io(e))) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+            Ok(Err(RequestError::Io(e))) => return Err(e),
+            Ok(Err(RequestError::Rejected { status, message })) => {
+                let response = HttpResponse::error(status, &message).with_header("Connection", "close");
+                writer.write_all(response.to_string().as_bytes()).await?;
+                writer.flush().await?;
+                return Ok(());
+            }
+        };
+
+        let keep_alive = request.keep_alive();
+        let version = request.version.clone();
+        let request_line = format!("{} {}", request.method, request.path);
+        let response = match tokio::task::spawn_blocking(move || respond(&request)).await {
+            Ok(response) => response,
+            Err(_) => HttpResponse::error(500, "Request handler panicked"),
+        };
+        let response = match keep_alive {
+            true => response,
+            false => response.with_header("Connection", "close"),
+        };
+
+        let status_code = response.status_code;
+        let reusable = write_response(&mut writer, response, &version).await?;
+        writer.flush().await?;
+        info!(format!("Responded with {} to {}", status_code, request_line));
+        if !keep_alive || !reusable {
+            return Ok(());
+        }
+    }
+}
 
-    stream.write_all(response.to_string().as_bytes())?;
-    stream.flush()?;
-    println!("Responded with {} to {}", response.status_code, first_request_line);
-    Ok(())
+/// Turn a connection away because the pool has no slot for it
+pub async fn reject_busy<S>(mut stream: S, message: &str, retry_after: Duration) -> Result<(), Error>
+where
+    S: AsyncWrite + Unpin,
+{
+    let response = HttpResponse::error(503, message)
+        .with_header("Retry-After", &retry_after.as_secs().to_string())
+        .with_header("Connection", "close");
+    stream.write_all(response.to_string().as_bytes()).await?;
+    stream.flush().await?;
+    stream.shutdown().await
 }
 
 /// Parse HTTP request line and return (method, path)
@@ -135,7 +913,332 @@ pub fn parse_request_line(request_line: &str) -> Option<(&str, &str)> {
     }
 }
 
-// Keep old function name as alias for backward compatibility
-pub async fn handleClient(stream: TcpStream) -> Result<(), Error> {
-    handle_client(stream).await
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    use crate::config::PoolConfig;
+    use crate::db::notify::{self, Notification};
+    use crate::db::pool::ConnectionPool;
+
+    async fn parse(raw: &[u8], limits: &Limits) -> Result<Option<HttpRequest>, RequestError> {
+        let mut reader = BufReader::new(raw);
+        read_request(&mut reader, limits, || async { Ok(()) }).await
+    }
+
+    fn status(result: Result<Option<HttpRequest>, RequestError>) -> usize {
+        match result {
+            Err(RequestError::Rejected { status, .. }) => status,
+            other => panic!("Expected a rejection, got {:?}", other),
+        }
+    }
+
+    /// Send `raw` over an in-memory connection and collect everything the
+    /// server writes back before it closes the connection
+    async fn exchange(raw: &[u8]) -> String {
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        let mut guard = ConnectionPool::default().acquire().await.unwrap();
+        let serve = tokio::spawn(async move { handle_client(server, &Limits::default(), &mut guard).await });
+        client.write_all(raw).await.unwrap();
+        client.shutdown().await.unwrap();
+        let mut out = String::new();
+        client.read_to_string(&mut out).await.unwrap();
+        serve.await.unwrap().unwrap();
+        out
+    }
+
+    #[test]
+    fn test_json_params_keep_types() {
+        assert_eq!(Value::from_json(&serde_json::json!(42)).unwrap(), Value::Integer(42));
+        assert_eq!(Value::from_json(&serde_json::json!(1.5)).unwrap(), Value::Float(1.5));
+        assert_eq!(Value::from_json(&serde_json::json!("a")).unwrap(), Value::Text("a".to_string()));
+        assert_eq!(Value::from_json(&serde_json::json!(null)).unwrap(), Value::Null);
+        assert!(Value::from_json(&serde_json::json!([1])).is_err());
+    }
+
+    #[test]
+    fn test_sql_request_with_params() {
+        let request = sql_request(r#"{"sql": "SELECT $1 AS a, $2 AS b", "params": ["it's", true]}"#).unwrap();
+        match run_sql_request(request).result {
+            ExecutionResult::Rows { rows, .. } => {
+                assert_eq!(rows[0]["a"], serde_json::json!("it's"));
+                assert_eq!(rows[0]["b"], serde_json::json!(true));
+            }
+            other => panic!("Expected rows, got {:?}", other),
+        }
+
+        assert!(sql_request("{not json").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_read_request_frames_bodies() {
+        let limits = Limits::default();
+        let request = parse(b"POST /sql HTTP/1.1\r\nHost: x\r\ncontent-length: 8\r\n\r\nSELECT 1trailing", &limits)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((request.method.as_str(), request.route()), ("POST", "/sql"));
+        assert_eq!(request.header("Content-Length"), Some("8"));
+        assert_eq!(request.body, b"SELECT 1");
+
+        let chunked = b"POST /sql?x=1 HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n7;ext=1\r\nSELECT \r\n1\r\n1\r\n0\r\nTrailer: t\r\n\r\n";
+        let request = parse(chunked, &limits).await.unwrap().unwrap();
+        assert_eq!(request.route(), "/sql");
+        assert_eq!(request.body, b"SELECT 1");
+
+        // A body larger than one 8 KiB read arrives whole
+        let sql = format!("SELECT '{}' AS padding", "x".repeat(20_000));
+        let raw = format!("POST /sql HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}", sql.len(), sql);
+        assert_eq!(parse(raw.as_bytes(), &limits).await.unwrap().unwrap().body, sql.as_bytes());
+
+        assert!(parse(b"", &limits).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_read_request_rejects_bad_framing() {
+        let limits = Limits {
+            max_header_bytes: 64,
+            max_body_bytes: 16,
+            ..Limits::default()
+        };
+        assert_eq!(status(parse(b"GET /\r\n\r\n", &limits).await), 400);
+        assert_eq!(status(parse(b"GET / HTTP/2.0\r\n\r\n", &limits).await), 505);
+        assert_eq!(status(parse(b"GET / HTTP/1.1\r\nno colon\r\n\r\n", &limits).await), 400);
+        assert_eq!(status(parse(b"POST / HTTP/1.1\r\nContent-Length: 17\r\n\r\n", &limits).await), 413);
+        assert_eq!(status(parse(b"POST / HTTP/1.1\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\n", &limits).await), 400);
+        assert_eq!(status(parse(b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n", &limits).await), 501);
+        assert_eq!(
+            status(parse(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n10\r\n0123456789abcdef\r\n1\r\nx\r\n0\r\n\r\n", &limits).await),
+            413
+        );
+        let long_header = format!("GET / HTTP/1.1\r\nX-Long: {}\r\n\r\n", "a".repeat(100));
+        assert_eq!(status(parse(long_header.as_bytes(), &limits).await), 431);
+    }
+
+    #[test]
+    fn test_keep_alive_defaults_by_version() {
+        let request = |version: &str, connection: Option<&str>| HttpRequest {
+            method: "GET".to_string(),
+            path: "/".to_string(),
+            version: version.to_string(),
+            headers: connection.map(|c| ("Connection".to_string(), c.to_string())).into_iter().collect(),
+            body: Vec::new(),
+        };
+        assert!(request("HTTP/1.1", None).keep_alive());
+        assert!(!request("HTTP/1.1", Some("Close")).keep_alive());
+        assert!(!request("HTTP/1.0", None).keep_alive());
+        assert!(request("HTTP/1.0", Some("keep-alive")).keep_alive());
+    }
+
+    #[tokio::test]
+    async fn test_connection_serves_pipelined_requests() {
+        let out = exchange(b"GET /ping HTTP/1.1\r\n\r\nPOST /sql HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 8\r\n\r\nSELECT 1GET /nowhere HTTP/1.1\r\nConnection: close\r\n\r\n").await;
+        // Bodies carry no trailing newline, so split on the status lines themselves
+        let statuses: Vec<&str> = out.split("HTTP/1.1 ").skip(1).filter_map(|part| part.lines().next()).collect();
+        assert_eq!(statuses, ["200 OK", "100 Continue", "200 OK", "404 Not Found"]);
+        assert!(out.ends_with(r#"{"error":"Not Found"}"#));
+
+        let out = exchange(b"GET / HTTP/3\r\n\r\n").await;
+        assert!(out.starts_with("HTTP/1.1 505 HTTP Version Not Supported\r\n"), "{}", out);
+        assert!(out.contains("Connection: close\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_streamed_bodies_are_framed_by_version() {
+        let response = || {
+            let chunks = [b"ab".to_vec(), Vec::new(), b"cde".to_vec()];
+            HttpResponse::streamed(200, "text/plain", chunks.into_iter().map(Ok))
+        };
+        let mut out = Vec::new();
+        assert!(write_response(&mut out, response(), "HTTP/1.1").await.unwrap());
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("Transfer-Encoding: chunked\r\n"), "{}", out);
+        assert!(out.ends_with("\r\n\r\n2\r\nab\r\n3\r\ncde\r\n0\r\n\r\n"), "{}", out);
+
+        let mut out = Vec::new();
+        assert!(!write_response(&mut out, response(), "HTTP/1.0").await.unwrap());
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("Connection: close\r\n") && out.ends_with("\r\n\r\nabcde"), "{}", out);
+
+        // A CSV result small enough for one piece goes out with a length
+        let out = exchange(b"POST /sql?format=csv HTTP/1.1\r\nConnection: close\r\nContent-Length: 23\r\n\r\nSELECT 1 AS a, 'x' AS b").await;
+        assert!(out.contains("Content-Type: text/csv; charset=utf-8; header=present\r\n"), "{}", out);
+        assert!(out.contains("Content-Length: 10\r\n") && out.ends_with("a,b\r\n1,x\r\n"), "{}", out);
+
+        let out = exchange(b"GET /sql HTTP/1.1\r\nAccept: text/html\r\nConnection: close\r\nContent-Length: 8\r\n\r\nSELECT 1").await;
+        assert!(out.starts_with("HTTP/1.1 406 Not Acceptable\r\n"), "{}", out);
+    }
+
+    #[tokio::test]
+    async fn test_query_rows_stream_in_typed_formats() {
+        let run = crate::db::sql::execute_sql;
+        run("DROP TABLE IF EXISTS http_streamed");
+        run("CREATE TABLE http_streamed (id INT PRIMARY KEY)");
+        let values: Vec<String> = (0..format::BATCH_ROWS + 5).map(|i| format!("({})", i)).collect();
+        run(&format!("INSERT INTO http_streamed VALUES {}", values.join(", ")));
+
+        let sql = "SELECT id FROM http_streamed";
+        let request = format!("POST /sql?format=ndjson HTTP/1.1\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}", sql.len(), sql);
+        let out = exchange(request.as_bytes()).await;
+        assert!(out.contains("Transfer-Encoding: chunked\r\n"), "{}", out);
+        assert_eq!(out.matches(r#"{"id":"#).count(), format::BATCH_ROWS + 5);
+
+        // A query that fails before its first row is still answered with an error status
+        let sql = "SELECT * FROM http_streamed_missing";
+        let request = format!("POST /sql?format=csv HTTP/1.1\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}", sql.len(), sql);
+        let out = exchange(request.as_bytes()).await;
+        assert!(out.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", out);
+        run("DROP TABLE http_streamed");
+    }
+
+    #[tokio::test]
+    async fn test_live_query_events_stream_as_server_sent_events() {
+        let request = HttpRequest {
+            method: "GET".to_string(),
+            path: "/subscribe?sql=SELECT+id+FROM+t+WHERE+name+%3D+%27a%26b%27&x".to_string(),
+            version: "HTTP/1.1".to_string(),
+            headers: Vec::new(),
+            body: Vec::new(),
+        };
+        assert_eq!(request.query("sql").as_deref(), Some("SELECT id FROM t WHERE name = 'a&b'"));
+        assert_eq!(request.query("x").as_deref(), Some(""));
+        assert_eq!(percent_decode("100%+%zz"), "100% %zz");
+
+        let (sender, events) = mpsc::channel(4);
+        let row = HashMap::from([("id".to_string(), serde_json::json!(1))]);
+        sender.send(live::Event::Insert { row }).await.unwrap();
+        drop(sender);
+        let mut out = Vec::new();
+        assert!(write_response(&mut out, HttpResponse::events(EventSource::Live(events)), "HTTP/1.1").await.unwrap());
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("Content-Type: text/event-stream\r\n") && out.contains("Transfer-Encoding: chunked\r\n"), "{}", out);
+        let frame = "event: insert\ndata: {\"row\":{\"id\":1}}\n\n";
+        assert!(out.ends_with(&format!("\r\n\r\n{:x}\r\n{}\r\n0\r\n\r\n", frame.len(), frame)), "{}", out);
+
+        let out = exchange(b"GET /subscribe?sql=SELECT+COUNT(*)+FROM+t HTTP/1.1\r\nConnection: close\r\n\r\n").await;
+        assert!(out.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", out);
+    }
+
+    #[tokio::test]
+    async fn test_listen_streams_notifications_as_server_sent_events() {
+        let request = HttpRequest {
+            method: "GET".to_string(),
+            path: "/listen?channel=http_cache&channel=http_jobs".to_string(),
+            version: "HTTP/1.1".to_string(),
+            headers: Vec::new(),
+            body: Vec::new(),
+        };
+        assert_eq!(request.query_all("channel"), ["http_cache", "http_jobs"]);
+        let Some(BodyStream::Events(mut source)) = respond(&request).stream else {
+            panic!("Expected an event stream");
+        };
+
+        notify::notify(&Notification::new("http_jobs", "run", 0).unwrap());
+        assert_eq!(
+            source.next().await.unwrap(),
+            "event: notification\ndata: {\"channel\":\"http_jobs\",\"payload\":\"run\",\"process_id\":0}\n\n"
+        );
+
+        let out = exchange(b"GET /listen HTTP/1.1\r\nConnection: close\r\n\r\n").await;
+        assert!(out.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", out);
+    }
+
+    #[tokio::test]
+    async fn test_idle_connections_are_reaped_down_to_the_minimum() {
+        let pool = ConnectionPool::new(PoolConfig {
+            min_connections: 1,
+            max_connections: 4,
+            connection_timeout_ms: 1000,
+            idle_timeout_ms: 20,
+        });
+        let mut connections = Vec::new();
+        for _ in 0..2 {
+            let (client, server) = tokio::io::duplex(1024);
+            let mut guard = pool.acquire().await.unwrap();
+            let serve = tokio::spawn(async move { handle_client(server, &Limits::default(), &mut guard).await });
+            connections.push((client, serve));
+        }
+
+        // One of the two idle connections is closed; the other is kept
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let stats = pool.stats();
+        assert_eq!((stats.in_use, stats.idle, stats.reaped), (1, 1, 1));
+        let (mut kept, serve) = connections.into_iter().find(|(_, serve)| !serve.is_finished()).unwrap();
+        kept.write_all(b"GET /ping HTTP/1.1\r\nConnection: close\r\n\r\n").await.unwrap();
+        let mut out = String::new();
+        kept.read_to_string(&mut out).await.unwrap();
+        assert!(out.ends_with("pong\n"), "{}", out);
+        serve.await.unwrap().unwrap();
+        assert_eq!(pool.stats().in_use, 0);
+    }
+
+    #[tokio::test]
+    async fn test_busy_rejection_carries_retry_after() {
+        let (mut client, server) = tokio::io::duplex(1024);
+        reject_busy(server, "Connection pool timeout after 5000ms", Duration::from_secs(5)).await.unwrap();
+        let mut out = String::new();
+        client.read_to_string(&mut out).await.unwrap();
+        assert!(out.starts_with("HTTP/1.1 503 Service Unavailable\r\n"), "{}", out);
+        assert!(out.contains("Retry-After: 5\r\n"));
+    }
+
+    #[test]
+    fn test_admin_pool_endpoint_reports_occupancy() {
+        let request = HttpRequest {
+            method: "GET".to_string(),
+            path: "/admin/pool".to_string(),
+            version: "HTTP/1.1".to_string(),
+            headers: Vec::new(),
+            body: Vec::new(),
+        };
+        let response = respond(&request);
+        assert_eq!(response.status_code, 200);
+        let stats: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+        assert_eq!(stats["max_connections"], serde_json::json!(POOL.max_connections()));
+        assert!(stats["in_use"].is_u64() && stats["idle"].is_u64());
+    }
+
+    #[test]
+    fn test_change_log_endpoints_follow_commits() {
+        let call = |method: &str, path: &str| {
+            let response = respond(&HttpRequest {
+                method: method.to_string(),
+                path: path.to_string(),
+                version: "HTTP/1.1".to_string(),
+                headers: Vec::new(),
+                body: Vec::new(),
+            });
+            let json: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+            (response.status_code, json)
+        };
+        crate::db::sql::execute_sql("DROP TABLE IF EXISTS cdc_http");
+        crate::db::sql::execute_sql("CREATE TABLE cdc_http (id INT, name TEXT)");
+
+        let (status, registered) = call("POST", "/cdc/http_test");
+        assert_eq!(status, 200);
+        let start = registered["offset"].as_u64().unwrap();
+        crate::db::sql::execute_sql("INSERT INTO cdc_http VALUES (1, 'a')");
+        crate::db::sql::execute_sql("UPDATE cdc_http SET name = 'b' WHERE id = 1");
+        crate::db::sql::execute_sql("DELETE FROM cdc_http WHERE id = 1");
+
+        // Other tests write concurrently, so look at this table only
+        let (_, read) = call("GET", &format!("/cdc/http_test?after={}&limit=100000", start));
+        let entries: Vec<&serde_json::Value> = read["entries"].as_array().unwrap().iter().filter(|entry| entry["table"] == "cdc_http").collect();
+        let ops: Vec<&str> = entries.iter().map(|entry| entry["changes"][0]["op"].as_str().unwrap()).collect();
+        assert_eq!(ops, ["insert", "update", "delete"]);
+        assert_eq!(entries[1]["changes"][0]["before"]["name"], "a");
+        assert_eq!(entries[1]["changes"][0]["after"]["name"], "b");
+        assert!(entries[2]["changes"][0]["after"].is_null());
+
+        let seq = entries[2]["seq"].as_u64().unwrap();
+        let (status, acked) = call("POST", &format!("/cdc/http_test/ack?seq={}", seq));
+        assert_eq!((status, acked["offset"].as_u64()), (200, Some(seq)));
+        let (_, read) = call("GET", "/cdc/http_test");
+        assert!(read["entries"].as_array().unwrap().iter().all(|entry| entry["seq"].as_u64().unwrap() > seq));
+
+        assert_eq!(call("DELETE", "/cdc/http_test").0, 200);
+        assert_eq!(call("GET", "/cdc/http_test").0, 400);
+        crate::db::sql::execute_sql("DROP TABLE cdc_http");
+    }
 }
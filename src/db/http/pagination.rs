@@ -0,0 +1,204 @@
+// Continuation tokens - paging through /sql results over stateless HTTP.
+// The rest of a paged result is held as a snapshot, and each token names
+// the snapshot and the offset its page starts at, so a page can be fetched
+// again (e.g. on retry) and always holds the same rows.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::db::executor::ExecutionResult;
+use crate::db::session::cursor::Snapshot;
+
+/// Results held for continuation at once; the least recently read goes first
+pub const MAX_HELD_RESULTS: usize = 64;
+/// Bytes of rows all held results may take together. A result that would
+/// go over is refused rather than pushing out results still being read.
+pub const MAX_HELD_BYTES: usize = 256 << 20;
+/// How long a held result survives without being read
+pub const HOLD_TIMEOUT: Duration = Duration::from_secs(300);
+
+struct Held {
+    /// SQL the result came from, for typing its columns
+    sql: String,
+    snapshot: Arc<Snapshot>,
+    page_size: usize,
+    last_read: Instant,
+}
+
+lazy_static::lazy_static! {
+    static ref HELD: Mutex<HashMap<u64, Held>> = Mutex::new(HashMap::new());
+}
+
+/// One page of a result
+#[derive(Debug)]
+pub struct Page {
+    pub sql: String,
+    pub result: ExecutionResult,
+    /// Token for the next page; None on the last
+    pub continuation: Option<String>,
+}
+
+fn held() -> MutexGuard<'static, HashMap<u64, Held>> {
+    let mut held = HELD.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let now = Instant::now();
+    held.retain(|_, entry| now.duration_since(entry.last_read) < HOLD_TIMEOUT);
+    held
+}
+
+fn token(id: u64, offset: usize) -> String {
+    format!("{:016x}{:x}", id, offset)
+}
+
+fn parse_token(token: &str) -> Option<(u64, usize)> {
+    if token.len() <= 16 || !token.is_ascii() {
+        return None;
+    }
+    let (id, offset) = token.split_at(16);
+    Some((u64::from_str_radix(id, 16).ok()?, usize::from_str_radix(offset, 16).ok()?))
+}
+
+/// Room for `bytes` more among the `held` results, once the one `making_way`
+/// (if any) is let go, or why there is none
+fn admit(held: &HashMap<u64, Held>, making_way: Option<u64>, bytes: usize, max_bytes: usize) -> Result<(), String> {
+    let in_use: usize = held
+        .iter()
+        .filter(|(id, _)| Some(**id) != making_way)
+        .map(|(_, entry)| entry.snapshot.bytes())
+        .sum();
+    if in_use.saturating_add(bytes) > max_bytes {
+        return Err(format!(
+            "Result is too large to page: it takes {} bytes and {} of at most {} are held; add a LIMIT or narrow the query",
+            bytes, in_use, max_bytes
+        ));
+    }
+    Ok(())
+}
+
+/// First `page_size` rows of `snapshot`, holding the rest for continuation
+/// if there are more
+pub fn first_page(sql: &str, snapshot: Snapshot, page_size: usize) -> Page {
+    let result = snapshot.page(0, page_size);
+    if snapshot.len() <= page_size {
+        return Page {
+            sql: sql.to_string(),
+            result,
+            continuation: None,
+        };
+    }
+
+    let id = rand::random::<u64>();
+    let mut held = held();
+    // With as many results held as allowed, the least recently read one
+    // makes way, but only for a result that is then held in its place
+    let oldest = match held.len() >= MAX_HELD_RESULTS {
+        true => held.iter().min_by_key(|(_, entry)| entry.last_read).map(|(id, _)| *id),
+        false => None,
+    };
+    if let Err(message) = admit(&held, oldest, snapshot.bytes(), MAX_HELD_BYTES) {
+        return Page {
+            sql: sql.to_string(),
+            result: ExecutionResult::Error { message },
+            continuation: None,
+        };
+    }
+    if let Some(oldest) = oldest {
+        held.remove(&oldest);
+    }
+    held.insert(
+        id,
+        Held {
+            sql: sql.to_string(),
+            snapshot: Arc::new(snapshot),
+            page_size,
+            last_read: Instant::now(),
+        },
+    );
+    Page {
+        sql: sql.to_string(),
+        result,
+        continuation: Some(token(id, page_size)),
+    }
+}
+
+/// The page `continuation` points at, `page_size` rows long (by default as
+/// long as the first page)
+pub fn next_page(continuation: &str, page_size: Option<usize>) -> Result<Page, String> {
+    let (id, offset) = parse_token(continuation).ok_or("Invalid continuation token")?;
+    let (sql, snapshot, page_size) = {
+        let mut held = held();
+        let entry = held.get_mut(&id).ok_or("Continuation token has expired")?;
+        entry.last_read = Instant::now();
+        (entry.sql.clone(), Arc::clone(&entry.snapshot), page_size.unwrap_or(entry.page_size))
+    };
+    let end = offset.saturating_add(page_size);
+    Ok(Page {
+        sql,
+        result: snapshot.page(offset, page_size),
+        continuation: (end < snapshot.len()).then(|| token(id, end)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(page: &Page) -> Vec<i64> {
+        match &page.result {
+            ExecutionResult::Rows { rows, .. } => rows.iter().map(|row| row["id"].as_i64().unwrap()).collect(),
+            other => panic!("Expected rows, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_pages_follow_tokens_and_repeat_on_retry() {
+        let rows = (0..5).map(|i| HashMap::from([("id".to_string(), serde_json::json!(i))])).collect();
+        let snapshot = Snapshot::new(vec!["id".to_string()], rows);
+
+        let first = first_page("SELECT id FROM t", snapshot, 2);
+        assert_eq!(ids(&first), [0, 1]);
+        let token = first.continuation.unwrap();
+        let second = next_page(&token, None).unwrap();
+        assert_eq!(ids(&second), [2, 3]);
+        assert_eq!(ids(&next_page(&token, None).unwrap()), [2, 3]);
+        assert_eq!(second.sql, "SELECT id FROM t");
+
+        let last = next_page(&second.continuation.unwrap(), Some(10)).unwrap();
+        assert_eq!(ids(&last), [4]);
+        assert!(last.continuation.is_none());
+
+        assert!(next_page("not a token", None).is_err());
+        assert!(next_page(&super::token(token_id(&token) ^ 1, 2), None).is_err());
+
+        let small = Snapshot::new(vec!["id".to_string()], Vec::new());
+        assert!(first_page("SELECT 1", small, 2).continuation.is_none());
+    }
+
+    #[test]
+    fn test_held_bytes_are_capped() {
+        let row = |i: i64| HashMap::from([("id".to_string(), serde_json::json!(i))]);
+        let snapshot = Snapshot::new(vec!["id".to_string()], (0..10).map(row).collect());
+        let bytes = snapshot.bytes();
+        assert!(bytes > 0);
+
+        let mut held = HashMap::new();
+        assert!(admit(&held, None, bytes, bytes).is_ok());
+        held.insert(
+            1,
+            Held {
+                sql: String::new(),
+                snapshot: Arc::new(snapshot),
+                page_size: 2,
+                last_read: Instant::now(),
+            },
+        );
+        assert!(admit(&held, None, 1, bytes).unwrap_err().contains("too large to page"));
+        assert!(admit(&held, None, bytes, bytes * 2).is_ok());
+        // The result making way for the new one no longer counts
+        assert!(admit(&held, Some(1), bytes, bytes).is_ok());
+        assert!(admit(&held, Some(2), bytes, bytes).is_err());
+    }
+
+    fn token_id(token: &str) -> u64 {
+        parse_token(token).unwrap().0
+    }
+}
@@ -0,0 +1,317 @@
+// Live queries - a SELECT over one table, kept current for its subscriber
+// with the insert, update and delete deltas of every committed write
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::mpsc;
+
+use crate::db::executor::{ExecutionResult, Executor};
+use crate::db::planner::is_aggregate_function;
+use crate::db::sql::{Expression, SqlParser, Statement, TableReference};
+use crate::db::storage::changes::{self, Change, ChangeListener, Commit};
+use crate::db::storage::Row;
+
+/// Events a subscriber may fall behind by; one that falls further is
+/// dropped, ending its stream, and has to subscribe again
+pub const MAX_PENDING_EVENTS: usize = 1024;
+
+type JsonRow = HashMap<String, serde_json::Value>;
+
+/// What a subscriber receives
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// The query's result when the subscription started
+    Snapshot { columns: Vec<String>, rows: Vec<JsonRow> },
+    /// A row entered the result
+    Insert { row: JsonRow },
+    /// A row in the result changed
+    Update { old: JsonRow, new: JsonRow },
+    /// A row left the result
+    Delete { row: JsonRow },
+}
+
+impl Event {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::Snapshot { .. } => "snapshot",
+            Event::Insert { .. } => "insert",
+            Event::Update { .. } => "update",
+            Event::Delete { .. } => "delete",
+        }
+    }
+
+    /// The event's payload
+    pub fn data(&self) -> serde_json::Value {
+        match self {
+            Event::Snapshot { columns, rows } => serde_json::json!({ "columns": columns, "rows": rows }),
+            Event::Insert { row } | Event::Delete { row } => serde_json::json!({ "row": row }),
+            Event::Update { old, new } => serde_json::json!({ "old": old, "new": new }),
+        }
+    }
+}
+
+/// The part of a live query that turns commits into events
+struct LiveQuery {
+    table: String,
+    projection: Vec<Expression>,
+    where_clause: Option<Expression>,
+}
+
+/// A live query's place among the listeners. Its commits go to a thread of
+/// its own, which works out the events, so writers never wait on that.
+struct Subscription {
+    table: String,
+    commits: mpsc::Sender<Arc<Commit>>,
+    /// Held only to see whether the subscriber has gone away
+    events: mpsc::Sender<Event>,
+}
+
+/// How a change shows in the result, before projection
+enum Delta {
+    Insert(Row),
+    Update(Row, Row),
+    Delete(Row),
+}
+
+impl LiveQuery {
+    fn matches(&self, row: &Row) -> bool {
+        self.where_clause
+            .as_ref()
+            .is_none_or(|condition| Executor::eval_condition(condition, row))
+    }
+
+    /// Events for the changes of one commit. A row that stops (starts)
+    /// matching the WHERE clause leaves (enters) the result.
+    fn events(&self, changes: &[Change]) -> Result<Vec<Event>, String> {
+        let deltas: Vec<Delta> = changes
+            .iter()
+            .filter_map(|change| match change {
+                Change::Insert { row } => self.matches(row).then(|| Delta::Insert(row.clone())),
+                Change::Delete { row } => self.matches(row).then(|| Delta::Delete(row.clone())),
+                Change::Update { old, new } => match (self.matches(old), self.matches(new)) {
+                    (true, true) => Some(Delta::Update(old.clone(), new.clone())),
+                    (true, false) => Some(Delta::Delete(old.clone())),
+                    (false, true) => Some(Delta::Insert(new.clone())),
+                    (false, false) => None,
+                },
+            })
+            .collect();
+        if deltas.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // Project every row image in one pass, then pair them back up
+        let images: Vec<Row> = deltas
+            .iter()
+            .flat_map(|delta| match delta {
+                Delta::Insert(row) | Delta::Delete(row) => vec![row.clone()],
+                Delta::Update(old, new) => vec![old.clone(), new.clone()],
+            })
+            .collect();
+        let mut projected = match Executor::returning_result(&self.table, &self.projection, images) {
+            ExecutionResult::Rows { rows, .. } => rows.into_iter(),
+            ExecutionResult::Error { message } => return Err(message),
+            other => return Err(format!("Unexpected projection result: {:?}", other)),
+        };
+        let mut next = || projected.next().unwrap_or_default();
+        Ok(deltas
+            .into_iter()
+            .filter_map(|delta| match delta {
+                Delta::Insert(_) => Some(Event::Insert { row: next() }),
+                Delta::Delete(_) => Some(Event::Delete { row: next() }),
+                Delta::Update(..) => {
+                    let (old, new) = (next(), next());
+                    // A change to columns the query does not select is invisible
+                    (old != new).then_some(Event::Update { old, new })
+                }
+            })
+            .collect())
+    }
+}
+
+#[derive(Default)]
+struct LiveQueries {
+    subscriptions: Mutex<HashMap<u64, Subscription>>,
+}
+
+impl LiveQueries {
+    fn subscriptions(&self) -> std::sync::MutexGuard<'_, HashMap<u64, Subscription>> {
+        self.subscriptions.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl ChangeListener for LiveQueries {
+    /// Subscribers that have gone away are dropped here, so writes to a
+    /// table nobody watches any more stop being recorded
+    fn watches(&self, table: &str) -> bool {
+        let mut subscriptions = self.subscriptions();
+        subscriptions.retain(|_, subscription| !subscription.events.is_closed());
+        subscriptions.values().any(|subscription| subscription.table == table)
+    }
+
+    /// Hand the commit to the thread of each subscription on its table. A
+    /// subscription that has fallen too far behind is dropped.
+    fn committed(&self, commit: &Commit) {
+        let mut shared = None;
+        self.subscriptions().retain(|_, subscription| {
+            if subscription.events.is_closed() {
+                return false;
+            }
+            if subscription.table != commit.table {
+                return true;
+            }
+            let commit = shared.get_or_insert_with(|| Arc::new(commit.clone()));
+            subscription.commits.try_send(Arc::clone(commit)).is_ok()
+        });
+    }
+}
+
+/// Turn a subscription's commits into events until it is dropped. A
+/// subscriber that has gone away or fallen too far behind ends it, as does
+/// a query that no longer evaluates; dropping the subscription then ends
+/// the subscriber's stream.
+fn run_subscription(id: u64, query: LiveQuery, mut commits: mpsc::Receiver<Arc<Commit>>, events: mpsc::Sender<Event>) {
+    while let Some(commit) = commits.blocking_recv() {
+        let sent = match query.events(&commit.changes) {
+            Ok(batch) => batch.into_iter().all(|event| events.try_send(event).is_ok()),
+            Err(_) => false,
+        };
+        if !sent {
+            LIVE.subscriptions().remove(&id);
+            return;
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref LIVE: Arc<LiveQueries> = {
+        let live = Arc::new(LiveQueries::default());
+        changes::listen(live.clone());
+        live
+    };
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Table, select list and WHERE clause of a query that can be kept live
+fn live_query(stmt: &Statement) -> Result<(String, Vec<Expression>, Option<Expression>), String> {
+    let aggregate = |expr: &Expression| match expr {
+        Expression::Alias { expr, .. } => matches!(expr.as_ref(), Expression::Function { name, .. } if is_aggregate_function(name)),
+        Expression::Function { name, .. } => is_aggregate_function(name),
+        _ => false,
+    };
+    match stmt {
+        Statement::Select {
+            projection,
+            from: Some(TableReference::Table { name, .. }),
+            joins,
+            where_clause,
+            group_by,
+            having,
+            order_by,
+            limit,
+            offset,
+            distinct,
+        } if joins.is_empty()
+            && group_by.is_empty()
+            && having.is_none()
+            && order_by.is_empty()
+            && limit.is_none()
+            && offset.is_none()
+            && !distinct
+            && !projection.iter().any(aggregate) =>
+        {
+            Ok((name.clone(), projection.clone(), where_clause.clone()))
+        }
+        _ => Err("A live query must be a SELECT from one table without joins, aggregates, \
+                  GROUP BY, ORDER BY, DISTINCT, LIMIT or OFFSET"
+            .to_string()),
+    }
+}
+
+/// Start a live query. The first event received is the query's current
+/// result; after it come the deltas of each write that commits, in commit
+/// order. The stream ends if the subscriber falls more than
+/// `MAX_PENDING_EVENTS` behind.
+pub fn subscribe(sql: &str) -> Result<mpsc::Receiver<Event>, String> {
+    let stmt = SqlParser::parse_statement(sql).map_err(|e| format!("SQL parse error: {}", e))?;
+    let (table, projection, where_clause) = live_query(&stmt)?;
+    let live = Arc::clone(&LIVE);
+    let (sender, receiver) = mpsc::channel(MAX_PENDING_EVENTS);
+    let (commits, pending) = mpsc::channel(MAX_PENDING_EVENTS);
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let query = LiveQuery {
+        table: table.clone(),
+        projection,
+        where_clause,
+    };
+
+    // No write commits between reading the result and watching for changes
+    changes::quiesce(|| {
+        let event = match Executor::execute(&stmt) {
+            ExecutionResult::Rows { columns, rows } => Event::Snapshot { columns, rows },
+            ExecutionResult::Error { message } => return Err(message),
+            other => return Err(format!("Unexpected query result: {:?}", other)),
+        };
+        sender.try_send(event).map_err(|e| e.to_string())?;
+        let events = sender.clone();
+        std::thread::Builder::new()
+            .name(format!("live-query-{}", id))
+            .spawn(move || run_subscription(id, query, pending, events))
+            .map_err(|e| format!("Could not start the live query: {}", e))?;
+        live.subscriptions().insert(id, Subscription { table, commits, events: sender });
+        Ok(())
+    })?;
+    Ok(receiver)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::sql::execute_sql;
+
+    #[test]
+    fn test_subscriber_sees_snapshot_then_deltas() {
+        execute_sql("DROP TABLE IF EXISTS live_orders");
+        execute_sql("CREATE TABLE live_orders (id INT, status TEXT, total INT)");
+        execute_sql("INSERT INTO live_orders VALUES (1, 'open', 10), (2, 'closed', 20)");
+
+        let mut events = subscribe("SELECT id, total FROM live_orders WHERE status = 'open'").unwrap();
+        match events.try_recv().unwrap() {
+            Event::Snapshot { columns, rows } => {
+                assert_eq!(columns, ["id", "total"]);
+                assert_eq!(rows.len(), 1);
+            }
+            other => panic!("Expected a snapshot, got {:?}", other),
+        }
+
+        execute_sql("INSERT INTO live_orders VALUES (3, 'open', 30), (4, 'closed', 40)");
+        execute_sql("UPDATE live_orders SET total = 11 WHERE id = 1");
+        execute_sql("UPDATE live_orders SET status = 'closed' WHERE id = 3");
+        execute_sql("UPDATE live_orders SET status = 'open' WHERE id = 2");
+        // Not a selected column, so not a visible change
+        execute_sql("UPDATE live_orders SET status = 'open' WHERE id = 1");
+        execute_sql("DELETE FROM live_orders WHERE id = 2");
+
+        let row = |id: i64, total: i64| HashMap::from([("id".to_string(), serde_json::json!(id)), ("total".to_string(), serde_json::json!(total))]);
+        // Events are worked out off the writers' path, so they arrive a little later
+        let received: Vec<Event> = (0..5).map(|_| events.blocking_recv().unwrap()).collect();
+        assert_eq!(
+            received,
+            [
+                Event::Insert { row: row(3, 30) },
+                Event::Update { old: row(1, 10), new: row(1, 11) },
+                Event::Delete { row: row(3, 30) },
+                Event::Insert { row: row(2, 20) },
+                Event::Delete { row: row(2, 20) },
+            ]
+        );
+
+        assert!(subscribe("SELECT status, COUNT(*) FROM live_orders GROUP BY status").is_err());
+        assert!(LIVE.watches("live_orders"));
+        drop(events);
+        assert!(!LIVE.watches("live_orders"));
+        execute_sql("DROP TABLE live_orders");
+    }
+}
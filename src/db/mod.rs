@@ -1,10 +1,20 @@
 pub mod admission_control;
 pub mod catalog;
+pub mod cdc;
+pub mod database;
 pub mod executor;
+pub mod grpc;
 pub mod http;
+pub mod live;
+pub mod notify;
+pub mod optimizer;
 pub mod pager;
 pub mod partition;
+pub mod pgwire;
+pub mod planner;
 pub mod pool;
 pub mod process;
+pub mod resp;
+pub mod session;
 pub mod sql;
 pub mod storage;
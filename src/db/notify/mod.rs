@@ -0,0 +1,186 @@
+// LISTEN / NOTIFY - publish-subscribe channels between sessions, whatever
+// connection they came in on. A notification goes to every listener on its
+// channel once the statement or transaction that sent it has committed;
+// nothing is kept for listeners that join later.
+use std::collections::{BTreeSet, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::task::{Context, Poll};
+
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+use crate::warn;
+
+/// Notifications a listener may have waiting; further ones are dropped for it
+pub const MAX_PENDING_NOTIFICATIONS: usize = 1024;
+/// Longest payload NOTIFY accepts, as in PostgreSQL
+pub const MAX_PAYLOAD_BYTES: usize = 8000;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Notification {
+    pub channel: String,
+    pub payload: String,
+    /// Process id of the sending session (0 if it has none)
+    pub process_id: i32,
+}
+
+impl Notification {
+    pub fn new(channel: &str, payload: &str, process_id: i32) -> Result<Self, String> {
+        if channel.is_empty() {
+            return Err("Channel name cannot be empty".to_string());
+        }
+        if payload.len() > MAX_PAYLOAD_BYTES {
+            return Err(format!("Payload string too long: at most {} bytes", MAX_PAYLOAD_BYTES));
+        }
+        Ok(Self {
+            channel: channel.to_string(),
+            payload: payload.to_string(),
+            process_id,
+        })
+    }
+}
+
+struct Registration {
+    channels: BTreeSet<String>,
+    sender: mpsc::Sender<Notification>,
+}
+
+lazy_static::lazy_static! {
+    static ref LISTENERS: Mutex<HashMap<u64, Registration>> = Mutex::new(HashMap::new());
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+fn listeners() -> MutexGuard<'static, HashMap<u64, Registration>> {
+    LISTENERS.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// One session's or connection's subscriptions, and the notifications
+/// waiting for it. Dropping it stops all of its subscriptions.
+pub struct Listener {
+    id: u64,
+    receiver: mpsc::Receiver<Notification>,
+}
+
+impl std::fmt::Debug for Listener {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Listener").field("id", &self.id).finish()
+    }
+}
+
+impl Default for Listener {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Listener {
+    /// A listener on no channels yet
+    pub fn new() -> Self {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = mpsc::channel(MAX_PENDING_NOTIFICATIONS);
+        listeners().insert(
+            id,
+            Registration {
+                channels: BTreeSet::new(),
+                sender,
+            },
+        );
+        Self { id, receiver }
+    }
+
+    pub fn listen(&self, channel: &str) {
+        if let Some(registration) = listeners().get_mut(&self.id) {
+            registration.channels.insert(channel.to_string());
+        }
+    }
+
+    /// Stop listening on `channel`, or on every channel when None
+    pub fn unlisten(&self, channel: Option<&str>) {
+        if let Some(registration) = listeners().get_mut(&self.id) {
+            match channel {
+                Some(channel) => registration.channels.remove(channel),
+                None => {
+                    registration.channels.clear();
+                    true
+                }
+            };
+        }
+    }
+
+    pub fn channels(&self) -> Vec<String> {
+        listeners()
+            .get(&self.id)
+            .map(|registration| registration.channels.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// The next notification, waiting for one to arrive
+    pub async fn recv(&mut self) -> Option<Notification> {
+        self.receiver.recv().await
+    }
+
+    /// The next notification if one is waiting
+    pub fn try_recv(&mut self) -> Option<Notification> {
+        self.receiver.try_recv().ok()
+    }
+
+    /// Poll for the next notification, for callers driving a stream by hand
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<Notification>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        listeners().remove(&self.id);
+    }
+}
+
+/// Hand `notification` to every listener on its channel, returning how many
+/// got it. A listener too far behind misses it.
+pub fn notify(notification: &Notification) -> usize {
+    let listeners = listeners();
+    listeners
+        .values()
+        .filter(|registration| registration.channels.contains(&notification.channel))
+        .filter(|registration| match registration.sender.try_send(notification.clone()) {
+            Ok(()) => true,
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                warn!(format!("Dropped a notification on {} for a listener that is too far behind", notification.channel));
+                false
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => false,
+        })
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notifications_reach_listeners_on_the_channel() {
+        let mut cache = Listener::new();
+        let mut both = Listener::new();
+        cache.listen("notify_test_cache");
+        both.listen("notify_test_cache");
+        both.listen("notify_test_jobs");
+        assert_eq!(both.channels(), ["notify_test_cache", "notify_test_jobs"]);
+
+        let invalidate = Notification::new("notify_test_cache", "users:42", 7).unwrap();
+        assert_eq!(notify(&invalidate), 2);
+        assert_eq!(notify(&Notification::new("notify_test_jobs", "", 7).unwrap()), 1);
+        assert_eq!(cache.try_recv(), Some(invalidate.clone()));
+        assert_eq!(cache.try_recv(), None);
+        assert_eq!(both.try_recv(), Some(invalidate.clone()));
+        assert_eq!(both.try_recv().map(|n| n.channel), Some("notify_test_jobs".to_string()));
+
+        both.unlisten(None);
+        drop(cache);
+        assert_eq!(notify(&invalidate), 0);
+        assert!(Notification::new("", "x", 0).is_err());
+        assert!(Notification::new("c", &"x".repeat(MAX_PAYLOAD_BYTES + 1), 0).is_err());
+    }
+}
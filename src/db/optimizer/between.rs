@@ -0,0 +1,137 @@
+// BETWEEN simplification
+use super::{walk_expression, walk_statement, RewriteRule};
+use crate::db::sql::constants::{BinaryOperator, Literal, Statement};
+use crate::db::sql::parser::{AstVisitor, Expression};
+use crate::db::storage::Value;
+
+/// Simplifies the `x >= low AND x <= high` form the parser turns
+/// `x BETWEEN low AND high` into:
+/// - literal bounds with `low > high` can never match, so the range becomes FALSE
+/// - equal text or boolean bounds become `x = low`. Numeric bounds keep the
+///   range, since `=` does not treat 1 and 1.0 as equal while `>=`/`<=` do.
+pub struct SimplifyBetween;
+
+impl RewriteRule for SimplifyBetween {
+    fn name(&self) -> &'static str {
+        "simplify_between"
+    }
+}
+
+impl AstVisitor for SimplifyBetween {
+    type StatementOutput = Statement;
+    type ExpressionOutput = Expression;
+
+    fn visit_statement(&mut self, stmt: &Statement) -> Statement {
+        walk_statement(self, stmt)
+    }
+
+    fn visit_expression(&mut self, expr: &Expression) -> Expression {
+        let expr = walk_expression(self, expr);
+        match as_range(&expr) {
+            Some((subject, low, high)) => simplify(subject, low, high).unwrap_or(expr),
+            None => expr,
+        }
+    }
+}
+
+/// Match `subject >= low AND subject <= high`
+fn as_range(expr: &Expression) -> Option<(&Expression, &Literal, &Literal)> {
+    let (lower, upper) = match expr {
+        Expression::BinaryOp {
+            left,
+            operator: BinaryOperator::And,
+            right,
+        } => (left.as_ref(), right.as_ref()),
+        _ => return None,
+    };
+
+    match (lower, upper) {
+        (
+            Expression::BinaryOp {
+                left: subject,
+                operator: BinaryOperator::GreaterThanOrEqual,
+                right: low,
+            },
+            Expression::BinaryOp {
+                left: subject2,
+                operator: BinaryOperator::LessThanOrEqual,
+                right: high,
+            },
+        ) if subject == subject2 => match (low.as_ref(), high.as_ref()) {
+            (Expression::Literal(low), Expression::Literal(high)) => Some((subject, low, high)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn simplify(subject: &Expression, low: &Literal, high: &Literal) -> Option<Expression> {
+    let (low_value, high_value) = (Value::from_literal(low), Value::from_literal(high));
+    let empty = match (&low_value, &high_value) {
+        (Value::Integer(l), Value::Integer(h)) => l > h,
+        (Value::Float(l), Value::Float(h)) => l > h,
+        (Value::Integer(l), Value::Float(h)) => (*l as f64) > *h,
+        (Value::Float(l), Value::Integer(h)) => *l > (*h as f64),
+        (Value::Text(l), Value::Text(h)) => l > h,
+        _ => false,
+    };
+    if empty {
+        return Some(Expression::Literal(Literal::Boolean(false)));
+    }
+
+    let exact = matches!(low, Literal::String(_) | Literal::Boolean(_)) && low == high;
+    if exact {
+        return Some(Expression::BinaryOp {
+            left: Box::new(subject.clone()),
+            operator: BinaryOperator::Equals,
+            right: Box::new(Expression::Literal(low.clone())),
+        });
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::sql::parser::SqlParser;
+
+    fn simplify_sql(sql: &str) -> Statement {
+        SimplifyBetween.visit_statement(&SqlParser::parse_statement(sql).unwrap())
+    }
+
+    fn parse(sql: &str) -> Statement {
+        SqlParser::parse_statement(sql).unwrap()
+    }
+
+    #[test]
+    fn test_empty_range_becomes_false() {
+        assert_eq!(
+            simplify_sql("SELECT * FROM t WHERE a BETWEEN 10 AND 1"),
+            parse("SELECT * FROM t WHERE FALSE")
+        );
+        assert_eq!(
+            simplify_sql("SELECT * FROM t WHERE name BETWEEN 'm' AND 'c'"),
+            parse("SELECT * FROM t WHERE FALSE")
+        );
+    }
+
+    #[test]
+    fn test_equal_text_bounds_become_equality() {
+        assert_eq!(
+            simplify_sql("SELECT * FROM t WHERE name BETWEEN 'bob' AND 'bob'"),
+            parse("SELECT * FROM t WHERE name = 'bob'")
+        );
+    }
+
+    #[test]
+    fn test_ordinary_ranges_are_kept() {
+        for sql in [
+            "SELECT * FROM t WHERE a BETWEEN 1 AND 10",
+            "SELECT * FROM t WHERE a BETWEEN 1 AND 1",
+            "SELECT * FROM t WHERE a BETWEEN b AND 1",
+            "SELECT * FROM t WHERE a >= 5 AND b <= 1",
+        ] {
+            assert_eq!(simplify_sql(sql), parse(sql));
+        }
+    }
+}
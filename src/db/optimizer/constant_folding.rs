@@ -0,0 +1,218 @@
+// Constant folding - evaluate subexpressions whose operands are all literals
+use super::{walk_expression, walk_statement, RewriteRule};
+use crate::db::executor::Executor;
+use crate::db::sql::constants::{BinaryOperator, Literal, Statement, UnaryOperator};
+use crate::db::sql::parser::{AstVisitor, Expression};
+use crate::db::storage::{Row, Value};
+
+/// Replaces literal-only arithmetic, comparisons and boolean logic with their
+/// value, and drops WHERE / HAVING clauses that fold to TRUE. Values are
+/// computed with the executor's own evaluator, so folding never changes a result.
+pub struct ConstantFolding;
+
+impl RewriteRule for ConstantFolding {
+    fn name(&self) -> &'static str {
+        "constant_folding"
+    }
+}
+
+impl AstVisitor for ConstantFolding {
+    type StatementOutput = Statement;
+    type ExpressionOutput = Expression;
+
+    fn visit_statement(&mut self, stmt: &Statement) -> Statement {
+        match walk_statement(self, stmt) {
+            Statement::Select {
+                projection,
+                from,
+                joins,
+                where_clause,
+                group_by,
+                having,
+                order_by,
+                limit,
+                offset,
+                distinct,
+            } => Statement::Select {
+                projection,
+                from,
+                joins,
+                where_clause: where_clause.filter(|e| !is_true(e)),
+                group_by,
+                having: having.filter(|e| !is_true(e)),
+                order_by,
+                limit,
+                offset,
+                distinct,
+            },
+            Statement::Update {
+                table,
+                assignments,
+                where_clause,
+                returning,
+            } => Statement::Update {
+                table,
+                assignments,
+                where_clause: where_clause.filter(|e| !is_true(e)),
+                returning,
+            },
+            Statement::Delete {
+                table,
+                where_clause,
+                returning,
+            } => Statement::Delete {
+                table,
+                where_clause: where_clause.filter(|e| !is_true(e)),
+                returning,
+            },
+            other => other,
+        }
+    }
+
+    fn visit_expression(&mut self, expr: &Expression) -> Expression {
+        // Fold bottom-up so nested constants collapse in one pass
+        fold(walk_expression(self, expr))
+    }
+}
+
+fn is_true(expr: &Expression) -> bool {
+    matches!(expr, Expression::Literal(Literal::Boolean(true)))
+}
+
+fn literal(value: Value) -> Expression {
+    Expression::Literal(value.to_literal())
+}
+
+fn fold(expr: Expression) -> Expression {
+    match expr {
+        Expression::BinaryOp {
+            left,
+            operator,
+            right,
+        } => fold_binary(*left, operator, *right),
+        Expression::UnaryOp { operator, operand } => match (&operator, operand.as_ref()) {
+            (UnaryOperator::Not, Expression::Literal(Literal::Boolean(b))) => {
+                Expression::Literal(Literal::Boolean(!b))
+            }
+            (UnaryOperator::Minus, Expression::Literal(lit @ Literal::Number(_))) => {
+                match Value::from_literal(lit) {
+                    Value::Integer(i) => match i.checked_neg() {
+                        Some(n) => literal(Value::Integer(n)),
+                        None => Expression::UnaryOp { operator, operand },
+                    },
+                    Value::Float(f) => literal(Value::Float(-f)),
+                    _ => Expression::UnaryOp { operator, operand },
+                }
+            }
+            (UnaryOperator::Plus, Expression::Literal(Literal::Number(_))) => *operand,
+            _ => Expression::UnaryOp { operator, operand },
+        },
+        other => other,
+    }
+}
+
+fn fold_binary(left: Expression, operator: BinaryOperator, right: Expression) -> Expression {
+    match (&left, &operator, &right) {
+        // Boolean identities hold whatever the other side evaluates to
+        (Expression::Literal(Literal::Boolean(false)), BinaryOperator::And, _)
+        | (_, BinaryOperator::And, Expression::Literal(Literal::Boolean(false))) => {
+            return Expression::Literal(Literal::Boolean(false));
+        }
+        (Expression::Literal(Literal::Boolean(true)), BinaryOperator::Or, _)
+        | (_, BinaryOperator::Or, Expression::Literal(Literal::Boolean(true))) => {
+            return Expression::Literal(Literal::Boolean(true));
+        }
+        (Expression::Literal(Literal::Boolean(true)), BinaryOperator::And, _)
+        | (Expression::Literal(Literal::Boolean(false)), BinaryOperator::Or, _) => return right,
+        (_, BinaryOperator::And, Expression::Literal(Literal::Boolean(true)))
+        | (_, BinaryOperator::Or, Expression::Literal(Literal::Boolean(false))) => return left,
+        _ => {}
+    }
+
+    let both_literal = matches!(
+        (&left, &right),
+        (Expression::Literal(_), Expression::Literal(_))
+    );
+    let expr = Expression::BinaryOp {
+        left: Box::new(left),
+        operator,
+        right: Box::new(right),
+    };
+    if !both_literal {
+        return expr;
+    }
+
+    let empty = Row::new();
+    match &expr {
+        Expression::BinaryOp {
+            left,
+            operator,
+            right,
+        } => match operator {
+            BinaryOperator::Plus
+            | BinaryOperator::Minus
+            | BinaryOperator::Multiply
+            | BinaryOperator::Divide
+            | BinaryOperator::Modulo => literal(Executor::eval_expression(&expr, &empty)),
+            BinaryOperator::Equals
+            | BinaryOperator::NotEquals
+            | BinaryOperator::LessThan
+            | BinaryOperator::LessThanOrEqual
+            | BinaryOperator::GreaterThan
+            | BinaryOperator::GreaterThanOrEqual
+            | BinaryOperator::Like => {
+                // A comparison with NULL stays as written: as a filter it is
+                // false, while a bare NULL literal would pass
+                let is_null = |e: &Expression| matches!(e, Expression::Literal(Literal::Null));
+                if is_null(left) || is_null(right) {
+                    expr
+                } else {
+                    literal(Value::Boolean(Executor::eval_condition(&expr, &empty)))
+                }
+            }
+            _ => expr,
+        },
+        _ => expr,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::sql::parser::SqlParser;
+
+    fn fold_sql(sql: &str) -> Statement {
+        ConstantFolding.visit_statement(&SqlParser::parse_statement(sql).unwrap())
+    }
+
+    fn parse(sql: &str) -> Statement {
+        SqlParser::parse_statement(sql).unwrap()
+    }
+
+    #[test]
+    fn test_folds_arithmetic() {
+        assert_eq!(fold_sql("SELECT 1 + 2 * 3, 1.5 + 0.5 FROM t"), parse("SELECT 7, 2.0 FROM t"));
+        assert_eq!(fold_sql("SELECT a + (2 - 1) FROM t"), parse("SELECT a + 1 FROM t"));
+        assert_eq!(fold_sql("SELECT 1 / 0 FROM t"), parse("SELECT NULL FROM t"));
+
+        let negated = ConstantFolding.visit_expression(&Expression::UnaryOp {
+            operator: UnaryOperator::Minus,
+            operand: Box::new(Expression::Literal(Literal::Number("4".to_string()))),
+        });
+        assert_eq!(negated, Expression::Literal(Literal::Number("-4".to_string())));
+    }
+
+    #[test]
+    fn test_folds_comparisons_and_boolean_logic() {
+        assert_eq!(fold_sql("SELECT * FROM t WHERE a = 1 AND 2 > 1"), parse("SELECT * FROM t WHERE a = 1"));
+        assert_eq!(fold_sql("SELECT * FROM t WHERE a = 1 AND 1 > 2"), parse("SELECT * FROM t WHERE FALSE"));
+        assert_eq!(fold_sql("SELECT * FROM t WHERE a = 1 OR 'x' = 'x'"), parse("SELECT * FROM t"));
+        assert_eq!(fold_sql("DELETE FROM t WHERE NOT FALSE"), parse("DELETE FROM t"));
+    }
+
+    #[test]
+    fn test_leaves_null_comparisons_and_columns() {
+        let stmt = parse("SELECT * FROM t WHERE NULL = 1 AND a < b");
+        assert_eq!(fold_sql("SELECT * FROM t WHERE NULL = 1 AND a < b"), stmt);
+    }
+}
@@ -0,0 +1,371 @@
+// Query Optimizer - Rule-based AST rewrites applied between parsing and execution
+pub mod between;
+pub mod constant_folding;
+pub mod predicate_pushdown;
+pub mod projection_pruning;
+
+use crate::db::sql::constants::{
+    Assignment, BinaryOperator, CommonTableExpression, ConflictAction, Join, OnConflict, OrderBy,
+    Statement, TableReference,
+};
+use crate::db::sql::parser::{AstVisitor, Expression};
+
+pub use between::SimplifyBetween;
+pub use constant_folding::ConstantFolding;
+pub use predicate_pushdown::PredicatePushdown;
+pub use projection_pruning::ProjectionPruning;
+
+/// A rewrite rule: an AST visitor that returns a rewritten copy of whatever it
+/// visits. Rules must preserve the meaning of the statement.
+pub trait RewriteRule: AstVisitor<StatementOutput = Statement, ExpressionOutput = Expression> {
+    fn name(&self) -> &'static str;
+}
+
+/// Applies a sequence of rewrite rules to a statement, one after another
+pub struct Rewriter {
+    rules: Vec<Box<dyn RewriteRule>>,
+}
+
+impl Default for Rewriter {
+    fn default() -> Self {
+        // BETWEEN runs first so folding sees the simplified form; pushdown runs
+        // after folding so constant-true conjuncts are already gone
+        Self::new(vec![
+            Box::new(SimplifyBetween),
+            Box::new(ConstantFolding),
+            Box::new(PredicatePushdown),
+            Box::new(ProjectionPruning),
+        ])
+    }
+}
+
+impl Rewriter {
+    pub fn new(rules: Vec<Box<dyn RewriteRule>>) -> Self {
+        Self { rules }
+    }
+
+    /// Names of the rules, in the order they run
+    pub fn rule_names(&self) -> Vec<&'static str> {
+        self.rules.iter().map(|r| r.name()).collect()
+    }
+
+    pub fn rewrite(&mut self, stmt: &Statement) -> Statement {
+        let mut stmt = stmt.clone();
+        for rule in self.rules.iter_mut() {
+            stmt = rule.visit_statement(&stmt);
+        }
+        stmt
+    }
+}
+
+/// Rebuild `stmt`, passing every expression it holds through
+/// `rule.visit_expression` and every nested statement (subquery, CTE, UNION
+/// side) through `rule.visit_statement`. Rules call this for the parts of a
+/// statement they do not rewrite themselves.
+pub fn walk_statement<R>(rule: &mut R, stmt: &Statement) -> Statement
+where
+    R: AstVisitor<StatementOutput = Statement, ExpressionOutput = Expression> + ?Sized,
+{
+    match stmt {
+        Statement::Select {
+            projection,
+            from,
+            joins,
+            where_clause,
+            group_by,
+            having,
+            order_by,
+            limit,
+            offset,
+            distinct,
+        } => Statement::Select {
+            projection: projection.iter().map(|e| rule.visit_expression(e)).collect(),
+            from: from.as_ref().map(|t| walk_table_reference(rule, t)),
+            joins: joins
+                .iter()
+                .map(|j| Join {
+                    join_type: j.join_type.clone(),
+                    table: walk_table_reference(rule, &j.table),
+                    condition: j.condition.as_ref().map(|c| rule.visit_expression(c)),
+                })
+                .collect(),
+            where_clause: where_clause.as_ref().map(|e| rule.visit_expression(e)),
+            group_by: group_by.iter().map(|e| rule.visit_expression(e)).collect(),
+            having: having.as_ref().map(|e| rule.visit_expression(e)),
+            order_by: order_by
+                .iter()
+                .map(|o| OrderBy {
+                    expression: rule.visit_expression(&o.expression),
+                    direction: o.direction.clone(),
+                })
+                .collect(),
+            limit: *limit,
+            offset: *offset,
+            distinct: *distinct,
+        },
+        Statement::Insert {
+            table,
+            columns,
+            values,
+            query,
+            on_conflict,
+            returning,
+        } => Statement::Insert {
+            table: table.clone(),
+            columns: columns.clone(),
+            values: values
+                .iter()
+                .map(|row| row.iter().map(|e| rule.visit_expression(e)).collect())
+                .collect(),
+            query: query.as_ref().map(|q| Box::new(rule.visit_statement(q))),
+            on_conflict: on_conflict.as_ref().map(|oc| OnConflict {
+                target: oc.target.clone(),
+                action: match &oc.action {
+                    ConflictAction::DoNothing => ConflictAction::DoNothing,
+                    ConflictAction::DoUpdate {
+                        assignments,
+                        where_clause,
+                    } => ConflictAction::DoUpdate {
+                        assignments: walk_assignments(rule, assignments),
+                        where_clause: where_clause.as_ref().map(|e| rule.visit_expression(e)),
+                    },
+                },
+            }),
+            returning: walk_list(rule, returning.as_deref()),
+        },
+        Statement::Update {
+            table,
+            assignments,
+            where_clause,
+            returning,
+        } => Statement::Update {
+            table: table.clone(),
+            assignments: walk_assignments(rule, assignments),
+            where_clause: where_clause.as_ref().map(|e| rule.visit_expression(e)),
+            returning: walk_list(rule, returning.as_deref()),
+        },
+        Statement::Delete {
+            table,
+            where_clause,
+            returning,
+        } => Statement::Delete {
+            table: table.clone(),
+            where_clause: where_clause.as_ref().map(|e| rule.visit_expression(e)),
+            returning: walk_list(rule, returning.as_deref()),
+        },
+        Statement::Union { left, right, all } => Statement::Union {
+            left: Box::new(rule.visit_statement(left)),
+            right: Box::new(rule.visit_statement(right)),
+            all: *all,
+        },
+        Statement::With {
+            recursive,
+            ctes,
+            body,
+        } => Statement::With {
+            recursive: *recursive,
+            ctes: ctes
+                .iter()
+                .map(|cte| CommonTableExpression {
+                    name: cte.name.clone(),
+                    columns: cte.columns.clone(),
+                    query: Box::new(rule.visit_statement(&cte.query)),
+                })
+                .collect(),
+            body: Box::new(rule.visit_statement(body)),
+        },
+        Statement::Explain {
+            analyze,
+            format,
+            statement,
+        } => Statement::Explain {
+            analyze: *analyze,
+            format: *format,
+            statement: Box::new(rule.visit_statement(statement)),
+        },
+        // DDL, transactions and session statements hold nothing to rewrite.
+        // PREPARE bodies are rewritten once their parameters are bound.
+        other => other.clone(),
+    }
+}
+
+/// Rebuild the direct children of `expr` through `rule`, keeping `expr` itself
+pub fn walk_expression<R>(rule: &mut R, expr: &Expression) -> Expression
+where
+    R: AstVisitor<StatementOutput = Statement, ExpressionOutput = Expression> + ?Sized,
+{
+    match expr {
+        Expression::BinaryOp {
+            left,
+            operator,
+            right,
+        } => Expression::BinaryOp {
+            left: Box::new(rule.visit_expression(left)),
+            operator: operator.clone(),
+            right: Box::new(rule.visit_expression(right)),
+        },
+        Expression::UnaryOp { operator, operand } => Expression::UnaryOp {
+            operator: operator.clone(),
+            operand: Box::new(rule.visit_expression(operand)),
+        },
+        Expression::Function { name, args } => Expression::Function {
+            name: name.clone(),
+            args: args.iter().map(|a| rule.visit_expression(a)).collect(),
+        },
+        Expression::Case {
+            when_clauses,
+            else_clause,
+        } => Expression::Case {
+            when_clauses: when_clauses
+                .iter()
+                .map(|(when, then)| (rule.visit_expression(when), rule.visit_expression(then)))
+                .collect(),
+            else_clause: else_clause.as_ref().map(|e| Box::new(rule.visit_expression(e))),
+        },
+        Expression::Subquery(query) => Expression::Subquery(Box::new(rule.visit_statement(query))),
+        Expression::Alias { expr, alias } => Expression::Alias {
+            expr: Box::new(rule.visit_expression(expr)),
+            alias: alias.clone(),
+        },
+        Expression::Literal(_)
+        | Expression::Identifier(_)
+        | Expression::QualifiedColumn { .. }
+        | Expression::Parameter(_) => expr.clone(),
+    }
+}
+
+fn walk_table_reference<R>(rule: &mut R, table_ref: &TableReference) -> TableReference
+where
+    R: AstVisitor<StatementOutput = Statement, ExpressionOutput = Expression> + ?Sized,
+{
+    match table_ref {
+        TableReference::Table { .. } => table_ref.clone(),
+        TableReference::Subquery { query, alias } => TableReference::Subquery {
+            query: Box::new(rule.visit_statement(query)),
+            alias: alias.clone(),
+        },
+    }
+}
+
+fn walk_assignments<R>(rule: &mut R, assignments: &[Assignment]) -> Vec<Assignment>
+where
+    R: AstVisitor<StatementOutput = Statement, ExpressionOutput = Expression> + ?Sized,
+{
+    assignments
+        .iter()
+        .map(|a| Assignment {
+            column: a.column.clone(),
+            value: rule.visit_expression(&a.value),
+        })
+        .collect()
+}
+
+fn walk_list<R>(rule: &mut R, exprs: Option<&[Expression]>) -> Option<Vec<Expression>>
+where
+    R: AstVisitor<StatementOutput = Statement, ExpressionOutput = Expression> + ?Sized,
+{
+    exprs.map(|exprs| exprs.iter().map(|e| rule.visit_expression(e)).collect())
+}
+
+/// Split a predicate into its AND-ed parts
+pub fn conjuncts(expr: &Expression) -> Vec<Expression> {
+    match expr {
+        Expression::BinaryOp {
+            left,
+            operator: BinaryOperator::And,
+            right,
+        } => {
+            let mut parts = conjuncts(left);
+            parts.extend(conjuncts(right));
+            parts
+        }
+        _ => vec![expr.clone()],
+    }
+}
+
+/// AND the parts back together (None when there are none)
+pub fn conjoin(parts: Vec<Expression>) -> Option<Expression> {
+    parts.into_iter().reduce(|left, right| Expression::BinaryOp {
+        left: Box::new(left),
+        operator: BinaryOperator::And,
+        right: Box::new(right),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::sql::parser::SqlParser;
+
+    fn parse(sql: &str) -> Statement {
+        SqlParser::parse_statement(sql).unwrap()
+    }
+
+    #[test]
+    fn test_default_rewriter_rule_order() {
+        assert_eq!(
+            Rewriter::default().rule_names(),
+            vec!["simplify_between", "constant_folding", "predicate_pushdown", "projection_pruning"]
+        );
+    }
+
+    #[test]
+    fn test_rewriter_applies_rules_inside_subqueries() {
+        let rewritten = Rewriter::default().rewrite(&parse(
+            "WITH c AS (SELECT a FROM t WHERE a > 1 + 1) SELECT * FROM c UNION SELECT b FROM u WHERE 2 * 3 = b",
+        ));
+        assert_eq!(
+            rewritten,
+            parse("WITH c AS (SELECT a FROM t WHERE a > 2) SELECT * FROM c UNION SELECT b FROM u WHERE 6 = b")
+        );
+    }
+
+    #[test]
+    fn test_rewritten_join_returns_same_rows() {
+        use crate::db::executor::ExecutionResult;
+        use crate::db::sql::execute_sql;
+
+        execute_sql("DROP TABLE IF EXISTS opt_orders");
+        execute_sql("DROP TABLE IF EXISTS opt_users");
+        execute_sql("CREATE TABLE opt_users (id INT, name TEXT)");
+        execute_sql("CREATE TABLE opt_orders (user_id INT, total INT)");
+        execute_sql("INSERT INTO opt_users VALUES (1, 'a'), (2, 'b'), (3, 'c')");
+        execute_sql("INSERT INTO opt_orders VALUES (1, 10), (1, 50), (2, 70)");
+
+        let sql = "SELECT u.name, o.total FROM opt_users u LEFT JOIN opt_orders o ON u.id = o.user_id \
+                   WHERE u.id BETWEEN 1 AND 1 + 1 AND o.total > 20 ORDER BY u.name";
+        match execute_sql(sql) {
+            ExecutionResult::Rows { rows, .. } => {
+                let totals: Vec<&serde_json::Value> = rows.iter().map(|r| &r["total"]).collect();
+                assert_eq!(totals, vec![50, 70]);
+            }
+            other => panic!("Expected rows, got {:?}", other),
+        }
+
+        // The filter on the preserved side now runs below the join
+        match execute_sql(&format!("EXPLAIN {}", sql)) {
+            ExecutionResult::Rows { rows, .. } => {
+                let lines: Vec<&str> = rows.iter().map(|r| r["QUERY PLAN"].as_str().unwrap()).collect();
+                let join = lines.iter().position(|l| l.contains("Join")).unwrap();
+                let pushed = lines.iter().position(|l| l.contains("Filter ((u.id >= 1) AND (u.id <= 2))"));
+                assert!(pushed.is_some_and(|p| p > join));
+            }
+            other => panic!("Expected rows, got {:?}", other),
+        }
+
+        execute_sql("DROP TABLE opt_orders");
+        execute_sql("DROP TABLE opt_users");
+    }
+
+    #[test]
+    fn test_conjuncts_round_trip() {
+        let expr = match parse("SELECT 1 WHERE a = 1 AND (b = 2 AND c = 3)") {
+            Statement::Select { where_clause, .. } => where_clause.unwrap(),
+            _ => unreachable!(),
+        };
+        let parts = conjuncts(&expr);
+        assert_eq!(parts.len(), 3);
+        assert_eq!(conjuncts(&conjoin(parts.clone()).unwrap()), parts);
+        assert_eq!(conjoin(Vec::new()), None);
+    }
+}
@@ -0,0 +1,240 @@
+// Predicate pushdown - filter join inputs before they are joined
+use super::{conjoin, conjuncts, walk_statement, RewriteRule};
+use crate::db::sql::constants::{Join, JoinType, Statement, TableReference};
+use crate::db::sql::parser::{AstVisitor, Expression};
+
+/// Moves WHERE conjuncts that only read columns of one FROM/JOIN item below the
+/// join, by wrapping that item in `(SELECT * FROM item WHERE ...) qualifier`.
+/// Only `qualifier.column` references are attributed to an item, and items on
+/// the NULL-padded side of an outer join are never filtered early (that would
+/// turn filtered-out rows into padded ones).
+pub struct PredicatePushdown;
+
+impl RewriteRule for PredicatePushdown {
+    fn name(&self) -> &'static str {
+        "predicate_pushdown"
+    }
+}
+
+impl AstVisitor for PredicatePushdown {
+    type StatementOutput = Statement;
+    type ExpressionOutput = Expression;
+
+    fn visit_statement(&mut self, stmt: &Statement) -> Statement {
+        match walk_statement(self, stmt) {
+            Statement::Select {
+                projection,
+                from: Some(from),
+                joins,
+                where_clause: Some(predicate),
+                group_by,
+                having,
+                order_by,
+                limit,
+                offset,
+                distinct,
+            } if !joins.is_empty() => {
+                let (from, joins, where_clause) = push_down(from, joins, &predicate);
+                Statement::Select {
+                    projection,
+                    from: Some(from),
+                    joins,
+                    where_clause,
+                    group_by,
+                    having,
+                    order_by,
+                    limit,
+                    offset,
+                    distinct,
+                }
+            }
+            other => other,
+        }
+    }
+
+    fn visit_expression(&mut self, expr: &Expression) -> Expression {
+        super::walk_expression(self, expr)
+    }
+}
+
+fn qualifier(table_ref: &TableReference) -> &str {
+    match table_ref {
+        TableReference::Table { name, alias } => alias.as_deref().unwrap_or(name),
+        TableReference::Subquery { alias, .. } => alias,
+    }
+}
+
+/// Whether rows of item `index` (0 = FROM, i = i-th JOIN) may be filtered
+/// before joining: no join may pad that side with NULLs
+fn can_filter_early(index: usize, joins: &[Join]) -> bool {
+    let padded_by_own_join =
+        index > 0 && matches!(joins[index - 1].join_type, JoinType::Left | JoinType::Full);
+    let padded_by_later_join = joins
+        .iter()
+        .skip(index)
+        .any(|j| matches!(j.join_type, JoinType::Right | JoinType::Full));
+    !padded_by_own_join && !padded_by_later_join
+}
+
+/// The single qualifier every column in `expr` is written with, if any
+fn single_qualifier(expr: &Expression) -> Option<String> {
+    let mut found: Option<String> = None;
+    let mut pushable = true;
+    collect_qualifiers(expr, &mut found, &mut pushable);
+    found.filter(|_| pushable)
+}
+
+fn collect_qualifiers(expr: &Expression, found: &mut Option<String>, pushable: &mut bool) {
+    match expr {
+        Expression::QualifiedColumn { table, .. } => match found {
+            Some(existing) if existing != table => *pushable = false,
+            Some(_) => {}
+            None => *found = Some(table.clone()),
+        },
+        // Unqualified names could belong to any item; subqueries may see the whole row
+        Expression::Identifier(_) | Expression::Subquery(_) => *pushable = false,
+        Expression::BinaryOp { left, right, .. } => {
+            collect_qualifiers(left, found, pushable);
+            collect_qualifiers(right, found, pushable);
+        }
+        Expression::UnaryOp { operand, .. } => collect_qualifiers(operand, found, pushable),
+        Expression::Function { args, .. } => {
+            args.iter().for_each(|a| collect_qualifiers(a, found, pushable))
+        }
+        Expression::Case {
+            when_clauses,
+            else_clause,
+        } => {
+            for (when, then) in when_clauses {
+                collect_qualifiers(when, found, pushable);
+                collect_qualifiers(then, found, pushable);
+            }
+            if let Some(else_expr) = else_clause {
+                collect_qualifiers(else_expr, found, pushable);
+            }
+        }
+        Expression::Alias { expr, .. } => collect_qualifiers(expr, found, pushable),
+        Expression::Literal(_) | Expression::Parameter(_) => {}
+    }
+}
+
+/// Wrap `table_ref` so only rows matching `predicate` come out, under the same qualifier
+fn filtered(table_ref: TableReference, predicate: Expression) -> TableReference {
+    let alias = qualifier(&table_ref).to_string();
+    TableReference::Subquery {
+        query: Box::new(Statement::Select {
+            projection: vec![Expression::Identifier("*".to_string())],
+            from: Some(table_ref),
+            joins: Vec::new(),
+            where_clause: Some(predicate),
+            group_by: Vec::new(),
+            having: None,
+            order_by: Vec::new(),
+            limit: None,
+            offset: None,
+            distinct: false,
+        }),
+        alias,
+    }
+}
+
+fn push_down(
+    from: TableReference,
+    joins: Vec<Join>,
+    predicate: &Expression,
+) -> (TableReference, Vec<Join>, Option<Expression>) {
+    let qualifiers: Vec<String> = std::iter::once(&from)
+        .chain(joins.iter().map(|j| &j.table))
+        .map(|t| qualifier(t).to_string())
+        .collect();
+
+    let mut pushed: Vec<Vec<Expression>> = vec![Vec::new(); qualifiers.len()];
+    let mut remaining = Vec::new();
+    for conjunct in conjuncts(predicate) {
+        let target = single_qualifier(&conjunct).and_then(|q| {
+            let mut matches = qualifiers.iter().enumerate().filter(|(_, name)| **name == q);
+            match (matches.next(), matches.next()) {
+                // An ambiguous qualifier (self-join without aliases) stays put
+                (Some((index, _)), None) => Some(index),
+                _ => None,
+            }
+        });
+        match target {
+            Some(index) if can_filter_early(index, &joins) => pushed[index].push(conjunct),
+            _ => remaining.push(conjunct),
+        }
+    }
+
+    let mut pushed = pushed.into_iter();
+    let from = match conjoin(pushed.next().unwrap_or_default()) {
+        Some(filter) => filtered(from, filter),
+        None => from,
+    };
+    let joins = joins
+        .into_iter()
+        .zip(pushed)
+        .map(|(join, parts)| match conjoin(parts) {
+            Some(filter) => Join {
+                table: filtered(join.table, filter),
+                ..join
+            },
+            None => join,
+        })
+        .collect();
+
+    (from, joins, conjoin(remaining))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::sql::parser::SqlParser;
+
+    fn push_sql(sql: &str) -> Statement {
+        PredicatePushdown.visit_statement(&SqlParser::parse_statement(sql).unwrap())
+    }
+
+    fn parse(sql: &str) -> Statement {
+        SqlParser::parse_statement(sql).unwrap()
+    }
+
+    #[test]
+    fn test_pushes_single_table_conjuncts_below_inner_join() {
+        assert_eq!(
+            push_sql(
+                "SELECT * FROM a JOIN b x ON a.id = x.a_id \
+                 WHERE a.n > 1 AND x.m = 2 AND a.n < x.m AND a.k = 3"
+            ),
+            parse(
+                "SELECT * FROM (SELECT * FROM a WHERE a.n > 1 AND a.k = 3) a \
+                 JOIN (SELECT * FROM b x WHERE x.m = 2) x ON a.id = x.a_id WHERE a.n < x.m"
+            )
+        );
+    }
+
+    #[test]
+    fn test_keeps_predicates_on_null_padded_side() {
+        assert_eq!(
+            push_sql("SELECT * FROM a LEFT JOIN b ON a.id = b.a_id WHERE b.m = 1"),
+            parse("SELECT * FROM a LEFT JOIN b ON a.id = b.a_id WHERE b.m = 1")
+        );
+        assert_eq!(
+            push_sql("SELECT * FROM a RIGHT JOIN b ON a.id = b.a_id WHERE a.n = 1"),
+            parse("SELECT * FROM a RIGHT JOIN b ON a.id = b.a_id WHERE a.n = 1")
+        );
+        assert_eq!(
+            push_sql("SELECT * FROM a LEFT JOIN b ON a.id = b.a_id WHERE a.n = 1"),
+            parse("SELECT * FROM (SELECT * FROM a WHERE a.n = 1) a LEFT JOIN b ON a.id = b.a_id")
+        );
+    }
+
+    #[test]
+    fn test_leaves_unqualified_and_single_table_queries() {
+        for sql in [
+            "SELECT * FROM a JOIN b ON a.id = b.a_id WHERE n > 1",
+            "SELECT * FROM a WHERE a.n > 1",
+        ] {
+            assert_eq!(push_sql(sql), parse(sql));
+        }
+    }
+}
@@ -0,0 +1,205 @@
+// Projection pruning - drop derived-table columns the outer query never reads
+use std::collections::HashSet;
+
+use super::{walk_expression, walk_statement, RewriteRule};
+use crate::db::executor::Executor;
+use crate::db::planner::contains_aggregate;
+use crate::db::sql::constants::{Statement, TableReference};
+use crate::db::sql::parser::{AstVisitor, Expression};
+
+/// Removes items from a derived table's SELECT list (`FROM (SELECT ...) alias`)
+/// when the enclosing query references neither `alias.column` nor a bare
+/// `column` of that name. Derived tables are left alone when their output is
+/// shaped by every item (DISTINCT, UNION), their ORDER BY may name an item, or
+/// dropping items could change their row count (aggregates without GROUP BY).
+pub struct ProjectionPruning;
+
+impl RewriteRule for ProjectionPruning {
+    fn name(&self) -> &'static str {
+        "projection_pruning"
+    }
+}
+
+impl AstVisitor for ProjectionPruning {
+    type StatementOutput = Statement;
+    type ExpressionOutput = Expression;
+
+    fn visit_statement(&mut self, stmt: &Statement) -> Statement {
+        let mut stmt = walk_statement(self, stmt);
+        if let Statement::Select {
+            projection,
+            from,
+            joins,
+            where_clause,
+            group_by,
+            having,
+            order_by,
+            ..
+        } = &mut stmt
+        {
+            if projection.iter().any(is_star) {
+                return stmt;
+            }
+
+            let mut referenced = References::default();
+            for expr in projection
+                .iter()
+                .chain(where_clause.iter())
+                .chain(group_by.iter())
+                .chain(having.iter())
+                .chain(order_by.iter().map(|o| &o.expression))
+                .chain(joins.iter().filter_map(|j| j.condition.as_ref()))
+            {
+                referenced.collect(expr);
+            }
+
+            for table_ref in from.iter_mut().chain(joins.iter_mut().map(|j| &mut j.table)) {
+                if let TableReference::Subquery { query, alias } = table_ref {
+                    prune(query, alias, &referenced);
+                }
+            }
+        }
+        stmt
+    }
+
+    fn visit_expression(&mut self, expr: &Expression) -> Expression {
+        walk_expression(self, expr)
+    }
+}
+
+fn is_star(expr: &Expression) -> bool {
+    matches!(expr, Expression::Identifier(name) if name == "*")
+}
+
+/// Column names an outer query reads: bare names, and qualified ones by qualifier
+#[derive(Default)]
+struct References {
+    bare: HashSet<String>,
+    qualified: HashSet<(String, String)>,
+}
+
+impl References {
+    fn collect(&mut self, expr: &Expression) {
+        match expr {
+            Expression::Identifier(name) => {
+                self.bare.insert(name.clone());
+            }
+            Expression::QualifiedColumn { table, column } => {
+                self.qualified.insert((table.clone(), column.clone()));
+            }
+            Expression::BinaryOp { left, right, .. } => {
+                self.collect(left);
+                self.collect(right);
+            }
+            Expression::UnaryOp { operand, .. } => self.collect(operand),
+            Expression::Function { args, .. } => args.iter().for_each(|a| self.collect(a)),
+            Expression::Case {
+                when_clauses,
+                else_clause,
+            } => {
+                for (when, then) in when_clauses {
+                    self.collect(when);
+                    self.collect(then);
+                }
+                if let Some(else_expr) = else_clause {
+                    self.collect(else_expr);
+                }
+            }
+            Expression::Alias { expr, .. } => self.collect(expr),
+            Expression::Subquery(_) | Expression::Literal(_) | Expression::Parameter(_) => {}
+        }
+    }
+
+    fn reads(&self, alias: &str, column: &str) -> bool {
+        self.bare.contains(column) || self.qualified.contains(&(alias.to_string(), column.to_string()))
+    }
+}
+
+fn prune(query: &mut Statement, alias: &str, referenced: &References) {
+    let (projection, group_by, order_by, distinct) = match query {
+        Statement::Select {
+            projection,
+            group_by,
+            order_by,
+            distinct,
+            ..
+        } => (projection, group_by, order_by, *distinct),
+        _ => return,
+    };
+    if distinct
+        || !order_by.is_empty()
+        || projection.iter().any(is_star)
+        || (group_by.is_empty() && projection.iter().any(contains_aggregate))
+    {
+        return;
+    }
+
+    let mut kept = Vec::new();
+    for (i, expr) in projection.iter().enumerate() {
+        let name = Executor::output_column_name(expr, i);
+        if !referenced.reads(alias, &name) {
+            continue;
+        }
+        // Positional names ("column2") depend on the item's index; pin them
+        kept.push(if name == Executor::output_column_name(expr, kept.len()) {
+            expr.clone()
+        } else {
+            Expression::Alias {
+                expr: Box::new(expr.clone()),
+                alias: name,
+            }
+        });
+    }
+
+    // Keep at least one column so the derived table still has a shape
+    if kept.is_empty() {
+        kept.push(projection[0].clone());
+    }
+    *projection = kept;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::sql::parser::SqlParser;
+
+    fn prune_sql(sql: &str) -> Statement {
+        ProjectionPruning.visit_statement(&SqlParser::parse_statement(sql).unwrap())
+    }
+
+    fn parse(sql: &str) -> Statement {
+        SqlParser::parse_statement(sql).unwrap()
+    }
+
+    #[test]
+    fn test_prunes_unread_derived_table_columns() {
+        assert_eq!(
+            prune_sql("SELECT s.a FROM (SELECT a, b, c + 1 AS d FROM t) s WHERE d > 1"),
+            parse("SELECT s.a FROM (SELECT a, c + 1 AS d FROM t) s WHERE d > 1")
+        );
+        assert_eq!(
+            prune_sql("SELECT column1 FROM (SELECT a, b + 1 FROM t) s"),
+            parse("SELECT column1 FROM (SELECT b + 1 AS column1 FROM t) s")
+        );
+    }
+
+    #[test]
+    fn test_keeps_columns_when_pruning_is_unsafe() {
+        for sql in [
+            "SELECT * FROM (SELECT a, b FROM t) s",
+            "SELECT a FROM (SELECT DISTINCT a, b FROM t) s",
+            "SELECT a FROM (SELECT a, b AS x FROM t ORDER BY x) s",
+            "SELECT a FROM (SELECT 1 AS a, COUNT(*) AS n FROM t) s",
+        ] {
+            assert_eq!(prune_sql(sql), parse(sql));
+        }
+    }
+
+    #[test]
+    fn test_prunes_grouped_aggregates() {
+        assert_eq!(
+            prune_sql("SELECT s.dept FROM (SELECT dept, COUNT(*) AS n FROM t GROUP BY dept) s"),
+            parse("SELECT s.dept FROM (SELECT dept FROM t GROUP BY dept) s")
+        );
+    }
+}
@@ -1,5 +1,14 @@
 use crate::{
-    db::http::{HttpResponse, handleClient},
+    config::get_config,
+    db::{
+        cdc,
+        database::default_dir,
+        grpc,
+        http::{self, Limits},
+        pgwire,
+        pool::POOL,
+        resp::{self, store::KvStore},
+    },
     error,
     hashing::aes,
     info, warn,
@@ -9,8 +18,6 @@ use std::{
     collections::BTreeMap,
     fs::OpenOptions,
     io::Error,
-    net::{TcpListener, TcpStream},
-    ops::Deref,
     path::Path,
     process::exit,
     sync::{Arc, Mutex},
@@ -20,7 +27,7 @@ use std::{
     net::SocketAddr,
     thread,
 };
-use tokio::{fs, task};
+use tokio::{fs, net::TcpListener, task};
 
 // ------------------------------------------------------------------------
 // --------------- Structs ------------------------------------------------
@@ -39,6 +46,17 @@ pub struct DataBaseClient {
     throttle: u32,
 }
 
+/// Socket address for `bind_address:port`, logging why there is none
+fn listen_address(bind_address: &str, port: u16) -> Option<SocketAddr> {
+    match format!("{}:{}", bind_address, port).parse() {
+        Ok(addr) => Some(addr),
+        Err(err) => {
+            warn!(format!("Invalid listen address {}:{}: {}", bind_address, port, err));
+            None
+        }
+    }
+}
+
 // --------------------------------------------------------------------------------
 // Implementations ----------------------------------------------------------------
 impl PartitionServer {
@@ -50,7 +68,7 @@ impl PartitionServer {
             .expect("Invalid address");
 
         // Attempt to bind to the port. The `bind` call itself checks for availability.
-        let listener = match TcpListener::bind(addr) {
+        let listener = match TcpListener::bind(addr).await {
             Ok(listener) => {
                 info!(format!("Server listening on {}", addr));
                 listener
@@ -78,13 +96,28 @@ impl PartitionServer {
             };
         }
 
-        // The loop is where accept and handle incoming connections
+        // Every connection gets its own task, so a slow client holds up
+        // nobody else
+        let limits = Arc::new(Limits::default());
         loop {
-            // `accept()` waits for a client to connect. It returns a Result<(TcpStream, SocketAddr), Error>.
-            let (stream, remote_addr) = listener.accept().unwrap();
-            println!("Accepted connection from: {}", remote_addr);
-            print!("Handling client now");
-            handleClient(stream).await?;
+            let (stream, remote_addr) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!(format!("Failed to accept a connection on port {}: {}", self.port, e));
+                    continue;
+                }
+            };
+            let limits = Arc::clone(&limits);
+            task::spawn(async move {
+                // The connection holds a pool slot for as long as it is open
+                let served = match POOL.acquire().await {
+                    Ok(mut guard) => http::handle_client(stream, &limits, &mut guard).await,
+                    Err(e) => http::reject_busy(stream, &e, POOL.retry_after()).await,
+                };
+                if let Err(e) = served {
+                    warn!(format!("Connection from {} failed: {}", remote_addr, e));
+                }
+            });
         }
 
         #[allow(unreachable_code)]
@@ -132,7 +165,7 @@ impl DataBaseClient {
         // ---------------------------------------------------------------
         let leader = PartitionServer {
             port: 1231,
-            data: None,
+            data: Some(Arc::new(Mutex::new(BTreeMap::new()))),
             leader_port: 1231,
         };
         DataBaseClient {
@@ -177,6 +210,47 @@ impl DataBaseClient {
         });
         tasks.push(task_handle);
 
+        match get_config() {
+            Ok(config) => {
+                // Change log consumers, fed from the first commit on
+                tasks.extend(cdc::start(&config.cdc));
+
+                let network = config.network;
+                // Postgres clients (psql, drivers) connect on their own port
+                if let Some(addr) = listen_address(&network.bind_address, network.pg_port) {
+                    tasks.push(task::spawn(async move {
+                        if let Err(err) = pgwire::serve(addr).await {
+                            warn!(format!("Postgres listener on {} failed: {}", addr, err));
+                        }
+                    }));
+                }
+
+                // Typed gRPC API
+                if let Some(addr) = listen_address(&network.bind_address, network.grpc_port) {
+                    tasks.push(task::spawn(async move {
+                        if let Err(err) = grpc::serve(addr).await {
+                            warn!(format!("gRPC listener on {} failed: {}", addr, err));
+                        }
+                    }));
+                }
+
+                // Redis clients read and write the leader's partition map,
+                // kept durable by a log of its own
+                let log_path = default_dir().join("kv").join(format!("partition_{}.log", self.leader.port));
+                if let (Some(data), Some(addr)) = (&self.leader.data, listen_address(&network.bind_address, network.port)) {
+                    match KvStore::open(Arc::clone(data), &log_path) {
+                        Ok(store) => tasks.push(task::spawn(async move {
+                            if let Err(err) = resp::serve(addr, Arc::new(store)).await {
+                                warn!(format!("Redis listener on {} failed: {}", addr, err));
+                            }
+                        })),
+                        Err(err) => warn!(format!("Failed to open {}: {}", log_path.display(), err)),
+                    }
+                }
+            }
+            Err(err) => warn!(format!("No protocol listeners, config not loaded: {}", err)),
+        }
+
         for server in &self.servers {
             let server_clone = server.clone();
 
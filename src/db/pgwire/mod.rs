@@ -0,0 +1,1007 @@
+// PostgreSQL wire protocol (v3) front end - lets psql, BI tools and drivers
+// run statements through the simple and extended query flows
+pub mod protocol;
+pub mod types;
+
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Arc;
+
+use rand::Rng;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, BufWriter};
+use tokio::net::TcpListener;
+
+use crate::db::catalog;
+use crate::db::executor::{ExecutionResult, Executor};
+use crate::db::pool::{ConnectionPool, POOL};
+use crate::db::session::{PreparedStatement, Session};
+use crate::db::sql::{SqlParser, Statement, TableReference, TransactionStatement};
+use crate::db::storage::Value;
+use crate::{info, warn};
+use protocol::{FieldDescription, FrontendMessage, StartupRequest};
+
+/// Version reported to clients; drivers pick features by it
+pub const SERVER_VERSION: &str = "14.0";
+
+/// Process ids handed out in BackendKeyData
+static NEXT_PROCESS_ID: AtomicI32 = AtomicI32::new(1);
+
+/// Accept Postgres clients on `addr`, each connection in its own task
+pub async fn serve(addr: SocketAddr) -> Result<(), Error> {
+    let listener = TcpListener::bind(addr).await?;
+    info!(format!("Postgres protocol listening on {}", addr));
+    loop {
+        let (stream, remote_addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!(format!("Failed to accept a Postgres connection: {}", e));
+                continue;
+            }
+        };
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, &POOL).await {
+                warn!(format!("Postgres connection from {} failed: {}", remote_addr, e));
+            }
+        });
+    }
+}
+
+/// Run one client connection from startup to Terminate. The connection holds
+/// a slot in `pool` once started up.
+pub async fn handle_connection<S>(stream: S, pool: &ConnectionPool) -> Result<(), Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (reader, writer) = tokio::io::split(stream);
+    let mut reader = BufReader::new(reader);
+    let mut writer = BufWriter::new(writer);
+
+    let params = loop {
+        match protocol::read_startup(&mut reader).await? {
+            // Nothing to cancel: statements are not interruptible
+            None | Some(StartupRequest::Cancel) => return Ok(()),
+            // No TLS or GSSAPI; the client carries on in plain text
+            Some(StartupRequest::Ssl | StartupRequest::GssEncryption) => {
+                writer.write_all(b"N").await?;
+                writer.flush().await?;
+            }
+            Some(StartupRequest::Startup { params }) => break params,
+        }
+    };
+
+    let _guard = match pool.acquire().await {
+        Ok(guard) => guard,
+        Err(e) => {
+            writer.write_all(&protocol::fatal_response("53300", &e)).await?;
+            return writer.flush().await;
+        }
+    };
+
+    let user = params.get("user").cloned().unwrap_or_default();
+    let mut out = protocol::authentication_ok();
+    for (name, value) in [
+        ("server_version", SERVER_VERSION),
+        ("server_encoding", "UTF8"),
+        ("client_encoding", "UTF8"),
+        ("DateStyle", "ISO, MDY"),
+        ("TimeZone", "UTC"),
+        ("integer_datetimes", "on"),
+        ("standard_conforming_strings", "on"),
+        ("session_authorization", &user),
+        ("application_name", params.get("application_name").map_or("", String::as_str)),
+    ] {
+        out.extend(protocol::parameter_status(name, value));
+    }
+    let process_id = NEXT_PROCESS_ID.fetch_add(1, Ordering::Relaxed);
+    out.extend(protocol::backend_key_data(process_id, rand::rng().random()));
+    out.extend(protocol::ready_for_query(b'I'));
+    writer.write_all(&out).await?;
+    writer.flush().await?;
+
+    let mut connection = Connection::new(process_id);
+    loop {
+        // Notifications go out as they arrive while the client is idle
+        tokio::select! {
+            buffered = reader.fill_buf() => {
+                if buffered?.is_empty() {
+                    return Ok(());
+                }
+            }
+            notification = connection.next_notification() => {
+                writer.write_all(&notification).await?;
+                writer.flush().await?;
+                continue;
+            }
+        }
+        let message = match protocol::read_message(&mut reader).await {
+            Ok(Some(FrontendMessage::Terminate)) | Ok(None) => return Ok(()),
+            Ok(Some(message)) => message,
+            Err(e) if e.kind() == ErrorKind::InvalidData => {
+                writer.write_all(&protocol::fatal_response("08P01", &e.to_string())).await?;
+                return writer.flush().await;
+            }
+            Err(e) => return Err(e),
+        };
+        let flush = matches!(message, FrontendMessage::Query(_) | FrontendMessage::Sync | FrontendMessage::Flush);
+        connection.handle(message).await;
+        writer.write_all(&std::mem::take(&mut connection.out)).await?;
+        if flush {
+            writer.flush().await?;
+        }
+    }
+}
+
+/// What a query string holds once parsed
+#[derive(Debug, Clone)]
+enum Query<S> {
+    /// Nothing but whitespace and semicolons
+    Empty,
+    /// A SET of a session setting; accepted and ignored
+    Set,
+    Statement(S),
+}
+
+/// A statement prepared by Parse
+struct Parsed {
+    query: Query<Arc<PreparedStatement>>,
+    /// Type of each parameter; `types::UNSPECIFIED` where the client left it open
+    param_types: Vec<i32>,
+}
+
+/// A statement bound by Bind, run on its first Describe or Execute
+struct Portal {
+    query: Query<Statement>,
+    result_formats: Vec<i16>,
+    outcome: Option<Outcome>,
+    /// Rows already sent by earlier Executes
+    sent: usize,
+}
+
+#[derive(Debug, Clone)]
+enum Outcome {
+    Rows {
+        columns: Vec<String>,
+        types: Vec<i32>,
+        rows: Vec<Vec<Value>>,
+        tag: String,
+    },
+    Done {
+        tag: String,
+        notice: Option<String>,
+    },
+    Failed(String),
+}
+
+/// Per-connection protocol state
+struct Connection {
+    /// Taken while a statement runs on a blocking thread
+    session: Option<Session>,
+    statements: HashMap<String, Parsed>,
+    portals: HashMap<String, Portal>,
+    /// Sent in BackendKeyData, and as the sender of the session's NOTIFYs
+    process_id: i32,
+    /// An extended-protocol message failed; the rest up to Sync are skipped
+    failed: bool,
+    /// Messages waiting to be written
+    out: Vec<u8>,
+}
+
+impl Connection {
+    fn new(process_id: i32) -> Self {
+        let mut session = Session::new();
+        session.enable_notifications(process_id);
+        Self {
+            session: Some(session),
+            statements: HashMap::new(),
+            portals: HashMap::new(),
+            process_id,
+            failed: false,
+            out: Vec::new(),
+        }
+    }
+
+    fn in_transaction(&self) -> bool {
+        self.session.as_ref().is_some_and(Session::in_transaction)
+    }
+
+    /// The next notification for an idle connection, as a message. Inside a
+    /// transaction block notifications wait for it to end.
+    async fn next_notification(&mut self) -> Vec<u8> {
+        match self.session.as_mut() {
+            Some(session) if !session.in_transaction() => {
+                let notification = session.next_notification().await;
+                protocol::notification_response(notification.process_id, &notification.channel, &notification.payload)
+            }
+            _ => std::future::pending().await,
+        }
+    }
+
+    async fn handle(&mut self, message: FrontendMessage) {
+        match message {
+            FrontendMessage::Query(sql) => self.simple_query(&sql).await,
+            FrontendMessage::Sync => {
+                self.failed = false;
+                // Autocommit ends the implicit transaction, and its portals with it
+                if !self.in_transaction() {
+                    self.portals.clear();
+                }
+                self.ready_for_query();
+            }
+            FrontendMessage::Flush | FrontendMessage::Terminate => {}
+            _ if self.failed => {}
+            message => {
+                if let Err(message) = self.extended(message).await {
+                    self.error(&message);
+                    self.failed = true;
+                }
+            }
+        }
+    }
+
+    fn error(&mut self, message: &str) {
+        self.out.extend(protocol::error_response(types::sqlstate(message), message));
+    }
+
+    /// Signal the end of a query, after any notifications that arrived for
+    /// the session outside a transaction block
+    fn ready_for_query(&mut self) {
+        let in_transaction = self.in_transaction();
+        if let (Some(session), false) = (self.session.as_mut(), in_transaction) {
+            while let Some(notification) = session.try_notification() {
+                self.out.extend(protocol::notification_response(
+                    notification.process_id,
+                    &notification.channel,
+                    &notification.payload,
+                ));
+            }
+        }
+        self.out.extend(protocol::ready_for_query(if in_transaction { b'T' } else { b'I' }));
+    }
+
+    async fn simple_query(&mut self, sql: &str) {
+        self.statements.remove("");
+        self.portals.remove("");
+        match parse_query(sql) {
+            Err(message) => self.error(&message),
+            Ok(queries) => {
+                for query in queries {
+                    let outcome = match query {
+                        Query::Empty => {
+                            self.out.extend(protocol::empty(b'I'));
+                            continue;
+                        }
+                        Query::Set => Outcome::Done {
+                            tag: "SET".to_string(),
+                            notice: None,
+                        },
+                        Query::Statement(stmt) => self.execute(stmt).await,
+                    };
+                    if let Outcome::Rows { columns, types, .. } = &outcome {
+                        let fields = fields(columns, types, &[]);
+                        self.out.extend(protocol::row_description(&fields));
+                    }
+                    if !self.send_outcome(&outcome, &[], 0, 0) {
+                        break;
+                    }
+                }
+            }
+        }
+        self.ready_for_query();
+    }
+
+    /// Send the rows of `outcome` from `start` (at most `max_rows` of them if
+    /// positive) and then its completion. Returns whether the statement
+    /// succeeded and all its rows went out.
+    fn send_outcome(&mut self, outcome: &Outcome, formats: &[i16], start: usize, max_rows: usize) -> bool {
+        match outcome {
+            Outcome::Failed(message) => {
+                self.error(message);
+                false
+            }
+            Outcome::Done { tag, notice } => {
+                if let Some(notice) = notice {
+                    self.out.extend(protocol::notice_response("01000", notice));
+                }
+                self.out.extend(protocol::command_complete(tag));
+                true
+            }
+            Outcome::Rows { types, rows, tag, .. } => {
+                let end = match max_rows {
+                    0 => rows.len(),
+                    n => rows.len().min(start + n),
+                };
+                for row in &rows[start.min(end)..end] {
+                    match encode_row(row, types, formats) {
+                        Ok(values) => self.out.extend(protocol::data_row(&values)),
+                        Err(message) => {
+                            self.error(&message);
+                            return false;
+                        }
+                    }
+                }
+                if end < rows.len() {
+                    self.out.extend(protocol::empty(b's'));
+                    return false;
+                }
+                self.out.extend(protocol::command_complete(tag));
+                true
+            }
+        }
+    }
+
+    /// Run a statement off the async runtime
+    async fn execute(&mut self, stmt: Statement) -> Outcome {
+        if let Statement::Transaction(transaction) = &stmt {
+            return self.transaction(transaction);
+        }
+        let Some(mut session) = self.session.take() else {
+            return Outcome::Failed("Connection has no session".to_string());
+        };
+        let ran = tokio::task::spawn_blocking(move || {
+            let result = session.execute(&stmt);
+            let outcome = outcome(&session, &stmt, result);
+            (session, outcome)
+        })
+        .await;
+        match ran {
+            Ok((session, outcome)) => {
+                self.session = Some(session);
+                outcome
+            }
+            Err(_) => {
+                let mut session = Session::new();
+                session.enable_notifications(self.process_id);
+                self.session = Some(session);
+                Outcome::Failed("Statement execution panicked".to_string())
+            }
+        }
+    }
+
+    /// Statements run and commit one at a time, so a transaction block only
+    /// changes the status reported to the client and holds back NOTIFYs.
+    /// ROLLBACK after a write fails with feature_not_supported.
+    fn transaction(&mut self, transaction: &TransactionStatement) -> Outcome {
+        let tag = match transaction {
+            TransactionStatement::Begin => "BEGIN",
+            TransactionStatement::Commit => "COMMIT",
+            TransactionStatement::Rollback => "ROLLBACK",
+        };
+        match self.session.as_mut().map(|session| session.transaction(transaction)) {
+            Some(ExecutionResult::Error { message }) => Outcome::Failed(message),
+            _ => Outcome::Done {
+                tag: tag.to_string(),
+                notice: None,
+            },
+        }
+    }
+
+    async fn extended(&mut self, message: FrontendMessage) -> Result<(), String> {
+        match message {
+            FrontendMessage::Parse { name, query, param_types } => {
+                if !name.is_empty() && self.statements.contains_key(&name) {
+                    return Err(format!("Prepared statement '{}' already exists", name));
+                }
+                let mut queries = parse_query(&query)?;
+                if queries.len() > 1 {
+                    return Err("SQL parse error: cannot insert multiple commands into a prepared statement".to_string());
+                }
+                let query = match queries.pop().unwrap_or(Query::Empty) {
+                    Query::Empty => Query::Empty,
+                    Query::Set => Query::Set,
+                    Query::Statement(stmt) => Query::Statement(Arc::new(PreparedStatement::new(stmt, Vec::new())?)),
+                };
+                let count = match &query {
+                    Query::Statement(prepared) => prepared.param_count.max(param_types.len()),
+                    _ => param_types.len(),
+                };
+                let param_types = (0..count)
+                    .map(|i| param_types.get(i).copied().unwrap_or(types::UNSPECIFIED))
+                    .collect();
+                self.statements.insert(name, Parsed { query, param_types });
+                self.out.extend(protocol::empty(b'1'));
+            }
+            FrontendMessage::Bind {
+                portal,
+                statement,
+                param_formats,
+                params,
+                result_formats,
+            } => {
+                if !portal.is_empty() && self.portals.contains_key(&portal) {
+                    return Err(format!("Portal '{}' already exists", portal));
+                }
+                let parsed = self
+                    .statements
+                    .get(&statement)
+                    .ok_or_else(|| format!("Prepared statement '{}' does not exist", statement))?;
+                if params.len() != parsed.param_types.len() {
+                    return Err(format!(
+                        "Wrong number of parameters: expected {}, got {}",
+                        parsed.param_types.len(),
+                        params.len()
+                    ));
+                }
+                let values = params
+                    .iter()
+                    .enumerate()
+                    .map(|(i, param)| {
+                        let format = match param_formats.len() {
+                            0 => 0,
+                            1 => param_formats[0],
+                            _ => param_formats.get(i).copied().unwrap_or(0),
+                        };
+                        types::decode_param(param.as_deref(), parsed.param_types[i], format)
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                let query = match &parsed.query {
+                    Query::Empty => Query::Empty,
+                    Query::Set => Query::Set,
+                    Query::Statement(prepared) => Query::Statement(prepared.bind(&values[..prepared.param_count])?),
+                };
+                self.portals.insert(
+                    portal,
+                    Portal {
+                        query,
+                        result_formats,
+                        outcome: None,
+                        sent: 0,
+                    },
+                );
+                self.out.extend(protocol::empty(b'2'));
+            }
+            FrontendMessage::Describe { kind: b'S', name } => {
+                let parsed = self
+                    .statements
+                    .get(&name)
+                    .ok_or_else(|| format!("Prepared statement '{}' does not exist", name))?;
+                let described: Vec<i32> = parsed
+                    .param_types
+                    .iter()
+                    .map(|oid| if *oid == types::UNSPECIFIED { types::TEXT } else { *oid })
+                    .collect();
+                self.out.extend(protocol::parameter_description(&described));
+                let columns = match &parsed.query {
+                    Query::Statement(prepared) => describe(Arc::clone(prepared)).await?,
+                    _ => None,
+                };
+                match columns {
+                    Some((columns, types)) => self.out.extend(protocol::row_description(&fields(&columns, &types, &[]))),
+                    None => self.out.extend(protocol::empty(b'n')),
+                }
+            }
+            FrontendMessage::Describe { name, .. } => {
+                let outcome = self.run_portal(&name).await?;
+                let portal = &self.portals[&name];
+                match outcome {
+                    Outcome::Rows { columns, types, .. } => {
+                        let fields = fields(&columns, &types, &portal.result_formats);
+                        self.out.extend(protocol::row_description(&fields));
+                    }
+                    Outcome::Failed(message) => return Err(message),
+                    Outcome::Done { .. } => self.out.extend(protocol::empty(b'n')),
+                }
+            }
+            FrontendMessage::Execute { portal: name, max_rows } => {
+                let outcome = self.run_portal(&name).await?;
+                let portal = &self.portals[&name];
+                let (start, formats) = (portal.sent, portal.result_formats.clone());
+                if matches!(portal.query, Query::Empty) {
+                    self.out.extend(protocol::empty(b'I'));
+                    return Ok(());
+                }
+                let max_rows = usize::try_from(max_rows).unwrap_or(0);
+                if !self.send_outcome(&outcome, &formats, start, max_rows) && matches!(outcome, Outcome::Failed(_)) {
+                    self.failed = true;
+                }
+                if let (Some(portal), Outcome::Rows { rows, .. }) = (self.portals.get_mut(&name), &outcome) {
+                    portal.sent = match max_rows {
+                        0 => rows.len(),
+                        n => rows.len().min(start + n),
+                    };
+                }
+            }
+            FrontendMessage::Close { kind, name } => {
+                match kind {
+                    b'S' => self.statements.remove(&name).map(|_| ()),
+                    _ => self.portals.remove(&name).map(|_| ()),
+                };
+                self.out.extend(protocol::empty(b'3'));
+            }
+            FrontendMessage::Query(_) | FrontendMessage::Sync | FrontendMessage::Flush | FrontendMessage::Terminate => {}
+        }
+        Ok(())
+    }
+
+    /// Outcome of a portal, running its statement the first time
+    async fn run_portal(&mut self, name: &str) -> Result<Outcome, String> {
+        let portal = self
+            .portals
+            .get(name)
+            .ok_or_else(|| format!("Portal '{}' does not exist", name))?;
+        if let Some(outcome) = &portal.outcome {
+            return Ok(outcome.clone());
+        }
+        let outcome = match portal.query.clone() {
+            Query::Empty | Query::Set => Outcome::Done {
+                tag: "SET".to_string(),
+                notice: None,
+            },
+            Query::Statement(stmt) => self.execute(stmt).await,
+        };
+        if let Some(portal) = self.portals.get_mut(name) {
+            portal.outcome = Some(outcome.clone());
+        }
+        Ok(outcome)
+    }
+}
+
+fn is_set_command(sql: &str) -> bool {
+    let sql = sql.trim_start();
+    sql.len() > 4 && sql[..4].eq_ignore_ascii_case("SET ")
+}
+
+/// Split a query string into the statements it holds
+fn parse_query(sql: &str) -> Result<Vec<Query<Statement>>, String> {
+    if sql.trim_matches(|c: char| c.is_whitespace() || c == ';').is_empty() {
+        return Ok(vec![Query::Empty]);
+    }
+    match SqlParser::parse(sql) {
+        Ok(statements) => Ok(statements.into_iter().map(Query::Statement).collect()),
+        Err(_) if is_set_command(sql) => Ok(vec![Query::Set]),
+        Err(e) => Err(format!("SQL parse error: {}", e)),
+    }
+}
+
+/// Output columns and their types of a prepared statement, planned with
+/// every parameter NULL
+async fn describe(prepared: Arc<PreparedStatement>) -> Result<Option<(Vec<String>, Vec<i32>)>, String> {
+    tokio::task::spawn_blocking(move || {
+        let stmt = prepared.bind(&vec![Value::Null; prepared.param_count])?;
+        Ok(Executor::describe(&stmt)?.map(|columns| {
+            let types = column_types(&stmt, &columns, &[]);
+            (columns, types)
+        }))
+    })
+    .await
+    .map_err(|_| "Describing the statement panicked".to_string())?
+}
+
+/// Turn an execution result into what is sent back for it
+fn outcome(session: &Session, stmt: &Statement, result: ExecutionResult) -> Outcome {
+    // EXECUTE reports the tag of the statement it runs
+    let stmt = match stmt {
+        Statement::Execute { name, .. } => match session.prepared_statement(name) {
+            Some(prepared) => prepared.statement.clone(),
+            None => stmt.clone(),
+        },
+        stmt => stmt.clone(),
+    };
+    match result {
+        ExecutionResult::Error { message } => Outcome::Failed(message),
+        ExecutionResult::Rows { columns, rows } => {
+            let rows: Vec<Vec<Value>> = rows
+                .iter()
+                .map(|row| {
+                    columns
+                        .iter()
+                        .map(|c| row.get(c).and_then(|v| Value::from_json(v).ok()).unwrap_or(Value::Null))
+                        .collect()
+                })
+                .collect();
+            // FETCH rows are typed by the query of their cursor
+            let typed = match &stmt {
+                Statement::Fetch { name, .. } => session.cursor_query(name).unwrap_or(&stmt),
+                stmt => stmt,
+            };
+            Outcome::Rows {
+                types: column_types(typed, &columns, &rows),
+                tag: command_tag(&stmt, rows.len()),
+                columns,
+                rows,
+            }
+        }
+        ExecutionResult::RowsAffected { count } => Outcome::Done {
+            tag: command_tag(&stmt, count),
+            notice: None,
+        },
+        ExecutionResult::Upserted { inserted, updated } => Outcome::Done {
+            tag: command_tag(&stmt, inserted + updated),
+            notice: None,
+        },
+        ExecutionResult::Success { .. } => Outcome::Done {
+            tag: command_tag(&stmt, 0),
+            notice: None,
+        },
+    }
+}
+
+/// CommandComplete tag of a statement that touched `rows` rows
+fn command_tag(stmt: &Statement, rows: usize) -> String {
+    let tag = match stmt {
+        Statement::Select { .. } | Statement::Union { .. } | Statement::With { .. } => return format!("SELECT {}", rows),
+        Statement::Insert { .. } => return format!("INSERT 0 {}", rows),
+        Statement::Update { .. } => return format!("UPDATE {}", rows),
+        Statement::Delete { .. } => return format!("DELETE {}", rows),
+        Statement::CreateTable { .. } => "CREATE TABLE",
+        Statement::CreateDatabase { .. } => "CREATE DATABASE",
+        Statement::CreateIndex { .. } => "CREATE INDEX",
+        Statement::DropTable { .. } => "DROP TABLE",
+        Statement::DropDatabase { .. } => "DROP DATABASE",
+        Statement::AlterTable { .. } => "ALTER TABLE",
+        Statement::Transaction(TransactionStatement::Begin) => "BEGIN",
+        Statement::Transaction(TransactionStatement::Commit) => "COMMIT",
+        Statement::Transaction(TransactionStatement::Rollback) => "ROLLBACK",
+        Statement::Prepare { .. } => "PREPARE",
+        Statement::Execute { .. } => "EXECUTE",
+        Statement::Deallocate { .. } => "DEALLOCATE",
+        Statement::DeclareCursor { .. } => "DECLARE CURSOR",
+        Statement::Fetch { .. } => return format!("FETCH {}", rows),
+        Statement::CloseCursor { .. } => "CLOSE CURSOR",
+        Statement::Listen { .. } => "LISTEN",
+        Statement::Unlisten { .. } => "UNLISTEN",
+        Statement::Notify { .. } => "NOTIFY",
+        Statement::Explain { .. } => "EXPLAIN",
+        Statement::Analyze { .. } => "ANALYZE",
+    };
+    tag.to_string()
+}
+
+/// Stored tables a statement reads or writes
+fn statement_tables(stmt: &Statement, tables: &mut Vec<String>) {
+    let from_reference = |reference: &TableReference, tables: &mut Vec<String>| match reference {
+        TableReference::Table { name, .. } => tables.push(name.clone()),
+        TableReference::Subquery { query, .. } => statement_tables(query, tables),
+    };
+    match stmt {
+        Statement::Select { from, joins, .. } => {
+            for reference in from.iter().chain(joins.iter().map(|join| &join.table)) {
+                from_reference(reference, tables);
+            }
+        }
+        Statement::Union { left, right, .. } => {
+            statement_tables(left, tables);
+            statement_tables(right, tables);
+        }
+        Statement::With { ctes, body, .. } => {
+            statement_tables(body, tables);
+            for cte in ctes {
+                statement_tables(&cte.query, tables);
+            }
+        }
+        Statement::Insert { table, .. } | Statement::Update { table, .. } | Statement::Delete { table, .. } => {
+            tables.push(table.clone())
+        }
+        _ => {}
+    }
+}
+
+/// Type OID of each output column: the catalog type of a stored column of
+/// that name in one of the statement's tables, else the type of the first
+/// non-NULL value in the column, else text
+pub(crate) fn column_types(stmt: &Statement, columns: &[String], rows: &[Vec<Value>]) -> Vec<i32> {
+    let mut tables = Vec::new();
+    statement_tables(stmt, &mut tables);
+    let catalog = catalog::current();
+    let schemas: Vec<_> = tables.iter().filter_map(|table| catalog.get_table(table).ok()).collect();
+
+    columns
+        .iter()
+        .enumerate()
+        .map(|(i, column)| {
+            let name = column.rsplit('.').next().unwrap_or(column);
+            schemas
+                .iter()
+                .find_map(|schema| schema.get_column(name))
+                .map(|c| types::oid_for_catalog_type(&c.data_type))
+                .or_else(|| rows.iter().find_map(|row| row.get(i).and_then(types::oid_for_value)))
+                .unwrap_or(types::TEXT)
+        })
+        .collect()
+}
+
+/// Format code of column `i` given a Bind's result format list
+fn format_of(formats: &[i16], i: usize) -> i16 {
+    match formats.len() {
+        0 => 0,
+        1 => formats[0],
+        _ => formats.get(i).copied().unwrap_or(0),
+    }
+}
+
+fn fields(columns: &[String], types: &[i32], formats: &[i16]) -> Vec<FieldDescription> {
+    columns
+        .iter()
+        .zip(types)
+        .enumerate()
+        .map(|(i, (name, oid))| {
+            let format = format_of(formats, i);
+            FieldDescription {
+                name: name.clone(),
+                type_oid: types::wire_type(*oid, format),
+                format,
+            }
+        })
+        .collect()
+}
+
+fn encode_row(row: &[Value], types: &[i32], formats: &[i16]) -> Result<Vec<Option<Vec<u8>>>, String> {
+    row.iter()
+        .zip(types)
+        .enumerate()
+        .map(|(i, (value, oid))| match format_of(formats, i) {
+            1 => types::encode_binary(value, types::wire_type(*oid, 1)),
+            _ => Ok(types::encode_text(value)),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::protocol::Message;
+    use super::*;
+    use crate::config::PoolConfig;
+    use tokio::io::{AsyncReadExt, DuplexStream};
+
+    /// A client connected over an in-memory stream
+    struct Client {
+        stream: DuplexStream,
+    }
+
+    impl Client {
+        async fn connect(pool: Arc<ConnectionPool>) -> Self {
+            let (mut stream, server) = tokio::io::duplex(1 << 20);
+            tokio::spawn(async move { handle_connection(server, &pool).await });
+            let body = [&196608i32.to_be_bytes()[..], b"user\0tester\0\0"].concat();
+            stream.write_all(&((body.len() + 4) as i32).to_be_bytes()).await.unwrap();
+            stream.write_all(&body).await.unwrap();
+            let mut client = Self { stream };
+            let startup = client.read_until_ready().await;
+            assert_eq!(startup.first().map(|(tag, _)| *tag), Some(b'R'));
+            client
+        }
+
+        async fn send(&mut self, message: Vec<u8>) {
+            self.stream.write_all(&message).await.unwrap();
+        }
+
+        async fn read(&mut self) -> (u8, Vec<u8>) {
+            let tag = self.stream.read_u8().await.unwrap();
+            let len = self.stream.read_i32().await.unwrap() as usize;
+            let mut body = vec![0; len - 4];
+            self.stream.read_exact(&mut body).await.unwrap();
+            (tag, body)
+        }
+
+        async fn read_until_ready(&mut self) -> Vec<(u8, Vec<u8>)> {
+            let mut messages = Vec::new();
+            loop {
+                let message = self.read().await;
+                let done = message.0 == b'Z';
+                messages.push(message);
+                if done {
+                    return messages;
+                }
+            }
+        }
+
+        async fn query(&mut self, sql: &str) -> Vec<(u8, Vec<u8>)> {
+            self.send(Message::new(b'Q').cstr(sql).finish()).await;
+            self.read_until_ready().await
+        }
+    }
+
+    fn tags(messages: &[(u8, Vec<u8>)]) -> String {
+        messages.iter().map(|(tag, _)| *tag as char).collect()
+    }
+
+    /// The text fields of a DataRow
+    fn row_values(body: &[u8]) -> Vec<Option<String>> {
+        let count = i16::from_be_bytes([body[0], body[1]]) as usize;
+        let mut rest = &body[2..];
+        (0..count)
+            .map(|_| {
+                let len = i32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]);
+                rest = &rest[4..];
+                (len >= 0).then(|| {
+                    let (value, tail) = rest.split_at(len as usize);
+                    rest = tail;
+                    String::from_utf8_lossy(value).into_owned()
+                })
+            })
+            .collect()
+    }
+
+    /// (name, type oid) of each field of a RowDescription
+    fn row_fields(body: &[u8]) -> Vec<(String, i32)> {
+        let count = i16::from_be_bytes([body[0], body[1]]) as usize;
+        let mut rest = &body[2..];
+        (0..count)
+            .map(|_| {
+                let end = rest.iter().position(|&b| b == 0).unwrap();
+                let name = String::from_utf8_lossy(&rest[..end]).into_owned();
+                let oid = i32::from_be_bytes([rest[end + 7], rest[end + 8], rest[end + 9], rest[end + 10]]);
+                rest = &rest[end + 19..];
+                (name, oid)
+            })
+            .collect()
+    }
+
+    /// The SQLSTATE field of an ErrorResponse
+    fn error_code(body: &[u8]) -> String {
+        let mut fields = body.split(|&b| b == 0);
+        fields
+            .find(|field| field.first() == Some(&b'C'))
+            .map(|field| String::from_utf8_lossy(&field[1..]).into_owned())
+            .unwrap()
+    }
+
+    fn pool() -> Arc<ConnectionPool> {
+        Arc::new(ConnectionPool::new(PoolConfig::default()))
+    }
+
+    #[tokio::test]
+    async fn test_simple_query_flow() {
+        let mut client = Client::connect(pool()).await;
+        client.query("DROP TABLE IF EXISTS pg_people").await;
+        let created = client.query("CREATE TABLE pg_people (id INTEGER PRIMARY KEY, name VARCHAR(40), score FLOAT)").await;
+        assert_eq!(tags(&created), "CZ");
+
+        let inserted = client.query("INSERT INTO pg_people VALUES (1, 'Ada', 9.5), (2, NULL, 7.0)").await;
+        assert_eq!(inserted[0].1, b"INSERT 0 2\0");
+
+        let selected = client.query("SELECT id, name, score, id * 2 AS doubled FROM pg_people ORDER BY id").await;
+        assert_eq!(tags(&selected), "TDDCZ");
+        assert_eq!(
+            row_fields(&selected[0].1),
+            [
+                ("id".to_string(), types::INT8),
+                ("name".to_string(), types::VARCHAR),
+                ("score".to_string(), types::FLOAT8),
+                ("doubled".to_string(), types::INT8),
+            ]
+        );
+        assert_eq!(
+            row_values(&selected[1].1),
+            [Some("1".to_string()), Some("Ada".to_string()), Some("9.5".to_string()), Some("2".to_string())]
+        );
+        assert_eq!(row_values(&selected[2].1)[1], None);
+        assert_eq!(selected[3].1, b"SELECT 2\0");
+
+        // An error ends the query string; the connection stays usable
+        let failed = client.query("SELECT * FROM pg_missing; SELECT 1").await;
+        assert_eq!(tags(&failed), "EZ");
+        assert_eq!(error_code(&failed[0].1), "42P01");
+        assert_eq!(error_code(&client.query("SELEC 1").await[0].1), "42601");
+
+        assert_eq!(tags(&client.query("").await), "IZ");
+        assert_eq!(tags(&client.query("SET extra_float_digits = 3").await), "CZ");
+        let begun = client.query("BEGIN").await;
+        assert_eq!(begun.last().unwrap().1, b"T");
+        assert_eq!(client.query("COMMIT").await.last().unwrap().1, b"I");
+
+        // Writes commit as they run, so they cannot be rolled back
+        client.query("BEGIN").await;
+        client.query("INSERT INTO pg_people VALUES (3, 'Cy', 1.0)").await;
+        let rolled = client.query("ROLLBACK").await;
+        assert_eq!(tags(&rolled), "EZ");
+        assert_eq!(error_code(&rolled[0].1), "0A000");
+        assert_eq!(rolled[1].1, b"I");
+        client.query("DROP TABLE pg_people").await;
+    }
+
+    #[tokio::test]
+    async fn test_notifications_follow_commit() {
+        let pool = pool();
+        let mut listener = Client::connect(Arc::clone(&pool)).await;
+        let mut sender = Client::connect(pool).await;
+        assert_eq!(tags(&listener.query("LISTEN pg_cache").await), "CZ");
+
+        // Held back until COMMIT, sent once however often it was issued
+        sender.query("BEGIN").await;
+        sender.query("NOTIFY pg_cache, 'users:1'").await;
+        sender.query("NOTIFY pg_cache, 'users:1'").await;
+        assert_eq!(tags(&listener.query("SELECT 1").await), "TDCZ");
+        sender.query("COMMIT").await;
+
+        // An idle listener gets it without asking
+        let (tag, body) = listener.read().await;
+        assert_eq!(tag, b'A');
+        assert!(body.ends_with(b"pg_cache\0users:1\0"), "{:?}", body);
+
+        sender.query("BEGIN").await;
+        sender.query("NOTIFY pg_cache, 'dropped'").await;
+        sender.query("ROLLBACK").await;
+        sender.query("NOTIFY pg_cache").await;
+        let (tag, body) = listener.read().await;
+        assert_eq!(tag, b'A');
+        assert!(body.ends_with(b"pg_cache\0\0"), "{:?}", body);
+
+        listener.query("UNLISTEN *").await;
+        sender.query("NOTIFY pg_cache, 'unheard'").await;
+        assert_eq!(tags(&listener.query("SELECT 1").await), "TDCZ");
+    }
+
+    #[tokio::test]
+    async fn test_extended_query_flow() {
+        let mut client = Client::connect(pool()).await;
+        client.query("DROP TABLE IF EXISTS pg_items").await;
+        client.query("CREATE TABLE pg_items (id INTEGER PRIMARY KEY, label TEXT)").await;
+        client.query("INSERT INTO pg_items VALUES (1, 'one'), (2, 'two'), (3, 'three')").await;
+
+        // Parse with one typed parameter, describe the statement
+        client.send(Message::new(b'P').cstr("by_id").cstr("SELECT id, label FROM pg_items WHERE id >= $1 ORDER BY id").i16(1).i32(types::INT8).finish()).await;
+        client.send(Message::new(b'D').u8(b'S').cstr("by_id").finish()).await;
+        client.send(Message::new(b'S').finish()).await;
+        let described = client.read_until_ready().await;
+        assert_eq!(tags(&described), "1tTZ");
+        assert_eq!(row_fields(&described[2].1), [("id".to_string(), types::INT8), ("label".to_string(), types::TEXT)]);
+
+        // Bind a binary parameter, ask for a binary id column, fetch two rows at a time
+        let bind = Message::new(b'B')
+            .cstr("")
+            .cstr("by_id")
+            .i16(1)
+            .i16(1)
+            .i16(1)
+            .i32(8)
+            .raw(&2i64.to_be_bytes())
+            .i16(2)
+            .i16(1)
+            .i16(0)
+            .finish();
+        client.send(bind).await;
+        client.send(Message::new(b'D').u8(b'P').cstr("").finish()).await;
+        client.send(Message::new(b'E').cstr("").i32(1).finish()).await;
+        client.send(Message::new(b'E').cstr("").i32(0).finish()).await;
+        client.send(Message::new(b'S').finish()).await;
+        let executed = client.read_until_ready().await;
+        assert_eq!(tags(&executed), "2TDsDCZ");
+        let first = &executed[2].1;
+        // Binary int8 id 2, then text label
+        assert_eq!(&first[6..14], &2i64.to_be_bytes());
+        assert_eq!(row_values(&executed[4].1)[1], Some("three".to_string()));
+        assert_eq!(executed[5].1, b"SELECT 2\0");
+
+        // Text parameters of unspecified type are inferred; errors skip to Sync
+        client.send(Message::new(b'P').cstr("").cstr("UPDATE pg_items SET label = $2 WHERE id = $1").i16(0).finish()).await;
+        client.send(Message::new(b'B').cstr("").cstr("").i16(0).i16(2).i32(1).raw(b"1").i32(3).raw(b"uno").i16(0).finish()).await;
+        client.send(Message::new(b'E').cstr("").i32(0).finish()).await;
+        client.send(Message::new(b'B').cstr("").cstr("missing").i16(0).i16(0).i16(0).finish()).await;
+        client.send(Message::new(b'E').cstr("").i32(0).finish()).await;
+        client.send(Message::new(b'S').finish()).await;
+        let updated = client.read_until_ready().await;
+        assert_eq!(tags(&updated), "12CEZ");
+        assert_eq!(updated[2].1, b"UPDATE 1\0");
+        assert_eq!(error_code(&updated[3].1), "26000");
+
+        let selected = client.query("SELECT label FROM pg_items WHERE id = 1").await;
+        assert_eq!(row_values(&selected[1].1), [Some("uno".to_string())]);
+        client.query("DROP TABLE pg_items").await;
+    }
+
+    #[tokio::test]
+    async fn test_startup_negotiation_and_pool_exhaustion() {
+        let pool = Arc::new(ConnectionPool::new(PoolConfig {
+            min_connections: 0,
+            max_connections: 1,
+            connection_timeout_ms: 20,
+            idle_timeout_ms: 1000,
+        }));
+        let _first = Client::connect(pool.clone()).await;
+
+        let (mut stream, server) = tokio::io::duplex(1024);
+        let pool_clone = pool.clone();
+        tokio::spawn(async move { handle_connection(server, &pool_clone).await });
+        stream.write_all(&[&8i32.to_be_bytes()[..], &80877103i32.to_be_bytes()].concat()).await.unwrap();
+        assert_eq!(stream.read_u8().await.unwrap(), b'N');
+        let body = [&196608i32.to_be_bytes()[..], b"user\0late\0\0"].concat();
+        stream.write_all(&((body.len() + 4) as i32).to_be_bytes()).await.unwrap();
+        stream.write_all(&body).await.unwrap();
+        let mut client = Client { stream };
+        let (tag, body) = client.read().await;
+        assert_eq!(tag, b'E');
+        assert_eq!(error_code(&body), "53300");
+    }
+}
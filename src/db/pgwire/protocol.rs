@@ -0,0 +1,394 @@
+// Postgres v3 message framing - reading frontend messages, building backend ones
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Largest message accepted from a client
+pub const MAX_MESSAGE_BYTES: usize = 64 * 1024 * 1024;
+
+/// Protocol version 3.0, as sent in a StartupMessage
+const PROTOCOL_V3: i32 = 196608;
+const SSL_REQUEST: i32 = 80877103;
+const GSSENC_REQUEST: i32 = 80877104;
+const CANCEL_REQUEST: i32 = 80877102;
+
+/// First packet of a connection
+#[derive(Debug, Clone, PartialEq)]
+pub enum StartupRequest {
+    Ssl,
+    GssEncryption,
+    Cancel,
+    Startup { params: HashMap<String, String> },
+}
+
+/// Messages a client sends once started up
+#[derive(Debug, Clone, PartialEq)]
+pub enum FrontendMessage {
+    Query(String),
+    Parse {
+        name: String,
+        query: String,
+        param_types: Vec<i32>,
+    },
+    Bind {
+        portal: String,
+        statement: String,
+        param_formats: Vec<i16>,
+        params: Vec<Option<Vec<u8>>>,
+        result_formats: Vec<i16>,
+    },
+    /// `kind` is b'S' (statement) or b'P' (portal)
+    Describe { kind: u8, name: String },
+    Execute { portal: String, max_rows: i32 },
+    Close { kind: u8, name: String },
+    Sync,
+    Flush,
+    Terminate,
+}
+
+fn invalid(message: impl Into<String>) -> Error {
+    Error::new(ErrorKind::InvalidData, message.into())
+}
+
+/// Read a length-prefixed body whose length (including its own four bytes)
+/// was just read
+async fn read_body<R: AsyncRead + Unpin>(reader: &mut R, len: i32) -> Result<Vec<u8>, Error> {
+    let len = usize::try_from(len).ok().filter(|len| *len >= 4).ok_or_else(|| invalid("Invalid message length"))?;
+    if len - 4 > MAX_MESSAGE_BYTES {
+        return Err(invalid("Message is too large"));
+    }
+    let mut body = vec![0; len - 4];
+    reader.read_exact(&mut body).await?;
+    Ok(body)
+}
+
+/// Read the untagged first packet. None means the client went away first.
+pub async fn read_startup<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Option<StartupRequest>, Error> {
+    let len = match reader.read_i32().await {
+        Ok(len) => len,
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let body = read_body(reader, len).await?;
+    let mut body = Reader::new(&body);
+    let code = body.i32()?;
+    match code {
+        SSL_REQUEST => Ok(Some(StartupRequest::Ssl)),
+        GSSENC_REQUEST => Ok(Some(StartupRequest::GssEncryption)),
+        CANCEL_REQUEST => Ok(Some(StartupRequest::Cancel)),
+        PROTOCOL_V3 => {
+            let mut params = HashMap::new();
+            loop {
+                let name = body.cstr()?;
+                if name.is_empty() {
+                    break;
+                }
+                params.insert(name, body.cstr()?);
+            }
+            Ok(Some(StartupRequest::Startup { params }))
+        }
+        other => Err(invalid(format!(
+            "Unsupported frontend protocol {}.{}",
+            other >> 16,
+            other & 0xffff
+        ))),
+    }
+}
+
+/// Read the next tagged message. None means the client closed the connection.
+pub async fn read_message<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Option<FrontendMessage>, Error> {
+    let tag = match reader.read_u8().await {
+        Ok(tag) => tag,
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let len = reader.read_i32().await?;
+    let body = read_body(reader, len).await?;
+    let mut body = Reader::new(&body);
+
+    let message = match tag {
+        b'Q' => FrontendMessage::Query(body.cstr()?),
+        b'P' => {
+            let name = body.cstr()?;
+            let query = body.cstr()?;
+            let count = body.i16()?;
+            let param_types = (0..count).map(|_| body.i32()).collect::<Result<_, _>>()?;
+            FrontendMessage::Parse { name, query, param_types }
+        }
+        b'B' => {
+            let portal = body.cstr()?;
+            let statement = body.cstr()?;
+            let count = body.i16()?;
+            let param_formats = (0..count).map(|_| body.i16()).collect::<Result<_, _>>()?;
+            let count = body.i16()?;
+            let params = (0..count)
+                .map(|_| match body.i32()? {
+                    -1 => Ok(None),
+                    len => Ok(Some(body.bytes(usize::try_from(len).map_err(|_| invalid("Invalid parameter length"))?)?.to_vec())),
+                })
+                .collect::<Result<_, Error>>()?;
+            let count = body.i16()?;
+            let result_formats = (0..count).map(|_| body.i16()).collect::<Result<_, _>>()?;
+            FrontendMessage::Bind {
+                portal,
+                statement,
+                param_formats,
+                params,
+                result_formats,
+            }
+        }
+        b'D' => FrontendMessage::Describe {
+            kind: body.u8()?,
+            name: body.cstr()?,
+        },
+        b'E' => FrontendMessage::Execute {
+            portal: body.cstr()?,
+            max_rows: body.i32()?,
+        },
+        b'C' => FrontendMessage::Close {
+            kind: body.u8()?,
+            name: body.cstr()?,
+        },
+        b'S' => FrontendMessage::Sync,
+        b'H' => FrontendMessage::Flush,
+        b'X' => FrontendMessage::Terminate,
+        other => return Err(invalid(format!("Unsupported message type '{}'", other as char))),
+    };
+    Ok(Some(message))
+}
+
+/// Cursor over a message body
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], Error> {
+        if self.bytes.len() < n {
+            return Err(invalid("Message is truncated"));
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn i16(&mut self) -> Result<i16, Error> {
+        let b = self.bytes(2)?;
+        Ok(i16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn i32(&mut self) -> Result<i32, Error> {
+        let b = self.bytes(4)?;
+        Ok(i32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    /// A NUL-terminated string
+    fn cstr(&mut self) -> Result<String, Error> {
+        let end = self.bytes.iter().position(|&b| b == 0).ok_or_else(|| invalid("Unterminated string"))?;
+        let s = String::from_utf8(self.bytes[..end].to_vec()).map_err(|_| invalid("String is not valid UTF-8"))?;
+        self.bytes = &self.bytes[end + 1..];
+        Ok(s)
+    }
+}
+
+/// One column of a RowDescription
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldDescription {
+    pub name: String,
+    pub type_oid: i32,
+    /// 0 for text, 1 for binary
+    pub format: i16,
+}
+
+/// Builder for one backend message
+pub struct Message {
+    bytes: Vec<u8>,
+}
+
+impl Message {
+    pub fn new(tag: u8) -> Self {
+        // Length is filled in by `finish`
+        Self {
+            bytes: vec![tag, 0, 0, 0, 0],
+        }
+    }
+
+    pub fn u8(mut self, value: u8) -> Self {
+        self.bytes.push(value);
+        self
+    }
+
+    pub fn i16(mut self, value: i16) -> Self {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    pub fn i32(mut self, value: i32) -> Self {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    pub fn cstr(mut self, value: &str) -> Self {
+        self.bytes.extend_from_slice(value.as_bytes());
+        self.bytes.push(0);
+        self
+    }
+
+    pub fn raw(mut self, value: &[u8]) -> Self {
+        self.bytes.extend_from_slice(value);
+        self
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        let len = (self.bytes.len() - 1) as i32;
+        self.bytes[1..5].copy_from_slice(&len.to_be_bytes());
+        self.bytes
+    }
+}
+
+pub fn authentication_ok() -> Vec<u8> {
+    Message::new(b'R').i32(0).finish()
+}
+
+pub fn parameter_status(name: &str, value: &str) -> Vec<u8> {
+    Message::new(b'S').cstr(name).cstr(value).finish()
+}
+
+pub fn backend_key_data(process_id: i32, secret_key: i32) -> Vec<u8> {
+    Message::new(b'K').i32(process_id).i32(secret_key).finish()
+}
+
+/// `status` is b'I' (idle), b'T' (in a transaction block) or b'E' (failed
+/// transaction block)
+pub fn ready_for_query(status: u8) -> Vec<u8> {
+    Message::new(b'Z').u8(status).finish()
+}
+
+pub fn row_description(fields: &[FieldDescription]) -> Vec<u8> {
+    let mut message = Message::new(b'T').i16(fields.len() as i16);
+    for field in fields {
+        // No source table or column attribute, variable size, no modifier
+        message = message
+            .cstr(&field.name)
+            .i32(0)
+            .i16(0)
+            .i32(field.type_oid)
+            .i16(-1)
+            .i32(-1)
+            .i16(field.format);
+    }
+    message.finish()
+}
+
+pub fn data_row(values: &[Option<Vec<u8>>]) -> Vec<u8> {
+    let mut message = Message::new(b'D').i16(values.len() as i16);
+    for value in values {
+        message = match value {
+            Some(bytes) => message.i32(bytes.len() as i32).raw(bytes),
+            None => message.i32(-1),
+        };
+    }
+    message.finish()
+}
+
+pub fn command_complete(tag: &str) -> Vec<u8> {
+    Message::new(b'C').cstr(tag).finish()
+}
+
+/// A NOTIFY on a channel the session listens to
+pub fn notification_response(process_id: i32, channel: &str, payload: &str) -> Vec<u8> {
+    Message::new(b'A').i32(process_id).cstr(channel).cstr(payload).finish()
+}
+
+pub fn parameter_description(types: &[i32]) -> Vec<u8> {
+    types
+        .iter()
+        .fold(Message::new(b't').i16(types.len() as i16), |message, oid| message.i32(*oid))
+        .finish()
+}
+
+/// An ErrorResponse (`tag` b'E') or NoticeResponse (b'N')
+fn report(tag: u8, severity: &str, code: &str, message: &str) -> Vec<u8> {
+    Message::new(tag)
+        .u8(b'S')
+        .cstr(severity)
+        .u8(b'V')
+        .cstr(severity)
+        .u8(b'C')
+        .cstr(code)
+        .u8(b'M')
+        .cstr(message)
+        .u8(0)
+        .finish()
+}
+
+pub fn error_response(code: &str, message: &str) -> Vec<u8> {
+    report(b'E', "ERROR", code, message)
+}
+
+pub fn fatal_response(code: &str, message: &str) -> Vec<u8> {
+    report(b'E', "FATAL", code, message)
+}
+
+pub fn notice_response(code: &str, message: &str) -> Vec<u8> {
+    report(b'N', "WARNING", code, message)
+}
+
+/// A message with no body: ParseComplete (b'1'), BindComplete (b'2'),
+/// CloseComplete (b'3'), NoData (b'n'), PortalSuspended (b's') or
+/// EmptyQueryResponse (b'I')
+pub fn empty(tag: u8) -> Vec<u8> {
+    Message::new(tag).finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_read_startup_and_messages() {
+        let mut startup = Vec::new();
+        let body = [&PROTOCOL_V3.to_be_bytes()[..], b"user\0alice\0database\0default\0\0"].concat();
+        startup.extend_from_slice(&((body.len() + 4) as i32).to_be_bytes());
+        startup.extend_from_slice(&body);
+        match read_startup(&mut &startup[..]).await.unwrap() {
+            Some(StartupRequest::Startup { params }) => assert_eq!(params["user"], "alice"),
+            other => panic!("Expected a startup message, got {:?}", other),
+        }
+        let ssl = [&8i32.to_be_bytes()[..], &SSL_REQUEST.to_be_bytes()].concat();
+        assert_eq!(read_startup(&mut &ssl[..]).await.unwrap(), Some(StartupRequest::Ssl));
+
+        // Parse, then Execute, encoded the way a driver sends them
+        let mut stream = Message::new(b'P').cstr("s1").cstr("SELECT $1").i16(1).i32(20).finish();
+        stream.extend(Message::new(b'E').cstr("").i32(10).finish());
+        let mut reader = &stream[..];
+        assert_eq!(
+            read_message(&mut reader).await.unwrap(),
+            Some(FrontendMessage::Parse {
+                name: "s1".to_string(),
+                query: "SELECT $1".to_string(),
+                param_types: vec![20],
+            })
+        );
+        assert_eq!(
+            read_message(&mut reader).await.unwrap(),
+            Some(FrontendMessage::Execute {
+                portal: String::new(),
+                max_rows: 10,
+            })
+        );
+        assert_eq!(read_message(&mut reader).await.unwrap(), None);
+
+        let truncated = Message::new(b'Q').raw(b"SELECT 1").finish();
+        assert!(read_message(&mut &truncated[..]).await.is_err());
+    }
+}
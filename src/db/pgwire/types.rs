@@ -0,0 +1,190 @@
+// Postgres type OIDs, value encodings and SQLSTATE codes for the wire protocol
+use crate::db::storage::Value;
+
+pub const BOOL: i32 = 16;
+pub const INT8: i32 = 20;
+pub const INT2: i32 = 21;
+pub const INT4: i32 = 23;
+pub const TEXT: i32 = 25;
+pub const FLOAT4: i32 = 700;
+pub const FLOAT8: i32 = 701;
+pub const VARCHAR: i32 = 1043;
+pub const DATE: i32 = 1082;
+pub const TIMESTAMP: i32 = 1114;
+/// A parameter whose type the client left for the server to infer
+pub const UNSPECIFIED: i32 = 0;
+
+/// Type OID of a catalog column type. Integers are stored as 64 bits and
+/// floats as doubles whatever width was declared, so they go out as int8
+/// and float8.
+pub fn oid_for_catalog_type(data_type: &str) -> i32 {
+    let base = data_type.split('(').next().unwrap_or("").trim().to_uppercase();
+    match base.as_str() {
+        "INTEGER" | "INT" | "BIGINT" | "SMALLINT" => INT8,
+        "FLOAT" | "DOUBLE" | "REAL" => FLOAT8,
+        "BOOLEAN" | "BOOL" => BOOL,
+        "VARCHAR" => VARCHAR,
+        "DATE" => DATE,
+        "DATETIME" | "TIMESTAMP" => TIMESTAMP,
+        _ => TEXT,
+    }
+}
+
+/// Type OID of a computed value, for columns the catalog does not describe
+pub fn oid_for_value(value: &Value) -> Option<i32> {
+    match value {
+        Value::Null => None,
+        Value::Integer(_) => Some(INT8),
+        Value::Float(_) => Some(FLOAT8),
+        Value::Boolean(_) => Some(BOOL),
+        Value::Text(_) => Some(TEXT),
+    }
+}
+
+/// Type a column is sent as in `format`: binary only has encodings for
+/// numbers, booleans and strings, so anything else goes out as text
+pub fn wire_type(oid: i32, format: i16) -> i32 {
+    match (format, oid) {
+        (1, BOOL | INT8 | FLOAT8 | VARCHAR | TEXT) => oid,
+        (1, _) => TEXT,
+        _ => oid,
+    }
+}
+
+/// Text form of a value, as Postgres prints it
+pub fn encode_text(value: &Value) -> Option<Vec<u8>> {
+    match value {
+        Value::Null => None,
+        Value::Boolean(b) => Some(if *b { b"t".to_vec() } else { b"f".to_vec() }),
+        Value::Float(f) if f.is_nan() => Some(b"NaN".to_vec()),
+        Value::Float(f) if f.is_infinite() => Some(if *f > 0.0 { b"Infinity".to_vec() } else { b"-Infinity".to_vec() }),
+        other => Some(other.to_string().into_bytes()),
+    }
+}
+
+/// Binary form of a value sent as type `oid` (see `wire_type`)
+pub fn encode_binary(value: &Value, oid: i32) -> Result<Option<Vec<u8>>, String> {
+    let bytes = match (oid, value) {
+        (_, Value::Null) => return Ok(None),
+        (INT8, Value::Integer(i)) => i.to_be_bytes().to_vec(),
+        (INT8, Value::Float(f)) if f.fract() == 0.0 => (*f as i64).to_be_bytes().to_vec(),
+        (FLOAT8, Value::Float(f)) => f.to_be_bytes().to_vec(),
+        (FLOAT8, Value::Integer(i)) => (*i as f64).to_be_bytes().to_vec(),
+        (BOOL, Value::Boolean(b)) => vec![u8::from(*b)],
+        (TEXT | VARCHAR, value) => return Ok(encode_text(value)),
+        (oid, value) => return Err(format!("Cannot send {:?} as binary type {}", value, oid)),
+    };
+    Ok(Some(bytes))
+}
+
+/// Decode a bound parameter. Text parameters of unspecified type are read
+/// as the number they spell if they spell one, else as text.
+pub fn decode_param(bytes: Option<&[u8]>, oid: i32, format: i16) -> Result<Value, String> {
+    let Some(bytes) = bytes else {
+        return Ok(Value::Null);
+    };
+    if format == 1 {
+        let fixed = |len: usize| -> Result<&[u8], String> {
+            (bytes.len() == len)
+                .then_some(bytes)
+                .ok_or_else(|| format!("Binary parameter of type {} must be {} bytes", oid, len))
+        };
+        return match oid {
+            INT8 => Ok(Value::Integer(i64::from_be_bytes(fixed(8)?.try_into().expect("8 bytes")))),
+            INT4 => Ok(Value::Integer(i32::from_be_bytes(fixed(4)?.try_into().expect("4 bytes")).into())),
+            INT2 => Ok(Value::Integer(i16::from_be_bytes(fixed(2)?.try_into().expect("2 bytes")).into())),
+            FLOAT8 => Ok(Value::Float(f64::from_be_bytes(fixed(8)?.try_into().expect("8 bytes")))),
+            FLOAT4 => Ok(Value::Float(f32::from_be_bytes(fixed(4)?.try_into().expect("4 bytes")).into())),
+            BOOL => Ok(Value::Boolean(fixed(1)?[0] != 0)),
+            UNSPECIFIED | TEXT | VARCHAR | DATE | TIMESTAMP => decode_param(Some(bytes), oid, 0),
+            other => Err(format!("Binary parameters of type {} are not supported", other)),
+        };
+    }
+
+    let text = std::str::from_utf8(bytes).map_err(|_| "Parameter is not valid UTF-8".to_string())?;
+    let invalid = |kind: &str| format!("Invalid input syntax for type {}: \"{}\"", kind, text);
+    match oid {
+        INT8 | INT4 | INT2 => text.trim().parse().map(Value::Integer).map_err(|_| invalid("integer")),
+        FLOAT8 | FLOAT4 => text.trim().parse().map(Value::Float).map_err(|_| invalid("double precision")),
+        BOOL => match text.trim().to_lowercase().as_str() {
+            "t" | "true" | "yes" | "on" | "1" => Ok(Value::Boolean(true)),
+            "f" | "false" | "no" | "off" | "0" => Ok(Value::Boolean(false)),
+            _ => Err(invalid("boolean")),
+        },
+        UNSPECIFIED => Ok(match (text.parse::<i64>(), text.parse::<f64>()) {
+            (Ok(i), _) => Value::Integer(i),
+            (_, Ok(f)) if text.contains(['.', 'e', 'E']) => Value::Float(f),
+            _ => Value::Text(text.to_string()),
+        }),
+        _ => Ok(Value::Text(text.to_string())),
+    }
+}
+
+/// SQLSTATE code for an error message from the parser or executor
+pub fn sqlstate(message: &str) -> &'static str {
+    let has = |pattern: &str| message.contains(pattern);
+    if message.starts_with("SQL parse error") || has("Column count mismatch") {
+        "42601" // syntax_error
+    } else if message.starts_with("Prepared statement") && has("does not exist") {
+        "26000" // invalid_sql_statement_name
+    } else if message.starts_with("Prepared statement") && has("already exists") {
+        "42P05" // duplicate_prepared_statement
+    } else if message.starts_with("Database") && (has("does not exist") || has("not found")) {
+        "3D000" // invalid_catalog_name
+    } else if message.starts_with("Database") && has("already exists") {
+        "42P04" // duplicate_database
+    } else if message.starts_with("Column") && (has("does not exist") || has("not found")) {
+        "42703" // undefined_column
+    } else if message.starts_with("Table") && (has("does not exist") || has("not found")) {
+        "42P01" // undefined_table
+    } else if (message.starts_with("Table") || message.starts_with("Index")) && has("already exists") {
+        "42P07" // duplicate_table
+    } else if message.starts_with("Duplicate key value") {
+        "23505" // unique_violation
+    } else if has("Wrong number of parameters") || has("parameter placeholder") {
+        "08P01" // protocol_violation
+    } else if message.starts_with("Parameter $") || message.starts_with("Invalid input syntax") {
+        "22P02" // invalid_text_representation
+    } else if has("not yet supported") || has("not supported") || message.starts_with("Unsupported") {
+        "0A000" // feature_not_supported
+    } else {
+        "XX000" // internal_error
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_values_round_trip_through_the_wire_formats() {
+        assert_eq!(oid_for_catalog_type("VARCHAR(255)"), VARCHAR);
+        assert_eq!(oid_for_catalog_type("INTEGER"), INT8);
+        assert_eq!(wire_type(DATE, 1), TEXT);
+
+        assert_eq!(encode_text(&Value::Boolean(true)), Some(b"t".to_vec()));
+        assert_eq!(encode_text(&Value::Null), None);
+        let bytes = encode_binary(&Value::Integer(-7), INT8).unwrap().unwrap();
+        assert_eq!(decode_param(Some(&bytes), INT8, 1), Ok(Value::Integer(-7)));
+        let bytes = encode_binary(&Value::Float(2.5), FLOAT8).unwrap().unwrap();
+        assert_eq!(decode_param(Some(&bytes), FLOAT8, 1), Ok(Value::Float(2.5)));
+        assert!(encode_binary(&Value::Text("x".to_string()), INT8).is_err());
+
+        assert_eq!(decode_param(Some(b"42"), UNSPECIFIED, 0), Ok(Value::Integer(42)));
+        assert_eq!(decode_param(Some(b"4.5"), UNSPECIFIED, 0), Ok(Value::Float(4.5)));
+        assert_eq!(decode_param(Some(b"42"), TEXT, 0), Ok(Value::Text("42".to_string())));
+        assert_eq!(decode_param(Some(b"on"), BOOL, 0), Ok(Value::Boolean(true)));
+        assert!(decode_param(Some(b"x"), INT4, 0).is_err());
+        assert_eq!(decode_param(None, INT4, 0), Ok(Value::Null));
+    }
+
+    #[test]
+    fn test_sqlstate_codes() {
+        assert_eq!(sqlstate("SQL parse error: Unexpected token"), "42601");
+        assert_eq!(sqlstate("Table 'missing' does not exist"), "42P01");
+        assert_eq!(sqlstate("Table 'users' already exists"), "42P07");
+        assert_eq!(sqlstate("Prepared statement 'q' does not exist"), "26000");
+        assert_eq!(sqlstate("Duplicate key value: (id)=(1) already exists"), "23505");
+        assert_eq!(sqlstate("Something else went wrong"), "XX000");
+    }
+}
@@ -0,0 +1,675 @@
+// Cost model - row estimates, access path selection and join ordering
+use std::collections::HashSet;
+use std::ops::Bound;
+
+use super::LogicalPlan;
+use crate::db::catalog::{self, ColumnStatistics, IndexSchema, TableSchema};
+use crate::db::executor::QueryScope;
+use crate::db::optimizer::{conjoin, conjuncts};
+use crate::db::sql::constants::{BinaryOperator, JoinType, Literal, UnaryOperator};
+use crate::db::sql::parser::Expression;
+use crate::db::storage::{engine, KeyRange, Value};
+
+/// Cost of reading and filtering one row during a sequential scan
+pub const SEQ_ROW_COST: f64 = 1.0;
+/// Cost of fetching one matching row through an index (random access)
+pub const INDEX_ROW_COST: f64 = 4.0;
+/// Fixed cost of starting an index lookup
+pub const INDEX_STARTUP_COST: f64 = 4.0;
+
+/// Selectivity guesses used when a column has not been analyzed
+pub const DEFAULT_EQ_SELECTIVITY: f64 = 0.005;
+pub const DEFAULT_RANGE_SELECTIVITY: f64 = 1.0 / 3.0;
+pub const DEFAULT_SELECTIVITY: f64 = 0.25;
+
+pub fn seq_scan_cost(rows: f64) -> f64 {
+    rows * SEQ_ROW_COST
+}
+
+pub fn index_scan_cost(rows: f64, selectivity: f64) -> f64 {
+    INDEX_STARTUP_COST + (rows + 1.0).log2() + rows * selectivity * INDEX_ROW_COST
+}
+
+/// How a filtered table is read
+#[derive(Debug, Clone, PartialEq)]
+pub enum AccessPath {
+    Sequential,
+    /// Fetch the rows whose `column` value lies in `range` through `index`
+    Index {
+        index: IndexSchema,
+        column: String,
+        range: KeyRange,
+    },
+}
+
+/// A reordered chain of inner joins
+#[derive(Debug, Clone, PartialEq)]
+pub struct JoinOrder {
+    pub plan: LogicalPlan,
+    /// Qualifiers of the joined relations in the order the query wrote them
+    pub original_order: Vec<String>,
+}
+
+/// What is known about the relation a column reference resolves to
+struct ColumnInfo {
+    statistics: Option<ColumnStatistics>,
+    relation_rows: f64,
+}
+
+impl ColumnInfo {
+    fn distinct_values(&self) -> f64 {
+        match &self.statistics {
+            Some(stats) if stats.distinct_count > 0 => stats.distinct_count as f64,
+            // Without statistics, assume the column is a key of its relation
+            _ => self.relation_rows.max(1.0),
+        }
+    }
+}
+
+/// Estimates row counts and costs from ANALYZE statistics, falling back to
+/// live row counts and default selectivities for tables never analyzed
+pub struct CostModel<'a> {
+    scope: &'a QueryScope,
+}
+
+impl<'a> CostModel<'a> {
+    pub fn new(scope: &'a QueryScope) -> Self {
+        Self { scope }
+    }
+
+    /// Current number of rows in a stored table or bound relation
+    pub fn table_rows(&self, table: &str) -> f64 {
+        match self.scope.get(table) {
+            Some(relation) => relation.rows.len() as f64,
+            None => engine::current().row_count(table).unwrap_or(0) as f64,
+        }
+    }
+
+    /// Estimated number of rows `plan` produces
+    pub fn estimate_rows(&self, plan: &LogicalPlan) -> f64 {
+        match plan {
+            LogicalPlan::Scan { table, .. } => self.table_rows(table),
+            LogicalPlan::SingleRow => 1.0,
+            LogicalPlan::Filter { input, predicate } => {
+                self.estimate_rows(input) * self.selectivity(input, predicate)
+            }
+            LogicalPlan::Join {
+                left,
+                right,
+                join_type,
+                condition,
+            } => {
+                let (left_rows, right_rows) = (self.estimate_rows(left), self.estimate_rows(right));
+                let matched = left_rows
+                    * right_rows
+                    * condition.as_ref().map_or(1.0, |c| self.selectivity(plan, c));
+                match join_type {
+                    JoinType::Inner | JoinType::Cross => matched,
+                    JoinType::Left => matched.max(left_rows),
+                    JoinType::Right => matched.max(right_rows),
+                    JoinType::Full => matched.max(left_rows + right_rows),
+                }
+            }
+            LogicalPlan::Aggregate { input, group_by, .. } => {
+                let input_rows = self.estimate_rows(input);
+                if group_by.is_empty() {
+                    return 1.0;
+                }
+                let groups: f64 = group_by
+                    .iter()
+                    .map(|expr| {
+                        self.column_info(input, expr)
+                            .map_or(input_rows * DEFAULT_SELECTIVITY, |info| info.distinct_values())
+                    })
+                    .product();
+                groups.clamp(1.0, input_rows.max(1.0))
+            }
+            LogicalPlan::Limit { input, limit, offset } => {
+                let rows = (self.estimate_rows(input) - *offset as f64).max(0.0);
+                limit.map_or(rows, |limit| rows.min(limit as f64))
+            }
+            LogicalPlan::Union { left, right, .. } => self.estimate_rows(left) + self.estimate_rows(right),
+            LogicalPlan::Subquery { input, .. }
+            | LogicalPlan::Project { input, .. }
+            | LogicalPlan::Distinct { input }
+            | LogicalPlan::Sort { input, .. }
+            | LogicalPlan::With { input, .. } => self.estimate_rows(input),
+        }
+    }
+
+    /// Estimated fraction of the rows of `input` for which `predicate` holds
+    pub fn selectivity(&self, input: &LogicalPlan, predicate: &Expression) -> f64 {
+        match predicate {
+            Expression::Literal(Literal::Boolean(b)) => f64::from(u8::from(*b)),
+            Expression::UnaryOp {
+                operator: UnaryOperator::Not,
+                operand,
+            } => 1.0 - self.selectivity(input, operand),
+            Expression::BinaryOp {
+                left,
+                operator: BinaryOperator::And,
+                right,
+            } => self.selectivity(input, left) * self.selectivity(input, right),
+            Expression::BinaryOp {
+                left,
+                operator: BinaryOperator::Or,
+                right,
+            } => {
+                let (l, r) = (self.selectivity(input, left), self.selectivity(input, right));
+                l + r - l * r
+            }
+            Expression::BinaryOp { left, operator, right } => {
+                if let Some((column, range)) = comparison_range(left, operator, right) {
+                    let stats = self
+                        .column_info(input, column)
+                        .and_then(|info| info.statistics);
+                    return match stats {
+                        Some(stats) => stats.range_selectivity(&range),
+                        None => default_range_selectivity(&range),
+                    };
+                }
+                if matches!(operator, BinaryOperator::NotEquals) {
+                    let equals = Expression::BinaryOp {
+                        left: left.clone(),
+                        operator: BinaryOperator::Equals,
+                        right: right.clone(),
+                    };
+                    return 1.0 - self.selectivity(input, &equals);
+                }
+                if matches!(operator, BinaryOperator::Equals) {
+                    if let (Some(l), Some(r)) =
+                        (self.column_info(input, left), self.column_info(input, right))
+                    {
+                        return 1.0 / l.distinct_values().max(r.distinct_values());
+                    }
+                }
+                DEFAULT_SELECTIVITY
+            }
+            _ => DEFAULT_SELECTIVITY,
+        }
+    }
+
+    /// Find the scanned relation a column reference belongs to
+    fn column_info(&self, plan: &LogicalPlan, expr: &Expression) -> Option<ColumnInfo> {
+        match (plan, expr) {
+            (LogicalPlan::Scan { table, qualifier }, Expression::QualifiedColumn { table: q, column })
+                if q == qualifier =>
+            {
+                self.scan_column_info(table, column)
+            }
+            (LogicalPlan::Scan { table, .. }, Expression::Identifier(column)) => {
+                self.scan_column_info(table, column)
+            }
+            (LogicalPlan::Subquery { input, alias }, Expression::QualifiedColumn { table, .. })
+                if table == alias =>
+            {
+                Some(ColumnInfo {
+                    statistics: None,
+                    relation_rows: self.estimate_rows(input),
+                })
+            }
+            (LogicalPlan::Subquery { .. }, _) => None,
+            _ => plan.children().into_iter().find_map(|child| self.column_info(child, expr)),
+        }
+    }
+
+    fn scan_column_info(&self, table: &str, column: &str) -> Option<ColumnInfo> {
+        let statistics = match self.scope.get(table) {
+            Some(relation) if relation.columns.iter().any(|c| c == column) => None,
+            Some(_) => return None,
+            None => {
+                let schema = catalog::current().get_table(table).ok()?;
+                schema.get_column(column)?;
+                catalog::current()
+                    .get_statistics(table)
+                    .and_then(|stats| stats.columns.get(column).cloned())
+            }
+        };
+        Some(ColumnInfo {
+            statistics,
+            relation_rows: self.table_rows(table),
+        })
+    }
+
+    /// Choose between a sequential scan and an index scan for `table`
+    /// filtered by `predicate`
+    pub fn access_path(&self, table: &str, qualifier: &str, predicate: &Expression) -> AccessPath {
+        let schema = match catalog::current().get_table(table) {
+            Ok(schema) if !schema.indexes.is_empty() => schema,
+            _ => return AccessPath::Sequential,
+        };
+
+        let ranges = column_ranges(predicate, qualifier, |column, range| indexable(&schema, column, range));
+
+        let rows = self.table_rows(table);
+        let statistics = catalog::current().get_statistics(table);
+        let mut best = AccessPath::Sequential;
+        let mut best_cost = seq_scan_cost(rows);
+        for (column, range) in ranges {
+            let Some(index) = schema.indexes_on(&column).next() else {
+                continue;
+            };
+            let selectivity = match statistics.as_ref().and_then(|s| s.columns.get(&column)) {
+                Some(stats) => stats.range_selectivity(&range),
+                None => default_range_selectivity(&range),
+            };
+            let cost = index_scan_cost(rows, selectivity);
+            if cost < best_cost {
+                best_cost = cost;
+                best = AccessPath::Index {
+                    index: index.clone(),
+                    column,
+                    range,
+                };
+            }
+        }
+        best
+    }
+
+    /// Reorder a chain of three or more inner/cross joins so that the
+    /// smallest relations and most selective join conditions come first.
+    /// Returns `None` when the chain cannot be reordered safely or the
+    /// written order is already the one chosen.
+    pub fn order_joins(&self, plan: &LogicalPlan) -> Option<JoinOrder> {
+        let mut leaves = Vec::new();
+        let mut conditions = Vec::new();
+        if !flatten_inner_joins(plan, &mut leaves, &mut conditions) || leaves.len() < 3 {
+            return None;
+        }
+
+        let qualifiers: Vec<String> = leaves
+            .iter()
+            .map(|leaf| leaf_qualifier(leaf))
+            .collect::<Option<_>>()?;
+        let unique: HashSet<&String> = qualifiers.iter().collect();
+        if unique.len() != qualifiers.len() {
+            return None;
+        }
+
+        // Attribute every join conjunct to the relations it reads
+        let mut pending: Vec<(Expression, HashSet<usize>, f64)> = Vec::new();
+        for conjunct in conditions.iter().flat_map(conjuncts) {
+            let mut used = HashSet::new();
+            if !referenced_relations(&conjunct, &qualifiers, &mut used) {
+                return None;
+            }
+            let selectivity = self.selectivity(plan, &conjunct);
+            pending.push((conjunct, used, selectivity));
+        }
+
+        let leaf_rows: Vec<f64> = leaves.iter().map(|leaf| self.estimate_rows(leaf)).collect();
+        let smallest = |candidates: &mut dyn Iterator<Item = usize>| {
+            candidates.min_by(|a, b| leaf_rows[*a].total_cmp(&leaf_rows[*b]))
+        };
+
+        let first = smallest(&mut (0..leaves.len()))?;
+        let mut order = vec![first];
+        let mut joined: HashSet<usize> = HashSet::from([first]);
+        let mut rows = leaf_rows[first];
+        let mut steps: Vec<Vec<Expression>> = vec![take_applicable(&mut pending, &joined)];
+
+        while joined.len() < leaves.len() {
+            // Prefer relations a condition connects to; cross join only if none is
+            let connected = (0..leaves.len()).filter(|i| {
+                !joined.contains(i)
+                    && pending.iter().any(|(_, used, _)| used.contains(i) && used.iter().any(|u| joined.contains(u)))
+            });
+            let estimate = |i: usize| {
+                let selectivity: f64 = pending
+                    .iter()
+                    .filter(|(_, used, _)| used.iter().all(|u| *u == i || joined.contains(u)))
+                    .map(|(_, _, s)| s)
+                    .product();
+                rows * leaf_rows[i] * selectivity
+            };
+            let next = connected
+                .min_by(|a, b| estimate(*a).total_cmp(&estimate(*b)))
+                .or_else(|| smallest(&mut (0..leaves.len()).filter(|i| !joined.contains(i))))?;
+
+            let next_rows = estimate(next);
+            rows = next_rows;
+            joined.insert(next);
+            order.push(next);
+            steps.push(take_applicable(&mut pending, &joined));
+        }
+
+        if order.iter().enumerate().all(|(position, leaf)| position == *leaf) {
+            return None;
+        }
+
+        // Conditions that hold before any join (no relation read) go on the first join
+        let mut steps = steps.into_iter();
+        let mut carried = steps.next().unwrap_or_default();
+        let mut reordered = leaves[order[0]].clone();
+        for (leaf, mut step) in order[1..].iter().zip(steps) {
+            step.append(&mut carried);
+            let condition = conjoin(step);
+            reordered = LogicalPlan::Join {
+                left: Box::new(reordered),
+                right: Box::new(leaves[*leaf].clone()),
+                join_type: if condition.is_some() { JoinType::Inner } else { JoinType::Cross },
+                condition,
+            };
+        }
+
+        Some(JoinOrder {
+            plan: reordered,
+            original_order: qualifiers,
+        })
+    }
+}
+
+/// Collect the relations and conditions of a left-deep chain of inner/cross
+/// joins, in the order written. Fails if any join in the chain is an outer join.
+fn flatten_inner_joins<'p>(
+    plan: &'p LogicalPlan,
+    leaves: &mut Vec<&'p LogicalPlan>,
+    conditions: &mut Vec<Expression>,
+) -> bool {
+    match plan {
+        LogicalPlan::Join {
+            left,
+            right,
+            join_type: JoinType::Inner | JoinType::Cross,
+            condition,
+        } => {
+            if !flatten_inner_joins(left, leaves, conditions) {
+                return false;
+            }
+            if matches!(right.as_ref(), LogicalPlan::Join { .. }) {
+                return false;
+            }
+            leaves.push(right);
+            conditions.extend(condition.iter().cloned());
+            true
+        }
+        LogicalPlan::Join { .. } => false,
+        leaf => {
+            leaves.push(leaf);
+            true
+        }
+    }
+}
+
+fn leaf_qualifier(leaf: &LogicalPlan) -> Option<String> {
+    match leaf {
+        LogicalPlan::Scan { qualifier, .. } => Some(qualifier.clone()),
+        LogicalPlan::Subquery { alias, .. } => Some(alias.clone()),
+        _ => None,
+    }
+}
+
+/// Record which relations `expr` reads. Fails on anything that cannot be
+/// attributed: unqualified columns, unknown qualifiers and subqueries.
+fn referenced_relations(expr: &Expression, qualifiers: &[String], used: &mut HashSet<usize>) -> bool {
+    match expr {
+        Expression::QualifiedColumn { table, .. } => match qualifiers.iter().position(|q| q == table) {
+            Some(position) => {
+                used.insert(position);
+                true
+            }
+            None => false,
+        },
+        Expression::Identifier(_) | Expression::Subquery(_) => false,
+        Expression::Literal(_) | Expression::Parameter(_) => true,
+        Expression::BinaryOp { left, right, .. } => {
+            referenced_relations(left, qualifiers, used) && referenced_relations(right, qualifiers, used)
+        }
+        Expression::UnaryOp { operand, .. } => referenced_relations(operand, qualifiers, used),
+        Expression::Function { args, .. } => args.iter().all(|a| referenced_relations(a, qualifiers, used)),
+        Expression::Case {
+            when_clauses,
+            else_clause,
+        } => {
+            when_clauses.iter().all(|(when, then)| {
+                referenced_relations(when, qualifiers, used) && referenced_relations(then, qualifiers, used)
+            }) && else_clause
+                .as_ref()
+                .is_none_or(|e| referenced_relations(e, qualifiers, used))
+        }
+        Expression::Alias { expr, .. } => referenced_relations(expr, qualifiers, used),
+    }
+}
+
+/// Remove and return the conditions that only read relations already joined
+fn take_applicable(
+    pending: &mut Vec<(Expression, HashSet<usize>, f64)>,
+    joined: &HashSet<usize>,
+) -> Vec<Expression> {
+    let (ready, waiting): (Vec<_>, Vec<_>) = pending
+        .drain(..)
+        .partition(|(_, used, _)| used.iter().all(|u| joined.contains(u)));
+    *pending = waiting;
+    ready.into_iter().map(|(expr, _, _)| expr).collect()
+}
+
+/// The ranges the conjuncts of `predicate` confine columns of `qualifier`
+/// to, one per column, counting only the comparisons `keep` accepts
+pub fn column_ranges(
+    predicate: &Expression,
+    qualifier: &str,
+    keep: impl Fn(&str, &KeyRange) -> bool,
+) -> Vec<(String, KeyRange)> {
+    // Combine the conjuncts on each column into a single range
+    let mut ranges: Vec<(String, KeyRange)> = Vec::new();
+    for conjunct in conjuncts(predicate) {
+        let Expression::BinaryOp { left, operator, right } = &conjunct else {
+            continue;
+        };
+        let Some((column_expr, range)) = comparison_range(left, operator, right) else {
+            continue;
+        };
+        let column = match column_expr {
+            Expression::Identifier(name) => name,
+            Expression::QualifiedColumn { table, column } if table == qualifier => column,
+            _ => continue,
+        };
+        if !keep(column, &range) {
+            continue;
+        }
+        match ranges.iter_mut().find(|(c, _)| c == column) {
+            Some((_, existing)) => *existing = existing.intersect(&range),
+            None => ranges.push((column.clone(), range)),
+        }
+    }
+    ranges
+}
+
+/// Read `left op right` as a range on a column when one side is a column
+/// and the other a non-NULL literal
+pub fn comparison_range<'e>(
+    left: &'e Expression,
+    operator: &BinaryOperator,
+    right: &'e Expression,
+) -> Option<(&'e Expression, KeyRange)> {
+    let is_column = |e: &Expression| matches!(e, Expression::Identifier(_) | Expression::QualifiedColumn { .. });
+    let (column, value, flipped) = match (left, right) {
+        (column, Expression::Literal(lit)) if is_column(column) => (column, Value::from_literal(lit), false),
+        (Expression::Literal(lit), column) if is_column(column) => (column, Value::from_literal(lit), true),
+        _ => return None,
+    };
+    if matches!(value, Value::Null) {
+        return None;
+    }
+
+    // `5 < x` is `x > 5`
+    let operator = match (operator, flipped) {
+        (BinaryOperator::LessThan, true) => BinaryOperator::GreaterThan,
+        (BinaryOperator::LessThanOrEqual, true) => BinaryOperator::GreaterThanOrEqual,
+        (BinaryOperator::GreaterThan, true) => BinaryOperator::LessThan,
+        (BinaryOperator::GreaterThanOrEqual, true) => BinaryOperator::LessThanOrEqual,
+        (op, _) => op.clone(),
+    };
+    let range = match operator {
+        BinaryOperator::Equals => KeyRange::exact(value),
+        BinaryOperator::LessThan => KeyRange {
+            lower: Bound::Unbounded,
+            upper: Bound::Excluded(value),
+        },
+        BinaryOperator::LessThanOrEqual => KeyRange {
+            lower: Bound::Unbounded,
+            upper: Bound::Included(value),
+        },
+        BinaryOperator::GreaterThan => KeyRange {
+            lower: Bound::Excluded(value),
+            upper: Bound::Unbounded,
+        },
+        BinaryOperator::GreaterThanOrEqual => KeyRange {
+            lower: Bound::Included(value),
+            upper: Bound::Unbounded,
+        },
+        _ => return None,
+    };
+    Some((column, range))
+}
+
+fn default_range_selectivity(range: &KeyRange) -> f64 {
+    match (&range.lower, &range.upper) {
+        (Bound::Included(low), Bound::Included(high)) if low == high => DEFAULT_EQ_SELECTIVITY,
+        (Bound::Unbounded, Bound::Unbounded) => 1.0,
+        (Bound::Unbounded, _) | (_, Bound::Unbounded) => DEFAULT_RANGE_SELECTIVITY,
+        _ => DEFAULT_RANGE_SELECTIVITY * DEFAULT_RANGE_SELECTIVITY,
+    }
+}
+
+/// Whether an index on `column` returns every row the range condition can
+/// match. The executor compares values of different kinds (and booleans) as
+/// equal, which an ordered index cannot reproduce, so bounds must be of the
+/// column's declared kind, and booleans only support equality.
+pub(crate) fn indexable(schema: &TableSchema, column: &str, range: &KeyRange) -> bool {
+    let Some(column) = schema.get_column(column) else {
+        return false;
+    };
+    let data_type = column.data_type.to_uppercase();
+    let numeric = ["INT", "FLOAT", "DOUBLE", "REAL", "DECIMAL", "NUMERIC"]
+        .iter()
+        .any(|t| data_type.contains(t));
+    let textual = ["CHAR", "TEXT", "DATE", "TIME"].iter().any(|t| data_type.contains(t));
+
+    let bound_fits = |bound: &Bound<Value>| match bound {
+        Bound::Unbounded => true,
+        Bound::Included(value) | Bound::Excluded(value) => match value {
+            Value::Integer(_) | Value::Float(_) => numeric,
+            Value::Text(_) => textual,
+            Value::Boolean(_) => data_type == "BOOLEAN" && range.lower == range.upper,
+            Value::Null => false,
+        },
+    };
+    bound_fits(&range.lower) && bound_fits(&range.upper)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::catalog::TableStatistics;
+    use crate::db::executor::Executor;
+    use crate::db::sql::execute_sql;
+    use crate::db::sql::parser::SqlParser;
+
+    fn where_clause(sql: &str) -> Expression {
+        match SqlParser::parse_statement(sql).unwrap() {
+            crate::db::sql::constants::Statement::Select {
+                where_clause: Some(predicate),
+                ..
+            } => predicate,
+            other => panic!("Expected SELECT with WHERE, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_comparison_range_normalizes_sides() {
+        let predicate = where_clause("SELECT * FROM t WHERE 5 < x");
+        let Expression::BinaryOp { left, operator, right } = &predicate else {
+            panic!("Expected comparison");
+        };
+        let (column, range) = comparison_range(left, operator, right).unwrap();
+        assert_eq!(column, &Expression::Identifier("x".to_string()));
+        assert_eq!(range.lower, Bound::Excluded(Value::Integer(5)));
+        assert_eq!(range.upper, Bound::Unbounded);
+    }
+
+    #[test]
+    fn test_access_path_uses_statistics() {
+        execute_sql("DROP TABLE IF EXISTS cost_items");
+        execute_sql("CREATE TABLE cost_items (id INTEGER, flag INTEGER)");
+        let values: Vec<String> = (0..200).map(|i| format!("({}, {})", i, i % 2)).collect();
+        execute_sql(&format!("INSERT INTO cost_items VALUES {}", values.join(", ")));
+        execute_sql("CREATE INDEX cost_items_id ON cost_items (id)");
+        execute_sql("CREATE INDEX cost_items_flag ON cost_items (flag)");
+        execute_sql("ANALYZE cost_items");
+
+        let scope = QueryScope::default();
+        let model = CostModel::new(&scope);
+        let path = |sql: &str| model.access_path("cost_items", "cost_items", &where_clause(sql));
+
+        // One id in 200 is worth an index lookup; half the table is not
+        assert!(matches!(
+            path("SELECT * FROM cost_items WHERE id = 42"),
+            AccessPath::Index { column, .. } if column == "id"
+        ));
+        assert_eq!(path("SELECT * FROM cost_items WHERE flag = 1"), AccessPath::Sequential);
+        assert!(matches!(
+            path("SELECT * FROM cost_items WHERE flag = 1 AND id BETWEEN 10 AND 12"),
+            AccessPath::Index { column, .. } if column == "id"
+        ));
+        assert_eq!(path("SELECT * FROM cost_items WHERE id > 10"), AccessPath::Sequential);
+        assert_eq!(path("SELECT * FROM cost_items WHERE id = 'x'"), AccessPath::Sequential);
+
+        let stats: TableStatistics = catalog::current().get_statistics("cost_items").unwrap();
+        assert_eq!(stats.row_count, 200);
+        assert_eq!(stats.columns["flag"].distinct_count, 2);
+        execute_sql("DROP TABLE cost_items");
+    }
+
+    #[test]
+    fn test_joins_start_from_the_smallest_relation() {
+        for (table, rows) in [("cost_big", 300), ("cost_mid", 30), ("cost_small", 3)] {
+            execute_sql(&format!("DROP TABLE IF EXISTS {}", table));
+            execute_sql(&format!("CREATE TABLE {} (id INTEGER, ref INTEGER)", table));
+            let values: Vec<String> = (0..rows).map(|i| format!("({}, {})", i, i % 3)).collect();
+            execute_sql(&format!("INSERT INTO {} VALUES {}", table, values.join(", ")));
+        }
+
+        let stmt = SqlParser::parse_statement(
+            "SELECT * FROM cost_big b JOIN cost_mid m ON b.ref = m.id JOIN cost_small s ON m.ref = s.id",
+        )
+        .unwrap();
+        let plan = LogicalPlan::from_statement(&stmt).unwrap();
+        let scope = QueryScope::default();
+        let join = match &plan {
+            LogicalPlan::Project { input, .. } => input.as_ref(),
+            other => other,
+        };
+        let order = CostModel::new(&scope).order_joins(join).unwrap();
+        assert_eq!(order.original_order, vec!["b", "m", "s"]);
+
+        let mut leaves = Vec::new();
+        assert!(flatten_inner_joins(&order.plan, &mut leaves, &mut Vec::new()));
+        let reordered: Vec<String> = leaves.iter().filter_map(|l| leaf_qualifier(l)).collect();
+        assert_eq!(reordered, vec!["s", "m", "b"]);
+
+        // The reordered plan returns the same rows, and `id` still names b.id
+        let stmt = SqlParser::parse_statement(
+            "SELECT id, b.ref, m.id AS m_id, s.id AS s_id FROM cost_big b \
+             JOIN cost_mid m ON b.ref = m.id JOIN cost_small s ON m.ref = s.id",
+        )
+        .unwrap();
+        let result = Executor::run_query(&stmt, &scope).unwrap();
+        assert_eq!(result.columns, vec!["id", "ref", "m_id", "s_id"]);
+        let mut ids: Vec<i64> = result
+            .rows
+            .iter()
+            .map(|r| match r["id"] {
+                Value::Integer(i) => i,
+                ref other => panic!("Unexpected id {:?}", other),
+            })
+            .collect();
+        ids.sort();
+        assert_eq!(ids, (0..300).collect::<Vec<i64>>());
+        assert!(result.rows.iter().all(|r| r["ref"] == r["m_id"]));
+
+        for table in ["cost_big", "cost_mid", "cost_small"] {
+            execute_sql(&format!("DROP TABLE {}", table));
+        }
+    }
+}
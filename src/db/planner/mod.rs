@@ -0,0 +1,380 @@
+// Query Planner - Builds logical plans from parsed queries
+pub mod cost;
+
+use std::fmt;
+
+use crate::db::sql::constants::{
+    CommonTableExpression, JoinType, Literal, OrderBy, Statement, TableReference,
+};
+use crate::db::sql::parser::Expression;
+
+/// Aggregate functions understood by the planner
+pub const AGGREGATE_FUNCTIONS: [&str; 5] = ["COUNT", "SUM", "AVG", "MIN", "MAX"];
+
+/// Relational operators describing what a query computes, independent of how
+#[derive(Debug, Clone, PartialEq)]
+pub enum LogicalPlan {
+    /// Read a stored table, or a relation bound by WITH, exposing its columns
+    /// as `qualifier.column`
+    Scan { table: String, qualifier: String },
+    /// A single row with no columns (SELECT without FROM)
+    SingleRow,
+    /// Derived table: re-qualifies the subquery's output columns with `alias`
+    Subquery { input: Box<LogicalPlan>, alias: String },
+    Filter { input: Box<LogicalPlan>, predicate: Expression },
+    Join {
+        left: Box<LogicalPlan>,
+        right: Box<LogicalPlan>,
+        join_type: JoinType,
+        condition: Option<Expression>,
+    },
+    /// Group rows and compute `aggregates`; each output row keeps the group's
+    /// first input row so grouped columns stay addressable
+    Aggregate {
+        input: Box<LogicalPlan>,
+        group_by: Vec<Expression>,
+        aggregates: Vec<Expression>,
+    },
+    Project { input: Box<LogicalPlan>, exprs: Vec<Expression> },
+    Distinct { input: Box<LogicalPlan> },
+    Sort { input: Box<LogicalPlan>, order_by: Vec<OrderBy> },
+    Limit {
+        input: Box<LogicalPlan>,
+        limit: Option<u64>,
+        offset: u64,
+    },
+    Union {
+        left: Box<LogicalPlan>,
+        right: Box<LogicalPlan>,
+        all: bool,
+    },
+    /// Common table expressions, materialized before `input` runs
+    With {
+        recursive: bool,
+        ctes: Vec<CommonTableExpression>,
+        input: Box<LogicalPlan>,
+    },
+}
+
+impl LogicalPlan {
+    /// Build the logical plan for a query statement (SELECT, UNION or WITH)
+    pub fn from_statement(stmt: &Statement) -> Result<Self, String> {
+        match stmt {
+            Statement::Select {
+                projection,
+                from,
+                joins,
+                where_clause,
+                group_by,
+                having,
+                order_by,
+                limit,
+                offset,
+                distinct,
+            } => {
+                // SELECT without FROM (e.g., SELECT 1+1) evaluates against a single empty row
+                let mut plan = match from {
+                    Some(table_ref) => Self::from_table_reference(table_ref)?,
+                    None => LogicalPlan::SingleRow,
+                };
+
+                for join in joins {
+                    plan = LogicalPlan::Join {
+                        left: Box::new(plan),
+                        right: Box::new(Self::from_table_reference(&join.table)?),
+                        join_type: join.join_type.clone(),
+                        condition: join.condition.clone(),
+                    };
+                }
+
+                if let Some(predicate) = where_clause {
+                    plan = LogicalPlan::Filter {
+                        input: Box::new(plan),
+                        predicate: predicate.clone(),
+                    };
+                }
+
+                // ORDER BY may name output aliases or positions; resolve them to
+                // the projected expressions so sorting can happen below Project
+                let order_by: Vec<OrderBy> = order_by
+                    .iter()
+                    .map(|o| OrderBy {
+                        expression: resolve_order_expression(&o.expression, projection),
+                        direction: o.direction.clone(),
+                    })
+                    .collect();
+
+                let mut aggregates = Vec::new();
+                for expr in projection
+                    .iter()
+                    .chain(having.iter())
+                    .chain(order_by.iter().map(|o| &o.expression))
+                {
+                    collect_aggregates(expr, &mut aggregates);
+                }
+
+                if !group_by.is_empty() || !aggregates.is_empty() {
+                    plan = LogicalPlan::Aggregate {
+                        input: Box::new(plan),
+                        group_by: group_by.clone(),
+                        aggregates,
+                    };
+                }
+
+                if let Some(predicate) = having {
+                    plan = LogicalPlan::Filter {
+                        input: Box::new(plan),
+                        predicate: predicate.clone(),
+                    };
+                }
+
+                if !order_by.is_empty() {
+                    plan = LogicalPlan::Sort {
+                        input: Box::new(plan),
+                        order_by,
+                    };
+                }
+
+                plan = LogicalPlan::Project {
+                    input: Box::new(plan),
+                    exprs: projection.clone(),
+                };
+
+                if *distinct {
+                    plan = LogicalPlan::Distinct { input: Box::new(plan) };
+                }
+
+                if limit.is_some() || offset.is_some() {
+                    plan = LogicalPlan::Limit {
+                        input: Box::new(plan),
+                        limit: *limit,
+                        offset: offset.unwrap_or(0),
+                    };
+                }
+
+                Ok(plan)
+            }
+            Statement::Union { left, right, all } => Ok(LogicalPlan::Union {
+                left: Box::new(Self::from_statement(left)?),
+                right: Box::new(Self::from_statement(right)?),
+                all: *all,
+            }),
+            Statement::With {
+                recursive,
+                ctes,
+                body,
+            } => Ok(LogicalPlan::With {
+                recursive: *recursive,
+                ctes: ctes.clone(),
+                input: Box::new(Self::from_statement(body)?),
+            }),
+            _ => Err("Expected a query (SELECT, UNION or WITH)".to_string()),
+        }
+    }
+
+    fn from_table_reference(table_ref: &TableReference) -> Result<Self, String> {
+        match table_ref {
+            TableReference::Table { name, alias } => Ok(LogicalPlan::Scan {
+                table: name.clone(),
+                qualifier: alias.clone().unwrap_or_else(|| name.clone()),
+            }),
+            TableReference::Subquery { query, alias } => Ok(LogicalPlan::Subquery {
+                input: Box::new(Self::from_statement(query)?),
+                alias: alias.clone(),
+            }),
+        }
+    }
+
+    /// Direct inputs of this node
+    pub fn children(&self) -> Vec<&LogicalPlan> {
+        match self {
+            LogicalPlan::Scan { .. } | LogicalPlan::SingleRow => Vec::new(),
+            LogicalPlan::Subquery { input, .. }
+            | LogicalPlan::Filter { input, .. }
+            | LogicalPlan::Aggregate { input, .. }
+            | LogicalPlan::Project { input, .. }
+            | LogicalPlan::Distinct { input }
+            | LogicalPlan::Sort { input, .. }
+            | LogicalPlan::Limit { input, .. }
+            | LogicalPlan::With { input, .. } => vec![input],
+            LogicalPlan::Join { left, right, .. } | LogicalPlan::Union { left, right, .. } => {
+                vec![left, right]
+            }
+        }
+    }
+
+    /// One-line description of this node (without its inputs)
+    pub fn label(&self) -> String {
+        match self {
+            LogicalPlan::Scan { table, qualifier } if table == qualifier => format!("Scan {}", table),
+            LogicalPlan::Scan { table, qualifier } => format!("Scan {} AS {}", table, qualifier),
+            LogicalPlan::SingleRow => "SingleRow".to_string(),
+            LogicalPlan::Subquery { alias, .. } => format!("Subquery AS {}", alias),
+            LogicalPlan::Filter { predicate, .. } => format!("Filter {:?}", predicate),
+            LogicalPlan::Join { join_type, .. } => format!("Join {:?}", join_type),
+            LogicalPlan::Aggregate {
+                group_by,
+                aggregates,
+                ..
+            } => format!("Aggregate group_by={} aggregates={}", group_by.len(), aggregates.len()),
+            LogicalPlan::Project { exprs, .. } => format!("Project {} column(s)", exprs.len()),
+            LogicalPlan::Distinct { .. } => "Distinct".to_string(),
+            LogicalPlan::Sort { order_by, .. } => format!("Sort {} key(s)", order_by.len()),
+            LogicalPlan::Limit { limit, offset, .. } => match limit {
+                Some(limit) => format!("Limit {} offset {}", limit, offset),
+                None => format!("Offset {}", offset),
+            },
+            LogicalPlan::Union { all: true, .. } => "Union All".to_string(),
+            LogicalPlan::Union { all: false, .. } => "Union".to_string(),
+            LogicalPlan::With { ctes, .. } => {
+                let names: Vec<&str> = ctes.iter().map(|c| c.name.as_str()).collect();
+                format!("With {}", names.join(", "))
+            }
+        }
+    }
+
+    fn fmt_tree(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+        writeln!(f, "{}{}", "  ".repeat(depth), self.label())?;
+        for child in self.children() {
+            child.fmt_tree(f, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for LogicalPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_tree(f, 0)
+    }
+}
+
+/// Whether `name` is an aggregate function (COUNT, SUM, ...)
+pub fn is_aggregate_function(name: &str) -> bool {
+    AGGREGATE_FUNCTIONS.iter().any(|f| f.eq_ignore_ascii_case(name))
+}
+
+/// Key under which an Aggregate node stores the value of `expr` in its output rows
+pub fn aggregate_key(expr: &Expression) -> String {
+    format!("{:?}", expr)
+}
+
+/// Whether `expr` calls an aggregate function (outside any subquery)
+pub fn contains_aggregate(expr: &Expression) -> bool {
+    let mut found = Vec::new();
+    collect_aggregates(expr, &mut found);
+    !found.is_empty()
+}
+
+/// Gather the distinct aggregate calls in `expr` (not descending into subqueries)
+fn collect_aggregates(expr: &Expression, out: &mut Vec<Expression>) {
+    match expr {
+        Expression::Function { name, args } => {
+            if is_aggregate_function(name) {
+                if !out.contains(expr) {
+                    out.push(expr.clone());
+                }
+            } else {
+                args.iter().for_each(|a| collect_aggregates(a, out));
+            }
+        }
+        Expression::BinaryOp { left, right, .. } => {
+            collect_aggregates(left, out);
+            collect_aggregates(right, out);
+        }
+        Expression::UnaryOp { operand, .. } => collect_aggregates(operand, out),
+        Expression::Case {
+            when_clauses,
+            else_clause,
+        } => {
+            for (when, then) in when_clauses {
+                collect_aggregates(when, out);
+                collect_aggregates(then, out);
+            }
+            if let Some(else_expr) = else_clause {
+                collect_aggregates(else_expr, out);
+            }
+        }
+        Expression::Alias { expr, .. } => collect_aggregates(expr, out),
+        _ => {}
+    }
+}
+
+/// Map an ORDER BY item that names a projection alias (`ORDER BY total`) or
+/// position (`ORDER BY 2`) to the projected expression
+fn resolve_order_expression(expr: &Expression, projection: &[Expression]) -> Expression {
+    match expr {
+        Expression::Literal(Literal::Number(n)) => match n.parse::<usize>() {
+            Ok(position) if position >= 1 && position <= projection.len() => {
+                unalias(&projection[position - 1]).clone()
+            }
+            _ => expr.clone(),
+        },
+        Expression::Identifier(name) => projection
+            .iter()
+            .find_map(|p| match p {
+                Expression::Alias { expr, alias } if alias == name => Some(expr.as_ref().clone()),
+                _ => None,
+            })
+            .unwrap_or_else(|| expr.clone()),
+        _ => expr.clone(),
+    }
+}
+
+fn unalias(expr: &Expression) -> &Expression {
+    match expr {
+        Expression::Alias { expr, .. } => expr,
+        _ => expr,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::sql::parse_sql;
+
+    fn plan(sql: &str) -> LogicalPlan {
+        LogicalPlan::from_statement(&parse_sql(sql).unwrap()[0]).unwrap()
+    }
+
+    #[test]
+    fn test_plan_shape_for_full_select() {
+        let plan = plan(
+            "SELECT dept, COUNT(*) AS n FROM emp e JOIN dept d ON e.dept_id = d.id \
+             WHERE e.active = TRUE GROUP BY dept HAVING COUNT(*) > 1 ORDER BY n DESC LIMIT 5",
+        );
+        let labels: Vec<String> = plan
+            .to_string()
+            .lines()
+            .map(|l| l.trim().split(' ').next().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            labels,
+            vec!["Limit", "Project", "Sort", "Filter", "Aggregate", "Filter", "Join", "Scan", "Scan"]
+        );
+    }
+
+    #[test]
+    fn test_order_by_alias_and_position_resolve_to_projection() {
+        let plan = plan("SELECT a + 1 AS b, c FROM t ORDER BY b, 2");
+        let sort = &plan.children()[0];
+        match sort {
+            LogicalPlan::Sort { order_by, .. } => {
+                assert!(matches!(order_by[0].expression, Expression::BinaryOp { .. }));
+                assert_eq!(order_by[1].expression, Expression::Identifier("c".to_string()));
+            }
+            other => panic!("Expected Sort, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_aggregates_collected_once() {
+        let plan = plan("SELECT COUNT(*), COUNT(*) + 1 FROM t");
+        match plan.children()[0] {
+            LogicalPlan::Aggregate { aggregates, group_by, .. } => {
+                assert_eq!(aggregates.len(), 1);
+                assert!(group_by.is_empty());
+            }
+            other => panic!("Expected Aggregate, got {:?}", other),
+        }
+    }
+}
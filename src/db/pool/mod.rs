@@ -1,6 +1,8 @@
 // Connection Pool Module
 // Provides connection limiting and management using semaphores
 
+use serde::Serialize;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use tokio::sync::Semaphore;
 use std::time::Duration;
@@ -9,22 +11,60 @@ use crate::config::PoolConfig;
 
 /// Connection pool using semaphore for limiting concurrent connections
 pub struct ConnectionPool {
+    shared: Arc<PoolShared>,
+}
+
+/// State shared between a pool and the guards it hands out
+struct PoolShared {
     /// Semaphore to limit concurrent connections
     semaphore: Arc<Semaphore>,
     /// Pool configuration
     config: PoolConfig,
+    /// Guards currently held
+    open: AtomicUsize,
+    /// Held connections waiting for their next request
+    idle: AtomicUsize,
+    /// Connections turned away because no slot freed up in time
+    rejected: AtomicUsize,
+    /// Idle connections closed by the reaper
+    reaped: AtomicUsize,
 }
 
 /// Guard that releases the semaphore permit when dropped
 pub struct ConnectionGuard {
     _permit: tokio::sync::OwnedSemaphorePermit,
+    shared: Arc<PoolShared>,
+    idle: bool,
+    /// Already taken off the open count by `try_reap`
+    reaped: bool,
+}
+
+/// Snapshot of pool occupancy, as reported by the admin endpoint
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PoolStats {
+    pub max_connections: u32,
+    pub min_connections: u32,
+    pub in_use: usize,
+    pub idle: usize,
+    pub available: usize,
+    pub rejected: usize,
+    pub reaped: usize,
 }
 
 impl ConnectionPool {
     /// Create a new connection pool with the given configuration
     pub fn new(config: PoolConfig) -> Self {
         let semaphore = Arc::new(Semaphore::new(config.max_connections as usize));
-        Self { semaphore, config }
+        Self {
+            shared: Arc::new(PoolShared {
+                semaphore,
+                config,
+                open: AtomicUsize::new(0),
+                idle: AtomicUsize::new(0),
+                rejected: AtomicUsize::new(0),
+                reaped: AtomicUsize::new(0),
+            }),
+        }
     }
 
     /// Create a connection pool with default configuration
@@ -35,31 +75,108 @@ impl ConnectionPool {
     /// Acquire a connection from the pool
     /// Returns a ConnectionGuard that releases the connection when dropped
     pub async fn acquire(&self) -> Result<ConnectionGuard, String> {
-        let timeout = Duration::from_millis(self.config.connection_timeout_ms);
+        let config = &self.shared.config;
+        let timeout = Duration::from_millis(config.connection_timeout_ms);
         
-        match tokio::time::timeout(timeout, self.semaphore.clone().acquire_owned()).await {
-            Ok(Ok(permit)) => Ok(ConnectionGuard { _permit: permit }),
+        match tokio::time::timeout(timeout, self.shared.semaphore.clone().acquire_owned()).await {
+            Ok(Ok(permit)) => {
+                self.shared.open.fetch_add(1, Ordering::AcqRel);
+                Ok(ConnectionGuard {
+                    _permit: permit,
+                    shared: Arc::clone(&self.shared),
+                    idle: false,
+                    reaped: false,
+                })
+            }
             Ok(Err(_)) => Err("Connection pool closed".to_string()),
-            Err(_) => Err(format!(
-                "Connection pool timeout after {}ms", 
-                self.config.connection_timeout_ms
-            )),
+            Err(_) => {
+                self.shared.rejected.fetch_add(1, Ordering::Relaxed);
+                Err(format!(
+                    "Connection pool timeout after {}ms", 
+                    config.connection_timeout_ms
+                ))
+            }
         }
     }
 
     /// Get the number of available connections
     pub fn available(&self) -> usize {
-        self.semaphore.available_permits()
+        self.shared.semaphore.available_permits()
     }
 
     /// Get the maximum number of connections
     pub fn max_connections(&self) -> u32 {
-        self.config.max_connections
+        self.shared.config.max_connections
     }
 
     /// Get the current pool configuration
     pub fn config(&self) -> &PoolConfig {
-        &self.config
+        &self.shared.config
+    }
+
+    /// How long a turned-away client should wait before trying again
+    pub fn retry_after(&self) -> Duration {
+        Duration::from_secs(self.shared.config.connection_timeout_ms.div_ceil(1000).max(1))
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        let shared = &self.shared;
+        PoolStats {
+            max_connections: shared.config.max_connections,
+            min_connections: shared.config.min_connections,
+            in_use: shared.open.load(Ordering::Acquire),
+            idle: shared.idle.load(Ordering::Acquire),
+            available: self.available(),
+            rejected: shared.rejected.load(Ordering::Relaxed),
+            reaped: shared.reaped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl ConnectionGuard {
+    /// How long the connection may wait for a request before it is reaped
+    pub fn idle_timeout(&self) -> Duration {
+        Duration::from_millis(self.shared.config.idle_timeout_ms)
+    }
+
+    /// Mark the connection as waiting for a request (or no longer waiting)
+    pub fn set_idle(&mut self, idle: bool) {
+        if idle != self.idle {
+            match idle {
+                true => self.shared.idle.fetch_add(1, Ordering::AcqRel),
+                false => self.shared.idle.fetch_sub(1, Ordering::AcqRel),
+            };
+            self.idle = idle;
+        }
+    }
+
+    /// Claim the right to close this idle connection. Refused while closing
+    /// it would leave fewer than `min_connections` open, so that many
+    /// clients always keep their connections.
+    pub fn try_reap(&mut self) -> bool {
+        if self.reaped {
+            return true;
+        }
+        let min = self.shared.config.min_connections as usize;
+        let claimed = self
+            .shared
+            .open
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |open| (open > min).then(|| open - 1))
+            .is_ok();
+        if claimed {
+            self.reaped = true;
+            self.shared.reaped.fetch_add(1, Ordering::Relaxed);
+        }
+        claimed
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.set_idle(false);
+        if !self.reaped {
+            self.shared.open.fetch_sub(1, Ordering::AcqRel);
+        }
     }
 }
 
@@ -138,4 +255,48 @@ mod tests {
         drop(g3);
         assert_eq!(pool.available(), 3);
     }
+
+    #[tokio::test]
+    async fn test_pool_exhausted_rejects_after_timeout() {
+        let config = PoolConfig {
+            min_connections: 0,
+            max_connections: 1,
+            connection_timeout_ms: 20,
+            idle_timeout_ms: 5000,
+        };
+        let pool = ConnectionPool::new(config);
+
+        let _held = pool.acquire().await.unwrap();
+        assert!(pool.acquire().await.is_err());
+        assert_eq!(pool.stats().rejected, 1);
+        assert_eq!(pool.retry_after(), Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_idle_reaping_keeps_min_connections() {
+        let config = PoolConfig {
+            min_connections: 2,
+            max_connections: 5,
+            connection_timeout_ms: 1000,
+            idle_timeout_ms: 5000,
+        };
+        let pool = ConnectionPool::new(config);
+        let mut guards = Vec::new();
+        for _ in 0..3 {
+            let mut guard = pool.acquire().await.unwrap();
+            guard.set_idle(true);
+            guards.push(guard);
+        }
+        assert_eq!((pool.stats().in_use, pool.stats().idle), (3, 3));
+
+        // Only the connection above the minimum may go
+        let reaped: Vec<bool> = guards.iter_mut().map(|guard| guard.try_reap()).collect();
+        assert_eq!(reaped, [true, false, false]);
+        assert_eq!(pool.stats().in_use, 2);
+        assert_eq!(pool.stats().reaped, 1);
+
+        guards.clear();
+        let stats = pool.stats();
+        assert_eq!((stats.in_use, stats.idle, stats.available), (0, 0, 5));
+    }
 }
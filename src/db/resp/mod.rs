@@ -0,0 +1,667 @@
+// Redis protocol (RESP2/RESP3) front end - key-value access to a partition's
+// map for off-the-shelf Redis clients, and pub/sub over the LISTEN / NOTIFY
+// channels
+pub mod protocol;
+pub mod store;
+
+use std::io::{Error, ErrorKind};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, BufWriter};
+use tokio::net::TcpListener;
+
+use crate::db::notify::{self, Listener, Notification};
+use crate::db::pool::{ConnectionPool, POOL};
+use crate::{info, warn};
+use protocol::Reply;
+use store::{now_ms, KvStore, SetCondition, SetExpiry, SetOptions, Ttl};
+
+/// How often keys past their deadline are purged, for keys nobody reads
+const EXPIRY_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Ids handed out to connections (CLIENT ID, HELLO)
+static NEXT_CLIENT_ID: AtomicI64 = AtomicI64::new(1);
+
+/// Accept Redis clients on `addr`, each connection in its own task, all
+/// served from `store`
+pub async fn serve(addr: SocketAddr, store: Arc<KvStore>) -> Result<(), Error> {
+    let listener = TcpListener::bind(addr).await?;
+    info!(format!("Redis protocol listening on {}", addr));
+
+    let sweeper = Arc::clone(&store);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(EXPIRY_SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            let store = Arc::clone(&sweeper);
+            if let Ok(Err(e)) = tokio::task::spawn_blocking(move || store.purge_expired()).await {
+                warn!(format!("Failed to purge expired keys: {}", e));
+            }
+        }
+    });
+
+    loop {
+        let (stream, remote_addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!(format!("Failed to accept a Redis connection: {}", e));
+                continue;
+            }
+        };
+        let store = Arc::clone(&store);
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, store, &POOL).await {
+                warn!(format!("Redis connection from {} failed: {}", remote_addr, e));
+            }
+        });
+    }
+}
+
+/// Per-connection state
+struct Client {
+    id: i64,
+    /// RESP version replies are encoded in; HELLO changes it
+    protocol: u8,
+    name: Option<String>,
+    /// Channels subscribed to with SUBSCRIBE, once there have been any
+    listener: Option<Listener>,
+}
+
+impl Client {
+    fn subscriptions(&self) -> usize {
+        self.listener.as_ref().map_or(0, |listener| listener.channels().len())
+    }
+}
+
+/// The next message on a channel the client subscribes to; never ready for
+/// a client that has not subscribed
+async fn next_message(listener: &mut Option<Listener>) -> Notification {
+    match listener {
+        Some(listener) => match listener.recv().await {
+            Some(notification) => notification,
+            None => std::future::pending().await,
+        },
+        None => std::future::pending().await,
+    }
+}
+
+/// Serve one client connection until it quits or hangs up. The connection
+/// holds a slot in `pool` while open.
+pub async fn handle_connection<S>(stream: S, store: Arc<KvStore>, pool: &ConnectionPool) -> Result<(), Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (reader, writer) = tokio::io::split(stream);
+    let mut reader = BufReader::new(reader);
+    let mut writer = BufWriter::new(writer);
+
+    let _guard = match pool.acquire().await {
+        Ok(guard) => guard,
+        Err(_) => {
+            writer.write_all(b"-ERR max number of clients reached\r\n").await?;
+            return writer.flush().await;
+        }
+    };
+
+    let mut client = Client {
+        id: NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed),
+        protocol: 2,
+        name: None,
+        listener: None,
+    };
+    let mut out = Vec::new();
+    loop {
+        // Between commands, messages on subscribed channels are passed on
+        if reader.buffer().is_empty() {
+            tokio::select! {
+                filled = reader.fill_buf() => {
+                    if filled?.is_empty() {
+                        return Ok(());
+                    }
+                }
+                notification = next_message(&mut client.listener) => {
+                    let message = [notification.channel.into_bytes(), notification.payload.into_bytes()];
+                    Reply::Push([b"message".to_vec()].into_iter().chain(message).map(Reply::Bulk).collect())
+                        .encode(client.protocol, &mut out);
+                    writer.write_all(&std::mem::take(&mut out)).await?;
+                    writer.flush().await?;
+                    continue;
+                }
+            }
+        }
+
+        let args = match protocol::read_command(&mut reader).await {
+            Ok(Some(args)) => args,
+            Ok(None) => return Ok(()),
+            Err(e) if e.kind() == ErrorKind::InvalidData => {
+                Reply::error(format!("ERR {}", e)).encode(client.protocol, &mut out);
+                writer.write_all(&out).await?;
+                return writer.flush().await;
+            }
+            Err(e) => return Err(e),
+        };
+
+        let command = String::from_utf8_lossy(&args[0]).to_uppercase();
+        let quit = command == "QUIT";
+        // A RESP2 connection carries nothing but messages once subscribed
+        let subscribed = client.protocol == 2 && client.subscriptions() > 0;
+        let replies = match command.as_str() {
+            "SUBSCRIBE" | "UNSUBSCRIBE" => subscription_command(&mut client, &command, &args),
+            "PING" if subscribed => {
+                let echo = args.get(1).cloned().unwrap_or_default();
+                vec![Reply::Array(vec![Reply::bulk("pong"), Reply::Bulk(echo)])]
+            }
+            _ if subscribed && !quit => vec![Reply::error(format!(
+                "ERR Can't execute '{}': only SUBSCRIBE / UNSUBSCRIBE / PING / QUIT are allowed in this context",
+                command.to_lowercase()
+            ))],
+            "HELLO" | "SELECT" | "CLIENT" | "QUIT" => vec![connection_command(&mut client, &command, &args)],
+            // Writes wait on the disk, so commands run off the runtime
+            _ => {
+                let store = Arc::clone(&store);
+                vec![tokio::task::spawn_blocking(move || execute(&store, &command, &args))
+                    .await
+                    .unwrap_or_else(|_| Reply::error("ERR command panicked"))]
+            }
+        };
+        for reply in replies {
+            reply.encode(client.protocol, &mut out);
+        }
+
+        if quit {
+            writer.write_all(&out).await?;
+            return writer.flush().await;
+        }
+        // Pipelined commands are answered together
+        if reader.buffer().is_empty() {
+            writer.write_all(&std::mem::take(&mut out)).await?;
+            writer.flush().await?;
+        }
+    }
+}
+
+fn wrong_arity(command: &str) -> String {
+    format!("ERR wrong number of arguments for '{}' command", command.to_lowercase())
+}
+
+fn text(arg: &[u8]) -> Result<String, String> {
+    String::from_utf8(arg.to_vec()).map_err(|_| "ERR keys and values must be valid UTF-8".to_string())
+}
+
+fn texts(args: &[Vec<u8>]) -> Result<Vec<String>, String> {
+    args.iter().map(|arg| text(arg)).collect()
+}
+
+fn integer(arg: &[u8]) -> Result<i64, String> {
+    std::str::from_utf8(arg)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| "ERR value is not an integer or out of range".to_string())
+}
+
+fn storage_error(message: String) -> String {
+    format!("ERR {}", message)
+}
+
+/// Unix-millisecond deadline `ms` from now, or at the epoch if that is
+/// already past
+fn deadline_in(ms: i64) -> u64 {
+    (now_ms() as i64).saturating_add(ms).max(0) as u64
+}
+
+/// Commands that act on the connection rather than the data
+fn connection_command(client: &mut Client, command: &str, args: &[Vec<u8>]) -> Reply {
+    let upper = |i: usize| String::from_utf8_lossy(&args[i]).to_uppercase();
+    match command {
+        "HELLO" => {
+            let protocol = match args.get(1).map(|version| integer(version)) {
+                None => client.protocol,
+                Some(Ok(version @ 2..=3)) => version as u8,
+                Some(_) => return Reply::error("NOPROTO unsupported protocol version"),
+            };
+            let mut name = client.name.clone();
+            let mut i = 2;
+            while i < args.len() {
+                match upper(i).as_str() {
+                    // No users or passwords are configured; any login is accepted
+                    "AUTH" if i + 2 < args.len() => i += 3,
+                    "SETNAME" if i + 1 < args.len() => {
+                        name = Some(String::from_utf8_lossy(&args[i + 1]).into_owned());
+                        i += 2;
+                    }
+                    _ => return Reply::error("ERR syntax error"),
+                }
+            }
+            client.protocol = protocol;
+            client.name = name;
+            Reply::Map(vec![
+                (Reply::bulk("server"), Reply::bulk("butterfly_db")),
+                (Reply::bulk("version"), Reply::bulk(env!("CARGO_PKG_VERSION"))),
+                (Reply::bulk("proto"), Reply::Integer(i64::from(client.protocol))),
+                (Reply::bulk("id"), Reply::Integer(client.id)),
+                (Reply::bulk("mode"), Reply::bulk("standalone")),
+                (Reply::bulk("role"), Reply::bulk("master")),
+                (Reply::bulk("modules"), Reply::Array(Vec::new())),
+            ])
+        }
+        "SELECT" if args.len() != 2 => Reply::Error(wrong_arity(command)),
+        // There is one keyspace per partition
+        "SELECT" => match integer(&args[1]) {
+            Ok(0) => Reply::ok(),
+            Ok(_) => Reply::error("ERR DB index is out of range"),
+            Err(e) => Reply::Error(e),
+        },
+        "CLIENT" if args.len() < 2 => Reply::Error(wrong_arity(command)),
+        "CLIENT" => match upper(1).as_str() {
+            "ID" => Reply::Integer(client.id),
+            "GETNAME" => client.name.clone().map_or(Reply::Null, Reply::bulk),
+            "SETNAME" if args.len() == 3 => {
+                client.name = Some(String::from_utf8_lossy(&args[2]).into_owned());
+                Reply::ok()
+            }
+            // Client libraries announce themselves; nothing is kept
+            "SETINFO" => Reply::ok(),
+            sub => Reply::error(format!("ERR unknown subcommand '{}'", sub.to_lowercase())),
+        },
+        _ => Reply::ok(),
+    }
+}
+
+/// SUBSCRIBE and UNSUBSCRIBE, which answer once per channel. UNSUBSCRIBE
+/// with no channels leaves every channel.
+fn subscription_command(client: &mut Client, command: &str, args: &[Vec<u8>]) -> Vec<Reply> {
+    let channels = match texts(&args[1..]) {
+        Ok(channels) => channels,
+        Err(message) => return vec![Reply::Error(message)],
+    };
+    let kind = command.to_lowercase();
+    let confirm = |channel: Option<&String>, count: usize| {
+        let channel = channel.map_or(Reply::Null, |channel| Reply::bulk(channel.as_str()));
+        Reply::Push(vec![Reply::bulk(kind.as_str()), channel, Reply::Integer(count as i64)])
+    };
+    if command == "SUBSCRIBE" {
+        if channels.is_empty() {
+            return vec![Reply::Error(wrong_arity(command))];
+        }
+        let listener = client.listener.get_or_insert_with(Listener::new);
+        return channels
+            .iter()
+            .map(|channel| {
+                listener.listen(channel);
+                confirm(Some(channel), listener.channels().len())
+            })
+            .collect();
+    }
+
+    let channels = match (&client.listener, channels.is_empty()) {
+        (Some(listener), true) => listener.channels(),
+        _ => channels,
+    };
+    if channels.is_empty() {
+        return vec![confirm(None, 0)];
+    }
+    channels
+        .iter()
+        .map(|channel| {
+            if let Some(listener) = &client.listener {
+                listener.unlisten(Some(channel));
+            }
+            confirm(Some(channel), client.subscriptions())
+        })
+        .collect()
+}
+
+/// Run a data command against `store`
+fn execute(store: &KvStore, command: &str, args: &[Vec<u8>]) -> Reply {
+    match run(store, command, args) {
+        Ok(reply) => reply,
+        Err(message) => Reply::Error(message),
+    }
+}
+
+fn run(store: &KvStore, command: &str, args: &[Vec<u8>]) -> Result<Reply, String> {
+    let arity = |ok: bool| if ok { Ok(()) } else { Err(wrong_arity(command)) };
+    let reply = match command {
+        "PING" => {
+            arity(args.len() <= 2)?;
+            args.get(1).map_or(Reply::Simple("PONG".to_string()), |message| Reply::bulk(message.clone()))
+        }
+        "ECHO" => {
+            arity(args.len() == 2)?;
+            Reply::bulk(args[1].clone())
+        }
+        // Reaches SQL sessions that LISTEN on the channel too
+        "PUBLISH" => {
+            arity(args.len() == 3)?;
+            let notification = Notification::new(&text(&args[1])?, &text(&args[2])?, 0).map_err(storage_error)?;
+            Reply::Integer(notify::notify(&notification) as i64)
+        }
+        // Clients ask for command metadata on connect; none is published
+        "COMMAND" => Reply::Array(Vec::new()),
+        "GET" => {
+            arity(args.len() == 2)?;
+            store.get(&text(&args[1])?).map_err(storage_error)?.map_or(Reply::Null, Reply::bulk)
+        }
+        "SET" => {
+            arity(args.len() >= 3)?;
+            set(store, args)?
+        }
+        "MGET" => {
+            arity(args.len() >= 2)?;
+            let values = store.mget(&texts(&args[1..])?).map_err(storage_error)?;
+            Reply::Array(values.into_iter().map(|v| v.map_or(Reply::Null, Reply::bulk)).collect())
+        }
+        "MSET" => {
+            arity(args.len() >= 3 && args.len() % 2 == 1)?;
+            let pairs = texts(&args[1..])?
+                .chunks(2)
+                .map(|pair| (pair[0].clone(), pair[1].clone()))
+                .collect::<Vec<_>>();
+            store.mset(&pairs).map_err(storage_error)?;
+            Reply::ok()
+        }
+        "DEL" => {
+            arity(args.len() >= 2)?;
+            Reply::Integer(store.del(&texts(&args[1..])?).map_err(storage_error)? as i64)
+        }
+        "EXISTS" => {
+            arity(args.len() >= 2)?;
+            Reply::Integer(store.exists(&texts(&args[1..])?).map_err(storage_error)? as i64)
+        }
+        "EXPIRE" | "PEXPIRE" => {
+            arity(args.len() == 3)?;
+            let amount = integer(&args[2])?;
+            let ms = match command {
+                "EXPIRE" => amount.checked_mul(1000).ok_or("ERR invalid expire time in 'expire' command")?,
+                _ => amount,
+            };
+            let updated = store.expire(&text(&args[1])?, Some(deadline_in(ms))).map_err(storage_error)?;
+            Reply::Integer(i64::from(updated))
+        }
+        "PERSIST" => {
+            arity(args.len() == 2)?;
+            Reply::Integer(i64::from(store.expire(&text(&args[1])?, None).map_err(storage_error)?))
+        }
+        "TTL" | "PTTL" => {
+            arity(args.len() == 2)?;
+            Reply::Integer(match store.ttl(&text(&args[1])?).map_err(storage_error)? {
+                Ttl::Missing => -2,
+                Ttl::Persistent => -1,
+                Ttl::Millis(ms) if command == "TTL" => ms.div_ceil(1000) as i64,
+                Ttl::Millis(ms) => ms as i64,
+            })
+        }
+        "KEYS" => {
+            arity(args.len() == 2)?;
+            let keys = store.keys(&text(&args[1])?).map_err(storage_error)?;
+            Reply::Array(keys.into_iter().map(Reply::bulk).collect())
+        }
+        "SCAN" => {
+            arity(args.len() >= 2)?;
+            scan(store, args)?
+        }
+        "DBSIZE" => Reply::Integer(store.len().map_err(storage_error)? as i64),
+        _ => {
+            let preview: Vec<String> = args[1..]
+                .iter()
+                .map(|arg| format!("'{}'", String::from_utf8_lossy(arg)))
+                .collect();
+            return Err(format!(
+                "ERR unknown command '{}', with args beginning with: {}",
+                String::from_utf8_lossy(&args[0]),
+                preview.join(" ")
+            ));
+        }
+    };
+    Ok(reply)
+}
+
+/// SET key value [NX | XX] [GET] [EX s | PX ms | EXAT s | PXAT ms | KEEPTTL]
+fn set(store: &KvStore, args: &[Vec<u8>]) -> Result<Reply, String> {
+    let syntax = || "ERR syntax error".to_string();
+    let invalid_expiry = || "ERR invalid expire time in 'set' command".to_string();
+    let mut options = SetOptions::default();
+    let mut get = false;
+    let mut expiry_given = false;
+    let mut i = 3;
+    while i < args.len() {
+        let option = String::from_utf8_lossy(&args[i]).to_uppercase();
+        match option.as_str() {
+            "NX" | "XX" if options.condition != SetCondition::Always => return Err(syntax()),
+            "NX" => options.condition = SetCondition::IfMissing,
+            "XX" => options.condition = SetCondition::IfExists,
+            "GET" => get = true,
+            "KEEPTTL" if expiry_given => return Err(syntax()),
+            "KEEPTTL" => {
+                options.expiry = SetExpiry::Keep;
+                expiry_given = true;
+            }
+            "EX" | "PX" | "EXAT" | "PXAT" => {
+                if expiry_given {
+                    return Err(syntax());
+                }
+                let amount = integer(args.get(i + 1).ok_or_else(syntax)?)?;
+                if amount <= 0 {
+                    return Err(invalid_expiry());
+                }
+                let deadline = match option.as_str() {
+                    "EX" => deadline_in(amount.checked_mul(1000).ok_or_else(invalid_expiry)?),
+                    "PX" => deadline_in(amount),
+                    "EXAT" => amount.checked_mul(1000).ok_or_else(invalid_expiry)? as u64,
+                    _ => amount as u64,
+                };
+                options.expiry = SetExpiry::At(deadline);
+                expiry_given = true;
+                i += 1;
+            }
+            _ => return Err(syntax()),
+        }
+        i += 1;
+    }
+
+    let (applied, previous) = store
+        .set(&text(&args[1])?, &text(&args[2])?, &options)
+        .map_err(storage_error)?;
+    Ok(match (get, applied) {
+        (true, _) => previous.map_or(Reply::Null, Reply::bulk),
+        (false, true) => Reply::ok(),
+        (false, false) => Reply::Null,
+    })
+}
+
+/// SCAN cursor [MATCH pattern] [COUNT count]
+fn scan(store: &KvStore, args: &[Vec<u8>]) -> Result<Reply, String> {
+    let cursor: u64 = std::str::from_utf8(&args[1])
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or("ERR invalid cursor")?;
+    let mut pattern = None;
+    let mut count = store::DEFAULT_SCAN_COUNT;
+    let mut i = 2;
+    while i < args.len() {
+        let value = args.get(i + 1).ok_or("ERR syntax error")?;
+        match String::from_utf8_lossy(&args[i]).to_uppercase().as_str() {
+            "MATCH" => pattern = Some(text(value)?),
+            "COUNT" => match integer(value)? {
+                n if n >= 1 => count = n as usize,
+                _ => return Err("ERR syntax error".to_string()),
+            },
+            _ => return Err("ERR syntax error".to_string()),
+        }
+        i += 2;
+    }
+    let (next, keys) = store.scan(cursor, pattern.as_deref(), count).map_err(storage_error)?;
+    Ok(Reply::Array(vec![
+        Reply::bulk(next.to_string()),
+        Reply::Array(keys.into_iter().map(Reply::bulk).collect()),
+    ]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PoolConfig;
+    use std::collections::BTreeMap;
+    use std::sync::Mutex;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, DuplexStream};
+
+    struct Client {
+        reader: BufReader<DuplexStream>,
+    }
+
+    impl Client {
+        fn connect(store: Arc<KvStore>) -> Self {
+            let (stream, server) = tokio::io::duplex(1 << 16);
+            let pool = Arc::new(ConnectionPool::new(PoolConfig::default()));
+            tokio::spawn(async move { handle_connection(server, store, &pool).await });
+            Self {
+                reader: BufReader::new(stream),
+            }
+        }
+
+        async fn send(&mut self, args: &[&str]) {
+            let mut command = format!("*{}\r\n", args.len());
+            for arg in args {
+                command.push_str(&format!("${}\r\n{}\r\n", arg.len(), arg));
+            }
+            self.reader.get_mut().write_all(command.as_bytes()).await.unwrap();
+        }
+
+        /// One reply, rendered back to its wire form
+        async fn read(&mut self) -> String {
+            let mut line = String::new();
+            self.reader.read_line(&mut line).await.unwrap();
+            let count = line[1..].trim_end().parse::<i64>().unwrap_or(0);
+            match line.as_bytes()[0] {
+                b'$' if count >= 0 => {
+                    let mut body = vec![0; count as usize + 2];
+                    self.reader.read_exact(&mut body).await.unwrap();
+                    format!("{}{}", line, String::from_utf8_lossy(&body))
+                }
+                b'*' | b'%' | b'>' => {
+                    let items = if line.starts_with('%') { count * 2 } else { count };
+                    let mut reply = line;
+                    for _ in 0..items {
+                        reply.push_str(&Box::pin(self.read()).await);
+                    }
+                    reply
+                }
+                _ => line,
+            }
+        }
+
+        async fn call(&mut self, args: &[&str]) -> String {
+            self.send(args).await;
+            self.read().await
+        }
+    }
+
+    fn store(name: &str) -> (Arc<KvStore>, std::path::PathBuf) {
+        let path = std::env::temp_dir().join(format!("butterfly_resp_{}_{}.log", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        (Arc::new(KvStore::open(Arc::new(Mutex::new(BTreeMap::new())), &path).unwrap()), path)
+    }
+
+    #[tokio::test]
+    async fn test_string_commands() {
+        let (store, path) = store("strings");
+        let mut client = Client::connect(Arc::clone(&store));
+
+        assert_eq!(client.call(&["PING"]).await, "+PONG\r\n");
+        assert_eq!(client.call(&["SET", "greeting", "hello world"]).await, "+OK\r\n");
+        assert_eq!(client.call(&["GET", "greeting"]).await, "$11\r\nhello world\r\n");
+        assert_eq!(client.call(&["GET", "missing"]).await, "$-1\r\n");
+        assert_eq!(client.call(&["SET", "greeting", "x", "NX"]).await, "$-1\r\n");
+        assert_eq!(client.call(&["SET", "fresh", "x", "XX"]).await, "$-1\r\n");
+        assert_eq!(client.call(&["SET", "greeting", "hi", "XX", "GET"]).await, "$11\r\nhello world\r\n");
+        assert_eq!(client.call(&["SET", "session", "s", "EX", "100"]).await, "+OK\r\n");
+        assert_eq!(client.call(&["TTL", "session"]).await, ":100\r\n");
+        assert_eq!(client.call(&["TTL", "greeting"]).await, ":-1\r\n");
+        assert_eq!(client.call(&["TTL", "missing"]).await, ":-2\r\n");
+        assert_eq!(client.call(&["SET", "k", "v", "EX", "0"]).await, "-ERR invalid expire time in 'set' command\r\n");
+        assert_eq!(client.call(&["SET", "k", "v", "EX", "1", "PX", "5"]).await, "-ERR syntax error\r\n");
+        assert_eq!(client.call(&["EXPIRE", "greeting", "50"]).await, ":1\r\n");
+        assert_eq!(client.call(&["EXPIRE", "missing", "50"]).await, ":0\r\n");
+        assert_eq!(client.call(&["SET", "blink", "b", "PX", "1"]).await, "+OK\r\n");
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert_eq!(client.call(&["GET", "blink"]).await, "$-1\r\n");
+
+        assert_eq!(client.call(&["MSET", "a", "1", "b", "2"]).await, "+OK\r\n");
+        assert_eq!(client.call(&["MSET", "a", "1", "b"]).await, "-ERR wrong number of arguments for 'mset' command\r\n");
+        assert_eq!(client.call(&["MGET", "a", "nope", "b"]).await, "*3\r\n$1\r\n1\r\n$-1\r\n$1\r\n2\r\n");
+        assert_eq!(client.call(&["EXISTS", "a", "a", "nope"]).await, ":2\r\n");
+        assert_eq!(client.call(&["DEL", "a", "nope"]).await, ":1\r\n");
+        assert_eq!(client.call(&["KEYS", "*"]).await, "*3\r\n$1\r\nb\r\n$8\r\ngreeting\r\n$7\r\nsession\r\n");
+        assert!(client.call(&["FLY", "away"]).await.starts_with("-ERR unknown command 'FLY'"));
+
+        // The partition map holds what was written
+        assert_eq!(store.data().lock().unwrap().get("b"), Some(&"2".to_string()));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_resp3_pipelining_and_scan() {
+        let (store, path) = store("resp3");
+        let mut client = Client::connect(store);
+
+        let hello = client.call(&["HELLO", "3", "SETNAME", "cache"]).await;
+        assert!(hello.starts_with("%7\r\n$6\r\nserver\r\n"));
+        assert!(hello.contains("$5\r\nproto\r\n:3\r\n"));
+        assert_eq!(client.call(&["HELLO", "4"]).await, "-NOPROTO unsupported protocol version\r\n");
+        assert_eq!(client.call(&["CLIENT", "GETNAME"]).await, "$5\r\ncache\r\n");
+        assert_eq!(client.call(&["GET", "missing"]).await, "_\r\n");
+
+        // Pipelined commands, answered in order
+        for i in 0..30 {
+            client.send(&["SET", &format!("item:{}", i), &i.to_string()]).await;
+        }
+        for _ in 0..30 {
+            assert_eq!(client.read().await, "+OK\r\n");
+        }
+
+        let mut found = 0;
+        let mut cursor = "0".to_string();
+        loop {
+            let reply = client.call(&["SCAN", &cursor, "MATCH", "item:*", "COUNT", "4"]).await;
+            let lines: Vec<&str> = reply.split("\r\n").collect();
+            cursor = lines[2].to_string();
+            found += lines.iter().filter(|line| line.starts_with("item:")).count();
+            if cursor == "0" {
+                break;
+            }
+        }
+        assert_eq!(found, 30);
+        assert_eq!(client.call(&["SCAN", "x"]).await, "-ERR invalid cursor\r\n");
+
+        client.reader.get_mut().write_all(b"*1\r\n$4\r\nPINGX\r\n").await.unwrap();
+        assert!(client.read().await.starts_with("-ERR Protocol error"));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_and_publish() {
+        let (store, path) = store("pubsub");
+        let mut subscriber = Client::connect(Arc::clone(&store));
+        let mut publisher = Client::connect(store);
+
+        subscriber.send(&["SUBSCRIBE", "resp_cache", "resp_jobs"]).await;
+        assert_eq!(subscriber.read().await, "*3\r\n$9\r\nsubscribe\r\n$10\r\nresp_cache\r\n:1\r\n");
+        assert_eq!(subscriber.read().await, "*3\r\n$9\r\nsubscribe\r\n$9\r\nresp_jobs\r\n:2\r\n");
+        assert!(subscriber.call(&["GET", "k"]).await.starts_with("-ERR Can't execute 'get'"));
+        assert_eq!(subscriber.call(&["PING"]).await, "*2\r\n$4\r\npong\r\n$0\r\n\r\n");
+
+        assert_eq!(publisher.call(&["PUBLISH", "resp_cache", "users:42"]).await, ":1\r\n");
+        assert_eq!(subscriber.read().await, "*3\r\n$7\r\nmessage\r\n$10\r\nresp_cache\r\n$8\r\nusers:42\r\n");
+
+        subscriber.send(&["UNSUBSCRIBE"]).await;
+        let left = [subscriber.read().await, subscriber.read().await];
+        assert!(left[1].ends_with(":0\r\n"));
+        assert_eq!(publisher.call(&["PUBLISH", "resp_cache", "users:42"]).await, ":0\r\n");
+        assert_eq!(subscriber.call(&["UNSUBSCRIBE"]).await, "*3\r\n$11\r\nunsubscribe\r\n$-1\r\n:0\r\n");
+        assert_eq!(subscriber.call(&["GET", "k"]).await, "$-1\r\n");
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,191 @@
+// RESP framing: commands in, RESP2 or RESP3 replies out
+use std::io::{Error, ErrorKind};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
+
+/// Longest bulk string a client may send
+pub const MAX_BULK_BYTES: usize = 64 * 1024 * 1024;
+/// Most arguments one command may have
+pub const MAX_ARGS: usize = 1024 * 1024;
+/// Longest inline command or header line
+const MAX_LINE_BYTES: usize = 64 * 1024;
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("Protocol error: {}", message))
+}
+
+async fn read_line<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<Option<Vec<u8>>, Error> {
+    let mut line = Vec::new();
+    let read = (&mut *reader).take(MAX_LINE_BYTES as u64 + 2).read_until(b'\n', &mut line).await?;
+    if read == 0 {
+        return Ok(None);
+    }
+    if line.last() != Some(&b'\n') {
+        return Err(invalid("line too long or truncated"));
+    }
+    line.pop();
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    Ok(Some(line))
+}
+
+/// Length in a `*<n>` or `$<n>` header
+fn header_length(line: &[u8], prefix: u8, max: usize) -> Result<usize, Error> {
+    let digits = line
+        .strip_prefix(&[prefix])
+        .ok_or_else(|| invalid(&format!("expected '{}'", prefix as char)))?;
+    let length: usize = std::str::from_utf8(digits)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| invalid("invalid length"))?;
+    if length > max {
+        return Err(invalid("length out of range"));
+    }
+    Ok(length)
+}
+
+/// Read the next command: an array of bulk strings, or an inline command
+/// (words on a line, as typed into telnet). Ok(None) at end of stream;
+/// InvalidData on a malformed command.
+pub async fn read_command<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<Option<Vec<Vec<u8>>>, Error> {
+    loop {
+        let Some(line) = read_line(reader).await? else {
+            return Ok(None);
+        };
+        if line.first() != Some(&b'*') {
+            let words: Vec<Vec<u8>> = line
+                .split(|b| b.is_ascii_whitespace())
+                .filter(|word| !word.is_empty())
+                .map(<[u8]>::to_vec)
+                .collect();
+            // Blank lines between commands are skipped
+            if words.is_empty() {
+                continue;
+            }
+            return Ok(Some(words));
+        }
+
+        let count = header_length(&line, b'*', MAX_ARGS)?;
+        let mut args = Vec::with_capacity(count.min(64));
+        for _ in 0..count {
+            let header = read_line(reader).await?.ok_or_else(|| invalid("truncated command"))?;
+            let length = header_length(&header, b'$', MAX_BULK_BYTES)?;
+            let mut arg = vec![0; length + 2];
+            reader.read_exact(&mut arg).await?;
+            if !arg.ends_with(b"\r\n") {
+                return Err(invalid("bulk string not terminated by CRLF"));
+            }
+            arg.truncate(length);
+            args.push(arg);
+        }
+        if !args.is_empty() {
+            return Ok(Some(args));
+        }
+    }
+}
+
+/// A reply to one command
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reply {
+    Simple(String),
+    /// Message starting with its error code, e.g. "ERR syntax error"
+    Error(String),
+    Integer(i64),
+    Bulk(Vec<u8>),
+    Null,
+    Array(Vec<Reply>),
+    /// A RESP3 map; RESP2 clients get it as a flat array
+    Map(Vec<(Reply, Reply)>),
+    /// Out-of-band data such as pub/sub messages; RESP2 clients get an array
+    Push(Vec<Reply>),
+}
+
+impl Reply {
+    pub fn ok() -> Reply {
+        Reply::Simple("OK".to_string())
+    }
+
+    pub fn error(message: impl Into<String>) -> Reply {
+        Reply::Error(message.into())
+    }
+
+    pub fn bulk(value: impl Into<Vec<u8>>) -> Reply {
+        Reply::Bulk(value.into())
+    }
+
+    /// Encode for a connection speaking protocol version `protocol` (2 or 3)
+    pub fn encode(&self, protocol: u8, out: &mut Vec<u8>) {
+        match self {
+            Reply::Simple(s) => out.extend(format!("+{}\r\n", s).into_bytes()),
+            Reply::Error(message) => out.extend(format!("-{}\r\n", message.replace(['\r', '\n'], " ")).into_bytes()),
+            Reply::Integer(i) => out.extend(format!(":{}\r\n", i).into_bytes()),
+            Reply::Bulk(bytes) => {
+                out.extend(format!("${}\r\n", bytes.len()).into_bytes());
+                out.extend(bytes);
+                out.extend(b"\r\n");
+            }
+            Reply::Null if protocol >= 3 => out.extend(b"_\r\n"),
+            Reply::Null => out.extend(b"$-1\r\n"),
+            Reply::Array(items) | Reply::Push(items) => {
+                match (self, protocol) {
+                    (Reply::Push(_), 3..) => out.extend(format!(">{}\r\n", items.len()).into_bytes()),
+                    _ => out.extend(format!("*{}\r\n", items.len()).into_bytes()),
+                }
+                for item in items {
+                    item.encode(protocol, out);
+                }
+            }
+            Reply::Map(pairs) => {
+                match protocol {
+                    3.. => out.extend(format!("%{}\r\n", pairs.len()).into_bytes()),
+                    _ => out.extend(format!("*{}\r\n", pairs.len() * 2).into_bytes()),
+                }
+                for (key, value) in pairs {
+                    key.encode(protocol, out);
+                    value.encode(protocol, out);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_commands_and_replies_frame() {
+        let input = b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$5\r\nva\r\nl\r\n\r\nPING  hello\n*1\r\n$3\r\nGET\r\n";
+        let mut reader = &input[..];
+        let set = read_command(&mut reader).await.unwrap().unwrap();
+        assert_eq!(set, [b"SET".to_vec(), b"key".to_vec(), b"va\r\nl".to_vec()]);
+        let ping = read_command(&mut reader).await.unwrap().unwrap();
+        assert_eq!(ping, [b"PING".to_vec(), b"hello".to_vec()]);
+        assert_eq!(read_command(&mut reader).await.unwrap().unwrap(), [b"GET".to_vec()]);
+        assert_eq!(read_command(&mut reader).await.unwrap(), None);
+
+        let mut bad = &b"*1\r\n$3\r\nGETX\r\n"[..];
+        assert_eq!(read_command(&mut bad).await.unwrap_err().kind(), ErrorKind::InvalidData);
+
+        let reply = Reply::Array(vec![Reply::bulk("a"), Reply::Null, Reply::Integer(-2)]);
+        let (mut v2, mut v3) = (Vec::new(), Vec::new());
+        reply.encode(2, &mut v2);
+        reply.encode(3, &mut v3);
+        assert_eq!(v2, b"*3\r\n$1\r\na\r\n$-1\r\n:-2\r\n");
+        assert_eq!(v3, b"*3\r\n$1\r\na\r\n_\r\n:-2\r\n");
+
+        let map = Reply::Map(vec![(Reply::bulk("proto"), Reply::Integer(3))]);
+        let (mut v2, mut v3) = (Vec::new(), Vec::new());
+        map.encode(2, &mut v2);
+        map.encode(3, &mut v3);
+        assert_eq!(v2, b"*2\r\n$5\r\nproto\r\n:3\r\n");
+        assert_eq!(v3, b"%1\r\n$5\r\nproto\r\n:3\r\n");
+
+        let push = Reply::Push(vec![Reply::bulk("message")]);
+        let (mut v2, mut v3) = (Vec::new(), Vec::new());
+        push.encode(2, &mut v2);
+        push.encode(3, &mut v3);
+        assert_eq!(v2, b"*1\r\n$7\r\nmessage\r\n");
+        assert_eq!(v3, b">1\r\n$7\r\nmessage\r\n");
+    }
+}
@@ -0,0 +1,663 @@
+// Durable key-value store over a partition's map
+//
+// Values live in the partition's `BTreeMap`; expiry deadlines and the keys in
+// SCAN order sit beside it.
+// Every write is appended to a log of JSON lines and synced before it is
+// applied, and the log is replayed on open. Once the log is mostly dead
+// records it is rewritten as a snapshot of the live keys.
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::db::storage::log_file;
+use crate::db::storage::lsm::bloom::fnv1a;
+
+/// Records the log holds before a rewrite is considered
+const COMPACT_MIN_RECORDS: usize = 1024;
+/// Keys SCAN returns per call when no COUNT is given
+pub const DEFAULT_SCAN_COUNT: usize = 10;
+
+/// One logged write. Deadlines are absolute, so replaying a key whose
+/// deadline has passed simply drops it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum Record {
+    Set {
+        key: String,
+        value: String,
+        expires_at: Option<u64>,
+    },
+    Del {
+        key: String,
+    },
+    /// Set (or with None clear) the deadline of an existing key
+    Expire {
+        key: String,
+        expires_at: Option<u64>,
+    },
+}
+
+/// When SET applies
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum SetCondition {
+    #[default]
+    Always,
+    /// NX: only if the key does not exist
+    IfMissing,
+    /// XX: only if the key exists
+    IfExists,
+}
+
+/// Deadline SET gives the key
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum SetExpiry {
+    /// No deadline; any previous one is cleared
+    #[default]
+    Persist,
+    /// KEEPTTL: whatever deadline the key had
+    Keep,
+    /// Unix milliseconds
+    At(u64),
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SetOptions {
+    pub condition: SetCondition,
+    pub expiry: SetExpiry,
+}
+
+/// Remaining lifetime of a key
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Ttl {
+    Missing,
+    Persistent,
+    Millis(u64),
+}
+
+struct KvLog {
+    path: PathBuf,
+    file: File,
+    /// Records in the file, live or not
+    records: usize,
+}
+
+pub struct KvStore {
+    /// Live values; the map the partition hands out
+    data: Arc<Mutex<BTreeMap<String, String>>>,
+    /// Deadlines and scan positions of the keys in `data`
+    keys: Mutex<Keys>,
+    /// Held by writers for the whole write, so log order is apply order.
+    /// Locked before `data`, which is locked before `keys`.
+    log: Mutex<KvLog>,
+}
+
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Position of a key in SCAN order. Cursors are positions, so a key present
+/// for a whole scan is returned however the map changes in between;
+/// position 0 is kept for the cursor that starts and ends a scan.
+fn scan_position(key: &str) -> u64 {
+    (fnv1a(key.as_bytes(), 0xcbf29ce484222325) >> 1) + 1
+}
+
+/// What is kept about the keys of the map besides their values
+#[derive(Default)]
+struct Keys {
+    /// Unix-millisecond deadlines of keys that have one
+    expires: HashMap<String, u64>,
+    /// Every key by scan position, so SCAN reads a range instead of sorting
+    positions: BTreeSet<(u64, String)>,
+}
+
+impl Keys {
+    fn insert(&mut self, key: &str) {
+        self.positions.insert((scan_position(key), key.to_string()));
+    }
+
+    /// Forget `key`, deadline and all
+    fn remove(&mut self, key: &str) {
+        self.expires.remove(key);
+        self.positions.remove(&(scan_position(key), key.to_string()));
+    }
+}
+
+/// The regex a glob-style pattern (`*`, `?`, `[a-z]`, `[^x]`, `\` escapes)
+/// stands for (None if it does not compile)
+pub fn glob_regex(pattern: &str) -> Option<regex::Regex> {
+    let mut regex = String::from("(?s)^");
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            '\\' => regex.push_str(&regex::escape(&chars.next().map(String::from).unwrap_or_default())),
+            '[' => {
+                regex.push('[');
+                let mut first = true;
+                for c in chars.by_ref() {
+                    match c {
+                        ']' => break,
+                        '^' if first => regex.push('^'),
+                        '-' => regex.push('-'),
+                        c => regex.push_str(&regex::escape(&c.to_string())),
+                    }
+                    first = false;
+                }
+                regex.push(']');
+            }
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    regex::Regex::new(&regex).ok()
+}
+
+impl KvStore {
+    /// Serve `data` from the log at `path`, replaying the log into the map
+    pub fn open(data: Arc<Mutex<BTreeMap<String, String>>>, path: &Path) -> Result<KvStore, String> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let now = now_ms();
+        let mut index = Keys::default();
+        let (file, batches) = log_file::open::<Vec<Record>>(path)?;
+        let mut records = 0;
+        {
+            let mut map = data.lock().map_err(|e| e.to_string())?;
+            for batch in batches {
+                records += batch.len();
+                for record in batch {
+                    Self::apply(&mut map, &mut index, record);
+                }
+            }
+            let expired: Vec<String> = index
+                .expires
+                .iter()
+                .filter(|(_, deadline)| **deadline <= now)
+                .map(|(key, _)| key.clone())
+                .collect();
+            for key in expired {
+                map.remove(&key);
+                index.remove(&key);
+            }
+        }
+        let store = KvStore {
+            data,
+            keys: Mutex::new(index),
+            log: Mutex::new(KvLog {
+                path: path.to_path_buf(),
+                file,
+                records,
+            }),
+        };
+        {
+            let mut log = store.log.lock().map_err(|e| e.to_string())?;
+            store.maybe_compact(&mut log)?;
+        }
+        Ok(store)
+    }
+
+    /// The map values are kept in
+    pub fn data(&self) -> Arc<Mutex<BTreeMap<String, String>>> {
+        Arc::clone(&self.data)
+    }
+
+    fn apply(map: &mut BTreeMap<String, String>, index: &mut Keys, record: Record) {
+        match record {
+            Record::Set { key, value, expires_at } => {
+                match expires_at {
+                    Some(deadline) => index.expires.insert(key.clone(), deadline),
+                    None => index.expires.remove(&key),
+                };
+                if !map.contains_key(&key) {
+                    index.insert(&key);
+                }
+                map.insert(key, value);
+            }
+            Record::Del { key } => {
+                map.remove(&key);
+                index.remove(&key);
+            }
+            Record::Expire { key, expires_at } => match expires_at {
+                Some(deadline) => {
+                    index.expires.insert(key, deadline);
+                }
+                None => {
+                    index.expires.remove(&key);
+                }
+            },
+        }
+    }
+
+    fn lock_data(&self) -> Result<MutexGuard<'_, BTreeMap<String, String>>, String> {
+        self.data.lock().map_err(|e| e.to_string())
+    }
+
+    fn lock_keys(&self) -> Result<MutexGuard<'_, Keys>, String> {
+        self.keys.lock().map_err(|e| e.to_string())
+    }
+
+    /// Drop `key` if its deadline has passed; returns whether it is live
+    fn live(map: &mut BTreeMap<String, String>, index: &mut Keys, key: &str, now: u64) -> bool {
+        if index.expires.get(key).is_some_and(|deadline| *deadline <= now) {
+            index.remove(key);
+            map.remove(key);
+        }
+        map.contains_key(key)
+    }
+
+    /// Log `batch`, then apply it
+    fn write(&self, log: &mut KvLog, map: &mut BTreeMap<String, String>, index: &mut Keys, batch: Vec<Record>) -> Result<(), String> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let mut line = serde_json::to_string(&batch).map_err(|e| e.to_string())?;
+        line.push('\n');
+        log_file::append(&mut log.file, line.as_bytes())?;
+        log.file.sync_data().map_err(|e| e.to_string())?;
+        log.records += batch.len();
+        for record in batch {
+            Self::apply(map, index, record);
+        }
+        Ok(())
+    }
+
+    /// Rewrite the log as one record per live key once it is mostly dead
+    /// records. Takes the log lock from the caller; locks the map itself.
+    fn maybe_compact(&self, log: &mut KvLog) -> Result<(), String> {
+        let map = self.lock_data()?;
+        if log.records < COMPACT_MIN_RECORDS || log.records < map.len() * 2 {
+            return Ok(());
+        }
+        let index = self.lock_keys()?;
+        let tmp = log.path.with_extension("tmp");
+        let mut file = File::create(&tmp).map_err(|e| e.to_string())?;
+        for (key, value) in map.iter() {
+            let record = Record::Set {
+                key: key.clone(),
+                value: value.clone(),
+                expires_at: index.expires.get(key).copied(),
+            };
+            let mut line = serde_json::to_string(&[record]).map_err(|e| e.to_string())?;
+            line.push('\n');
+            file.write_all(line.as_bytes()).map_err(|e| e.to_string())?;
+        }
+        file.sync_all().map_err(|e| e.to_string())?;
+        fs::rename(&tmp, &log.path).map_err(|e| e.to_string())?;
+        log.file = OpenOptions::new()
+            .append(true)
+            .open(&log.path)
+            .map_err(|e| e.to_string())?;
+        log.records = map.len();
+        Ok(())
+    }
+
+    /// Run a write with the log, map and deadlines locked, then compact the
+    /// log if it has grown enough
+    fn writing<T>(
+        &self,
+        f: impl FnOnce(&mut KvLog, &mut BTreeMap<String, String>, &mut Keys) -> Result<T, String>,
+    ) -> Result<T, String> {
+        let mut log = self.log.lock().map_err(|e| e.to_string())?;
+        let result = {
+            let mut map = self.lock_data()?;
+            let mut index = self.lock_keys()?;
+            f(&mut log, &mut map, &mut index)?
+        };
+        self.maybe_compact(&mut log)?;
+        Ok(result)
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<String>, String> {
+        let mut map = self.lock_data()?;
+        let mut index = self.lock_keys()?;
+        if !Self::live(&mut map, &mut index, key, now_ms()) {
+            return Ok(None);
+        }
+        Ok(map.get(key).cloned())
+    }
+
+    pub fn mget(&self, keys: &[String]) -> Result<Vec<Option<String>>, String> {
+        let mut map = self.lock_data()?;
+        let mut index = self.lock_keys()?;
+        let now = now_ms();
+        Ok(keys
+            .iter()
+            .map(|key| {
+                Self::live(&mut map, &mut index, key, now)
+                    .then(|| map.get(key).cloned())
+                    .flatten()
+            })
+            .collect())
+    }
+
+    /// Set `key` unless `options.condition` rules it out. Returns whether
+    /// it was set, and the value it had before.
+    pub fn set(&self, key: &str, value: &str, options: &SetOptions) -> Result<(bool, Option<String>), String> {
+        self.writing(|log, map, index| {
+            let now = now_ms();
+            let exists = Self::live(map, index, key, now);
+            let previous = map.get(key).cloned();
+            let allowed = match options.condition {
+                SetCondition::Always => true,
+                SetCondition::IfMissing => !exists,
+                SetCondition::IfExists => exists,
+            };
+            if !allowed {
+                return Ok((false, previous));
+            }
+            let record = match options.expiry {
+                // A deadline already past leaves nothing to set
+                SetExpiry::At(deadline) if deadline <= now => Record::Del { key: key.to_string() },
+                SetExpiry::At(deadline) => Record::Set {
+                    key: key.to_string(),
+                    value: value.to_string(),
+                    expires_at: Some(deadline),
+                },
+                SetExpiry::Keep => Record::Set {
+                    key: key.to_string(),
+                    value: value.to_string(),
+                    expires_at: index.expires.get(key).copied(),
+                },
+                SetExpiry::Persist => Record::Set {
+                    key: key.to_string(),
+                    value: value.to_string(),
+                    expires_at: None,
+                },
+            };
+            self.write(log, map, index, vec![record])?;
+            Ok((true, previous))
+        })
+    }
+
+    /// Set every pair in one logged batch, clearing their deadlines
+    pub fn mset(&self, pairs: &[(String, String)]) -> Result<(), String> {
+        self.writing(|log, map, index| {
+            let batch = pairs
+                .iter()
+                .map(|(key, value)| Record::Set {
+                    key: key.clone(),
+                    value: value.clone(),
+                    expires_at: None,
+                })
+                .collect();
+            self.write(log, map, index, batch)
+        })
+    }
+
+    /// Remove `keys`; returns how many existed
+    pub fn del(&self, keys: &[String]) -> Result<usize, String> {
+        self.writing(|log, map, index| {
+            let now = now_ms();
+            let mut batch = Vec::new();
+            for key in keys {
+                if Self::live(map, index, key, now) && !batch.iter().any(|r| matches!(r, Record::Del { key: k } if k == key)) {
+                    batch.push(Record::Del { key: key.clone() });
+                }
+            }
+            let removed = batch.len();
+            self.write(log, map, index, batch)?;
+            Ok(removed)
+        })
+    }
+
+    /// How many of `keys` exist, counting a key named twice twice
+    pub fn exists(&self, keys: &[String]) -> Result<usize, String> {
+        let mut map = self.lock_data()?;
+        let mut index = self.lock_keys()?;
+        let now = now_ms();
+        Ok(keys.iter().filter(|key| Self::live(&mut map, &mut index, key, now)).count())
+    }
+
+    /// Give an existing key a deadline (None removes it). A deadline already
+    /// past deletes the key. Returns whether the key existed.
+    pub fn expire(&self, key: &str, expires_at: Option<u64>) -> Result<bool, String> {
+        self.writing(|log, map, index| {
+            let now = now_ms();
+            if !Self::live(map, index, key, now) {
+                return Ok(false);
+            }
+            let record = match expires_at {
+                Some(deadline) if deadline <= now => Record::Del { key: key.to_string() },
+                // PERSIST on a key without a deadline changes nothing
+                None if !index.expires.contains_key(key) => return Ok(false),
+                expires_at => Record::Expire {
+                    key: key.to_string(),
+                    expires_at,
+                },
+            };
+            self.write(log, map, index, vec![record])?;
+            Ok(true)
+        })
+    }
+
+    pub fn ttl(&self, key: &str) -> Result<Ttl, String> {
+        let mut map = self.lock_data()?;
+        let mut index = self.lock_keys()?;
+        let now = now_ms();
+        if !Self::live(&mut map, &mut index, key, now) {
+            return Ok(Ttl::Missing);
+        }
+        Ok(match index.expires.get(key) {
+            Some(deadline) => Ttl::Millis(deadline - now),
+            None => Ttl::Persistent,
+        })
+    }
+
+    /// Live keys matching a glob-style `pattern`, in key order
+    pub fn keys(&self, pattern: &str) -> Result<Vec<String>, String> {
+        let regex = glob_regex(pattern).ok_or_else(|| format!("Invalid pattern '{}'", pattern))?;
+        let map = self.lock_data()?;
+        let index = self.lock_keys()?;
+        let now = now_ms();
+        Ok(map
+            .keys()
+            .filter(|key| index.expires.get(*key).is_none_or(|deadline| *deadline > now) && regex.is_match(key))
+            .cloned()
+            .collect())
+    }
+
+    /// Up to `count` keys from scan position `cursor` on, filtered by
+    /// `pattern`, and the cursor to continue from (0 when done). Keys sharing
+    /// a position are never split across calls.
+    pub fn scan(&self, cursor: u64, pattern: Option<&str>, count: usize) -> Result<(u64, Vec<String>), String> {
+        let regex = match pattern {
+            Some(pattern) => Some(glob_regex(pattern).ok_or_else(|| format!("Invalid pattern '{}'", pattern))?),
+            None => None,
+        };
+        let index = self.lock_keys()?;
+        let now = now_ms();
+
+        let mut positioned: Vec<(u64, &String)> = Vec::new();
+        let mut next = 0;
+        for (position, key) in index.positions.range((cursor, String::new())..) {
+            if positioned.len() >= count.max(1) && positioned.last().is_some_and(|(last, _)| last != position) {
+                next = *position;
+                break;
+            }
+            positioned.push((*position, key));
+        }
+        let keys = positioned
+            .into_iter()
+            .map(|(_, key)| key)
+            .filter(|key| index.expires.get(*key).is_none_or(|deadline| *deadline > now))
+            .filter(|key| regex.as_ref().is_none_or(|regex| regex.is_match(key)))
+            .cloned()
+            .collect();
+        Ok((next, keys))
+    }
+
+    /// Keys stored, including expired ones not yet purged
+    pub fn len(&self) -> Result<usize, String> {
+        Ok(self.lock_data()?.len())
+    }
+
+    pub fn is_empty(&self) -> Result<bool, String> {
+        Ok(self.lock_data()?.is_empty())
+    }
+
+    /// Remove every key whose deadline has passed; returns how many
+    pub fn purge_expired(&self) -> Result<usize, String> {
+        self.writing(|log, map, index| {
+            let now = now_ms();
+            let batch: Vec<Record> = index
+                .expires
+                .iter()
+                .filter(|(_, deadline)| **deadline <= now)
+                .map(|(key, _)| Record::Del { key: key.clone() })
+                .collect();
+            let purged = batch.len();
+            self.write(log, map, index, batch)?;
+            Ok(purged)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("butterfly_kv_{}_{}.log", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn open(path: &Path) -> KvStore {
+        KvStore::open(Arc::new(Mutex::new(BTreeMap::new())), path).unwrap()
+    }
+
+    #[test]
+    fn test_writes_survive_reopen() {
+        let path = log_path("reopen");
+        {
+            let store = open(&path);
+            store.set("a", "1", &SetOptions::default()).unwrap();
+            store.mset(&[("b".to_string(), "2".to_string()), ("c".to_string(), "3".to_string())]).unwrap();
+            let later = SetOptions {
+                expiry: SetExpiry::At(now_ms() + 60_000),
+                ..SetOptions::default()
+            };
+            store.set("d", "4", &later).unwrap();
+            let gone = SetOptions {
+                expiry: SetExpiry::At(now_ms() + 1),
+                ..SetOptions::default()
+            };
+            store.set("e", "5", &gone).unwrap();
+            assert_eq!(store.del(&["b".to_string(), "b".to_string(), "x".to_string()]).unwrap(), 1);
+        }
+        std::thread::sleep(std::time::Duration::from_millis(5));
+
+        let store = open(&path);
+        assert_eq!(store.get("a").unwrap(), Some("1".to_string()));
+        assert_eq!(store.get("b").unwrap(), None);
+        assert_eq!(store.get("e").unwrap(), None);
+        assert!(matches!(store.ttl("d").unwrap(), Ttl::Millis(ms) if ms > 50_000));
+        assert_eq!(store.ttl("a").unwrap(), Ttl::Persistent);
+        assert_eq!(store.keys("*").unwrap(), ["a", "c", "d"]);
+        assert_eq!(store.data().lock().unwrap().len(), 3);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_writes_after_a_torn_tail_survive_reopen() {
+        let path = log_path("torn");
+        open(&path).set("a", "1", &SetOptions::default()).unwrap();
+        // A crash in the middle of appending the next write
+        let mut log = OpenOptions::new().append(true).open(&path).unwrap();
+        log.write_all(b"[{\"Set\":{\"key\"").unwrap();
+        drop(log);
+
+        open(&path).set("b", "2", &SetOptions::default()).unwrap();
+        let store = open(&path);
+        assert_eq!(store.get("a").unwrap(), Some("1".to_string()));
+        assert_eq!(store.get("b").unwrap(), Some("2".to_string()));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_conditions_expiry_and_compaction() {
+        let path = log_path("conditions");
+        let store = open(&path);
+        let nx = SetOptions {
+            condition: SetCondition::IfMissing,
+            ..SetOptions::default()
+        };
+        let xx = SetOptions {
+            condition: SetCondition::IfExists,
+            ..SetOptions::default()
+        };
+        assert_eq!(store.set("k", "1", &xx).unwrap(), (false, None));
+        assert_eq!(store.set("k", "1", &nx).unwrap(), (true, None));
+        assert_eq!(store.set("k", "2", &nx).unwrap(), (false, Some("1".to_string())));
+        assert_eq!(store.set("k", "3", &xx).unwrap(), (true, Some("1".to_string())));
+
+        assert!(store.expire("k", Some(now_ms() + 10_000)).unwrap());
+        let keep = SetOptions {
+            expiry: SetExpiry::Keep,
+            ..SetOptions::default()
+        };
+        store.set("k", "4", &keep).unwrap();
+        assert!(matches!(store.ttl("k").unwrap(), Ttl::Millis(_)));
+        assert!(store.expire("k", None).unwrap());
+        assert_eq!(store.ttl("k").unwrap(), Ttl::Persistent);
+        assert!(store.expire("k", Some(0)).unwrap());
+        assert_eq!(store.ttl("k").unwrap(), Ttl::Missing);
+        assert!(!store.expire("k", Some(now_ms() + 1000)).unwrap());
+
+        // Overwriting one key over and over keeps the log small
+        for i in 0..COMPACT_MIN_RECORDS * 2 {
+            store.set("hot", &i.to_string(), &SetOptions::default()).unwrap();
+        }
+        assert!(store.log.lock().unwrap().records < COMPACT_MIN_RECORDS);
+        drop(store);
+        assert_eq!(open(&path).get("hot").unwrap(), Some((COMPACT_MIN_RECORDS * 2 - 1).to_string()));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_scan_visits_every_key_once() {
+        let path = log_path("scan");
+        let store = open(&path);
+        let pairs: Vec<(String, String)> = (0..100).map(|i| (format!("user:{}", i), i.to_string())).collect();
+        store.mset(&pairs).unwrap();
+        store.set("other", "x", &SetOptions::default()).unwrap();
+
+        let mut seen = Vec::new();
+        let mut cursor = 0;
+        loop {
+            let (next, keys) = store.scan(cursor, Some("user:*"), 7).unwrap();
+            seen.extend(keys);
+            // Writes between calls do not disturb the scan
+            store.set(&format!("new:{}", cursor), "y", &SetOptions::default()).unwrap();
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+        seen.sort();
+        seen.dedup();
+        assert_eq!(seen.len(), 100);
+
+        // Deleted and expired keys leave the scan order too
+        store.del(&["user:1".to_string()]).unwrap();
+        store.expire("user:2", Some(0)).unwrap();
+        assert_eq!(store.keys.lock().unwrap().positions.len(), store.len().unwrap());
+        let (_, keys) = store.scan(0, Some("user:*"), 1000).unwrap();
+        assert_eq!(keys.len(), 98);
+
+        assert_eq!(store.keys("user:1?").unwrap().len(), 10);
+        assert_eq!(store.keys("[ou]ther").unwrap(), ["other"]);
+        assert_eq!(store.keys("user:[^0-8]").unwrap(), ["user:9"]);
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,103 @@
+// Cursors - a query's rows held as of when it ran, read a batch at a time
+use std::collections::HashMap;
+
+use crate::db::executor::ExecutionResult;
+use crate::db::sql::Statement;
+
+/// Bytes of rows the open cursors of one session may hold at once
+pub const MAX_CURSOR_BYTES: usize = 64 << 20;
+
+/// The rows of one result, fixed when the query ran. Writes made later are
+/// not seen, so batches read from it never shift, skip or repeat rows.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub columns: Vec<String>,
+    rows: Vec<HashMap<String, serde_json::Value>>,
+    /// Roughly what the rows take in memory, to cap how much is held
+    bytes: usize,
+}
+
+/// Rough in-memory size of a JSON value
+fn value_bytes(value: &serde_json::Value) -> usize {
+    let nested = match value {
+        serde_json::Value::String(s) => s.len(),
+        serde_json::Value::Array(values) => values.iter().map(value_bytes).sum(),
+        serde_json::Value::Object(map) => map.iter().map(|(key, value)| key.len() + value_bytes(value)).sum(),
+        _ => 0,
+    };
+    std::mem::size_of::<serde_json::Value>() + nested
+}
+
+impl Snapshot {
+    pub fn new(columns: Vec<String>, rows: Vec<HashMap<String, serde_json::Value>>) -> Self {
+        let bytes = rows
+            .iter()
+            .flat_map(|row| row.iter())
+            .map(|(column, value)| std::mem::size_of::<String>() + column.len() + value_bytes(value))
+            .sum();
+        Self { columns, rows, bytes }
+    }
+
+    /// Hold the rows of `result`; Err(result) if it has none to hold
+    pub fn from_result(result: ExecutionResult) -> Result<Self, ExecutionResult> {
+        match result {
+            ExecutionResult::Rows { columns, rows } => Ok(Self::new(columns, rows)),
+            other => Err(other),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Up to `count` rows starting at `offset`
+    pub fn page(&self, offset: usize, count: usize) -> ExecutionResult {
+        let start = offset.min(self.rows.len());
+        let end = start.saturating_add(count).min(self.rows.len());
+        ExecutionResult::Rows {
+            columns: self.columns.clone(),
+            rows: self.rows[start..end].to_vec(),
+        }
+    }
+}
+
+/// A cursor opened by DECLARE: the snapshot of its query and how far FETCH
+/// has read into it
+#[derive(Debug)]
+pub struct Cursor {
+    /// The query, kept so clients can be told the types of fetched columns
+    pub query: Statement,
+    snapshot: Snapshot,
+    position: usize,
+}
+
+impl Cursor {
+    pub fn new(query: Statement, snapshot: Snapshot) -> Self {
+        Self {
+            query,
+            snapshot,
+            position: 0,
+        }
+    }
+
+    /// Bytes the cursor's snapshot holds
+    pub fn bytes(&self) -> usize {
+        self.snapshot.bytes()
+    }
+
+    /// The next `count` rows (all remaining ones when None), moving past them
+    pub fn fetch(&mut self, count: Option<usize>) -> ExecutionResult {
+        let count = count.unwrap_or(usize::MAX);
+        let result = self.snapshot.page(self.position, count);
+        self.position = self.position.saturating_add(count).min(self.snapshot.len());
+        result
+    }
+}
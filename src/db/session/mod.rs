@@ -0,0 +1,651 @@
+// Client sessions - per-connection state such as prepared statements
+pub mod cursor;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::db::catalog::data_type_to_string;
+use crate::db::executor::{ExecutionResult, Executor, RowStream};
+use crate::db::notify::{self, Listener, Notification};
+use crate::db::sql::params::{bind_parameters, parameter_count};
+use crate::db::sql::{DataType, SqlParser, Statement, TransactionStatement};
+use crate::db::storage::{Row, Value};
+use cursor::{Cursor, Snapshot, MAX_CURSOR_BYTES};
+
+/// Maximum number of parameterized SQL texts whose parsed plans are kept
+pub const PLAN_CACHE_CAPACITY: usize = 128;
+
+/// A parsed statement with placeholders, ready to be bound and executed
+#[derive(Debug, Clone)]
+pub struct PreparedStatement {
+    pub statement: Statement,
+    /// Declared parameter types; may cover fewer parameters than `param_count`
+    pub param_types: Vec<DataType>,
+    pub param_count: usize,
+}
+
+impl PreparedStatement {
+    pub fn new(statement: Statement, param_types: Vec<DataType>) -> Result<Self, String> {
+        if matches!(
+            statement,
+            Statement::Prepare { .. } | Statement::Execute { .. } | Statement::Deallocate { .. }
+        ) {
+            return Err("Cannot prepare a PREPARE, EXECUTE or DEALLOCATE statement".to_string());
+        }
+
+        let param_count = parameter_count(&statement).max(param_types.len());
+        Ok(Self {
+            statement,
+            param_types,
+            param_count,
+        })
+    }
+
+    /// Coerce `values` to the declared parameter types and substitute them
+    pub fn bind(&self, values: &[Value]) -> Result<Statement, String> {
+        if values.len() != self.param_count {
+            return Err(format!(
+                "Wrong number of parameters: expected {}, got {}",
+                self.param_count,
+                values.len()
+            ));
+        }
+
+        let literals = values
+            .iter()
+            .enumerate()
+            .map(|(i, value)| match self.param_types.get(i) {
+                Some(data_type) => coerce_parameter(i + 1, value, data_type),
+                None => Ok(value.clone()),
+            })
+            .map(|value| value.map(|v| v.to_literal()))
+            .collect::<Result<Vec<_>, String>>()?;
+
+        // Declared-but-unused trailing parameters have no placeholder to fill
+        bind_parameters(&self.statement, &literals[..parameter_count(&self.statement)])
+    }
+}
+
+/// Error for a statement run with placeholders but no values to fill them
+fn unbound_parameters(stmt: &Statement) -> Option<String> {
+    match parameter_count(stmt) {
+        0 => None,
+        count => Some(format!(
+            "Statement has {} parameter placeholder(s) but no values were supplied",
+            count
+        )),
+    }
+}
+
+/// Name of a statement whose effect outlasts it within the session
+fn session_statement(stmt: &Statement) -> Option<&'static str> {
+    match stmt {
+        Statement::Prepare { .. } => Some("PREPARE"),
+        Statement::Execute { .. } => Some("EXECUTE"),
+        Statement::Deallocate { .. } => Some("DEALLOCATE"),
+        Statement::DeclareCursor { .. } => Some("DECLARE"),
+        Statement::Fetch { .. } => Some("FETCH"),
+        Statement::CloseCursor { .. } => Some("CLOSE"),
+        Statement::Transaction(_) => Some("A transaction block"),
+        _ => None,
+    }
+}
+
+/// Whether a statement changes stored data or the catalog
+fn writes(stmt: &Statement) -> bool {
+    match stmt {
+        Statement::Select { .. } | Statement::Union { .. } | Statement::Explain { analyze: false, .. } => false,
+        Statement::With { body, .. } | Statement::Explain { statement: body, .. } => writes(body),
+        _ => true,
+    }
+}
+
+/// Convert a parameter value to the type declared in PREPARE name (type, ...)
+fn coerce_parameter(position: usize, value: &Value, data_type: &DataType) -> Result<Value, String> {
+    let coerced = match (data_type, value) {
+        (_, Value::Null) => Some(Value::Null),
+        (DataType::Integer, Value::Integer(_)) => Some(value.clone()),
+        (DataType::Integer, Value::Float(f)) if f.fract() == 0.0 => Some(Value::Integer(*f as i64)),
+        (DataType::Integer, Value::Text(s)) => s.trim().parse().ok().map(Value::Integer),
+        (DataType::Float | DataType::Double, Value::Float(_)) => Some(value.clone()),
+        (DataType::Float | DataType::Double, Value::Integer(i)) => Some(Value::Float(*i as f64)),
+        (DataType::Float | DataType::Double, Value::Text(s)) => s.trim().parse().ok().map(Value::Float),
+        (DataType::Boolean, Value::Boolean(_)) => Some(value.clone()),
+        (DataType::Boolean, Value::Text(s)) => match s.to_lowercase().as_str() {
+            "true" | "t" | "1" => Some(Value::Boolean(true)),
+            "false" | "f" | "0" => Some(Value::Boolean(false)),
+            _ => None,
+        },
+        (DataType::Boolean, Value::Integer(i)) if *i == 0 || *i == 1 => Some(Value::Boolean(*i == 1)),
+        (DataType::Boolean, _) | (DataType::Integer, _) | (DataType::Float | DataType::Double, _) => None,
+        // Character and date/time types take the value's text form
+        (_, Value::Text(_)) => Some(value.clone()),
+        (_, other) => Some(Value::Text(other.to_string())),
+    };
+
+    coerced.ok_or_else(|| {
+        format!(
+            "Parameter ${} expects {}, got {:?}",
+            position,
+            data_type_to_string(data_type),
+            value
+        )
+    })
+}
+
+/// State a client keeps across statements. Statements that need no session
+/// state are handed straight to the `Executor`.
+#[derive(Debug, Default)]
+pub struct Session {
+    prepared: HashMap<String, Arc<PreparedStatement>>,
+    /// Parsed plans for parameterized SQL sent without an explicit PREPARE
+    plan_cache: HashMap<String, Arc<PreparedStatement>>,
+    /// Open cursors by name
+    cursors: HashMap<String, Cursor>,
+    /// Notifications on the channels the session LISTENs to; only sessions
+    /// of connections that can deliver them have one
+    listener: Option<Listener>,
+    /// Process id the session's NOTIFYs carry
+    process_id: i32,
+    /// Inside BEGIN ... COMMIT
+    in_transaction: bool,
+    /// A statement of the open transaction block has written, which
+    /// ROLLBACK cannot undo
+    wrote_in_transaction: bool,
+    /// NOTIFYs of the open transaction block, sent once it commits
+    pending_notifications: Vec<Notification>,
+    /// Lives for a single request, so statements that leave state for later
+    /// ones are refused instead of being forgotten
+    stateless: bool,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A session for one stateless request, such as an HTTP /sql call
+    pub fn stateless() -> Self {
+        Self {
+            stateless: true,
+            ..Self::default()
+        }
+    }
+
+    /// Parse and execute a SQL string (only the first statement is run)
+    pub fn execute_sql(&mut self, sql: &str) -> ExecutionResult {
+        match SqlParser::parse(sql) {
+            Ok(statements) => match statements.first() {
+                Some(stmt) => self.execute(stmt),
+                None => ExecutionResult::Error {
+                    message: "No SQL statements found".to_string(),
+                },
+            },
+            Err(e) => ExecutionResult::Error {
+                message: format!("SQL parse error: {}", e),
+            },
+        }
+    }
+
+    /// Execute SQL containing `$n` / `?` placeholders with the given values.
+    /// The parsed statement is cached by SQL text so repeated calls skip parsing.
+    pub fn execute_with_params(&mut self, sql: &str, params: &[Value]) -> ExecutionResult {
+        let prepared = match self.plan_cache.get(sql) {
+            Some(prepared) => Arc::clone(prepared),
+            None => {
+                let prepared = match SqlParser::parse_statement(sql)
+                    .map_err(|e| format!("SQL parse error: {}", e))
+                    .and_then(|stmt| PreparedStatement::new(stmt, Vec::new()))
+                {
+                    Ok(prepared) => Arc::new(prepared),
+                    Err(message) => return ExecutionResult::Error { message },
+                };
+                if self.plan_cache.len() >= PLAN_CACHE_CAPACITY {
+                    self.plan_cache.clear();
+                }
+                self.plan_cache.insert(sql.to_string(), Arc::clone(&prepared));
+                prepared
+            }
+        };
+
+        match prepared.bind(params) {
+            Ok(stmt) => self.execute(&stmt),
+            Err(message) => ExecutionResult::Error { message },
+        }
+    }
+
+    /// Execute a parsed statement, handling session-level statements here
+    pub fn execute(&mut self, stmt: &Statement) -> ExecutionResult {
+        if self.stateless {
+            if let Some(kind) = session_statement(stmt) {
+                return ExecutionResult::Error {
+                    message: format!(
+                        "{} needs a session, and each request runs in its own; send params with the SQL, or use a PostgreSQL or gRPC connection",
+                        kind
+                    ),
+                };
+            }
+        }
+        match stmt {
+            Statement::Prepare {
+                name,
+                param_types,
+                statement,
+            } => match self.prepare(name, statement.as_ref().clone(), param_types.clone()) {
+                Ok(()) => ExecutionResult::Success {
+                    message: format!("Prepared statement '{}' created", name),
+                },
+                Err(message) => ExecutionResult::Error { message },
+            },
+            Statement::Execute { name, params } => {
+                let values: Vec<Value> = params
+                    .iter()
+                    .map(|expr| Executor::eval_expression(expr, &Row::new()))
+                    .collect();
+                self.execute_prepared(name, &values)
+            }
+            Statement::Deallocate { name } => match self.deallocate(name.as_deref()) {
+                Ok(()) => ExecutionResult::Success {
+                    message: match name {
+                        Some(name) => format!("Prepared statement '{}' deallocated", name),
+                        None => "All prepared statements deallocated".to_string(),
+                    },
+                },
+                Err(message) => ExecutionResult::Error { message },
+            },
+            Statement::DeclareCursor { name, query } => match self.declare_cursor(name, query) {
+                Ok(()) => ExecutionResult::Success {
+                    message: format!("Cursor '{}' declared", name),
+                },
+                Err(message) => ExecutionResult::Error { message },
+            },
+            Statement::Fetch { name, count } => match self.cursors.get_mut(name) {
+                Some(cursor) => cursor.fetch(*count),
+                None => ExecutionResult::Error {
+                    message: format!("Cursor '{}' does not exist", name),
+                },
+            },
+            Statement::CloseCursor { name } => match self.close_cursor(name.as_deref()) {
+                Ok(()) => ExecutionResult::Success {
+                    message: match name {
+                        Some(name) => format!("Cursor '{}' closed", name),
+                        None => "All cursors closed".to_string(),
+                    },
+                },
+                Err(message) => ExecutionResult::Error { message },
+            },
+            Statement::Transaction(transaction) => self.transaction(transaction),
+            Statement::Listen { channel } => match &self.listener {
+                Some(listener) => {
+                    listener.listen(channel);
+                    ExecutionResult::Success {
+                        message: format!("Listening on '{}'", channel),
+                    }
+                }
+                None => ExecutionResult::Error {
+                    message: "LISTEN is not supported on this connection; HTTP clients use /listen and gRPC clients the Listen call".to_string(),
+                },
+            },
+            Statement::Unlisten { channel } => {
+                if let Some(listener) = &self.listener {
+                    listener.unlisten(channel.as_deref());
+                }
+                ExecutionResult::Success {
+                    message: match channel {
+                        Some(channel) => format!("Stopped listening on '{}'", channel),
+                        None => "Stopped listening on all channels".to_string(),
+                    },
+                }
+            }
+            Statement::Notify { channel, payload } => match Notification::new(channel, payload, self.process_id) {
+                Ok(notification) => {
+                    // Within a transaction block it waits for COMMIT; the same
+                    // notification twice in one transaction is sent once
+                    if !self.in_transaction {
+                        notify::notify(&notification);
+                    } else if !self.pending_notifications.contains(&notification) {
+                        self.pending_notifications.push(notification);
+                    }
+                    ExecutionResult::Success {
+                        message: format!("Notified '{}'", channel),
+                    }
+                }
+                Err(message) => ExecutionResult::Error { message },
+            },
+            _ => match unbound_parameters(stmt) {
+                Some(message) => ExecutionResult::Error { message },
+                None => {
+                    self.wrote_in_transaction |= self.in_transaction && writes(stmt);
+                    Executor::execute(stmt)
+                }
+            },
+        }
+    }
+
+    /// The rows of a query statement as a stream, for callers that send
+    /// them on as they are produced; None for any other statement, which
+    /// goes through `execute`
+    pub fn stream(&mut self, stmt: &Statement) -> Option<Result<RowStream, String>> {
+        if !matches!(stmt, Statement::Select { .. } | Statement::Union { .. } | Statement::With { .. }) {
+            return None;
+        }
+        Some(match unbound_parameters(stmt) {
+            Some(message) => Err(message),
+            None => Executor::stream(stmt),
+        })
+    }
+
+    /// Start, commit or roll back a transaction block. Statements commit as
+    /// they run, so the block only holds back the session's NOTIFYs: COMMIT
+    /// sends them and ROLLBACK drops them. Writes cannot be rolled back, so
+    /// ROLLBACK after one is an error, and the block commits instead.
+    pub fn transaction(&mut self, transaction: &TransactionStatement) -> ExecutionResult {
+        let result = match transaction {
+            TransactionStatement::Begin => Ok("Transaction started"),
+            TransactionStatement::Rollback if !self.wrote_in_transaction => {
+                self.pending_notifications.clear();
+                Ok("Transaction rolled back")
+            }
+            TransactionStatement::Commit | TransactionStatement::Rollback => {
+                for notification in std::mem::take(&mut self.pending_notifications) {
+                    notify::notify(&notification);
+                }
+                match transaction {
+                    TransactionStatement::Rollback => Err("ROLLBACK after a write is not supported: statements commit as they run, so the transaction's writes were kept and it was committed instead"),
+                    _ => Ok("Transaction committed"),
+                }
+            }
+        };
+        self.in_transaction = matches!(transaction, TransactionStatement::Begin);
+        self.wrote_in_transaction &= self.in_transaction;
+        match result {
+            Ok(message) => ExecutionResult::Success {
+                message: message.to_string(),
+            },
+            Err(message) => ExecutionResult::Error {
+                message: message.to_string(),
+            },
+        }
+    }
+
+    pub fn in_transaction(&self) -> bool {
+        self.in_transaction
+    }
+
+    /// Let the session LISTEN, for a connection that delivers notifications
+    /// as they arrive. `process_id` identifies it as the sender of its own.
+    pub fn enable_notifications(&mut self, process_id: i32) {
+        self.process_id = process_id;
+        self.listener.get_or_insert_with(Listener::new);
+    }
+
+    /// The next notification for the session if one is waiting
+    pub fn try_notification(&mut self) -> Option<Notification> {
+        self.listener.as_mut().and_then(Listener::try_recv)
+    }
+
+    /// The next notification for the session, waiting for one to arrive;
+    /// never resolves for a session that cannot LISTEN
+    pub async fn next_notification(&mut self) -> Notification {
+        match self.listener.as_mut() {
+            Some(listener) => match listener.recv().await {
+                Some(notification) => notification,
+                None => std::future::pending().await,
+            },
+            None => std::future::pending().await,
+        }
+    }
+
+    /// Run `query` and open cursor `name` over a snapshot of its rows
+    pub fn declare_cursor(&mut self, name: &str, query: &Statement) -> Result<(), String> {
+        if self.cursors.contains_key(name) {
+            return Err(format!("Cursor '{}' already exists", name));
+        }
+        if let Some(message) = unbound_parameters(query) {
+            return Err(message);
+        }
+        let snapshot = match Snapshot::from_result(Executor::execute(query)) {
+            Ok(snapshot) => snapshot,
+            Err(ExecutionResult::Error { message }) => return Err(message),
+            Err(_) => return Err(format!("Query of cursor '{}' returns no rows", name)),
+        };
+        let held: usize = self.cursors.values().map(Cursor::bytes).sum();
+        if held + snapshot.bytes() > MAX_CURSOR_BYTES {
+            return Err(format!(
+                "Cursor '{}' would hold {} bytes of rows, and the session's cursors may hold at most {} ({} already held); close cursors or narrow the query",
+                name,
+                snapshot.bytes(),
+                MAX_CURSOR_BYTES,
+                held
+            ));
+        }
+        self.cursors.insert(name.to_string(), Cursor::new(query.clone(), snapshot));
+        Ok(())
+    }
+
+    /// Close one cursor, or all of them when `name` is None
+    pub fn close_cursor(&mut self, name: Option<&str>) -> Result<(), String> {
+        match name {
+            Some(name) => self
+                .cursors
+                .remove(name)
+                .map(|_| ())
+                .ok_or_else(|| format!("Cursor '{}' does not exist", name)),
+            None => {
+                self.cursors.clear();
+                Ok(())
+            }
+        }
+    }
+
+    /// The query cursor `name` was declared for
+    pub fn cursor_query(&self, name: &str) -> Option<&Statement> {
+        self.cursors.get(name).map(|cursor| &cursor.query)
+    }
+
+    pub fn prepare(
+        &mut self,
+        name: &str,
+        statement: Statement,
+        param_types: Vec<DataType>,
+    ) -> Result<(), String> {
+        if self.prepared.contains_key(name) {
+            return Err(format!("Prepared statement '{}' already exists", name));
+        }
+        let prepared = PreparedStatement::new(statement, param_types)?;
+        self.prepared.insert(name.to_string(), Arc::new(prepared));
+        Ok(())
+    }
+
+    pub fn execute_prepared(&mut self, name: &str, params: &[Value]) -> ExecutionResult {
+        let prepared = match self.prepared.get(name) {
+            Some(prepared) => Arc::clone(prepared),
+            None => {
+                return ExecutionResult::Error {
+                    message: format!("Prepared statement '{}' does not exist", name),
+                }
+            }
+        };
+
+        match prepared.bind(params) {
+            Ok(stmt) => self.execute(&stmt),
+            Err(message) => ExecutionResult::Error {
+                message: format!("Prepared statement '{}': {}", name, message),
+            },
+        }
+    }
+
+    /// Drop one prepared statement, or all of them when `name` is None
+    pub fn deallocate(&mut self, name: Option<&str>) -> Result<(), String> {
+        match name {
+            Some(name) => self
+                .prepared
+                .remove(name)
+                .map(|_| ())
+                .ok_or_else(|| format!("Prepared statement '{}' does not exist", name)),
+            None => {
+                self.prepared.clear();
+                Ok(())
+            }
+        }
+    }
+
+    pub fn prepared_statement(&self, name: &str) -> Option<Arc<PreparedStatement>> {
+        self.prepared.get(name).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(result: ExecutionResult) -> Vec<HashMap<String, serde_json::Value>> {
+        match result {
+            ExecutionResult::Rows { rows, .. } => rows,
+            other => panic!("Expected rows, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_prepare_execute_deallocate() {
+        let mut session = Session::new();
+        session.execute_sql("DROP TABLE IF EXISTS session_prepared");
+        session.execute_sql("CREATE TABLE session_prepared (id INT, name TEXT)");
+
+        let result = session.execute_sql("PREPARE add_user (INT, TEXT) AS INSERT INTO session_prepared VALUES ($1, $2)");
+        assert!(matches!(result, ExecutionResult::Success { .. }));
+
+        let result = session.execute_sql("EXECUTE add_user ('7', 'O\\'Brien')");
+        assert!(matches!(result, ExecutionResult::RowsAffected { count: 1 }), "{:?}", result);
+        // Values are bound, not spliced into SQL, so quotes need no escaping
+        session.execute_prepared("add_user", &[Value::Integer(8), Value::Text("x'); DROP TABLE t; --".to_string())]);
+
+        let found = rows(session.execute_with_params(
+            "SELECT id, name FROM session_prepared WHERE id = ?",
+            &[Value::Integer(8)],
+        ));
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0]["name"], serde_json::json!("x'); DROP TABLE t; --"));
+
+        let found = rows(session.execute_sql("SELECT id FROM session_prepared WHERE id = 7"));
+        assert_eq!(found.len(), 1, "INT parameter should be coerced from text");
+
+        match session.execute_sql("EXECUTE add_user (1)") {
+            ExecutionResult::Error { message } => assert!(message.contains("expected 2, got 1")),
+            other => panic!("Expected error, got {:?}", other),
+        }
+
+        assert!(matches!(session.execute_sql("DEALLOCATE add_user"), ExecutionResult::Success { .. }));
+        assert!(matches!(session.execute_sql("EXECUTE add_user (1, 'a')"), ExecutionResult::Error { .. }));
+
+        session.execute_sql("DROP TABLE session_prepared");
+    }
+
+    #[test]
+    fn test_prepare_duplicate_name() {
+        let mut session = Session::new();
+        session.execute_sql("PREPARE q AS SELECT 1");
+        assert!(matches!(session.execute_sql("PREPARE q AS SELECT 2"), ExecutionResult::Error { .. }));
+        assert!(matches!(session.execute_sql("DEALLOCATE ALL"), ExecutionResult::Success { .. }));
+        assert!(session.prepared_statement("q").is_none());
+    }
+
+    #[test]
+    fn test_stateless_session_refuses_session_statements() {
+        let mut session = Session::stateless();
+        for sql in ["PREPARE q AS SELECT 1", "DECLARE c CURSOR FOR SELECT 1", "BEGIN"] {
+            match session.execute_sql(sql) {
+                ExecutionResult::Error { message } => assert!(message.contains("needs a session"), "{}", message),
+                other => panic!("Expected error for {}, got {:?}", sql, other),
+            }
+        }
+        assert!(session.prepared_statement("q").is_none());
+        assert!(!session.in_transaction());
+        assert!(!rows(session.execute_with_params("SELECT $1 AS n", &[Value::Integer(1)])).is_empty());
+    }
+
+    #[test]
+    fn test_rollback_after_a_write_is_refused() {
+        let mut session = Session::new();
+        session.execute_sql("DROP TABLE IF EXISTS session_rollback");
+        session.execute_sql("CREATE TABLE session_rollback (id INT)");
+
+        session.execute_sql("BEGIN");
+        session.execute_sql("SELECT * FROM session_rollback");
+        assert!(matches!(session.execute_sql("ROLLBACK"), ExecutionResult::Success { .. }));
+
+        session.execute_sql("BEGIN");
+        session.execute_sql("INSERT INTO session_rollback VALUES (1)");
+        match session.execute_sql("ROLLBACK") {
+            ExecutionResult::Error { message } => assert!(message.contains("not supported")),
+            other => panic!("Expected error, got {:?}", other),
+        }
+        assert!(!session.in_transaction());
+        assert_eq!(rows(session.execute_sql("SELECT id FROM session_rollback")).len(), 1);
+
+        // The next block starts clean
+        session.execute_sql("BEGIN");
+        assert!(matches!(session.execute_sql("ROLLBACK"), ExecutionResult::Success { .. }));
+        session.execute_sql("DROP TABLE session_rollback");
+    }
+
+    #[test]
+    fn test_cursor_reads_a_snapshot_in_batches() {
+        let mut session = Session::new();
+        session.execute_sql("DROP TABLE IF EXISTS session_cursor");
+        session.execute_sql("CREATE TABLE session_cursor (id INT)");
+        session.execute_sql("INSERT INTO session_cursor VALUES (1), (2), (3)");
+
+        let result = session.execute_with_params("DECLARE c CURSOR FOR SELECT id FROM session_cursor WHERE id >= $1", &[Value::Integer(1)]);
+        assert!(matches!(result, ExecutionResult::Success { .. }), "{:?}", result);
+        assert!(matches!(session.execute_sql("DECLARE c CURSOR FOR SELECT 1"), ExecutionResult::Error { .. }));
+
+        // Rows written after DECLARE are not seen
+        session.execute_sql("INSERT INTO session_cursor VALUES (4)");
+        session.execute_sql("DELETE FROM session_cursor WHERE id = 2");
+
+        let ids = |result| rows(result).iter().map(|row| row["id"].as_i64().unwrap()).collect::<Vec<_>>();
+        assert_eq!(ids(session.execute_sql("FETCH 2 FROM c")), [1, 2]);
+        assert_eq!(ids(session.execute_sql("FETCH ALL FROM c")), [3]);
+        assert!(ids(session.execute_sql("FETCH NEXT FROM c")).is_empty());
+
+        assert!(matches!(session.execute_sql("CLOSE c"), ExecutionResult::Success { .. }));
+        assert!(matches!(session.execute_sql("FETCH c"), ExecutionResult::Error { .. }));
+        session.execute_sql("DROP TABLE session_cursor");
+    }
+
+    #[test]
+    fn test_plan_cache_reuses_parsed_statement() {
+        let mut session = Session::new();
+        let sql = "SELECT $1 + 1 AS n";
+
+        let first = rows(session.execute_with_params(sql, &[Value::Integer(1)]));
+        let second = rows(session.execute_with_params(sql, &[Value::Integer(41)]));
+
+        assert_eq!(first[0]["n"], serde_json::json!(2));
+        assert_eq!(second[0]["n"], serde_json::json!(42));
+        assert_eq!(session.plan_cache.len(), 1);
+    }
+
+    #[test]
+    fn test_unbound_placeholder_is_an_error() {
+        let mut session = Session::new();
+        match session.execute_sql("SELECT ? AS n") {
+            ExecutionResult::Error { message } => assert!(message.contains("no values were supplied")),
+            other => panic!("Expected error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_coerce_parameter() {
+        assert_eq!(
+            coerce_parameter(1, &Value::Text("42".to_string()), &DataType::Integer).unwrap(),
+            Value::Integer(42)
+        );
+        assert_eq!(
+            coerce_parameter(1, &Value::Integer(1), &DataType::Boolean).unwrap(),
+            Value::Boolean(true)
+        );
+        assert!(coerce_parameter(2, &Value::Text("abc".to_string()), &DataType::Float).is_err());
+    }
+}
@@ -68,6 +68,14 @@ pub enum Token {
     Commit,
     Rollback,
     Transaction,
+    With,
+    Recursive,
+    Returning,
+    Prepare,
+    Execute,
+    Deallocate,
+    Explain,
+    Analyze,
 
     // Data types
     Integer,
@@ -98,6 +106,8 @@ pub enum Token {
     StringLiteral(String),
     NumberLiteral(String),
     BooleanLiteral(bool),
+    /// Bind parameter: `$n`, or `?` numbered left to right (1-based)
+    Parameter(usize),
 
     // Punctuation
     LeftParen,
@@ -219,21 +229,30 @@ pub enum Statement {
         table: String,
         columns: Option<Vec<String>>,
         values: Vec<Vec<Expression>>,
+        /// Source query for INSERT INTO ... SELECT (`values` is empty when set)
+        query: Option<Box<Statement>>,
+        on_conflict: Option<OnConflict>,
+        /// RETURNING list, evaluated against each written row
+        returning: Option<Vec<Expression>>,
     },
     Update {
         table: String,
         assignments: Vec<Assignment>,
         where_clause: Option<Expression>,
+        returning: Option<Vec<Expression>>,
     },
     Delete {
         table: String,
         where_clause: Option<Expression>,
+        returning: Option<Vec<Expression>>,
     },
     CreateTable {
         name: String,
         columns: Vec<ColumnDef>,
         constraints: Vec<TableConstraint>,
         if_not_exists: bool,
+        /// Storage options from `WITH (key = value, ...)`, keys lowercased
+        options: Vec<(String, String)>,
     },
     CreateDatabase {
         name: String,
@@ -264,6 +283,82 @@ pub enum Statement {
         all: bool,
     },
     Transaction(TransactionStatement),
+    /// Query prefixed by common table expressions (WITH [RECURSIVE] ...)
+    With {
+        recursive: bool,
+        ctes: Vec<CommonTableExpression>,
+        body: Box<Statement>,
+    },
+    /// PREPARE name [(type, ...)] AS statement
+    Prepare {
+        name: String,
+        param_types: Vec<DataType>,
+        statement: Box<Statement>,
+    },
+    /// EXECUTE name [(value, ...)]
+    Execute {
+        name: String,
+        params: Vec<Expression>,
+    },
+    /// DEALLOCATE [PREPARE] name | ALL (`None` means ALL)
+    Deallocate {
+        name: Option<String>,
+    },
+    /// DECLARE name CURSOR FOR query
+    DeclareCursor {
+        name: String,
+        query: Box<Statement>,
+    },
+    /// FETCH [NEXT | count | ALL] [FROM | IN] name (`None` count means ALL)
+    Fetch {
+        name: String,
+        count: Option<usize>,
+    },
+    /// CLOSE name | ALL (`None` means ALL)
+    CloseCursor {
+        name: Option<String>,
+    },
+    /// LISTEN channel
+    Listen {
+        channel: String,
+    },
+    /// UNLISTEN channel | * (`None` means every channel)
+    Unlisten {
+        channel: Option<String>,
+    },
+    /// NOTIFY channel [, 'payload']
+    Notify {
+        channel: String,
+        payload: String,
+    },
+    /// EXPLAIN [ANALYZE] statement
+    Explain {
+        analyze: bool,
+        format: ExplainFormat,
+        statement: Box<Statement>,
+    },
+    /// ANALYZE [table]: collect planner statistics (`None` means every table)
+    Analyze {
+        table: Option<String>,
+    },
+}
+
+/// Output format of EXPLAIN
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ExplainFormat {
+    /// One row per plan node
+    #[default]
+    Text,
+    /// A single row holding the plan as a JSON tree
+    Json,
+}
+
+/// A named subquery introduced by a WITH clause
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommonTableExpression {
+    pub name: String,
+    pub columns: Option<Vec<String>>,
+    pub query: Box<Statement>,
 }
 
 /// Table references (can be table name or subquery)
@@ -301,6 +396,25 @@ pub struct Assignment {
     pub value: Expression,
 }
 
+/// ON CONFLICT clause of an INSERT (upsert)
+#[derive(Debug, Clone, PartialEq)]
+pub struct OnConflict {
+    /// Conflict target columns; empty means any primary key or unique constraint
+    pub target: Vec<String>,
+    pub action: ConflictAction,
+}
+
+/// What to do with a row that collides with an existing one
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConflictAction {
+    DoNothing,
+    /// `DO UPDATE SET ... [WHERE ...]`; the proposed row is visible as `EXCLUDED`
+    DoUpdate {
+        assignments: Vec<Assignment>,
+        where_clause: Option<Expression>,
+    },
+}
+
 /// Table constraints
 #[derive(Debug, Clone, PartialEq)]
 pub enum TableConstraint {
@@ -338,4 +452,6 @@ pub struct Tokenizer {
     pub line: usize,
     pub column: usize,
     pub keywords: HashMap<String, Token>,
+    /// Number of `?` placeholders seen so far
+    pub positional_parameters: usize,
 }
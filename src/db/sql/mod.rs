@@ -1,5 +1,6 @@
 // SQL Module - Parser and query interface
 pub mod constants;
+pub mod params;
 pub mod parser;
 
 // Re-export key types for external use
@@ -7,32 +8,19 @@ pub use constants::{
     Statement, Token, ParseError, Literal, BinaryOperator, UnaryOperator,
     JoinType, OrderDirection, ColumnDef, ColumnConstraint, TableReference,
     Join, OrderBy, Assignment, TableConstraint, AlterAction, TransactionStatement,
-    Tokenizer,
+    CommonTableExpression, Tokenizer, OnConflict, ConflictAction, ExplainFormat,
 };
 pub use parser::{SqlParser, Expression, DataType};
 
-use crate::db::executor::{Executor, ExecutionResult};
+use crate::db::executor::ExecutionResult;
+use crate::db::session::Session;
 
 /// Execute a SQL query string and return the result
+///
+/// Each call runs in a session of its own, so statements that keep state for
+/// later ones (PREPARE, DECLARE, BEGIN) are refused.
 pub fn execute_sql(query: &str) -> ExecutionResult {
-    // Parse the SQL
-    match SqlParser::parse(query) {
-        Ok(statements) => {
-            if statements.is_empty() {
-                return ExecutionResult::Error {
-                    message: "No SQL statements found".to_string(),
-                };
-            }
-            
-            // Execute each statement (for now, just the first one)
-            // In the future, we could support multi-statement execution
-            let stmt = &statements[0];
-            Executor::execute(stmt)
-        }
-        Err(e) => ExecutionResult::Error {
-            message: format!("SQL parse error: {}", e),
-        },
-    }
+    Session::stateless().execute_sql(query)
 }
 
 /// Parse SQL without executing (for validation)
@@ -0,0 +1,206 @@
+// Bind parameters ($1 / ?) for prepared statements
+use super::constants::{ConflictAction, Literal, Statement, TableReference};
+use super::parser::Expression;
+
+/// Number of values a statement needs: the highest placeholder index it uses
+pub fn parameter_count(stmt: &Statement) -> usize {
+    let mut max = 0;
+    let mut scratch = stmt.clone();
+    walk_statement(&mut scratch, &mut |expr| {
+        if let Expression::Parameter(index) = expr {
+            max = max.max(*index);
+        }
+    });
+    max
+}
+
+/// Copy of `stmt` with every placeholder replaced by its value
+/// (`$1` takes `values[0]`)
+pub fn bind_parameters(stmt: &Statement, values: &[Literal]) -> Result<Statement, String> {
+    let expected = parameter_count(stmt);
+    if values.len() != expected {
+        return Err(format!(
+            "Wrong number of parameters: expected {}, got {}",
+            expected,
+            values.len()
+        ));
+    }
+
+    let mut bound = stmt.clone();
+    walk_statement(&mut bound, &mut |expr| {
+        if let Expression::Parameter(index) = expr {
+            *expr = Expression::Literal(values[*index - 1].clone());
+        }
+    });
+    Ok(bound)
+}
+
+/// Visit every expression in a statement, including those inside subqueries
+fn walk_statement(stmt: &mut Statement, f: &mut dyn FnMut(&mut Expression)) {
+    match stmt {
+        Statement::Select {
+            projection,
+            from,
+            joins,
+            where_clause,
+            group_by,
+            having,
+            order_by,
+            ..
+        } => {
+            projection.iter_mut().for_each(|e| walk_expression(e, f));
+            if let Some(table_ref) = from {
+                walk_table_reference(table_ref, f);
+            }
+            for join in joins {
+                walk_table_reference(&mut join.table, f);
+                if let Some(condition) = &mut join.condition {
+                    walk_expression(condition, f);
+                }
+            }
+            if let Some(expr) = where_clause {
+                walk_expression(expr, f);
+            }
+            group_by.iter_mut().for_each(|e| walk_expression(e, f));
+            if let Some(expr) = having {
+                walk_expression(expr, f);
+            }
+            order_by.iter_mut().for_each(|o| walk_expression(&mut o.expression, f));
+        }
+        Statement::Insert {
+            values,
+            query,
+            on_conflict,
+            returning,
+            ..
+        } => {
+            values.iter_mut().flatten().for_each(|e| walk_expression(e, f));
+            if let Some(query) = query {
+                walk_statement(query, f);
+            }
+            if let Some(on_conflict) = on_conflict {
+                if let ConflictAction::DoUpdate {
+                    assignments,
+                    where_clause,
+                } = &mut on_conflict.action
+                {
+                    assignments.iter_mut().for_each(|a| walk_expression(&mut a.value, f));
+                    if let Some(expr) = where_clause {
+                        walk_expression(expr, f);
+                    }
+                }
+            }
+            returning.iter_mut().flatten().for_each(|e| walk_expression(e, f));
+        }
+        Statement::Update {
+            assignments,
+            where_clause,
+            returning,
+            ..
+        } => {
+            assignments.iter_mut().for_each(|a| walk_expression(&mut a.value, f));
+            if let Some(expr) = where_clause {
+                walk_expression(expr, f);
+            }
+            returning.iter_mut().flatten().for_each(|e| walk_expression(e, f));
+        }
+        Statement::Delete {
+            where_clause,
+            returning,
+            ..
+        } => {
+            if let Some(expr) = where_clause {
+                walk_expression(expr, f);
+            }
+            returning.iter_mut().flatten().for_each(|e| walk_expression(e, f));
+        }
+        Statement::Union { left, right, .. } => {
+            walk_statement(left, f);
+            walk_statement(right, f);
+        }
+        Statement::With { ctes, body, .. } => {
+            for cte in ctes {
+                walk_statement(&mut cte.query, f);
+            }
+            walk_statement(body, f);
+        }
+        Statement::Explain { statement, .. } => walk_statement(statement, f),
+        Statement::DeclareCursor { query, .. } => walk_statement(query, f),
+        _ => {}
+    }
+}
+
+fn walk_table_reference(table_ref: &mut TableReference, f: &mut dyn FnMut(&mut Expression)) {
+    if let TableReference::Subquery { query, .. } = table_ref {
+        walk_statement(query, f);
+    }
+}
+
+fn walk_expression(expr: &mut Expression, f: &mut dyn FnMut(&mut Expression)) {
+    match expr {
+        Expression::BinaryOp { left, right, .. } => {
+            walk_expression(left, f);
+            walk_expression(right, f);
+        }
+        Expression::UnaryOp { operand, .. } => walk_expression(operand, f),
+        Expression::Function { args, .. } => args.iter_mut().for_each(|a| walk_expression(a, f)),
+        Expression::Case {
+            when_clauses,
+            else_clause,
+        } => {
+            for (when, then) in when_clauses {
+                walk_expression(when, f);
+                walk_expression(then, f);
+            }
+            if let Some(else_expr) = else_clause {
+                walk_expression(else_expr, f);
+            }
+        }
+        Expression::Subquery(query) => walk_statement(query, f),
+        Expression::Alias { expr, .. } => walk_expression(expr, f),
+        _ => {}
+    }
+    f(expr);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::sql::parser::SqlParser;
+
+    #[test]
+    fn test_tokenizer_placeholders() {
+        let stmt = SqlParser::parse_statement("SELECT * FROM t WHERE a = ? AND b = ?").unwrap();
+        assert_eq!(parameter_count(&stmt), 2);
+
+        let stmt = SqlParser::parse_statement("SELECT * FROM t WHERE a = $2 OR b = $1").unwrap();
+        assert_eq!(parameter_count(&stmt), 2);
+
+        assert!(SqlParser::parse_statement("SELECT $ FROM t").is_err());
+    }
+
+    #[test]
+    fn test_bind_parameters_replaces_placeholders() {
+        let stmt = SqlParser::parse_statement(
+            "SELECT name FROM (SELECT * FROM u WHERE tag = $2) s WHERE id = $1",
+        )
+        .unwrap();
+        let bound = bind_parameters(
+            &stmt,
+            &[Literal::Number("7".to_string()), Literal::String("it's".to_string())],
+        )
+        .unwrap();
+
+        assert_eq!(parameter_count(&bound), 0);
+        let printed = format!("{:?}", bound);
+        assert!(printed.contains("Number(\"7\")"));
+        assert!(printed.contains("String(\"it's\")"));
+    }
+
+    #[test]
+    fn test_bind_parameters_count_mismatch() {
+        let stmt = SqlParser::parse_statement("UPDATE t SET a = ? WHERE id = ?").unwrap();
+        let err = bind_parameters(&stmt, &[Literal::Null]).unwrap_err();
+        assert!(err.contains("expected 2, got 1"));
+    }
+}
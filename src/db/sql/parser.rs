@@ -60,6 +60,8 @@ pub enum Expression {
         expr: Box<Expression>,
         alias: String,
     },
+    /// Bind parameter placeholder (1-based), filled in at EXECUTE time
+    Parameter(usize),
 }
 impl Tokenizer {
     /// Create a new tokenizer with the given input
@@ -130,6 +132,14 @@ impl Tokenizer {
             ("COMMIT", Token::Commit),
             ("ROLLBACK", Token::Rollback),
             ("TRANSACTION", Token::Transaction),
+            ("WITH", Token::With),
+            ("RECURSIVE", Token::Recursive),
+            ("RETURNING", Token::Returning),
+            ("PREPARE", Token::Prepare),
+            ("EXECUTE", Token::Execute),
+            ("DEALLOCATE", Token::Deallocate),
+            ("EXPLAIN", Token::Explain),
+            ("ANALYZE", Token::Analyze),
             ("INTEGER", Token::Integer),
             ("INT", Token::Integer),
             ("VARCHAR", Token::Varchar),
@@ -155,6 +165,7 @@ impl Tokenizer {
             line: 1,
             column: 1,
             keywords,
+            positional_parameters: 0,
         }
     }
 
@@ -412,6 +423,28 @@ impl Tokenizer {
                 let number = self.read_number_literal();
                 Ok(Token::NumberLiteral(number))
             }
+            Some('?') => {
+                self.consume();
+                self.positional_parameters += 1;
+                Ok(Token::Parameter(self.positional_parameters))
+            }
+            Some('$') => {
+                self.consume();
+                let mut digits = String::new();
+                while let Some(ch) = self.peek().filter(|c| c.is_ascii_digit()) {
+                    digits.push(ch);
+                    self.consume();
+                }
+                match digits.parse::<usize>() {
+                    Ok(index) if index > 0 => Ok(Token::Parameter(index)),
+                    _ => Err(ParseError {
+                        message: "Expected parameter number after '$' (e.g. $1)".to_string(),
+                        position: self.position,
+                        line: self.line,
+                        column: self.column,
+                    }),
+                }
+            }
             Some(ch) if ch.is_alphabetic() || ch == '_' => {
                 let identifier = self.read_identifier();
                 let upper_identifier = identifier.to_uppercase();
@@ -499,10 +532,25 @@ impl Parser {
         }
     }
 
+    /// Consume a non-reserved word (e.g. CONFLICT, NOTHING) that the tokenizer
+    /// leaves as an identifier
+    fn expect_word(&mut self, word: &str) -> Result<(), ParseError> {
+        match self.consume() {
+            Token::Identifier(ident) if ident.eq_ignore_ascii_case(word) => Ok(()),
+            other => Err(ParseError {
+                message: format!("Expected {}, found {:?}", word, other),
+                position: self.position,
+                line: 0,
+                column: 0,
+            }),
+        }
+    }
+
     /// Parse a complete SQL statement
     pub fn parse_statement(&mut self) -> Result<Statement, ParseError> {
         match self.peek() {
-            Token::Select => self.parse_select(),
+            Token::Select => self.parse_query(),
+            Token::With => self.parse_with(),
             Token::Insert => self.parse_insert(),
             Token::Update => self.parse_update(),
             Token::Delete => self.parse_delete(),
@@ -512,6 +560,17 @@ impl Parser {
             Token::Begin => self.parse_transaction(),
             Token::Commit => self.parse_transaction(),
             Token::Rollback => self.parse_transaction(),
+            Token::Prepare => self.parse_prepare(),
+            Token::Execute => self.parse_execute(),
+            Token::Deallocate => self.parse_deallocate(),
+            Token::Identifier(word) if word.eq_ignore_ascii_case("DECLARE") => self.parse_declare(),
+            Token::Identifier(word) if word.eq_ignore_ascii_case("FETCH") => self.parse_fetch(),
+            Token::Identifier(word) if word.eq_ignore_ascii_case("CLOSE") => self.parse_close(),
+            Token::Identifier(word) if word.eq_ignore_ascii_case("LISTEN") => self.parse_listen(),
+            Token::Identifier(word) if word.eq_ignore_ascii_case("UNLISTEN") => self.parse_unlisten(),
+            Token::Identifier(word) if word.eq_ignore_ascii_case("NOTIFY") => self.parse_notify(),
+            Token::Explain => self.parse_explain(),
+            Token::Analyze => self.parse_analyze(),
             _ => Err(ParseError {
                 message: format!("Unexpected token at start of statement: {:?}", self.peek()),
                 position: self.position,
@@ -521,6 +580,116 @@ impl Parser {
         }
     }
 
+    /// Parse a SELECT, optionally combined with further SELECTs via UNION [ALL]
+    fn parse_query(&mut self) -> Result<Statement, ParseError> {
+        let mut left = self.parse_select()?;
+
+        while matches!(self.peek(), Token::Union) {
+            self.consume();
+            let all = if matches!(self.peek(), Token::All) {
+                self.consume();
+                true
+            } else {
+                false
+            };
+            let right = self.parse_select()?;
+            left = Statement::Union {
+                left: Box::new(left),
+                right: Box::new(right),
+                all,
+            };
+        }
+
+        Ok(left)
+    }
+
+    /// Parse WITH [RECURSIVE] name [(columns)] AS (query), ... followed by the main query
+    fn parse_with(&mut self) -> Result<Statement, ParseError> {
+        self.expect(Token::With)?;
+
+        let recursive = if matches!(self.peek(), Token::Recursive) {
+            self.consume();
+            true
+        } else {
+            false
+        };
+
+        let mut ctes = Vec::new();
+        loop {
+            let name = if let Token::Identifier(n) = self.consume() {
+                n
+            } else {
+                return Err(ParseError {
+                    message: "Expected name for common table expression".to_string(),
+                    position: self.position,
+                    line: 0,
+                    column: 0,
+                });
+            };
+
+            // Optional column list
+            let columns = if matches!(self.peek(), Token::LeftParen) {
+                self.consume();
+                let mut cols = Vec::new();
+                loop {
+                    if let Token::Identifier(col) = self.consume() {
+                        cols.push(col);
+                    } else {
+                        return Err(ParseError {
+                            message: format!("Expected column name in column list of '{}'", name),
+                            position: self.position,
+                            line: 0,
+                            column: 0,
+                        });
+                    }
+
+                    if matches!(self.peek(), Token::Comma) {
+                        self.consume();
+                    } else {
+                        break;
+                    }
+                }
+                self.expect(Token::RightParen)?;
+                Some(cols)
+            } else {
+                None
+            };
+
+            self.expect(Token::As)?;
+            self.expect(Token::LeftParen)?;
+            let query = Box::new(self.parse_statement()?);
+            self.expect(Token::RightParen)?;
+
+            ctes.push(CommonTableExpression {
+                name,
+                columns,
+                query,
+            });
+
+            if matches!(self.peek(), Token::Comma) {
+                self.consume();
+            } else {
+                break;
+            }
+        }
+
+        if !matches!(self.peek(), Token::Select) {
+            return Err(ParseError {
+                message: format!("Expected SELECT after WITH clause, found {:?}", self.peek()),
+                position: self.position,
+                line: 0,
+                column: 0,
+            });
+        }
+        let body = Box::new(self.parse_query()?);
+
+        Ok(Statement::With {
+            recursive,
+            ctes,
+            body,
+        })
+    }
+
     /// Parse SELECT statement
     fn parse_select(&mut self) -> Result<Statement, ParseError> {
         self.expect(Token::Select)?;
@@ -798,7 +967,9 @@ impl Parser {
             Token::Begin | Token::Commit | Token::Rollback | Token::Distinct | Token::All |
             Token::Union | Token::Case | Token::When |
             Token::Then | Token::Else | Token::End |
-            Token::If | Token::Exists
+            Token::If | Token::Exists | Token::With | Token::Recursive |
+            Token::Returning | Token::Prepare | Token::Execute | Token::Deallocate |
+            Token::Explain | Token::Analyze
         )
     }
 
@@ -1127,6 +1298,10 @@ impl Parser {
                 self.consume();
                 Ok(Expression::Literal(Literal::Null))
             }
+            Token::Parameter(index) => {
+                self.consume();
+                Ok(Expression::Parameter(index))
+            }
             Token::Identifier(name) => {
                 self.consume();
 
@@ -1135,7 +1310,11 @@ impl Parser {
                     self.consume(); // consume '('
 
                     let mut args = Vec::new();
-                    if !matches!(self.peek(), Token::RightParen) {
+                    if matches!(self.peek(), Token::Star) {
+                        // COUNT(*)
+                        self.consume();
+                        args.push(Expression::Identifier("*".to_string()));
+                    } else if !matches!(self.peek(), Token::RightParen) {
                         args = self.parse_expression_list()?;
                     }
 
@@ -1306,49 +1485,121 @@ impl Parser {
             None
         };
 
-        self.expect(Token::Values)?;
-
-        // Parse values
         let mut values = Vec::new();
+        let mut query = None;
 
-        loop {
-            self.expect(Token::LeftParen)?;
-            let row_values = self.parse_expression_list()?;
-            self.expect(Token::RightParen)?;
-            values.push(row_values);
+        // INSERT INTO ... SELECT / WITH ... SELECT
+        if matches!(self.peek(), Token::Select | Token::With) {
+            query = Some(Box::new(self.parse_statement()?));
+        } else {
+            self.expect(Token::Values)?;
 
-            if matches!(self.peek(), Token::Comma) {
-                self.consume();
-            } else {
-                break;
+            // Parse values
+            loop {
+                self.expect(Token::LeftParen)?;
+                let row_values = self.parse_expression_list()?;
+                self.expect(Token::RightParen)?;
+                values.push(row_values);
+
+                if matches!(self.peek(), Token::Comma) {
+                    self.consume();
+                } else {
+                    break;
+                }
             }
         }
 
+        let on_conflict = if matches!(self.peek(), Token::On) {
+            Some(self.parse_on_conflict()?)
+        } else {
+            None
+        };
+
+        let returning = self.parse_returning()?;
+
         Ok(Statement::Insert {
             table,
             columns,
             values,
+            query,
+            on_conflict,
+            returning,
         })
     }
 
-    /// Parse UPDATE statement
-    fn parse_update(&mut self) -> Result<Statement, ParseError> {
-        self.expect(Token::Update)?;
+    /// Parse an optional RETURNING expression list
+    fn parse_returning(&mut self) -> Result<Option<Vec<Expression>>, ParseError> {
+        if matches!(self.peek(), Token::Returning) {
+            self.consume();
+            Ok(Some(self.parse_projection()?))
+        } else {
+            Ok(None)
+        }
+    }
 
-        let table = if let Token::Identifier(name) = self.consume() {
-            name
+    /// Parse ON CONFLICT [(col, ...)] DO NOTHING | DO UPDATE SET ... [WHERE ...]
+    fn parse_on_conflict(&mut self) -> Result<OnConflict, ParseError> {
+        self.expect(Token::On)?;
+        self.expect_word("CONFLICT")?;
+
+        let mut target = Vec::new();
+        if matches!(self.peek(), Token::LeftParen) {
+            self.consume();
+            loop {
+                if let Token::Identifier(col) = self.consume() {
+                    target.push(col);
+                } else {
+                    return Err(ParseError {
+                        message: "Expected column name in ON CONFLICT target".to_string(),
+                        position: self.position,
+                        line: 0,
+                        column: 0,
+                    });
+                }
+
+                if matches!(self.peek(), Token::Comma) {
+                    self.consume();
+                } else {
+                    break;
+                }
+            }
+            self.expect(Token::RightParen)?;
+        }
+
+        self.expect_word("DO")?;
+
+        let action = if matches!(self.peek(), Token::Update) {
+            self.consume();
+            if target.is_empty() {
+                return Err(ParseError {
+                    message: "ON CONFLICT DO UPDATE requires a conflict target column list".to_string(),
+                    position: self.position,
+                    line: 0,
+                    column: 0,
+                });
+            }
+            self.expect(Token::Set)?;
+            let assignments = self.parse_assignments()?;
+            let where_clause = if matches!(self.peek(), Token::Where) {
+                self.consume();
+                Some(self.parse_expression()?)
+            } else {
+                None
+            };
+            ConflictAction::DoUpdate {
+                assignments,
+                where_clause,
+            }
         } else {
-            return Err(ParseError {
-                message: "Expected table name after UPDATE".to_string(),
-                position: self.position,
-                line: 0,
-                column: 0,
-            });
+            self.expect_word("NOTHING")?;
+            ConflictAction::DoNothing
         };
 
-        self.expect(Token::Set)?;
+        Ok(OnConflict { target, action })
+    }
 
-        // Parse assignments
+    /// Parse `col = expr, ...` as used by UPDATE and ON CONFLICT DO UPDATE
+    fn parse_assignments(&mut self) -> Result<Vec<Assignment>, ParseError> {
         let mut assignments = Vec::new();
 
         loop {
@@ -1375,37 +1626,29 @@ impl Parser {
             }
         }
 
-        // Parse WHERE clause
-        let where_clause = if matches!(self.peek(), Token::Where) {
-            self.consume();
-            Some(self.parse_expression()?)
-        } else {
-            None
-        };
-
-        Ok(Statement::Update {
-            table,
-            assignments,
-            where_clause,
-        })
+        Ok(assignments)
     }
 
-    /// Parse DELETE statement
-    fn parse_delete(&mut self) -> Result<Statement, ParseError> {
-        self.expect(Token::Delete)?;
-        self.expect(Token::From)?;
+    /// Parse UPDATE statement
+    fn parse_update(&mut self) -> Result<Statement, ParseError> {
+        self.expect(Token::Update)?;
 
         let table = if let Token::Identifier(name) = self.consume() {
             name
         } else {
             return Err(ParseError {
-                message: "Expected table name after DELETE FROM".to_string(),
+                message: "Expected table name after UPDATE".to_string(),
                 position: self.position,
                 line: 0,
                 column: 0,
             });
         };
 
+        self.expect(Token::Set)?;
+
+        // Parse assignments
+        let assignments = self.parse_assignments()?;
+
         // Parse WHERE clause
         let where_clause = if matches!(self.peek(), Token::Where) {
             self.consume();
@@ -1414,52 +1657,423 @@ impl Parser {
             None
         };
 
-        Ok(Statement::Delete {
+        let returning = self.parse_returning()?;
+
+        Ok(Statement::Update {
             table,
+            assignments,
             where_clause,
+            returning,
         })
     }
 
-    /// Parse CREATE statement
-    fn parse_create(&mut self) -> Result<Statement, ParseError> {
-        self.expect(Token::Create)?;
+    /// Parse PREPARE name [(type, ...)] AS statement
+    fn parse_prepare(&mut self) -> Result<Statement, ParseError> {
+        self.expect(Token::Prepare)?;
+        let name = self.parse_statement_name("PREPARE")?;
 
-        match self.peek() {
-            Token::Table => self.parse_create_table(),
-            Token::Database => self.parse_create_database(),
-            Token::Index => self.parse_create_index(),
-            _ => Err(ParseError {
-                message: "Expected TABLE, DATABASE, or INDEX after CREATE".to_string(),
-                position: self.position,
-                line: 0,
-                column: 0,
-            }),
+        let mut param_types = Vec::new();
+        if matches!(self.peek(), Token::LeftParen) {
+            self.consume();
+            loop {
+                param_types.push(self.parse_data_type()?);
+                if matches!(self.peek(), Token::Comma) {
+                    self.consume();
+                } else {
+                    break;
+                }
+            }
+            self.expect(Token::RightParen)?;
         }
+
+        self.expect(Token::As)?;
+        let statement = Box::new(self.parse_statement()?);
+
+        Ok(Statement::Prepare {
+            name,
+            param_types,
+            statement,
+        })
     }
 
-    /// Parse CREATE TABLE statement
-    fn parse_create_table(&mut self) -> Result<Statement, ParseError> {
-        self.expect(Token::Table)?;
+    /// Parse EXECUTE name [(value, ...)]
+    fn parse_execute(&mut self) -> Result<Statement, ParseError> {
+        self.expect(Token::Execute)?;
+        let name = self.parse_statement_name("EXECUTE")?;
 
-        let if_not_exists = if matches!(self.peek(), Token::If) {
+        let mut params = Vec::new();
+        if matches!(self.peek(), Token::LeftParen) {
             self.consume();
-            self.expect(Token::Not)?;
-            self.expect(Token::Exists)?;
-            true
-        } else {
-            false
-        };
+            if !matches!(self.peek(), Token::RightParen) {
+                params = self.parse_expression_list()?;
+            }
+            self.expect(Token::RightParen)?;
+        }
 
-        let name = if let Token::Identifier(n) = self.consume() {
-            n
-        } else {
-            return Err(ParseError {
-                message: "Expected table name".to_string(),
-                position: self.position,
-                line: 0,
-                column: 0,
-            });
-        };
+        Ok(Statement::Execute { name, params })
+    }
+
+    /// Parse DEALLOCATE [PREPARE] name | ALL
+    fn parse_deallocate(&mut self) -> Result<Statement, ParseError> {
+        self.expect(Token::Deallocate)?;
+        if matches!(self.peek(), Token::Prepare) {
+            self.consume();
+        }
+
+        if matches!(self.peek(), Token::All) {
+            self.consume();
+            return Ok(Statement::Deallocate { name: None });
+        }
+
+        let name = self.parse_statement_name("DEALLOCATE")?;
+        Ok(Statement::Deallocate { name: Some(name) })
+    }
+
+    /// Parse DECLARE name [INSENSITIVE] [NO SCROLL] CURSOR [WITH | WITHOUT HOLD]
+    /// FOR query. Cursors always read a snapshot taken when they are declared
+    /// and live until closed, so the options change nothing.
+    fn parse_declare(&mut self) -> Result<Statement, ParseError> {
+        self.expect_word("DECLARE")?;
+        let name = self.parse_cursor_name("DECLARE")?;
+
+        loop {
+            match self.consume() {
+                Token::Identifier(word) if word.eq_ignore_ascii_case("CURSOR") => break,
+                Token::Identifier(word) if word.eq_ignore_ascii_case("INSENSITIVE") => {}
+                Token::Identifier(word) if word.eq_ignore_ascii_case("NO") => self.expect_word("SCROLL")?,
+                other => {
+                    return Err(ParseError {
+                        message: format!("Expected CURSOR, found {:?}", other),
+                        position: self.position,
+                        line: 0,
+                        column: 0,
+                    })
+                }
+            }
+        }
+        match self.peek() {
+            Token::With => {
+                self.consume();
+                self.expect_word("HOLD")?;
+            }
+            Token::Identifier(word) if word.eq_ignore_ascii_case("WITHOUT") => {
+                self.consume();
+                self.expect_word("HOLD")?;
+            }
+            _ => {}
+        }
+        self.expect_word("FOR")?;
+
+        let query = match self.peek() {
+            Token::Select => self.parse_query()?,
+            Token::With => self.parse_with()?,
+            other => {
+                return Err(ParseError {
+                    message: format!("Expected a query after DECLARE ... FOR, found {:?}", other),
+                    position: self.position,
+                    line: 0,
+                    column: 0,
+                })
+            }
+        };
+        Ok(Statement::DeclareCursor {
+            name,
+            query: Box::new(query),
+        })
+    }
+
+    /// Parse FETCH [NEXT | FORWARD [count | ALL] | count | ALL] [FROM | IN] name
+    fn parse_fetch(&mut self) -> Result<Statement, ParseError> {
+        self.expect_word("FETCH")?;
+
+        let mut count = Some(1);
+        let forward = matches!(self.peek(), Token::Identifier(word) if word.eq_ignore_ascii_case("FORWARD"));
+        if forward || matches!(self.peek(), Token::Identifier(word) if word.eq_ignore_ascii_case("NEXT")) {
+            self.consume();
+        }
+        if forward || !matches!(self.peek(), Token::Identifier(_)) {
+            match self.peek().clone() {
+                Token::All => {
+                    self.consume();
+                    count = None;
+                }
+                Token::NumberLiteral(n) => {
+                    self.consume();
+                    count = Some(n.parse::<usize>().map_err(|_| ParseError {
+                        message: format!("Invalid FETCH count: {}", n),
+                        position: self.position,
+                        line: 0,
+                        column: 0,
+                    })?);
+                }
+                _ => {}
+            }
+        }
+        if matches!(self.peek(), Token::From | Token::In) {
+            self.consume();
+        }
+
+        let name = self.parse_cursor_name("FETCH")?;
+        Ok(Statement::Fetch { name, count })
+    }
+
+    /// Parse CLOSE name | ALL
+    fn parse_close(&mut self) -> Result<Statement, ParseError> {
+        self.expect_word("CLOSE")?;
+        if matches!(self.peek(), Token::All) {
+            self.consume();
+            return Ok(Statement::CloseCursor { name: None });
+        }
+        let name = self.parse_cursor_name("CLOSE")?;
+        Ok(Statement::CloseCursor { name: Some(name) })
+    }
+
+    fn parse_cursor_name(&mut self, keyword: &str) -> Result<String, ParseError> {
+        if let Token::Identifier(name) = self.consume() {
+            Ok(name)
+        } else {
+            Err(ParseError {
+                message: format!("Expected cursor name after {}", keyword),
+                position: self.position,
+                line: 0,
+                column: 0,
+            })
+        }
+    }
+
+    /// Parse LISTEN channel
+    fn parse_listen(&mut self) -> Result<Statement, ParseError> {
+        self.expect_word("LISTEN")?;
+        let channel = self.parse_channel_name("LISTEN")?;
+        Ok(Statement::Listen { channel })
+    }
+
+    /// Parse UNLISTEN channel | *
+    fn parse_unlisten(&mut self) -> Result<Statement, ParseError> {
+        self.expect_word("UNLISTEN")?;
+        if matches!(self.peek(), Token::Star) {
+            self.consume();
+            return Ok(Statement::Unlisten { channel: None });
+        }
+        let channel = self.parse_channel_name("UNLISTEN")?;
+        Ok(Statement::Unlisten { channel: Some(channel) })
+    }
+
+    /// Parse NOTIFY channel [, 'payload']
+    fn parse_notify(&mut self) -> Result<Statement, ParseError> {
+        self.expect_word("NOTIFY")?;
+        let channel = self.parse_channel_name("NOTIFY")?;
+        let mut payload = String::new();
+        if matches!(self.peek(), Token::Comma) {
+            self.consume();
+            payload = match self.consume() {
+                Token::StringLiteral(payload) => payload,
+                _ => {
+                    return Err(ParseError {
+                        message: "Expected a string payload after NOTIFY channel,".to_string(),
+                        position: self.position,
+                        line: 0,
+                        column: 0,
+                    })
+                }
+            };
+        }
+        Ok(Statement::Notify { channel, payload })
+    }
+
+    fn parse_channel_name(&mut self, keyword: &str) -> Result<String, ParseError> {
+        if let Token::Identifier(name) = self.consume() {
+            Ok(name)
+        } else {
+            Err(ParseError {
+                message: format!("Expected channel name after {}", keyword),
+                position: self.position,
+                line: 0,
+                column: 0,
+            })
+        }
+    }
+
+    /// Parse EXPLAIN [ANALYZE] [FORMAT TEXT | JSON] statement, or the
+    /// parenthesized form EXPLAIN (ANALYZE [bool], FORMAT TEXT | JSON) statement
+    fn parse_explain(&mut self) -> Result<Statement, ParseError> {
+        self.expect(Token::Explain)?;
+        let mut analyze = false;
+        let mut format = ExplainFormat::Text;
+
+        if matches!(self.peek(), Token::LeftParen) {
+            self.consume();
+            loop {
+                match self.consume() {
+                    Token::Analyze => {
+                        analyze = match self.peek() {
+                            Token::BooleanLiteral(b) => {
+                                let b = *b;
+                                self.consume();
+                                b
+                            }
+                            _ => true,
+                        };
+                    }
+                    Token::Identifier(word) if word.eq_ignore_ascii_case("FORMAT") => {
+                        format = self.parse_explain_format()?;
+                    }
+                    other => {
+                        return Err(ParseError {
+                            message: format!("Unknown EXPLAIN option: {:?}", other),
+                            position: self.position,
+                            line: 0,
+                            column: 0,
+                        })
+                    }
+                }
+                if matches!(self.peek(), Token::Comma) {
+                    self.consume();
+                } else {
+                    break;
+                }
+            }
+            self.expect(Token::RightParen)?;
+        } else {
+            if matches!(self.peek(), Token::Analyze) {
+                self.consume();
+                analyze = true;
+            }
+            if matches!(self.peek(), Token::Identifier(word) if word.eq_ignore_ascii_case("FORMAT")) {
+                self.consume();
+                format = self.parse_explain_format()?;
+            }
+        }
+
+        if matches!(self.peek(), Token::Explain) {
+            return Err(ParseError {
+                message: "EXPLAIN cannot be nested".to_string(),
+                position: self.position,
+                line: 0,
+                column: 0,
+            });
+        }
+        let statement = Box::new(self.parse_statement()?);
+
+        Ok(Statement::Explain {
+            analyze,
+            format,
+            statement,
+        })
+    }
+
+    /// Parse ANALYZE [table]
+    fn parse_analyze(&mut self) -> Result<Statement, ParseError> {
+        self.expect(Token::Analyze)?;
+        match self.peek() {
+            Token::Identifier(table) => {
+                let table = table.clone();
+                self.consume();
+                Ok(Statement::Analyze { table: Some(table) })
+            }
+            _ => Ok(Statement::Analyze { table: None }),
+        }
+    }
+
+    fn parse_explain_format(&mut self) -> Result<ExplainFormat, ParseError> {
+        match self.consume() {
+            Token::Text => Ok(ExplainFormat::Text),
+            Token::Identifier(word) if word.eq_ignore_ascii_case("JSON") => Ok(ExplainFormat::Json),
+            other => Err(ParseError {
+                message: format!("Expected TEXT or JSON after FORMAT, found {:?}", other),
+                position: self.position,
+                line: 0,
+                column: 0,
+            }),
+        }
+    }
+
+    fn parse_statement_name(&mut self, keyword: &str) -> Result<String, ParseError> {
+        if let Token::Identifier(name) = self.consume() {
+            Ok(name)
+        } else {
+            Err(ParseError {
+                message: format!("Expected prepared statement name after {}", keyword),
+                position: self.position,
+                line: 0,
+                column: 0,
+            })
+        }
+    }
+
+    /// Parse DELETE statement
+    fn parse_delete(&mut self) -> Result<Statement, ParseError> {
+        self.expect(Token::Delete)?;
+        self.expect(Token::From)?;
+
+        let table = if let Token::Identifier(name) = self.consume() {
+            name
+        } else {
+            return Err(ParseError {
+                message: "Expected table name after DELETE FROM".to_string(),
+                position: self.position,
+                line: 0,
+                column: 0,
+            });
+        };
+
+        // Parse WHERE clause
+        let where_clause = if matches!(self.peek(), Token::Where) {
+            self.consume();
+            Some(self.parse_expression()?)
+        } else {
+            None
+        };
+
+        let returning = self.parse_returning()?;
+
+        Ok(Statement::Delete {
+            table,
+            where_clause,
+            returning,
+        })
+    }
+
+    /// Parse CREATE statement
+    fn parse_create(&mut self) -> Result<Statement, ParseError> {
+        self.expect(Token::Create)?;
+
+        match self.peek() {
+            Token::Table => self.parse_create_table(),
+            Token::Database => self.parse_create_database(),
+            Token::Index | Token::Unique => self.parse_create_index(),
+            _ => Err(ParseError {
+                message: "Expected TABLE, DATABASE, INDEX, or UNIQUE INDEX after CREATE".to_string(),
+                position: self.position,
+                line: 0,
+                column: 0,
+            }),
+        }
+    }
+
+    /// Parse CREATE TABLE statement
+    fn parse_create_table(&mut self) -> Result<Statement, ParseError> {
+        self.expect(Token::Table)?;
+
+        let if_not_exists = if matches!(self.peek(), Token::If) {
+            self.consume();
+            self.expect(Token::Not)?;
+            self.expect(Token::Exists)?;
+            true
+        } else {
+            false
+        };
+
+        let name = if let Token::Identifier(n) = self.consume() {
+            n
+        } else {
+            return Err(ParseError {
+                message: "Expected table name".to_string(),
+                position: self.position,
+                line: 0,
+                column: 0,
+            });
+        };
 
         self.expect(Token::LeftParen)?;
 
@@ -1482,14 +2096,63 @@ impl Parser {
 
         self.expect(Token::RightParen)?;
 
+        let options = if matches!(self.peek(), Token::With) {
+            self.consume();
+            self.parse_table_options()?
+        } else {
+            Vec::new()
+        };
+
         Ok(Statement::CreateTable {
             name,
             columns,
             constraints,
             if_not_exists,
+            options,
         })
     }
 
+    /// Parse the `(key = value, ...)` after CREATE TABLE ... WITH. Values may
+    /// be quoted strings, bare words or numbers.
+    fn parse_table_options(&mut self) -> Result<Vec<(String, String)>, ParseError> {
+        self.expect(Token::LeftParen)?;
+        let mut options = Vec::new();
+        loop {
+            let key = match self.consume() {
+                Token::Identifier(key) => key.to_lowercase(),
+                other => {
+                    return Err(ParseError {
+                        message: format!("Expected table option name, found {:?}", other),
+                        position: self.position,
+                        line: 0,
+                        column: 0,
+                    })
+                }
+            };
+            self.expect(Token::Equals)?;
+            let value = match self.consume() {
+                Token::StringLiteral(value) | Token::Identifier(value) | Token::NumberLiteral(value) => value,
+                other => {
+                    return Err(ParseError {
+                        message: format!("Expected a value for table option '{}', found {:?}", key, other),
+                        position: self.position,
+                        line: 0,
+                        column: 0,
+                    })
+                }
+            };
+            options.push((key, value));
+
+            if matches!(self.peek(), Token::Comma) {
+                self.consume();
+            } else {
+                break;
+            }
+        }
+        self.expect(Token::RightParen)?;
+        Ok(options)
+    }
+
     /// Parse CREATE DATABASE statement
     fn parse_create_database(&mut self) -> Result<Statement, ParseError> {
         self.expect(Token::Database)?;
@@ -1822,12 +2485,14 @@ impl SqlParser {
     }
 }
 
-/// Visitor trait for traversing the AST
+/// Visitor trait for traversing the AST. Statements and expressions may
+/// produce different outputs (a rewrite returns a new node of the same kind).
 pub trait AstVisitor {
-    type Output;
+    type StatementOutput;
+    type ExpressionOutput;
 
-    fn visit_statement(&mut self, stmt: &Statement) -> Self::Output;
-    fn visit_expression(&mut self, expr: &Expression) -> Self::Output;
+    fn visit_statement(&mut self, stmt: &Statement) -> Self::StatementOutput;
+    fn visit_expression(&mut self, expr: &Expression) -> Self::ExpressionOutput;
 }
 
 /// Pretty printer for SQL AST
@@ -1950,6 +2615,9 @@ impl SqlPrettyPrinter {
                 table,
                 columns,
                 values,
+                query,
+                on_conflict,
+                returning,
             } => {
                 let mut result = format!("INSERT INTO {}", table);
 
@@ -1964,28 +2632,67 @@ impl SqlPrettyPrinter {
                     result.push(')');
                 }
 
-                result.push_str("\nVALUES ");
+                if let Some(query) = query {
+                    result.push('\n');
+                    result.push_str(&self.print_statement(query));
+                } else {
+                    result.push_str("\nVALUES ");
 
-                for (i, row) in values.iter().enumerate() {
-                    if i > 0 {
-                        result.push_str(",\n       ");
+                    for (i, row) in values.iter().enumerate() {
+                        if i > 0 {
+                            result.push_str(",\n       ");
+                        }
+                        result.push('(');
+                        for (j, val) in row.iter().enumerate() {
+                            if j > 0 {
+                                result.push_str(", ");
+                            }
+                            result.push_str(&self.print_expression(val));
+                        }
+                        result.push(')');
                     }
-                    result.push('(');
-                    for (j, val) in row.iter().enumerate() {
-                        if j > 0 {
-                            result.push_str(", ");
+                }
+
+                if let Some(on_conflict) = on_conflict {
+                    result.push_str("\nON CONFLICT");
+                    if !on_conflict.target.is_empty() {
+                        result.push_str(&format!(" ({})", on_conflict.target.join(", ")));
+                    }
+                    match &on_conflict.action {
+                        ConflictAction::DoNothing => result.push_str(" DO NOTHING"),
+                        ConflictAction::DoUpdate {
+                            assignments,
+                            where_clause,
+                        } => {
+                            result.push_str(" DO UPDATE SET ");
+                            for (i, assignment) in assignments.iter().enumerate() {
+                                if i > 0 {
+                                    result.push_str(", ");
+                                }
+                                result.push_str(&format!(
+                                    "{} = {}",
+                                    assignment.column,
+                                    self.print_expression(&assignment.value)
+                                ));
+                            }
+                            if let Some(where_expr) = where_clause {
+                                result.push_str(&format!(
+                                    " WHERE {}",
+                                    self.print_expression(where_expr)
+                                ));
+                            }
                         }
-                        result.push_str(&self.print_expression(val));
                     }
-                    result.push(')');
                 }
 
+                result.push_str(&self.print_returning(returning.as_deref()));
                 result
             }
             Statement::Update {
                 table,
                 assignments,
                 where_clause,
+                returning,
             } => {
                 let mut result = format!("UPDATE {}\nSET ", table);
 
@@ -2005,11 +2712,13 @@ impl SqlPrettyPrinter {
                     result.push_str(&self.print_expression(where_expr));
                 }
 
+                result.push_str(&self.print_returning(returning.as_deref()));
                 result
             }
             Statement::Delete {
                 table,
                 where_clause,
+                returning,
             } => {
                 let mut result = format!("DELETE FROM {}", table);
 
@@ -2018,13 +2727,24 @@ impl SqlPrettyPrinter {
                     result.push_str(&self.print_expression(where_expr));
                 }
 
+                result.push_str(&self.print_returning(returning.as_deref()));
                 result
             }
             _ => format!("{:?}", stmt), // Fallback for other statement types
         }
     }
 
-    fn print_expression(&self, expr: &Expression) -> String {
+    fn print_returning(&self, returning: Option<&[Expression]>) -> String {
+        match returning {
+            Some(exprs) => {
+                let items: Vec<String> = exprs.iter().map(|e| self.print_expression(e)).collect();
+                format!("\nRETURNING {}", items.join(", "))
+            }
+            None => String::new(),
+        }
+    }
+
+    pub fn print_expression(&self, expr: &Expression) -> String {
         match expr {
             Expression::Literal(lit) => match lit {
                 Literal::String(s) => format!("'{}'", s),
@@ -2089,6 +2809,7 @@ impl SqlPrettyPrinter {
                 result.push(')');
                 result
             }
+            Expression::Parameter(index) => format!("${}", index),
             _ => format!("{:?}", expr), // Fallback
         }
     }
@@ -2193,6 +2914,7 @@ mod tests {
                 table,
                 columns,
                 values,
+                ..
             } => {
                 assert_eq!(table, "users");
                 assert!(columns.is_some());
@@ -2202,6 +2924,175 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_insert_select() {
+        let input = "INSERT INTO archive (id, name) SELECT id, name FROM users WHERE active = FALSE";
+        let result = SqlParser::parse_statement(input).unwrap();
+
+        match result {
+            Statement::Insert {
+                table,
+                columns,
+                values,
+                query,
+                on_conflict,
+                ..
+            } => {
+                assert_eq!(table, "archive");
+                assert_eq!(columns.unwrap().len(), 2);
+                assert!(values.is_empty());
+                assert!(matches!(query.as_deref(), Some(Statement::Select { .. })));
+                assert!(on_conflict.is_none());
+            }
+            _ => panic!("Expected INSERT statement"),
+        }
+    }
+
+    #[test]
+    fn test_insert_multiple_rows() {
+        let input = "INSERT INTO t VALUES (1, 'a'), (2, 'b'), (3, 'c')";
+        let result = SqlParser::parse_statement(input).unwrap();
+
+        match result {
+            Statement::Insert { values, query, .. } => {
+                assert_eq!(values.len(), 3);
+                assert!(query.is_none());
+            }
+            _ => panic!("Expected INSERT statement"),
+        }
+    }
+
+    #[test]
+    fn test_insert_on_conflict_do_nothing() {
+        let input = "INSERT INTO t (id, name) VALUES (1, 'a') ON CONFLICT (id) DO NOTHING";
+        let result = SqlParser::parse_statement(input).unwrap();
+
+        match result {
+            Statement::Insert { on_conflict, .. } => {
+                let on_conflict = on_conflict.unwrap();
+                assert_eq!(on_conflict.target, vec!["id"]);
+                assert_eq!(on_conflict.action, ConflictAction::DoNothing);
+            }
+            _ => panic!("Expected INSERT statement"),
+        }
+    }
+
+    #[test]
+    fn test_insert_on_conflict_do_update() {
+        let input = "INSERT INTO t (id, hits) SELECT id, hits FROM staging \
+                     ON CONFLICT (id) DO UPDATE SET hits = EXCLUDED.hits WHERE t.hits < EXCLUDED.hits";
+        let result = SqlParser::parse_statement(input).unwrap();
+
+        match result {
+            Statement::Insert { query, on_conflict, .. } => {
+                assert!(query.is_some());
+                match on_conflict.unwrap().action {
+                    ConflictAction::DoUpdate {
+                        assignments,
+                        where_clause,
+                    } => {
+                        assert_eq!(assignments.len(), 1);
+                        assert_eq!(assignments[0].column, "hits");
+                        assert!(where_clause.is_some());
+                    }
+                    other => panic!("Expected DO UPDATE, got {:?}", other),
+                }
+            }
+            _ => panic!("Expected INSERT statement"),
+        }
+    }
+
+    #[test]
+    fn test_insert_on_conflict_do_update_requires_target() {
+        let input = "INSERT INTO t VALUES (1) ON CONFLICT DO UPDATE SET id = 2";
+        assert!(SqlParser::parse_statement(input).is_err());
+    }
+
+    #[test]
+    fn test_returning_clause() {
+        let stmt = SqlParser::parse_statement("INSERT INTO t (id) VALUES (1) RETURNING id, id * 2 AS doubled").unwrap();
+        match stmt {
+            Statement::Insert { returning, .. } => assert_eq!(returning.unwrap().len(), 2),
+            _ => panic!("Expected INSERT statement"),
+        }
+
+        let stmt = SqlParser::parse_statement("UPDATE t SET n = 1 WHERE id = 2 RETURNING *").unwrap();
+        match stmt {
+            Statement::Update { where_clause, returning, .. } => {
+                assert!(where_clause.is_some());
+                assert_eq!(returning.unwrap(), vec![Expression::Identifier("*".to_string())]);
+            }
+            _ => panic!("Expected UPDATE statement"),
+        }
+
+        let stmt = SqlParser::parse_statement("DELETE FROM t RETURNING id").unwrap();
+        assert!(matches!(stmt, Statement::Delete { returning: Some(_), .. }));
+    }
+
+    #[test]
+    fn test_explain() {
+        let stmt = SqlParser::parse_statement("EXPLAIN SELECT * FROM t").unwrap();
+        assert!(matches!(
+            stmt,
+            Statement::Explain { analyze: false, format: ExplainFormat::Text, .. }
+        ));
+
+        let stmt = SqlParser::parse_statement("EXPLAIN ANALYZE FORMAT JSON DELETE FROM t").unwrap();
+        match stmt {
+            Statement::Explain { analyze, format, statement } => {
+                assert!(analyze);
+                assert_eq!(format, ExplainFormat::Json);
+                assert!(matches!(*statement, Statement::Delete { .. }));
+            }
+            _ => panic!("Expected EXPLAIN statement"),
+        }
+
+        let stmt = SqlParser::parse_statement("EXPLAIN (ANALYZE, FORMAT TEXT) SELECT 1").unwrap();
+        assert!(matches!(stmt, Statement::Explain { analyze: true, format: ExplainFormat::Text, .. }));
+
+        assert!(SqlParser::parse_statement("EXPLAIN EXPLAIN SELECT 1").is_err());
+        assert!(SqlParser::parse_statement("EXPLAIN (FORMAT XML) SELECT 1").is_err());
+    }
+
+    #[test]
+    fn test_analyze() {
+        assert_eq!(
+            SqlParser::parse_statement("ANALYZE users").unwrap(),
+            Statement::Analyze { table: Some("users".to_string()) }
+        );
+        assert_eq!(SqlParser::parse_statement("ANALYZE;").unwrap(), Statement::Analyze { table: None });
+    }
+
+    #[test]
+    fn test_cursor_statements() {
+        let stmt = SqlParser::parse_statement("DECLARE c NO SCROLL CURSOR WITH HOLD FOR SELECT id FROM users").unwrap();
+        assert!(matches!(stmt, Statement::DeclareCursor { ref name, .. } if name == "c"));
+        assert!(SqlParser::parse_statement("DECLARE c CURSOR FOR DELETE FROM users").is_err());
+
+        let fetch = |sql: &str| SqlParser::parse_statement(sql).unwrap();
+        let expected = |count| Statement::Fetch { name: "c".to_string(), count };
+        assert_eq!(fetch("FETCH c"), expected(Some(1)));
+        assert_eq!(fetch("FETCH NEXT FROM c"), expected(Some(1)));
+        assert_eq!(fetch("fetch 50 in c"), expected(Some(50)));
+        assert_eq!(fetch("FETCH FORWARD ALL FROM c"), expected(None));
+        assert_eq!(fetch("CLOSE ALL"), Statement::CloseCursor { name: None });
+    }
+
+    #[test]
+    fn test_listen_notify_statements() {
+        let parse = |sql: &str| SqlParser::parse_statement(sql).unwrap();
+        assert_eq!(parse("LISTEN cache"), Statement::Listen { channel: "cache".to_string() });
+        assert_eq!(parse("unlisten *"), Statement::Unlisten { channel: None });
+        assert_eq!(parse("UNLISTEN cache"), Statement::Unlisten { channel: Some("cache".to_string()) });
+        assert_eq!(
+            parse("NOTIFY cache, 'users:42'"),
+            Statement::Notify { channel: "cache".to_string(), payload: "users:42".to_string() }
+        );
+        assert_eq!(parse("NOTIFY cache"), Statement::Notify { channel: "cache".to_string(), payload: String::new() });
+        assert!(SqlParser::parse_statement("NOTIFY cache, 42").is_err());
+        assert!(SqlParser::parse_statement("LISTEN").is_err());
+    }
+
     #[test]
     fn test_create_table() {
         let input = r#"
@@ -2224,6 +3115,19 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_create_table_with_options() {
+        let stmt =
+            SqlParser::parse_statement("CREATE TABLE events (id INT, kind TEXT) WITH (FORMAT = 'columnar')").unwrap();
+        match stmt {
+            Statement::CreateTable { options, .. } => {
+                assert_eq!(options, vec![("format".to_string(), "columnar".to_string())]);
+            }
+            _ => panic!("Expected CREATE TABLE statement"),
+        }
+        assert!(SqlParser::parse_statement("CREATE TABLE events (id INT) WITH (format)").is_err());
+    }
+
     #[test]
     fn test_complex_query() {
         let input = r#"
@@ -2265,6 +3169,70 @@ mod tests {
             _ => panic!("Expected SELECT statement"),
         }
     }
+
+    #[test]
+    fn test_union_all() {
+        let input = "SELECT id FROM a UNION ALL SELECT id FROM b UNION SELECT id FROM c";
+        let result = SqlParser::parse_statement(input).unwrap();
+
+        match result {
+            Statement::Union { left, all, .. } => {
+                assert!(!all);
+                assert!(matches!(*left, Statement::Union { all: true, .. }));
+            }
+            _ => panic!("Expected UNION statement"),
+        }
+    }
+
+    #[test]
+    fn test_with_clause() {
+        let input = "WITH a AS (SELECT 1), b (x, y) AS (SELECT 1, 2) SELECT * FROM a JOIN b ON a.id = b.x";
+        let result = SqlParser::parse_statement(input).unwrap();
+
+        match result {
+            Statement::With {
+                recursive,
+                ctes,
+                body,
+            } => {
+                assert!(!recursive);
+                assert_eq!(ctes.len(), 2);
+                assert_eq!(ctes[0].name, "a");
+                assert!(ctes[0].columns.is_none());
+                assert_eq!(ctes[1].columns, Some(vec!["x".to_string(), "y".to_string()]));
+                assert!(matches!(*body, Statement::Select { .. }));
+            }
+            _ => panic!("Expected WITH statement"),
+        }
+    }
+
+    #[test]
+    fn test_with_recursive() {
+        let input = r#"
+            WITH RECURSIVE subordinates(id, manager_id) AS (
+                SELECT id, manager_id FROM employees WHERE id = 1
+                UNION ALL
+                SELECT e.id, e.manager_id FROM employees e
+                INNER JOIN subordinates s ON e.manager_id = s.id
+            )
+            SELECT id FROM subordinates
+        "#;
+        let result = SqlParser::parse_statement(input).unwrap();
+
+        match result {
+            Statement::With { recursive, ctes, .. } => {
+                assert!(recursive);
+                assert!(matches!(*ctes[0].query, Statement::Union { all: true, .. }));
+            }
+            _ => panic!("Expected WITH statement"),
+        }
+    }
+
+    #[test]
+    fn test_with_requires_select_body() {
+        let result = SqlParser::parse_statement("WITH a AS (SELECT 1) DELETE FROM a");
+        assert!(result.is_err());
+    }
 }
 
 impl Parser {
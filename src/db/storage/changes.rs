@@ -0,0 +1,201 @@
+// Change capture - the rows each committed write changed, handed to
+// listeners (live queries, ...) in commit order
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard};
+
+use super::Row;
+
+/// One row changed by a write
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    Insert { row: Row },
+    Update { old: Row, new: Row },
+    Delete { row: Row },
+}
+
+/// The changes one statement made to one table
+#[derive(Debug, Clone, PartialEq)]
+pub struct Commit {
+    pub table: String,
+    pub changes: Vec<Change>,
+}
+
+/// Something that wants to hear about committed writes
+pub trait ChangeListener: Send + Sync {
+    /// Whether writes to `table` should be recorded for this listener
+    fn watches(&self, table: &str) -> bool;
+
+    /// Called with a write's changes just before it commits to storage, in
+    /// commit order. An error fails the write, which then never commits.
+    fn prepare(&self, _commit: &Commit) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// The write last prepared failed to commit after all
+    fn aborted(&self, _commit: &Commit) {}
+
+    /// Called once per commit to a watched table, in commit order. Runs
+    /// while other writers wait, so it should hand the commit off quickly.
+    fn committed(&self, commit: &Commit);
+
+    /// Called after `committed` once other writers no longer wait on this
+    /// one, for slow work such as syncing files. The writer is told of an
+    /// error, but its write stays committed.
+    fn settle(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref LISTENERS: RwLock<Vec<Arc<dyn ChangeListener>>> = RwLock::new(Vec::new());
+    /// Held shared by every recorded write and exclusively by `quiesce`
+    static ref GATE: RwLock<()> = RwLock::new(());
+    /// Serializes writes to watched tables from commit to publication
+    static ref ORDER: Mutex<()> = Mutex::new(());
+}
+
+/// Start handing commits to `listener`
+pub fn listen(listener: Arc<dyn ChangeListener>) {
+    LISTENERS.write().unwrap_or_else(|poisoned| poisoned.into_inner()).push(listener);
+}
+
+fn watched(table: &str) -> bool {
+    LISTENERS
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .iter()
+        .any(|listener| listener.watches(table))
+}
+
+/// Run `f` with no recorded write in progress: every write either
+/// committed and was published before `f`, or starts after it. Listeners
+/// use this to read a starting state that no change is missing from or
+/// counted twice in.
+pub fn quiesce<T>(f: impl FnOnce() -> T) -> T {
+    let _gate = GATE.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+    f()
+}
+
+/// Collects the changes of one write as it makes them. Writes to tables
+/// nobody watches get a disabled recorder, which keeps nothing; callers
+/// check `enabled` before copying rows for it.
+#[derive(Debug)]
+pub struct ChangeRecorder {
+    table: String,
+    enabled: bool,
+    changes: Vec<Change>,
+}
+
+impl ChangeRecorder {
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn insert(&mut self, row: &Row) {
+        if self.enabled {
+            self.changes.push(Change::Insert { row: row.clone() });
+        }
+    }
+
+    pub fn update(&mut self, old: Row, new: &Row) {
+        if self.enabled {
+            self.changes.push(Change::Update { old, new: new.clone() });
+        }
+    }
+
+    pub fn delete(&mut self, row: &Row) {
+        if self.enabled {
+            self.changes.push(Change::Delete { row: row.clone() });
+        }
+    }
+}
+
+/// A write in progress: holds off `quiesce`, and for watched tables keeps
+/// commits in order until they are published
+pub struct Recording {
+    _gate: Option<RwLockReadGuard<'static, ()>>,
+    order: Option<MutexGuard<'static, ()>>,
+    pub recorder: ChangeRecorder,
+    /// The write's changes and the listeners that prepared them
+    prepared: Option<(Commit, Vec<Arc<dyn ChangeListener>>)>,
+}
+
+/// Begin a write to `table`
+pub fn record(table: &str) -> Recording {
+    let gate = GATE.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let enabled = watched(table);
+    Recording {
+        _gate: Some(gate),
+        order: enabled.then(|| ORDER.lock().unwrap_or_else(|poisoned| poisoned.into_inner())),
+        recorder: ChangeRecorder {
+            table: table.to_string(),
+            enabled,
+            changes: Vec::new(),
+        },
+        prepared: None,
+    }
+}
+
+/// Begin a write nobody is told about (one to an embedded database)
+pub fn unrecorded(table: &str) -> Recording {
+    Recording {
+        _gate: None,
+        order: None,
+        recorder: ChangeRecorder {
+            table: table.to_string(),
+            enabled: false,
+            changes: Vec::new(),
+        },
+        prepared: None,
+    }
+}
+
+impl Recording {
+    /// Hand the write's changes to the listeners watching its table before
+    /// it commits. If one refuses them, the ones before it are told the
+    /// write aborted and the error is returned: the write must not commit.
+    pub fn prepare(&mut self) -> Result<(), String> {
+        let ChangeRecorder { table, enabled, changes } = &mut self.recorder;
+        if !*enabled || changes.is_empty() {
+            return Ok(());
+        }
+        let commit = Commit {
+            table: table.clone(),
+            changes: std::mem::take(changes),
+        };
+        let listeners: Vec<Arc<dyn ChangeListener>> = LISTENERS
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .iter()
+            .filter(|listener| listener.watches(&commit.table))
+            .cloned()
+            .collect();
+        for (i, listener) in listeners.iter().enumerate() {
+            if let Err(e) = listener.prepare(&commit) {
+                listeners[..i].iter().for_each(|listener| listener.aborted(&commit));
+                return Err(e);
+            }
+        }
+        self.prepared = Some((commit, listeners));
+        Ok(())
+    }
+
+    /// The prepared write failed to commit
+    pub fn abort(self) {
+        if let Some((commit, listeners)) = &self.prepared {
+            listeners.iter().for_each(|listener| listener.aborted(commit));
+        }
+    }
+
+    /// Tell the listeners the prepared write committed, then let the next
+    /// writer go before they settle it
+    pub fn publish(mut self) -> Result<(), String> {
+        let Some((commit, listeners)) = self.prepared.take() else {
+            return Ok(());
+        };
+        for listener in &listeners {
+            listener.committed(&commit);
+        }
+        drop(self.order.take());
+        listeners.iter().try_for_each(|listener| listener.settle())
+    }
+}
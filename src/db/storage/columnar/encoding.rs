@@ -0,0 +1,265 @@
+// Lightweight column encodings - run-length, dictionary and delta
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::db::storage::Value;
+
+/// The values of one column of one chunk. `encode` picks whichever form is
+/// smallest for the data; every form decodes back to the exact values.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum EncodedColumn {
+    Plain(Vec<Value>),
+    /// Runs of one repeated value, with their lengths
+    RunLength(Vec<(Value, u32)>),
+    /// Distinct text values (NULL included), and for each row the position
+    /// of its value among them
+    Dictionary { values: Vec<Value>, codes: Vec<u32> },
+    /// Integers without NULLs: the first value, then the zig-zag varint
+    /// encoded difference of each value from the one before
+    Delta { first: i64, len: u32, deltas: Vec<u8> },
+}
+
+impl EncodedColumn {
+    pub fn encode(values: &[Value]) -> Self {
+        let mut best = (plain_size(values), Encoding::Plain);
+        let runs = runs(values);
+        let run_length_size = runs.iter().map(|(v, _)| value_size(v) + 4).sum();
+        if run_length_size < best.0 {
+            best = (run_length_size, Encoding::RunLength);
+        }
+        if let Some(size) = dictionary_size(values) {
+            if size < best.0 {
+                best = (size, Encoding::Dictionary);
+            }
+        }
+        if let Some(size) = delta_size(values) {
+            if size < best.0 {
+                best = (size, Encoding::Delta);
+            }
+        }
+
+        match best.1 {
+            Encoding::Plain => EncodedColumn::Plain(values.to_vec()),
+            Encoding::RunLength => EncodedColumn::RunLength(runs),
+            Encoding::Dictionary => {
+                let mut positions: HashMap<Option<&str>, u32> = HashMap::new();
+                let mut dictionary = Vec::new();
+                let codes = values
+                    .iter()
+                    .map(|v| {
+                        let key = match v {
+                            Value::Text(s) => Some(s.as_str()),
+                            _ => None,
+                        };
+                        *positions.entry(key).or_insert_with(|| {
+                            dictionary.push(v.clone());
+                            dictionary.len() as u32 - 1
+                        })
+                    })
+                    .collect();
+                EncodedColumn::Dictionary {
+                    values: dictionary,
+                    codes,
+                }
+            }
+            Encoding::Delta => {
+                let ints: Vec<i64> = values
+                    .iter()
+                    .map(|v| if let Value::Integer(i) = v { *i } else { 0 })
+                    .collect();
+                let mut deltas = Vec::new();
+                for pair in ints.windows(2) {
+                    write_varint(&mut deltas, zigzag(pair[1].wrapping_sub(pair[0])));
+                }
+                EncodedColumn::Delta {
+                    first: ints[0],
+                    len: ints.len() as u32,
+                    deltas,
+                }
+            }
+        }
+    }
+
+    pub fn decode(&self) -> Vec<Value> {
+        match self {
+            EncodedColumn::Plain(values) => values.clone(),
+            EncodedColumn::RunLength(runs) => runs
+                .iter()
+                .flat_map(|(value, count)| std::iter::repeat_n(value.clone(), *count as usize))
+                .collect(),
+            EncodedColumn::Dictionary { values, codes } => {
+                codes.iter().map(|&code| values[code as usize].clone()).collect()
+            }
+            EncodedColumn::Delta { first, len, deltas } => {
+                let mut out = Vec::with_capacity(*len as usize);
+                let mut current = *first;
+                out.push(Value::Integer(current));
+                let mut bytes = deltas.iter();
+                while out.len() < *len as usize {
+                    current = current.wrapping_add(unzigzag(read_varint(&mut bytes)));
+                    out.push(Value::Integer(current));
+                }
+                out
+            }
+        }
+    }
+
+    pub fn encoding_name(&self) -> &'static str {
+        match self {
+            EncodedColumn::Plain(_) => "plain",
+            EncodedColumn::RunLength(_) => "rle",
+            EncodedColumn::Dictionary { .. } => "dictionary",
+            EncodedColumn::Delta { .. } => "delta",
+        }
+    }
+}
+
+enum Encoding {
+    Plain,
+    RunLength,
+    Dictionary,
+    Delta,
+}
+
+/// Rough bytes one value takes, for choosing between encodings
+fn value_size(value: &Value) -> usize {
+    match value {
+        Value::Null | Value::Boolean(_) => 1,
+        Value::Integer(_) | Value::Float(_) => 8,
+        Value::Text(s) => s.len() + 4,
+    }
+}
+
+fn plain_size(values: &[Value]) -> usize {
+    values.iter().map(value_size).sum()
+}
+
+/// Whether two values are stored identically. Unlike `==`, -0.0 and 0.0
+/// differ and NaN matches itself, so runs decode to the exact values.
+fn same(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Float(x), Value::Float(y)) => x.to_bits() == y.to_bits(),
+        _ => a == b,
+    }
+}
+
+fn runs(values: &[Value]) -> Vec<(Value, u32)> {
+    let mut runs: Vec<(Value, u32)> = Vec::new();
+    for value in values {
+        match runs.last_mut() {
+            Some((last, count)) if same(last, value) => *count += 1,
+            _ => runs.push((value.clone(), 1)),
+        }
+    }
+    runs
+}
+
+/// Dictionaries are kept for text columns (with or without NULLs)
+fn dictionary_size(values: &[Value]) -> Option<usize> {
+    let mut distinct: HashMap<Option<&str>, usize> = HashMap::new();
+    for value in values {
+        match value {
+            Value::Text(s) => distinct.entry(Some(s.as_str())).or_insert(s.len() + 4),
+            Value::Null => distinct.entry(None).or_insert(1),
+            _ => return None,
+        };
+    }
+    if !distinct.keys().any(Option::is_some) {
+        return None;
+    }
+    Some(distinct.values().sum::<usize>() + values.len() * 4)
+}
+
+fn delta_size(values: &[Value]) -> Option<usize> {
+    let mut size = 12;
+    let mut previous: Option<i64> = None;
+    for value in values {
+        let Value::Integer(i) = value else {
+            return None;
+        };
+        if let Some(previous) = previous {
+            size += varint_len(zigzag(i.wrapping_sub(previous)));
+        }
+        previous = Some(*i);
+    }
+    previous.map(|_| size)
+}
+
+fn zigzag(n: i64) -> u64 {
+    ((n << 1) ^ (n >> 63)) as u64
+}
+
+fn unzigzag(n: u64) -> i64 {
+    ((n >> 1) as i64) ^ -((n & 1) as i64)
+}
+
+fn varint_len(mut n: u64) -> usize {
+    let mut len = 1;
+    while n >= 0x80 {
+        n >>= 7;
+        len += 1;
+    }
+    len
+}
+
+fn write_varint(out: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        out.push((n as u8 & 0x7f) | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+fn read_varint<'a>(bytes: &mut impl Iterator<Item = &'a u8>) -> u64 {
+    let mut n = 0u64;
+    let mut shift = 0;
+    for &byte in bytes {
+        n |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+    }
+    n
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(values: Vec<Value>) -> &'static str {
+        let encoded = EncodedColumn::encode(&values);
+        let decoded = encoded.decode();
+        assert_eq!(decoded.len(), values.len());
+        for (a, b) in decoded.iter().zip(&values) {
+            assert!(same(a, b), "{:?} decoded as {:?}", b, a);
+        }
+        encoded.encoding_name()
+    }
+
+    #[test]
+    fn test_encodings_round_trip() {
+        let ascending: Vec<Value> = (0..1000).map(|i| Value::Integer(1_000_000 + i * 3)).collect();
+        assert_eq!(round_trip(ascending), "delta");
+
+        let extremes = vec![Value::Integer(i64::MAX), Value::Integer(i64::MIN), Value::Integer(-1)];
+        round_trip(extremes);
+
+        let flags: Vec<Value> = (0..1000).map(|i| Value::Boolean(i < 400)).collect();
+        assert_eq!(round_trip(flags), "rle");
+
+        let kinds: Vec<Value> = (0..1000)
+            .map(|i| match i % 7 {
+                0 => Value::Null,
+                k => Value::Text(format!("kind-{}", k % 3)),
+            })
+            .collect();
+        assert_eq!(round_trip(kinds), "dictionary");
+
+        let floats = vec![Value::Float(0.0), Value::Float(-0.0), Value::Float(f64::NAN), Value::Float(f64::NAN)];
+        round_trip(floats);
+
+        let mixed = vec![Value::Integer(1), Value::Text("a".to_string()), Value::Float(2.5), Value::Null];
+        assert_eq!(round_trip(mixed), "plain");
+    }
+}
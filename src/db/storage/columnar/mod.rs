@@ -0,0 +1,436 @@
+// Columnar table storage - encoded column chunks with min/max zone maps
+pub mod encoding;
+
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::ops::{Bound, Range};
+
+use super::{KeyRange, Row, ScanBatch, ScanFilter, Value};
+pub use encoding::EncodedColumn;
+
+/// Rows per column chunk. Matches the scan batch and morsel size, so batched
+/// and parallel reads line up with chunk boundaries.
+pub const CHUNK_ROWS: usize = 1024;
+
+/// Bounds of the values in one column of one chunk
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ZoneMap {
+    /// Smallest and largest non-NULL value (None when every value is NULL)
+    pub min: Option<Value>,
+    pub max: Option<Value>,
+    pub null_count: usize,
+    /// Whether `min` and `max` bound the values under the executor's
+    /// comparison rules. Cleared for mixed kinds, booleans and NaN, which
+    /// compare as equal to anything.
+    pub ordered: bool,
+}
+
+/// Kinds of value that compare with each other by order
+#[derive(PartialEq)]
+enum OrderClass {
+    Numeric,
+    Text,
+    Unordered,
+}
+
+fn order_class(value: &Value) -> OrderClass {
+    match value {
+        Value::Float(f) if f.is_nan() => OrderClass::Unordered,
+        Value::Integer(_) | Value::Float(_) => OrderClass::Numeric,
+        Value::Text(_) => OrderClass::Text,
+        Value::Null | Value::Boolean(_) => OrderClass::Unordered,
+    }
+}
+
+/// The executor compares -0.0 equal to 0.0; `total_cmp` does not
+fn normalized(value: &Value) -> Value {
+    match value {
+        Value::Float(0.0) => Value::Float(0.0),
+        other => other.clone(),
+    }
+}
+
+impl ZoneMap {
+    fn build(values: &[Value]) -> Self {
+        let mut zone = ZoneMap {
+            ordered: true,
+            ..ZoneMap::default()
+        };
+        let mut class = None;
+        for value in values {
+            if matches!(value, Value::Null) {
+                zone.null_count += 1;
+                continue;
+            }
+            let value_class = order_class(value);
+            if value_class == OrderClass::Unordered || class.as_ref().is_some_and(|c| *c != value_class) {
+                zone.ordered = false;
+            }
+            class = Some(value_class);
+
+            let value = normalized(value);
+            if zone.min.as_ref().is_none_or(|min| value.total_cmp(min) == Ordering::Less) {
+                zone.min = Some(value.clone());
+            }
+            if zone.max.as_ref().is_none_or(|max| value.total_cmp(max) == Ordering::Greater) {
+                zone.max = Some(value);
+            }
+        }
+        zone
+    }
+
+    /// Whether a row of the chunk may satisfy a comparison that only holds
+    /// for values inside `range`. False means the chunk can be skipped.
+    pub fn may_contain(&self, range: &KeyRange) -> bool {
+        let (Some(min), Some(max)) = (&self.min, &self.max) else {
+            // Comparisons with NULL never hold
+            return false;
+        };
+        if !self.ordered {
+            return true;
+        }
+        // A bound of another kind compares as equal to every value, so it
+        // rules nothing out
+        let comparable = |bound: &Value| order_class(bound) == order_class(min);
+        let lower_ok = match &range.lower {
+            Bound::Included(v) if comparable(v) => max.total_cmp(&normalized(v)) != Ordering::Less,
+            Bound::Excluded(v) if comparable(v) => max.total_cmp(&normalized(v)) == Ordering::Greater,
+            _ => true,
+        };
+        let upper_ok = match &range.upper {
+            Bound::Included(v) if comparable(v) => min.total_cmp(&normalized(v)) != Ordering::Greater,
+            Bound::Excluded(v) if comparable(v) => min.total_cmp(&normalized(v)) == Ordering::Less,
+            _ => true,
+        };
+        lower_ok && upper_ok
+    }
+}
+
+/// One column of one chunk
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct ChunkColumn {
+    data: EncodedColumn,
+    zone: ZoneMap,
+    /// Rows that had no value at all for the column (not even NULL)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    absent: Vec<u32>,
+}
+
+/// `CHUNK_ROWS` consecutive rows, stored column by column
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ColumnChunk {
+    rows: usize,
+    columns: BTreeMap<String, ChunkColumn>,
+}
+
+impl ColumnChunk {
+    fn encode(rows: &[Row]) -> Self {
+        let mut names: Vec<&String> = rows.iter().flat_map(|row| row.keys()).collect();
+        names.sort();
+        names.dedup();
+
+        let columns = names
+            .into_iter()
+            .map(|name| {
+                let mut absent = Vec::new();
+                let values: Vec<Value> = rows
+                    .iter()
+                    .enumerate()
+                    .map(|(i, row)| {
+                        row.get(name).cloned().unwrap_or_else(|| {
+                            absent.push(i as u32);
+                            Value::Null
+                        })
+                    })
+                    .collect();
+                let column = ChunkColumn {
+                    data: EncodedColumn::encode(&values),
+                    zone: ZoneMap::build(&values),
+                    absent,
+                };
+                (name.clone(), column)
+            })
+            .collect();
+        Self {
+            rows: rows.len(),
+            columns,
+        }
+    }
+
+    /// Whether no row can satisfy every one of `ranges`. A column the chunk
+    /// never saw is NULL throughout, so a range on it rules the chunk out.
+    fn excluded_by(&self, ranges: &[(String, KeyRange)]) -> bool {
+        ranges.iter().any(|(column, range)| match self.columns.get(column) {
+            Some(column) => !column.zone.may_contain(range),
+            None => true,
+        })
+    }
+
+    /// Decode `rows` of the chunk, keeping only `columns` when given
+    fn decode(&self, columns: Option<&[String]>, rows: Range<usize>) -> Vec<Row> {
+        let mut out: Vec<Row> = vec![Row::new(); rows.len()];
+        for (name, column) in &self.columns {
+            if columns.is_some_and(|wanted| !wanted.contains(name)) {
+                continue;
+            }
+            let values = column.data.decode();
+            let mut absent = column.absent.iter().map(|&i| i as usize).peekable();
+            for (i, value) in values.into_iter().enumerate().take(rows.end) {
+                if absent.next_if_eq(&i).is_some() || i < rows.start {
+                    continue;
+                }
+                out[i - rows.start].insert(name.clone(), value);
+            }
+        }
+        out
+    }
+}
+
+/// The rows of a columnar table: full chunks, encoded, then the rows that do
+/// not fill a chunk yet, kept as they are until enough arrive
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ColumnStore {
+    chunks: Vec<ColumnChunk>,
+    tail: Vec<Row>,
+}
+
+impl ColumnStore {
+    pub fn from_rows(rows: Vec<Row>) -> Self {
+        let mut store = Self::default();
+        store.append(rows);
+        store
+    }
+
+    /// Add rows at the end, sealing each chunk as it fills
+    pub fn append(&mut self, rows: Vec<Row>) {
+        self.tail.extend(rows);
+        while self.tail.len() >= CHUNK_ROWS {
+            let rest = self.tail.split_off(CHUNK_ROWS);
+            let full = std::mem::replace(&mut self.tail, rest);
+            self.chunks.push(ColumnChunk::encode(&full));
+        }
+    }
+
+    /// Keep the first `len` rows
+    pub fn truncate(&mut self, len: usize) {
+        let full = len / CHUNK_ROWS;
+        if full < self.chunks.len() {
+            self.tail = self.chunks[full].decode(None, 0..len % CHUNK_ROWS);
+            self.chunks.truncate(full);
+        } else {
+            self.tail.truncate(len - full * CHUNK_ROWS);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.len() * CHUNK_ROWS + self.tail.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn chunk_count(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// Every row, decoded
+    pub fn rows(&self) -> Vec<Row> {
+        let mut rows: Vec<Row> = Vec::with_capacity(self.len());
+        for chunk in &self.chunks {
+            rows.extend(chunk.decode(None, 0..chunk.rows));
+        }
+        rows.extend(self.tail.iter().cloned());
+        rows
+    }
+
+    /// Read positions `offset..offset + limit`, decoding only the columns and
+    /// chunks `filter` asks for
+    pub fn scan(&self, offset: usize, limit: usize, filter: &ScanFilter) -> ScanBatch {
+        let end = offset.saturating_add(limit).min(self.len());
+        let mut batch = ScanBatch::default();
+        let mut position = offset;
+        while position < end {
+            let chunk_start = position - position % CHUNK_ROWS;
+            let stop = end.min(chunk_start + CHUNK_ROWS);
+            let rows = match self.chunks.get(position / CHUNK_ROWS) {
+                Some(chunk) if chunk.excluded_by(&filter.ranges) => {
+                    batch.skipped_chunks += 1;
+                    Vec::new()
+                }
+                Some(chunk) => chunk.decode(filter.columns.as_deref(), position - chunk_start..stop - chunk_start),
+                None => self.tail[position - chunk_start..stop - chunk_start]
+                    .iter()
+                    .map(|row| filter.project(row))
+                    .collect(),
+            };
+            batch.positions.extend(position..position + rows.len());
+            batch.rows.extend(rows);
+            position = stop;
+        }
+        batch.scanned = end.saturating_sub(offset);
+        batch
+    }
+
+    /// Encoding of each column of each chunk, for EXPLAIN-style reporting
+    pub fn encodings(&self) -> Vec<BTreeMap<String, &'static str>> {
+        self.chunks
+            .iter()
+            .map(|chunk| {
+                chunk
+                    .columns
+                    .iter()
+                    .map(|(name, column)| (name.clone(), column.data.encoding_name()))
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::catalog::TableFormat;
+
+    fn event(i: i64) -> Row {
+        let mut row = Row::from([
+            ("id".to_string(), Value::Integer(i)),
+            ("kind".to_string(), Value::Text(format!("k{}", i % 4))),
+        ]);
+        // Some rows leave `score` out entirely, some set it NULL
+        match i % 10 {
+            0 => {}
+            1 => {
+                row.insert("score".to_string(), Value::Null);
+            }
+            _ => {
+                row.insert("score".to_string(), Value::Float(i as f64 / 2.0));
+            }
+        }
+        row
+    }
+
+    #[test]
+    fn test_column_store_round_trip() {
+        let rows: Vec<Row> = (0..2500).map(event).collect();
+        let mut store = ColumnStore::from_rows(rows.clone());
+        assert_eq!(store.chunk_count(), 2);
+        assert_eq!(store.len(), 2500);
+        assert_eq!(store.rows(), rows);
+        assert_eq!(store.encodings()[0]["id"], "delta");
+        assert_eq!(store.encodings()[0]["kind"], "dictionary");
+
+        let middle = store.scan(1000, 50, &ScanFilter::default());
+        assert_eq!(middle.rows, rows[1000..1050]);
+        assert_eq!(middle.positions, (1000..1050).collect::<Vec<_>>());
+
+        store.truncate(1500);
+        assert_eq!(store.chunk_count(), 1);
+        assert_eq!(store.rows(), rows[..1500]);
+        store.append(rows[1500..].to_vec());
+        assert_eq!(store.rows(), rows);
+    }
+
+    #[test]
+    fn test_zone_maps_skip_chunks() {
+        let store = ColumnStore::from_rows((0..4096).map(event).collect());
+        let filter = |column: &str, range: KeyRange| ScanFilter {
+            columns: Some(vec!["id".to_string()]),
+            ranges: vec![(column.to_string(), range)],
+        };
+
+        let recent = store.scan(0, 4096, &filter("id", KeyRange {
+            lower: Bound::Included(Value::Integer(3000)),
+            upper: Bound::Unbounded,
+        }));
+        assert_eq!(recent.skipped_chunks, 2);
+        assert_eq!(recent.scanned, 4096);
+        assert_eq!(recent.rows.len(), 2048);
+        assert_eq!(recent.rows[0].len(), 1);
+        assert_eq!(recent.positions[0], 2048);
+
+        // Integer columns are bounded by float literals too
+        let none = store.scan(0, 4096, &filter("id", KeyRange::exact(Value::Float(-1.5))));
+        assert_eq!(none.skipped_chunks, 4);
+        assert!(none.rows.is_empty());
+
+        // A bound of another kind (or a column never written) rules out nothing / everything
+        let text = store.scan(0, 4096, &filter("id", KeyRange::exact(Value::Text("x".to_string()))));
+        assert_eq!(text.skipped_chunks, 0);
+        let missing = store.scan(0, 4096, &filter("nope", KeyRange::exact(Value::Integer(1))));
+        assert_eq!(missing.skipped_chunks, 4);
+
+        let zone = ZoneMap::build(&[Value::Float(-0.0), Value::Float(-3.0)]);
+        assert!(zone.may_contain(&KeyRange::exact(Value::Float(0.0))));
+        let nan = ZoneMap::build(&[Value::Float(f64::NAN), Value::Float(1.0)]);
+        assert!(nan.may_contain(&KeyRange::exact(Value::Float(9.0))));
+    }
+
+    #[test]
+    fn test_columnar_tables_answer_like_row_tables() {
+        use crate::db::executor::explain::PlanNode;
+        use crate::db::executor::{Executor, QueryScope, ResultSet};
+        use crate::db::planner::LogicalPlan;
+        use crate::db::sql::execute_sql;
+        use crate::db::sql::parser::SqlParser;
+        use crate::db::storage::{StorageEngine, STORAGE};
+
+        // Runs `sql` with the workers and vectorization given, returning the
+        // result and the plan as it stood after execution
+        let run = |sql: &str, workers: usize, vectorize: bool| -> (ResultSet, Vec<String>) {
+            let stmt = SqlParser::parse_statement(sql).unwrap();
+            let plan = LogicalPlan::from_statement(&stmt).unwrap();
+            let mut op = Executor::lower_plan_with(&plan, &QueryScope::default(), workers, vectorize).unwrap();
+            let mut rows = Vec::new();
+            while let Some(row) = op.next().unwrap() {
+                rows.push(row);
+            }
+            let lines = PlanNode::from_operator(op.as_ref()).to_lines();
+            let columns = op.schema().iter().map(|(_, c)| c.clone()).collect();
+            (ResultSet { columns, rows }, lines)
+        };
+
+        for (table, with) in [("columnar_events", " WITH (format = 'columnar')"), ("columnar_events_rows", "")] {
+            execute_sql(&format!("DROP TABLE IF EXISTS {}", table));
+            execute_sql(&format!("CREATE TABLE {} (id INT, kind TEXT, score FLOAT){}", table, with));
+            let values: Vec<String> = (0..3000)
+                .map(|i| match i % 10 {
+                    0 => format!("({}, NULL, NULL)", i),
+                    _ => format!("({}, 'k{}', {}.5)", i, i % 4, i),
+                })
+                .collect();
+            execute_sql(&format!("INSERT INTO {} VALUES {}", table, values.join(", ")));
+        }
+        assert_eq!(STORAGE.table_format("columnar_events"), Ok(TableFormat::Columnar));
+        assert_eq!(STORAGE.table_format("columnar_events_rows"), Ok(TableFormat::Row));
+
+        let queries = [
+            "SELECT * FROM {} e WHERE e.id >= 2500 ORDER BY id",
+            "SELECT kind, COUNT(*), SUM(score), MIN(id) FROM {} WHERE id < 900 AND score > 10 GROUP BY kind ORDER BY kind",
+            "SELECT COUNT(*) FROM {} WHERE score BETWEEN 100 AND 120.5",
+            "SELECT id FROM {} WHERE kind = 'k2' AND id > 2990",
+        ];
+        for query in queries {
+            for (workers, vectorize) in [(1, false), (1, true), (3, true)] {
+                let (columnar, _) = run(&query.replace("{}", "columnar_events"), workers, vectorize);
+                let (rows, _) = run(&query.replace("{}", "columnar_events_rows"), workers, vectorize);
+                assert_eq!(columnar, rows, "{} (workers={}, vectorize={})", query, workers, vectorize);
+            }
+        }
+
+        // Only the last of the three chunks can hold id >= 2500
+        let (_, lines) = run("SELECT id FROM columnar_events WHERE id >= 2500", 1, false);
+        assert!(lines.iter().any(|l| l.contains("chunks skipped=2")), "{:?}", lines);
+
+        // Writes other than appends re-encode the table
+        for table in ["columnar_events", "columnar_events_rows"] {
+            execute_sql(&format!("DELETE FROM {} WHERE id % 3 = 0", table));
+            execute_sql(&format!("UPDATE {} SET kind = 'late' WHERE id > 2900", table));
+        }
+        let query = "SELECT kind, COUNT(*), MAX(id) FROM {} GROUP BY kind ORDER BY kind";
+        let (columnar, _) = run(&query.replace("{}", "columnar_events"), 1, true);
+        let (rows, _) = run(&query.replace("{}", "columnar_events_rows"), 1, true);
+        assert_eq!(columnar, rows);
+    }
+}
@@ -0,0 +1,388 @@
+// Storage engine interface - everything the executor asks of a table store
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::changes::{self, Change, ChangeRecorder, Recording};
+use super::{KeyRange, Row, ScanBatch, ScanFilter, Value, STORAGE};
+use crate::db::catalog::{TableFormat, TableSchema};
+
+/// A store of table rows. Each engine keeps its tables its own way; the
+/// executor only goes through these methods, so engines (and test doubles)
+/// can be swapped without it noticing.
+pub trait StorageEngine: Send + Sync {
+    /// Set up storage for a table the catalog has just created
+    fn create_table(&self, schema: &TableSchema) -> Result<(), String>;
+
+    /// Remove a table and all of its rows
+    fn drop_table(&self, table: &str) -> Result<(), String>;
+
+    /// Whether the engine holds storage for `table`
+    fn has_table(&self, table: &str) -> bool;
+
+    /// The table as it is now. Writes that commit later leave it alone, so
+    /// a scan reading it in batches sees every row exactly once.
+    fn snapshot(&self, table: &str) -> Result<Arc<dyn TableSnapshot>, String>;
+
+    /// Copy up to `limit` whole rows starting at row `offset`
+    fn scan(&self, table: &str, offset: usize, limit: usize) -> Result<Vec<Row>, String> {
+        self.snapshot(table)?.scan(offset, limit, &ScanFilter::default()).map(|batch| batch.rows)
+    }
+
+    /// The rows matching `predicate`, cut down to `columns` (all for none or `*`)
+    fn select(&self, table: &str, columns: &[String], predicate: &dyn Fn(&Row) -> bool) -> Result<Vec<Row>, String>;
+
+    /// The rows whose `column` value falls inside `range`
+    fn index_scan(&self, table: &str, column: &str, range: &KeyRange) -> Result<Vec<Row>, String>;
+
+    /// Number of rows in a table (0 if it has none yet)
+    fn row_count(&self, table: &str) -> Result<usize, String>;
+
+    /// Layout the table's rows are stored in
+    fn table_format(&self, _table: &str) -> Result<TableFormat, String> {
+        Ok(TableFormat::Row)
+    }
+
+    /// Whether writes through this engine are published to the change
+    /// listeners (live queries, the change log). Only the process-wide
+    /// storage publishes; an embedded database is nobody else's to watch.
+    fn publishes(&self) -> bool {
+        false
+    }
+
+    /// Begin a write to `table` that keeps its rows unique on each column
+    /// set in `keys` (NULLs never collide): a write that would leave two
+    /// rows with the same values fails. No other write to the table
+    /// interleaves with it until it commits or rolls back.
+    fn begin(&self, table: &str, keys: &[Vec<String>]) -> Result<Box<dyn TableWrite + '_>, String>;
+
+    /// Add rows to a table, all of them or (on error) none
+    fn insert(&self, table: &str, rows: Vec<Row>) -> Result<usize, String> {
+        if rows.is_empty() {
+            return Ok(0);
+        }
+        let mut write = self.begin(table, &[])?;
+        let count = write.insert(rows)?;
+        write.commit()?;
+        Ok(count)
+    }
+
+    /// Set `updates` on the rows matching `predicate`, returning how many
+    fn update(
+        &self,
+        table: &str,
+        updates: &HashMap<String, Value>,
+        predicate: &dyn Fn(&Row) -> bool,
+    ) -> Result<usize, String> {
+        let mut write = self.begin(table, &[])?;
+        let updated = write.update(&Selection::matching(predicate), &mut |row| {
+            let mut row = row.clone();
+            row.extend(updates.iter().map(|(c, v)| (c.clone(), v.clone())));
+            Ok(row)
+        })?;
+        write.commit()?;
+        Ok(updated.len())
+    }
+
+    /// Delete the rows matching `predicate`, returning how many
+    fn delete(&self, table: &str, predicate: &dyn Fn(&Row) -> bool) -> Result<usize, String> {
+        let mut write = self.begin(table, &[])?;
+        let deleted = write.delete(&Selection::matching(predicate))?;
+        write.commit()?;
+        Ok(deleted.len())
+    }
+}
+
+/// A table's rows at one point in time, numbered by position
+pub trait TableSnapshot: Send + Sync {
+    /// Read up to `limit` row positions starting at `offset`. Engines may
+    /// use `filter` to read less (see `ScanBatch::scanned`); callers still
+    /// evaluate their own predicate.
+    fn scan(&self, offset: usize, limit: usize, filter: &ScanFilter) -> Result<ScanBatch, String>;
+}
+
+/// The rows a write applies to: those `predicate` accepts. When the
+/// predicate confines a column to a range, `key` says so, and engines that
+/// keep that column indexed or in key order visit only the rows inside it.
+pub struct Selection<'a> {
+    pub predicate: &'a dyn Fn(&Row) -> bool,
+    pub key: Option<(String, KeyRange)>,
+}
+
+impl<'a> Selection<'a> {
+    pub fn matching(predicate: &'a dyn Fn(&Row) -> bool) -> Self {
+        Self { predicate, key: None }
+    }
+
+    /// Whether `row` is selected
+    pub fn accepts(&self, row: &Row) -> bool {
+        let in_range = match &self.key {
+            Some((column, range)) => row.get(column).is_some_and(|value| range.contains(value)),
+            None => true,
+        };
+        in_range && (self.predicate)(row)
+    }
+}
+
+/// Given a stored row and the upserted row that collides with it, what
+/// the stored row is to be replaced with, if anything
+pub type Resolve<'a> = dyn FnMut(&Row, &Row) -> Result<Option<Row>, String> + 'a;
+
+/// One write to a table, from `StorageEngine::begin` until `commit`. Its
+/// changes are seen by its own reads, kept only if it commits, and rolled
+/// back if it is dropped uncommitted.
+pub trait TableWrite {
+    /// Add rows at the end of the table, returning how many. A row that
+    /// collides with another on one of the write's keys fails the write.
+    fn insert(&mut self, rows: Vec<Row>) -> Result<usize, String>;
+
+    /// Replace every selected row with what `f` makes of it, returning the
+    /// old and new image of each. Each new image is checked against the
+    /// write's keys as it is stored.
+    fn update(
+        &mut self,
+        selection: &Selection,
+        f: &mut dyn FnMut(&Row) -> Result<Row, String>,
+    ) -> Result<Vec<(Row, Row)>, String>;
+
+    /// Delete the selected rows, returning them
+    fn delete(&mut self, selection: &Selection) -> Result<Vec<Row>, String>;
+
+    /// Insert `rows`, except those that collide with a stored row on one of
+    /// the column sets in `arbiters`. Those are handed to `resolve` along
+    /// with the row they hit, which may return the hit row's replacement.
+    /// A row is replaced at most once per write; a second collision with
+    /// it fails the write, as does a collision on any other of the write's
+    /// keys.
+    fn upsert(
+        &mut self,
+        arbiters: &[Vec<String>],
+        rows: Vec<Row>,
+        resolve: &mut Resolve,
+    ) -> Result<Vec<Change>, String>;
+
+    /// Keep the rest of the write unique on `columns` as well, failing if
+    /// two stored rows already collide on them
+    fn add_key(&mut self, columns: &[String]) -> Result<(), String>;
+
+    /// Persist the write and make it visible
+    fn commit(self: Box<Self>) -> Result<(), String>;
+
+    /// Undo whatever the write changed
+    fn rollback(self: Box<Self>);
+}
+
+impl dyn StorageEngine + '_ {
+    /// Run `f` as one write to `table`, unique on `keys`: committed if it
+    /// succeeds, rolled back if it fails
+    pub fn write<T, F>(&self, table: &str, keys: &[Vec<String>], f: F) -> Result<T, String>
+    where
+        F: FnOnce(&mut dyn TableWrite) -> Result<T, String>,
+    {
+        let mut write = self.begin(table, keys)?;
+        match f(write.as_mut()) {
+            Ok(result) => {
+                write.commit()?;
+                Ok(result)
+            }
+            Err(e) => {
+                write.rollback();
+                Err(e)
+            }
+        }
+    }
+
+    /// Recording for a write to `table`, kept only if the engine publishes
+    fn recording(&self, table: &str) -> Recording {
+        match self.publishes() {
+            true => changes::record(table),
+            false => changes::unrecorded(table),
+        }
+    }
+
+    /// `write` for a write that reports each row it changes to the
+    /// recorder. The changes go to whoever watches the table before the
+    /// write commits, so none of them can refuse a committed write, and
+    /// are published once it has.
+    pub fn write_recorded<T, F>(&self, table: &str, keys: &[Vec<String>], f: F) -> Result<T, String>
+    where
+        F: FnOnce(&mut dyn TableWrite, &mut ChangeRecorder) -> Result<T, String>,
+    {
+        let mut recording = self.recording(table);
+        let mut write = self.begin(table, keys)?;
+        let result = match f(write.as_mut(), &mut recording.recorder).and_then(|result| {
+            recording.prepare()?;
+            Ok(result)
+        }) {
+            Ok(result) => result,
+            Err(e) => {
+                write.rollback();
+                return Err(e);
+            }
+        };
+        if let Err(e) = write.commit() {
+            recording.abort();
+            return Err(e);
+        }
+        recording.publish()?;
+        Ok(result)
+    }
+
+    /// `insert` unique on `keys`, publishing the new rows once they are
+    /// stored
+    pub fn insert_recorded(&self, table: &str, keys: &[Vec<String>], rows: Vec<Row>) -> Result<usize, String> {
+        if rows.is_empty() {
+            return Ok(0);
+        }
+        self.write_recorded(table, keys, |write, recorder| {
+            if recorder.enabled() {
+                rows.iter().for_each(|row| recorder.insert(row));
+            }
+            write.insert(rows)
+        })
+    }
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<Arc<dyn StorageEngine>>> = const { RefCell::new(None) };
+}
+
+/// The engine statements on this thread run against: the one installed by
+/// `with_engine`, or else the global `STORAGE`
+pub fn current() -> Arc<dyn StorageEngine> {
+    CURRENT
+        .with(|current| current.borrow().clone())
+        .unwrap_or_else(|| STORAGE.clone() as Arc<dyn StorageEngine>)
+}
+
+/// Run `f` with `engine` as this thread's storage. Threads `f` starts do
+/// not inherit it; code that fans out passes `current()` along itself.
+pub fn with_engine<T>(engine: Arc<dyn StorageEngine>, f: impl FnOnce() -> T) -> T {
+    struct Restore(Option<Arc<dyn StorageEngine>>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let previous = self.0.take();
+            CURRENT.with(|current| *current.borrow_mut() = previous);
+        }
+    }
+
+    let _restore = Restore(CURRENT.with(|current| current.borrow_mut().replace(engine)));
+    f()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::executor::ExecutionResult;
+    use crate::db::sql::execute_sql;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Passes everything through to `STORAGE`, counting the scans
+    struct CountingEngine {
+        scans: AtomicUsize,
+    }
+
+    impl StorageEngine for CountingEngine {
+        fn create_table(&self, schema: &TableSchema) -> Result<(), String> {
+            STORAGE.create_table(schema)
+        }
+        fn drop_table(&self, table: &str) -> Result<(), String> {
+            STORAGE.drop_table(table)
+        }
+        fn has_table(&self, table: &str) -> bool {
+            STORAGE.has_table(table)
+        }
+        fn snapshot(&self, table: &str) -> Result<Arc<dyn TableSnapshot>, String> {
+            self.scans.fetch_add(1, Ordering::Relaxed);
+            STORAGE.snapshot(table)
+        }
+        fn select(&self, table: &str, columns: &[String], predicate: &dyn Fn(&Row) -> bool) -> Result<Vec<Row>, String> {
+            STORAGE.select(table, columns, predicate)
+        }
+        fn index_scan(&self, table: &str, column: &str, range: &KeyRange) -> Result<Vec<Row>, String> {
+            STORAGE.index_scan(table, column, range)
+        }
+        fn row_count(&self, table: &str) -> Result<usize, String> {
+            STORAGE.row_count(table)
+        }
+        fn begin(&self, table: &str, keys: &[Vec<String>]) -> Result<Box<dyn TableWrite + '_>, String> {
+            STORAGE.begin(table, keys)
+        }
+    }
+
+    /// Refuses every write to one table
+    struct Refusing;
+
+    impl changes::ChangeListener for Refusing {
+        fn watches(&self, table: &str) -> bool {
+            table == "engine_refused"
+        }
+        fn prepare(&self, _commit: &changes::Commit) -> Result<(), String> {
+            Err("change log is full".to_string())
+        }
+        fn committed(&self, _commit: &changes::Commit) {
+            panic!("a refused write committed");
+        }
+    }
+
+    #[test]
+    fn test_refused_changes_fail_the_write() {
+        execute_sql("DROP TABLE IF EXISTS engine_refused");
+        execute_sql("CREATE TABLE engine_refused (id INT)");
+        changes::listen(Arc::new(Refusing));
+
+        match execute_sql("INSERT INTO engine_refused VALUES (1), (2)") {
+            ExecutionResult::Error { message } => assert_eq!(message, "change log is full"),
+            other => panic!("Expected an error, got {:?}", other),
+        }
+        assert_eq!(STORAGE.row_count("engine_refused").unwrap(), 0);
+        execute_sql("DROP TABLE engine_refused");
+    }
+
+    /// Counts the commits to one table
+    struct Counting(AtomicUsize);
+
+    impl changes::ChangeListener for Counting {
+        fn watches(&self, table: &str) -> bool {
+            table == "engine_published"
+        }
+        fn committed(&self, _commit: &changes::Commit) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_only_publishing_storage_reaches_listeners() {
+        let counting = Arc::new(Counting(AtomicUsize::new(0)));
+        changes::listen(counting.clone());
+
+        let db = crate::db::database::Database::in_memory();
+        db.execute("CREATE TABLE engine_published (id INT)").unwrap();
+        db.execute("INSERT INTO engine_published VALUES (1)").unwrap();
+        assert_eq!(counting.0.load(Ordering::Relaxed), 0);
+
+        execute_sql("DROP TABLE IF EXISTS engine_published");
+        execute_sql("CREATE TABLE engine_published (id INT)");
+        execute_sql("INSERT INTO engine_published VALUES (1)");
+        assert_eq!(counting.0.load(Ordering::Relaxed), 1);
+        execute_sql("DROP TABLE engine_published");
+    }
+
+    #[test]
+    fn test_statements_run_against_the_installed_engine() {
+        execute_sql("DROP TABLE IF EXISTS engine_double");
+        execute_sql("CREATE TABLE engine_double (id INT)");
+        execute_sql("INSERT INTO engine_double VALUES (1), (2), (3)");
+
+        let counting = Arc::new(CountingEngine { scans: AtomicUsize::new(0) });
+        let result = with_engine(counting.clone(), || execute_sql("SELECT COUNT(*) FROM engine_double"));
+        assert!(matches!(result, ExecutionResult::Rows { .. }), "{:?}", result);
+        let scans = counting.scans.load(Ordering::Relaxed);
+        assert!(scans > 0);
+
+        // Outside `with_engine` the global storage is used again
+        execute_sql("SELECT COUNT(*) FROM engine_double");
+        assert_eq!(counting.scans.load(Ordering::Relaxed), scans);
+        execute_sql("DROP TABLE engine_double");
+    }
+}
@@ -0,0 +1,69 @@
+// Append-only files of one JSON value per line, as kept by the write-ahead
+// logs: replayed when opened, appended to one whole line at a time
+use serde::de::DeserializeOwned;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+
+/// Open the log at `path` for appending, creating it if missing, with the
+/// values it holds. A line that is unfinished or does not parse is a write
+/// a crash cut short: it and anything after it are cut off, so later
+/// appends don't land behind a line the next replay would stop at.
+pub fn open<T: DeserializeOwned>(path: &Path) -> Result<(File, Vec<T>), String> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(format!("{}: {}", path.display(), e)),
+    };
+    let mut values = Vec::new();
+    let mut complete = 0;
+    for line in bytes.split_inclusive(|byte| *byte == b'\n') {
+        let Some(Ok(value)) = line.strip_suffix(b"\n").map(serde_json::from_slice) else {
+            break;
+        };
+        values.push(value);
+        complete += line.len();
+    }
+
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    if complete < bytes.len() {
+        file.set_len(complete as u64).map_err(|e| e.to_string())?;
+        file.sync_data().map_err(|e| e.to_string())?;
+    }
+    Ok((file, values))
+}
+
+/// Append `line`, which ends in a newline. If the write fails, whatever
+/// part of it reached the file is cut off again.
+pub fn append(file: &mut File, line: &[u8]) -> Result<(), String> {
+    let len = file.metadata().map_err(|e| e.to_string())?.len();
+    if let Err(e) = file.write_all(line) {
+        let _ = file.set_len(len);
+        return Err(e.to_string());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_torn_tail_is_cut_off() {
+        let path = std::env::temp_dir().join(format!("butterfly_log_file_{}", std::process::id()));
+        fs::write(&path, "[1]\n[2]\n[3").unwrap();
+
+        let (mut file, values) = open::<Vec<i64>>(&path).unwrap();
+        assert_eq!(values, vec![vec![1], vec![2]]);
+        append(&mut file, b"[4]\n").unwrap();
+        drop(file);
+
+        let (_, values) = open::<Vec<i64>>(&path).unwrap();
+        assert_eq!(values, vec![vec![1], vec![2], vec![4]]);
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,87 @@
+// Bloom filters - a compact "definitely absent" test for the keys of an SSTable
+
+/// Bits per key; with `HASHES` probes this gives about 1% false positives
+const BITS_PER_KEY: usize = 10;
+const HASHES: u32 = 7;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BloomFilter {
+    bits: Vec<u8>,
+    hashes: u32,
+}
+
+impl BloomFilter {
+    /// An empty filter sized for `keys` keys
+    pub fn new(keys: usize) -> Self {
+        Self {
+            bits: vec![0; (keys * BITS_PER_KEY).div_ceil(8).max(8)],
+            hashes: HASHES,
+        }
+    }
+
+    fn probes(&self, key: &[u8]) -> impl Iterator<Item = usize> {
+        // Double hashing: probe i is h1 + i * h2
+        let h1 = fnv1a(key, 0xcbf29ce484222325);
+        let h2 = fnv1a(key, 0x84222325cbf29ce4) | 1;
+        let bits = self.bits.len() as u64 * 8;
+        (0..u64::from(self.hashes)).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % bits) as usize)
+    }
+
+    pub fn insert(&mut self, key: &[u8]) {
+        for bit in self.probes(key).collect::<Vec<_>>() {
+            self.bits[bit / 8] |= 1 << (bit % 8);
+        }
+    }
+
+    /// False only if `key` was never inserted
+    pub fn may_contain(&self, key: &[u8]) -> bool {
+        self.probes(key).all(|bit| self.bits[bit / 8] & (1 << (bit % 8)) != 0)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.hashes.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&self.bits);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() < 5 {
+            return Err("Bloom filter is truncated".to_string());
+        }
+        Ok(Self {
+            hashes: u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            bits: bytes[4..].to_vec(),
+        })
+    }
+}
+
+/// FNV-1a, which unlike `DefaultHasher` is fixed across builds, so filters
+/// written to disk stay valid
+pub(crate) fn fnv1a(bytes: &[u8], seed: u64) -> u64 {
+    let mut hash = seed;
+    for &byte in bytes {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bloom_filter_has_no_false_negatives() {
+        let mut filter = BloomFilter::new(1000);
+        for i in 0..1000 {
+            filter.insert(format!("key-{}", i).as_bytes());
+        }
+        let filter = BloomFilter::from_bytes(&filter.to_bytes()).unwrap();
+        assert!((0..1000).all(|i| filter.may_contain(format!("key-{}", i).as_bytes())));
+
+        let false_positives = (0..1000)
+            .filter(|i| filter.may_contain(format!("other-{}", i).as_bytes()))
+            .count();
+        assert!(false_positives < 50, "{} false positives", false_positives);
+    }
+}
@@ -0,0 +1,1316 @@
+// Log-structured merge tree storage for tables created WITH (engine = 'lsm')
+//
+// Writes go to a write-ahead log and a skip-list memtable. A full memtable is
+// flushed to an immutable sorted table (SSTable) on disk, and a background
+// thread merges runs of similarly sized SSTables (size-tiered compaction).
+// Reads merge the memtable with every SSTable, newest version winning.
+pub mod bloom;
+pub mod sstable;
+
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::{self, File};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering as AtomicOrdering};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use super::changes::Change;
+use super::log_file;
+use super::engine::{Resolve, Selection, StorageEngine, TableSnapshot, TableWrite};
+use super::{duplicate_key, select_columns, unique_key, IndexKey, KeyRange, Row, ScanBatch, ScanFilter, Value};
+use crate::db::catalog::TableSchema;
+use crate::DS::skip_list::SkipList;
+use sstable::SsTable;
+
+/// Entries the memtable takes before it is flushed to an SSTable
+pub const MEMTABLE_LIMIT: usize = 4096;
+/// Fewest SSTables one compaction merges
+const MIN_MERGE: usize = 4;
+/// Tables merged together are at most this many times apart in size
+const SIZE_RATIO: u64 = 2;
+/// Scan positions a snapshot remembers, so batched scans resume without
+/// re-reading
+const SCAN_HINTS: usize = 16;
+
+const MANIFEST: &str = "manifest.json";
+const WAL: &str = "wal.log";
+
+/// Key of one stored row version: the row's primary key values (NULL where
+/// missing, empty without a primary key) and a sequence number. A row with
+/// a whole primary key is keyed by it alone (sequence 0), so a write to
+/// it is a put over its previous version. Rows without one get a sequence
+/// number unique to the row: a table without a primary key keeps insertion
+/// order, and rows whose key has a NULL, which never collides, stay
+/// distinct.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LsmKey {
+    pub prefix: Vec<Value>,
+    pub seq: u64,
+}
+
+impl LsmKey {
+    /// Bytes the bloom filters hash a key prefix as. Numbers are hashed as
+    /// floats, since integers and floats compare equal by value.
+    pub fn prefix_bytes(prefix: &[Value]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for value in prefix {
+            match value {
+                Value::Null => bytes.push(0),
+                Value::Boolean(b) => bytes.extend_from_slice(&[1, *b as u8]),
+                Value::Integer(i) => {
+                    bytes.push(2);
+                    bytes.extend_from_slice(&(*i as f64).to_bits().to_le_bytes());
+                }
+                Value::Float(f) => {
+                    bytes.push(2);
+                    bytes.extend_from_slice(&f.to_bits().to_le_bytes());
+                }
+                Value::Text(s) => {
+                    bytes.push(3);
+                    bytes.extend_from_slice(&(s.len() as u32).to_le_bytes());
+                    bytes.extend_from_slice(s.as_bytes());
+                }
+            }
+        }
+        bytes
+    }
+
+    /// The smallest key above this one
+    fn successor(&self) -> LsmKey {
+        LsmKey {
+            prefix: self.prefix.clone(),
+            seq: self.seq + 1,
+        }
+    }
+}
+
+fn cmp_prefix(a: &[Value], b: &[Value]) -> Ordering {
+    a.iter()
+        .zip(b)
+        .map(|(x, y)| x.total_cmp(y))
+        .find(|order| *order != Ordering::Equal)
+        .unwrap_or_else(|| a.len().cmp(&b.len()))
+}
+
+impl PartialEq for LsmKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for LsmKey {}
+
+impl PartialOrd for LsmKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for LsmKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        cmp_prefix(&self.prefix, &other.prefix).then(self.seq.cmp(&other.seq))
+    }
+}
+
+/// A row version, or a tombstone recording that the row was deleted
+pub type Entry = (LsmKey, Option<Row>);
+
+/// Which SSTables make up the tree, newest first
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    key_columns: Vec<String>,
+    tables: Vec<String>,
+    /// Sequence numbers below this are used, by flushed rows at least
+    next_seq: u64,
+}
+
+/// The on-disk part of a tree, shared with the compaction thread
+struct TreeFiles {
+    dir: PathBuf,
+    key_columns: Vec<String>,
+    /// Newest first
+    tables: RwLock<Vec<Arc<SsTable>>>,
+    next_file: AtomicU64,
+    flushed_seq: AtomicU64,
+    compacting: AtomicBool,
+    dropped: AtomicBool,
+    /// Serializes manifest rewrites
+    manifest: Mutex<()>,
+}
+
+struct LsmState {
+    memtable: SkipList<LsmKey, Option<Row>>,
+    wal: File,
+    next_seq: u64,
+    /// Rows currently in the table
+    live: usize,
+}
+
+pub struct LsmTree {
+    files: Arc<TreeFiles>,
+    state: RwLock<LsmState>,
+    memtable_limit: usize,
+    /// Whether a write is in progress; writes take turns
+    writing: Mutex<bool>,
+    write_done: Condvar,
+}
+
+impl LsmTree {
+    /// Whether a tree has been created in `dir`
+    pub fn exists(dir: &Path) -> bool {
+        dir.join(MANIFEST).exists()
+    }
+
+    /// Open the tree in `dir`, creating it keyed on `key_columns` if there
+    /// is none (an existing tree keeps the key it was created with)
+    pub fn open(dir: &Path, key_columns: Vec<String>) -> Result<LsmTree, String> {
+        Self::open_with_limit(dir, key_columns, MEMTABLE_LIMIT)
+    }
+
+    pub fn open_with_limit(dir: &Path, key_columns: Vec<String>, memtable_limit: usize) -> Result<LsmTree, String> {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        let manifest = match fs::read_to_string(dir.join(MANIFEST)) {
+            Ok(content) => serde_json::from_str::<Manifest>(&content).map_err(|e| e.to_string())?,
+            Err(_) => Manifest {
+                key_columns,
+                tables: Vec::new(),
+                next_seq: 0,
+            },
+        };
+
+        // Tables a crash left behind before they made it into the manifest,
+        // or after compaction took them out of it
+        for entry in fs::read_dir(dir).map_err(|e| e.to_string())?.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.ends_with(".tmp") || (name.ends_with(".sst") && !manifest.tables.contains(&name)) {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+
+        let mut tables = Vec::new();
+        let mut next_file = 0;
+        for name in &manifest.tables {
+            tables.push(Arc::new(SsTable::open(&dir.join(name))?));
+            if let Some(number) = name.strip_suffix(".sst").and_then(|n| n.parse::<u64>().ok()) {
+                next_file = next_file.max(number + 1);
+            }
+        }
+
+        let files = Arc::new(TreeFiles {
+            dir: dir.to_path_buf(),
+            key_columns: manifest.key_columns,
+            tables: RwLock::new(tables),
+            next_file: AtomicU64::new(next_file),
+            flushed_seq: AtomicU64::new(manifest.next_seq),
+            compacting: AtomicBool::new(false),
+            dropped: AtomicBool::new(false),
+            manifest: Mutex::new(()),
+        });
+        files.write_manifest()?;
+
+        // Replay writes that were not flushed yet
+        let mut memtable = SkipList::new();
+        let mut next_seq = manifest.next_seq;
+        let (wal, batches) = log_file::open::<Vec<Entry>>(&dir.join(WAL))?;
+        for (key, row) in batches.into_iter().flatten() {
+            next_seq = next_seq.max(key.seq + 1);
+            memtable.insert(key, row);
+        }
+
+        let mut state = LsmState {
+            memtable,
+            wal,
+            next_seq,
+            live: 0,
+        };
+        let tables = files.snapshot()?;
+        let mut live = 0;
+        for entry in merge(&state, None, tables, None, false) {
+            entry?;
+            live += 1;
+        }
+        state.live = live;
+
+        let tree = LsmTree {
+            files,
+            state: RwLock::new(state),
+            memtable_limit: memtable_limit.max(1),
+            writing: Mutex::new(false),
+            write_done: Condvar::new(),
+        };
+        TreeFiles::maybe_compact(&tree.files);
+        Ok(tree)
+    }
+
+    /// Primary key values of `row`
+    fn prefix(&self, row: &Row) -> Vec<Value> {
+        self.files
+            .key_columns
+            .iter()
+            .map(|c| row.get(c).cloned().unwrap_or(Value::Null))
+            .collect()
+    }
+
+    /// Key `row` is stored under (see `LsmKey`)
+    fn new_key(&self, row: &Row, next_seq: &mut u64) -> LsmKey {
+        let prefix = self.prefix(row);
+        if !prefix.is_empty() && !prefix.contains(&Value::Null) {
+            return LsmKey { prefix, seq: 0 };
+        }
+        let key = LsmKey { prefix, seq: *next_seq };
+        *next_seq += 1;
+        key
+    }
+
+    /// Log `batch` and apply it to the memtable, flushing it if it is full
+    fn write_batch(&self, state: &mut LsmState, batch: Vec<Entry>) -> Result<(), String> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let mut line = serde_json::to_string(&batch).map_err(|e| e.to_string())?;
+        line.push('\n');
+        log_file::append(&mut state.wal, line.as_bytes())?;
+        state.wal.sync_data().map_err(|e| e.to_string())?;
+
+        for (key, row) in batch {
+            state.memtable.insert(key, row);
+        }
+        if state.memtable.len() >= self.memtable_limit {
+            self.flush_locked(state)?;
+        }
+        Ok(())
+    }
+
+    /// Write the memtable out as a new SSTable and start a fresh log
+    fn flush_locked(&self, state: &mut LsmState) -> Result<(), String> {
+        if state.memtable.is_empty() {
+            return Ok(());
+        }
+        let entries: Vec<Entry> = state.memtable.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+        let table = SsTable::write(&self.files.new_table_path(), entries)?;
+        self.files
+            .tables
+            .write()
+            .map_err(|e| e.to_string())?
+            .insert(0, Arc::new(table));
+        self.files.flushed_seq.store(state.next_seq, AtomicOrdering::Release);
+        self.files.write_manifest()?;
+
+        state.wal.set_len(0).map_err(|e| e.to_string())?;
+        state.memtable = SkipList::new();
+        TreeFiles::maybe_compact(&self.files);
+        Ok(())
+    }
+
+    /// Flush the memtable to disk
+    pub fn flush(&self) -> Result<(), String> {
+        let mut state = self.state.write().map_err(|e| e.to_string())?;
+        self.flush_locked(&mut state)
+    }
+
+    /// Begin a write unique on `keys` (the primary key always is), once
+    /// the one in progress (if any) is done
+    pub fn begin(self: &Arc<Self>, keys: &[Vec<String>]) -> Result<Box<LsmWrite>, String> {
+        let mut writing = self.writing.lock().map_err(|e| e.to_string())?;
+        while *writing {
+            writing = self.write_done.wait(writing).map_err(|e| e.to_string())?;
+        }
+        *writing = true;
+        drop(writing);
+
+        let next_seq = self.state.read().map_err(|e| e.to_string())?.next_seq;
+        Ok(Box::new(LsmWrite {
+            tree: Arc::clone(self),
+            batch: BTreeMap::new(),
+            next_seq,
+            added: 0,
+            unique: keys.iter().filter(|key| **key != self.files.key_columns).cloned().collect(),
+            holders: None,
+        }))
+    }
+
+    /// Add rows to the table
+    pub fn append(self: &Arc<Self>, rows: Vec<Row>) -> Result<usize, String> {
+        let mut write = self.begin(&[])?;
+        let count = write.insert(rows)?;
+        write.commit()?;
+        Ok(count)
+    }
+
+    /// Number of rows in the table
+    pub fn len(&self) -> usize {
+        self.state.read().map(|state| state.live).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Every row, in key order
+    pub fn rows(&self) -> Result<Vec<Row>, String> {
+        let state = self.state.read().map_err(|e| e.to_string())?;
+        let tables = self.files.snapshot()?;
+        merge(&state, None, tables, None, false)
+            .map(|entry| entry.map(|(_, row)| row.unwrap_or_default()))
+            .collect()
+    }
+
+    /// The tree as it is now: a copy of the memtable, and the SSTables
+    /// beneath it, which stay on disk while the snapshot holds them
+    pub fn snapshot(&self) -> Result<LsmSnapshot, String> {
+        let state = self.state.read().map_err(|e| e.to_string())?;
+        Ok(LsmSnapshot {
+            memtable: state.memtable.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+            tables: self.files.snapshot()?,
+            hints: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Rows whose `column` value falls inside `range`. On a single-column
+    /// primary key this reads only that stretch of the tree, and a lookup of
+    /// one key skips the SSTables whose bloom filters rule it out.
+    pub fn index_scan(&self, column: &str, range: &KeyRange) -> Result<Vec<Row>, String> {
+        if range.is_empty() {
+            return Ok(Vec::new());
+        }
+        let state = self.state.read().map_err(|e| e.to_string())?;
+        let mut tables = self.files.snapshot()?;
+
+        if self.files.key_columns != [column] {
+            let mut rows = Vec::new();
+            for entry in merge(&state, None, tables, None, false) {
+                let row = entry?.1.unwrap_or_default();
+                if row.get(column).is_some_and(|v| range.contains(v)) {
+                    rows.push(row);
+                }
+            }
+            return Ok(rows);
+        }
+
+        if let (Bound::Included(lower), Bound::Included(upper)) = (&range.lower, &range.upper) {
+            if lower.total_cmp(upper) == Ordering::Equal {
+                tables.retain(|table| table.may_contain_prefix(std::slice::from_ref(lower)));
+            }
+        }
+        let mut rows = Vec::new();
+        for entry in merge(&state, None, tables, range_start(range).as_ref(), false) {
+            let (key, row) = entry?;
+            let value = &key.prefix[0];
+            if beyond(range, value) {
+                break;
+            }
+            if range.contains(value) {
+                rows.push(row.unwrap_or_default());
+            }
+        }
+        Ok(rows)
+    }
+
+    /// Remove the tree's files for good
+    pub fn destroy(&self) -> Result<(), String> {
+        self.files.dropped.store(true, AtomicOrdering::Release);
+        self.wait_for_compaction();
+        for table in self.files.tables.write().map_err(|e| e.to_string())?.drain(..) {
+            table.mark_obsolete();
+        }
+        fs::remove_dir_all(&self.files.dir).map_err(|e| e.to_string())
+    }
+
+    /// Block until no compaction is running
+    pub fn wait_for_compaction(&self) {
+        while self.files.compacting.load(AtomicOrdering::Acquire) {
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+}
+
+/// A tree as it was at one point, for scans that read it a batch at a time
+pub struct LsmSnapshot {
+    memtable: BTreeMap<LsmKey, Option<Row>>,
+    tables: Vec<Arc<SsTable>>,
+    /// Positions earlier batches stopped at, with the last key they read
+    hints: Mutex<Vec<(usize, LsmKey)>>,
+}
+
+impl LsmSnapshot {
+    /// Up to `limit` rows from position `offset` in key order. A scan that
+    /// continues where the previous batch stopped seeks straight to it.
+    pub fn rows(&self, offset: usize, limit: usize) -> Result<Vec<Row>, String> {
+        if limit == 0 {
+            return Ok(Vec::new());
+        }
+        let resume = self
+            .hints
+            .lock()
+            .map_err(|e| e.to_string())?
+            .iter()
+            .find(|(position, _)| *position == offset)
+            .map(|(_, last_key)| last_key.successor());
+        let mut skip = if resume.is_some() { 0 } else { offset };
+
+        let mut rows = Vec::new();
+        let mut last_key = None;
+        for entry in self.entries_from(resume.as_ref()) {
+            let (key, row) = entry?;
+            if skip > 0 {
+                skip -= 1;
+                continue;
+            }
+            rows.push(row.unwrap_or_default());
+            last_key = Some(key);
+            if rows.len() == limit {
+                break;
+            }
+        }
+
+        if let Some(last_key) = last_key {
+            let mut hints = self.hints.lock().map_err(|e| e.to_string())?;
+            hints.retain(|(position, _)| *position != offset);
+            if hints.len() >= SCAN_HINTS {
+                hints.remove(0);
+            }
+            hints.push((offset + rows.len(), last_key));
+        }
+        Ok(rows)
+    }
+
+    /// Live entries in key order from `start`, newest version winning
+    fn entries_from(&self, start: Option<&LsmKey>) -> MergeIter<'_> {
+        let from = start.map_or(Bound::Unbounded, |start| Bound::Included(start.clone()));
+        let memtable = self.memtable.range((from, Bound::Unbounded));
+        let mut sources: Vec<Source<'_>> = vec![Box::new(memtable.map(|(k, v)| Ok((k.clone(), v.clone()))))];
+        sources.extend(self.tables.iter().map(|table| Box::new(table.iter_from(start)) as Source<'_>));
+        MergeIter::new(sources, false)
+    }
+}
+
+/// Rows come in key order; the filter only projects them
+impl TableSnapshot for LsmSnapshot {
+    fn scan(&self, offset: usize, limit: usize, filter: &ScanFilter) -> Result<ScanBatch, String> {
+        let rows: Vec<Row> = self.rows(offset, limit)?.iter().map(|row| filter.project(row)).collect();
+        Ok(ScanBatch {
+            positions: (offset..offset + rows.len()).collect(),
+            scanned: rows.len(),
+            rows,
+            skipped_chunks: 0,
+        })
+    }
+}
+
+/// A write to a tree. Its entries wait in `batch`, where its own reads
+/// see them, and reach the log and memtable together when it commits.
+pub struct LsmWrite {
+    tree: Arc<LsmTree>,
+    batch: BTreeMap<LsmKey, Option<Row>>,
+    next_seq: u64,
+    /// Rows added less rows removed
+    added: isize,
+    /// Column sets besides the primary key that rows are unique on
+    unique: Vec<Vec<String>>,
+    /// For each of `unique`, the key of the row holding each value; built
+    /// in one pass over the tree when first needed
+    holders: Option<Vec<Holders>>,
+}
+
+type Holders = BTreeMap<Vec<IndexKey>, LsmKey>;
+
+impl LsmWrite {
+    /// The selected rows with their keys. A key range on a single-column
+    /// primary key reads only that stretch of the tree.
+    fn selected(&self, selection: &Selection) -> Result<Vec<(LsmKey, Row)>, String> {
+        let range = selection
+            .key
+            .as_ref()
+            .filter(|(column, _)| self.tree.files.key_columns == [column.as_str()])
+            .map(|(_, range)| range);
+        if range.is_some_and(KeyRange::is_empty) {
+            return Ok(Vec::new());
+        }
+
+        let state = self.tree.state.read().map_err(|e| e.to_string())?;
+        let tables = self.tree.files.snapshot()?;
+        let start = range.and_then(range_start);
+        let mut selected = Vec::new();
+        for entry in merge(&state, Some(&self.batch), tables, start.as_ref(), false) {
+            let (key, row) = entry?;
+            if range.is_some_and(|range| beyond(range, &key.prefix[0])) {
+                break;
+            }
+            let row = row.unwrap_or_default();
+            if selection.accepts(&row) {
+                selected.push((key, row));
+            }
+        }
+        Ok(selected)
+    }
+
+    /// The first row stored at or after `start` with its primary key
+    fn first_from(&self, start: &LsmKey) -> Result<Option<(LsmKey, Row)>, String> {
+        let state = self.tree.state.read().map_err(|e| e.to_string())?;
+        let mut tables = self.tree.files.snapshot()?;
+        tables.retain(|table| table.may_contain_prefix(&start.prefix));
+        let first = merge(&state, Some(&self.batch), tables, Some(start), false).next().transpose()?;
+        Ok(first
+            .filter(|(key, _)| cmp_prefix(&key.prefix, &start.prefix) == Ordering::Equal)
+            .map(|(key, row)| (key, row.unwrap_or_default())))
+    }
+
+    /// Where each value of the `unique` keys is held, read from the tree
+    fn scan_holders(&self) -> Result<Vec<Holders>, String> {
+        let mut holders = vec![Holders::new(); self.unique.len()];
+        let state = self.tree.state.read().map_err(|e| e.to_string())?;
+        for entry in merge(&state, Some(&self.batch), self.tree.files.snapshot()?, None, false) {
+            let (key, row) = entry?;
+            let row = row.unwrap_or_default();
+            for (columns, held) in self.unique.iter().zip(holders.iter_mut()) {
+                if let Some(value) = unique_key(&row, columns) {
+                    if held.insert(value, key.clone()).is_some() {
+                        return Err(duplicate_key(columns, &row));
+                    }
+                }
+            }
+        }
+        Ok(holders)
+    }
+
+    /// Record that the row stored under `key` holds `row`'s values in the
+    /// `unique` keys, instead of its `previous` image and key if it had
+    /// one. Fails if another row holds one of them.
+    fn claim(&mut self, previous: Option<(&LsmKey, &Row)>, key: &LsmKey, row: &Row) -> Result<(), String> {
+        let unchanged = previous.is_some_and(|(previous_key, old)| {
+            previous_key == key && self.unique.iter().all(|c| unique_key(old, c) == unique_key(row, c))
+        });
+        if self.unique.is_empty() || unchanged {
+            return Ok(());
+        }
+        if self.holders.is_none() {
+            self.holders = Some(self.scan_holders()?);
+        }
+        let Some(holders) = self.holders.as_mut() else {
+            return Ok(());
+        };
+
+        let previous_key = previous.map(|(previous_key, _)| previous_key);
+        for (columns, held) in self.unique.iter().zip(holders.iter()) {
+            let holder = unique_key(row, columns).and_then(|value| held.get(&value));
+            if holder.is_some_and(|holder| Some(holder) != previous_key) {
+                return Err(duplicate_key(columns, row));
+            }
+        }
+        for (columns, held) in self.unique.iter().zip(holders.iter_mut()) {
+            if let Some((previous_key, old)) = previous {
+                if let Some(value) = unique_key(old, columns).filter(|value| held.get(value) == Some(previous_key)) {
+                    held.remove(&value);
+                }
+            }
+            if let Some(value) = unique_key(row, columns) {
+                held.insert(value, key.clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// Let go of the values a row being removed held
+    fn release(&mut self, key: &LsmKey, row: &Row) {
+        let Some(holders) = self.holders.as_mut() else {
+            return;
+        };
+        for (columns, held) in self.unique.iter().zip(holders.iter_mut()) {
+            if let Some(value) = unique_key(row, columns).filter(|value| held.get(value) == Some(key)) {
+                held.remove(&value);
+            }
+        }
+    }
+
+    /// Key for a row about to be stored, failing if another row already
+    /// holds its primary key
+    fn free_key(&mut self, row: &Row) -> Result<LsmKey, String> {
+        let key = self.tree.new_key(row, &mut self.next_seq);
+        if key.seq == 0 && self.first_from(&key)?.is_some() {
+            return Err(duplicate_key(&self.tree.files.key_columns, row));
+        }
+        Ok(key)
+    }
+
+    /// Store a new row
+    fn add(&mut self, row: Row) -> Result<LsmKey, String> {
+        let key = self.free_key(&row)?;
+        self.claim(None, &key, &row)?;
+        self.batch.insert(key.clone(), Some(row));
+        self.added += 1;
+        Ok(key)
+    }
+
+    /// Store `row` in place of `old`, the row under `key`, moving it if its
+    /// primary key changed; returns the key it is stored under
+    fn put(&mut self, key: LsmKey, old: &Row, row: Row) -> Result<LsmKey, String> {
+        let moved = match cmp_prefix(&self.tree.prefix(&row), &key.prefix) {
+            Ordering::Equal => key.clone(),
+            _ => self.free_key(&row)?,
+        };
+        self.claim(Some((&key, old)), &moved, &row)?;
+        if moved != key {
+            self.batch.insert(key, None);
+        }
+        self.batch.insert(moved.clone(), Some(row));
+        Ok(moved)
+    }
+}
+
+impl TableWrite for LsmWrite {
+    /// A row whose primary key is taken fails the write
+    fn insert(&mut self, rows: Vec<Row>) -> Result<usize, String> {
+        let count = rows.len();
+        for row in rows {
+            self.add(row)?;
+        }
+        Ok(count)
+    }
+
+    fn update(
+        &mut self,
+        selection: &Selection,
+        f: &mut dyn FnMut(&Row) -> Result<Row, String>,
+    ) -> Result<Vec<(Row, Row)>, String> {
+        let mut updated = Vec::new();
+        for (key, old) in self.selected(selection)? {
+            let new = f(&old)?;
+            self.put(key, &old, new.clone())?;
+            updated.push((old, new));
+        }
+        Ok(updated)
+    }
+
+    fn delete(&mut self, selection: &Selection) -> Result<Vec<Row>, String> {
+        let mut deleted = Vec::new();
+        for (key, row) in self.selected(selection)? {
+            self.release(&key, &row);
+            self.batch.insert(key, None);
+            deleted.push(row);
+        }
+        self.added -= deleted.len() as isize;
+        Ok(deleted)
+    }
+
+    /// Collisions on the primary key are point lookups; other arbiters are
+    /// looked up among the holders of the unique keys
+    fn upsert(
+        &mut self,
+        arbiters: &[Vec<String>],
+        rows: Vec<Row>,
+        resolve: &mut Resolve,
+    ) -> Result<Vec<Change>, String> {
+        let primary = self.tree.files.key_columns.clone();
+        let by_primary = |columns: &Vec<String>| !primary.is_empty() && *columns == primary;
+        for arbiter in arbiters.iter().filter(|arbiter| !by_primary(arbiter)) {
+            if !self.unique.contains(arbiter) {
+                self.unique.push(arbiter.clone());
+                self.holders = None;
+            }
+        }
+
+        let mut touched = BTreeSet::new();
+        let mut changes = Vec::new();
+        for row in rows {
+            let mut hit = None;
+            for columns in arbiters {
+                let Some(value) = unique_key(&row, columns) else {
+                    continue;
+                };
+                if by_primary(columns) {
+                    let start = LsmKey {
+                        prefix: self.tree.prefix(&row),
+                        seq: 0,
+                    };
+                    hit = self.first_from(&start)?;
+                } else {
+                    if self.holders.is_none() {
+                        self.holders = Some(self.scan_holders()?);
+                    }
+                    let i = self.unique.iter().position(|key| key == columns);
+                    let holder = i.and_then(|i| self.holders.as_ref()?[i].get(&value).cloned());
+                    if let Some(holder) = holder {
+                        hit = self.first_from(&holder)?.filter(|(key, _)| *key == holder);
+                    }
+                }
+                if hit.is_some() {
+                    break;
+                }
+            }
+
+            let Some((key, existing)) = hit else {
+                changes.push(Change::Insert { row: row.clone() });
+                self.add(row)?;
+                continue;
+            };
+            let Some(new) = resolve(&existing, &row)? else {
+                continue;
+            };
+            if !touched.insert(key.clone()) {
+                return Err("ON CONFLICT DO UPDATE command cannot affect row a second time".to_string());
+            }
+            touched.insert(self.put(key, &existing, new.clone())?);
+            changes.push(Change::Update { old: existing, new });
+        }
+        Ok(changes)
+    }
+
+    /// Logs the batch and applies it to the memtable
+    /// The stored rows are checked in the pass that builds the holders
+    fn add_key(&mut self, columns: &[String]) -> Result<(), String> {
+        if columns == self.tree.files.key_columns || self.unique.iter().any(|key| key == columns) {
+            return Ok(());
+        }
+        self.unique.push(columns.to_vec());
+        self.holders = Some(self.scan_holders()?);
+        Ok(())
+    }
+
+    fn commit(mut self: Box<Self>) -> Result<(), String> {
+        let tree = Arc::clone(&self.tree);
+        let mut state = tree.state.write().map_err(|e| e.to_string())?;
+        let batch = std::mem::take(&mut self.batch).into_iter().collect();
+        tree.write_batch(&mut state, batch)?;
+        state.next_seq = self.next_seq;
+        state.live = state.live.saturating_add_signed(self.added);
+        Ok(())
+    }
+
+    /// The batch was never written anywhere, so dropping it is enough
+    fn rollback(self: Box<Self>) {}
+}
+
+impl Drop for LsmWrite {
+    /// Let the next write begin
+    fn drop(&mut self) {
+        *self.tree.writing.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = false;
+        self.tree.write_done.notify_one();
+    }
+}
+
+/// First key a read of `range` on a single-column key starts from
+fn range_start(range: &KeyRange) -> Option<LsmKey> {
+    match &range.lower {
+        Bound::Included(v) | Bound::Excluded(v) => Some(LsmKey {
+            prefix: vec![v.clone()],
+            seq: 0,
+        }),
+        Bound::Unbounded => None,
+    }
+}
+
+/// Whether `value` lies past the upper end of `range`
+fn beyond(range: &KeyRange, value: &Value) -> bool {
+    match &range.upper {
+        Bound::Included(upper) => value.total_cmp(upper) == Ordering::Greater,
+        Bound::Excluded(upper) => value.total_cmp(upper) != Ordering::Less,
+        Bound::Unbounded => false,
+    }
+}
+
+impl TreeFiles {
+    fn snapshot(&self) -> Result<Vec<Arc<SsTable>>, String> {
+        Ok(self.tables.read().map_err(|e| e.to_string())?.clone())
+    }
+
+    fn new_table_path(&self) -> PathBuf {
+        let number = self.next_file.fetch_add(1, AtomicOrdering::AcqRel);
+        self.dir.join(format!("{:06}.sst", number))
+    }
+
+    /// Record the current tables; written aside and renamed into place
+    fn write_manifest(&self) -> Result<(), String> {
+        let _guard = self.manifest.lock().map_err(|e| e.to_string())?;
+        let manifest = Manifest {
+            key_columns: self.key_columns.clone(),
+            tables: self.snapshot()?.iter().map(|t| t.name()).collect(),
+            next_seq: self.flushed_seq.load(AtomicOrdering::Acquire),
+        };
+        let staging = self.dir.join(format!("{}.tmp", MANIFEST));
+        let json = serde_json::to_string_pretty(&manifest).map_err(|e| e.to_string())?;
+        fs::write(&staging, json).map_err(|e| e.to_string())?;
+        fs::rename(&staging, self.dir.join(MANIFEST)).map_err(|e| e.to_string())
+    }
+
+    /// Runs of at least `MIN_MERGE` adjacent tables of similar size, and
+    /// whether the run reaches the oldest table
+    fn pick_window(tables: &[Arc<SsTable>]) -> Option<(Vec<Arc<SsTable>>, bool)> {
+        for start in 0..tables.len() {
+            let (mut min, mut max) = (u64::MAX, 0);
+            let mut end = start;
+            while end < tables.len() {
+                let size = tables[end].size();
+                let (lo, hi) = (min.min(size), max.max(size));
+                if hi > lo.saturating_mul(SIZE_RATIO) {
+                    break;
+                }
+                (min, max) = (lo, hi);
+                end += 1;
+            }
+            if end - start >= MIN_MERGE {
+                return Some((tables[start..end].to_vec(), end == tables.len()));
+            }
+        }
+        None
+    }
+
+    /// Start a background compaction if one is due and none is running
+    fn maybe_compact(files: &Arc<TreeFiles>) {
+        if files.compacting.swap(true, AtomicOrdering::AcqRel) {
+            return;
+        }
+        let files = Arc::clone(files);
+        thread::spawn(move || {
+            while !files.dropped.load(AtomicOrdering::Acquire) {
+                let window = files.snapshot().ok().and_then(|tables| Self::pick_window(&tables));
+                let Some((window, bottom)) = window else {
+                    break;
+                };
+                if files.compact(window, bottom).is_err() {
+                    break;
+                }
+            }
+            files.compacting.store(false, AtomicOrdering::Release);
+        });
+    }
+
+    /// Merge `window` into one table. Tombstones are dropped only when the
+    /// window holds the oldest table, since older versions they hide could
+    /// otherwise live on below it.
+    fn compact(&self, window: Vec<Arc<SsTable>>, bottom: bool) -> Result<(), String> {
+        let sources = window
+            .iter()
+            .map(|table| Box::new(table.iter_from(None)) as Source<'static>)
+            .collect();
+        let entries = MergeIter::new(sources, !bottom).collect::<Result<Vec<Entry>, String>>()?;
+        let merged = match entries.is_empty() {
+            true => None,
+            false => Some(Arc::new(SsTable::write(&self.new_table_path(), entries)?)),
+        };
+
+        {
+            let mut tables = self.tables.write().map_err(|e| e.to_string())?;
+            let start = tables.iter().position(|t| Arc::ptr_eq(t, &window[0]));
+            let in_place = start.is_some_and(|start| {
+                tables.len() >= start + window.len()
+                    && tables[start..start + window.len()].iter().zip(&window).all(|(a, b)| Arc::ptr_eq(a, b))
+            });
+            if self.dropped.load(AtomicOrdering::Acquire) || !in_place {
+                if let Some(merged) = &merged {
+                    merged.mark_obsolete();
+                }
+                return Ok(());
+            }
+            let start = start.unwrap_or_default();
+            tables.splice(start..start + window.len(), merged);
+        }
+        self.write_manifest()?;
+        for table in &window {
+            table.mark_obsolete();
+        }
+        Ok(())
+    }
+}
+
+/// Storage engine keeping each table in its own LSM tree, in a directory
+/// named after the table
+pub struct LsmEngine {
+    dir: PathBuf,
+    /// Trees opened so far
+    trees: RwLock<HashMap<String, Arc<LsmTree>>>,
+}
+
+impl LsmEngine {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            trees: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// The tree holding `table`, opened on first use
+    fn tree(&self, table: &str) -> Result<Arc<LsmTree>, String> {
+        if let Some(tree) = self.trees.read().map_err(|e| e.to_string())?.get(table) {
+            return Ok(Arc::clone(tree));
+        }
+        let dir = self.dir.join(table);
+        if !LsmTree::exists(&dir) {
+            return Err(format!("Table '{}' not found", table));
+        }
+        let mut trees = self.trees.write().map_err(|e| e.to_string())?;
+        if let Some(tree) = trees.get(table) {
+            return Ok(Arc::clone(tree));
+        }
+        let tree = Arc::new(LsmTree::open(&dir, Vec::new())?);
+        trees.insert(table.to_string(), Arc::clone(&tree));
+        Ok(tree)
+    }
+}
+
+impl StorageEngine for LsmEngine {
+    /// The tree is keyed on the table's primary key
+    fn create_table(&self, schema: &TableSchema) -> Result<(), String> {
+        let mut trees = self.trees.write().map_err(|e| e.to_string())?;
+        if !trees.contains_key(&schema.name) {
+            let tree = LsmTree::open(&self.dir.join(&schema.name), schema.primary_key())?;
+            trees.insert(schema.name.clone(), Arc::new(tree));
+        }
+        Ok(())
+    }
+
+    fn drop_table(&self, table: &str) -> Result<(), String> {
+        if !self.has_table(table) {
+            return Ok(());
+        }
+        let tree = self.tree(table)?;
+        self.trees.write().map_err(|e| e.to_string())?.remove(table);
+        tree.destroy()
+    }
+
+    fn has_table(&self, table: &str) -> bool {
+        self.trees.read().is_ok_and(|trees| trees.contains_key(table)) || LsmTree::exists(&self.dir.join(table))
+    }
+
+    fn snapshot(&self, table: &str) -> Result<Arc<dyn TableSnapshot>, String> {
+        Ok(Arc::new(self.tree(table)?.snapshot()?))
+    }
+
+    fn select(&self, table: &str, columns: &[String], predicate: &dyn Fn(&Row) -> bool) -> Result<Vec<Row>, String> {
+        Ok(self
+            .tree(table)?
+            .rows()?
+            .iter()
+            .filter(|row| predicate(row))
+            .map(|row| select_columns(row, columns))
+            .collect())
+    }
+
+    fn index_scan(&self, table: &str, column: &str, range: &KeyRange) -> Result<Vec<Row>, String> {
+        self.tree(table)?.index_scan(column, range)
+    }
+
+    fn row_count(&self, table: &str) -> Result<usize, String> {
+        Ok(self.tree(table).map_or(0, |tree| tree.len()))
+    }
+
+    fn begin(&self, table: &str, keys: &[Vec<String>]) -> Result<Box<dyn TableWrite + '_>, String> {
+        Ok(self.tree(table)?.begin(keys)?)
+    }
+}
+
+type Source<'a> = Box<dyn Iterator<Item = Result<Entry, String>> + 'a>;
+
+/// Merges sorted sources, listed newest first, into one sorted stream with
+/// a single (the newest) version of every key
+struct MergeIter<'a> {
+    sources: Vec<Source<'a>>,
+    heads: Vec<Option<Entry>>,
+    keep_tombstones: bool,
+    started: bool,
+}
+
+impl<'a> MergeIter<'a> {
+    fn new(sources: Vec<Source<'a>>, keep_tombstones: bool) -> Self {
+        let heads = sources.iter().map(|_| None).collect();
+        Self {
+            sources,
+            heads,
+            keep_tombstones,
+            started: false,
+        }
+    }
+
+    fn advance(&mut self, source: usize) -> Result<(), String> {
+        self.heads[source] = self.sources[source].next().transpose()?;
+        Ok(())
+    }
+}
+
+impl Iterator for MergeIter<'_> {
+    type Item = Result<Entry, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.started {
+            self.started = true;
+            for source in 0..self.sources.len() {
+                if let Err(e) = self.advance(source) {
+                    self.heads.clear();
+                    return Some(Err(e));
+                }
+            }
+        }
+        loop {
+            // The smallest key; on ties the first (newest) source holding it
+            let newest = self
+                .heads
+                .iter()
+                .enumerate()
+                .filter_map(|(i, head)| head.as_ref().map(|(key, _)| (i, key)))
+                .min_by(|(_, a), (_, b)| a.cmp(b))
+                .map(|(i, _)| i)?;
+            let entry = self.heads[newest].take()?;
+            for source in 0..self.heads.len() {
+                let shadowed = source == newest || self.heads[source].as_ref().is_some_and(|(key, _)| *key == entry.0);
+                if shadowed {
+                    if let Err(e) = self.advance(source) {
+                        self.heads.clear();
+                        return Some(Err(e));
+                    }
+                }
+            }
+            if entry.1.is_some() || self.keep_tombstones {
+                return Some(Ok(entry));
+            }
+        }
+    }
+}
+
+/// The live entries of a tree from `start` on: a write's `pending` batch
+/// over its memtable over `tables`
+fn merge<'a>(
+    state: &'a LsmState,
+    pending: Option<&'a BTreeMap<LsmKey, Option<Row>>>,
+    tables: Vec<Arc<SsTable>>,
+    start: Option<&LsmKey>,
+    keep_tombstones: bool,
+) -> MergeIter<'a> {
+    let memtable = match start {
+        Some(start) => state.memtable.range_from(start),
+        None => state.memtable.iter(),
+    };
+    let mut sources: Vec<Source<'a>> = Vec::new();
+    if let Some(pending) = pending {
+        let from = start.map_or(Bound::Unbounded, |start| Bound::Included(start.clone()));
+        let entries = pending.range((from, Bound::Unbounded));
+        sources.push(Box::new(entries.map(|(k, v)| Ok((k.clone(), v.clone())))));
+    }
+    sources.push(Box::new(memtable.map(|(k, v)| Ok((k.clone(), v.clone())))));
+    sources.extend(tables.iter().map(|table| Box::new(table.iter_from(start)) as Source<'a>));
+    MergeIter::new(sources, keep_tombstones)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::catalog::TableEngine;
+    use crate::db::executor::ExecutionResult;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or_default();
+        std::env::temp_dir().join(format!("lsm_{}_{}_{}", name, std::process::id(), nanos))
+    }
+
+    fn row(id: i64, name: &str) -> Row {
+        HashMap::from([
+            ("id".to_string(), Value::Integer(id)),
+            ("name".to_string(), Value::Text(name.to_string())),
+        ])
+    }
+
+    fn ids(rows: &[Row]) -> Vec<i64> {
+        rows.iter()
+            .map(|r| match r["id"] {
+                Value::Integer(i) => i,
+                _ => panic!("id is not an integer"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_lsm_flushes_and_recovers() {
+        let dir = temp_dir("recover");
+        {
+            let tree = Arc::new(LsmTree::open_with_limit(&dir, vec!["id".to_string()], 50).unwrap());
+            tree.append((0..120).rev().map(|i| row(i, "a")).collect()).unwrap();
+            assert_eq!(tree.files.snapshot().unwrap().len(), 1);
+            tree.append((120..130).map(|i| row(i, "b")).collect()).unwrap();
+            let mut write = tree.begin(&[]).unwrap();
+            let deleted = write.delete(&Selection::matching(&|r| r["id"] == Value::Integer(5))).unwrap();
+            assert_eq!(ids(&deleted), vec![5]);
+            write.commit().unwrap();
+            assert_eq!(tree.len(), 129);
+        }
+
+        // The unflushed writes come back from the log
+        let tree = Arc::new(LsmTree::open(&dir, Vec::new()).unwrap());
+        assert_eq!(tree.len(), 129);
+        let rows = tree.rows().unwrap();
+        assert_eq!(ids(&rows), (0..130).filter(|&i| i != 5).collect::<Vec<_>>());
+
+        // Batched scans resume where they stopped
+        let snapshot = tree.snapshot().unwrap();
+        let mut scanned = Vec::new();
+        while let Ok(batch) = snapshot.rows(scanned.len(), 17) {
+            if batch.is_empty() {
+                break;
+            }
+            scanned.extend(batch);
+        }
+        assert_eq!(ids(&scanned), ids(&rows));
+
+        // ...and read the tree as it was when the snapshot was taken
+        tree.append(vec![row(200, "c")]).unwrap();
+        assert_eq!(snapshot.rows(0, 1000).unwrap().len(), rows.len());
+        assert_eq!(tree.snapshot().unwrap().rows(0, 1000).unwrap().len(), rows.len() + 1);
+
+        let exact = KeyRange::exact(Value::Integer(42));
+        assert_eq!(ids(&tree.index_scan("id", &exact).unwrap()), vec![42]);
+        let range = KeyRange {
+            lower: Bound::Excluded(Value::Integer(3)),
+            upper: Bound::Included(Value::Float(7.0)),
+        };
+        assert_eq!(ids(&tree.index_scan("id", &range).unwrap()), vec![4, 6, 7]);
+        let by_name = KeyRange::exact(Value::Text("b".to_string()));
+        assert_eq!(tree.index_scan("name", &by_name).unwrap().len(), 10);
+
+        tree.destroy().unwrap();
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn test_lsm_torn_log_tail_survives_two_restarts() {
+        let dir = temp_dir("torn");
+        {
+            let tree = Arc::new(LsmTree::open(&dir, vec!["id".to_string()]).unwrap());
+            tree.append(vec![row(1, "a")]).unwrap();
+        }
+        // A crash in the middle of appending the next batch
+        let mut wal = fs::OpenOptions::new().append(true).open(dir.join(WAL)).unwrap();
+        std::io::Write::write_all(&mut wal, b"[[{\"prefix\"").unwrap();
+        drop(wal);
+
+        {
+            let tree = Arc::new(LsmTree::open(&dir, vec!["id".to_string()]).unwrap());
+            assert_eq!(tree.len(), 1);
+            tree.append(vec![row(2, "b")]).unwrap();
+        }
+        let tree = LsmTree::open(&dir, vec!["id".to_string()]).unwrap();
+        assert_eq!(ids(&tree.rows().unwrap()), vec![1, 2]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_lsm_compaction_merges_versions() {
+        let dir = temp_dir("compact");
+        let tree = Arc::new(LsmTree::open_with_limit(&dir, vec!["id".to_string()], 1000).unwrap());
+        tree.append((0..10).map(|i| row(i, "new")).collect()).unwrap();
+        tree.flush().unwrap();
+        for round in 0..5 {
+            let mut write = tree.begin(&[]).unwrap();
+            let renamed = write
+                .update(&Selection::matching(&|_| true), &mut |r| {
+                    Ok(row(ids(std::slice::from_ref(r))[0], &format!("round {}", round)))
+                })
+                .unwrap();
+            assert_eq!(renamed.len(), 10);
+            write.commit().unwrap();
+            tree.flush().unwrap();
+        }
+        let mut write = tree.begin(&[]).unwrap();
+        let even = |r: &Row| matches!(r["id"], Value::Integer(i) if i % 2 == 0);
+        write.delete(&Selection::matching(&even)).unwrap();
+        write.commit().unwrap();
+        tree.flush().unwrap();
+        tree.wait_for_compaction();
+
+        let tables = tree.files.snapshot().unwrap();
+        assert!(tables.len() < 7, "{} tables after compaction", tables.len());
+        let rows = tree.rows().unwrap();
+        assert_eq!(ids(&rows), vec![1, 3, 5, 7, 9]);
+        assert!(rows.iter().all(|r| r["name"] == Value::Text("round 4".to_string())));
+
+        // Compacted away versions and tombstones are not read back on reopen
+        drop(tree);
+        let tree = LsmTree::open(&dir, Vec::new()).unwrap();
+        assert_eq!(tree.len(), 5);
+        tree.destroy().unwrap();
+    }
+
+    #[test]
+    fn test_lsm_writes_are_puts_on_the_primary_key() {
+        let dir = temp_dir("puts");
+        let tree = Arc::new(LsmTree::open(&dir, vec!["id".to_string()]).unwrap());
+        tree.append((1..=3).map(|i| row(i, "a")).collect()).unwrap();
+
+        // A duplicate key is refused, not stored as a second row
+        let duplicate = tree.append(vec![row(4, "b"), row(2, "b")]);
+        assert!(duplicate.unwrap_err().contains("(id)=(2) already exists"));
+        let mut write = tree.begin(&[]).unwrap();
+        let onto_3 = write.update(&Selection::matching(&|r| r["id"] == Value::Integer(1)), &mut |_| Ok(row(3, "c")));
+        assert!(onto_3.is_err());
+        drop(write);
+        assert_eq!(ids(&tree.rows().unwrap()), vec![1, 2, 3]);
+
+        // Rewriting a row replaces its memtable entry; moving it to a free
+        // key leaves a tombstone behind
+        let by_key = |id: i64| Selection {
+            predicate: &|_| true,
+            key: Some(("id".to_string(), KeyRange::exact(Value::Integer(id)))),
+        };
+        let mut write = tree.begin(&[]).unwrap();
+        write.update(&by_key(2), &mut |_| Ok(row(2, "renamed"))).unwrap();
+        write.update(&by_key(3), &mut |_| Ok(row(7, "moved"))).unwrap();
+        write.commit().unwrap();
+        assert_eq!(tree.state.read().unwrap().memtable.len(), 4);
+        assert_eq!(ids(&tree.rows().unwrap()), vec![1, 2, 7]);
+        assert_eq!(tree.len(), 3);
+        tree.destroy().unwrap();
+    }
+
+    #[test]
+    fn test_lsm_tables_answer_like_memory_tables() {
+        use crate::db::sql::execute_sql;
+        use crate::db::storage::STORAGE;
+
+        let tables = [("lsm_orders", " WITH (engine = 'lsm')"), ("lsm_orders_memory", "")];
+        for (table, with) in tables {
+            execute_sql(&format!("DROP TABLE IF EXISTS {}", table));
+            execute_sql(&format!(
+                "CREATE TABLE {} (id INT PRIMARY KEY, customer TEXT, total FLOAT){}",
+                table, with
+            ));
+            let values: Vec<String> = (0..800)
+                .map(|i| format!("({}, 'c{}', {}.25)", (i * 7) % 800, i % 9, i))
+                .collect();
+            execute_sql(&format!("INSERT INTO {} VALUES {}", table, values.join(", ")));
+            execute_sql(&format!("UPDATE {} SET total = 0 WHERE customer = 'c3'", table));
+            execute_sql(&format!("DELETE FROM {} WHERE id % 5 = 0", table));
+            execute_sql(&format!(
+                "INSERT INTO {} VALUES (5, 'back', 1.0), (3, 'moved', 2.0) ON CONFLICT (id) DO UPDATE SET customer = 'moved'",
+                table
+            ));
+        }
+        assert_eq!(STORAGE.table_engine("lsm_orders"), TableEngine::Lsm);
+        assert!(STORAGE.lsm.has_table("lsm_orders"));
+        assert!(!STORAGE.lsm.has_table("lsm_orders_memory"));
+
+        let queries = [
+            "SELECT * FROM {} ORDER BY id",
+            "SELECT customer, COUNT(*), SUM(total) FROM {} GROUP BY customer ORDER BY customer",
+            "SELECT * FROM {} WHERE id = 3",
+            "SELECT id FROM {} WHERE id > 790 ORDER BY id",
+        ];
+        for query in queries {
+            let lsm = execute_sql(&query.replace("{}", "lsm_orders"));
+            let memory = execute_sql(&query.replace("{}", "lsm_orders_memory"));
+            assert!(matches!(lsm, ExecutionResult::Rows { .. }), "{}: {:?}", query, lsm);
+            assert_eq!(serde_json::to_value(&lsm).unwrap(), serde_json::to_value(&memory).unwrap(), "{}", query);
+        }
+
+        assert!(matches!(
+            execute_sql("CREATE TABLE lsm_bad (id INT) WITH (engine = 'lsm', format = 'columnar')"),
+            ExecutionResult::Error { .. }
+        ));
+        for (table, _) in tables {
+            execute_sql(&format!("DROP TABLE {}", table));
+        }
+        assert!(!STORAGE.lsm.has_table("lsm_orders"));
+    }
+}
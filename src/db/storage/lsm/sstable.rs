@@ -0,0 +1,294 @@
+// Sorted string tables - immutable, sorted runs of LSM entries on disk
+//
+// Layout: data blocks of encoded entries, then the block index (the last key
+// and position of every block), then the bloom filter over key prefixes, then
+// a fixed-size footer locating the index and the filter.
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use super::bloom::BloomFilter;
+use super::{Entry, LsmKey};
+use crate::db::storage::Value;
+
+/// Target size of a data block
+pub const BLOCK_SIZE: usize = 4096;
+
+/// "LSMTABLE"
+const MAGIC: u64 = 0x4c53_4d54_4142_4c45;
+const FOOTER_SIZE: u64 = 32;
+
+/// Where one data block lies, and the last key in it
+#[derive(Debug)]
+struct BlockHandle {
+    last_key: LsmKey,
+    offset: u64,
+    len: u32,
+}
+
+#[derive(Debug)]
+pub struct SsTable {
+    path: PathBuf,
+    file: Mutex<File>,
+    index: Vec<BlockHandle>,
+    bloom: BloomFilter,
+    entries: u64,
+    size: u64,
+    /// Set once compaction has replaced the table; the file is removed when
+    /// the last reader lets go of it
+    obsolete: AtomicBool,
+}
+
+fn put_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    out.extend_from_slice(bytes);
+}
+
+/// Split a length-prefixed byte string off the front of `bytes`
+fn take_bytes<'a>(bytes: &mut &'a [u8]) -> Result<&'a [u8], String> {
+    if bytes.len() < 4 {
+        return Err("SSTable entry is truncated".to_string());
+    }
+    let len = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+    if bytes.len() < 4 + len {
+        return Err("SSTable entry is truncated".to_string());
+    }
+    let (taken, rest) = bytes[4..].split_at(len);
+    *bytes = rest;
+    Ok(taken)
+}
+
+fn encode_entry(out: &mut Vec<u8>, (key, row): &Entry) -> Result<(), String> {
+    put_bytes(out, &serde_json::to_vec(key).map_err(|e| e.to_string())?);
+    match row {
+        Some(row) => {
+            out.push(1);
+            put_bytes(out, &serde_json::to_vec(row).map_err(|e| e.to_string())?);
+        }
+        // Tombstone
+        None => out.push(0),
+    }
+    Ok(())
+}
+
+fn decode_entries(mut bytes: &[u8]) -> Result<Vec<Entry>, String> {
+    let mut entries = Vec::new();
+    while !bytes.is_empty() {
+        let key: LsmKey = serde_json::from_slice(take_bytes(&mut bytes)?).map_err(|e| e.to_string())?;
+        let (&tag, rest) = bytes.split_first().ok_or("SSTable entry is truncated")?;
+        bytes = rest;
+        let row = match tag {
+            0 => None,
+            _ => Some(serde_json::from_slice(take_bytes(&mut bytes)?).map_err(|e| e.to_string())?),
+        };
+        entries.push((key, row));
+    }
+    Ok(entries)
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> u64 {
+    let mut word = [0; 8];
+    word.copy_from_slice(&bytes[at..at + 8]);
+    u64::from_le_bytes(word)
+}
+
+impl SsTable {
+    /// Write `entries` (sorted by key) to a new table at `path`. The file is
+    /// written under a temporary name and renamed, so a crash never leaves a
+    /// half-written table behind.
+    pub fn write(path: &Path, entries: impl IntoIterator<Item = Entry>) -> Result<SsTable, String> {
+        let staging = path.with_extension("tmp");
+        let mut out = BufWriter::new(File::create(&staging).map_err(|e| e.to_string())?);
+
+        let mut offset = 0u64;
+        let mut block = Vec::new();
+        let mut index_bytes = Vec::new();
+        let mut prefixes = Vec::new();
+        let mut last_key: Option<LsmKey> = None;
+        let mut count = 0u64;
+        let mut blocks = 0u32;
+
+        let mut finish_block = |block: &mut Vec<u8>, last_key: &LsmKey, offset: &mut u64| -> Result<(), String> {
+            out.write_all(block).map_err(|e| e.to_string())?;
+            put_bytes(&mut index_bytes, &serde_json::to_vec(last_key).map_err(|e| e.to_string())?);
+            index_bytes.extend_from_slice(&offset.to_le_bytes());
+            index_bytes.extend_from_slice(&(block.len() as u32).to_le_bytes());
+            *offset += block.len() as u64;
+            blocks += 1;
+            block.clear();
+            Ok(())
+        };
+
+        for entry in entries {
+            if last_key.as_ref().is_some_and(|last| last.prefix != entry.0.prefix) || last_key.is_none() {
+                prefixes.push(LsmKey::prefix_bytes(&entry.0.prefix));
+            }
+            encode_entry(&mut block, &entry)?;
+            last_key = Some(entry.0);
+            count += 1;
+            if block.len() >= BLOCK_SIZE {
+                finish_block(&mut block, last_key.as_ref().expect("block has an entry"), &mut offset)?;
+            }
+        }
+        if !block.is_empty() {
+            finish_block(&mut block, last_key.as_ref().expect("block has an entry"), &mut offset)?;
+        }
+
+        let mut bloom = BloomFilter::new(prefixes.len());
+        for prefix in &prefixes {
+            bloom.insert(prefix);
+        }
+        let index_offset = offset;
+        let mut index = blocks.to_le_bytes().to_vec();
+        index.extend_from_slice(&index_bytes);
+        let bloom_offset = index_offset + index.len() as u64;
+        out.write_all(&index).map_err(|e| e.to_string())?;
+        out.write_all(&bloom.to_bytes()).map_err(|e| e.to_string())?;
+        for word in [index_offset, bloom_offset, count, MAGIC] {
+            out.write_all(&word.to_le_bytes()).map_err(|e| e.to_string())?;
+        }
+        let file = out.into_inner().map_err(|e| e.to_string())?;
+        file.sync_all().map_err(|e| e.to_string())?;
+        drop(file);
+
+        fs::rename(&staging, path).map_err(|e| e.to_string())?;
+        Self::open(path)
+    }
+
+    /// Open a table, reading its block index and bloom filter into memory
+    pub fn open(path: &Path) -> Result<SsTable, String> {
+        let mut file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let size = file.metadata().map_err(|e| e.to_string())?.len();
+        if size < FOOTER_SIZE {
+            return Err(format!("{}: not an SSTable", path.display()));
+        }
+        let mut footer = [0; FOOTER_SIZE as usize];
+        file.seek(SeekFrom::Start(size - FOOTER_SIZE)).map_err(|e| e.to_string())?;
+        file.read_exact(&mut footer).map_err(|e| e.to_string())?;
+        let (index_offset, bloom_offset, entries) = (read_u64(&footer, 0), read_u64(&footer, 8), read_u64(&footer, 16));
+        if read_u64(&footer, 24) != MAGIC || index_offset > bloom_offset || bloom_offset > size - FOOTER_SIZE {
+            return Err(format!("{}: not an SSTable", path.display()));
+        }
+
+        let mut meta = vec![0; (size - FOOTER_SIZE - index_offset) as usize];
+        file.seek(SeekFrom::Start(index_offset)).map_err(|e| e.to_string())?;
+        file.read_exact(&mut meta).map_err(|e| e.to_string())?;
+        let (index_bytes, bloom_bytes) = meta.split_at((bloom_offset - index_offset) as usize);
+
+        let mut rest = index_bytes.get(4..).ok_or("SSTable index is truncated")?;
+        let blocks = u32::from_le_bytes([index_bytes[0], index_bytes[1], index_bytes[2], index_bytes[3]]);
+        let mut index = Vec::with_capacity(blocks as usize);
+        for _ in 0..blocks {
+            let last_key = serde_json::from_slice(take_bytes(&mut rest)?).map_err(|e| e.to_string())?;
+            if rest.len() < 12 {
+                return Err("SSTable index is truncated".to_string());
+            }
+            let offset = read_u64(rest, 0);
+            let len = u32::from_le_bytes([rest[8], rest[9], rest[10], rest[11]]);
+            rest = &rest[12..];
+            index.push(BlockHandle { last_key, offset, len });
+        }
+
+        Ok(SsTable {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+            index,
+            bloom: BloomFilter::from_bytes(bloom_bytes)?,
+            entries,
+            size,
+            obsolete: AtomicBool::new(false),
+        })
+    }
+
+    /// File name, as listed in the manifest
+    pub fn name(&self) -> String {
+        self.path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default()
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn entries(&self) -> u64 {
+        self.entries
+    }
+
+    /// Whether any key with this prefix (primary key) may be in the table
+    pub fn may_contain_prefix(&self, prefix: &[Value]) -> bool {
+        self.bloom.may_contain(&LsmKey::prefix_bytes(prefix))
+    }
+
+    pub fn mark_obsolete(&self) {
+        self.obsolete.store(true, Ordering::Release);
+    }
+
+    fn read_block(&self, block: usize) -> Result<Vec<Entry>, String> {
+        let handle = &self.index[block];
+        let mut bytes = vec![0; handle.len as usize];
+        {
+            let mut file = self.file.lock().map_err(|e| e.to_string())?;
+            file.seek(SeekFrom::Start(handle.offset)).map_err(|e| e.to_string())?;
+            file.read_exact(&mut bytes).map_err(|e| e.to_string())?;
+        }
+        decode_entries(&bytes)
+    }
+
+    /// Entries in key order from the first key not below `start`, reading
+    /// one block at a time
+    pub fn iter_from(self: &Arc<Self>, start: Option<&LsmKey>) -> SsTableIter {
+        let block = match start {
+            Some(start) => self.index.partition_point(|handle| handle.last_key < *start),
+            None => 0,
+        };
+        SsTableIter {
+            table: Arc::clone(self),
+            next_block: block,
+            start: start.cloned(),
+            buffer: VecDeque::new(),
+        }
+    }
+}
+
+impl Drop for SsTable {
+    fn drop(&mut self) {
+        if self.obsolete.load(Ordering::Acquire) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+pub struct SsTableIter {
+    table: Arc<SsTable>,
+    next_block: usize,
+    /// Entries below this key are dropped from the first block read
+    start: Option<LsmKey>,
+    buffer: VecDeque<Entry>,
+}
+
+impl Iterator for SsTableIter {
+    type Item = Result<Entry, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.buffer.is_empty() {
+            if self.next_block >= self.table.index.len() {
+                return None;
+            }
+            let entries = match self.table.read_block(self.next_block) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    self.next_block = self.table.index.len();
+                    return Some(Err(e));
+                }
+            };
+            self.next_block += 1;
+            self.buffer = entries.into();
+            if let Some(start) = self.start.take() {
+                self.buffer.retain(|(key, _)| *key >= start);
+            }
+        }
+        self.buffer.pop_front().map(Ok)
+    }
+}
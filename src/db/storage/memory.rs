@@ -0,0 +1,547 @@
+// In-memory storage engine - tables held in memory, saved to a JSON file
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock, RwLockWriteGuard};
+
+use super::changes::Change;
+use super::columnar::ColumnStore;
+use super::engine::{Resolve, Selection, StorageEngine, TableSnapshot, TableWrite};
+use super::{duplicate_key, unique_key, IndexKey, KeyRange, Row, ScanBatch, ScanFilter, TableData};
+use crate::db::catalog::{TableFormat, TableSchema};
+
+/// Serializable storage data for persistence
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct StorageData {
+    tables: HashMap<String, Vec<Row>>,
+    /// Columnar tables, kept encoded
+    #[serde(default)]
+    columnar: HashMap<String, ColumnStore>,
+}
+
+/// Tables kept in memory and written out whole after every change
+pub struct MemoryEngine {
+    /// Shared with the snapshots scans read; a write copies a table first
+    /// if a scan still holds it
+    tables: RwLock<HashMap<String, Arc<TableData>>>,
+    /// File the tables are saved to; None keeps them in memory only
+    storage_path: Option<PathBuf>,
+}
+
+impl MemoryEngine {
+    /// Engine persisting to `storage_path`, loading whatever is there
+    pub fn open(storage_path: PathBuf) -> Self {
+        let mut engine = Self {
+            tables: RwLock::new(HashMap::new()),
+            storage_path: Some(storage_path),
+        };
+        let _ = engine.load();
+        engine
+    }
+
+    /// Engine whose tables live only as long as it does
+    pub fn in_memory() -> Self {
+        Self {
+            tables: RwLock::new(HashMap::new()),
+            storage_path: None,
+        }
+    }
+
+    /// Save all table data to disk
+    pub fn save(&self) -> Result<(), String> {
+        let tables = self.tables.read().map_err(|e| e.to_string())?;
+        self.save_tables(&tables)
+    }
+
+    /// Save `tables`, which the caller has locked. Writers save before
+    /// letting go of the write lock, so what is saved, and what a failed
+    /// save rolls back, is only ever their own change.
+    fn save_tables(&self, tables: &HashMap<String, Arc<TableData>>) -> Result<(), String> {
+        let Some(storage_path) = &self.storage_path else {
+            return Ok(());
+        };
+
+        // Convert TableData to serializable format
+        let mut data = StorageData::default();
+        for (name, table_data) in tables.iter() {
+            match &table_data.columnar {
+                Some(store) => {
+                    data.columnar.insert(name.clone(), store.clone());
+                }
+                None => {
+                    data.tables.insert(name.clone(), table_data.rows.clone());
+                }
+            }
+        }
+
+        let json = serde_json::to_string_pretty(&data)
+            .map_err(|e| e.to_string())?;
+        std::fs::write(storage_path, json)
+            .map_err(|e| e.to_string())?;
+
+        Ok(())
+    }
+
+    /// Load table data from disk
+    pub fn load(&mut self) -> Result<(), String> {
+        let Some(storage_path) = self.storage_path.as_ref().filter(|path| path.exists()) else {
+            return Ok(());
+        };
+
+        let content = std::fs::read_to_string(storage_path)
+            .map_err(|e| e.to_string())?;
+
+        let data: StorageData = serde_json::from_str(&content)
+            .map_err(|e| e.to_string())?;
+
+        let mut tables = self.tables.write().map_err(|e| e.to_string())?;
+        for (name, rows) in data.tables {
+            tables.insert(name, Arc::new(TableData { rows, ..TableData::default() }));
+        }
+        for (name, store) in data.columnar {
+            let table = TableData {
+                columnar: Some(store),
+                ..TableData::default()
+            };
+            tables.insert(name, Arc::new(table));
+        }
+
+        Ok(())
+    }
+
+    /// Create storage for a table unless it has some already
+    fn ensure_table(&self, table_name: &str, format: TableFormat) -> Result<(), String> {
+        if self.tables.read().map_err(|e| e.to_string())?.contains_key(table_name) {
+            return Ok(());
+        }
+        let mut tables = self.tables.write().map_err(|e| e.to_string())?;
+        if !tables.contains_key(table_name) {
+            tables.insert(table_name.to_string(), Arc::new(TableData::with_format(format)));
+        }
+        Ok(())
+    }
+}
+
+impl StorageEngine for MemoryEngine {
+    fn create_table(&self, schema: &TableSchema) -> Result<(), String> {
+        self.ensure_table(&schema.name, schema.format)
+    }
+
+    /// Drop a table's data (auto-persists)
+    fn drop_table(&self, table_name: &str) -> Result<(), String> {
+        let mut tables = self.tables.write().map_err(|e| e.to_string())?;
+        tables.remove(table_name);
+
+        // Auto-persist after drop
+        let _ = self.save_tables(&tables);
+
+        Ok(())
+    }
+
+    fn has_table(&self, table_name: &str) -> bool {
+        self.tables.read().is_ok_and(|tables| tables.contains_key(table_name))
+    }
+
+    /// The table itself, kept alive by the snapshot; writers copy it
+    /// rather than change it under a scan
+    fn snapshot(&self, table_name: &str) -> Result<Arc<dyn TableSnapshot>, String> {
+        let tables = self.tables.read().map_err(|e| e.to_string())?;
+        let table = tables
+            .get(table_name)
+            .ok_or(format!("Table '{}' not found", table_name))?;
+
+        Ok(Arc::clone(table) as Arc<dyn TableSnapshot>)
+    }
+
+    fn select(&self, table_name: &str, columns: &[String], predicate: &dyn Fn(&Row) -> bool) -> Result<Vec<Row>, String> {
+        let tables = self.tables.read().map_err(|e| e.to_string())?;
+        let table = tables
+            .get(table_name)
+            .ok_or(format!("Table '{}' not found", table_name))?;
+
+        Ok(table.select_columns(columns, predicate))
+    }
+
+    /// Rows in table order, using (and if needed first building) the
+    /// table's index on `column`
+    fn index_scan(&self, table_name: &str, column: &str, range: &KeyRange) -> Result<Vec<Row>, String> {
+        {
+            let tables = self.tables.read().map_err(|e| e.to_string())?;
+            let table = tables
+                .get(table_name)
+                .ok_or(format!("Table '{}' not found", table_name))?;
+            if let Some(index) = table.indexes.get(column) {
+                return Ok(table.rows_at(&index.positions(range)));
+            }
+        }
+
+        let mut tables = self.tables.write().map_err(|e| e.to_string())?;
+        let table = tables
+            .get_mut(table_name)
+            .map(Arc::make_mut)
+            .ok_or(format!("Table '{}' not found", table_name))?;
+        table.build_index(column);
+        let positions = table.indexes[column].positions(range);
+        Ok(table.rows_at(&positions))
+    }
+
+    fn row_count(&self, table_name: &str) -> Result<usize, String> {
+        let tables = self.tables.read().map_err(|e| e.to_string())?;
+        Ok(tables.get(table_name).map_or(0, |table| table.len()))
+    }
+
+    /// Storage layout of a table (row format if it has no data yet)
+    fn table_format(&self, table_name: &str) -> Result<TableFormat, String> {
+        let tables = self.tables.read().map_err(|e| e.to_string())?;
+        Ok(tables.get(table_name).map_or(TableFormat::Row, |table| table.format()))
+    }
+
+    /// Holds the engine's write lock until the write commits or rolls back
+    fn begin(&self, table_name: &str, keys: &[Vec<String>]) -> Result<Box<dyn TableWrite + '_>, String> {
+        let tables = self.tables.write().map_err(|e| e.to_string())?;
+        if !tables.contains_key(table_name) {
+            return Err(format!("Table '{}' not found", table_name));
+        }
+        Ok(Box::new(MemoryWrite {
+            engine: self,
+            tables,
+            table: table_name.to_string(),
+            keys: keys.to_vec(),
+            undo: Vec::new(),
+        }))
+    }
+
+    /// Creates the table on first insert
+    fn insert(&self, table_name: &str, rows: Vec<Row>) -> Result<usize, String> {
+        if rows.is_empty() {
+            return Ok(0);
+        }
+        self.ensure_table(table_name, TableFormat::Row)?;
+        (self as &dyn StorageEngine).write(table_name, &[], |write| write.insert(rows))
+    }
+}
+
+/// A columnar table decodes only what `filter` asks for and skips chunks
+/// its zone maps rule out, so the batch may hold fewer rows than it covers
+impl TableSnapshot for TableData {
+    fn scan(&self, offset: usize, limit: usize, filter: &ScanFilter) -> Result<ScanBatch, String> {
+        Ok(TableData::scan(self, offset, limit, filter))
+    }
+}
+
+/// How to take back one change of an uncommitted write
+enum Undo {
+    /// Rows were appended to a table this long
+    Appended(usize),
+    /// The row at this position held this before
+    Replaced(usize, Row),
+    /// These rows were removed from these positions (ascending)
+    Removed(Vec<(usize, Row)>),
+    /// A columnar table held this before it was re-encoded
+    Rewritten(TableData),
+}
+
+/// A write to one table. It changes the rows in place under the engine's
+/// write lock, and keeps what it needs to put them back if it does not
+/// commit: the rows it replaced or removed, not a copy of the table.
+struct MemoryWrite<'a> {
+    engine: &'a MemoryEngine,
+    tables: RwLockWriteGuard<'a, HashMap<String, Arc<TableData>>>,
+    table: String,
+    /// Column sets the table's rows are unique on
+    keys: Vec<Vec<String>>,
+    /// Oldest change first
+    undo: Vec<Undo>,
+}
+
+impl MemoryWrite<'_> {
+    fn data(&mut self) -> Result<&mut TableData, String> {
+        self.tables
+            .get_mut(&self.table)
+            .map(Arc::make_mut)
+            .ok_or(format!("Table '{}' not found", self.table))
+    }
+
+    /// For a columnar table, remember its encoded rows before they are
+    /// rewritten; returns whether it is one
+    fn rewrites(&mut self) -> Result<bool, String> {
+        let table = self.data()?;
+        if table.columnar.is_none() {
+            return Ok(false);
+        }
+        let snapshot = table.snapshot();
+        table.invalidate_indexes();
+        self.undo.push(Undo::Rewritten(snapshot));
+        Ok(true)
+    }
+
+    /// Take back every change made so far
+    fn undo_changes(&mut self) {
+        let Some(table) = self.tables.get_mut(&self.table).map(Arc::make_mut) else {
+            return;
+        };
+        if self.undo.is_empty() {
+            return;
+        }
+        for undo in self.undo.drain(..).rev() {
+            match undo {
+                Undo::Appended(len) => table.truncate(len),
+                Undo::Replaced(position, row) => table.rows[position] = row,
+                Undo::Removed(rows) => table.restore_at(rows),
+                Undo::Rewritten(snapshot) => *table = snapshot,
+            }
+        }
+        table.invalidate_indexes();
+    }
+}
+
+impl TableWrite for MemoryWrite<'_> {
+    /// New rows are checked against the stored ones through an index on
+    /// each key's first column, and against each other
+    fn insert(&mut self, rows: Vec<Row>) -> Result<usize, String> {
+        let table = self.tables
+            .get_mut(&self.table)
+            .map(Arc::make_mut)
+            .ok_or(format!("Table '{}' not found", self.table))?;
+        let mut seen = vec![BTreeSet::new(); self.keys.len()];
+        for row in &rows {
+            for (columns, seen) in self.keys.iter().zip(seen.iter_mut()) {
+                let Some(key) = unique_key(row, columns) else {
+                    continue;
+                };
+                if !seen.insert(key) || table.holder(columns, row, None).is_some() {
+                    return Err(duplicate_key(columns, row));
+                }
+            }
+        }
+
+        let (len, count) = (table.len(), rows.len());
+        table.append(rows);
+        self.undo.push(Undo::Appended(len));
+        Ok(count)
+    }
+
+    /// Row tables visit only the positions the selection's key range
+    /// covers, and keep their built indexes current
+    fn update(
+        &mut self,
+        selection: &Selection,
+        f: &mut dyn FnMut(&Row) -> Result<Row, String>,
+    ) -> Result<Vec<(Row, Row)>, String> {
+        if self.rewrites()? {
+            let keys = &self.keys;
+            let table = self.tables
+                .get_mut(&self.table)
+                .map(Arc::make_mut)
+                .ok_or(format!("Table '{}' not found", self.table))?;
+            return table.with_rows_mut(|rows| {
+                let mut updated = Vec::new();
+                for row in rows.iter_mut().filter(|row| selection.accepts(row)) {
+                    let new = f(row)?;
+                    updated.push((std::mem::replace(row, new.clone()), new));
+                }
+                check_unique(rows, keys)?;
+                Ok(updated)
+            });
+        }
+
+        let table = self.tables
+            .get_mut(&self.table)
+            .map(Arc::make_mut)
+            .ok_or(format!("Table '{}' not found", self.table))?;
+        let mut updated = Vec::new();
+        for position in table.candidates(selection) {
+            if !selection.accepts(&table.rows[position]) {
+                continue;
+            }
+            let new = f(&table.rows[position])?;
+            for columns in &self.keys {
+                let moved = unique_key(&new, columns) != unique_key(&table.rows[position], columns);
+                if moved && table.holder(columns, &new, Some(position)).is_some() {
+                    return Err(duplicate_key(columns, &new));
+                }
+            }
+            let old = std::mem::replace(&mut table.rows[position], new.clone());
+            table.reindex(position, &old, &new);
+            self.undo.push(Undo::Replaced(position, old.clone()));
+            updated.push((old, new));
+        }
+        Ok(updated)
+    }
+
+    fn delete(&mut self, selection: &Selection) -> Result<Vec<Row>, String> {
+        if self.rewrites()? {
+            return self.data()?.with_rows_mut(|rows| {
+                let (deleted, kept) = std::mem::take(rows).into_iter().partition(|row| selection.accepts(row));
+                *rows = kept;
+                Ok(deleted)
+            });
+        }
+
+        let table = self.data()?;
+        let positions: Vec<usize> = table
+            .candidates(selection)
+            .into_iter()
+            .filter(|&position| selection.accepts(&table.rows[position]))
+            .collect();
+        if positions.is_empty() {
+            return Ok(Vec::new());
+        }
+        let deleted = table.remove_at(&positions);
+        self.undo.push(Undo::Removed(positions.into_iter().zip(deleted.iter().cloned()).collect()));
+        Ok(deleted)
+    }
+
+    fn upsert(
+        &mut self,
+        arbiters: &[Vec<String>],
+        rows: Vec<Row>,
+        resolve: &mut Resolve,
+    ) -> Result<Vec<Change>, String> {
+        let rewrites = self.rewrites()?;
+        let keys = Keys {
+            arbiters,
+            unique: &self.keys,
+        };
+        let table = self.tables
+            .get_mut(&self.table)
+            .map(Arc::make_mut)
+            .ok_or(format!("Table '{}' not found", self.table))?;
+        if rewrites {
+            return table.with_rows_mut(|stored| upsert_rows(stored, keys, rows, resolve, &mut |_, _| {}));
+        }
+
+        let undo = &mut self.undo;
+        undo.push(Undo::Appended(table.len()));
+        let changes = upsert_rows(&mut table.rows, keys, rows, resolve, &mut |position, old| {
+            undo.push(Undo::Replaced(position, old.clone()));
+        });
+        table.invalidate_indexes();
+        changes
+    }
+
+    fn add_key(&mut self, columns: &[String]) -> Result<(), String> {
+        let table = self.tables.get(&self.table).ok_or(format!("Table '{}' not found", self.table))?;
+        let rows = table.select_columns(&[], |_: &Row| true);
+        check_unique(&rows, &[columns.to_vec()])?;
+        self.keys.push(columns.to_vec());
+        Ok(())
+    }
+
+    /// Saves the tables before the write lock is let go, so what is saved
+    /// is only ever committed writes. If saving fails the write rolls back.
+    fn commit(mut self: Box<Self>) -> Result<(), String> {
+        if self.undo.is_empty() {
+            return Ok(());
+        }
+        self.engine
+            .save_tables(&self.tables)
+            .map_err(|e| format!("Failed to persist changes: {}", e))?;
+        self.undo.clear();
+        Ok(())
+    }
+
+    /// Dropping the write undoes it
+    fn rollback(self: Box<Self>) {}
+}
+
+impl Drop for MemoryWrite<'_> {
+    /// A write that did not commit leaves the table as it found it
+    fn drop(&mut self) {
+        self.undo_changes();
+    }
+}
+
+/// Fails if two of `rows` share their values in one of `keys`
+fn check_unique(rows: &[Row], keys: &[Vec<String>]) -> Result<(), String> {
+    for columns in keys {
+        let mut seen = BTreeSet::new();
+        for row in rows {
+            if unique_key(row, columns).is_some_and(|key| !seen.insert(key)) {
+                return Err(duplicate_key(columns, row));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The column sets an upsert checks rows on
+#[derive(Clone, Copy)]
+struct Keys<'a> {
+    /// Collisions here are resolved
+    arbiters: &'a [Vec<String>],
+    /// Collisions here fail the write
+    unique: &'a [Vec<String>],
+}
+
+/// `TableWrite::upsert` over a table's rows, telling `replaced` the
+/// position and old image of each stored row it replaces
+fn upsert_rows(
+    stored: &mut Vec<Row>,
+    keys: Keys,
+    rows: Vec<Row>,
+    resolve: &mut Resolve,
+    replaced: &mut dyn FnMut(usize, &Row),
+) -> Result<Vec<Change>, String> {
+    // Arbiters first, then the other keys
+    let mut columns: Vec<&Vec<String>> = keys.arbiters.iter().collect();
+    columns.extend(keys.unique.iter().filter(|key| !keys.arbiters.contains(key)));
+    // Position of the row holding each value, for every key
+    let mut indexes: Vec<BTreeMap<Vec<IndexKey>, usize>> = columns
+        .iter()
+        .map(|columns| {
+            let keyed = stored.iter().enumerate();
+            keyed.filter_map(|(position, row)| Some((unique_key(row, columns)?, position))).collect()
+        })
+        .collect();
+    // The first key `row` collides on, with the position of the row it hits
+    let collision = |indexes: &[BTreeMap<Vec<IndexKey>, usize>], row: &Row| {
+        columns.iter().zip(indexes).enumerate().find_map(|(i, (columns, index))| {
+            let position = index.get(&unique_key(row, columns)?)?;
+            Some((i, *position))
+        })
+    };
+    let mut touched = HashSet::new();
+    let mut changes = Vec::new();
+
+    for row in rows {
+        let position = match collision(&indexes, &row) {
+            Some((i, position)) if i < keys.arbiters.len() => position,
+            Some((i, _)) => return Err(duplicate_key(columns[i], &row)),
+            None => {
+                for (columns, index) in columns.iter().zip(indexes.iter_mut()) {
+                    if let Some(key) = unique_key(&row, columns) {
+                        index.insert(key, stored.len());
+                    }
+                }
+                changes.push(Change::Insert { row: row.clone() });
+                stored.push(row);
+                continue;
+            }
+        };
+
+        let Some(new) = resolve(&stored[position], &row)? else {
+            continue;
+        };
+        if !touched.insert(position) {
+            return Err("ON CONFLICT DO UPDATE command cannot affect row a second time".to_string());
+        }
+        for (columns, index) in columns.iter().zip(indexes.iter_mut()) {
+            if let Some(key) = unique_key(&stored[position], columns) {
+                index.remove(&key);
+            }
+        }
+        if let Some((i, _)) = collision(&indexes, &new) {
+            return Err(duplicate_key(columns[i], &new));
+        }
+        for (columns, index) in columns.iter().zip(indexes.iter_mut()) {
+            if let Some(key) = unique_key(&new, columns) {
+                index.insert(key, position);
+            }
+        }
+        let old = std::mem::replace(&mut stored[position], new.clone());
+        replaced(position, &old);
+        changes.push(Change::Update { old, new });
+    }
+    Ok(changes)
+}
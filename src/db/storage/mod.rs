@@ -1,11 +1,27 @@
-// In-memory Storage Engine for table data
+// Storage for table data - row and columnar table layouts, the in-memory
+// and LSM engines, and the per-table routing between them
+pub mod changes;
+pub mod columnar;
+pub mod engine;
+pub mod log_file;
+pub mod lsm;
+pub mod memory;
+
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
+use std::path::Path;
 use std::sync::{Arc, RwLock};
 
-use crate::db::catalog::{TableSchema, CATALOG};
+use crate::db::catalog::{Catalog, TableEngine, TableFormat, TableSchema, CATALOG};
+use crate::db::database::default_dir;
 use crate::db::sql::parser::Expression;
 use crate::db::sql::constants::Literal;
+use columnar::ColumnStore;
+pub use engine::{Selection, StorageEngine, TableSnapshot, TableWrite};
+use lsm::LsmEngine;
+use memory::MemoryEngine;
 
 /// Represents a value in a row
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -47,34 +63,500 @@ impl Value {
             }
         }
     }
+
+    /// Map a JSON value (a request parameter, or a value of a result row)
+    /// onto a storage value, keeping its JSON type
+    pub fn from_json(value: &serde_json::Value) -> Result<Self, String> {
+        match value {
+            serde_json::Value::Null => Ok(Value::Null),
+            serde_json::Value::Bool(b) => Ok(Value::Boolean(*b)),
+            serde_json::Value::Number(n) => match n.as_i64() {
+                Some(i) => Ok(Value::Integer(i)),
+                None => n
+                    .as_f64()
+                    .map(Value::Float)
+                    .ok_or_else(|| format!("Unsupported number: {}", n)),
+            },
+            serde_json::Value::String(s) => Ok(Value::Text(s.clone())),
+            other => Err(format!("Unsupported value type: {}", other)),
+        }
+    }
+
+    /// Convert back to a parser Literal (e.g. to bind a parameter value)
+    pub fn to_literal(&self) -> Literal {
+        match self {
+            Value::Null => Literal::Null,
+            Value::Integer(i) => Literal::Number(i.to_string()),
+            // Debug formatting keeps the fraction ("2.0"), so the literal reads back as a float
+            Value::Float(f) => Literal::Number(format!("{:?}", f)),
+            Value::Text(s) => Literal::String(s.clone()),
+            Value::Boolean(b) => Literal::Boolean(*b),
+        }
+    }
+
+    /// Total order over values, used by indexes and histograms: NULL first,
+    /// then booleans, numbers (integers and floats compared numerically) and text
+    pub fn total_cmp(&self, other: &Value) -> Ordering {
+        fn rank(value: &Value) -> u8 {
+            match value {
+                Value::Null => 0,
+                Value::Boolean(_) => 1,
+                Value::Integer(_) | Value::Float(_) => 2,
+                Value::Text(_) => 3,
+            }
+        }
+
+        match (self, other) {
+            (Value::Boolean(a), Value::Boolean(b)) => a.cmp(b),
+            (Value::Integer(a), Value::Integer(b)) => a.cmp(b),
+            (Value::Float(a), Value::Float(b)) => a.total_cmp(b),
+            (Value::Integer(a), Value::Float(b)) => (*a as f64).total_cmp(b),
+            (Value::Float(a), Value::Integer(b)) => a.total_cmp(&(*b as f64)),
+            (Value::Text(a), Value::Text(b)) => a.cmp(b),
+            _ => rank(self).cmp(&rank(other)),
+        }
+    }
 }
 
 /// A row of data as a map from column name to value
 pub type Row = HashMap<String, Value>;
 
+/// Index key: a value ordered by `Value::total_cmp`
+#[derive(Debug, Clone)]
+struct IndexKey(Value);
+
+impl PartialEq for IndexKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for IndexKey {}
+
+impl PartialOrd for IndexKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for IndexKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// Error for a write that would leave two rows with `row`'s values in
+/// `columns`
+fn duplicate_key(columns: &[String], row: &Row) -> String {
+    let values: Vec<String> = columns
+        .iter()
+        .map(|c| row.get(c).unwrap_or(&Value::Null).to_string())
+        .collect();
+    format!("Duplicate key value: ({})=({}) already exists", columns.join(", "), values.join(", "))
+}
+
+/// `row`'s values in `columns` as an ordered key, or None when one of them
+/// is NULL, since NULLs never collide
+fn unique_key(row: &Row, columns: &[String]) -> Option<Vec<IndexKey>> {
+    columns
+        .iter()
+        .map(|c| match row.get(c) {
+            None | Some(Value::Null) => None,
+            Some(value) => Some(IndexKey(value.clone())),
+        })
+        .collect()
+}
+
+/// Bounds of an index range lookup on one column
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyRange {
+    pub lower: Bound<Value>,
+    pub upper: Bound<Value>,
+}
+
+impl KeyRange {
+    /// Every non-NULL key
+    pub fn full() -> Self {
+        Self {
+            lower: Bound::Unbounded,
+            upper: Bound::Unbounded,
+        }
+    }
+
+    pub fn exact(value: Value) -> Self {
+        Self {
+            lower: Bound::Included(value.clone()),
+            upper: Bound::Included(value),
+        }
+    }
+
+    /// Keys inside both ranges
+    pub fn intersect(&self, other: &KeyRange) -> KeyRange {
+        // Between two bounds on the same side, keep the tighter one
+        fn tighter(a: &Bound<Value>, b: &Bound<Value>, wanted: Ordering) -> Bound<Value> {
+            match (a, b) {
+                (Bound::Unbounded, other) | (other, Bound::Unbounded) => other.clone(),
+                (Bound::Included(x) | Bound::Excluded(x), Bound::Included(y) | Bound::Excluded(y)) => {
+                    match x.total_cmp(y) {
+                        Ordering::Equal if matches!(a, Bound::Excluded(_)) => a.clone(),
+                        Ordering::Equal => b.clone(),
+                        order if order == wanted => a.clone(),
+                        _ => b.clone(),
+                    }
+                }
+            }
+        }
+
+        KeyRange {
+            lower: tighter(&self.lower, &other.lower, Ordering::Greater),
+            upper: tighter(&self.upper, &other.upper, Ordering::Less),
+        }
+    }
+
+    /// Whether `value` lies inside the range (NULL never does)
+    pub fn contains(&self, value: &Value) -> bool {
+        if *value == Value::Null {
+            return false;
+        }
+        let above = match &self.lower {
+            Bound::Included(l) => value.total_cmp(l) != Ordering::Less,
+            Bound::Excluded(l) => value.total_cmp(l) == Ordering::Greater,
+            Bound::Unbounded => true,
+        };
+        let below = match &self.upper {
+            Bound::Included(u) => value.total_cmp(u) != Ordering::Greater,
+            Bound::Excluded(u) => value.total_cmp(u) == Ordering::Less,
+            Bound::Unbounded => true,
+        };
+        above && below
+    }
+
+    /// Whether no key can fall inside the range
+    pub fn is_empty(&self) -> bool {
+        match (&self.lower, &self.upper) {
+            (Bound::Included(l), Bound::Included(u)) => l.total_cmp(u) == Ordering::Greater,
+            (Bound::Included(l), Bound::Excluded(u))
+            | (Bound::Excluded(l), Bound::Included(u))
+            | (Bound::Excluded(l), Bound::Excluded(u)) => l.total_cmp(u) != Ordering::Less,
+            _ => false,
+        }
+    }
+}
+
+/// What a scan needs from a table. Only columnar tables use it to read less:
+/// row tables return whole rows, and callers still evaluate their predicate.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScanFilter {
+    /// Columns to return (None for all of them)
+    pub columns: Option<Vec<String>>,
+    /// Ranges the predicate confines columns to; chunks whose zone maps lie
+    /// outside any of them are skipped
+    pub ranges: Vec<(String, KeyRange)>,
+}
+
+impl ScanFilter {
+    /// `row` cut down to the requested columns
+    pub fn project(&self, row: &Row) -> Row {
+        match &self.columns {
+            None => row.clone(),
+            Some(columns) => columns
+                .iter()
+                .filter_map(|c| row.get(c).map(|v| (c.clone(), v.clone())))
+                .collect(),
+        }
+    }
+}
+
+/// Rows read from a stretch of a table
+#[derive(Debug, Default, PartialEq)]
+pub struct ScanBatch {
+    pub rows: Vec<Row>,
+    /// Table position of each row in `rows`
+    pub positions: Vec<usize>,
+    /// Positions covered, read or skipped; fewer than asked for only at the
+    /// end of the table
+    pub scanned: usize,
+    /// Column chunks passed over on the strength of their zone maps
+    pub skipped_chunks: usize,
+}
+
+/// Ordered map from one column's values to the positions of the rows holding them.
+/// NULLs are not indexed, since no comparison with NULL is ever true.
+#[derive(Debug, Clone, Default)]
+struct ColumnIndex {
+    entries: BTreeMap<IndexKey, Vec<usize>>,
+}
+
+impl ColumnIndex {
+    fn add(&mut self, column: &str, position: usize, row: &Row) {
+        match row.get(column) {
+            None | Some(Value::Null) => {}
+            Some(value) => self.entries.entry(IndexKey(value.clone())).or_default().push(position),
+        }
+    }
+
+    fn remove(&mut self, column: &str, position: usize, row: &Row) {
+        let Some(value) = row.get(column).filter(|v| **v != Value::Null) else {
+            return;
+        };
+        let key = IndexKey(value.clone());
+        if let Some(positions) = self.entries.get_mut(&key) {
+            positions.retain(|&p| p != position);
+            if positions.is_empty() {
+                self.entries.remove(&key);
+            }
+        }
+    }
+
+    fn positions(&self, range: &KeyRange) -> Vec<usize> {
+        if range.is_empty() {
+            return Vec::new();
+        }
+        let wrap = |bound: &Bound<Value>| match bound {
+            Bound::Included(v) => Bound::Included(IndexKey(v.clone())),
+            Bound::Excluded(v) => Bound::Excluded(IndexKey(v.clone())),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let mut positions: Vec<usize> = self
+            .entries
+            .range((wrap(&range.lower), wrap(&range.upper)))
+            .flat_map(|(_, positions)| positions.iter().copied())
+            .collect();
+        // Hand rows back in table order, like a sequential scan would
+        positions.sort_unstable();
+        positions
+    }
+}
+
 /// In-memory table data storage
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct TableData {
+    /// Rows of a row-format table (empty for columnar tables)
     pub rows: Vec<Row>,
+    /// Rows of a columnar table. Writes other than appends decode the
+    /// table, change it and encode it again.
+    columnar: Option<ColumnStore>,
+    /// Column indexes, built on first lookup. Appends keep them current;
+    /// any other change drops them so the next lookup rebuilds.
+    indexes: HashMap<String, ColumnIndex>,
 }
 
 impl TableData {
     pub fn new() -> Self {
-        Self { rows: Vec::new() }
+        Self::default()
+    }
+
+    pub fn with_format(format: TableFormat) -> Self {
+        Self {
+            columnar: (format == TableFormat::Columnar).then(ColumnStore::default),
+            ..Self::default()
+        }
+    }
+
+    pub fn format(&self) -> TableFormat {
+        match self.columnar {
+            Some(_) => TableFormat::Columnar,
+            None => TableFormat::Row,
+        }
     }
 
     /// Insert a row
     pub fn insert(&mut self, row: Row) -> usize {
-        self.rows.push(row);
+        self.append(vec![row]);
         1
     }
 
+    /// Add rows at the end of the table
+    pub fn append(&mut self, rows: Vec<Row>) {
+        let from = self.len();
+        match &mut self.columnar {
+            Some(store) => store.append(rows),
+            None => self.rows.extend(rows),
+        }
+        self.index_appended(from);
+    }
+
+    /// Keep the first `len` rows
+    fn truncate(&mut self, len: usize) {
+        match &mut self.columnar {
+            Some(store) => store.truncate(len),
+            None => self.rows.truncate(len),
+        }
+        self.invalidate_indexes();
+    }
+
+    /// A copy of the rows, to restore if a change has to be undone
+    fn snapshot(&self) -> TableData {
+        TableData {
+            rows: self.rows.clone(),
+            columnar: self.columnar.clone(),
+            indexes: HashMap::new(),
+        }
+    }
+
+    /// Run `f` over the rows: borrowed for row tables, decoded for columnar ones
+    fn with_rows<T>(&self, f: impl FnOnce(&[Row]) -> T) -> T {
+        match &self.columnar {
+            Some(store) => f(&store.rows()),
+            None => f(&self.rows),
+        }
+    }
+
+    /// Let `f` change the rows in place; columnar tables are re-encoded after
+    fn with_rows_mut<T>(&mut self, f: impl FnOnce(&mut Vec<Row>) -> T) -> T {
+        match &mut self.columnar {
+            Some(store) => {
+                let mut rows = store.rows();
+                let result = f(&mut rows);
+                *store = ColumnStore::from_rows(rows);
+                result
+            }
+            None => f(&mut self.rows),
+        }
+    }
+
+    /// Add rows from position `from` onwards to every built index
+    fn index_appended(&mut self, from: usize) {
+        if self.indexes.is_empty() {
+            return;
+        }
+        let rows = self.scan(from, usize::MAX, &ScanFilter::default()).rows;
+        for (column, index) in self.indexes.iter_mut() {
+            for (position, row) in rows.iter().enumerate() {
+                index.add(column, from + position, row);
+            }
+        }
+    }
+
+    fn invalidate_indexes(&mut self) {
+        self.indexes.clear();
+    }
+
+    /// Move the built indexes' entry for the row at `position` from its
+    /// `old` values to its `new` ones
+    fn reindex(&mut self, position: usize, old: &Row, new: &Row) {
+        for (column, index) in self.indexes.iter_mut() {
+            index.remove(column, position, old);
+            index.add(column, position, new);
+        }
+    }
+
+    /// Positions of a row table that may hold the rows `selection` picks,
+    /// ascending: those its key range covers, found through the index on
+    /// the key column, or else every one
+    fn candidates(&mut self, selection: &Selection) -> Vec<usize> {
+        match &selection.key {
+            Some((column, range)) => {
+                self.build_index(column);
+                self.indexes[column].positions(range)
+            }
+            None => (0..self.len()).collect(),
+        }
+    }
+
+    /// Position of a row, other than the one at `except`, with `row`'s
+    /// values in `columns`, found through the index on the first of them
+    fn holder(&mut self, columns: &[String], row: &Row, except: Option<usize>) -> Option<usize> {
+        let key = unique_key(row, columns)?;
+        self.build_index(&columns[0]);
+        let positions: Vec<usize> = self.indexes[&columns[0]]
+            .positions(&KeyRange::exact(key[0].0.clone()))
+            .into_iter()
+            .filter(|&position| Some(position) != except)
+            .collect();
+        let rows = self.rows_at(&positions);
+        positions
+            .into_iter()
+            .zip(rows)
+            .find(|(_, held)| unique_key(held, columns).as_ref() == Some(&key))
+            .map(|(position, _)| position)
+    }
+
+    /// Take the rows at `positions` (ascending) out of a row table
+    fn remove_at(&mut self, positions: &[usize]) -> Vec<Row> {
+        let mut doomed = positions.iter().copied().peekable();
+        let mut removed = Vec::with_capacity(positions.len());
+        let rows = std::mem::take(&mut self.rows);
+        self.rows.reserve(rows.len() - positions.len());
+        for (position, row) in rows.into_iter().enumerate() {
+            match doomed.next_if_eq(&position) {
+                Some(_) => removed.push(row),
+                None => self.rows.push(row),
+            }
+        }
+        self.invalidate_indexes();
+        removed
+    }
+
+    /// Put rows `remove_at` took out back where they were
+    fn restore_at(&mut self, removed: Vec<(usize, Row)>) {
+        let mut kept = std::mem::take(&mut self.rows).into_iter();
+        self.rows.reserve(kept.len() + removed.len());
+        for (position, row) in removed {
+            self.rows.extend(kept.by_ref().take(position.saturating_sub(self.rows.len())));
+            self.rows.push(row);
+        }
+        self.rows.extend(kept);
+        self.invalidate_indexes();
+    }
+
+    fn build_index(&mut self, column: &str) {
+        if self.indexes.contains_key(column) {
+            return;
+        }
+        let index = self.with_rows(|rows| {
+            let mut index = ColumnIndex::default();
+            for (position, row) in rows.iter().enumerate() {
+                index.add(column, position, row);
+            }
+            index
+        });
+        self.indexes.insert(column.to_string(), index);
+    }
+
+    /// Copy the rows at `positions` (ascending)
+    fn rows_at(&self, positions: &[usize]) -> Vec<Row> {
+        match &self.columnar {
+            Some(store) => {
+                let mut rows = Vec::with_capacity(positions.len());
+                let mut i = 0;
+                while i < positions.len() {
+                    // Decode each chunk the positions fall in once
+                    let chunk_end = (positions[i] / columnar::CHUNK_ROWS + 1) * columnar::CHUNK_ROWS;
+                    let batch = store.scan(positions[i], chunk_end - positions[i], &ScanFilter::default());
+                    while i < positions.len() && positions[i] < chunk_end {
+                        rows.push(batch.rows[positions[i] - batch.positions[0]].clone());
+                        i += 1;
+                    }
+                }
+                rows
+            }
+            None => positions.iter().map(|&p| self.rows[p].clone()).collect(),
+        }
+    }
+
+    /// Copy up to `limit` rows starting at position `offset`
+    pub fn scan(&self, offset: usize, limit: usize, filter: &ScanFilter) -> ScanBatch {
+        match &self.columnar {
+            Some(store) => store.scan(offset, limit, filter),
+            None => {
+                let rows: Vec<Row> = self.rows.iter().skip(offset).take(limit).cloned().collect();
+                ScanBatch {
+                    positions: (offset..offset + rows.len()).collect(),
+                    scanned: rows.len(),
+                    rows,
+                    skipped_chunks: 0,
+                }
+            }
+        }
+    }
+
     /// Select rows matching a predicate
-    pub fn select<F>(&self, predicate: F) -> Vec<&Row>
+    pub fn select<F>(&self, predicate: F) -> Vec<Row>
     where
         F: Fn(&Row) -> bool,
     {
-        self.rows.iter().filter(|row| predicate(*row)).collect()
+        self.with_rows(|rows| rows.iter().filter(|row| predicate(row)).cloned().collect())
     }
 
     /// Select specific columns from rows matching a predicate  
@@ -82,20 +564,12 @@ impl TableData {
     where
         F: Fn(&Row) -> bool,
     {
-        self.rows
-            .iter()
-            .filter(|row| predicate(*row))
-            .map(|row| {
-                if columns.is_empty() || columns.iter().any(|c| c == "*") {
-                    row.clone()
-                } else {
-                    columns
-                        .iter()
-                        .filter_map(|col| row.get(col).map(|v| (col.clone(), v.clone())))
-                        .collect()
-                }
-            })
-            .collect()
+        self.with_rows(|rows| {
+            rows.iter()
+                .filter(|row| predicate(row))
+                .map(|row| select_columns(row, columns))
+                .collect()
+        })
     }
 
     /// Delete rows matching a predicate, return count deleted
@@ -103,9 +577,15 @@ impl TableData {
     where
         F: Fn(&Row) -> bool,
     {
-        let original_len = self.rows.len();
-        self.rows.retain(|row| !predicate(row));
-        original_len - self.rows.len()
+        let deleted = self.with_rows_mut(|rows| {
+            let original_len = rows.len();
+            rows.retain(|row| !predicate(row));
+            original_len - rows.len()
+        });
+        if deleted > 0 {
+            self.invalidate_indexes();
+        }
+        deleted
     }
 
     /// Update rows matching a predicate
@@ -113,193 +593,176 @@ impl TableData {
     where
         F: Fn(&Row) -> bool,
     {
-        let mut count = 0;
-        for row in &mut self.rows {
-            if predicate(row) {
-                for (col, val) in updates {
-                    row.insert(col.clone(), val.clone());
+        let count = self.with_rows_mut(|rows| {
+            let mut count = 0;
+            for row in rows.iter_mut() {
+                if predicate(row) {
+                    for (col, val) in updates {
+                        row.insert(col.clone(), val.clone());
+                    }
+                    count += 1;
                 }
-                count += 1;
             }
+            count
+        });
+        if count > 0 {
+            self.invalidate_indexes();
         }
         count
     }
 
     /// Get row count
     pub fn len(&self) -> usize {
-        self.rows.len()
+        match &self.columnar {
+            Some(store) => store.len(),
+            None => self.rows.len(),
+        }
     }
 
     pub fn is_empty(&self) -> bool {
-        self.rows.is_empty()
+        self.len() == 0
     }
 }
 
-/// Serializable storage data for persistence
-#[derive(Debug, Default, Serialize, Deserialize)]
-struct StorageData {
-    tables: HashMap<String, Vec<Row>>,
+/// `row` cut down to `columns` (all of it for none or `*`)
+fn select_columns(row: &Row, columns: &[String]) -> Row {
+    if columns.is_empty() || columns.iter().any(|c| c == "*") {
+        row.clone()
+    } else {
+        columns
+            .iter()
+            .filter_map(|col| row.get(col).map(|v| (col.clone(), v.clone())))
+            .collect()
+    }
 }
 
-/// Global storage manager for all tables with persistence
+/// Storage for every table, each kept by the engine the catalog assigns it
 pub struct Storage {
-    tables: Arc<RwLock<HashMap<String, TableData>>>,
-    storage_path: std::path::PathBuf,
+    memory: MemoryEngine,
+    lsm: LsmEngine,
+    /// Catalog naming each table's engine
+    catalog: Arc<Catalog>,
+    /// Engine of each table looked up so far
+    engines: RwLock<HashMap<String, TableEngine>>,
+    /// Writes are published to the change listeners
+    publish: bool,
 }
 
 impl Storage {
     pub fn new() -> Self {
-        let storage_path = Self::get_default_path();
-        let mut storage = Self {
-            tables: Arc::new(RwLock::new(HashMap::new())),
-            storage_path,
-        };
-        // Load existing data from disk
-        let _ = storage.load();
-        storage
+        Self::open(&default_dir(), CATALOG.clone())
     }
 
-    fn get_default_path() -> std::path::PathBuf {
-        let home = std::env::var("HOME").unwrap_or_else(|_| ".".to_string());
-        let path = std::path::PathBuf::from(home).join(".butterfly_db");
-        std::fs::create_dir_all(&path).ok();
-        path.join("data.json")
+    /// Storage for the tables `catalog` describes, kept in `dir`. Existing
+    /// data is loaded from disk.
+    pub fn open(dir: &Path, catalog: Arc<Catalog>) -> Self {
+        std::fs::create_dir_all(dir).ok();
+        Self::with_engines(MemoryEngine::open(dir.join("data.json")), LsmEngine::new(dir.join("lsm")), catalog)
     }
 
-    /// Save all table data to disk
-    pub fn save(&self) -> Result<(), String> {
-        let tables = self.tables.read().map_err(|e| e.to_string())?;
-        
-        // Convert TableData to serializable format
-        let mut data = StorageData::default();
-        for (name, table_data) in tables.iter() {
-            data.tables.insert(name.clone(), table_data.rows.clone());
+    pub fn with_engines(memory: MemoryEngine, lsm: LsmEngine, catalog: Arc<Catalog>) -> Self {
+        Self {
+            memory,
+            lsm,
+            catalog,
+            engines: RwLock::new(HashMap::new()),
+            publish: false,
         }
-        
-        let json = serde_json::to_string_pretty(&data)
-            .map_err(|e| e.to_string())?;
-        std::fs::write(&self.storage_path, json)
-            .map_err(|e| e.to_string())?;
-        
-        Ok(())
     }
 
-    /// Load table data from disk
-    pub fn load(&mut self) -> Result<(), String> {
-        if !self.storage_path.exists() {
-            return Ok(());
-        }
+    /// Publish every write to the change listeners, as the process-wide
+    /// storage does
+    pub fn publishing(mut self) -> Self {
+        self.publish = true;
+        self
+    }
 
-        let content = std::fs::read_to_string(&self.storage_path)
-            .map_err(|e| e.to_string())?;
-        
-        let data: StorageData = serde_json::from_str(&content)
-            .map_err(|e| e.to_string())?;
-        
-        let mut tables = self.tables.write().map_err(|e| e.to_string())?;
-        for (name, rows) in data.tables {
-            let mut table_data = TableData::new();
-            table_data.rows = rows;
-            tables.insert(name, table_data);
+    /// Save the in-memory engine's tables to disk (the LSM engine writes
+    /// its own as it goes)
+    pub fn save(&self) -> Result<(), String> {
+        self.memory.save()
+    }
+
+    /// Engine holding a table: the one the catalog names, or for tables it
+    /// does not know whichever engine already has storage for them
+    pub fn table_engine(&self, table_name: &str) -> TableEngine {
+        if let Some(engine) = self.engines.read().ok().and_then(|engines| engines.get(table_name).copied()) {
+            return engine;
         }
-        
-        Ok(())
+        let engine = match self.catalog.get_table(table_name) {
+            Ok(schema) => schema.engine,
+            Err(_) if self.lsm.has_table(table_name) => TableEngine::Lsm,
+            Err(_) => TableEngine::Memory,
+        };
+        if let Ok(mut engines) = self.engines.write() {
+            engines.insert(table_name.to_string(), engine);
+        }
+        engine
     }
 
-    /// Get or create table data storage
-    pub fn get_or_create_table(&self, table_name: &str) -> Result<(), String> {
-        let mut tables = self.tables.write().map_err(|e| e.to_string())?;
-        if !tables.contains_key(table_name) {
-            tables.insert(table_name.to_string(), TableData::new());
+    fn engine(&self, table_name: &str) -> &dyn StorageEngine {
+        match self.table_engine(table_name) {
+            TableEngine::Memory => &self.memory,
+            TableEngine::Lsm => &self.lsm,
         }
-        Ok(())
     }
+}
 
-    /// Insert a row into a table (auto-persists)
-    pub fn insert(&self, table_name: &str, row: Row) -> Result<usize, String> {
-        self.get_or_create_table(table_name)?;
-        
-        let mut tables = self.tables.write().map_err(|e| e.to_string())?;
-        let table = tables
-            .get_mut(table_name)
-            .ok_or(format!("Table '{}' not found", table_name))?;
-        
-        let count = table.insert(row);
-        drop(tables);
-        
-        // Auto-persist after insert
-        let _ = self.save();
-        
-        Ok(count)
+impl StorageEngine for Storage {
+    fn create_table(&self, schema: &TableSchema) -> Result<(), String> {
+        self.engines
+            .write()
+            .map_err(|e| e.to_string())?
+            .insert(schema.name.clone(), schema.engine);
+        self.engine(&schema.name).create_table(schema)
     }
 
-    /// Select from a table
-    pub fn select<F>(&self, table_name: &str, columns: &[String], predicate: F) -> Result<Vec<Row>, String>
-    where
-        F: Fn(&Row) -> bool,
-    {
-        let tables = self.tables.read().map_err(|e| e.to_string())?;
-        let table = tables
-            .get(table_name)
-            .ok_or(format!("Table '{}' not found", table_name))?;
-        
-        Ok(table.select_columns(columns, predicate))
+    fn drop_table(&self, table_name: &str) -> Result<(), String> {
+        let result = self.engine(table_name).drop_table(table_name);
+        self.engines.write().map_err(|e| e.to_string())?.remove(table_name);
+        result
     }
 
-    /// Delete from a table (auto-persists)
-    pub fn delete<F>(&self, table_name: &str, predicate: F) -> Result<usize, String>
-    where
-        F: Fn(&Row) -> bool,
-    {
-        let mut tables = self.tables.write().map_err(|e| e.to_string())?;
-        let table = tables
-            .get_mut(table_name)
-            .ok_or(format!("Table '{}' not found", table_name))?;
-        
-        let count = table.delete(predicate);
-        drop(tables);
-        
-        // Auto-persist after delete
-        let _ = self.save();
-        
-        Ok(count)
+    fn has_table(&self, table_name: &str) -> bool {
+        self.engine(table_name).has_table(table_name)
     }
 
-    /// Update a table (auto-persists)
-    pub fn update<F>(&self, table_name: &str, updates: &HashMap<String, Value>, predicate: F) -> Result<usize, String>
-    where
-        F: Fn(&Row) -> bool,
-    {
-        let mut tables = self.tables.write().map_err(|e| e.to_string())?;
-        let table = tables
-            .get_mut(table_name)
-            .ok_or(format!("Table '{}' not found", table_name))?;
-        
-        let count = table.update(updates, predicate);
-        drop(tables);
-        
-        // Auto-persist after update
-        let _ = self.save();
-        
-        Ok(count)
+    fn snapshot(&self, table_name: &str) -> Result<Arc<dyn TableSnapshot>, String> {
+        self.engine(table_name).snapshot(table_name)
     }
 
-    /// Drop a table's data (auto-persists)
-    pub fn drop_table(&self, table_name: &str) -> Result<(), String> {
-        let mut tables = self.tables.write().map_err(|e| e.to_string())?;
-        tables.remove(table_name);
-        drop(tables);
-        
-        // Auto-persist after drop
-        let _ = self.save();
-        
-        Ok(())
+    fn select(&self, table_name: &str, columns: &[String], predicate: &dyn Fn(&Row) -> bool) -> Result<Vec<Row>, String> {
+        self.engine(table_name).select(table_name, columns, predicate)
+    }
+
+    fn index_scan(&self, table_name: &str, column: &str, range: &KeyRange) -> Result<Vec<Row>, String> {
+        self.engine(table_name).index_scan(table_name, column, range)
+    }
+
+    fn row_count(&self, table_name: &str) -> Result<usize, String> {
+        self.engine(table_name).row_count(table_name)
+    }
+
+    fn table_format(&self, table_name: &str) -> Result<TableFormat, String> {
+        self.engine(table_name).table_format(table_name)
+    }
+
+    fn publishes(&self) -> bool {
+        self.publish
+    }
+
+    fn begin(&self, table_name: &str, keys: &[Vec<String>]) -> Result<Box<dyn TableWrite + '_>, String> {
+        self.engine(table_name).begin(table_name, keys)
+    }
+
+    fn insert(&self, table_name: &str, rows: Vec<Row>) -> Result<usize, String> {
+        self.engine(table_name).insert(table_name, rows)
     }
 }
 
 // Global storage instance (loads data from disk on creation)
 lazy_static::lazy_static! {
-    pub static ref STORAGE: Storage = Storage::new();
+    pub static ref STORAGE: Arc<Storage> = Arc::new(Storage::new().publishing());
 }
 
 #[cfg(test)]
@@ -550,7 +1013,7 @@ mod tests {
     fn test_storage_new() {
         let storage = Storage::new();
         // Should not panic
-        assert!(storage.get_or_create_table("test").is_ok());
+        assert!(storage.create_table(&TableSchema::new("test".to_string(), Vec::new())).is_ok());
     }
 
     #[test]
@@ -561,25 +1024,166 @@ mod tests {
         row.insert("id".to_string(), Value::Integer(1));
         row.insert("name".to_string(), Value::Text("Test".to_string()));
         
-        storage.insert("test_table", row).unwrap();
+        storage.insert("test_table", vec![row]).unwrap();
         
-        let results = storage.select("test_table", &[], |_| true).unwrap();
+        let results = storage.select("test_table", &[], &|_| true).unwrap();
         assert_eq!(results.len(), 1);
         assert_eq!(results[0].get("id"), Some(&Value::Integer(1)));
     }
 
+    #[test]
+    fn test_storage_insert_many() {
+        let storage = Storage::new();
+        storage.drop_table("bulk_table").unwrap();
+
+        let rows: Vec<Row> = (0..1000)
+            .map(|i| {
+                let mut row = Row::new();
+                row.insert("id".to_string(), Value::Integer(i));
+                row
+            })
+            .collect();
+
+        assert_eq!(storage.insert("bulk_table", rows).unwrap(), 1000);
+        assert_eq!(storage.insert("bulk_table", Vec::new()).unwrap(), 0);
+
+        let results = storage.select("bulk_table", &[], &|_| true).unwrap();
+        assert_eq!(results.len(), 1000);
+        assert_eq!(results[999].get("id"), Some(&Value::Integer(999)));
+    }
+
+    #[test]
+    fn test_storage_write_rolls_back_on_error() {
+        let storage: &dyn StorageEngine = &Storage::new();
+        storage.drop_table("modify_table").unwrap();
+
+        let row = |id: i64| Row::from([("id".to_string(), Value::Integer(id))]);
+        storage.insert("modify_table", (1..=3).map(row).collect()).unwrap();
+        let ids = || -> Vec<Value> {
+            let rows = storage.select("modify_table", &[], &|_| true).unwrap();
+            rows.into_iter().map(|r| r["id"].clone()).collect()
+        };
+
+        let result: Result<(), String> = storage.write("modify_table", &[], |write| {
+            write.insert(vec![row(4)])?;
+            write.delete(&Selection::matching(&|r| r["id"] != Value::Integer(2)))?;
+            write.update(&Selection::matching(&|_| true), &mut |_| Ok(row(9)))?;
+            Err("boom".to_string())
+        });
+        assert!(result.is_err());
+        assert_eq!(ids(), vec![Value::Integer(1), Value::Integer(2), Value::Integer(3)]);
+
+        let deleted = storage
+            .write("modify_table", &[], |write| {
+                write.insert(vec![row(4)])?;
+                let selection = Selection {
+                    predicate: &|_| true,
+                    key: Some(("id".to_string(), KeyRange::exact(Value::Integer(2)))),
+                };
+                write.delete(&selection)
+            })
+            .unwrap();
+        assert_eq!(deleted, vec![row(2)]);
+        assert_eq!(ids(), vec![Value::Integer(1), Value::Integer(3), Value::Integer(4)]);
+    }
+
+    #[test]
+    fn test_storage_scan_batches() {
+        let storage = Storage::new();
+        storage.drop_table("scan_table").unwrap();
+        let rows: Vec<Row> = (0..5)
+            .map(|i| Row::from([("id".to_string(), Value::Integer(i))]))
+            .collect();
+        storage.insert("scan_table", rows).unwrap();
+
+        let first = storage.scan("scan_table", 0, 2).unwrap();
+        let last = storage.scan("scan_table", 4, 2).unwrap();
+        assert_eq!(first.len(), 2);
+        assert_eq!(last.len(), 1);
+        assert_eq!(last[0].get("id"), Some(&Value::Integer(4)));
+        assert!(storage.scan("scan_table", 10, 2).unwrap().is_empty());
+        assert!(storage.scan("missing_scan_table", 0, 2).is_err());
+    }
+
+    #[test]
+    fn test_snapshot_scan_ignores_later_writes() {
+        let storage = Storage::new();
+        storage.drop_table("snapshot_table").unwrap();
+        let row = |i: i64| Row::from([("id".to_string(), Value::Integer(i))]);
+        storage.insert("snapshot_table", (0..6).map(row).collect()).unwrap();
+
+        // Batches read by position keep their positions while rows come and go
+        let snapshot = storage.snapshot("snapshot_table").unwrap();
+        let mut scanned = snapshot.scan(0, 2, &ScanFilter::default()).unwrap().rows;
+        storage.delete("snapshot_table", &|row| matches!(row["id"], Value::Integer(i) if i < 2)).unwrap();
+        storage.insert("snapshot_table", vec![row(6)]).unwrap();
+        scanned.extend(snapshot.scan(2, 10, &ScanFilter::default()).unwrap().rows);
+        assert_eq!(scanned, (0..6).map(row).collect::<Vec<_>>());
+
+        let now = storage.scan("snapshot_table", 0, 10).unwrap();
+        assert_eq!(now, (2..7).map(row).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_storage_index_scan_tracks_writes() {
+        let storage = Storage::new();
+        storage.drop_table("index_table").unwrap();
+        let row = |id: i64, score: Value| {
+            Row::from([("id".to_string(), Value::Integer(id)), ("score".to_string(), score)])
+        };
+        storage
+            .insert(
+                "index_table",
+                vec![row(1, Value::Integer(10)), row(2, Value::Null), row(3, Value::Float(20.5))],
+            )
+            .unwrap();
+
+        let ids = |range: &KeyRange| -> Vec<Value> {
+            storage
+                .index_scan("index_table", "score", range)
+                .unwrap()
+                .into_iter()
+                .map(|r| r["id"].clone())
+                .collect()
+        };
+        let at_least_10 = KeyRange {
+            lower: Bound::Included(Value::Integer(10)),
+            upper: Bound::Unbounded,
+        };
+        assert_eq!(ids(&at_least_10), vec![Value::Integer(1), Value::Integer(3)]);
+
+        // Appends extend the built index; deletes drop it for a rebuild
+        storage.insert("index_table", vec![row(4, Value::Integer(15))]).unwrap();
+        assert_eq!(ids(&KeyRange::exact(Value::Integer(15))), vec![Value::Integer(4)]);
+        storage.delete("index_table", &|r| r["id"] == Value::Integer(1)).unwrap();
+        assert_eq!(ids(&at_least_10), vec![Value::Integer(3), Value::Integer(4)]);
+        assert!(ids(&KeyRange {
+            lower: Bound::Excluded(Value::Integer(5)),
+            upper: Bound::Excluded(Value::Integer(5)),
+        })
+        .is_empty());
+    }
+
+    #[test]
+    fn test_value_total_cmp() {
+        assert_eq!(Value::Null.total_cmp(&Value::Boolean(false)), Ordering::Less);
+        assert_eq!(Value::Integer(2).total_cmp(&Value::Float(1.5)), Ordering::Greater);
+        assert_eq!(Value::Integer(1).total_cmp(&Value::Float(1.0)), Ordering::Equal);
+        assert_eq!(Value::Float(1e9).total_cmp(&Value::Text("a".to_string())), Ordering::Less);
+    }
+
     #[test]
     fn test_storage_delete() {
         let storage = Storage::new();
         
         let mut row = Row::new();
         row.insert("id".to_string(), Value::Integer(1));
-        storage.insert("del_table", row).unwrap();
+        storage.insert("del_table", vec![row]).unwrap();
         
-        let deleted = storage.delete("del_table", |_| true).unwrap();
+        let deleted = storage.delete("del_table", &|_| true).unwrap();
         assert_eq!(deleted, 1);
         
-        let results = storage.select("del_table", &[], |_| true).unwrap();
+        let results = storage.select("del_table", &[], &|_| true).unwrap();
         assert!(results.is_empty());
     }
 
@@ -590,15 +1194,15 @@ mod tests {
         let mut row = Row::new();
         row.insert("id".to_string(), Value::Integer(1));
         row.insert("value".to_string(), Value::Integer(100));
-        storage.insert("upd_table", row).unwrap();
+        storage.insert("upd_table", vec![row]).unwrap();
         
         let mut updates = HashMap::new();
         updates.insert("value".to_string(), Value::Integer(200));
         
-        let count = storage.update("upd_table", &updates, |_| true).unwrap();
+        let count = storage.update("upd_table", &updates, &|_| true).unwrap();
         assert_eq!(count, 1);
         
-        let results = storage.select("upd_table", &[], |_| true).unwrap();
+        let results = storage.select("upd_table", &[], &|_| true).unwrap();
         assert_eq!(results[0].get("value"), Some(&Value::Integer(200)));
     }
 
@@ -608,12 +1212,12 @@ mod tests {
         
         let mut row = Row::new();
         row.insert("id".to_string(), Value::Integer(1));
-        storage.insert("drop_test", row).unwrap();
+        storage.insert("drop_test", vec![row]).unwrap();
         
         storage.drop_table("drop_test").unwrap();
         
         // Table no longer exists, select should fail
-        let result = storage.select("drop_test", &[], |_| true);
+        let result = storage.select("drop_test", &[], &|_| true);
         assert!(result.is_err());
     }
 
@@ -621,7 +1225,7 @@ mod tests {
     fn test_storage_select_nonexistent_table() {
         let storage = Storage::new();
         
-        let result = storage.select("nonexistent", &[], |_| true);
+        let result = storage.select("nonexistent", &[], &|_| true);
         assert!(result.is_err());
         assert!(result.unwrap_err().contains("not found"));
     }
@@ -7,6 +7,7 @@ use std::{
 
 use crate::{
     config::{self, Config},
+    db::executor::parallel,
     info,
 };
 use proctitle::set_title;
@@ -16,6 +17,7 @@ pub async fn INIT(config: Config) -> Result<System, Box<dyn Error>> {
     let pid = get_current_pid().expect("Failed to get PID");
     info!(format!("pid = {pid}"));
     let mut sys = System::new_all();
+    parallel::set_worker_limit(&config.resource);
     set_title(config.name);
 
     let file_path = Path::new("~/data/__leader.db");
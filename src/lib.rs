@@ -0,0 +1,12 @@
+// butterfly_db as a library - embed the engine with `Database`
+pub mod DS;
+pub mod config;
+pub mod db;
+pub mod hashing;
+pub mod init;
+pub mod log;
+pub mod pools;
+
+pub use db::database::{Database, FromValue, ResultRow, Rows};
+pub use db::executor::ExecutionResult;
+pub use db::storage::Value;
@@ -1,17 +1,11 @@
-use crate::{
+use db::{
     config::{Config, get_config},
     db::{admission_control::can_take_task, partition::DataBaseClient},
+    info,
     init::INIT,
 };
 #[allow(unused_imports)]
 use std::env;
-pub mod DS;
-pub mod config;
-pub mod db;
-pub mod hashing;
-pub mod init;
-pub mod log;
-pub mod pools;
 
 #[tokio::main]
 async fn main() {